@@ -1,360 +1,7065 @@
-use chrono::{NaiveDate, TimeZone, Utc};
+use clap::{error::ErrorKind, CommandFactory, Parser};
 use sha1::Digest;
 use std::{
-    fs,
-    io::{Read, Write},
-    os::unix::fs::PermissionsExt,
+    collections::{HashMap, HashSet},
+    env, fs,
+    io::{self, BufRead, IsTerminal, Read, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::Mutex,
 };
 
-use crate::git_client::{get_refs, get_objects, Repo};
+use crate::{
+    apply,
+    attributes::Attributes,
+    blame::{self, Blame, Origin, DEFAULT_COPY_SCORE, DEFAULT_MOVE_SCORE},
+    cli::{
+        BlameArgs, CatFileArgs, Cli, CloneArgs, ColorArgs, Command, CommitGraphCommand, ConfigArgs,
+        DiffFormatArgs, FastExportArgs, FastImportArgs, InterpretTrailersArgs, LogArgs, LsTreeArgs,
+        MaintenanceCommand, MaintenanceRunArgs, MergeArgs, MultiPackIndexCommand, NameRevArgs,
+        ProgressArgs, ReflogCommand, ReflogOptions, ReplaceArgs, RerereCommand, RewriteArgs,
+        ShallowArgs, StashApplyArgs, StashArgs, StashCommand, StashPushArgs, SubmoduleCommand,
+        SubtreeCommand, TagArgs, TrailerArg, UpdateIndexArgs, VerifyArgs, WorktreeCommand,
+    },
+    color::{self, When},
+    config::{self, Config},
+    convert::{self, Converter},
+    diff::{self, Change, DiffFormat, FileStat},
+    editor,
+    error::{Error, Result},
+    fast_export::{FastExport, SignedTags},
+    fast_import::{DateFormat, FastImport},
+    fsmonitor,
+    git_client::{self, Deepen, Repo},
+    graph::Graph,
+    index::{find_entry, Index, IndexEntry},
+    maintenance::{self, Schedule, Scheduler, Task},
+    merge::{self, Favor, TreeMerge},
+    message, midx,
+    name_rev::{NameRev, RefFilter},
+    objects::{self, GitObjectType, TreeEntry},
+    pager::{self, Pager},
+    parallel,
+    patch::{self, PatchOptions, Side, WordDiff, WordDiffMode},
+    paths,
+    pathspec::{self, Pathspecs},
+    pickaxe::Pickaxe,
+    pretty::{self, Format, Pretty},
+    progress::{NoProgress, Progress, TerminalProgress},
+    reflog::{self, Expiry},
+    refname,
+    regex::Regex,
+    repository::{self, Commit, ReflogEntry, Repository, Signature},
+    rerere,
+    rewrite::{self, Rewrite},
+    sign, submodule, subtree, trace, trailer,
+    untracked::{self, UntrackedCache},
+    wildmatch::wildmatch,
+    worktree,
+    xdiff::{self, Algorithm},
+};
 
+// an entry of the stash: the commit of the work tree, whose parents are the commit it was made
+// on, the index, and the untracked files if it has them; `reflog` is its place in the stash
+// reflog when it was named by that
+struct Stash {
+    name: String,
+    reflog: Option<usize>,
+    commit: Commit,
+}
 
-pub struct App {}
+#[derive(Default)]
+pub struct App {
+    // set by --git-dir/--work-tree or GIT_DIR/GIT_WORK_TREE
+    git_dir: Option<PathBuf>,
+    work_tree: Option<PathBuf>,
+    // the aliases expanded so far, to catch one that comes back to itself
+    aliases: Vec<String>,
+    // set by --no-pager, which holds for the commands aliases expand to
+    no_pager: bool,
+}
 
 impl App {
     pub fn new() -> Self {
-        Self {}
-    }
-
-    pub fn run(&self, args: Vec<String>) {
-        if args[1] == "init" {
-            self.init()
-        } else if args[1] == "cat-file" {
-            let blob_sha = args[3].clone();
-            self.cat_file(blob_sha)
-        } else if args[1] == "hash-object" {
-            let file_path = args[3].clone();
-            self.hash_object(&file_path);
-        } else if args[1] == "ls-tree" {
-            let tree_sha = args[3].clone();
-            self.ls_tree(tree_sha)
-        } else if args[1] == "write-tree" {
-            self.write_tree()
-        } else if args[1] == "commit-tree" {
-            let tree_sha = args[2].clone();
-            let message = if args.len() == 5 {
-                args[4].clone()
-            } else {
-                args[6].clone()
-            };
-            let parent_hash = if args.len() == 7 {
-                Some(&args[4])
-            } else {
-                None
-            };
-            self.commit_tree(&tree_sha, &message, parent_hash.map(|x| &x[..]));
-        } else if args[1] == "clone" {
-            let url = args[2].strip_suffix('/').clone().unwrap_or(&args[2]);
-            let dir = if args.len() == 4 {
-                &args[3]
-            } else {
-                ""
+        Self::default()
+    }
+
+    pub fn run(&mut self, args: Vec<String>) -> Result<()> {
+        trace::init();
+        tracing::trace!(target: "command", "built-in: git {}", trace::command_line(&args[1..]));
+        let program = args.first().cloned().unwrap_or_default();
+        let cli = match Cli::try_parse_from(args) {
+            Ok(cli) => cli,
+            // --help and --version are not errors
+            Err(error) if !error.use_stderr() => {
+                let _ = write!(io::stdout(), "{}", error);
+                return Ok(());
+            }
+            Err(error) => {
+                return Err(Error::Usage(
+                    error.render().to_string().trim_end().to_string(),
+                ))
+            }
+        };
+
+        // each -C is relative to the one before it, and an empty one is ignored
+        for directory in cli
+            .directories
+            .iter()
+            .filter(|dir| !dir.as_os_str().is_empty())
+        {
+            env::set_current_dir(directory).map_err(|error| {
+                io::Error::new(
+                    error.kind(),
+                    format!("cannot change to '{}': {}", directory.display(), error),
+                )
+            })?;
+        }
+        self.git_dir = path_option(cli.git_dir, "GIT_DIR")?;
+        self.work_tree = path_option(cli.work_tree, "GIT_WORK_TREE")?;
+        // filters and credential helpers run as subprocesses and need to see the same repository
+        if let Some(git_dir) = &self.git_dir {
+            env::set_var("GIT_DIR", git_dir);
+        }
+        if let Some(work_tree) = &self.work_tree {
+            env::set_var("GIT_WORK_TREE", work_tree);
+        }
+        if cli.no_replace_objects {
+            env::set_var("GIT_NO_REPLACE_OBJECTS", "1");
+        }
+        self.no_pager |= cli.no_pager;
+
+        match cli.command {
+            Command::Init {
+                bare,
+                initial_branch,
+                template,
+                quiet,
+                directory,
+            } => self.init(
+                bare,
+                initial_branch.as_deref(),
+                template,
+                quiet,
+                directory.as_deref(),
+            ),
+            Command::CatFile(args) => self.cat_file(&args),
+            Command::HashObject {
+                write,
+                object_type,
+                stdin,
+                files,
+            } => self.hash_object(&files, &object_type, write, stdin),
+            Command::LsTree(args) => self.ls_tree(&args),
+            Command::WriteTree => self.write_tree(),
+            Command::UpdateIndex(args) => self.update_index(&args),
+            Command::DiffTree {
+                format,
+                recursive,
+                no_commit_id,
+                root,
+                args,
+                paths,
+            } => self.diff_tree(
+                diff_format(&format)?,
+                recursive,
+                !no_commit_id,
+                root,
+                &args,
+                &paths,
+            ),
+            Command::DiffIndex {
+                format,
+                cached,
+                tree,
+                paths,
+            } => self.diff_index(diff_format(&format)?, cached, &tree, &paths),
+            Command::DiffFiles { format, paths } => self.diff_files(diff_format(&format)?, &paths),
+            Command::MergeTree {
+                name_only,
+                base,
+                ours,
+                theirs,
+            } => self.merge_tree(&base, &ours, &theirs, name_only),
+            Command::Merge(args) => self.merge(&args).and_then(|()| self.auto_gc(false)),
+            Command::Commit {
+                messages,
+                gpg_sign,
+                no_gpg_sign,
+                template,
+                verbose,
+                signoff,
+            } => self
+                .commit(
+                    &messages,
+                    gpg_sign.as_deref(),
+                    no_gpg_sign,
+                    template.as_deref(),
+                    verbose,
+                    signoff,
+                )
+                .and_then(|()| self.auto_gc(false)),
+            Command::Apply {
+                reverse,
+                check,
+                patches,
+            } => self.apply(reverse, check, &patches),
+            Command::InterpretTrailers(args) => self.interpret_trailers(&args),
+            Command::Stripspace {
+                strip_comments,
+                comment_lines,
+            } => self.stripspace(strip_comments, comment_lines),
+            Command::Tag(args) => self.tag(&args),
+            Command::FastExport(args) => self.fast_export(&args),
+            Command::FastImport(args) => self.fast_import(&args),
+            Command::Rewrite(args) => self.rewrite(&args),
+            Command::Replace(args) => self.replace(&args),
+            Command::VerifyCommit { args } => self.verify(&args, GitObjectType::Commit),
+            Command::VerifyTag { args } => self.verify(&args, GitObjectType::Tag),
+            Command::Status {
+                untracked_files,
+                paths,
+            } => self.status(untracked_files.as_deref(), &paths),
+            Command::ReadTree {
+                merge,
+                prefix,
+                trees,
+            } => self.read_tree(&trees, merge, prefix.as_deref()),
+            Command::CommitTree {
+                tree,
+                parents,
+                messages,
+            } => self.commit_tree(&tree, &parents, &messages),
+            Command::RevList { count, revisions } => self.rev_list(count, &revisions),
+            Command::Log(args) => self.log(&args),
+            Command::Blame(args) => self.blame(&args),
+            Command::NameRev(args) => self.name_rev(&args),
+            Command::Cherry {
+                verbose,
+                abbrev,
+                upstream,
+                head,
+                limit,
+            } => self.cherry(
+                verbose,
+                abbrev,
+                upstream.as_deref(),
+                head.as_deref(),
+                limit.as_deref(),
+            ),
+            Command::MergeBase {
+                all,
+                octopus,
+                is_ancestor,
+                commits,
+            } => self.merge_base(all, octopus, is_ancestor, &commits),
+            Command::SymbolicRef {
+                quiet,
+                short,
+                name,
+                target,
+            } => self.symbolic_ref(quiet, short, &name, target.as_deref()),
+            Command::CheckRefFormat {
+                normalize,
+                allow_onelevel,
+                no_allow_onelevel: _,
+                refspec_pattern,
+                branch,
+                refname,
+            } => {
+                let format = refname::Format {
+                    allow_onelevel,
+                    refspec_pattern,
+                };
+                self.check_ref_format(&refname, normalize, format, branch)
+            }
+            Command::Var { list, variable } => self.var(list, variable.as_deref()),
+            Command::UpdateRef {
+                no_deref,
+                delete,
+                name,
+                new_value,
+                old_value,
+            } => match delete {
+                // the value after the name is then the one the ref must have
+                true => self.delete_ref(no_deref, &name, new_value.as_deref()),
+                false => self.update_ref(
+                    no_deref,
+                    &name,
+                    &new_value.unwrap_or_default(),
+                    old_value.as_deref(),
+                ),
+            },
+            Command::Clone(args) => self.clone(&args),
+            Command::Fetch {
+                progress,
+                shallow,
+                deepen,
+                unshallow,
+                repository,
+            } => {
+                let url = repository.strip_suffix('/').unwrap_or(&repository);
+                self.fetch(url, &progress, &shallow, deepen, unshallow)?;
+                self.auto_gc(progress.quiet)
+            }
+            Command::Rerere { command } => self.rerere(command),
+            Command::MultiPackIndex {
+                command: MultiPackIndexCommand::Write,
+            } => self.write_multi_pack_index(),
+            Command::CommitGraph {
+                command: CommitGraphCommand::Write,
+            } => self.write_commit_graph(),
+            Command::PackRefs { all, no_prune } => self.pack_refs(all, !no_prune),
+            Command::Prune {
+                dry_run,
+                verbose,
+                expire,
+            } => self.prune(dry_run, verbose, expire.as_deref()),
+            Command::Gc {
+                prune,
+                no_prune,
+                auto,
+                detach,
+                no_detach,
+                progress,
+            } => {
+                let detach = match (detach, no_detach) {
+                    (false, false) => None,
+                    (detach, _) => Some(detach),
+                };
+                self.gc(prune, no_prune, auto, detach, &progress)
+            }
+            Command::Reflog { command } => self.reflog(&command),
+            Command::Maintenance { command } => self.maintenance(&command),
+            Command::ForEachRepo { config, args } => self.for_each_repo(&config, &args),
+            Command::CheckAttr { all, args, paths } => self.check_attr(all, &args, &paths),
+            Command::Checkout { tree_ish, paths } => match (tree_ish, paths.is_empty()) {
+                (Some(name), true) => self.checkout(&name),
+                (tree_ish, _) => self.checkout_paths(tree_ish.as_deref(), &paths),
+            },
+            Command::Config(args) => self.config(&args),
+            Command::Alias(args) => self.alias(&program, &args),
+            Command::Restore {
+                source,
+                staged,
+                worktree,
+                paths,
+            } => self.restore(source.as_deref(), staged, worktree, &paths),
+            Command::Stash(args) => self.stash(args),
+            Command::Switch {
+                create,
+                detach,
+                branch,
+            } => self.switch(create.as_deref(), detach, branch.as_deref()),
+            Command::Worktree { command } => self.worktree(command),
+            Command::Submodule { quiet, command } => self.submodule(quiet, command),
+            Command::Subtree {
+                prefix,
+                quiet,
+                command,
+            } => self.subtree(prefix.as_deref(), quiet, command),
+        }
+    }
+
+    fn init(
+        &self,
+        bare: bool,
+        initial_branch: Option<&str>,
+        template: Option<PathBuf>,
+        quiet: bool,
+        directory: Option<&Path>,
+    ) -> Result<()> {
+        let top = env::current_dir()?.join(directory.unwrap_or(Path::new("")));
+        fs::create_dir_all(&top)?;
+        let work_tree = self.work_tree.clone().unwrap_or_else(|| top.clone());
+        let (git_dir, work_tree) = match (&self.git_dir, bare) {
+            (Some(git_dir), true) => (git_dir.clone(), None),
+            (Some(git_dir), false) => (git_dir.clone(), Some(work_tree)),
+            (None, true) => (top, None),
+            (None, false) => (work_tree.join(".git"), Some(work_tree)),
+        };
+
+        let user = Config::user();
+        let reinit = git_dir.join("HEAD").is_file();
+        if let (true, Some(branch)) = (reinit, initial_branch) {
+            eprintln!("warning: re-init: ignored --initial-branch={}", branch);
+        }
+        let branch = initial_branch
+            .or_else(|| user.get("init.defaultBranch"))
+            .unwrap_or("master");
+        Repository::init(&git_dir, work_tree.as_deref(), branch)?;
+
+        let template = template
+            .or_else(|| env::var_os("GIT_TEMPLATE_DIR").map(PathBuf::from))
+            .or_else(|| user.get("init.templateDir").map(PathBuf::from));
+        if let Some(template) = template.filter(|template| template.is_dir()) {
+            copy_template(&template, &git_dir)?;
+        }
+
+        if !quiet {
+            let state = match reinit {
+                true => "Reinitialized existing",
+                false => "Initialized empty",
             };
-            self.clone(&url, dir);
+            println!("{} Git repository in {}/", state, git_dir.display());
         }
+        Ok(())
     }
 
-    fn init(&self) {
-        fs::create_dir(".git").unwrap();
-        fs::create_dir(".git/objects").unwrap();
-        fs::create_dir(".git/refs").unwrap();
-        fs::write(".git/HEAD", "ref: refs/heads/master\n").unwrap();
-        println!("Initialized git directory")
+    // the config of the repository, or the user's outside of one, for the commands that can
+    // run anywhere
+    fn config_anywhere(&self) -> Config {
+        match self.repository() {
+            Ok(repository) => Config::for_repository(repository.common_dir()),
+            Err(_) => Config::user(),
+        }
     }
 
-    fn cat_file(&self, blob_sha: String) {
-        let subfolder = &blob_sha[0..2];
-        let binary_content =
-            fs::read(format!(".git/objects/{}/{}", subfolder, &blob_sha[2..])).unwrap();
-        let mut content = Vec::new();
-        flate2::read::ZlibDecoder::new(&binary_content[..])
-            .read_to_end(&mut content)
-            .unwrap();
-        let content = String::from_utf8_lossy(&content);
-        let (_, content) = content.split_once('\x00').unwrap();
-        print!("{}", content);
+    fn repository(&self) -> Result<Repository> {
+        match &self.git_dir {
+            // with an explicit repository, the current directory is the work tree by default
+            Some(git_dir) => Repository::open_with(git_dir, Some(&self.work_tree_or_cwd()?)),
+            None => {
+                let repository = Repository::discover(&env::current_dir()?)?;
+                match &self.work_tree {
+                    Some(work_tree) => Repository::open_with(repository.git_dir(), Some(work_tree)),
+                    None => Ok(repository),
+                }
+            }
+        }
     }
 
-    fn hash_object(&self, file_path: &str) -> Vec<u8> {
-        let (compressed, bin_hash) = self.make_blob_object(file_path);
-        self.persist_git_object(&bin_hash[..], &compressed[..]);
+    fn work_tree_or_cwd(&self) -> Result<PathBuf> {
+        match &self.work_tree {
+            Some(work_tree) => Ok(work_tree.clone()),
+            None => Ok(env::current_dir()?),
+        }
+    }
 
-        bin_hash
+    // where the output of a command like log goes: the pager, unless --no-pager
+    fn pager(&self, config: &Config) -> Pager {
+        match self.no_pager {
+            true => Pager::none(),
+            false => Pager::start(config),
+        }
     }
 
-    fn make_blob_object(&self, file_path: &str) -> (Vec<u8>, Vec<u8>) {
-        let content = fs::read(file_path).unwrap();
-        self.make_git_object(&content, "blob")
+    fn cat_file(&self, args: &CatFileArgs) -> Result<()> {
+        if let Some(format) = &args.batch {
+            return self.cat_file_batch(format, true);
+        }
+        if let Some(format) = &args.batch_check {
+            return self.cat_file_batch(format, false);
+        }
+
+        let option = args.show_type || args.size || args.exists || args.pretty;
+        let (object_type, name) = match &args.args[..] {
+            [name] if option => (None, name),
+            [object_type, name] if !option => (Some(object_type.parse()?), name),
+            _ => {
+                return Err(usage_error(
+                    "cat-file",
+                    "expected one of -t, -s, -e, -p with an object, or a type and an object",
+                ))
+            }
+        };
+        let repository = self.repository()?;
+        let id = repository.rev_parse(name)?;
+        if args.exists {
+            return match repository.has_object(&id) {
+                true => Ok(()),
+                false => Err(Error::Exit(1)),
+            };
+        }
+
+        let mut stdout = io::stdout();
+        match object_type {
+            Some(object_type) => stdout.write_all(&repository.peel(&id, object_type)?.data)?,
+            None => {
+                let object = repository.find_object(&id)?;
+                if args.show_type {
+                    writeln!(stdout, "{}", object.object_type)?;
+                } else if args.size {
+                    writeln!(stdout, "{}", object.data.len())?;
+                } else if object.object_type == GitObjectType::Tree {
+                    for entry in objects::parse_tree(&object.data)? {
+                        writeln!(stdout, "{}", entry)?;
+                    }
+                } else {
+                    stdout.write_all(&object.data)?;
+                }
+            }
+        }
+
+        Ok(())
     }
 
-    fn make_git_object(&self, content: &[u8], obj_type: &str) -> (Vec<u8>, Vec<u8>) {
-        let header_bytes = format!("{obj_type} {}\0", content.len()).into_bytes();
-        let content = [&header_bytes[..], content].concat();
-        let mut compressed = Vec::new();
-        let mut compressor =
-            flate2::write::ZlibEncoder::new(&mut compressed, flate2::Compression::fast());
-        compressor.write_all(&content).unwrap();
-        compressor.finish().unwrap();
-        let mut hasher = sha1::Sha1::new();
-        hasher.update(&content);
-        let hash = hasher.finalize();
-        (compressed, hash.as_slice().to_vec())
-    }
-
-    fn persist_git_object(&self, bin_hash: &[u8], compressed_content: &[u8]) {
-        let hash = hex::encode(bin_hash);
-        let subfolder = &hash[0..2];
-        fs::create_dir_all(format!(".git/objects/{}/", subfolder)).unwrap();
-        match fs::write(
-            format!(".git/objects/{}/{}", subfolder, &hash[2..]),
-            compressed_content,
-        ) {
-            Ok(_) => {}
-            Err(e) => println!("{e}"),
+    // each line of stdin names an object, which is described by `format` and followed by its
+    // content when `contents` is set
+    fn cat_file_batch(&self, format: &str, contents: bool) -> Result<()> {
+        let format = match format {
+            "" => "%(objectname) %(objecttype) %(objectsize)",
+            format => format,
+        };
+        // with %(rest) in the format only the first word of a line names the object
+        let split = format.contains("%(rest)");
+        let repository = self.repository()?;
+        let mut stdout = io::stdout().lock();
+        for line in io::stdin().lock().lines() {
+            let line = line?;
+            let (name, rest) = match line.trim_start().split_once(char::is_whitespace) {
+                Some((name, rest)) if split => (name, rest.trim_start()),
+                _ => (line.as_str(), ""),
+            };
+            let object = repository
+                .rev_parse(name)
+                .and_then(|id| repository.find_object(&id));
+            let object = match object {
+                Ok(object) => object,
+                Err(Error::ObjectNotFound(_)) => {
+                    writeln!(stdout, "{} missing", name)?;
+                    stdout.flush()?;
+                    continue;
+                }
+                Err(error) => return Err(error),
+            };
+
+            let description = format
+                .replace("%(objectname)", &object.id)
+                .replace("%(objecttype)", &object.object_type.to_string())
+                .replace("%(objectsize)", &object.data.len().to_string())
+                .replace("%(rest)", rest);
+            writeln!(stdout, "{}", description)?;
+            if contents {
+                stdout.write_all(&object.data)?;
+                writeln!(stdout)?;
+            }
+            // whoever is feeding stdin may wait for each answer before asking the next
+            stdout.flush()?;
         }
-        println!("{}", hash);
+
+        Ok(())
     }
 
-    fn ls_tree(&self, tree_sha: String) {
-        let content = self.read_object(&tree_sha);
-        let str_content = String::from_utf8_lossy(&content[..]);
-        let normalized_tree_sha =
-            if content.starts_with(b"tree") && str_content.split_once('\x00').is_none() {
-                let (_, content) = content.split_at(5);
+    fn write_multi_pack_index(&self) -> Result<()> {
+        let repository = self.repository()?;
+        midx::write(&repository.object_dir().join("pack"))?;
+        Ok(())
+    }
 
-                hex::encode(&content[0..20])
-            } else {
-                tree_sha
+    // the objects these work on are the ones stored, whatever refs/replace/ says
+    fn write_commit_graph(&self) -> Result<()> {
+        let repository = self.repository()?.without_replacements();
+        maintenance::write_commit_graph(&repository)?;
+        Ok(())
+    }
+
+    fn pack_refs(&self, all: bool, prune: bool) -> Result<()> {
+        let repository = self.repository()?.without_replacements();
+        repository.pack_references(all, prune)
+    }
+
+    fn prune(&self, dry_run: bool, verbose: bool, expire: Option<&str>) -> Result<()> {
+        let repository = self.repository()?.without_replacements();
+        // without --expire every unreachable object goes, however new
+        let expire = match expire {
+            Some(date) => repository::parse_expiry_date(date)
+                .ok_or_else(|| Error::InvalidDate(date.to_string()))?,
+            None => i64::MAX,
+        };
+        for (id, object_type) in maintenance::prune(&repository, expire, dry_run)? {
+            if dry_run || verbose {
+                match object_type {
+                    Some(object_type) => println!("{} {}", id, object_type),
+                    None => println!("{} unknown", id),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // with --auto, gc only runs when there is enough to pack, and then in the background
+    // unless told otherwise, by running itself again; it leaves the repository to any other
+    // gc or maintenance run working on it
+    fn gc(
+        &self,
+        prune: Option<Option<String>>,
+        no_prune: bool,
+        auto: bool,
+        detach: Option<bool>,
+        progress: &ProgressArgs,
+    ) -> Result<()> {
+        let repository = self.repository()?.without_replacements();
+        let prune = prune.flatten();
+        let expire = match (&prune, no_prune) {
+            (_, true) => 0,
+            (Some(date), false) => repository::parse_expiry_date(date)
+                .ok_or_else(|| Error::InvalidDate(date.clone()))?,
+            (None, false) => maintenance::prune_expire(&repository)?,
+        };
+        if !auto {
+            return maintenance::gc(&repository, expire, progress_for(progress).as_mut());
+        }
+
+        if !maintenance::needs_gc(&repository) {
+            return Ok(());
+        }
+        let config = Config::for_repository(repository.common_dir());
+        let detach = detach.unwrap_or_else(|| {
+            config
+                .get("gc.autoDetach")
+                .and_then(config::parse_bool)
+                .unwrap_or(true)
+        });
+        if !progress.quiet {
+            match detach {
+                true => eprintln!(
+                    "Auto packing the repository in background for optimum performance.\n\
+                     See \"git help gc\" for manual housekeeping."
+                ),
+                false => eprintln!(
+                    "Auto packing the repository for optimum performance. You may also\n\
+                     run \"git gc\" by hand. See \"git help gc\" for manual housekeeping."
+                ),
+            }
+        }
+        if detach {
+            let mut command = process::Command::new(env::current_exe()?);
+            command
+                .env("GIT_DIR", repository.git_dir())
+                .args(["gc", "--auto", "--no-detach", "--quiet"])
+                .stdin(process::Stdio::null())
+                .stdout(process::Stdio::null())
+                .stderr(process::Stdio::null());
+            match (&prune, no_prune) {
+                (_, true) => command.arg("--no-prune"),
+                (Some(date), false) => command.arg(format!("--prune={}", date)),
+                (None, false) => &mut command,
             };
+            // where there's no running in the background, gc runs here and now
+            if command.spawn().is_ok() {
+                return Ok(());
+            }
+        }
+        let Some(_lock) = maintenance::Lock::acquire(&repository.object_dir())? else {
+            return Ok(());
+        };
+        maintenance::gc(&repository, expire, progress_for(progress).as_mut())
+    }
 
-        let content = self.read_object(&normalized_tree_sha);
+    // what commands that can leave many loose objects behind do when they're done, unless
+    // maintenance.auto is off
+    fn auto_gc(&self, quiet: bool) -> Result<()> {
+        let repository = self.repository()?;
+        let config = Config::for_repository(repository.common_dir());
+        if config.get("maintenance.auto").and_then(config::parse_bool) == Some(false) {
+            return Ok(());
+        }
+        let progress = ProgressArgs {
+            quiet,
+            progress: false,
+        };
+        self.gc(None, false, true, None, &progress)
+    }
 
-        let parts = content.split(|x| *x == b'\0');
+    // like git, a ref that can't be expired or an entry that can't be deleted doesn't stop the
+    // others, but makes the command fail
+    fn reflog(&self, command: &ReflogCommand) -> Result<()> {
+        let repository = self.repository()?;
+        let options = |args: &ReflogOptions, stale_fix: bool| reflog::Options {
+            rewrite: args.rewrite,
+            update_ref: args.updateref,
+            stale_fix,
+            dry_run: args.dry_run,
+            verbose: args.verbose,
+        };
+        let mut failed = false;
+        match command {
+            ReflogCommand::Expire(args) => {
+                let date = |date: &Option<String>, option: &str| -> Result<Option<i64>> {
+                    date.as_ref()
+                        .map(|date| {
+                            repository::parse_expiry_date(date).ok_or_else(|| {
+                                Error::InvalidTimestamp(date.clone(), option.to_string())
+                            })
+                        })
+                        .transpose()
+                };
+                let total = date(&args.expire, "expire")?;
+                let unreachable = date(&args.expire_unreachable, "expire-unreachable")?;
+                let options = options(&args.options, args.stale_fix);
+                if args.stale_fix && args.options.verbose {
+                    eprintln!("Marking reachable objects...");
+                }
+                if args.all {
+                    reflog::expire_all(
+                        &repository,
+                        total,
+                        unreachable,
+                        options,
+                        args.single_worktree,
+                    )?;
+                }
+                let config = Config::for_repository(repository.common_dir());
+                for name in &args.refs {
+                    let Some(full_name) = repository.reflog_name(name) else {
+                        eprintln!("error: {} points nowhere!", name);
+                        failed = true;
+                        continue;
+                    };
+                    let expiry = Expiry::for_reflog(&config, &full_name, total, unreachable)?;
+                    reflog::expire(&repository, &full_name, expiry, None, options)?;
+                }
+            }
+            ReflogCommand::Delete {
+                options: args,
+                entries,
+            } => {
+                let never = Expiry {
+                    total: 0,
+                    unreachable: 0,
+                };
+                for entry in entries {
+                    let Some((name, selector)) = entry.split_once("@{") else {
+                        eprintln!("error: not a reflog: {}", entry);
+                        failed = true;
+                        continue;
+                    };
+                    let Some(full_name) = repository.reflog_name(name) else {
+                        eprintln!("error: no reflog for '{}'", entry);
+                        failed = true;
+                        continue;
+                    };
+                    let selector = selector.strip_suffix('}').unwrap_or(selector);
+                    let drop = reflog::selected_entry(&repository, &full_name, selector);
+                    reflog::expire(&repository, &full_name, never, drop, options(args, false))?;
+                }
+            }
+        }
+        match failed {
+            true => Err(Error::Exit(255)),
+            false => Ok(()),
+        }
+    }
 
-        let mut entries = parts
-            .skip(1)
-            .enumerate()
-            .filter(|(_, x)| !x.is_empty())
-            .fold(Vec::new(), |mut acc, (i, part)| {
-                if i == 0 {
-                    let (mode, name) = std::str::from_utf8(part).unwrap().split_once(' ').unwrap();
-                    acc.push((mode, name, "".into()));
-                } else {
-                    if part.len() < 20 {
-                        return acc
+    fn maintenance(&self, command: &MaintenanceCommand) -> Result<()> {
+        match command {
+            MaintenanceCommand::Run(args) => self.maintenance_run(args),
+            MaintenanceCommand::Start { scheduler } => {
+                let scheduler = match scheduler.as_deref() {
+                    None | Some("auto") => Scheduler::ALL
+                        .into_iter()
+                        .find(|scheduler| scheduler.is_available())
+                        .unwrap_or(Scheduler::Crontab),
+                    Some(name) => Scheduler::from_name(name).ok_or_else(|| {
+                        usage_error(
+                            "maintenance",
+                            &format!("unrecognized --scheduler argument '{}'", name),
+                        )
+                    })?,
+                };
+                maintenance::register(&self.repository()?)?;
+                // the schedule is in one place only
+                for other in Scheduler::ALL {
+                    if other != scheduler && other.is_available() {
+                        other.update(false)?;
+                    }
+                }
+                scheduler.update(true)
+            }
+            MaintenanceCommand::Stop => {
+                for scheduler in Scheduler::ALL {
+                    if scheduler.is_available() {
+                        scheduler.update(false)?;
                     }
-                    let sha = &part[0..20];
-                    acc[i - 1].2 = hex::encode(sha);
+                }
+                Ok(())
+            }
+            MaintenanceCommand::Register => maintenance::register(&self.repository()?),
+            MaintenanceCommand::Unregister => maintenance::unregister(&self.repository()?),
+        }
+    }
 
-                    if let Some((mode, name)) =
-                        std::str::from_utf8(&part[20..]).unwrap().split_once(' ')
-                    {
-                        acc.push((mode, name, "".into()))
+    // a task that fails doesn't stop the others, but makes the run fail
+    fn maintenance_run(&self, args: &MaintenanceRunArgs) -> Result<()> {
+        let mut selected = vec![];
+        for name in &args.tasks {
+            let task = Task::from_name(name).ok_or_else(|| {
+                usage_error("maintenance", &format!("'{}' is not a valid task", name))
+            })?;
+            if selected.contains(&task) {
+                return Err(usage_error(
+                    "maintenance",
+                    &format!("task '{}' cannot be selected multiple times", name),
+                ));
+            }
+            selected.push(task);
+        }
+        let schedule = match &args.schedule {
+            Some(name) => Some(Schedule::from_name(name).ok_or_else(|| {
+                usage_error(
+                    "maintenance",
+                    &format!("unrecognized --schedule argument '{}'", name),
+                )
+            })?),
+            None => None,
+        };
+
+        let repository = self.repository()?.without_replacements();
+        let object_dir = repository.object_dir();
+        let Some(_lock) = maintenance::Lock::acquire(&object_dir)? else {
+            eprintln!(
+                "error: lock file '{}' exists, skipping maintenance",
+                object_dir.join("maintenance.lock").display()
+            );
+            return Ok(());
+        };
+        let config = Config::for_repository(repository.common_dir());
+        let mut progress = progress_for(&args.progress);
+        let mut failed = false;
+        for task in maintenance::tasks(&config, &selected, schedule) {
+            if let Err(error) = task.run(&repository, progress.as_mut()) {
+                eprintln!("error: {}", error);
+                eprintln!("error: task '{}' failed", task.name());
+                failed = true;
+            }
+        }
+        match failed {
+            true => Err(Error::Exit(1)),
+            false => Ok(()),
+        }
+    }
+
+    // with no action, one argument gets a value and two set it. Values are read from the
+    // system's, the user's and the repository's config together, includes and all, unless a
+    // file is named, and written to the repository's; as in git, mistakes exit 1 or 2, and unsetting what isn't there 5
+    fn config(&self, args: &ConfigArgs) -> Result<()> {
+        let (allowed, expected) = match () {
+            _ if args.list || args.edit => (0..=0, "0"),
+            _ if args.add => (2..=2, "2"),
+            _ => (1..=2, "from 1 to 2"),
+        };
+        if !allowed.contains(&args.args.len()) {
+            return Err(usage_error(
+                "config",
+                &format!("wrong number of arguments, should be {}", expected),
+            ));
+        }
+        let local = || match self.repository() {
+            Ok(repository) => Ok(repository.common_dir().join("config")),
+            Err(_) => Err(Error::NotInGitDirectory),
+        };
+        let file = match &args.file {
+            Some(file) => Some(file.clone()),
+            None if args.system => Some(config::system_file()),
+            None if args.global => Some(config::global_file().ok_or(Error::NoHome)?),
+            None if args.local => Some(local()?),
+            None => None,
+        };
+        let read = || match (&file, self.repository()) {
+            (Some(file), _) => Config::from_file(file),
+            (None, Ok(repository)) => Config::for_repository(repository.common_dir()),
+            (None, Err(_)) => Config::user(),
+        };
+        let writable = || file.clone().map_or_else(local, Ok);
+        let fail = |error: Error, code: i32| {
+            eprintln!("error: {}", error);
+            Error::Exit(code)
+        };
+
+        if args.list {
+            let config = read();
+            let mut out = io::stdout().lock();
+            for (name, value) in config.entries() {
+                match value {
+                    Some(value) => writeln!(out, "{}={}", name, value)?,
+                    None => writeln!(out, "{}", name)?,
+                }
+            }
+            return Ok(());
+        }
+        if args.edit {
+            return editor::edit(&writable()?, &read());
+        }
+
+        let key = &args.args[0];
+        let (getting, unsetting) = (args.get || args.get_all, args.unset || args.unset_all);
+        let setting = !getting && !unsetting && args.args.len() == 2;
+        let writing = setting || unsetting || args.add;
+        if let Err(error) = config::check_key(key) {
+            let code = match error {
+                Error::ConfigKeyNoSection(_) | Error::ConfigKeyNoName(_) if writing => 2,
+                _ => 1,
+            };
+            return Err(fail(error, code));
+        }
+        // a value pattern is a regex, which a leading "!" negates
+        let pattern = match args.args.get(1).filter(|_| getting || unsetting) {
+            Some(pattern) => {
+                let (negated, pattern) = match pattern.strip_prefix('!') {
+                    Some(pattern) => (true, pattern),
+                    None => (false, pattern.as_str()),
+                };
+                let regex = Regex::new(pattern)
+                    .map_err(|_| fail(Error::InvalidValuePattern(pattern.to_string()), 6))?;
+                Some((negated, regex))
+            }
+            None => None,
+        };
+        let matches = |value: Option<&str>| {
+            pattern.as_ref().is_none_or(|(negated, regex)| {
+                regex.is_match(value.unwrap_or("").as_bytes()) != *negated
+            })
+        };
+
+        if args.add {
+            return Ok(config::add_value(&writable()?, key, &args.args[1])?);
+        }
+        if unsetting {
+            let path = writable()?;
+            let found = Config::from_file(&path)
+                .values(key)
+                .into_iter()
+                .filter(|value| matches(*value))
+                .count();
+            if found > 1 && args.unset {
+                eprintln!("warning: {} has multiple values", key);
+            }
+            if found == 0 || (found > 1 && args.unset) {
+                return Err(Error::Exit(5));
+            }
+            config::remove_values(&path, key, matches)?;
+            return Ok(());
+        }
+        if setting {
+            let (path, value) = (writable()?, &args.args[1]);
+            if Config::from_file(&path).values(key).len() > 1 {
+                eprintln!("warning: {} has multiple values", key);
+                eprintln!("error: cannot overwrite multiple values with a single value");
+                eprintln!(
+                    "       Use a regexp, --add or --replace-all to change {}.",
+                    key
+                );
+                return Err(Error::Exit(5));
+            }
+            return Ok(config::set_value(&path, key, value)?);
+        }
+
+        let config = read();
+        let values: Vec<&str> = config
+            .values(key)
+            .into_iter()
+            .filter(|value| matches(*value))
+            .map(|value| value.unwrap_or(""))
+            .collect();
+        let values = match args.get_all {
+            true => &values[..],
+            false => &values[values.len().saturating_sub(1)..],
+        };
+        if values.is_empty() {
+            return Err(Error::Exit(1));
+        }
+        let mut out = io::stdout().lock();
+        for value in values {
+            writeln!(out, "{}", value)?;
+        }
+        Ok(())
+    }
+
+    // runs what "alias.<name>" stands for: git arguments to run in place of the name, or after
+    // a "!" a shell command, run from the top of the work tree with GIT_PREFIX set to where it
+    // was started; either way the arguments given follow
+    fn alias(&mut self, program: &str, args: &[String]) -> Result<()> {
+        let (name, args) = args.split_first().ok_or(Error::Exit(1))?;
+        let repository = self.repository().ok();
+        let config = match &repository {
+            Some(repository) => Config::for_repository(repository.common_dir()),
+            None => Config::user(),
+        };
+        let Some(alias) = config.get(&format!("alias.{}", name)) else {
+            match self.aliases.last() {
+                Some(alias) => eprintln!(
+                    "expansion of alias '{}' failed; '{}' is not a git command",
+                    alias, name
+                ),
+                None => eprintln!("git: '{}' is not a git command. See 'git --help'.", name),
+            }
+            return Err(Error::Exit(1));
+        };
+        if self.aliases.contains(name) {
+            let chain: Vec<String> = self
+                .aliases
+                .iter()
+                .enumerate()
+                .map(|(i, alias)| {
+                    let repeated = if alias == name { " <==" } else { "" };
+                    let last = if i == self.aliases.len() - 1 {
+                        " ==>"
+                    } else {
+                        ""
                     };
+                    format!("  {}{}{}", alias, repeated, last)
+                })
+                .collect();
+            return Err(Error::AliasLoop(self.aliases[0].clone(), chain.join("\n")));
+        }
+
+        if let Some(shell) = alias.strip_prefix('!') {
+            tracing::trace!(target: "command", "run_command: {} {}", shell, trace::command_line(args));
+            let mut command = std::process::Command::new("sh");
+            match args.is_empty() {
+                true => command.arg("-c").arg(shell),
+                false => command.arg("-c").arg(format!("{} \"$@\"", shell)),
+            };
+            command.arg(shell).args(args);
+            if let Some(repository) = &repository {
+                if let Some(work_tree) = repository.work_tree() {
+                    let prefix = cwd_prefix(repository)?;
+                    command.current_dir(work_tree).env(
+                        "GIT_PREFIX",
+                        prefix.map_or(String::new(), |prefix| format!("{}/", prefix)),
+                    );
                 }
+            }
+            return match command.status()?.code() {
+                Some(0) => Ok(()),
+                code => Err(Error::Exit(code.unwrap_or(128))),
+            };
+        }
 
-                acc
-            });
-        entries.sort_by(|a, b| a.1.cmp(b.1));
-        for (_mode, name, _sha) in entries {
-            println!("{}", name)
+        let words = split_command_line(alias)
+            .ok_or_else(|| Error::BadAlias(name.clone(), "unclosed quote".to_string()))?;
+        if words.is_empty() {
+            eprintln!(
+                "expansion of alias '{}' failed; '' is not a git command",
+                name
+            );
+            return Err(Error::Exit(1));
         }
+        tracing::trace!(target: "command", "alias expansion: {} => {}", name, trace::command_line(&words));
+        self.aliases.push(name.clone());
+        let args = [program.to_string()]
+            .into_iter()
+            .chain(words)
+            .chain(args.iter().cloned())
+            .collect();
+        self.run(args)
     }
 
-    fn parse_tree_object(&self, content: &[u8]) {
-        let parts = content.split(|x| *x == b'\n');
+    // runs "git -C <path> <args>" for each path `key` lists, carrying on past failures
+    fn for_each_repo(&self, key: &str, args: &[String]) -> Result<()> {
+        let config = match self.repository() {
+            Ok(repository) => Config::for_repository(repository.common_dir()),
+            Err(_) => Config::user(),
+        };
+        let program = env::current_exe()?;
+        let mut failed = false;
+        for path in config.get_all(key) {
+            let status = std::process::Command::new(&program)
+                .arg("-C")
+                .arg(paths::expand_home(path))
+                .args(args)
+                .status()?;
+            failed |= !status.success();
+        }
+        match failed {
+            true => Err(Error::Exit(1)),
+            false => Ok(()),
+        }
+    }
 
-        let mut entries = parts
-            .skip(1)
-            .enumerate()
-            .filter(|(_, x)| !x.is_empty())
-            .fold(Vec::new(), |mut acc, (i, part)| {
-                if i == 0 {
-                    let (mode, name) = std::str::from_utf8(part).unwrap().split_once(' ').unwrap();
-                    acc.push((mode, name, "".into()));
-                } else {
-                    if part.len() < 20 {
-                        panic!("Invalid tree entry: {}", String::from_utf8_lossy(part));
-                    }
-                    let sha = &part[0..20];
-                    acc[i - 1].2 = hex::encode(sha);
+    fn check_attr(&self, all: bool, args: &[String], paths: &[String]) -> Result<()> {
+        let (names, paths) = match (paths.is_empty(), all) {
+            (false, _) => (args, paths),
+            (true, true) => (&args[..0], args),
+            (true, false) => (&args[..1.min(args.len())], &args[1.min(args.len())..]),
+        };
 
-                    if let Some((mode, name)) =
-                        std::str::from_utf8(&part[20..]).unwrap().split_once(' ')
-                    {
-                        acc.push((mode, name, "".into()))
-                    };
+        let repository = self.repository()?;
+        let work_tree = repository.work_tree().ok_or(Error::NoWorkTree)?;
+        let mut attributes = Attributes::new(work_tree, repository.common_dir());
+        let prefix = cwd_prefix(&repository)?;
+        for path in paths {
+            let full_path = match &prefix {
+                Some(prefix) => format!("{}/{}", prefix, path),
+                None => path.clone(),
+            };
+            if all {
+                for (name, state) in attributes.check_all(&full_path) {
+                    println!("{}: {}: {}", path, name, state);
                 }
+            } else {
+                for name in names {
+                    println!("{}: {}: {}", path, name, attributes.check(&full_path, name));
+                }
+            }
+        }
 
-                acc
-            });
-        entries.sort_by(|a, b| a.1.cmp(b.1));
-        for (_mode, name, _sha) in entries {
-            println!("{}", name)
+        Ok(())
+    }
+
+    // stdin is hashed as it is, files are converted the way they would be when added
+    fn hash_object(
+        &self,
+        files: &[String],
+        object_type: &str,
+        write: bool,
+        stdin: bool,
+    ) -> Result<()> {
+        let object_type: GitObjectType = object_type.parse()?;
+        let repository = self.repository()?;
+        let converter = Mutex::new(self.converter(&repository));
+        if stdin {
+            let mut content = vec![];
+            io::stdin().lock().read_to_end(&mut content)?;
+            println!(
+                "{}",
+                store_object(&repository, object_type, &content, write)?
+            );
+        }
+
+        for file_path in files {
+            let file_path = Path::new(file_path);
+            let id = if object_type == GitObjectType::Blob {
+                // attributes match against the path inside the work tree
+                let path = repository
+                    .work_tree()
+                    .and_then(|work_tree| {
+                        let work_tree = fs::canonicalize(work_tree).ok()?;
+                        let file_path = fs::canonicalize(file_path).ok()?;
+                        Some(paths::to_git(file_path.strip_prefix(work_tree).ok()?))
+                    })
+                    .unwrap_or_else(|| paths::to_git(file_path));
+                self.make_blob_object(&repository, file_path, &path, &converter, write)?
+            } else {
+                store_object(&repository, object_type, &fs::read(file_path)?, write)?
+            };
+            println!("{}", id);
+        }
+
+        Ok(())
+    }
+
+    // `path` is the "/"-separated path relative to the work tree, used for attribute lookups
+    fn make_blob_object(
+        &self,
+        repository: &Repository,
+        file_path: &Path,
+        path: &str,
+        converter: &Mutex<Converter>,
+        write: bool,
+    ) -> Result<String> {
+        let lock = || converter.lock().unwrap_or_else(|error| error.into_inner());
+        if !lock().converts_to_git(path) {
+            // nothing to convert, so the file never has to be in memory as a whole
+            let file = fs::File::open(file_path)?;
+            let size = file.metadata()?.len();
+            return match write {
+                true => repository.write_object_from(GitObjectType::Blob, size, file),
+                false => Ok(objects::hash_object_from(GitObjectType::Blob, size, file)?),
+            };
         }
+        let content = fs::read(file_path)?;
+        let content = lock().convert_to_git(path, content)?;
+        store_object(repository, GitObjectType::Blob, &content, write)
     }
 
-    fn read_object(&self, blob_sha: &str) -> Vec<u8> {
-        let subfolder = &blob_sha[0..2];
-        let binary_content =
-            fs::read(format!(".git/objects/{}/{}", subfolder, &blob_sha[2..])).unwrap();
-        let mut content = Vec::new();
-        flate2::read::ZlibDecoder::new(&binary_content[..])
-            .read_to_end(&mut content)
-            .unwrap();
+    fn converter(&self, repository: &Repository) -> Converter {
+        let git_dir = repository.common_dir();
+        let work_tree = repository.work_tree().unwrap_or(git_dir);
+        Converter::new(
+            self.attributes(repository),
+            &Config::load(git_dir),
+            work_tree,
+        )
+    }
 
-        content
+    fn attributes(&self, repository: &Repository) -> Attributes {
+        let git_dir = repository.common_dir();
+        // without a work tree only $GIT_DIR/info/attributes applies
+        let work_tree = repository.work_tree().unwrap_or(git_dir);
+        Attributes::new(work_tree, git_dir)
     }
 
-    fn write_tree(&self) {
-        let tree_hash = self.make_tree_object(".");
-        println!("{}", hex::encode(tree_hash));
+    fn ls_tree(&self, args: &LsTreeArgs) -> Result<()> {
+        let repository = self.repository()?;
+        let id = repository.rev_parse(&args.tree_ish)?;
+        let tree = repository.peel(&id, GitObjectType::Tree)?;
+        let entries = objects::parse_tree(&tree.data)?;
+        // paths are relative to the current directory, which limits the listing by itself
+        let cwd = cwd_prefix(&repository)?;
+        let pathspecs = match (&cwd, args.paths.is_empty()) {
+            (Some(cwd), true) => vec![format!("{}/", cwd)],
+            _ => args
+                .paths
+                .iter()
+                .map(|path| pathspec::resolve(cwd.as_deref(), path))
+                .collect::<Result<_>>()?,
+        };
+        let listing = TreeListing {
+            repository: &repository,
+            args,
+            pathspecs,
+            cwd: cwd.filter(|_| !args.full_name),
+        };
+        listing.list(&entries, "", &mut io::stdout().lock())
     }
 
-    fn make_tree_object(&self, path: &str) -> Vec<u8> {
-        let mut tree_entries = Vec::new();
-        let git_ignore = if let Ok(file) = fs::read(".gitignore") {
+    fn write_tree(&self) -> Result<()> {
+        let repository = self.repository()?;
+        let work_tree = repository.work_tree().ok_or(Error::NoWorkTree)?;
+        let git_ignore = if let Ok(file) = fs::read(work_tree.join(".gitignore")) {
             String::from_utf8(file).unwrap_or_default()
         } else {
             "".into()
         };
+        let mut files = vec![];
+        let tree = self.scan_tree(&repository, work_tree, &git_ignore, &mut files)?;
 
-        if let Ok(entries) = fs::read_dir(path) {
-            entries
-                .filter(|entry| {
-                    let file_name = entry.as_ref().unwrap().file_name();
-                    if git_ignore.contains(file_name.to_str().unwrap()) {
-                        return false;
-                    }
+        // hashing and compressing the files is the bulk of the work, so it is spread over
+        // threads; only the trees are put together in order
+        let converter = Mutex::new(self.converter(&repository));
+        let ids = parallel::map(&files, parallel::available_workers(), |path: &PathBuf| {
+            let git_path = paths::to_git(path.strip_prefix(work_tree).unwrap_or(path));
+            self.make_blob_object(&repository, path, &git_path, &converter, true)
+        })?;
+        let tree_hash = make_tree_object(&repository, tree, &ids)?;
+        println!("{}", tree_hash);
+        Ok(())
+    }
+
+    // collects the entries of the directory at `path`, adding the files to hash to `files`
+    fn scan_tree(
+        &self,
+        repository: &Repository,
+        path: &Path,
+        git_ignore: &str,
+        files: &mut Vec<PathBuf>,
+    ) -> Result<PendingTree> {
+        let mut tree = PendingTree::default();
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            // ".GIT" is the repository too on case-insensitive filesystems
+            if git_ignore.contains(&name)
+                || name.eq_ignore_ascii_case(".git")
+                || entry.path() == repository.git_dir()
+            {
+                continue;
+            }
+
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() && entry.path().join(".git").exists() {
+                // a nested repository is recorded as a gitlink to its checked out commit
+                let Some(commit) = Repository::open(&entry.path())
+                    .ok()
+                    .and_then(|nested| nested.head())
+                else {
+                    continue;
+                };
+                tree.entries.push(TreeEntry {
+                    mode: "160000".into(),
+                    name,
+                    id: commit,
+                });
+            } else if file_type.is_dir() {
+                let subtree = self.scan_tree(repository, &entry.path(), git_ignore, files)?;
+                tree.subtrees.push((name, subtree));
+            } else if file_type.is_symlink() {
+                let target = symlink_target(&entry.path())?;
+                tree.entries.push(TreeEntry {
+                    mode: "120000".into(),
+                    name,
+                    id: repository.create_blob(&target)?,
+                });
+            } else if file_type.is_file() {
+                tree.files
+                    .push((file_mode(&entry.path())?, name, files.len()));
+                files.push(entry.path());
+            }
+        }
+
+        Ok(tree)
+    }
 
-                    if file_name.to_str().unwrap() == ".git" {
-                        return false;
+    fn read_tree(&self, trees: &[String], merge: bool, prefix: Option<&str>) -> Result<()> {
+        if (merge && trees.len() > 3) || (prefix.is_some() && trees.len() > 1) {
+            return Err(usage_error(
+                "read-tree",
+                "-m takes at most three trees and --prefix only one",
+            ));
+        }
+        let repository = self.repository()?;
+        let mut index = repository.index()?;
+        let prefix = prefix
+            .map(|prefix| prefix.trim_end_matches('/'))
+            .filter(|prefix| !prefix.is_empty())
+            .map(|prefix| format!("{}/", prefix));
+        let trees = trees
+            .iter()
+            .map(|tree| {
+                let id = repository.rev_parse(tree)?;
+                repository.tree_files(&id, prefix.as_deref().unwrap_or(""))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        match &trees[..] {
+            [files] if prefix.is_some() => {
+                for file in files {
+                    if index.entries().iter().any(|entry| entry.path == file.path) {
+                        return Err(Error::IndexOverlap(file.path.clone()));
+                    }
+                }
+                files.iter().for_each(|file| index.add(file.clone()));
+            }
+            // reading the tree the index already has keeps the stat data of unchanged files
+            [files] if merge => {
+                if index.has_conflicts() {
+                    return Err(Error::UnmergedIndex);
+                }
+                let mut merged = Index::default();
+                for file in files {
+                    match index.find(&file.path, 0) {
+                        Some(entry) if entry.same_content(file) => merged.add(entry.clone()),
+                        _ => merged.add(file.clone()),
                     }
+                }
+                index = merged;
+            }
+            [head, next] if merge => index.two_way_merge(head, next)?,
+            [base, ours, theirs] if merge => index.three_way_merge(base, ours, theirs)?,
+            // without -m each tree is laid over the ones before it
+            trees => {
+                index.clear();
+                for files in trees {
+                    files.iter().for_each(|file| index.add(file.clone()));
+                }
+            }
+        }
 
-                    true
-                })
-                .for_each(|entry| {
-                    let entry = entry.unwrap();
-                    if entry.file_type().unwrap().is_dir() {
-                        let mode = format!("{:o}", 0o40000);
-                        let tree_hash = self.make_tree_object(entry.path().to_str().unwrap());
-                        tree_entries.push((
-                            mode,
-                            entry.file_name().to_string_lossy().to_string(),
-                            tree_hash,
-                        ));
-                    } else if entry.file_type().unwrap().is_file() {
-                        let perms = entry.metadata().unwrap().permissions();
-                        let mode = format!("{:o}", perms.mode());
-                        let (_, hash) = self.make_blob_object(entry.path().to_str().unwrap());
-                        tree_entries.push((
-                            mode,
-                            entry.file_name().to_string_lossy().to_string(),
-                            hash,
-                        ));
+        index.write(&repository.index_path())
+    }
+
+    fn update_index(&self, args: &UpdateIndexArgs) -> Result<()> {
+        let executable = match args.chmod.as_deref() {
+            None => None,
+            Some("+x") => Some(true),
+            Some("-x") => Some(false),
+            Some(_) => {
+                return Err(usage_error(
+                    "update-index",
+                    "option 'chmod' expects \"+x\" or \"-x\"",
+                ))
+            }
+        };
+        let repository = self.repository()?;
+        let mut index = repository.index()?;
+        let converter = Mutex::new(self.converter(&repository));
+        let cwd = cwd_prefix(&repository)?;
+        let mut paths = vec![];
+
+        for cacheinfo in &args.cacheinfo {
+            let mut fields = cacheinfo.splitn(3, ',');
+            let (Some(mode), Some(id), Some(path)) = (fields.next(), fields.next(), fields.next())
+            else {
+                return Err(usage_error(
+                    "update-index",
+                    "option 'cacheinfo' expects <mode>,<sha1>,<path>",
+                ));
+            };
+            let mode = u32::from_str_radix(mode, 8).map_err(|_| {
+                Error::Usage(format!(
+                    "git update-index: {} cannot add to the index",
+                    path
+                ))
+            })?;
+            if id.len() != 40 || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(Error::InvalidObjectId(id.to_string()));
+            }
+            if index.find(path, 0).is_none() && !args.add {
+                eprintln!(
+                    "error: {}: cannot add to the index - missing --add option?",
+                    path
+                );
+                return Err(Error::UnprocessablePath(path.to_string()));
+            }
+            index.add(IndexEntry::new(path, mode, &id.to_ascii_lowercase()));
+            paths.push(path.to_string());
+        }
+
+        for name in &args.paths {
+            let path = pathspec::resolve(cwd.as_deref(), name)?
+                .trim_end_matches('/')
+                .to_string();
+            if args.force_remove {
+                index.remove(&path);
+                continue;
+            }
+            let work_tree = repository.work_tree().ok_or(Error::NoWorkTree)?;
+            let file = paths::to_native(work_tree, &path);
+            let metadata = match fs::symlink_metadata(&file) {
+                Ok(metadata) if metadata.is_dir() => {
+                    eprintln!("error: {}: is a directory - add files inside instead", name);
+                    return Err(Error::UnprocessablePath(name.clone()));
+                }
+                Ok(metadata) => metadata,
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                    if !args.remove {
+                        eprintln!("error: {}: does not exist and --remove not passed", name);
+                        return Err(Error::UnprocessablePath(name.clone()));
                     }
-                })
+                    index.remove(&path);
+                    continue;
+                }
+                Err(error) => return Err(error.into()),
+            };
+            let known = index.entries().iter().any(|entry| entry.path == path);
+            if !known && !args.add {
+                eprintln!(
+                    "error: {}: cannot add to the index - missing --add option?",
+                    name
+                );
+                return Err(Error::UnprocessablePath(name.clone()));
+            }
+            let entry =
+                self.work_tree_entry(&repository, &converter, &path, &file, &metadata, true)?;
+            index.add(entry);
+            paths.push(path);
         }
 
-        tree_entries.sort_by(|a, b| a.1.cmp(&b.1));
-        let content: Vec<u8> =
-            tree_entries
-                .iter()
-                .fold(vec![], |mut content, (mode, name, sha)| {
-                    content.extend(format!("{} {}\0", mode, name).as_bytes());
-                    content.extend(sha);
-                    content
-                });
+        if let Some(executable) = executable {
+            for path in &paths {
+                let Some(entry) = index.find_mut(path, 0) else {
+                    continue;
+                };
+                if entry.mode & 0o170000 != 0o100000 {
+                    return Err(Error::Usage(format!(
+                        "git update-index: cannot chmod {}x '{}'",
+                        if executable { '+' } else { '-' },
+                        path
+                    )));
+                }
+                entry.mode = if executable { 0o100755 } else { 0o100644 };
+            }
+        }
+
+        let mut stale = false;
+        if args.refresh {
+            stale = self.refresh_index(&repository, &mut index, &converter)?;
+        }
 
-        /*
-        [mode] [file/folder name]\0[SHA-1 of referencing blob or tree]
-        */
-        let (compressed, bin_hash) = self.make_git_object(&content, "tree");
-        let hash = hex::encode(&bin_hash[..]);
-        let subfolder = &hash[0..2];
-        fs::create_dir_all(format!(".git/objects/{}/", subfolder)).unwrap();
-        match fs::write(
-            format!(".git/objects/{}/{}", subfolder, &hash[2..]),
-            compressed,
-        ) {
-            Ok(_) => {}
-            Err(e) => println!("{e}"),
+        // config has the last word, so say when it will undo what was asked for
+        let config = Config::for_repository(repository.common_dir());
+        let untracked_cache = config
+            .get("core.untrackedCache")
+            .and_then(config::parse_bool);
+        if args.untracked_cache {
+            if untracked_cache == Some(false) {
+                eprintln!("warning: core.untrackedCache is set to 'false'; remove or change it, if you really want to enable the untracked cache");
+            }
+            let work_tree = repository.work_tree().ok_or(Error::NoWorkTree)?;
+            let cache = index
+                .take_untracked_cache()
+                .unwrap_or_else(|| UntrackedCache::new(work_tree));
+            index.set_untracked_cache(Some(cache));
+        } else if args.no_untracked_cache {
+            if untracked_cache == Some(true) {
+                eprintln!("warning: core.untrackedCache is set to 'true'; remove or change it, if you really want to disable the untracked cache");
+            }
+            index.set_untracked_cache(None);
+        }
+        if args.fsmonitor {
+            if fsmonitor::hook(&config).is_none() {
+                eprintln!("warning: core.fsmonitor is unset; set it if you really want to enable fsmonitor");
+            }
+            if index.fsmonitor_token().is_none() {
+                index.update_fsmonitor(Some(fsmonitor::FAKE_TOKEN.to_string()), None);
+            }
+        } else if args.no_fsmonitor {
+            if fsmonitor::hook(&config).is_some() {
+                eprintln!("warning: core.fsmonitor is set; remove it if you really want to disable fsmonitor");
+            }
+            index.update_fsmonitor(None, None);
+        }
+        let split_index = config.get("core.splitIndex").and_then(config::parse_bool);
+        if args.split_index {
+            if split_index == Some(false) {
+                eprintln!("warning: core.splitIndex is set to false; remove or change it, if you really want to enable split index");
+            }
+            index.set_split_index(None);
+            index.set_split_index(Some(repository.split_index(&config)?));
+        } else if args.no_split_index {
+            if split_index == Some(true) {
+                eprintln!("warning: core.splitIndex is set to true; remove or change it, if you really want to disable split index");
+            }
+            index.set_split_index(None);
+        }
+        index.write(&repository.index_path())?;
+        match stale && !args.quiet {
+            true => Err(Error::Exit(1)),
+            false => Ok(()),
+        }
+    }
+
+    // brings the stat data of the files that haven't changed up to date, printing the ones
+    // that have; says whether there were any
+    fn refresh_index(
+        &self,
+        repository: &Repository,
+        index: &mut Index,
+        converter: &Mutex<Converter>,
+    ) -> Result<bool> {
+        let work_tree = repository.work_tree().ok_or(Error::NoWorkTree)?;
+        let mut stale = false;
+        let mut last_conflict = None;
+        let entries = index.entries().to_vec();
+        for entry in entries {
+            if entry.stage != 0 {
+                if last_conflict.as_ref() != Some(&entry.path) {
+                    println!("{}: needs merge", entry.path);
+                    last_conflict = Some(entry.path);
+                }
+                stale = true;
+                continue;
+            }
+            let file = paths::to_native(work_tree, &entry.path);
+            let current = match fs::symlink_metadata(&file) {
+                Ok(metadata) if entry.stat_matches(&metadata) => continue,
+                Ok(metadata) if !metadata.is_dir() => self
+                    .work_tree_entry(repository, converter, &entry.path, &file, &metadata, false)
+                    .ok(),
+                _ => None,
+            };
+            match current {
+                Some(current) if current.same_content(&entry) => {
+                    index.add(IndexEntry {
+                        assume_valid: entry.assume_valid,
+                        extended_flags: entry.extended_flags,
+                        ..current
+                    });
+                }
+                _ => {
+                    println!("{}: needs update", entry.path);
+                    stale = true;
+                }
+            }
         }
 
-        bin_hash.as_slice().to_vec()
+        Ok(stale)
     }
 
-    fn commit_tree(&self, tree_hash: &str, message: &str, parent_hash: Option<&str>) {
-        let bin_hash = self.make_commit_object(tree_hash, message, parent_hash);
-        let hash = hex::encode(&bin_hash[..]);
-        println!("{}", hash);
+    // the index entry for the file at `file` as it is now, storing its blob when `write` is set
+    fn work_tree_entry(
+        &self,
+        repository: &Repository,
+        converter: &Mutex<Converter>,
+        path: &str,
+        file: &Path,
+        metadata: &fs::Metadata,
+        write: bool,
+    ) -> Result<IndexEntry> {
+        let id = if metadata.is_symlink() {
+            let target = symlink_target(file)?;
+            store_object(repository, GitObjectType::Blob, &target, write)?
+        } else {
+            self.make_blob_object(repository, file, path, converter, write)?
+        };
+        let mut entry = IndexEntry::new(path, work_tree_mode(file, metadata)?, &id);
+        entry.set_stat(metadata);
+
+        Ok(entry)
     }
 
-    fn make_commit_object(
+    fn diff_tree(
         &self,
-        tree_hash: &str,
-        message: &str,
-        parent_hash: Option<&str>,
-    ) -> Vec<u8> {
-        let committer_name = "Trung Tran";
-        let committer_email = "trungtran@email.com";
-        let mut content: Vec<u8> = Vec::new();
-        let now = chrono::Local::now();
-        let timestamp = now.timestamp();
-        // let timezone = now.timezone().offset_from_local_date();
-        let offset = now.offset();
-        let hour = offset.local_minus_utc() / 3600;
-        let timezone = format!("{}{:02}00", if hour < 0 { "-" } else { "+" }, hour.abs());
+        format: DiffFormat,
+        recursive: bool,
+        show_commit_id: bool,
+        root: bool,
+        args: &[String],
+        paths: &[String],
+    ) -> Result<()> {
+        let repository = self.repository()?;
+        // the first argument names a tree, the second one too if it can, and the rest are paths
+        let first = repository.rev_parse(&args[0])?;
+        let second = args.get(1).and_then(|arg| repository.rev_parse(arg).ok());
+        let rest = &args[if second.is_some() { 2 } else { 1 }..];
+        let cwd = cwd_prefix(&repository)?;
+        let args: Vec<String> = rest.iter().chain(paths).cloned().collect();
+        let pathspecs = Pathspecs::parse(cwd.as_deref(), &args)?;
 
-        content.extend(format!("tree {}\n", tree_hash).as_bytes());
-        if let Some(parent_hash) = parent_hash {
-            content.extend(format!("parent {}\n", parent_hash).as_bytes());
+        let (header, old, new) = match second {
+            Some(second) => (None, Some(first), second),
+            // a single commit is compared with its parent, but merges are not shown at all
+            None => {
+                let commit = repository.peel(&first, GitObjectType::Commit)?;
+                let parents = repository.commit_parents(&commit.id)?;
+                match &parents[..] {
+                    [] if !root => return Ok(()),
+                    [] => (Some(commit.id.clone()), None, commit.id),
+                    [parent] => (Some(commit.id.clone()), Some(parent.clone()), commit.id),
+                    _ => return Ok(()),
+                }
+            }
+        };
+        let side = |id: Option<&String>| -> Result<Vec<IndexEntry>> {
+            match id {
+                None => Ok(vec![]),
+                // showing content needs the files, so the trees are always gone into
+                Some(id) if recursive || format.shows_content() => repository.tree_files(id, ""),
+                Some(id) => tree_entries(&repository, id),
+            }
+        };
+        let changes: Vec<Change> = diff::diff_entries(&side(old.as_ref())?, &side(Some(&new))?)
+            .into_iter()
+            .filter(|change| {
+                pathspecs.matches(&change.path)
+                    // without -r, the trees the paths are in stand for them
+                    || (!recursive && !format.shows_content() && pathspecs.goes_into(&change.path))
+            })
+            .collect();
+        if changes.is_empty() {
+            return Ok(());
         }
-        content.extend(
-            format!(
-                "author {} <{}> {} {}\n",
-                committer_name, committer_email, timestamp, timezone
-            )
-            .as_bytes(),
-        );
-        content.extend(
-            format!(
-                "committer {} <{}> {} {}\n\n",
-                committer_name, committer_email, timestamp, timezone
-            )
-            .as_bytes(),
-        );
-        content.extend(format!("{}\n", message).as_bytes());
-        let (compressed, bin_hash) = self.make_git_object(&content, "commit");
-        let hash = hex::encode(&bin_hash[..]);
-        let subfolder = &hash[0..2];
-        fs::create_dir_all(format!(".git/objects/{}/", subfolder)).unwrap();
-        match fs::write(
-            format!(".git/objects/{}/{}", subfolder, &hash[2..]),
-            compressed,
-        ) {
-            Ok(_) => {}
-            Err(e) => println!("{e}"),
-        }
-
-        bin_hash.as_slice().to_vec()
-    }
-
-    fn clone(&self, url: &str, path: &str) {
-        // let refs 
-        // let refs = get_refs(url).unwrap();
-        // let packs = get_objects(url, refs.refs.iter().map(|x| x.hash.clone()).collect());
-        let current_dir = std::env::current_dir().unwrap();
-        let path = format!("{}/{}", current_dir.to_str().unwrap(), path);
-        println!("path = {path}");
-        println!("url = {url}");
-        let mut repo = Repo::new(url, &path);
-        repo.clone();
-    }
-
-    fn timestamp() -> u128 {
-        let time = std::time::SystemTime::now();
-        let since_the_epoch = time.duration_since(std::time::UNIX_EPOCH).unwrap();
-        since_the_epoch.as_millis()
+
+        let mut stdout = io::stdout().lock();
+        if let Some(header) = header.filter(|_| show_commit_id) {
+            writeln!(stdout, "{}", header)?;
+        }
+        self.write_diff(&repository, &mut stdout, &changes, &format)
+    }
+
+    fn diff_index(
+        &self,
+        format: DiffFormat,
+        cached: bool,
+        tree: &str,
+        paths: &[String],
+    ) -> Result<()> {
+        let repository = self.repository()?;
+        let tree = repository.tree_files(&repository.rev_parse(tree)?, "")?;
+        let index = repository.index()?;
+        if !cached {
+            let work_tree = self.work_tree_side(&repository, &index)?;
+            return self.write_changes(
+                &repository,
+                diff::diff_entries(&tree, &work_tree),
+                format,
+                paths,
+            );
+        }
+
+        // the paths with conflicts are unmerged whatever the tree has
+        let (merged, unmerged) = split_unmerged(&index);
+        let (conflicts, tree): (Vec<_>, Vec<_>) = tree
+            .into_iter()
+            .partition(|entry| unmerged.contains(&entry.path));
+        let mut changes = diff::diff_entries(&tree, &merged);
+        for path in unmerged {
+            changes.push(Change {
+                old: conflicts.iter().find(|entry| entry.path == path).cloned(),
+                new: None,
+                unmerged: true,
+                path,
+            });
+        }
+        self.write_changes(&repository, changes, format, paths)
+    }
+
+    fn diff_files(&self, format: DiffFormat, paths: &[String]) -> Result<()> {
+        let repository = self.repository()?;
+        let index = repository.index()?;
+        let work_tree = self.work_tree_side(&repository, &index)?;
+        let (merged, unmerged) = split_unmerged(&index);
+        let (unmerged_files, files): (Vec<_>, Vec<_>) = work_tree
+            .into_iter()
+            .partition(|entry| unmerged.contains(&entry.path));
+        let mut changes = diff::diff_entries(&merged, &files);
+
+        // a conflict is followed by how the work tree differs from our side of it
+        for path in unmerged {
+            let file = unmerged_files.iter().find(|entry| entry.path == path);
+            changes.push(Change {
+                path: path.clone(),
+                old: None,
+                new: file.cloned(),
+                unmerged: true,
+            });
+            if let Some(ours) = index.find(&path, 2) {
+                let ours = IndexEntry {
+                    stage: 0,
+                    ..ours.clone()
+                };
+                changes.extend(diff::diff_entries(&[ours], &Vec::from_iter(file.cloned())));
+            }
+        }
+        self.write_changes(&repository, changes, format, paths)
+    }
+
+    // prints the changes under the paths given relative to the current directory, in path order
+    fn write_changes(
+        &self,
+        repository: &Repository,
+        mut changes: Vec<Change>,
+        format: DiffFormat,
+        paths: &[String],
+    ) -> Result<()> {
+        let cwd = cwd_prefix(repository)?;
+        let pathspecs = Pathspecs::parse(cwd.as_deref(), paths)?;
+        changes.retain(|change| pathspecs.matches(&change.path));
+        changes.sort_by(|a, b| a.path.as_bytes().cmp(b.path.as_bytes()));
+
+        self.write_diff(repository, &mut io::stdout().lock(), &changes, &format)
+    }
+
+    // the changes in `format`; the formats showing content read what the work tree has for
+    // files whose id isn't known, as the index would store it
+    fn write_diff(
+        &self,
+        repository: &Repository,
+        out: &mut impl Write,
+        changes: &[Change],
+        format: &DiffFormat,
+    ) -> Result<()> {
+        if !format.shows_content() {
+            for change in changes {
+                change.write(out, format)?;
+            }
+            return Ok(());
+        }
+
+        let mut converter = None;
+        let mut content = |entry: &IndexEntry| -> Result<Vec<u8>> {
+            if entry.id != diff::NULL_ID {
+                return repository.entry_content(entry);
+            }
+            let work_tree = repository.work_tree().ok_or(Error::NoWorkTree)?;
+            let file = paths::to_native(work_tree, &entry.path);
+            if entry.mode == 0o120000 {
+                return Ok(symlink_target(&file)?);
+            }
+            let converter = converter.get_or_insert_with(|| self.converter(repository));
+            Ok(converter.convert_to_git(&entry.path, fs::read(&file)?)?)
+        };
+        let mut attributes = None;
+        let mut is_binary = |path: &str, old: &[u8], new: &[u8]| {
+            let attributes = attributes.get_or_insert_with(|| self.attributes(repository));
+            diff::is_binary(&attributes.check(path, "diff"), old, new)
+        };
+        if let DiffFormat::Patch(options) = format {
+            for change in changes {
+                if change.unmerged {
+                    writeln!(out, "* Unmerged path {}", change.path)?;
+                    continue;
+                }
+                let mut side = |entry: &IndexEntry| -> Result<Side> {
+                    let content = content(entry)?;
+                    let id = match entry.id.as_str() {
+                        diff::NULL_ID => objects::encode_object(GitObjectType::Blob, &content).0,
+                        id => id.to_string(),
+                    };
+                    Ok(Side {
+                        mode: entry.mode,
+                        id,
+                        content,
+                    })
+                };
+                let old = change.old.as_ref().map(&mut side).transpose()?;
+                let new = change.new.as_ref().map(&mut side).transpose()?;
+                let (old, new) = (old.as_ref(), new.as_ref());
+                let binary = is_binary(&change.path, patch::content(old), patch::content(new));
+                let path = &change.path;
+                match (old, new) {
+                    // a file that became another kind of file is deleted and added again
+                    (Some(old), Some(new)) if (old.mode ^ new.mode) & 0o170000 != 0 => {
+                        patch::write_patch(out, path, Some(old), None, binary, options)?;
+                        patch::write_patch(out, path, None, Some(new), binary, options)?;
+                    }
+                    _ => patch::write_patch(out, path, old, new, binary, options)?,
+                }
+            }
+            return Ok(());
+        }
+
+        let mut stats = vec![];
+        for change in changes {
+            let stat = match (&change.old, &change.new) {
+                _ if change.unmerged => FileStat::Unmerged,
+                (old, new) => {
+                    let same = matches!((old, new), (Some(old), Some(new))
+                        if old.id == new.id && old.id != diff::NULL_ID);
+                    let old = old.as_ref().map(&mut content).transpose()?;
+                    let new = new.as_ref().map(&mut content).transpose()?;
+                    let (old, new) = (old.as_deref(), new.as_deref());
+                    let binary = is_binary(
+                        &change.path,
+                        old.unwrap_or_default(),
+                        new.unwrap_or_default(),
+                    );
+                    FileStat::new(old, new, same, binary)
+                }
+            };
+            stats.push((change.path.clone(), stat));
+        }
+
+        match format {
+            // the plumbing commands don't follow the terminal's width
+            DiffFormat::Stat { color } => diff::write_stat(out, &stats, 80, *color)?,
+            _ => diff::write_numstat(out, &stats)?,
+        }
+
+        Ok(())
+    }
+
+    // the work tree as the index sees it: files whose stat data still matches, or that the
+    // fsmonitor hook vouches for, are taken to be what the index has, the others get NULL_ID,
+    // and missing ones are left out
+    fn work_tree_side(&self, repository: &Repository, index: &Index) -> Result<Vec<IndexEntry>> {
+        let work_tree = repository.work_tree().ok_or(Error::NoWorkTree)?;
+        let mut entries: Vec<IndexEntry> = vec![];
+        for entry in index.entries() {
+            // each stage of a conflict stands for the same file
+            if entries.last().is_some_and(|last| last.path == entry.path) {
+                continue;
+            }
+            if entry.stage == 0 && entry.fsmonitor_valid {
+                entries.push(entry.clone());
+                continue;
+            }
+            let file = paths::to_native(work_tree, &entry.path);
+            match fs::symlink_metadata(&file) {
+                Ok(metadata) if metadata.is_dir() => {}
+                Ok(metadata) if entry.stage == 0 && entry.stat_matches(&metadata) => {
+                    entries.push(entry.clone())
+                }
+                Ok(metadata) => entries.push(IndexEntry::new(
+                    &entry.path,
+                    work_tree_mode(&file, &metadata)?,
+                    diff::NULL_ID,
+                )),
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+                Err(error) => return Err(error.into()),
+            }
+        }
+
+        Ok(entries)
+    }
+
+    // the merged tree, then the stages of each conflicted path, then what happened to each
+    // path that needed merging; conflicts exit with 1 like git
+    fn merge_tree(&self, base: &str, ours: &str, theirs: &str, name_only: bool) -> Result<()> {
+        let repository = self.repository()?;
+        let merge = merge::merge_trees(
+            &repository,
+            &repository.rev_parse(base)?,
+            &repository.rev_parse(ours)?,
+            &repository.rev_parse(theirs)?,
+            (ours, theirs),
+            None,
+        )?;
+
+        let mut out = io::stdout().lock();
+        writeln!(out, "{}", merge.tree)?;
+        if merge.is_clean() {
+            return Ok(());
+        }
+        let mut listed: Vec<&str> = vec![];
+        for entry in &merge.conflicts {
+            if !name_only {
+                writeln!(
+                    out,
+                    "{:06o} {} {}\t{}",
+                    entry.mode, entry.id, entry.stage, entry.path
+                )?;
+            } else if !listed.contains(&entry.path.as_str()) {
+                writeln!(out, "{}", entry.path)?;
+                listed.push(&entry.path);
+            }
+        }
+        writeln!(out)?;
+        for message in &merge.messages {
+            writeln!(out, "{}", message)?;
+        }
+        out.flush()?;
+
+        Err(Error::Exit(1))
+    }
+
+    // merges `args.commits` into the current branch: fast-forwarding when there is one and it
+    // is ahead, and otherwise committing the merged trees, or leaving the conflicts in the
+    // index and the work tree with MERGE_HEAD set, for the user to resolve and commit
+    fn merge(&self, args: &MergeArgs) -> Result<()> {
+        let repository = self.repository()?;
+        let mut index = repository.index()?;
+        if args.abort {
+            return self.merge_abort(&repository, &mut index);
+        }
+        if index.has_conflicts() {
+            eprintln!("error: Merging is not possible because you have unmerged files.");
+            eprintln!("hint: Fix them up in the work tree, and then use 'git add/rm <file>'");
+            eprintln!("hint: as appropriate to mark resolution and make a commit.");
+            return Err(Error::UnresolvedConflict);
+        }
+        if repository.git_dir().join("MERGE_HEAD").exists() {
+            return Err(Error::MergeInProgress);
+        }
+        let strategy = args.strategy.as_deref();
+        if let Some(strategy) = strategy {
+            if !["ort", "recursive", "octopus", "ours"].contains(&strategy) {
+                eprintln!("Could not find merge strategy '{}'.", strategy);
+                eprintln!("Available strategies are: octopus ort ours recursive.");
+                return Err(Error::Exit(1));
+            }
+        }
+        let mut favor = None;
+        let mut subtree = None;
+        for option in &args.strategy_option {
+            match option.as_str() {
+                "ours" => favor = Some(Favor::Ours),
+                "theirs" => favor = Some(Favor::Theirs),
+                _ => match option.strip_prefix("subtree=") {
+                    Some(prefix) => subtree = Some(prefix),
+                    None => return Err(Error::UnknownStrategyOption(option.clone())),
+                },
+            }
+        }
+
+        let head = repository
+            .head()
+            .ok_or_else(|| Error::ObjectNotFound("HEAD".to_string()))?;
+        let mut heads: Vec<(&str, String)> = vec![];
+        for name in &args.commits {
+            let Ok(commit) = repository
+                .rev_parse(name)
+                .and_then(|id| repository.peel(&id, GitObjectType::Commit))
+            else {
+                eprintln!("merge: {} - not something we can merge", name);
+                return Err(Error::Exit(1));
+            };
+            if heads.iter().all(|(_, id)| *id != commit.id) {
+                heads.push((name, commit.id));
+            }
+        }
+        // commits HEAD or another of them already has are left out, and HEAD itself is left
+        // out of the parents when one of them has it
+        let others: Vec<String> = heads.iter().map(|(_, id)| id.clone()).collect();
+        heads.retain(|(_, id)| {
+            !repository.is_ancestor(id, &head)
+                && !others
+                    .iter()
+                    .any(|other| other != id && repository.is_ancestor(id, other))
+        });
+        if heads.is_empty() {
+            match args.squash {
+                true => println!("Already up to date. (nothing to squash)"),
+                false => println!("Already up to date."),
+            }
+            return Ok(());
+        }
+        let head_subsumed = heads
+            .iter()
+            .any(|(_, id)| repository.is_ancestor(&head, id));
+        // "ours" would only ever fast-forward to the tree it throws away
+        let no_ff = args.no_ff || strategy == Some("ours");
+
+        let head_files = repository.tree_files(&head, "")?;
+        if head_subsumed && heads.len() == 1 && !no_ff {
+            let theirs = &heads[0].1;
+            println!(
+                "Updating {}..{}",
+                pretty::abbreviate(&head),
+                pretty::abbreviate(theirs)
+            );
+            let files = repository.tree_files(theirs, "")?;
+            self.check_out(&repository, &mut index, &head_files, &files)?;
+            println!("Fast-forward");
+            index.write(&repository.index_path())?;
+            if args.squash {
+                println!("Squash commit -- not updating HEAD");
+                self.write_squash_message(&repository, &head, &heads)?;
+            } else {
+                repository.set_reference("ORIG_HEAD", &head)?;
+                repository.update_reference("HEAD", theirs)?;
+            }
+            return self.write_summary(&repository, &head_files, &files, false);
+        }
+
+        let strategy = match strategy {
+            Some(strategy) => strategy,
+            None if heads.len() > 1 => "octopus",
+            None => "ort",
+        };
+        let merge = match strategy {
+            "ours" => TreeMerge {
+                tree: repository.peel(&head, GitObjectType::Tree)?.id,
+                ..TreeMerge::default()
+            },
+            "octopus" => self.merge_octopus(&repository, &head, &heads, favor)?,
+            _ if heads.len() > 1 => {
+                eprintln!("error: Not handling anything other than two heads merge.");
+                eprintln!("Merge with strategy {} failed.", strategy);
+                return Err(Error::Exit(2));
+            }
+            _ => {
+                let (name, theirs) = &heads[0];
+                let base = repository
+                    .merge_bases(&head, std::slice::from_ref(theirs))?
+                    .into_iter()
+                    .next()
+                    .ok_or(Error::UnrelatedHistories)?;
+                // their side and the base are moved to where ours has the subtree
+                let (base, theirs) = match subtree {
+                    Some(prefix) => (
+                        merge::shift_tree(&repository, &head, &base, prefix)?,
+                        merge::shift_tree(&repository, &head, theirs, prefix)?,
+                    ),
+                    None => (base, theirs.clone()),
+                };
+                let merge =
+                    merge::merge_trees(&repository, &base, &head, &theirs, ("HEAD", name), favor)?;
+                for line in &merge.messages {
+                    println!("{}", line);
+                }
+                merge
+            }
+        };
+        let files = repository.tree_files(&merge.tree, "")?;
+        self.check_out(&repository, &mut index, &head_files, &files)?;
+        // a conflicted path has its stages in place of the merged file, which is left in the
+        // work tree with conflict markers
+        for entry in &merge.conflicts {
+            if entry.stage == 1 || index.find(&entry.path, 0).is_some() {
+                index.remove(&entry.path);
+            }
+        }
+        for entry in &merge.conflicts {
+            index.add(entry.clone());
+        }
+        index.write(&repository.index_path())?;
+
+        if args.squash {
+            println!("Squash commit -- not updating HEAD");
+            self.write_squash_message(&repository, &head, &heads)?;
+            if !merge.is_clean() {
+                self.reuse_resolutions(&repository, &mut index)?;
+                println!("Automatic merge failed; fix conflicts and then commit the result.");
+                return Err(Error::Exit(1));
+            }
+            println!("Automatic merge went well; stopped before committing as requested");
+            return Ok(());
+        }
+        repository.set_reference("ORIG_HEAD", &head)?;
+        let message = match &args.message {
+            Some(message) => message.clone(),
+            None => {
+                let names: Vec<&str> = heads.iter().map(|(name, _)| *name).collect();
+                merge_message(&repository, &names)
+            }
+        };
+        if !merge.is_clean() {
+            let mut message = format!("{}\n\n# Conflicts:\n", message);
+            let (_, unmerged) = split_unmerged(&index);
+            for path in unmerged {
+                message += &format!("#\t{}\n", path);
+            }
+            let merge_heads: String = heads.iter().map(|(_, id)| format!("{}\n", id)).collect();
+            let git_dir = repository.git_dir();
+            fs::write(git_dir.join("MERGE_HEAD"), merge_heads)?;
+            fs::write(git_dir.join("MERGE_MSG"), message)?;
+            fs::write(git_dir.join("MERGE_MODE"), if no_ff { "no-ff" } else { "" })?;
+            self.reuse_resolutions(&repository, &mut index)?;
+            println!("Automatic merge failed; fix conflicts and then commit the result.");
+            return Err(Error::Exit(1));
+        }
+
+        let mut parents: Vec<&str> = vec![];
+        if !head_subsumed || no_ff {
+            parents.push(&head);
+        }
+        parents.extend(heads.iter().map(|(_, id)| id.as_str()));
+        let config = Config::for_repository(repository.common_dir());
+        let author = signature(&config, "author")?;
+        let committer = signature(&config, "committer")?;
+        let commit =
+            repository.create_commit(&merge.tree, &parents, &author, &committer, &message)?;
+        repository.update_reference("HEAD", &commit)?;
+        println!("Merge made by the '{}' strategy.", strategy);
+        self.write_summary(&repository, &head_files, &files, false)
+    }
+
+    // merges the heads one after the other into the tree so far, the way git's octopus strategy
+    // does: the first is fast-forwarded to when HEAD is behind it, and only the last one may
+    // leave conflicts
+    // puts the index and the tracked files back as HEAD has them and forgets the merge
+    fn merge_abort(&self, repository: &Repository, index: &mut Index) -> Result<()> {
+        let git_dir = repository.git_dir();
+        if !git_dir.join("MERGE_HEAD").exists() {
+            return Err(Error::NoMergeToAbort);
+        }
+        let head_files = match repository.head() {
+            Some(head) => repository.tree_files(&head, "")?,
+            None => vec![],
+        };
+        self.reset_work_tree(repository, index, &head_files, &Pathspecs::default())?;
+        index.write(&repository.index_path())?;
+        for file in ["MERGE_HEAD", "MERGE_MSG", "MERGE_MODE"] {
+            match fs::remove_file(git_dir.join(file)) {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn merge_octopus(
+        &self,
+        repository: &Repository,
+        head: &str,
+        heads: &[(&str, String)],
+        favor: Option<Favor>,
+    ) -> Result<TreeMerge> {
+        let mut merged = vec![head.to_string()];
+        let mut reference = head.to_string();
+        let mut merge = TreeMerge::default();
+        for (name, id) in heads {
+            if !merge.is_clean() {
+                println!("Automated merge did not work.");
+                println!("Should not be doing an octopus.");
+                eprintln!("Merge with strategy octopus failed.");
+                return Err(Error::Exit(2));
+            }
+            let Some(base) = repository.merge_bases(id, &merged)?.into_iter().next() else {
+                eprintln!("Unable to find common commit with {}", name);
+                eprintln!("Merge with strategy octopus failed.");
+                return Err(Error::Exit(2));
+            };
+            if merge.tree.is_empty() && merged == [base.as_str()] {
+                println!("Fast-forwarding to: {}", name);
+                reference = id.clone();
+                merged = vec![id.clone()];
+                continue;
+            }
+
+            println!("Trying simple merge with {}", name);
+            merge = merge::merge_trees(repository, &base, &reference, id, ("HEAD", name), favor)?;
+            // files changed on both sides make it more than a simple merge
+            if !merge.messages.is_empty() {
+                println!("Simple merge did not work, trying automatic merge.");
+            }
+            for line in &merge.messages {
+                println!("{}", line);
+            }
+            reference = merge.tree.clone();
+            merged.push(id.clone());
+        }
+
+        Ok(merge)
+    }
+
+    // SQUASH_MSG, which the commit of a squashed merge takes its message from: every commit
+    // the merge brings in, as log shows them
+    fn write_squash_message(
+        &self,
+        repository: &Repository,
+        head: &str,
+        heads: &[(&str, String)],
+    ) -> Result<()> {
+        let include: Vec<String> = heads.iter().map(|(_, id)| id.clone()).collect();
+        let pretty = Pretty::new(Format::Medium);
+        let mut commits = vec![];
+        for id in repository.rev_list(&include, &[head.to_string()])? {
+            commits.push(pretty.format(&repository.find_commit(&id)?));
+        }
+        let message = format!(
+            "Squashed commit of the following:\n\n{}",
+            commits.join("\n")
+        );
+        fs::write(repository.git_dir().join("SQUASH_MSG"), message)?;
+        Ok(())
+    }
+
+    // lets rerere record the conflicts left in the index and the resolutions of those that
+    // were, and reuse the resolutions it has, staging the files it resolves when
+    // rerere.autoupdate is set
+    fn reuse_resolutions(&self, repository: &Repository, index: &mut Index) -> Result<()> {
+        if !rerere::is_enabled(repository) {
+            return Ok(());
+        }
+        let work_tree = repository.work_tree().ok_or(Error::NoWorkTree)?;
+        let autoupdate = rerere::autoupdate(repository);
+        let (_, unmerged) = split_unmerged(index);
+        let resolved = rerere::rerere(repository, work_tree, &unmerged, autoupdate)?;
+        if !autoupdate || resolved.is_empty() {
+            return Ok(());
+        }
+
+        let converter = Mutex::new(self.converter(repository));
+        for path in resolved {
+            let file = paths::to_native(work_tree, &path);
+            let metadata = fs::symlink_metadata(&file)?;
+            let entry =
+                self.work_tree_entry(repository, &converter, &path, &file, &metadata, true)?;
+            index.remove(&path);
+            index.add(entry);
+        }
+        index.write(&repository.index_path())
+    }
+
+    fn rerere(&self, command: Option<RerereCommand>) -> Result<()> {
+        let repository = self.repository()?;
+        let mut index = repository.index()?;
+        match command {
+            None => self.reuse_resolutions(&repository, &mut index),
+            Some(RerereCommand::Status) => {
+                for (_, path) in rerere::read_merge_rr(&repository) {
+                    println!("{}", path);
+                }
+                Ok(())
+            }
+            // the directories of conflicts that were never resolved go with them
+            Some(RerereCommand::Clear) => {
+                for (id, _) in rerere::read_merge_rr(&repository) {
+                    let dir = rerere::cache_dir(&repository).join(id);
+                    if !dir.join("postimage").exists() {
+                        let _ = fs::remove_dir_all(dir);
+                    }
+                }
+                rerere::write_merge_rr(&repository, &[])
+            }
+            Some(RerereCommand::Forget { paths }) => {
+                let cwd = cwd_prefix(&repository)?;
+                let pathspecs = Pathspecs::parse(cwd.as_deref(), &paths)?;
+                self.forget_resolutions(&repository, &index, &pathspecs)
+            }
+        }
+    }
+
+    // drops the resolutions recorded for the conflicts of the unmerged paths `pathspecs`
+    // match, which are merged again from their stages to find them, and records them as new
+    // conflicts of the merge going on
+    fn forget_resolutions(
+        &self,
+        repository: &Repository,
+        index: &Index,
+        pathspecs: &Pathspecs,
+    ) -> Result<()> {
+        let mut conflicts = rerere::read_merge_rr(repository);
+        let (_, unmerged) = split_unmerged(index);
+        for path in unmerged {
+            if !pathspecs.matches(&path) {
+                continue;
+            }
+            let content = |stage| -> Result<Vec<u8>> {
+                match index.find(&path, stage) {
+                    Some(entry) => repository.entry_content(entry),
+                    None => Ok(vec![]),
+                }
+            };
+            if index.find(&path, 2).is_none() || index.find(&path, 3).is_none() {
+                continue;
+            }
+            let (merged, _) =
+                merge::merge_content(&content(1)?, &content(2)?, &content(3)?, ("", ""), None);
+            let Some((id, preimage)) = rerere::normalize(&merged) else {
+                continue;
+            };
+            let dir = rerere::cache_dir(repository).join(&id);
+            fs::create_dir_all(&dir)?;
+            fs::write(dir.join("preimage"), preimage)?;
+            eprintln!("Updated preimage for '{}'", path);
+            if fs::remove_file(dir.join("postimage")).is_ok() {
+                eprintln!("Forgot resolution for '{}'", path);
+            }
+            conflicts.retain(|(_, known)| *known != path);
+            conflicts.push((id, path));
+        }
+        rerere::write_merge_rr(repository, &conflicts)
+    }
+
+    // moves the index and the work tree from the files of one tree to those of another, first
+    // making sure no change that hasn't been committed would be lost on the way
+    fn check_out(
+        &self,
+        repository: &Repository,
+        index: &mut Index,
+        from: &[IndexEntry],
+        to: &[IndexEntry],
+    ) -> Result<()> {
+        self.check_out_for(repository, index, from, to, "merge")
+    }
+
+    // check_out, naming `action` as what would overwrite the changes in the way
+    fn check_out_for(
+        &self,
+        repository: &Repository,
+        index: &mut Index,
+        from: &[IndexEntry],
+        to: &[IndexEntry],
+        action: &str,
+    ) -> Result<()> {
+        let work_tree = repository.work_tree().ok_or(Error::NoWorkTree)?;
+        let converter = Mutex::new(self.converter(repository));
+        let changes = diff::diff_entries(from, to);
+
+        // both the index and the file have to be what was checked out, and a file that isn't
+        // tracked can't be in the way
+        let (mut overwritten, mut untracked) = (vec![], vec![]);
+        for change in &changes {
+            let current = index.find(&change.path, 0);
+            let staged = match (current, &change.old) {
+                (Some(current), Some(old)) => !current.same_content(old),
+                (current, old) => current.is_some() || old.is_some(),
+            };
+            let file = paths::to_native(work_tree, &change.path);
+            let modified = match (fs::symlink_metadata(&file), current) {
+                (Ok(metadata), _) if metadata.is_dir() => false,
+                (Ok(metadata), Some(current)) if !current.stat_matches(&metadata) => {
+                    let entry = self.work_tree_entry(
+                        repository,
+                        &converter,
+                        &change.path,
+                        &file,
+                        &metadata,
+                        false,
+                    )?;
+                    !entry.same_content(current)
+                }
+                (Ok(_), None) if !staged => {
+                    untracked.push(change.path.as_str());
+                    continue;
+                }
+                _ => false,
+            };
+            if staged || modified {
+                overwritten.push(change.path.as_str());
+            }
+        }
+        let (paths, what, advice) = match overwritten.is_empty() {
+            false => (
+                overwritten,
+                "Your local changes to the following files",
+                "commit your changes or stash them",
+            ),
+            true => (
+                untracked,
+                "The following untracked working tree files",
+                "move or remove them",
+            ),
+        };
+        if !paths.is_empty() {
+            eprintln!("error: {} would be overwritten by {}:", what, action);
+            for path in paths {
+                eprintln!("\t{}", path);
+            }
+            let before = match action {
+                "checkout" => "switch branches",
+                action => action,
+            };
+            eprintln!("Please {} before you {}.", advice, before);
+            eprintln!("Aborting");
+            return Err(Error::Exit(1));
+        }
+
+        // files are removed first, so that a directory can take the place of one
+        let mut converter = converter
+            .into_inner()
+            .unwrap_or_else(|error| error.into_inner());
+        for change in changes.iter().filter(|change| change.new.is_none()) {
+            let file = paths::to_native(work_tree, &change.path);
+            match fs::remove_file(&file) {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error.into()),
+                _ => {}
+            }
+            remove_empty_parents(work_tree, &file);
+            index.remove(&change.path);
+        }
+        for change in &changes {
+            if let Some(new) = &change.new {
+                index.add(self.check_out_file(repository, &mut converter, new)?);
+            }
+        }
+
+        Ok(())
+    }
+
+    // writes the file `entry` records into the work tree, in place of whatever is there, and
+    // returns it with the stat data of the file
+    fn check_out_file(
+        &self,
+        repository: &Repository,
+        converter: &mut Converter,
+        entry: &IndexEntry,
+    ) -> Result<IndexEntry> {
+        let work_tree = repository.work_tree().ok_or(Error::NoWorkTree)?;
+        let file = paths::to_native(work_tree, &entry.path);
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if fs::symlink_metadata(&file).is_ok() {
+            fs::remove_file(&file)?;
+        }
+        let content = repository.find_object(&entry.id)?.data;
+        if entry.mode == 0o120000 {
+            git_client::write_symlink(&content, &file)?;
+        } else {
+            fs::write(
+                &file,
+                converter.convert_to_working_tree(&entry.path, content)?,
+            )?;
+            git_client::set_executable(&file, entry.mode == 0o100755)?;
+        }
+        let mut entry = IndexEntry::new(&entry.path, entry.mode, &entry.id);
+        entry.set_stat(&fs::symlink_metadata(&file)?);
+
+        Ok(entry)
+    }
+
+    // records the index as a commit on top of HEAD, and of MERGE_HEAD when concluding a merge
+    fn commit(
+        &self,
+        messages: &[String],
+        gpg_sign: Option<&str>,
+        no_gpg_sign: bool,
+        template: Option<&str>,
+        verbose: bool,
+        signoff: bool,
+    ) -> Result<()> {
+        let repository = self.repository()?;
+        let mut index = repository.index()?;
+        if index.has_conflicts() {
+            for path in split_unmerged(&index).1 {
+                println!("U\t{}", path);
+            }
+            eprintln!("error: Committing is not possible because you have unmerged files.");
+            eprintln!("hint: Fix them up in the work tree, and then use 'git add/rm <file>'");
+            eprintln!("hint: as appropriate to mark resolution and make a commit.");
+            return Err(Error::UnresolvedConflict);
+        }
+
+        let git_dir = repository.git_dir();
+        let merge_heads = fs::read_to_string(git_dir.join("MERGE_HEAD")).unwrap_or_default();
+        let head = repository.head();
+        let mut parents: Vec<&str> = head.iter().map(String::as_str).collect();
+        parents.extend(merge_heads.lines().filter(|line| !line.is_empty()));
+        let tree = repository.write_tree_entries(index.entries())?;
+        let head_tree = head
+            .as_ref()
+            .map(|head| repository.peel(head, GitObjectType::Tree))
+            .transpose()?;
+        if parents.len() < 2 && head_tree.is_some_and(|head_tree| head_tree.id == tree) {
+            self.status(None, &[])?;
+            return Err(Error::Exit(1));
+        }
+
+        // without -m the message is edited in COMMIT_EDITMSG, starting from the one a squashed
+        // merge prepared followed by the one a merge did, or else from the template, and its
+        // comment lines are dropped, as is everything below the scissors line -v puts in
+        let config = Config::for_repository(repository.common_dir());
+        let comment = message::comment_char(&config);
+        let edit = messages.is_empty();
+        let prepared = |name: &str| fs::read_to_string(git_dir.join(name)).unwrap_or_default();
+        let mut template_text = None;
+        let mut message = match edit {
+            true => prepared("SQUASH_MSG") + &prepared("MERGE_MSG"),
+            false => message::stripspace(&messages.join("\n\n"), None),
+        };
+        if edit && message.is_empty() {
+            if let Some(file) = template.or(config.get("commit.template")) {
+                let text = fs::read_to_string(paths::expand_home(file)).map_err(|error| {
+                    io::Error::new(
+                        error.kind(),
+                        format!("could not read '{}': {}", file, error),
+                    )
+                })?;
+                message += &text;
+                template_text = Some(text);
+            }
+        }
+        let author = signature(&config, "author")?;
+        let committer = signature(&config, "committer")?;
+        if signoff {
+            let identity = format!("{} <{}>", committer.name, committer.email);
+            trailer::sign_off(&mut message, &identity, comment);
+        }
+        if edit {
+            message += &self.commit_template(&repository, comment, parents.len() > 1, verbose)?;
+        }
+        let path = git_dir.join("COMMIT_EDITMSG");
+        fs::write(&path, &message)?;
+        if edit {
+            match editor::edit(&path, &config) {
+                Err(error @ (Error::EditorFailed(_) | Error::NoEditor)) => {
+                    eprintln!("error: {}", error);
+                    eprintln!("Please supply the message using either -m or -F option.");
+                    return Err(Error::Exit(1));
+                }
+                result => result?,
+            }
+            message = fs::read_to_string(&path)?;
+            if verbose {
+                message.truncate(message::cut(&message, comment).len());
+            }
+        }
+        let message = message::stripspace(&message, edit.then_some(comment));
+        if trailer::is_empty(&message) {
+            eprintln!("Aborting commit due to empty commit message.");
+            return Err(Error::Exit(1));
+        }
+        // a template left as it was, with nothing but blank lines and sign-offs added, isn't a
+        // message
+        let untouched = template_text.is_some_and(|text| {
+            message
+                .strip_prefix(&message::stripspace(&text, Some(comment)))
+                .is_some_and(trailer::is_empty)
+        });
+        if untouched {
+            eprintln!("Aborting commit; you did not edit the message.");
+            return Err(Error::Exit(1));
+        }
+        let message = message.trim_end();
+
+        let mut content = repository::commit_content(&tree, &parents, &author, &committer, message);
+        if let Some(key) = signing_key(&config, gpg_sign, no_gpg_sign, "commit.gpgSign") {
+            let identity = format!("{} <{}>", committer.name, committer.email);
+            let signature = sign::signer(&config, &key, &identity)?.sign(content.as_bytes())?;
+            content = sign::add_signature_header(&content, &signature);
+        }
+        let commit = repository.write_object(GitObjectType::Commit, content.as_bytes())?;
+        repository.update_reference("HEAD", &commit)?;
+        self.reuse_resolutions(&repository, &mut index)?;
+        for name in [
+            "MERGE_HEAD",
+            "MERGE_MSG",
+            "MERGE_MODE",
+            "MERGE_RR",
+            "SQUASH_MSG",
+        ] {
+            let _ = fs::remove_file(git_dir.join(name));
+        }
+
+        let branch = match repository.head_target() {
+            Some(target) => target
+                .strip_prefix("refs/heads/")
+                .unwrap_or(&target)
+                .to_string(),
+            None => "detached HEAD".to_string(),
+        };
+        let root = if head.is_none() { " (root-commit)" } else { "" };
+        let subject = pretty::subject(message);
+        println!(
+            "[{}{} {}] {}",
+            branch,
+            root,
+            pretty::abbreviate(&commit),
+            subject
+        );
+        // a merge isn't diffed against its first parent
+        if parents.len() > 1 {
+            return Ok(());
+        }
+        let head_files = match &head {
+            Some(head) => repository.tree_files(head, "")?,
+            None => vec![],
+        };
+        self.write_summary(&repository, &head_files, index.entries(), true)
+    }
+
+    // what merge and commit say they changed: a diffstat, or only its last line when `short`,
+    // then the files that were created or deleted or had their mode changed
+    fn write_summary(
+        &self,
+        repository: &Repository,
+        from: &[IndexEntry],
+        to: &[IndexEntry],
+        short: bool,
+    ) -> Result<()> {
+        let changes = diff::diff_entries(from, to);
+        let mut stat = vec![];
+        let format = DiffFormat::Stat { color: false };
+        self.write_diff(repository, &mut stat, &changes, &format)?;
+        if short {
+            let start = stat[..stat.len().saturating_sub(1)]
+                .iter()
+                .rposition(|&b| b == b'\n')
+                .map_or(0, |i| i + 1);
+            stat.drain(..start);
+        }
+        let mut out = io::stdout().lock();
+        out.write_all(&stat)?;
+        for change in &changes {
+            match (&change.old, &change.new) {
+                (None, Some(new)) => {
+                    writeln!(out, " create mode {:06o} {}", new.mode, change.path)?
+                }
+                (Some(old), None) => {
+                    writeln!(out, " delete mode {:06o} {}", old.mode, change.path)?
+                }
+                (Some(old), Some(new)) if old.mode != new.mode => writeln!(
+                    out,
+                    " mode change {:06o} => {:06o} {}",
+                    old.mode, new.mode, change.path
+                )?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // the branch, whether a merge is going on, the paths whose index differs from HEAD, that
+    // have conflicts, or whose work tree differs from the index, and the untracked files. The
+    // fsmonitor hook and the untracked cache save looking at what hasn't changed, and the index
+    // is written back to keep what they found if it can be
+    fn status(&self, untracked_files: Option<&str>, paths: &[String]) -> Result<()> {
+        self.write_status(&mut io::stdout().lock(), untracked_files, paths, false)
+    }
+
+    // what follows the message in COMMIT_EDITMSG: how to write it and the status, as comments
+    fn commit_template(
+        &self,
+        repository: &Repository,
+        comment: char,
+        merge: bool,
+        verbose: bool,
+    ) -> Result<String> {
+        let mut template = String::new();
+        if merge {
+            template += &message::comment_lines(
+                "\nIt looks like you may be committing a merge.\nIf this is not correct, please run\n\tgit update-ref -d MERGE_HEAD\nand try again.\n",
+                comment,
+            );
+            template.push('\n');
+        }
+        template.push('\n');
+        template += &message::comment_lines(
+            &format!(
+                "Please enter the commit message for your changes. Lines starting\nwith '{}' will be ignored, and an empty message aborts the commit.\n\n",
+                comment
+            ),
+            comment,
+        );
+        let mut status = vec![];
+        self.write_status(&mut status, None, &[], true)?;
+        template += &message::comment_lines(&String::from_utf8_lossy(&status), comment);
+        if verbose {
+            // what is staged, as diff --cached shows it, where the message stops
+            template += &message::cut_lines(comment);
+            let head_files = match repository.head() {
+                Some(head) => repository.tree_files(&head, "")?,
+                None => vec![],
+            };
+            let changes = diff::diff_entries(&head_files, repository.index()?.entries());
+            let format = DiffFormat::Patch(PatchOptions {
+                words: None,
+                binary: false,
+                algorithm: Algorithm::Myers,
+                color: false,
+            });
+            let mut diff = vec![];
+            self.write_diff(repository, &mut diff, &changes, &format)?;
+            template += &String::from_utf8_lossy(&diff);
+        }
+        Ok(template)
+    }
+
+    // the long status of the paths `paths` match; for the `template` of a commit message it's
+    // without colors, hints and what there is to commit, since there is something
+    fn write_status(
+        &self,
+        out: &mut impl Write,
+        untracked_files: Option<&str>,
+        paths: &[String],
+        template: bool,
+    ) -> Result<()> {
+        let repository = self.repository()?;
+        let work_tree = repository.work_tree().ok_or(Error::NoWorkTree)?;
+        let pathspecs = Pathspecs::parse(cwd_prefix(&repository)?.as_deref(), paths)?;
+        let config = Config::for_repository(repository.common_dir());
+        let mode = untracked_files
+            .or(config.get("status.showUntrackedFiles"))
+            .unwrap_or("normal");
+        let all_untracked = match mode {
+            "no" | "false" => None,
+            "normal" | "true" => Some(false),
+            "all" => Some(true),
+            _ => return Err(Error::InvalidUntrackedMode(mode.to_string())),
+        };
+
+        let mut index = repository.index()?;
+        let mut save = index.fsmonitor_token().is_some();
+        match fsmonitor::hook(&config) {
+            Some(hook) => {
+                let token = index.fsmonitor_token().map(str::to_string);
+                match fsmonitor::query(&config, hook, work_tree, token.as_deref()) {
+                    Some((token, changed)) => {
+                        index.update_fsmonitor(Some(token), changed.as_deref())
+                    }
+                    None => index.update_fsmonitor(None, None),
+                }
+                save = true;
+            }
+            None => index.update_fsmonitor(None, None),
+        }
+        let mut cache = index.take_untracked_cache();
+        save |= cache.is_some();
+        match config
+            .get("core.untrackedCache")
+            .and_then(config::parse_bool)
+        {
+            Some(true) if cache.is_none() => {
+                cache = Some(UntrackedCache::new(work_tree));
+                save = true;
+            }
+            Some(false) => cache = None,
+            _ => {}
+        }
+        let mut untracked = match all_untracked {
+            Some(all) => untracked::untracked_files(
+                work_tree,
+                repository.common_dir(),
+                &config,
+                index.entries(),
+                all,
+                cache.as_mut(),
+            )?,
+            None => vec![],
+        };
+        untracked.retain(|path| pathspecs.matches(path));
+
+        let head = repository.head();
+        let color = !template && color::enabled(&config, "color.status", None);
+        let hints = !template;
+        let paint = |code: &str, text: &str| color::paint(color, code, text);
+
+        match repository.head_target() {
+            Some(target) => writeln!(
+                out,
+                "On branch {}",
+                target.strip_prefix("refs/heads/").unwrap_or(&target)
+            )?,
+            None => writeln!(
+                out,
+                "{}{}",
+                paint(color::RED, "HEAD detached at "),
+                pretty::abbreviate(head.as_deref().unwrap_or_default())
+            )?,
+        }
+        match head {
+            None if template => writeln!(out, "\nInitial commit\n")?,
+            None => writeln!(out, "\nNo commits yet\n")?,
+            Some(_) => {}
+        }
+        let (merged, mut unmerged) = split_unmerged(&index);
+        unmerged.retain(|path| pathspecs.matches(path));
+        let merging = repository.git_dir().join("MERGE_HEAD").exists();
+        if merging {
+            match unmerged.is_empty() {
+                false => {
+                    writeln!(out, "You have unmerged paths.")?;
+                    if hints {
+                        writeln!(out, "  (fix conflicts and run \"git commit\")")?;
+                        writeln!(out, "  (use \"git merge --abort\" to abort the merge)")?;
+                    }
+                }
+                true => {
+                    writeln!(out, "All conflicts fixed but you are still merging.")?;
+                    if hints {
+                        writeln!(out, "  (use \"git commit\" to conclude merge)")?;
+                    }
+                }
+            }
+            writeln!(out)?;
+        }
+
+        let head_files = match &head {
+            Some(head) => repository.tree_files(head, "")?,
+            None => vec![],
+        };
+        let staged: Vec<Change> = diff::diff_entries(&head_files, &merged)
+            .into_iter()
+            .filter(|change| !unmerged.contains(&change.path) && pathspecs.matches(&change.path))
+            .collect();
+
+        // files whose stat data changed are hashed to see whether their content did
+        let converter = Mutex::new(self.converter(&repository));
+        let mut changed = vec![];
+        let work_tree_side = self.work_tree_side(&repository, &index)?;
+        for change in diff::diff_entries(&merged, &work_tree_side) {
+            if let (Some(old), Some(new)) = (&change.old, &change.new) {
+                if new.id == diff::NULL_ID {
+                    let file = paths::to_native(work_tree, &change.path);
+                    let metadata = fs::symlink_metadata(&file)?;
+                    let entry = self.work_tree_entry(
+                        &repository,
+                        &converter,
+                        &change.path,
+                        &file,
+                        &metadata,
+                        false,
+                    )?;
+                    if entry.same_content(old) {
+                        continue;
+                    }
+                }
+            }
+            // files only the work tree has are untracked, which isn't what this lists
+            if change.old.is_some() && pathspecs.matches(&change.path) {
+                changed.push(change);
+            }
+        }
+
+        let label = |change: &Change| match change.status() {
+            'A' => "new file:",
+            'D' => "deleted:",
+            'T' => "typechange:",
+            _ => "modified:",
+        };
+        if !staged.is_empty() {
+            writeln!(out, "Changes to be committed:")?;
+            match head {
+                _ if merging || !hints => {}
+                Some(_) => writeln!(out, "  (use \"git restore --staged <file>...\" to unstage)")?,
+                None => writeln!(out, "  (use \"git rm --cached <file>...\" to unstage)")?,
+            }
+            for change in &staged {
+                let line = format!("{:<12}{}", label(change), change.path);
+                writeln!(out, "\t{}", paint(color::GREEN, &line))?;
+            }
+            writeln!(out)?;
+        }
+        if !unmerged.is_empty() {
+            // the stages each path has say how its sides changed it
+            let stages: Vec<Vec<u8>> = unmerged
+                .iter()
+                .map(|path| {
+                    [1, 2, 3]
+                        .into_iter()
+                        .filter(|&stage| index.find(path, stage).is_some())
+                        .collect()
+                })
+                .collect();
+            writeln!(out, "Unmerged paths:")?;
+            // outside a merge, such as after a stash conflicted, the stages can be dropped
+            if hints && !merging {
+                writeln!(out, "  (use \"git restore --staged <file>...\" to unstage)")?;
+            }
+            match stages.iter().all(|stages| stages.ends_with(&[2, 3])) {
+                _ if !hints => {}
+                true => writeln!(out, "  (use \"git add <file>...\" to mark resolution)")?,
+                false => writeln!(
+                    out,
+                    "  (use \"git add/rm <file>...\" as appropriate to mark resolution)"
+                )?,
+            }
+            for (path, stages) in unmerged.iter().zip(&stages) {
+                let how = match &stages[..] {
+                    [1] => "both deleted:",
+                    [2] => "added by us:",
+                    [1, 3] => "deleted by us:",
+                    [3] => "added by them:",
+                    [1, 2] => "deleted by them:",
+                    [2, 3] => "both added:",
+                    _ => "both modified:",
+                };
+                let line = format!("{:<17}{}", how, path);
+                writeln!(out, "\t{}", paint(color::RED, &line))?;
+            }
+            writeln!(out)?;
+        }
+        if !changed.is_empty() {
+            writeln!(out, "Changes not staged for commit:")?;
+            if hints {
+                writeln!(
+                    out,
+                    "  (use \"git add{} <file>...\" to update what will be committed)",
+                    if changed.iter().any(|change| change.new.is_none()) {
+                        "/rm"
+                    } else {
+                        ""
+                    }
+                )?;
+                writeln!(
+                    out,
+                    "  (use \"git restore <file>...\" to discard changes in working directory)"
+                )?;
+            }
+            for change in &changed {
+                let line = format!("{:<12}{}", label(change), change.path);
+                writeln!(out, "\t{}", paint(color::RED, &line))?;
+            }
+            writeln!(out)?;
+        }
+        if !untracked.is_empty() {
+            writeln!(out, "Untracked files:")?;
+            if hints {
+                writeln!(
+                    out,
+                    "  (use \"git add <file>...\" to include in what will be committed)"
+                )?;
+            }
+            for path in &untracked {
+                writeln!(out, "\t{}", paint(color::RED, path))?;
+            }
+            writeln!(out)?;
+        } else if all_untracked.is_none() && !staged.is_empty() {
+            let hint = match hints {
+                true => " (use -u option to show untracked files)",
+                false => "",
+            };
+            writeln!(out, "Untracked files not listed{}", hint)?;
+        }
+
+        // a commit message is written when there is something to commit, even only a merge
+        if !template {
+            if staged.is_empty() && (!changed.is_empty() || !unmerged.is_empty()) {
+                writeln!(
+                    out,
+                    "no changes added to commit (use \"git add\" and/or \"git commit -a\")"
+                )?;
+            } else if staged.is_empty() && !untracked.is_empty() {
+                writeln!(
+                    out,
+                    "nothing added to commit but untracked files present (use \"git add\" to track)"
+                )?;
+            } else if staged.is_empty() {
+                match head {
+                    Some(_) if all_untracked.is_none() => {
+                        writeln!(out, "nothing to commit (use -u to show untracked files)")?
+                    }
+                    Some(_) => writeln!(out, "nothing to commit, working tree clean")?,
+                    None => writeln!(
+                        out,
+                        "nothing to commit (create/copy files and use \"git add\" to track)"
+                    )?,
+                }
+            }
+        }
+        out.flush()?;
+
+        if save {
+            // the files whose stat data matched needn't be looked at until the hook reports them
+            if index.fsmonitor_token().is_some() {
+                for entry in work_tree_side
+                    .iter()
+                    .filter(|entry| entry.id != diff::NULL_ID)
+                {
+                    if let Some(entry) = index.find_mut(&entry.path, 0) {
+                        entry.fsmonitor_valid = true;
+                    }
+                }
+            }
+            index.set_untracked_cache(cache);
+            // someone else holding the index lock only means the findings aren't kept
+            let _ = index.write(&repository.index_path());
+        }
+        Ok(())
+    }
+
+    // puts the files `paths` matches back in the work tree as the index has them, or in the
+    // index as HEAD has them with --staged, or both from HEAD; --source gives another tree to
+    // take them from. What the source doesn't have is removed
+    fn restore(
+        &self,
+        source: Option<&str>,
+        staged: bool,
+        worktree: bool,
+        paths: &[String],
+    ) -> Result<()> {
+        let repository = self.repository()?;
+        if paths.is_empty() {
+            return Err(Error::NoPathsToRestore);
+        }
+        let source = match source.or(staged.then_some("HEAD")) {
+            Some(source) => Some(
+                repository
+                    .rev_parse(source)
+                    .and_then(|id| repository.peel(&id, GitObjectType::Tree))
+                    .map_err(|_| Error::CouldNotResolve(source.to_string()))?
+                    .id,
+            ),
+            None => None,
+        };
+        let worktree = worktree || !staged;
+        self.restore_paths(
+            &repository,
+            source.as_deref(),
+            staged,
+            worktree,
+            false,
+            paths,
+        )
+    }
+
+    // checkout without paths: switches to `name` when it is a branch, and otherwise detaches
+    // HEAD at the commit it names
+    fn checkout(&self, name: &str) -> Result<()> {
+        let repository = self.repository()?;
+        let branch = format!("refs/heads/{}", name);
+        let detach = repository.find_reference(&branch).is_none();
+        self.switch(None, detach, Some(name))
+    }
+
+    // checks out `paths` from the tree-ish `source` into the index and the work tree, or from
+    // the index into the work tree, leaving HEAD where it is
+    fn checkout_paths(&self, source: Option<&str>, paths: &[String]) -> Result<()> {
+        let repository = self.repository()?;
+        let source = match source {
+            Some(source) => {
+                let id = repository
+                    .rev_parse(source)
+                    .map_err(|_| Error::InvalidReference(source.to_string()))?;
+                let tree = repository
+                    .peel(&id, GitObjectType::Tree)
+                    .map_err(|_| Error::NotATree(source.to_string()))?;
+                Some(tree.id)
+            }
+            None => None,
+        };
+        self.restore_paths(
+            &repository,
+            source.as_deref(),
+            source.is_some(),
+            true,
+            true,
+            paths,
+        )
+    }
+
+    // puts the files `paths` match back as they are in the tree `source`, or in the index, into
+    // the index with `staged` and into the work tree with `worktree`. Without `overlay` what
+    // they match that the source lacks is removed; with it, only the source's files count
+    fn restore_paths(
+        &self,
+        repository: &Repository,
+        source: Option<&str>,
+        staged: bool,
+        worktree: bool,
+        overlay: bool,
+        paths: &[String],
+    ) -> Result<()> {
+        let source_files = match source {
+            Some(tree) => Some(repository.tree_files(tree, "")?),
+            None => None,
+        };
+        let cwd = cwd_prefix(repository)?;
+        let pathspecs = Pathspecs::parse(cwd.as_deref(), paths)?;
+        let mut index = repository.index()?;
+
+        let known = || {
+            let current = match (&source_files, overlay) {
+                (Some(_), true) => &[],
+                _ => index.entries(),
+            };
+            current
+                .iter()
+                .chain(source_files.iter().flatten())
+                .map(|entry| entry.path.as_str())
+        };
+        let unmatched: Vec<&String> = paths
+            .iter()
+            .zip(pathspecs.iter())
+            .filter(|(_, spec)| {
+                // as in git, an exclude counts as matching once the others match anything
+                !known().any(|path| match spec.is_exclude() {
+                    true => pathspecs.includes(path),
+                    false => spec.matches(path),
+                })
+            })
+            .map(|(path, _)| path)
+            .collect();
+        if !unmatched.is_empty() {
+            for path in unmatched {
+                eprintln!(
+                    "error: pathspec '{}' did not match any file(s) known to git",
+                    path
+                );
+            }
+            return Err(Error::Exit(1));
+        }
+        let matching = |entry: &&IndexEntry| pathspecs.matches(&entry.path);
+        let current: Vec<IndexEntry> = index.entries().iter().filter(matching).cloned().collect();
+        let files: Vec<IndexEntry> = match &source_files {
+            Some(files) => files.iter().filter(matching).cloned().collect(),
+            None => {
+                let unmerged: Vec<&IndexEntry> =
+                    current.iter().filter(|entry| entry.stage != 0).collect();
+                if !unmerged.is_empty() {
+                    let mut paths: Vec<&str> = unmerged.iter().map(|e| e.path.as_str()).collect();
+                    paths.dedup();
+                    for path in paths {
+                        eprintln!("error: path '{}' is unmerged", path);
+                    }
+                    return Err(Error::Exit(1));
+                }
+                current.clone()
+            }
+        };
+
+        let work_tree = repository.work_tree().ok_or(Error::NoWorkTree)?;
+        for entry in &current {
+            if overlay || find_entry(&files, &entry.path).is_some() {
+                continue;
+            }
+            if worktree {
+                let file = paths::to_native(work_tree, &entry.path);
+                match fs::remove_file(&file) {
+                    Err(error) if error.kind() != io::ErrorKind::NotFound => {
+                        return Err(error.into())
+                    }
+                    _ => {}
+                }
+                remove_empty_parents(work_tree, &file);
+            }
+            if staged {
+                index.remove(&entry.path);
+            }
+        }
+        let mut converter = self.converter(repository);
+        for file in &files {
+            let existing = index.find(&file.path, 0).cloned();
+            let mut entry = match existing {
+                Some(existing) if existing.same_content(file) => existing,
+                _ => IndexEntry::new(&file.path, file.mode, &file.id),
+            };
+            if worktree && file.mode != 0o160000 {
+                let path = paths::to_native(work_tree, &file.path);
+                let unchanged = source_files.is_none()
+                    && fs::symlink_metadata(&path)
+                        .is_ok_and(|metadata| entry.stat_matches(&metadata));
+                if !unchanged {
+                    let checked_out = self.check_out_file(repository, &mut converter, file)?;
+                    // the index keeps the stat data only of what it has
+                    if staged || source_files.is_none() {
+                        entry = checked_out;
+                    }
+                }
+            }
+            if staged || source_files.is_none() {
+                index.add(entry);
+            }
+        }
+
+        index.write(&repository.index_path())
+    }
+
+    // switches HEAD to a branch, or to a commit with --detach, carrying the local changes
+    // along when the files they are in are the same on both sides, and refusing otherwise; -c
+    // makes the branch first. As in git, -c or --detach without a commit, which stay on HEAD's,
+    // leave the work tree alone
+    fn switch(&self, create: Option<&str>, detach: bool, name: Option<&str>) -> Result<()> {
+        let repository = self.repository()?;
+        let resolve = |name: &str| {
+            repository
+                .rev_parse(name)
+                .and_then(|id| repository.peel(&id, GitObjectType::Commit))
+                .map(|commit| commit.id)
+                .map_err(|_| Error::InvalidReference(name.to_string()))
+        };
+        let (branch, id) = match (create, name) {
+            (Some(new), start) => {
+                if !refname::is_valid_branch(new) {
+                    return Err(Error::InvalidBranchName(new.to_string()));
+                }
+                let branch = format!("refs/heads/{}", new);
+                if repository.find_reference(&branch).is_some() {
+                    return Err(Error::BranchExists(new.to_string()));
+                }
+                (Some(branch), resolve(start.unwrap_or("HEAD"))?)
+            }
+            (None, start) if detach => (None, resolve(start.unwrap_or("HEAD"))?),
+            (None, None) => return Err(Error::MissingBranch),
+            (None, Some(name)) => {
+                let branch = format!("refs/heads/{}", name);
+                if let Some(id) = repository.find_reference(&branch) {
+                    (Some(branch), resolve(&id)?)
+                } else {
+                    resolve(name)?;
+                    let is_ref = |name: String| repository.find_reference(&name).is_some();
+                    let kind = if is_ref(format!("refs/tags/{}", name)) {
+                        "tag "
+                    } else if is_ref(format!("refs/remotes/{}", name)) {
+                        "remote branch "
+                    } else {
+                        "commit "
+                    };
+                    return Err(Error::BranchExpected(kind.to_string(), name.to_string()));
+                }
+            }
+        };
+        if repository.git_dir().join("MERGE_HEAD").exists() {
+            return Err(Error::SwitchWhileMerging);
+        }
+
+        let old_branch = repository.head_target();
+        let old_head = repository.head();
+        if name.is_some() || old_head.as_ref() != Some(&id) {
+            let mut index = repository.index()?;
+            let from = match &old_head {
+                Some(head) => repository.tree_files(head, "")?,
+                None => vec![],
+            };
+            let to = repository.tree_files(&id, "")?;
+            self.check_out_for(&repository, &mut index, &from, &to, "checkout")?;
+            index.write(&repository.index_path())?;
+            let mut out = io::stdout().lock();
+            for change in self.work_tree_changes(&repository, &index, &to)? {
+                writeln!(out, "{}\t{}", change.status(), change.path)?;
+            }
+        }
+        let describe = |id: &str| -> Result<String> {
+            let message = repository.find_commit(id)?.message;
+            Ok(format!(
+                "{} {}",
+                pretty::abbreviate(id),
+                pretty::subject(&message)
+            ))
+        };
+        if let (None, Some(old_head)) = (&old_branch, &old_head) {
+            if *old_head != id {
+                eprintln!("Previous HEAD position was {}", describe(old_head)?);
+            }
+        }
+
+        let Some(branch) = branch else {
+            repository.set_reference("HEAD", &id)?;
+            eprintln!("HEAD is now at {}", describe(&id)?);
+            return Ok(());
+        };
+        if create.is_some() {
+            repository.set_reference(&branch, &id)?;
+        }
+        repository.set_symbolic_reference("HEAD", &branch)?;
+        let name = branch.strip_prefix("refs/heads/").unwrap_or(&branch);
+        match old_branch {
+            Some(old) if old == branch => eprintln!("Already on '{}'", name),
+            _ if create.is_some() => eprintln!("Switched to a new branch '{}'", name),
+            _ => eprintln!("Switched to branch '{}'", name),
+        }
+        Ok(())
+    }
+
+    // what the work tree changes from `files`, as diff-index sees it without --cached: the
+    // files whose stat data no longer matches the index are hashed
+    fn work_tree_changes(
+        &self,
+        repository: &Repository,
+        index: &Index,
+        files: &[IndexEntry],
+    ) -> Result<Vec<Change>> {
+        let work_tree = repository.work_tree().ok_or(Error::NoWorkTree)?;
+        let converter = Mutex::new(self.converter(repository));
+        let mut work_tree_side = self.work_tree_side(repository, index)?;
+        for entry in &mut work_tree_side {
+            if entry.id == diff::NULL_ID {
+                let path = entry.path.clone();
+                let file = paths::to_native(work_tree, &path);
+                let metadata = fs::symlink_metadata(&file)?;
+                *entry =
+                    self.work_tree_entry(repository, &converter, &path, &file, &metadata, false)?;
+            }
+        }
+        Ok(diff::diff_entries(files, &work_tree_side))
+    }
+
+    fn stash(&self, args: StashArgs) -> Result<()> {
+        let repository = self.repository()?;
+        match args.command {
+            None => self.stash_push(&repository, &args.push),
+            Some(StashCommand::Push(push)) => self.stash_push(&repository, &push),
+            Some(StashCommand::List) => {
+                let entries = repository.reflog("refs/stash");
+                for (n, entry) in entries.iter().rev().enumerate() {
+                    println!("stash@{{{}}}: {}", n, entry.message);
+                }
+                Ok(())
+            }
+            Some(StashCommand::Show { patch, stat, stash }) => {
+                let stash = self.find_stash(&repository, stash.as_deref())?;
+                let base = repository.tree_files(&stash.commit.parents[0], "")?;
+                let files = repository.tree_files(&stash.commit.id, "")?;
+                let changes = diff::diff_entries(&base, &files);
+                let config = Config::for_repository(repository.common_dir());
+                let color = color::enabled(&config, "color.diff", None);
+                let mut formats = vec![];
+                if stat || !patch {
+                    formats.push(DiffFormat::Stat { color });
+                }
+                if patch {
+                    formats.push(DiffFormat::Patch(PatchOptions {
+                        words: None,
+                        binary: false,
+                        algorithm: Algorithm::Myers,
+                        color,
+                    }));
+                }
+                let mut out = self.pager(&config);
+                for (i, format) in formats.iter().enumerate() {
+                    // a blank line goes between the diffstat and the patch
+                    if i > 0 {
+                        writeln!(out)?;
+                    }
+                    self.write_diff(&repository, &mut out, &changes, format)?;
+                }
+                Ok(())
+            }
+            Some(StashCommand::Apply(args)) => self.stash_apply(&repository, &args, false),
+            Some(StashCommand::Pop(args)) => self.stash_apply(&repository, &args, true),
+            Some(StashCommand::Drop { quiet, stash }) => {
+                let stash = self.find_stash(&repository, stash.as_deref())?;
+                self.stash_drop(&repository, &stash, quiet)
+            }
+            Some(StashCommand::Branch { branch, stash }) => {
+                let found = self.find_stash(&repository, stash.as_deref())?;
+                // as git runs checkout for it, a failure there only fails the stash with 1
+                let base = &found.commit.parents[0];
+                if let Err(error) = self.switch(Some(&branch), false, Some(base)) {
+                    eprintln!("fatal: {}", error);
+                    return Err(Error::Exit(1));
+                }
+                let args = StashApplyArgs {
+                    index: true,
+                    quiet: false,
+                    stash,
+                };
+                self.stash_apply(&repository, &args, false)?;
+                match found.reflog {
+                    Some(_) => self.stash_drop(&repository, &found, false),
+                    None => Ok(()),
+                }
+            }
+            Some(StashCommand::Clear) => {
+                repository.delete_reflog("refs/stash")?;
+                repository.delete_reference("refs/stash")
+            }
+        }
+    }
+
+    // records the tracked files of the work tree as a commit on HEAD whose second parent
+    // records the index, and whose third records the untracked files with -u, then puts back
+    // what HEAD has, or with --keep-index what the index has. With pathspecs only the files
+    // they match are stashed and put back; the others are recorded as the index has them
+    fn stash_push(&self, repository: &Repository, args: &StashPushArgs) -> Result<()> {
+        let work_tree = repository.work_tree().ok_or(Error::NoWorkTree)?;
+        let Some(head) = repository.head() else {
+            eprintln!("You do not have the initial commit yet");
+            return Err(Error::Exit(1));
+        };
+        let mut index = repository.index()?;
+        if index.has_conflicts() {
+            return Err(Error::UnmergedIndex);
+        }
+        let cwd = cwd_prefix(repository)?;
+        let pathspecs = Pathspecs::parse(cwd.as_deref(), &args.pathspecs)?;
+        // as in git, untracked files don't count as known unless they are to be stashed too
+        if !args.include_untracked {
+            let unmatched: Vec<&String> = args
+                .pathspecs
+                .iter()
+                .zip(pathspecs.iter())
+                .filter(|(_, spec)| {
+                    !index.entries().iter().any(|entry| match spec.is_exclude() {
+                        true => pathspecs.includes(&entry.path),
+                        false => spec.matches(&entry.path),
+                    })
+                })
+                .map(|(path, _)| path)
+                .collect();
+            if !unmatched.is_empty() {
+                for path in unmatched {
+                    eprintln!(
+                        "error: pathspec '{}' did not match any file(s) known to git",
+                        path
+                    );
+                }
+                eprintln!("Did you forget to 'git add'?");
+                return Err(Error::Exit(1));
+            }
+        }
+        let matching = |files: &[IndexEntry]| -> Vec<IndexEntry> {
+            files
+                .iter()
+                .filter(|entry| pathspecs.matches(&entry.path))
+                .cloned()
+                .collect()
+        };
+        let head_commit = repository.find_commit(&head)?;
+        let index_tree = repository.write_tree_entries(index.entries())?;
+
+        let converter = Mutex::new(self.converter(repository));
+        let mut files = vec![];
+        for entry in self.work_tree_side(repository, &index)? {
+            if entry.id != diff::NULL_ID {
+                files.push(entry);
+                continue;
+            }
+            let file = paths::to_native(work_tree, &entry.path);
+            let metadata = fs::symlink_metadata(&file)?;
+            files.push(self.work_tree_entry(
+                repository,
+                &converter,
+                &entry.path,
+                &file,
+                &metadata,
+                true,
+            )?);
+        }
+        // submodules are what the index says they are, and files taken out of the index but
+        // still in the work tree are stashed as they are there
+        for entry in index.entries() {
+            if entry.mode == 0o160000 && find_entry(&files, &entry.path).is_none() {
+                files.push(entry.clone());
+            }
+        }
+        for entry in repository.tree_files(&head, "")? {
+            if index.find(&entry.path, 0).is_some() || entry.mode == 0o160000 {
+                continue;
+            }
+            let file = paths::to_native(work_tree, &entry.path);
+            match fs::symlink_metadata(&file) {
+                Ok(metadata) if !metadata.is_dir() => files.push(self.work_tree_entry(
+                    repository,
+                    &converter,
+                    &entry.path,
+                    &file,
+                    &metadata,
+                    true,
+                )?),
+                _ => {}
+            }
+        }
+        let mut files = matching(&files);
+        files.extend(
+            index
+                .entries()
+                .iter()
+                .filter(|entry| !pathspecs.matches(&entry.path))
+                .cloned(),
+        );
+        files.sort_by(|a, b| a.path.as_bytes().cmp(b.path.as_bytes()));
+        let work_tree_tree = repository.write_tree_entries(&files)?;
+
+        let mut untracked = vec![];
+        if args.include_untracked {
+            let config = Config::for_repository(repository.common_dir());
+            for path in untracked::untracked_files(
+                work_tree,
+                repository.common_dir(),
+                &config,
+                index.entries(),
+                true,
+                None,
+            )? {
+                // a repository of its own isn't stashed
+                if path.ends_with('/') || !pathspecs.matches(&path) {
+                    continue;
+                }
+                let file = paths::to_native(work_tree, &path);
+                let metadata = fs::symlink_metadata(&file)?;
+                untracked.push(
+                    self.work_tree_entry(repository, &converter, &path, &file, &metadata, true)?,
+                );
+            }
+            untracked.sort_by(|a, b| a.path.as_bytes().cmp(b.path.as_bytes()));
+        }
+        let head_files = matching(&repository.tree_files(&head, "")?);
+        if diff::diff_entries(&head_files, &matching(index.entries())).is_empty()
+            && diff::diff_entries(&head_files, &matching(&files)).is_empty()
+            && untracked.is_empty()
+        {
+            println!("No local changes to save");
+            return Ok(());
+        }
+
+        let branch = match repository.head_target() {
+            Some(target) => target
+                .strip_prefix("refs/heads/")
+                .unwrap_or(&target)
+                .to_string(),
+            None => "(no branch)".to_string(),
+        };
+        let on = format!(
+            "{}: {} {}",
+            branch,
+            pretty::abbreviate(&head),
+            head_commit.message.lines().next().unwrap_or_default()
+        );
+        let config = Config::for_repository(repository.common_dir());
+        let author = signature(&config, "author")?;
+        let committer = signature(&config, "committer")?;
+        let index_commit = repository.create_commit(
+            &index_tree,
+            &[&head],
+            &author,
+            &committer,
+            &format!("index on {}", on),
+        )?;
+        let mut parents = vec![head.clone(), index_commit];
+        if !untracked.is_empty() {
+            let tree = repository.write_tree_entries(&untracked)?;
+            parents.push(repository.create_commit(
+                &tree,
+                &[],
+                &author,
+                &committer,
+                &format!("untracked files on {}", on),
+            )?);
+        }
+        let message = match &args.message {
+            Some(message) => format!("On {}: {}", branch, message),
+            None => format!("WIP on {}", on),
+        };
+        // git leaves the message of the stash commit itself without a newline at the end
+        let parents: Vec<&str> = parents.iter().map(String::as_str).collect();
+        let mut content =
+            repository::commit_content(&work_tree_tree, &parents, &author, &committer, &message);
+        content.pop();
+        let stash = repository.write_object(GitObjectType::Commit, content.as_bytes())?;
+        let old = repository.find_reference("refs/stash");
+        repository.set_reference("refs/stash", &stash)?;
+        repository.append_reflog(
+            "refs/stash",
+            &ReflogEntry {
+                old: old.unwrap_or_else(|| diff::NULL_ID.to_string()),
+                new: stash,
+                committer,
+                message: message.clone(),
+            },
+        )?;
+        if !args.quiet {
+            println!("Saved working directory and index state {}", message);
+        }
+
+        let kept = match args.keep_index {
+            true => index_tree,
+            false => head,
+        };
+        let kept = repository.tree_files(&kept, "")?;
+        self.reset_work_tree(repository, &mut index, &kept, &pathspecs)?;
+        for entry in &untracked {
+            let file = paths::to_native(work_tree, &entry.path);
+            fs::remove_file(&file)?;
+            remove_empty_parents(work_tree, &file);
+        }
+        index.write(&repository.index_path())
+    }
+
+    // makes the index and the tracked files of the work tree that `pathspecs` match what
+    // `files` has, throwing away any changes to them; files that already match keep their stat
+    // data
+    fn reset_work_tree(
+        &self,
+        repository: &Repository,
+        index: &mut Index,
+        files: &[IndexEntry],
+        pathspecs: &Pathspecs,
+    ) -> Result<()> {
+        let work_tree = repository.work_tree().ok_or(Error::NoWorkTree)?;
+        let current = self.work_tree_side(repository, index)?;
+        // files are removed first, so that a directory can take the place of one
+        for entry in index.entries().to_vec() {
+            if find_entry(files, &entry.path).is_some() || !pathspecs.matches(&entry.path) {
+                continue;
+            }
+            let file = paths::to_native(work_tree, &entry.path);
+            match fs::remove_file(&file) {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error.into()),
+                _ => {}
+            }
+            remove_empty_parents(work_tree, &file);
+            index.remove(&entry.path);
+        }
+
+        let mut converter = self.converter(repository);
+        for file in files.iter().filter(|file| pathspecs.matches(&file.path)) {
+            let unchanged = index
+                .find(&file.path, 0)
+                .is_some_and(|entry| entry.same_content(file))
+                && find_entry(&current, &file.path).is_some_and(|entry| entry.id == file.id);
+            if unchanged {
+                continue;
+            }
+            index.remove(&file.path);
+            match file.mode {
+                0o160000 => index.add(file.clone()),
+                _ => index.add(self.check_out_file(repository, &mut converter, file)?),
+            }
+        }
+
+        Ok(())
+    }
+
+    // merges what a stash entry changed since the commit it was made on into the work tree,
+    // leaving the index as it was but for the files the entry adds, unless --index asks for
+    // the changes it had in the index back as well; then its untracked files come back. Pop
+    // drops the entry after, unless there were conflicts
+    fn stash_apply(&self, repository: &Repository, args: &StashApplyArgs, pop: bool) -> Result<()> {
+        let stash = self.find_stash(repository, args.stash.as_deref())?;
+        if pop && stash.reflog.is_none() {
+            eprintln!("error: '{}' is not a stash reference", stash.name);
+            return Err(Error::Exit(1));
+        }
+        let mut index = repository.index()?;
+        if index.has_conflicts() {
+            eprintln!("error: cannot apply a stash in the middle of a merge");
+            return Err(Error::Exit(1));
+        }
+        let current_tree = repository.write_tree_entries(index.entries())?;
+        let base_tree = repository.find_commit(&stash.commit.parents[0])?.tree;
+        let index_tree = repository.find_commit(&stash.commit.parents[1])?.tree;
+
+        let mut stashed_index = None;
+        if args.index && index_tree != base_tree && index_tree != current_tree {
+            let merge = merge::merge_trees(
+                repository,
+                &base_tree,
+                &current_tree,
+                &index_tree,
+                ("", ""),
+                None,
+            )?;
+            if !merge.is_clean() {
+                eprintln!("error: conflicts in index. Try without --index.");
+                return Err(Error::Exit(1));
+            }
+            stashed_index = Some(merge.tree);
+        }
+
+        let ours = match base_tree == current_tree {
+            true => "Version stash was based on",
+            false => "Updated upstream",
+        };
+        let merge = merge::merge_trees(
+            repository,
+            &base_tree,
+            &current_tree,
+            &stash.commit.tree,
+            (ours, "Stashed changes"),
+            None,
+        )?;
+        // as when the entry only has untracked files
+        if base_tree == stash.commit.tree {
+            println!("Already up to date.");
+        }
+        for line in &merge.messages {
+            println!("{}", line);
+        }
+        let current = repository.tree_files(&current_tree, "")?;
+        let merged = repository.tree_files(&merge.tree, "")?;
+        let before = index.entries().to_vec();
+        if let Err(error) = self.check_out(repository, &mut index, &current, &merged) {
+            if !args.quiet {
+                self.status(None, &[])?;
+            }
+            return Err(error);
+        }
+
+        let clean = merge.is_clean();
+        if !clean {
+            for entry in &merge.conflicts {
+                if entry.stage == 1 || index.find(&entry.path, 0).is_some() {
+                    index.remove(&entry.path);
+                }
+            }
+            for entry in &merge.conflicts {
+                index.add(entry.clone());
+            }
+            if args.index {
+                eprintln!("Index was not unstashed.");
+            }
+        } else if let Some(tree) = stashed_index {
+            let files = repository.tree_files(&tree, "")?;
+            for change in diff::diff_entries(&merged, &files) {
+                index.remove(&change.path);
+                if let Some(new) = &change.new {
+                    index.add(IndexEntry::new(&change.path, new.mode, &new.id));
+                }
+            }
+        } else {
+            // only the files the stash adds stay added
+            for change in diff::diff_entries(&current, &merged) {
+                if change.old.is_some() {
+                    index.remove(&change.path);
+                    if let Some(entry) = find_entry(&before, &change.path) {
+                        index.add(entry.clone());
+                    }
+                }
+            }
+        }
+        index.write(&repository.index_path())?;
+        if !clean {
+            self.reuse_resolutions(repository, &mut index)?;
+        }
+
+        let mut restored = true;
+        if let Some(untracked) = stash.commit.parents.get(2) {
+            restored = self.restore_untracked(repository, untracked)?;
+            if !restored {
+                eprintln!("error: could not restore untracked files from stash");
+            }
+        }
+        if !args.quiet {
+            self.status(None, &[])?;
+        }
+        if !clean || !restored {
+            if pop {
+                println!("The stash entry is kept in case you need it again.");
+            }
+            return Err(Error::Exit(1));
+        }
+
+        match pop {
+            true => self.stash_drop(repository, &stash, args.quiet),
+            false => Ok(()),
+        }
+    }
+
+    // checks out the files of the commit of a stash entry's untracked files, none of which
+    // may be in the way; says whether they were
+    fn restore_untracked(&self, repository: &Repository, commit: &str) -> Result<bool> {
+        let work_tree = repository.work_tree().ok_or(Error::NoWorkTree)?;
+        let files = repository.tree_files(commit, "")?;
+        let mut clear = true;
+        for file in &files {
+            if fs::symlink_metadata(paths::to_native(work_tree, &file.path)).is_ok() {
+                eprintln!("{} already exists, no checkout", file.path);
+                clear = false;
+            }
+        }
+        if !clear {
+            return Ok(false);
+        }
+
+        let mut converter = self.converter(repository);
+        for file in &files {
+            self.check_out_file(repository, &mut converter, file)?;
+        }
+        Ok(true)
+    }
+
+    // removes an entry from the stash reflog, moving refs/stash to the one before it, if any
+    fn stash_drop(&self, repository: &Repository, stash: &Stash, quiet: bool) -> Result<()> {
+        let Some(n) = stash.reflog else {
+            eprintln!("error: '{}' is not a stash reference", stash.name);
+            return Err(Error::Exit(1));
+        };
+        let mut entries = repository.reflog("refs/stash");
+        let at = entries.len() - 1 - n;
+        let dropped = entries.remove(at);
+        // the next entry now moves the ref from where the one dropped did
+        if let Some(next) = entries.get_mut(at) {
+            next.old = dropped.old;
+        }
+        match entries.last() {
+            Some(last) => {
+                repository.write_reflog("refs/stash", &entries)?;
+                repository.set_reference("refs/stash", &last.new)?
+            }
+            None => {
+                repository.delete_reflog("refs/stash")?;
+                repository.delete_reference("refs/stash")?
+            }
+        }
+        if !quiet {
+            println!("Dropped {} ({})", stash.name, stash.commit.id);
+        }
+        Ok(())
+    }
+
+    // a stash entry by its place in the stash reflog, as "stash@{<n>}" or just "<n>", or any
+    // commit that looks like one; the newest entry when no name is given
+    fn find_stash(&self, repository: &Repository, name: Option<&str>) -> Result<Stash> {
+        let entries = repository.reflog("refs/stash");
+        let exists = repository.find_reference("refs/stash").is_some();
+        let name = match name {
+            Some(name) if name.bytes().all(|b| b.is_ascii_digit()) => {
+                format!("refs/stash@{{{}}}", name)
+            }
+            Some(name) => name.to_string(),
+            None if !exists => {
+                eprintln!("No stash entries found.");
+                return Err(Error::Exit(1));
+            }
+            None => "refs/stash@{0}".to_string(),
+        };
+        let reflog = ["refs/stash@{", "stash@{"].iter().find_map(|prefix| {
+            name.strip_prefix(prefix)?
+                .strip_suffix('}')?
+                .parse::<usize>()
+                .ok()
+        });
+
+        let id = match reflog {
+            Some(_) if !exists || entries.is_empty() => {
+                eprintln!("error: {} is not a valid reference", name);
+                return Err(Error::Exit(1));
+            }
+            Some(n) if n >= entries.len() => {
+                return Err(Error::ReflogTooShort("stash".to_string(), entries.len()))
+            }
+            Some(n) => entries[entries.len() - 1 - n].new.clone(),
+            None => match repository.rev_parse(&name) {
+                Ok(id) => id,
+                Err(_) => {
+                    eprintln!("error: {} is not a valid reference", name);
+                    return Err(Error::Exit(1));
+                }
+            },
+        };
+        let commit = repository
+            .peel(&id, GitObjectType::Commit)
+            .and_then(|commit| repository.find_commit(&commit.id))
+            .ok()
+            .filter(|commit| (2..=3).contains(&commit.parents.len()))
+            .ok_or_else(|| Error::NotAStashLikeCommit(name.clone()))?;
+
+        Ok(Stash {
+            name,
+            reflog,
+            commit,
+        })
+    }
+
+    fn worktree(&self, command: WorktreeCommand) -> Result<()> {
+        let repository = self.repository()?;
+        let common_dir = repository.common_dir();
+        match command {
+            WorktreeCommand::Add {
+                branch,
+                detach,
+                path,
+                commit,
+            } => self.worktree_add(
+                &repository,
+                &path,
+                commit.as_deref(),
+                branch.as_deref(),
+                detach,
+            ),
+            WorktreeCommand::List { porcelain } => {
+                let worktrees = worktree::list(&repository);
+                let mut out = io::stdout().lock();
+                let width = worktrees
+                    .iter()
+                    .map(|worktree| worktree.path.display().to_string().len())
+                    .max()
+                    .unwrap_or_default();
+                for worktree in &worktrees {
+                    let head = worktree.head.as_deref().unwrap_or(diff::NULL_ID);
+                    if porcelain {
+                        writeln!(out, "worktree {}", worktree.path.display())?;
+                        match (&worktree.branch, worktree.bare) {
+                            (_, true) => writeln!(out, "bare")?,
+                            (Some(branch), false) => {
+                                writeln!(out, "HEAD {}\nbranch {}", head, branch)?
+                            }
+                            (None, false) => writeln!(out, "HEAD {}\ndetached", head)?,
+                        }
+                        match worktree.locked.as_deref() {
+                            Some("") => writeln!(out, "locked")?,
+                            Some(reason) => writeln!(out, "locked {}", reason)?,
+                            None => {}
+                        }
+                        if let Some(reason) = &worktree.prunable {
+                            writeln!(out, "prunable {}", reason)?;
+                        }
+                        writeln!(out)?;
+                        continue;
+                    }
+
+                    let path = worktree.path.display().to_string();
+                    let mut line = format!("{:<width$} ", path, width = width + 1);
+                    match (&worktree.branch, worktree.bare) {
+                        (_, true) => line.push_str("(bare)"),
+                        (Some(branch), false) => line.push_str(&format!(
+                            "{} [{}]",
+                            pretty::abbreviate(head),
+                            branch.strip_prefix("refs/heads/").unwrap_or(branch)
+                        )),
+                        (None, false) => {
+                            line.push_str(&format!("{} (detached HEAD)", pretty::abbreviate(head)))
+                        }
+                    }
+                    if worktree.locked.is_some() {
+                        line.push_str(" locked");
+                    }
+                    if worktree.prunable.is_some() {
+                        line.push_str(" prunable");
+                    }
+                    writeln!(out, "{}", line)?;
+                }
+                Ok(())
+            }
+            WorktreeCommand::Lock { reason, worktree } => {
+                let found = worktree::find(&repository, &worktree)?;
+                let id = found.id.as_deref().ok_or(Error::LockMainWorktree)?;
+                if let Some(locked) = found.locked {
+                    let locked = (!locked.is_empty()).then_some(locked);
+                    return Err(Error::WorktreeLocked(worktree, locked));
+                }
+                worktree::lock(common_dir, id, reason.as_deref().unwrap_or_default())
+            }
+            WorktreeCommand::Unlock { worktree } => {
+                let found = worktree::find(&repository, &worktree)?;
+                let id = found.id.as_deref().ok_or(Error::LockMainWorktree)?;
+                if found.locked.is_none() {
+                    return Err(Error::WorktreeNotLocked(worktree));
+                }
+                worktree::unlock(common_dir, id)
+            }
+            WorktreeCommand::Move {
+                force,
+                worktree,
+                new_path,
+            } => {
+                let found = worktree::find(&repository, &worktree)?;
+                if found.is_main() {
+                    return Err(Error::MainWorktree(worktree));
+                }
+                let mut to = std::path::absolute(&new_path)?;
+                if to.is_dir() {
+                    to = to.join(found.path.file_name().unwrap_or_default());
+                }
+                if to.exists() {
+                    return Err(Error::AlreadyExists(to.display().to_string()));
+                }
+                if let Some(locked) = &found.locked {
+                    if force < 2 {
+                        let locked = (!locked.is_empty()).then(|| locked.clone());
+                        return Err(Error::MoveLockedWorktree(locked));
+                    }
+                }
+                worktree::relocate(&found, &to)
+            }
+            WorktreeCommand::Prune {
+                dry_run,
+                verbose,
+                expire,
+            } => {
+                let expire = match expire {
+                    Some(date) => {
+                        repository::parse_expiry_date(&date).ok_or(Error::InvalidDate(date))?
+                    }
+                    None => i64::MAX,
+                };
+                worktree::prune(&repository, expire, dry_run, verbose)
+            }
+        }
+    }
+
+    // checks `commit` out into a new work tree at `path`: on a new branch with -b, detached
+    // with --detach or when it isn't a branch, and otherwise on the branch. With no commit,
+    // the branch is the one named after the work tree, which starts at HEAD if it is new
+    fn worktree_add(
+        &self,
+        repository: &Repository,
+        path: &Path,
+        commit: Option<&str>,
+        new_branch: Option<&str>,
+        detach: bool,
+    ) -> Result<()> {
+        let is_branch = |name: &str| {
+            repository
+                .find_reference(&format!("refs/heads/{}", name))
+                .is_some()
+        };
+        let resolve = |name: &str| {
+            repository
+                .rev_parse(name)
+                .and_then(|id| repository.peel(&id, GitObjectType::Commit))
+                .map(|commit| commit.id)
+                .map_err(|_| Error::InvalidReference(name.to_string()))
+        };
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let (branch, new) = match (new_branch, commit) {
+            (Some(branch), _) => (Some(branch.to_string()), true),
+            (None, _) if detach => (None, false),
+            (None, Some(commit)) => (is_branch(commit).then(|| commit.to_string()), false),
+            (None, None) => (Some(name.clone()), !is_branch(&name)),
+        };
+        let start = match (&branch, commit) {
+            (Some(branch), None) if !new => resolve(&format!("refs/heads/{}", branch))?,
+            (Some(branch), Some(commit)) if !new => {
+                resolve(&format!("refs/heads/{}", branch)).or_else(|_| resolve(commit))?
+            }
+            (_, commit) => resolve(commit.unwrap_or("HEAD"))?,
+        };
+
+        match (&branch, new) {
+            (Some(branch), true) => eprintln!("Preparing worktree (new branch '{}')", branch),
+            (Some(branch), false) => eprintln!("Preparing worktree (checking out '{}')", branch),
+            (None, _) => eprintln!(
+                "Preparing worktree (detached HEAD {})",
+                pretty::abbreviate(&start)
+            ),
+        }
+        if let (Some(branch), true) = (&branch, new) {
+            if !refname::is_valid_branch(branch) {
+                return Err(Error::InvalidBranchName(branch.clone()));
+            }
+        }
+        let in_use = fs::read_dir(path).map_or(path.exists(), |mut dir| dir.next().is_some());
+        if in_use {
+            return Err(Error::AlreadyExists(path.display().to_string()));
+        }
+        let head = match &branch {
+            Some(branch) => {
+                let full_name = format!("refs/heads/{}", branch);
+                if new && is_branch(branch) {
+                    return Err(Error::BranchExists(branch.clone()));
+                }
+                if !new {
+                    let worktrees = worktree::list(repository);
+                    if let Some(other) = worktrees
+                        .iter()
+                        .find(|worktree| worktree.branch.as_ref() == Some(&full_name))
+                    {
+                        return Err(Error::AlreadyCheckedOut(
+                            branch.clone(),
+                            other.path.display().to_string(),
+                        ));
+                    }
+                }
+                format!("ref: {}", full_name)
+            }
+            None => start.clone(),
+        };
+
+        fs::create_dir_all(path)?;
+        let path = fs::canonicalize(path)?;
+        let common_dir = fs::canonicalize(repository.common_dir())?;
+        if let (Some(branch), true) = (&branch, new) {
+            repository.set_reference(&format!("refs/heads/{}", branch), &start)?;
+        }
+        let id = worktree::new_id(&common_dir, &path);
+        let git_dir = worktree::create(&common_dir, &id, &path, &head)?;
+
+        let linked = Repository::open_with(&git_dir, Some(&path))?;
+        let mut index = Index::default();
+        self.check_out(&linked, &mut index, &[], &linked.tree_files(&start, "")?)?;
+        index.write(&git_dir.join("index"))?;
+        let commit = repository.find_commit(&start)?;
+        eprintln!(
+            "HEAD is now at {} {}",
+            pretty::abbreviate(&start),
+            commit.message.lines().next().unwrap_or_default()
+        );
+        Ok(())
+    }
+
+    // the submodules that are checked out are opened as repositories of their own, and --recursive
+    // goes on into theirs, where the paths shown start with the path of the submodule
+    fn submodule(&self, quiet: bool, command: Option<SubmoduleCommand>) -> Result<()> {
+        let repository = self.repository()?;
+        let cwd = cwd_prefix(&repository)?;
+        let command = command.unwrap_or(SubmoduleCommand::Status {
+            cached: false,
+            recursive: false,
+            paths: vec![],
+        });
+        match command {
+            SubmoduleCommand::Status {
+                cached,
+                recursive,
+                paths,
+            } => {
+                let entries = self.submodule_entries(&repository, cwd.as_deref(), &paths)?;
+                let at = (cwd.as_deref(), None);
+                self.submodule_status(&repository, &entries, at, cached, recursive, quiet)
+            }
+            SubmoduleCommand::Foreach { recursive, command } => {
+                let entries = self.submodule_entries(&repository, None, &[])?;
+                let at = (cwd.as_deref(), None);
+                self.submodule_foreach(&repository, &entries, at, &command, recursive, quiet)
+            }
+            SubmoduleCommand::Sync { recursive, paths } => {
+                let entries = self.submodule_entries(&repository, cwd.as_deref(), &paths)?;
+                let at = (cwd.as_deref(), None);
+                self.submodule_sync(&repository, &entries, at, recursive, quiet)
+            }
+        }
+    }
+
+    // the gitlinks of the index that `paths` match, each once however many stages it has
+    fn submodule_entries(
+        &self,
+        repository: &Repository,
+        cwd: Option<&str>,
+        paths: &[String],
+    ) -> Result<Vec<IndexEntry>> {
+        let pathspecs = Pathspecs::parse(cwd, paths)?;
+        let index = repository.index()?;
+        let unmatched: Vec<&String> = paths
+            .iter()
+            .zip(pathspecs.iter())
+            .filter(|(_, spec)| {
+                !index.entries().iter().any(|entry| match spec.is_exclude() {
+                    true => pathspecs.includes(&entry.path),
+                    false => spec.matches(&entry.path),
+                })
+            })
+            .map(|(path, _)| path)
+            .collect();
+        if !unmatched.is_empty() {
+            for path in unmatched {
+                eprintln!(
+                    "error: pathspec '{}' did not match any file(s) known to git",
+                    path
+                );
+            }
+            return Err(Error::Exit(1));
+        }
+
+        let mut entries: Vec<IndexEntry> = index
+            .entries()
+            .iter()
+            .filter(|entry| entry.mode == 0o160000 && pathspecs.matches(&entry.path))
+            .cloned()
+            .collect();
+        entries.dedup_by(|a, b| a.path == b.path);
+        Ok(entries)
+    }
+
+    // a line for each submodule: "-" and the commit the index has when it isn't checked out
+    // or in use, "U" when it has conflicts, and otherwise the commit checked out, marked "+"
+    // when the index has another, with a name for it
+    fn submodule_status(
+        &self,
+        repository: &Repository,
+        entries: &[IndexEntry],
+        at: (Option<&str>, Option<&str>),
+        cached: bool,
+        recursive: bool,
+        quiet: bool,
+    ) -> Result<()> {
+        let work_tree = repository.work_tree().ok_or(Error::NoWorkTree)?;
+        let gitmodules = submodule::gitmodules(repository)?;
+        let config = Config::for_repository(repository.common_dir());
+        for entry in entries {
+            let submodule = submodule::find(&gitmodules, &entry.path)
+                .ok_or_else(|| Error::NoSubmoduleMapping(entry.path.clone()))?;
+            let display_path = submodule_display_path(&entry.path, at);
+            if entry.stage != 0 {
+                if !quiet {
+                    println!("U{} {}", diff::NULL_ID, display_path);
+                }
+                continue;
+            }
+            let opened = Repository::open(&paths::to_native(work_tree, &entry.path));
+            let nested = match opened {
+                Ok(nested) if submodule::is_active(&config, &submodule)? => nested,
+                _ => {
+                    if !quiet {
+                        println!("-{} {}", entry.id, display_path);
+                    }
+                    continue;
+                }
+            };
+
+            let (state, id) = match nested.head() {
+                Some(head) if head == entry.id => (' ', head),
+                _ if cached => ('+', entry.id.clone()),
+                Some(head) => ('+', head),
+                None => return Err(Error::SubmoduleHeadUnresolved(entry.path.clone())),
+            };
+            if !quiet {
+                match submodule::rev_name(&nested, &id) {
+                    Some(name) => println!("{}{} {} ({})", state, id, display_path, name),
+                    None => println!("{}{} {}", state, id, display_path),
+                }
+            }
+            if recursive {
+                let entries = self.submodule_entries(&nested, None, &[])?;
+                let prefix = format!("{}/", display_path);
+                let at = (None, Some(prefix.as_str()));
+                self.submodule_status(&nested, &entries, at, cached, recursive, quiet)?;
+            }
+        }
+        Ok(())
+    }
+
+    // runs `command` in each submodule that is checked out, through the shell; a command given
+    // as one argument can use the variables set for it
+    fn submodule_foreach(
+        &self,
+        repository: &Repository,
+        entries: &[IndexEntry],
+        at: (Option<&str>, Option<&str>),
+        command: &[String],
+        recursive: bool,
+        quiet: bool,
+    ) -> Result<()> {
+        let work_tree = repository.work_tree().ok_or(Error::NoWorkTree)?;
+        let toplevel = fs::canonicalize(work_tree)?;
+        let gitmodules = submodule::gitmodules(repository)?;
+        for entry in entries {
+            let dir = paths::to_native(work_tree, &entry.path);
+            let Ok(nested) = Repository::open(&dir) else {
+                continue;
+            };
+            let display_path = submodule_display_path(&entry.path, at);
+            let submodule = submodule::find(&gitmodules, &entry.path)
+                .ok_or_else(|| Error::NoSubmoduleUrl(display_path.clone()))?;
+            if !quiet {
+                println!("Entering '{}'", display_path);
+            }
+
+            let mut child = std::process::Command::new("sh");
+            child.current_dir(&dir);
+            for variable in submodule::LOCAL_ENV {
+                child.env_remove(variable);
+            }
+            match command {
+                // as in git, $path is set in the script, so as not to clash with $PATH where
+                // the environment ignores case
+                [script] => {
+                    let script = format!("path={}; {}", convert::shell_quote(&entry.path), script);
+                    child
+                        .arg("-c")
+                        .arg(&script)
+                        .arg(&script)
+                        .env("name", &submodule.name)
+                        .env("sm_path", &entry.path)
+                        .env("displaypath", &display_path)
+                        .env("sha1", &entry.id)
+                        .env("toplevel", &toplevel);
+                }
+                [program, args @ ..] => {
+                    child
+                        .arg("-c")
+                        .arg(format!("{} \"$@\"", program))
+                        .arg(program)
+                        .args(args);
+                }
+                [] => continue,
+            }
+            if !child.status()?.success() {
+                return Err(Error::SubmoduleCommandFailed(display_path));
+            }
+
+            if recursive {
+                let entries = self.submodule_entries(&nested, None, &[])?;
+                let prefix = format!("{}/", display_path);
+                let at = (None, Some(prefix.as_str()));
+                if let Err(error) =
+                    self.submodule_foreach(&nested, &entries, at, command, recursive, quiet)
+                {
+                    eprintln!("fatal: {}", error);
+                    return Err(Error::NestedSubmoduleCommandFailed(display_path));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // sets submodule.<name>.url for each submodule in use to the url .gitmodules has, and the
+    // url of the remote of the submodule's repository, if it's checked out, to match. A url
+    // relative to the superproject's remote is resolved against the remote's url, or against
+    // the work tree when there's no remote
+    fn submodule_sync(
+        &self,
+        repository: &Repository,
+        entries: &[IndexEntry],
+        at: (Option<&str>, Option<&str>),
+        recursive: bool,
+        quiet: bool,
+    ) -> Result<()> {
+        let work_tree = repository.work_tree().ok_or(Error::NoWorkTree)?;
+        let gitmodules = submodule::gitmodules(repository)?;
+        let config = Config::for_repository(repository.common_dir());
+        for entry in entries {
+            let Some(submodule) = submodule::find(&gitmodules, &entry.path) else {
+                continue;
+            };
+            if !submodule::is_active(&config, &submodule)? {
+                continue;
+            }
+            let (url, nested_url) = match &submodule.url {
+                Some(url) if url.starts_with("./") || url.starts_with("../") => {
+                    let remote = submodule::default_remote(repository);
+                    let remote_url = match config.get(&format!("remote.{}.url", remote)) {
+                        Some(remote_url) => remote_url.to_string(),
+                        None => fs::canonicalize(work_tree)?.display().to_string(),
+                    };
+                    let up_path = submodule::up_path(&entry.path);
+                    (
+                        submodule::relative_url(&remote_url, url, None)?,
+                        submodule::relative_url(&remote_url, url, Some(&up_path))?,
+                    )
+                }
+                Some(url) => (url.clone(), url.clone()),
+                None => (String::new(), String::new()),
+            };
+            let display_path = submodule_display_path(&entry.path, at);
+            if !quiet {
+                println!("Synchronizing submodule url for '{}'", display_path);
+            }
+            let key = format!("submodule.{}.url", submodule.name);
+            config::set_value(&repository.common_dir().join("config"), &key, &url)?;
+
+            let Ok(nested) = Repository::open(&paths::to_native(work_tree, &entry.path)) else {
+                continue;
+            };
+            let key = format!("remote.{}.url", submodule::default_remote(&nested));
+            config::set_value(&nested.common_dir().join("config"), &key, &nested_url)?;
+            if recursive {
+                let entries = self.submodule_entries(&nested, None, &[])?;
+                let prefix = format!("{}/", display_path);
+                let at = (None, Some(prefix.as_str()));
+                self.submodule_sync(&nested, &entries, at, recursive, quiet)?;
+            }
+        }
+        Ok(())
+    }
+
+    // git subtree: `prefix` is the directory the subtree is in, from the top of the work tree
+    fn subtree(&self, prefix: Option<&str>, quiet: bool, command: SubtreeCommand) -> Result<()> {
+        let repository = self.repository()?;
+        let work_tree = repository.work_tree().ok_or(Error::NoWorkTree)?;
+        let Some(dir) = prefix
+            .map(|prefix| prefix.strip_suffix('/').unwrap_or(prefix))
+            .filter(|prefix| !prefix.is_empty())
+        else {
+            return Err(subtree_error("you must provide the --prefix option."));
+        };
+        let exists = paths::to_native(work_tree, dir).exists();
+        match command {
+            SubtreeCommand::Add { .. } if exists => {
+                return Err(subtree_error(&format!("prefix '{}' already exists.", dir)))
+            }
+            SubtreeCommand::Add { .. } => {}
+            _ if !exists => {
+                return Err(subtree_error(&format!(
+                    "'{}' does not exist; use 'git subtree add'",
+                    dir
+                )))
+            }
+            _ => {}
+        }
+        let commit = |name: &str| {
+            repository
+                .rev_parse(name)
+                .and_then(|id| repository.peel(&id, GitObjectType::Commit))
+                .map(|commit| commit.id)
+                .map_err(|_| subtree_error(&format!("'{}' does not refer to a commit", name)))
+        };
+
+        match command {
+            SubtreeCommand::Add { merge, args } => {
+                self.subtree_ensure_clean(&repository)?;
+                let rev = match &args[..] {
+                    [name] => commit(name)?,
+                    [url, reference] => {
+                        subtree_check_ref(reference)?;
+                        println!("git fetch {} {}", url, reference);
+                        self.subtree_fetch(&repository, url, reference)?
+                    }
+                    _ => unreachable!("clap takes one or two arguments"),
+                };
+                let message = merge.message.as_deref();
+                self.subtree_add(&repository, dir, &rev, merge.squash, message, false, quiet)
+            }
+            SubtreeCommand::Merge {
+                merge,
+                commit: name,
+            } => {
+                let rev = commit(&name)?;
+                let message = merge.message.as_deref();
+                self.subtree_merge(&repository, dir, &rev, merge.squash, message, quiet)
+            }
+            SubtreeCommand::Pull {
+                merge,
+                repository: url,
+                reference,
+            } => {
+                self.subtree_ensure_clean(&repository)?;
+                subtree_check_ref(&reference)?;
+                let rev = self.subtree_fetch(&repository, &url, &reference)?;
+                let message = merge.message.as_deref();
+                self.subtree_merge(&repository, dir, &rev, merge.squash, message, quiet)
+            }
+            SubtreeCommand::Split {
+                annotate,
+                branch,
+                ignore_joins,
+                onto,
+                rejoin,
+                squash,
+                message,
+                commit: name,
+            } => {
+                let rev = match name {
+                    Some(name) => commit(&name)?,
+                    None => repository
+                        .head()
+                        .ok_or_else(|| Error::ObjectNotFound("HEAD".to_string()))?,
+                };
+                if rejoin {
+                    self.subtree_ensure_clean(&repository)?;
+                }
+                let mut split =
+                    subtree::Split::new(&repository, dir, annotate.as_deref().unwrap_or(""), quiet);
+                if let Some(onto) = onto {
+                    split.onto(&commit(&onto)?)?;
+                }
+                split.run(&rev, ignore_joins)?;
+                let Some((latest_old, latest_new)) = split.latest else {
+                    return Err(subtree_error("no new revisions were found"));
+                };
+
+                if rejoin {
+                    let message = format!(
+                        "{}\n\ngit-subtree-dir: {}\ngit-subtree-mainline: {}\n\
+                         git-subtree-split: {}",
+                        message.unwrap_or_else(|| format!(
+                            "Split '{}/' into commit '{}'",
+                            dir, latest_new
+                        )),
+                        dir,
+                        latest_old,
+                        latest_new
+                    );
+                    match subtree::latest_squash(&repository, dir)? {
+                        None => self.subtree_add(
+                            &repository,
+                            dir,
+                            &latest_new,
+                            squash,
+                            Some(&message),
+                            true,
+                            quiet,
+                        )?,
+                        Some(_) => self.subtree_merge(
+                            &repository,
+                            dir,
+                            &latest_new,
+                            squash,
+                            Some(&message),
+                            quiet,
+                        )?,
+                    }
+                }
+                if let Some(branch) = branch {
+                    let name = format!("refs/heads/{}", branch);
+                    let action = match repository.find_reference(&name) {
+                        Some(id) if !repository.is_ancestor(&id, &latest_new) => {
+                            return Err(subtree_error(&format!(
+                                "branch '{}' is not an ancestor of commit '{}'.",
+                                branch, latest_new
+                            )))
+                        }
+                        Some(_) => "Updated",
+                        None => "Created",
+                    };
+                    repository.update_reference(&name, &latest_new)?;
+                    if !quiet {
+                        eprintln!("{} branch '{}'", action, branch);
+                    }
+                }
+                println!("{}", latest_new);
+                Ok(())
+            }
+        }
+    }
+
+    // the subtree commands leave alone a work tree or an index with changes from HEAD
+    fn subtree_ensure_clean(&self, repository: &Repository) -> Result<()> {
+        let head = repository
+            .head()
+            .ok_or_else(|| Error::ObjectNotFound("HEAD".to_string()))?;
+        let head_files = repository.tree_files(&head, "")?;
+        let index = repository.index()?;
+        if !self
+            .work_tree_changes(repository, &index, &head_files)?
+            .is_empty()
+        {
+            return Err(subtree_error(
+                "working tree has modifications.  Cannot add.",
+            ));
+        }
+        if !diff::diff_entries(&head_files, index.entries()).is_empty() {
+            return Err(subtree_error("index has modifications.  Cannot add."));
+        }
+        Ok(())
+    }
+
+    // fetches `reference` from `url` into FETCH_HEAD, and returns the commit it is at
+    fn subtree_fetch(&self, repository: &Repository, url: &str, reference: &str) -> Result<String> {
+        let git_dir = repository.common_dir();
+        let url = url.strip_suffix('/').unwrap_or(url);
+        Repo::new(url, git_dir, repository.work_tree().unwrap_or(git_dir))
+            .branch(reference)
+            .fetch()?;
+        let fetched = repository.rev_parse("FETCH_HEAD")?;
+        Ok(repository.peel(&fetched, GitObjectType::Commit)?.id)
+    }
+
+    // commits `rev` as the directory `dir`, merging its history, or a squash of it, into
+    // HEAD's. With `rejoin` the directory already has what `rev` has, and `message` is the
+    // whole message
+    #[allow(clippy::too_many_arguments)]
+    fn subtree_add(
+        &self,
+        repository: &Repository,
+        dir: &str,
+        rev: &str,
+        squash: bool,
+        message: Option<&str>,
+        rejoin: bool,
+        quiet: bool,
+    ) -> Result<()> {
+        let head = repository
+            .head()
+            .ok_or_else(|| Error::ObjectNotFound("HEAD".to_string()))?;
+        let head_files = repository.tree_files(&head, "")?;
+        let mut files = head_files.clone();
+        if !rejoin {
+            files.extend(repository.tree_files(rev, &format!("{}/", dir))?);
+            files.sort_by(|a, b| a.path.as_bytes().cmp(b.path.as_bytes()));
+        }
+        let mut index = repository.index()?;
+        self.check_out(repository, &mut index, &head_files, &files)?;
+        index.write(&repository.index_path())?;
+        let tree = repository.write_tree_entries(&files)?;
+
+        let (merged, message) = match squash {
+            true => {
+                let squashed = self.subtree_squash(repository, dir, None, rev)?;
+                let message = match message {
+                    Some(message) => format!("{}\n", message),
+                    None => format!("Merge commit '{}' as '{}'\n", squashed, dir),
+                };
+                (squashed, message)
+            }
+            false => {
+                let message = match (message, rejoin) {
+                    (Some(message), true) => format!("{}\n", message),
+                    (message, _) => format!(
+                        "{}\n\ngit-subtree-dir: {}\ngit-subtree-mainline: {}\n\
+                         git-subtree-split: {}\n",
+                        message.map_or_else(
+                            || format!("Add '{}/' from commit '{}'", dir, rev),
+                            str::to_string
+                        ),
+                        dir,
+                        head,
+                        rev
+                    ),
+                };
+                (rev.to_string(), message)
+            }
+        };
+        let mut parents = vec![];
+        if head != rev {
+            parents.push(head.as_str());
+        }
+        parents.push(&merged);
+        let config = Config::for_repository(repository.common_dir());
+        let author = signature(&config, "author")?;
+        let committer = signature(&config, "committer")?;
+        let commit = repository.create_commit(&tree, &parents, &author, &committer, &message)?;
+        repository.update_reference("HEAD", &commit)?;
+        if !quiet {
+            eprintln!("Added dir '{}'", dir);
+        }
+        Ok(())
+    }
+
+    // merges `rev`, or a squash of the changes up to it since the last one, into the
+    // directory `dir`
+    fn subtree_merge(
+        &self,
+        repository: &Repository,
+        dir: &str,
+        rev: &str,
+        squash: bool,
+        message: Option<&str>,
+        quiet: bool,
+    ) -> Result<()> {
+        self.subtree_ensure_clean(repository)?;
+        let mut rev = rev.to_string();
+        if squash {
+            let Some((old, split)) = subtree::latest_squash(repository, dir)? else {
+                return Err(subtree_error(&format!(
+                    "can't squash-merge: '{}' was never added.",
+                    dir
+                )));
+            };
+            if split == rev {
+                if !quiet {
+                    eprintln!("Subtree is already at commit {}.", rev);
+                }
+                return Ok(());
+            }
+            rev = self.subtree_squash(repository, dir, Some((&old, &split)), &rev)?;
+        }
+        self.merge(&MergeArgs {
+            message: message.map(str::to_string),
+            strategy: None,
+            strategy_option: vec![format!("subtree={}", dir)],
+            no_ff: true,
+            squash: false,
+            abort: false,
+            commits: vec![rev],
+        })
+    }
+
+    // a commit with the tree of `new`, squashing the project's changes since `old`, the last
+    // squash and the commit it was at
+    fn subtree_squash(
+        &self,
+        repository: &Repository,
+        dir: &str,
+        old: Option<(&str, &str)>,
+        new: &str,
+    ) -> Result<String> {
+        let tree = repository.find_commit(new)?.tree;
+        let message = subtree::squash_message(repository, dir, old.map(|(_, split)| split), new)?;
+        let parents: Vec<&str> = old.map(|(squash, _)| squash).into_iter().collect();
+        let config = Config::for_repository(repository.common_dir());
+        let author = signature(&config, "author")?;
+        let committer = signature(&config, "committer")?;
+        repository.create_commit(&tree, &parents, &author, &committer, &message)
+    }
+
+    fn stripspace(&self, strip_comments: bool, comment_lines: bool) -> Result<()> {
+        let comment = message::comment_char(&self.config_anywhere());
+        let text = io::read_to_string(io::stdin())?;
+        let text = match comment_lines {
+            true => message::comment_lines(&text, comment),
+            false => message::stripspace(&text, strip_comments.then_some(comment)),
+        };
+        io::stdout().write_all(text.as_bytes())?;
+        Ok(())
+    }
+
+    fn apply(&self, reverse: bool, check: bool, patches: &[String]) -> Result<()> {
+        let mut input = vec![];
+        match patches.is_empty() {
+            true => io::stdin().read_to_end(&mut input).map(drop)?,
+            false => {
+                for patch in patches {
+                    input.extend(fs::read(patch).map_err(|error| {
+                        io::Error::new(
+                            error.kind(),
+                            format!("can't open patch '{}': {}", patch, error),
+                        )
+                    })?);
+                }
+            }
+        }
+        let mut file_patches = apply::parse(&input)?;
+        if reverse {
+            file_patches = file_patches
+                .into_iter()
+                .map(apply::FilePatch::reverse)
+                .collect::<Result<_>>()?;
+        }
+        let top = match self.repository() {
+            Ok(repository) => repository
+                .work_tree()
+                .ok_or(Error::NoWorkTree)?
+                .to_path_buf(),
+            Err(_) => env::current_dir()?,
+        };
+
+        // every patch has to apply before any file is touched
+        let mut results = vec![];
+        for patch in &file_patches {
+            let old = match &patch.old_path {
+                Some(path) => {
+                    let file = top.join(path);
+                    let missing = || Error::PatchTargetMissing(path.clone());
+                    match fs::symlink_metadata(&file) {
+                        Ok(metadata) if metadata.is_symlink() => symlink_target(&file)?,
+                        Ok(_) => fs::read(&file)?,
+                        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                            return Err(missing())
+                        }
+                        Err(error) => return Err(error.into()),
+                    }
+                }
+                None => vec![],
+            };
+            if let Some(path) = &patch.new_path {
+                let moved = patch.old_path.as_ref() != Some(path);
+                if moved && fs::symlink_metadata(top.join(path)).is_ok() {
+                    return Err(Error::PatchTargetExists(path.clone()));
+                }
+            }
+            results.push(patch.apply(&old)?);
+        }
+        if check {
+            return Ok(());
+        }
+
+        for (patch, new) in file_patches.iter().zip(results) {
+            if let Some(path) = &patch.old_path {
+                if patch.new_path.as_ref() != Some(path) {
+                    let file = top.join(path);
+                    fs::remove_file(&file)?;
+                    remove_empty_parents(&top, &file);
+                }
+            }
+            let Some(path) = &patch.new_path else {
+                continue;
+            };
+            let file = top.join(path);
+            if let Some(parent) = file.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mode = patch.new_mode.or(patch.old_mode).unwrap_or(0o100644);
+            if mode == 0o120000 {
+                if fs::symlink_metadata(&file).is_ok() {
+                    fs::remove_file(&file)?;
+                }
+                git_client::write_symlink(&new, &file)?;
+            } else {
+                if fs::symlink_metadata(&file).is_ok_and(|metadata| metadata.is_symlink()) {
+                    fs::remove_file(&file)?;
+                }
+                fs::write(&file, &new)?;
+                git_client::set_executable(&file, mode == 0o100755)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn interpret_trailers(&self, args: &InterpretTrailersArgs) -> Result<()> {
+        if args.in_place && args.files.is_empty() {
+            return Err(Error::NoInPlaceFile);
+        }
+        let config = self.config_anywhere();
+        let comment = message::comment_char(&config);
+        let only_trailers = args.only_trailers || args.parse;
+        let only_input = args.only_input || args.parse;
+        let unfold = args.unfold || args.parse;
+
+        let defaults = trailer::Rules::default();
+        let configured = trailer::Rules {
+            place: trailer_rule(
+                &config,
+                "trailer.where",
+                "where",
+                None,
+                trailer::Where::parse,
+            )?
+            .unwrap_or(defaults.place),
+            if_exists: trailer_rule(
+                &config,
+                "trailer.ifExists",
+                "if-exists",
+                None,
+                trailer::IfExists::parse,
+            )?
+            .unwrap_or(defaults.if_exists),
+            if_missing: trailer_rule(
+                &config,
+                "trailer.ifMissing",
+                "if-missing",
+                None,
+                trailer::IfMissing::parse,
+            )?
+            .unwrap_or(defaults.if_missing),
+        };
+
+        // each trailer is added by the rules the options before it left in force
+        let mut rules = configured;
+        let mut new = vec![];
+        for arg in &args.trailers.0 {
+            match arg {
+                TrailerArg::Trailer(arg) => match trailer::Trailer::from_arg(arg) {
+                    Some(trailer) => new.push((trailer, rules)),
+                    None => eprintln!("error: empty trailer token in trailer '{}'", arg.trim()),
+                },
+                TrailerArg::Where(value) => {
+                    rules.place = match value {
+                        Some(value) => trailer_rule(
+                            &config,
+                            "trailer.where",
+                            "where",
+                            Some(value),
+                            trailer::Where::parse,
+                        )?
+                        .unwrap_or(configured.place),
+                        None => configured.place,
+                    }
+                }
+                TrailerArg::IfExists(value) => {
+                    rules.if_exists = match value {
+                        Some(value) => trailer_rule(
+                            &config,
+                            "trailer.ifExists",
+                            "if-exists",
+                            Some(value),
+                            trailer::IfExists::parse,
+                        )?
+                        .unwrap_or(configured.if_exists),
+                        None => configured.if_exists,
+                    }
+                }
+                TrailerArg::IfMissing(value) => {
+                    rules.if_missing = match value {
+                        Some(value) => trailer_rule(
+                            &config,
+                            "trailer.ifMissing",
+                            "if-missing",
+                            Some(value),
+                            trailer::IfMissing::parse,
+                        )?
+                        .unwrap_or(configured.if_missing),
+                        None => configured.if_missing,
+                    }
+                }
+            }
+        }
+
+        let inputs: Vec<Option<&PathBuf>> = match args.files.is_empty() {
+            true => vec![None],
+            false => args.files.iter().map(Some).collect(),
+        };
+        for file in inputs {
+            let text = match file {
+                Some(file) => fs::read_to_string(file).map_err(|error| {
+                    io::Error::new(
+                        error.kind(),
+                        format!("could not read input file '{}': {}", file.display(), error),
+                    )
+                })?,
+                None => io::read_to_string(io::stdin())?,
+            };
+            let block = trailer::Block::find(&text, comment, !args.no_divider);
+            let mut trailers = block.trailers(comment);
+            if unfold {
+                trailers.iter_mut().for_each(trailer::Trailer::unfold);
+            }
+            if !only_input {
+                for (trailer, rules) in &new {
+                    trailer::add(&mut trailers, trailer.clone(), rules);
+                }
+            }
+
+            let mut out = String::new();
+            if !only_trailers {
+                out += &text[..block.start];
+                if !block.blank_line_before {
+                    out.push('\n');
+                }
+            }
+            for trailer in &trailers {
+                let skip = match trailer.token {
+                    Some(_) => args.trim_empty && trailer.value.is_empty(),
+                    None => only_trailers,
+                };
+                if !skip {
+                    out += &format!("{}\n", trailer);
+                }
+            }
+            if !only_trailers {
+                out += &text[block.end..];
+            }
+            match file {
+                Some(file) if args.in_place => fs::write(file, out)?,
+                _ => io::stdout().write_all(out.as_bytes())?,
+            }
+        }
+        Ok(())
+    }
+
+    fn tag(&self, args: &TagArgs) -> Result<()> {
+        let repository = self.repository()?;
+        if args.delete {
+            let mut failed = false;
+            for name in &args.args {
+                let ref_name = format!("refs/tags/{}", name);
+                match repository.find_reference(&ref_name) {
+                    Some(id) => {
+                        repository.delete_reference(&ref_name)?;
+                        println!("Deleted tag '{}' (was {})", name, pretty::abbreviate(&id));
+                    }
+                    None => {
+                        eprintln!("error: tag '{}' not found.", name);
+                        failed = true;
+                    }
+                }
+            }
+            return if failed { Err(Error::Exit(1)) } else { Ok(()) };
+        }
+        if args.verify {
+            let mut ids = vec![];
+            for name in &args.args {
+                ids.push(repository.find_reference(&format!("refs/tags/{}", name)));
+            }
+            return verify_objects(
+                &repository,
+                &args.args,
+                ids,
+                GitObjectType::Tag,
+                true,
+                false,
+            );
+        }
+        if args.list || args.args.is_empty() {
+            let mut out = io::stdout().lock();
+            for (name, _) in repository.references() {
+                let Some(tag) = name.strip_prefix("refs/tags/") else {
+                    continue;
+                };
+                let listed = args.args.is_empty()
+                    || args
+                        .args
+                        .iter()
+                        .any(|pattern| wildmatch(pattern.as_bytes(), tag.as_bytes(), false));
+                if listed {
+                    writeln!(out, "{}", tag)?;
+                }
+            }
+            return Ok(());
+        }
+        if args.args.len() > 2 {
+            return Err(usage_error("tag", "too many arguments"));
+        }
+
+        let name = &args.args[0];
+        let ref_name = format!("refs/tags/{}", name);
+        if !refname::is_valid(&ref_name, refname::Format::default()) {
+            return Err(Error::InvalidTagName(name.clone()));
+        }
+        let old = repository.find_reference(&ref_name);
+        if old.is_some() && !args.force {
+            return Err(Error::TagExists(name.clone()));
+        }
+        let target = args.args.get(1).map_or("HEAD", String::as_str);
+        let object = repository.find_object(&repository.rev_parse(target)?)?;
+
+        let config = Config::for_repository(repository.common_dir());
+        let tagger = signature(&config, "committer")?;
+        let sign = args.sign || args.local_user.is_some();
+        let annotate = args.annotate || sign || !args.messages.is_empty();
+        // tag.gpgSign only signs the tags that are annotated anyway
+        let key = match (annotate, sign) {
+            (false, _) => None,
+            (true, true) => {
+                signing_key(&config, args.local_user.as_deref().or(Some("")), false, "")
+            }
+            (true, false) => signing_key(&config, None, false, "tag.gpgSign"),
+        };
+        let id = match annotate {
+            true => {
+                if args.messages.is_empty() {
+                    return Err(usage_error("tag", "an annotated tag needs a message (-m)"));
+                }
+                let comment = message::comment_char(&config);
+                let message = message::stripspace(&args.messages.join("\n\n"), Some(comment));
+                let mut content = repository::tag_content(
+                    &object.id,
+                    object.object_type,
+                    name,
+                    &tagger,
+                    &message,
+                );
+                // a tag's signature follows its message
+                if let Some(key) = key {
+                    let identity = format!("{} <{}>", tagger.name, tagger.email);
+                    let signer = sign::signer(&config, &key, &identity)?;
+                    content += &signer.sign(content.as_bytes())?;
+                }
+                repository.write_object(GitObjectType::Tag, content.as_bytes())?
+            }
+            false => object.id,
+        };
+        repository.set_reference(&ref_name, &id)?;
+        if let Some(old) = old.filter(|old| *old != id) {
+            println!("Updated tag '{}' (was {})", name, pretty::abbreviate(&old));
+        }
+        Ok(())
+    }
+
+    fn fast_export(&self, args: &FastExportArgs) -> Result<()> {
+        let repository = self.repository()?;
+        let mut export = FastExport::new(&repository);
+        if let Some(mode) = &args.signed_tags {
+            export.signed_tags = SignedTags::parse(mode).ok_or_else(|| {
+                usage_error(
+                    "fast-export",
+                    &format!("Unknown signed-tags mode: {}", mode),
+                )
+            })?;
+        }
+        export.drop_filtered_tags = match args.tag_of_filtered_object.as_deref() {
+            None | Some("abort") => false,
+            Some("drop") => true,
+            Some(mode) => {
+                let message = format!("Unknown tag-of-filtered mode: {}", mode);
+                return Err(usage_error("fast-export", &message));
+            }
+        };
+        export.no_data = args.no_data;
+        export.full_tree = args.full_tree;
+        export.mark_tags = args.mark_tags;
+        if let Some(file) = &args.import_marks {
+            export.import_marks(&fs::read_to_string(file)?)?;
+        }
+
+        // --all is every ref, then HEAD if it points anywhere
+        let mut revisions = vec![];
+        if args.all {
+            revisions.extend(repository.references().map(|(name, _)| name));
+            if repository.head().is_some() {
+                revisions.push("HEAD".to_string());
+            }
+        }
+        revisions.extend(args.revisions.iter().cloned());
+        if revisions.is_empty() {
+            return Ok(());
+        }
+
+        export.export(&mut io::stdout().lock(), &revisions)?;
+        if let Some(file) = &args.export_marks {
+            fs::write(file, export.export_marks()?)?;
+        }
+        Ok(())
+    }
+
+    fn fast_import(&self, args: &FastImportArgs) -> Result<()> {
+        let repository = self.repository()?;
+        let mut import = FastImport::new(&repository);
+        import.force = args.force;
+        import.done = args.done;
+        import.date_format = match args.date_format.as_deref() {
+            None | Some("raw") => DateFormat::Raw,
+            Some("now") => DateFormat::Now,
+            Some(format) => {
+                let message = format!("unknown --date-format argument {}", format);
+                return Err(usage_error("fast-import", &message));
+            }
+        };
+        if let Some(file) = &args.import_marks {
+            import.import_marks(&fs::read_to_string(file)?)?;
+        }
+        import.export_marks = args
+            .export_marks
+            .as_ref()
+            .map(|file| file.to_string_lossy().into_owned());
+
+        let result = import.import(io::stdin().lock(), &mut io::stdout().lock());
+        if !args.quiet {
+            eprint!("{}", import.statistics());
+        }
+        result
+    }
+
+    fn rewrite(&self, args: &RewriteArgs) -> Result<()> {
+        let repository = self.repository()?;
+        let mut rewrite = Rewrite::new(&repository);
+        rewrite.paths = args.paths.clone();
+        rewrite.invert_paths = args.invert_paths;
+        for rename in &args.renames {
+            let (old, new) = rename.split_once(':').ok_or_else(|| {
+                usage_error(
+                    "rewrite",
+                    "--path-rename expects one colon in its argument: <old>:<new>",
+                )
+            })?;
+            rewrite.renames.push((old.to_string(), new.to_string()));
+        }
+        if let Some(size) = &args.strip_blobs_bigger_than {
+            rewrite.max_blob_size = Some(
+                config::parse_size(size)
+                    .ok_or_else(|| usage_error("rewrite", &format!("invalid size '{}'", size)))?,
+            );
+        }
+        rewrite.keep_empty = args.keep_empty;
+
+        // the refs as they were are kept under refs/original/, which has to be cleared first
+        let backups: Vec<String> = repository
+            .references()
+            .map(|(name, _)| name)
+            .filter(|name| name.starts_with("refs/original/"))
+            .collect();
+        if !backups.is_empty() && !args.force {
+            eprintln!("A previous backup already exists in refs/original/");
+            eprintln!("Force overwriting the backup with -f");
+            return Err(Error::Exit(1));
+        }
+
+        // every ref but the backups, and HEAD when it is detached
+        let mut refs = vec![];
+        match args.refs.is_empty() {
+            true => {
+                refs.extend(
+                    repository
+                        .references()
+                        .filter(|(name, _)| !name.starts_with("refs/original/")),
+                );
+                if let (None, Some(head)) = (repository.head_target(), repository.head()) {
+                    refs.push(("HEAD".to_string(), head));
+                }
+            }
+            false => {
+                for name in &args.refs {
+                    let full_name = repository
+                        .full_reference_name(name)
+                        .ok_or_else(|| Error::ObjectNotFound(name.clone()))?;
+                    let id = repository.rev_parse(&full_name)?;
+                    refs.push((full_name, id));
+                }
+                refs.sort();
+                refs.dedup();
+            }
+        }
+
+        let mut tips = vec![];
+        for (_, id) in &refs {
+            if let Ok(commit) = repository.peel(id, GitObjectType::Commit) {
+                tips.push(commit.id);
+            }
+        }
+        tips.sort();
+        tips.dedup();
+        rewrite.rewrite(&tips)?;
+        let mut updates = vec![];
+        for (name, id) in &refs {
+            updates.push((name, id, rewrite.rewrite_ref(id)?));
+        }
+
+        // the work tree follows HEAD, before any ref moves, so that local changes in the way
+        // leave everything as it was
+        let head_name = repository
+            .head_target()
+            .unwrap_or_else(|| "HEAD".to_string());
+        let head = updates
+            .iter()
+            .find(|(name, id, new)| **name == head_name && new.as_ref() != Some(*id));
+        if let (Some((_, old, new)), Some(_)) = (head, repository.work_tree()) {
+            let mut index = repository.index()?;
+            let from = repository.tree_files(old, "")?;
+            let to = match new {
+                Some(new) => repository.tree_files(new, "")?,
+                None => vec![],
+            };
+            self.check_out(&repository, &mut index, &from, &to)?;
+            index.write(&repository.index_path())?;
+        }
+
+        for name in &backups {
+            repository.delete_reference(name)?;
+        }
+        for (name, id, new) in updates {
+            match new {
+                Some(new) if new == *id => {
+                    eprintln!("WARNING: Ref '{}' is unchanged", name);
+                    continue;
+                }
+                Some(new) => {
+                    repository.set_reference(name, &new)?;
+                    println!("Ref '{}' was rewritten", name);
+                }
+                None => {
+                    repository.delete_reference(name)?;
+                    println!("Ref '{}' was deleted", name);
+                }
+            }
+            repository.set_reference(&format!("refs/original/{}", name), id)?;
+        }
+        Ok(())
+    }
+
+    fn replace(&self, args: &ReplaceArgs) -> Result<()> {
+        // what is replaced and what replaces it are looked at as they are stored
+        let repository = self.repository()?.without_replacements();
+        if args.delete {
+            if args.args.is_empty() {
+                return Err(usage_error("replace", "-d needs at least one argument"));
+            }
+            let mut failed = false;
+            for name in &args.args {
+                let Ok(id) = repository.rev_parse(name) else {
+                    eprintln!("error: failed to resolve '{}' as a valid ref", name);
+                    failed = true;
+                    continue;
+                };
+                let ref_name = format!("refs/replace/{}", id);
+                match repository.find_reference(&ref_name) {
+                    Some(_) => {
+                        repository.delete_reference(&ref_name)?;
+                        println!("Deleted replace ref '{}'", id);
+                    }
+                    None => {
+                        eprintln!("error: replace ref '{}' not found", id);
+                        failed = true;
+                    }
+                }
+            }
+            return if failed { Err(Error::Exit(1)) } else { Ok(()) };
+        }
+
+        if args.graft {
+            let Some((name, parents)) = args.args.split_first() else {
+                return Err(usage_error("replace", "-g needs at least one argument"));
+            };
+            let commit = repository
+                .rev_parse(name)
+                .and_then(|id| repository.peel(&id, GitObjectType::Commit));
+            let commit = match commit {
+                Ok(commit) => commit,
+                Err(error) => {
+                    eprintln!("error: {}", error);
+                    eprintln!("error: could not parse {} as a commit", name);
+                    return Err(Error::Exit(255));
+                }
+            };
+            let mut new_parents = vec![];
+            for parent in parents {
+                match repository
+                    .rev_parse(parent)
+                    .and_then(|id| repository.peel(&id, GitObjectType::Commit))
+                {
+                    Ok(parent) => new_parents.push(parent.id),
+                    Err(_) => {
+                        eprintln!("error: not a valid object name: '{}'", parent);
+                        return Err(Error::Exit(255));
+                    }
+                }
+            }
+            if rewrite::has_signature(&commit.data) {
+                eprintln!(
+                    "warning: the original commit '{}' has a gpg signature",
+                    name
+                );
+                eprintln!("warning: the signature will be removed in the replacement commit!");
+            }
+            let tree = repository.find_commit(&commit.id)?.tree;
+            let content = rewrite::commit_content(&commit.data, &tree, &new_parents);
+            let new = repository.write_object(GitObjectType::Commit, &content)?;
+            if new == commit.id {
+                eprintln!("error: new commit is the same as the old one: '{}'", new);
+                return Err(Error::Exit(255));
+            }
+            return replace_object(&repository, &commit.id, &new, args.force);
+        }
+
+        if args.list || args.args.is_empty() {
+            if args.args.len() > 1 {
+                return Err(usage_error(
+                    "replace",
+                    "only one pattern can be given with -l",
+                ));
+            }
+            let format = args.format.as_deref().unwrap_or("short");
+            if !["short", "medium", "long"].contains(&format) {
+                eprintln!("error: invalid replace format '{}'", format);
+                eprintln!("valid formats are 'short', 'medium' and 'long'");
+                return Err(Error::Exit(255));
+            }
+            let pattern = args.args.first().map_or("*", String::as_str);
+            let object_type = |id: &str| {
+                repository
+                    .find_object(id)
+                    .map_or("unknown".to_string(), |object| {
+                        object.object_type.to_string()
+                    })
+            };
+            for (name, replacement) in repository.references() {
+                let Some(id) = name.strip_prefix("refs/replace/") else {
+                    continue;
+                };
+                if !wildmatch(pattern.as_bytes(), id.as_bytes(), false) {
+                    continue;
+                }
+                match format {
+                    "short" => println!("{}", id),
+                    "medium" => println!("{} -> {}", id, replacement),
+                    _ => println!(
+                        "{} ({}) -> {} ({})",
+                        id,
+                        object_type(id),
+                        replacement,
+                        object_type(&replacement)
+                    ),
+                }
+            }
+            return Ok(());
+        }
+
+        let [object, replacement] = args.args.as_slice() else {
+            return Err(usage_error("replace", "bad number of arguments"));
+        };
+        let mut ids = vec![];
+        for name in [object, replacement] {
+            match repository.rev_parse(name) {
+                Ok(id) => ids.push(id),
+                Err(_) => {
+                    eprintln!("error: failed to resolve '{}' as a valid ref", name);
+                    return Err(Error::Exit(255));
+                }
+            }
+        }
+        if !args.force {
+            let replaced_type = repository.find_object(&ids[0])?.object_type;
+            let replacement_type = repository.find_object(&ids[1])?.object_type;
+            if replaced_type != replacement_type {
+                eprintln!("error: Objects must be of the same type.");
+                eprintln!(
+                    "'{}' points to a replaced object of type '{}'",
+                    object, replaced_type
+                );
+                eprintln!(
+                    "while '{}' points to a replacement object of type '{}'.",
+                    replacement, replacement_type
+                );
+                return Err(Error::Exit(255));
+            }
+        }
+        replace_object(&repository, &ids[0], &ids[1], args.force)
+    }
+
+    fn verify(&self, args: &VerifyArgs, object_type: GitObjectType) -> Result<()> {
+        let repository = self.repository()?;
+        let ids = args
+            .objects
+            .iter()
+            .map(|name| repository.rev_parse(name).ok())
+            .collect();
+        verify_objects(
+            &repository,
+            &args.objects,
+            ids,
+            object_type,
+            args.verbose,
+            args.raw,
+        )
+    }
+
+    fn commit_tree(&self, tree: &str, parent_names: &[String], messages: &[String]) -> Result<()> {
+        let repository = self.repository()?;
+        let tree = repository.peel(&repository.rev_parse(tree)?, GitObjectType::Tree)?;
+        let mut parents: Vec<String> = vec![];
+        for name in parent_names {
+            let parent = repository.peel(&repository.rev_parse(name)?, GitObjectType::Commit)?;
+            if parents.contains(&parent.id) {
+                eprintln!("error: duplicate parent {} ignored", parent.id);
+                continue;
+            }
+            parents.push(parent.id);
+        }
+        let message = match messages.is_empty() {
+            true => {
+                let mut message = String::new();
+                io::stdin().lock().read_to_string(&mut message)?;
+                message
+            }
+            false => messages.join("\n\n"),
+        };
+
+        let config = Config::for_repository(repository.common_dir());
+        let author = signature(&config, "author")?;
+        let committer = signature(&config, "committer")?;
+        let parents: Vec<&str> = parents.iter().map(String::as_str).collect();
+        let hash = repository.create_commit(&tree.id, &parents, &author, &committer, &message)?;
+        println!("{}", hash);
+        Ok(())
+    }
+
+    fn rev_list(&self, count: bool, revisions: &[String]) -> Result<()> {
+        let repository = self.repository()?;
+        let (include, exclude) = repository.parse_revisions(revisions)?;
+        let commits = repository.rev_list(&include, &exclude)?;
+
+        if count {
+            println!("{}", commits.len());
+            return Ok(());
+        }
+        let mut out = io::stdout().lock();
+        for id in commits {
+            writeln!(out, "{}", id)?;
+        }
+        Ok(())
+    }
+
+    fn log(&self, args: &LogArgs) -> Result<()> {
+        let repository = self.repository()?;
+        let revisions = match args.revisions.is_empty() {
+            true => vec!["HEAD".to_string()],
+            false => args.revisions.clone(),
+        };
+        let (include, exclude) = repository.parse_revisions(&revisions)?;
+        let cwd = cwd_prefix(&repository)?;
+        let pathspecs = Pathspecs::parse(cwd.as_deref(), &args.paths)?;
+        if args.follow && pathspecs.len() != 1 {
+            return Err(Error::FollowPathspec);
+        }
+
+        // with paths, the commits that don't change them are left out and the graph joins
+        // each commit to the nearest ancestors that are shown; --follow walks every commit
+        let history = match pathspecs.is_empty() || args.follow {
+            true => None,
+            false => Some(repository.rev_list_paths(&include, &exclude, &pathspecs)?),
+        };
+        let (mut commits, parents) = match &history {
+            Some(history) => (history.commits.clone(), history.parents.clone()),
+            None => {
+                let commits = repository.rev_list(&include, &exclude)?;
+                let mut parents = HashMap::new();
+                for id in &commits {
+                    parents.insert(id.clone(), repository.commit_parents(id)?);
+                }
+                (commits, parents)
+            }
+        };
+        // the graph needs every line of history in one go
+        if args.graph {
+            commits = repository::topo_order(commits, &parents);
+        }
+        if let Some(history) = &history {
+            commits.retain(|id| history.shown.contains(id));
+        }
+        let shown: HashSet<String> = commits.iter().cloned().collect();
+        let shown_parents = |id: &str| match &history {
+            Some(history) => history.rewritten_parents(id),
+            None => parents[id].clone(),
+        };
+        let regex = |pattern: &str| Regex::new(pattern).map_err(Error::InvalidRegex);
+        let pickaxe = match (&args.pickaxe, &args.pickaxe_grep) {
+            (Some(string), _) if args.pickaxe_regex => Some(Pickaxe::CountMatches(regex(string)?)),
+            (Some(string), _) => Some(Pickaxe::Count(string.clone().into_bytes())),
+            (None, Some(pattern)) => Some(Pickaxe::Lines(regex(pattern)?)),
+            (None, None) => None,
+        };
+        // which commits have changes to show is only known from their diffs, and the path
+        // followed changes along the way, so it's worked out in the order they're shown
+        let changing = match args.follow || pickaxe.is_some() {
+            true => {
+                // the parents the graph shows are the ones a commit is compared with
+                let mut log_parents = HashMap::new();
+                for id in &commits {
+                    let parents = match args.graph {
+                        true => shown_parents(id),
+                        false => repository.commit_parents(id)?,
+                    };
+                    log_parents.insert(id.clone(), parents);
+                }
+                Some(repository.changing(
+                    &commits,
+                    &log_parents,
+                    &pathspecs,
+                    args.follow,
+                    pickaxe.as_ref(),
+                )?)
+            }
+            false => None,
+        };
+
+        // --oneline is the oneline preset with abbreviated ids, and the last of the three wins
+        let format = match (&args.format, &args.pretty) {
+            (Some(format), _) => Format::parse(format).map(|format| match format {
+                Format::User { template, .. } => Format::User {
+                    template,
+                    terminator: true,
+                },
+                format => format,
+            })?,
+            (None, Some(pretty)) => Format::parse(pretty)?,
+            (None, None) if args.oneline => Format::Oneline,
+            (None, None) => Format::Medium,
+        };
+        let (terminator, empty) = (format.uses_terminator(), format.is_empty());
+        let mut pretty = Pretty::new(format);
+        pretty.abbrev_commit = args.abbrev_commit || args.oneline;
+        pretty.decorate = args.decorate;
+        pretty.decorations = pretty::decorations(&repository);
+        let config = Config::for_repository(repository.common_dir());
+        pretty.color = color::enabled(&config, "color.diff", color_option(&args.color)?);
+
+        let mut graph = args.graph.then(Graph::new);
+        let mut out = self.pager(&config);
+        // how many commits have been written, and whether the last one ended without a newline
+        let (mut written, mut missing_newline) = (0, false);
+        let limit = args.max_count.unwrap_or(usize::MAX);
+        for id in &commits {
+            if written == limit {
+                break;
+            }
+            let mut commit = repository.find_commit(id)?;
+            if let Some(graph) = &mut graph {
+                let parents = shown_parents(id);
+                graph.update(
+                    id,
+                    parents
+                        .iter()
+                        .filter(|parent| shown.contains(*parent))
+                        .cloned()
+                        .collect(),
+                );
+                commit.parents = parents;
+            }
+            // commits without changes to show still take their place in the graph
+            if changing
+                .as_ref()
+                .is_some_and(|changing| !changing.contains(id))
+            {
+                continue;
+            }
+
+            // a newline between commits carries on the graph, unless it just ends a line
+            if written > 0 && !terminator {
+                if let Some(graph) = graph.as_mut().filter(|_| !missing_newline) {
+                    write!(out, "{}", graph.padding_line())?;
+                }
+                writeln!(out)?;
+            }
+            let text = match args.show_signature {
+                true => pretty.format_signed(&commit, &signature_report(&repository, &config, id)?),
+                false => pretty.format(&commit),
+            };
+            (written, missing_newline) = (written + 1, !text.ends_with('\n'));
+            match &mut graph {
+                Some(graph) => write_with_graph(&mut out, graph, &text)?,
+                None => out.write_all(text.as_bytes())?,
+            }
+            if terminator && !empty {
+                if let Some(graph) = graph.as_mut().filter(|_| !missing_newline) {
+                    write!(out, "{}", graph.padding_line())?;
+                }
+                writeln!(out)?;
+            }
+        }
+        Ok(())
+    }
+
+    // without a revision, the file in the work tree is blamed, with the lines HEAD doesn't have
+    // on a commit that isn't there yet
+    fn blame(&self, args: &BlameArgs) -> Result<()> {
+        let repository = self.repository()?;
+        let (revision, file) = match (&args.args[..], &args.file[..]) {
+            ([], [file]) | ([file], []) => (None, file),
+            ([revision], [file]) | ([revision, file], []) => (Some(revision), file),
+            _ => return Err(usage_error("blame", "expected [<rev>] [--] <file>")),
+        };
+        let cwd = cwd_prefix(&repository)?;
+        let path = pathspec::resolve(cwd.as_deref(), file)?;
+        let (commit, content) = match revision {
+            Some(revision) => {
+                let id = repository.rev_parse(revision)?;
+                let commit =
+                    repository.find_commit(&repository.peel(&id, GitObjectType::Commit)?.id)?;
+                let file = repository
+                    .find_file(&commit.tree, &path)?
+                    .ok_or_else(|| Error::NoSuchPath(path.clone(), revision.clone()))?;
+                let content = repository.entry_content(&file)?;
+                (commit, content)
+            }
+            None => {
+                let no_such_path = || Error::NoSuchPathInHead(path.clone());
+                let head = repository.head().ok_or_else(no_such_path)?;
+                let tree = repository.find_commit(&head)?.tree;
+                // a file only added to the index is all uncommitted
+                if repository.find_file(&tree, &path)?.is_none()
+                    && repository.index()?.find(&path, 0).is_none()
+                {
+                    return Err(no_such_path());
+                }
+                let work_tree = repository.work_tree().ok_or(Error::NoWorkTree)?;
+                let file = paths::to_native(work_tree, &path);
+                let content = match fs::symlink_metadata(&file)?.is_symlink() {
+                    true => symlink_target(&file)?,
+                    false => self
+                        .converter(&repository)
+                        .convert_to_git(&path, fs::read(&file)?)?,
+                };
+                (blame::work_tree_commit(&head, &path), content)
+            }
+        };
+
+        // a score that isn't a number, or is 0, is the default
+        let score = |arg: &str, default: usize| match arg.parse() {
+            Ok(0) | Err(_) => default,
+            Ok(score) => score,
+        };
+        let copies = args.copies.len();
+        let options = blame::Options {
+            ignore_whitespace: args.ignore_whitespace,
+            move_score: match &args.moves {
+                Some(arg) => Some(score(arg, DEFAULT_MOVE_SCORE)),
+                None => (copies > 0).then_some(DEFAULT_MOVE_SCORE),
+            },
+            copies,
+            copy_score: args
+                .copies
+                .iter()
+                .rfind(|arg| !arg.is_empty())
+                .map_or(DEFAULT_COPY_SCORE, |arg| score(arg, DEFAULT_COPY_SCORE)),
+        };
+        let ranges =
+            blame::line_ranges(&args.ranges, &xdiff::lines(&content), &path).map_err(|error| {
+                match error {
+                    Error::InvalidLineRange(_) => usage_error("blame", &error.to_string()),
+                    error => error,
+                }
+            })?;
+        let blame = blame::blame(&repository, commit, &path, content, &ranges, options)?;
+        let config = Config::for_repository(repository.common_dir());
+        let mut out = self.pager(&config);
+        if args.porcelain || args.line_porcelain {
+            return write_blame_porcelain(&mut out, &blame, args.line_porcelain);
+        }
+
+        // the columns are as wide as the widest of what's shown, and the file names are only
+        // shown when some lines come from another file
+        let origins = || {
+            blame
+                .entries
+                .iter()
+                .map(|entry| &blame.origins[entry.origin])
+        };
+        let show_name = origins().any(|origin| origin.path != path);
+        let name_width = origins().map(|origin| origin.path.len()).max().unwrap_or(0);
+        let author_width = origins()
+            .map(|origin| blame.commit(origin).author.name.chars().count())
+            .max()
+            .unwrap_or(0);
+        let line_width = blame
+            .entries
+            .last()
+            .map_or(1, |entry| (entry.lno + entry.len).to_string().len());
+
+        for entry in &blame.entries {
+            let origin = &blame.origins[entry.origin];
+            let commit = blame.commit(origin);
+            // a commit lines can't be blamed past is marked, keeping the width
+            let id = match origin.boundary {
+                true => format!("^{}", &commit.id[..7]),
+                false => commit.id[..8].to_string(),
+            };
+            let date = commit.author.format_date("%Y-%m-%d %H:%M:%S %z");
+            for lno in entry.lno..entry.lno + entry.len {
+                write!(out, "{}", id)?;
+                if show_name {
+                    write!(out, " {:<width$}", origin.path, width = name_width)?;
+                }
+                write!(
+                    out,
+                    " ({:<author_width$} {} {:>line_width$}) ",
+                    commit.author.name,
+                    date,
+                    lno + 1
+                )?;
+                let line = &blame.lines[lno];
+                out.write_all(line)?;
+                if !line.ends_with(b"\n") {
+                    writeln!(out)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn name_rev(&self, args: &NameRevArgs) -> Result<()> {
+        let repository = self.repository()?;
+
+        let mut revisions = vec![];
+        let mut oldest = i64::MAX;
+        for revision in &args.revisions {
+            let Ok(id) = repository.rev_parse(revision) else {
+                eprintln!("Could not get sha1 for {}. Skipping.", revision);
+                continue;
+            };
+            if !repository.has_object(&id) {
+                eprintln!("Could not get object for {}. Skipping.", revision);
+                continue;
+            }
+            if let Ok(commit) = repository.find_commit(&id) {
+                oldest = oldest.min(commit.committer.time);
+            }
+            revisions.push((revision, id));
+        }
+        let oldest = (!args.annotate_stdin).then_some(oldest);
+        let filter = RefFilter {
+            tags_only: args.tags,
+            name_only: args.name_only,
+            patterns: args.patterns.clone(),
+            exclude: args.exclude.clone(),
+        };
+        let names = NameRev::new(&repository, &filter, oldest)?;
+
+        let mut out = io::stdout().lock();
+        if args.annotate_stdin {
+            let mut input = io::stdin().lock();
+            let mut line = String::new();
+            while input.read_line(&mut line)? > 0 {
+                out.write_all(names.annotate(&line, args.name_only).as_bytes())?;
+                out.flush()?;
+                line.clear();
+            }
+            return Ok(());
+        }
+        for (revision, id) in revisions {
+            if !args.name_only {
+                write!(out, "{} ", revision)?;
+            }
+            match names.name(&id) {
+                Some(name) => writeln!(out, "{}", name)?,
+                None if !args.no_undefined => writeln!(out, "undefined")?,
+                None if args.always => writeln!(out, "{}", pretty::abbreviate(&id))?,
+                None => {
+                    out.flush()?;
+                    return Err(Error::CannotDescribe(id));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn cherry(
+        &self,
+        verbose: bool,
+        abbrev: Option<usize>,
+        upstream: Option<&str>,
+        head: Option<&str>,
+        limit: Option<&str>,
+    ) -> Result<()> {
+        let repository = self.repository()?;
+        let upstream = match upstream {
+            Some(upstream) => upstream.to_string(),
+            None => self.tracked_branch(&repository).ok_or_else(|| {
+                usage_error(
+                    "cherry",
+                    "Could not find a tracked remote branch, please specify <upstream> manually.",
+                )
+            })?,
+        };
+        let commit = |name: &str| {
+            repository
+                .rev_parse(name)
+                .and_then(|id| repository.peel(&id, GitObjectType::Commit))
+                .map(|commit| commit.id)
+                .map_err(|_| Error::UnknownCommit(name.to_string()))
+        };
+        let head = commit(head.unwrap_or("HEAD"))?;
+        let upstream = commit(&upstream)?;
+        if head == upstream {
+            return Ok(());
+        }
+        let non_merges = |include: &str, exclude: Vec<String>| -> Result<Vec<String>> {
+            let mut commits = vec![];
+            for id in repository.rev_list(&[include.to_string()], &exclude)? {
+                if repository.commit_parents(&id)?.len() <= 1 {
+                    commits.push(id);
+                }
+            }
+            Ok(commits)
+        };
+
+        let mut upstream_ids = HashSet::new();
+        for id in non_merges(&upstream, vec![head.clone()])? {
+            upstream_ids.extend(self.patch_id(&repository, &id)?);
+        }
+        let mut exclude = vec![upstream];
+        if let Some(limit) = limit {
+            exclude.push(commit(limit)?);
+        }
+        let mut out = io::stdout().lock();
+        for id in non_merges(&head, exclude)?.iter().rev() {
+            let sign = match self.patch_id(&repository, id)? {
+                Some(patch_id) if upstream_ids.contains(&patch_id) => '-',
+                _ => '+',
+            };
+            let shown = &id[..abbrev.map_or(id.len(), |abbrev| abbrev.clamp(4, id.len()))];
+            match verbose {
+                true => {
+                    let message = repository.find_commit(id)?.message;
+                    writeln!(out, "{} {} {}", sign, shown, pretty::subject(&message))?
+                }
+                false => writeln!(out, "{} {}", sign, shown)?,
+            }
+        }
+        Ok(())
+    }
+
+    // the branch the current one tracks: branch.<name>.merge as the remote branch of
+    // branch.<name>.remote, or as it is when that is "."
+    fn tracked_branch(&self, repository: &Repository) -> Option<String> {
+        let branch = repository.head_target()?;
+        let branch = branch.strip_prefix("refs/heads/")?;
+        let config = Config::for_repository(repository.common_dir());
+        let remote = config.get(&format!("branch.{}.remote", branch))?;
+        let merge = config.get(&format!("branch.{}.merge", branch))?;
+        match remote {
+            "." => Some(merge.to_string()),
+            remote => Some(format!(
+                "refs/remotes/{}/{}",
+                remote,
+                merge.strip_prefix("refs/heads/")?
+            )),
+        }
+    }
+
+    // git's patch-id of what the commit `id` changes from its parent, or None for a merge
+    fn patch_id(&self, repository: &Repository, id: &str) -> Result<Option<String>> {
+        let old = match &repository.commit_parents(id)?[..] {
+            [] => vec![],
+            [parent] => repository.tree_files(parent, "")?,
+            _ => return Ok(None),
+        };
+        let changes = diff::diff_entries(&old, &repository.tree_files(id, "")?);
+        let mut attributes = self.attributes(repository);
+        let mut hasher = sha1::Sha1::new();
+        for change in &changes {
+            let side = |entry: &IndexEntry| -> Result<Side> {
+                Ok(Side {
+                    mode: entry.mode,
+                    id: entry.id.clone(),
+                    content: repository.entry_content(entry)?,
+                })
+            };
+            let old = change.old.as_ref().map(side).transpose()?;
+            let new = change.new.as_ref().map(side).transpose()?;
+            let (old, new) = (old.as_ref(), new.as_ref());
+            let binary = diff::is_binary(
+                &attributes.check(&change.path, "diff"),
+                patch::content(old),
+                patch::content(new),
+            );
+            let path = &change.path;
+            match (old, new) {
+                // as in a patch, a file that became another kind of file is deleted and added
+                (Some(old), Some(new)) if (old.mode ^ new.mode) & 0o170000 != 0 => {
+                    patch::add_to_patch_id(&mut hasher, path, Some(old), None, binary);
+                    patch::add_to_patch_id(&mut hasher, path, None, Some(new), binary);
+                }
+                _ => patch::add_to_patch_id(&mut hasher, path, old, new, binary),
+            }
+        }
+        Ok(Some(hex::encode(hasher.finalize())))
+    }
+
+    fn merge_base(
+        &self,
+        all: bool,
+        octopus: bool,
+        is_ancestor: bool,
+        names: &[String],
+    ) -> Result<()> {
+        let repository = self.repository()?;
+        let mut commits = vec![];
+        for name in names {
+            let id = repository.rev_parse(name)?;
+            commits.push(repository.peel(&id, GitObjectType::Commit)?.id);
+        }
+
+        if is_ancestor {
+            let [ancestor, descendant] = &commits[..] else {
+                return Err(usage_error("merge-base", "--is-ancestor takes two commits"));
+            };
+            return match repository.is_ancestor(ancestor, descendant) {
+                true => Ok(()),
+                false => Err(Error::Exit(1)),
+            };
+        }
+        let bases = match &commits[..] {
+            _ if octopus => repository.octopus_merge_bases(&commits)?,
+            [one, others @ ..] if !others.is_empty() => repository.merge_bases(one, others)?,
+            _ => return Err(usage_error("merge-base", "expected at least two commits")),
+        };
+
+        // no common history at all is a failure, without a message
+        if bases.is_empty() {
+            return Err(Error::Exit(1));
+        }
+        for base in bases.iter().take(if all { bases.len() } else { 1 }) {
+            println!("{}", base);
+        }
+        Ok(())
+    }
+
+    fn symbolic_ref(
+        &self,
+        quiet: bool,
+        short: bool,
+        name: &str,
+        target: Option<&str>,
+    ) -> Result<()> {
+        let repository = self.repository()?;
+        if let Some(target) = target {
+            if name == "HEAD" && !target.starts_with("refs/") {
+                return Err(Error::OutsideRefs(name.to_string()));
+            }
+            let format = refname::Format {
+                allow_onelevel: true,
+                ..Default::default()
+            };
+            if !refname::is_valid(target, format) {
+                return Err(Error::InvalidSymbolicRefTarget(
+                    name.to_string(),
+                    target.to_string(),
+                ));
+            }
+            return repository.set_symbolic_reference(name, target);
+        }
+
+        // a detached HEAD holds an object id instead
+        let Some(target) = repository.symbolic_reference(name) else {
+            return match quiet {
+                true => Err(Error::Exit(1)),
+                false => Err(Error::NotASymbolicRef(name.to_string())),
+            };
+        };
+        let shown = ["refs/heads/", "refs/tags/", "refs/remotes/", "refs/"]
+            .iter()
+            .find_map(|prefix| target.strip_prefix(prefix))
+            .filter(|_| short)
+            .unwrap_or(&target);
+        println!("{}", shown);
+        Ok(())
+    }
+
+    // exits with 1 when `name` can't be the name of a ref, printing it with `normalize`; a
+    // branch name is printed when it can be one, and fails when it can't
+    fn check_ref_format(
+        &self,
+        name: &str,
+        normalize: bool,
+        format: refname::Format,
+        branch: bool,
+    ) -> Result<()> {
+        if branch {
+            if !refname::is_valid_branch(name) {
+                return Err(Error::InvalidBranchName(name.to_string()));
+            }
+            println!("{}", name);
+            return Ok(());
+        }
+        let name = match normalize {
+            true => refname::collapse_slashes(name),
+            false => name.to_string(),
+        };
+        if !refname::is_valid(&name, format) {
+            return Err(Error::Exit(1));
+        }
+        if normalize {
+            println!("{}", name);
+        }
+        Ok(())
+    }
+
+    // prints a variable as the commands that use it work it out, or with -l the config and
+    // every variable
+    fn var(&self, list: bool, variable: Option<&str>) -> Result<()> {
+        let config = self.config_anywhere();
+        let value = |name: &str| -> Result<Option<String>> {
+            Ok(Some(match name {
+                "GIT_AUTHOR_IDENT" => signature(&config, "author")?.to_string(),
+                "GIT_COMMITTER_IDENT" => signature(&config, "committer")?.to_string(),
+                "GIT_EDITOR" => editor::editor(&config)?,
+                "GIT_PAGER" => pager::pager(&config).unwrap_or_else(|| "cat".to_string()),
+                _ => return Ok(None),
+            }))
+        };
+        let Some(variable) = variable.filter(|_| !list) else {
+            let mut out = io::stdout().lock();
+            for (name, value) in config.entries() {
+                match value {
+                    Some(value) => writeln!(out, "{}={}", name, value)?,
+                    None => writeln!(out, "{}", name)?,
+                }
+            }
+            // a variable that can't be worked out is left out
+            for name in [
+                "GIT_COMMITTER_IDENT",
+                "GIT_AUTHOR_IDENT",
+                "GIT_EDITOR",
+                "GIT_PAGER",
+            ] {
+                if let Ok(Some(value)) = value(name) {
+                    writeln!(out, "{}={}", name, value)?;
+                }
+            }
+            return Ok(());
+        };
+        match value(variable)? {
+            Some(value) => println!("{}", value),
+            None => {
+                return Err(usage_error(
+                    "var",
+                    &format!("unknown variable '{}'", variable),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    fn update_ref(
+        &self,
+        no_deref: bool,
+        name: &str,
+        new_value: &str,
+        old_value: Option<&str>,
+    ) -> Result<()> {
+        let repository = self.repository()?;
+        let new_id = repository.rev_parse(new_value)?;
+        check_ref_value(&repository, name, old_value)?;
+
+        match no_deref {
+            true => repository.set_reference(name, &new_id),
+            false => repository.update_reference(name, &new_id),
+        }
+    }
+
+    // deletes the ref `name`, or the one it stands for when it is symbolic, with its reflog
+    fn delete_ref(&self, no_deref: bool, name: &str, old_value: Option<&str>) -> Result<()> {
+        let repository = self.repository()?;
+        check_ref_value(&repository, name, old_value)?;
+        let name = match no_deref {
+            true => name.to_string(),
+            false => repository.dereference(name),
+        };
+        repository.delete_reference(&name)?;
+        repository.delete_reflog(&name)
+    }
+
+    fn clone(&self, args: &CloneArgs) -> Result<()> {
+        let url = args
+            .repository
+            .strip_suffix('/')
+            .unwrap_or(&args.repository);
+        let bare = args.bare || args.mirror;
+        let directory = match &args.directory {
+            Some(directory) => directory.clone(),
+            None if bare => format!("{}.git", clone_directory(url)),
+            None => clone_directory(url),
+        };
+        let current_dir = env::current_dir()?;
+        let path = current_dir.join(&directory);
+        let existed = path.exists();
+        if existed && fs::read_dir(&path)?.next().is_some() {
+            return Err(Error::DestinationExists(directory.into()));
+        }
+        let mut repo = match bare {
+            true => Repo::new(url, &path, &path).bare(),
+            false => Repo::new(url, &path.join(".git"), &path),
+        }
+        .with_progress(progress_for(&args.progress));
+        if args.mirror {
+            repo = repo.mirror();
+        }
+        if let Some(branch) = &args.branch {
+            repo = repo.branch(branch);
+        }
+        // a shallow clone is of the one branch too
+        if let Some(deepen) = deepen_for(&args.shallow)? {
+            repo = repo.single_branch().deepen(deepen);
+        }
+        if args.single_branch {
+            repo = repo.single_branch();
+        }
+        let result = repo.clone();
+        // don't leave a half cloned repository behind
+        if result.is_err() {
+            if existed {
+                for entry in fs::read_dir(&path)?.flatten() {
+                    let _ = match entry.file_type() {
+                        Ok(file_type) if file_type.is_dir() => fs::remove_dir_all(entry.path()),
+                        _ => fs::remove_file(entry.path()),
+                    };
+                }
+            } else {
+                let _ = fs::remove_dir_all(&path);
+            }
+        }
+
+        result
+    }
+
+    fn fetch(
+        &self,
+        url: &str,
+        progress: &ProgressArgs,
+        shallow: &ShallowArgs,
+        deepen: Option<u32>,
+        unshallow: bool,
+    ) -> Result<()> {
+        let repository = self.repository()?;
+        // fetched objects and remote-tracking refs are shared by all worktrees
+        let git_dir = repository.common_dir();
+        let mut repo = Repo::new(url, git_dir, repository.work_tree().unwrap_or(git_dir))
+            .with_progress(progress_for(progress));
+        if unshallow && repository::read_shallow(git_dir).is_empty() {
+            return Err(Error::UnshallowComplete);
+        }
+        let deepen = match (deepen, unshallow) {
+            (Some(depth), _) => Some(Deepen {
+                depth: Some(depth),
+                relative: true,
+                ..Deepen::default()
+            }),
+            (None, true) => Some(Deepen {
+                depth: Some(git_client::INFINITE_DEPTH),
+                ..Deepen::default()
+            }),
+            (None, false) => deepen_for(shallow)?,
+        };
+        if let Some(deepen) = deepen {
+            repo = repo.deepen(deepen);
+        }
+        repo.fetch()
+    }
+}
+
+// a commit's text with the graph down the left of every line, and any of the graph that is
+// left for the commit after it
+fn write_with_graph(out: &mut impl Write, graph: &mut Graph, text: &str) -> Result<()> {
+    loop {
+        let (line, shown_commit) = graph.next_line();
+        write!(out, "{}", line)?;
+        if shown_commit {
+            break;
+        }
+        writeln!(out)?;
+    }
+    let mut lines = text.split_inclusive('\n').peekable();
+    while let Some(line) = lines.next() {
+        write!(out, "{}", line)?;
+        if line.ends_with('\n') && lines.peek().is_some() {
+            write!(out, "{}", graph.next_line().0)?;
+        }
+    }
+
+    if !graph.is_commit_finished() {
+        if !text.ends_with('\n') {
+            writeln!(out)?;
+        }
+        let mut remainder = vec![];
+        while !graph.is_commit_finished() {
+            remainder.push(graph.next_line().0);
+        }
+        write!(out, "{}", remainder.join("\n"))?;
+        if text.ends_with('\n') {
+            writeln!(out)?;
+        }
+    }
+    Ok(())
+}
+
+// the lines of `blame` in the form of "blame --porcelain", with the details of each commit
+// only the first time it's seen unless `every_line`
+fn write_blame_porcelain(out: &mut impl Write, blame: &Blame, every_line: bool) -> Result<()> {
+    // the name of the file is given with the details, and again for each run of lines of a
+    // commit that lines of more than one file are blamed on
+    let mut paths: HashMap<&str, HashSet<&str>> = HashMap::new();
+    for entry in &blame.entries {
+        let origin = &blame.origins[entry.origin];
+        paths
+            .entry(&origin.commit)
+            .or_default()
+            .insert(&origin.path);
+    }
+
+    let mut shown = HashSet::new();
+    for entry in &blame.entries {
+        let origin = &blame.origins[entry.origin];
+        let commit = blame.commit(origin);
+        for lno in entry.lno..entry.lno + entry.len {
+            let s_lno = entry.s_lno + lno - entry.lno;
+            write!(out, "{} {} {}", commit.id, s_lno + 1, lno + 1)?;
+            match lno == entry.lno {
+                true => writeln!(out, " {}", entry.len)?,
+                false => writeln!(out)?,
+            }
+            if lno == entry.lno || every_line {
+                write_blame_details(out, blame, origin, &mut shown, every_line, &paths)?;
+            }
+            out.write_all(b"\t")?;
+            let line = &blame.lines[lno];
+            out.write_all(line)?;
+            if !line.ends_with(b"\n") {
+                writeln!(out)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// the details of the commit `origin` is in, unless they've been `shown` already and aren't to
+// be repeated, and the file the lines come from
+fn write_blame_details<'a>(
+    out: &mut impl Write,
+    blame: &'a Blame,
+    origin: &Origin,
+    shown: &mut HashSet<&'a str>,
+    repeat: bool,
+    paths: &HashMap<&str, HashSet<&str>>,
+) -> Result<()> {
+    let commit = blame.commit(origin);
+    let new = shown.insert(&commit.id) || repeat;
+    if new {
+        for (role, signature) in [("author", &commit.author), ("committer", &commit.committer)] {
+            writeln!(out, "{} {}", role, signature.name)?;
+            writeln!(out, "{}-mail <{}>", role, signature.email)?;
+            writeln!(out, "{}-time {}", role, signature.time)?;
+            writeln!(out, "{}-tz {}", role, signature.format_date("%z"))?;
+        }
+        // the first line of the message that isn't blank
+        match commit.message.lines().find(|line| !line.trim().is_empty()) {
+            Some(summary) => writeln!(out, "summary {}", summary)?,
+            None => writeln!(out, "summary ({})", commit.id)?,
+        }
+        if origin.boundary {
+            writeln!(out, "boundary")?;
+        }
+    }
+    if new || paths[&origin.commit[..]].len() > 1 {
+        if let Some(previous) = origin.previous {
+            let previous = &blame.origins[previous];
+            writeln!(out, "previous {} {}", previous.commit, previous.path)?;
+        }
+        writeln!(out, "filename {}", origin.path)?;
+    }
+    Ok(())
+}
+
+// the path of a submodule as it's shown: after the path of the submodule it's in when `at`
+// has that, or else relative to the current directory in the top repository
+fn submodule_display_path(path: &str, at: (Option<&str>, Option<&str>)) -> String {
+    match at {
+        (_, Some(prefix)) => format!("{}{}", prefix, path),
+        (Some(cwd), None) => relative_path(path, cwd),
+        (None, None) => path.to_string(),
+    }
+}
+
+// a path option given on the command line, or else from the environment, made absolute so
+// that it doesn't depend on the current directory
+fn path_option(option: Option<PathBuf>, variable: &str) -> Result<Option<PathBuf>> {
+    let path = option.or_else(|| {
+        env::var_os(variable)
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from)
+    });
+    match path {
+        Some(path) => Ok(Some(env::current_dir()?.join(path))),
+        None => Ok(None),
+    }
+}
+
+// copies the files of an init template that the repository doesn't have yet
+fn copy_template(from: &Path, to: &Path) -> Result<()> {
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            fs::create_dir_all(&target)?;
+            copy_template(&entry.path(), &target)?;
+        } else if !target.exists() {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+
+    Ok(())
+}
+
+// the directory a clone goes into by default: "https://host/path/repo.git" clones into "repo"
+fn clone_directory(url: &str) -> String {
+    let url = url.trim_end_matches('/');
+    let url = url.strip_suffix("/.git").unwrap_or(url);
+    let name = url.rsplit('/').next().unwrap_or(url);
+    name.strip_suffix(".git").unwrap_or(name).to_string()
+}
+
+struct TreeListing<'a> {
+    repository: &'a Repository,
+    args: &'a LsTreeArgs,
+    // paths from the top of the tree; one ending in "/" only matches what is under it
+    pathspecs: Vec<String>,
+    // names are shown relative to this directory
+    cwd: Option<String>,
+}
+
+impl TreeListing<'_> {
+    // prints `entries` as ls-tree does, with their paths under `prefix`
+    fn list(&self, entries: &[TreeEntry], prefix: &str, out: &mut impl Write) -> Result<()> {
+        for entry in entries {
+            let path = format!("{}{}", prefix, entry.name);
+            let is_tree = entry.object_type() == GitObjectType::Tree;
+            // trees on the way to a pathspec are entered without being listed themselves
+            let leading = is_tree
+                && self.pathspecs.iter().any(|spec| {
+                    spec.len() > path.len()
+                        && spec.starts_with(&path)
+                        && spec.as_bytes()[path.len()] == b'/'
+                });
+            if !leading && !diff::matches_pathspec(&path, &self.pathspecs) {
+                continue;
+            }
+
+            let recurse = is_tree && (self.args.recursive || leading);
+            // -r -d lists every tree it passes through
+            let show = match is_tree {
+                true => !recurse || (self.args.recursive && self.args.trees_only),
+                false => !self.args.trees_only,
+            };
+            if show {
+                self.show(entry, &path, out)?;
+            }
+            if recurse {
+                let subtree = self.repository.peel(&entry.id, GitObjectType::Tree)?;
+                let entries = objects::parse_tree(&subtree.data)?;
+                self.list(&entries, &format!("{}/", path), out)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn show(&self, entry: &TreeEntry, path: &str, out: &mut impl Write) -> Result<()> {
+        let entry = TreeEntry {
+            name: match &self.cwd {
+                Some(cwd) => relative_path(path, cwd),
+                None => path.to_string(),
+            },
+            ..entry.clone()
+        };
+        if self.args.name_only {
+            writeln!(out, "{}", entry.name)?;
+        } else if self.args.long {
+            let size = match entry.object_type() {
+                GitObjectType::Blob => self
+                    .repository
+                    .find_object(&entry.id)?
+                    .data
+                    .len()
+                    .to_string(),
+                _ => "-".to_string(),
+            };
+            writeln!(
+                out,
+                "{:0>6} {} {} {:>7}\t{}",
+                entry.mode,
+                entry.object_type(),
+                entry.id,
+                size,
+                entry.name
+            )?;
+        } else {
+            writeln!(out, "{}", entry)?;
+        }
+
+        Ok(())
+    }
+}
+
+// the entries right in a tree, as the index would have them
+fn tree_entries(repository: &Repository, id: &str) -> Result<Vec<IndexEntry>> {
+    let mut entries = vec![];
+    for entry in repository.tree_entries(id)? {
+        let mode = u32::from_str_radix(&entry.mode, 8)
+            .map_err(|_| Error::CorruptObject(id.to_string()))?;
+        entries.push(IndexEntry::new(&entry.name, mode, &entry.id));
+    }
+    entries.sort_by(|a, b| a.path.as_bytes().cmp(b.path.as_bytes()));
+
+    Ok(entries)
+}
+
+// the merged entries of the index and the paths with conflicts
+fn split_unmerged(index: &Index) -> (Vec<IndexEntry>, Vec<String>) {
+    let mut unmerged: Vec<String> = vec![];
+    let mut merged = vec![];
+    for entry in index.entries() {
+        if entry.stage == 0 {
+            merged.push(entry.clone());
+        } else if unmerged.last() != Some(&entry.path) {
+            unmerged.push(entry.path.clone());
+        }
+    }
+
+    (merged, unmerged)
+}
+
+fn diff_format(args: &DiffFormatArgs) -> Result<DiffFormat> {
+    // --color-words is --word-diff=color, with the regex for words if given
+    let (mode, regex) = match &args.color_words {
+        Some(regex) => (
+            Some("color"),
+            regex.as_ref().or(args.word_diff_regex.as_ref()),
+        ),
+        // a regex for words alone asks for the plain word diff
+        None => (
+            args.word_diff
+                .as_deref()
+                .or(args.word_diff_regex.as_ref().map(|_| "plain")),
+            args.word_diff_regex.as_ref(),
+        ),
+    };
+    let mode = match mode {
+        None | Some("none") => None,
+        Some("plain") => Some(WordDiffMode::Plain),
+        Some("color") => Some(WordDiffMode::Color),
+        Some("porcelain") => Some(WordDiffMode::Porcelain),
+        Some(mode) => {
+            return Err(Error::Usage(format!(
+                "error: bad --word-diff argument: {}",
+                mode
+            )))
+        }
+    };
+    let words = match mode {
+        Some(mode) => Some(WordDiff {
+            mode,
+            regex: regex
+                .map(|regex| Regex::new(regex).map_err(|_| Error::InvalidWordRegex(regex.clone())))
+                .transpose()?,
+        }),
+        None => None,
+    };
+
+    let algorithm = match args.diff_algorithm.as_deref() {
+        None | Some("myers" | "default") => Algorithm::Myers,
+        Some("minimal") => Algorithm::Minimal,
+        Some("patience") => Algorithm::Patience,
+        Some("histogram") => Algorithm::Histogram,
+        Some(_) => {
+            return Err(Error::Usage(
+                "error: option diff-algorithm accepts \"myers\", \"minimal\", \"patience\" and \"histogram\""
+                    .to_string(),
+            ))
+        }
+    };
+
+    // the plumbing commands don't look at color.diff
+    let color = color_option(&args.color)?.is_some_and(When::enabled);
+
+    Ok(match args {
+        DiffFormatArgs {
+            name_only: true, ..
+        } => DiffFormat::NameOnly,
+        DiffFormatArgs {
+            name_status: true, ..
+        } => DiffFormat::NameStatus,
+        DiffFormatArgs { stat: true, .. } => DiffFormat::Stat { color },
+        DiffFormatArgs { numstat: true, .. } => DiffFormat::NumStat,
+        // --binary, like -p, asks for a patch
+        DiffFormatArgs { patch, binary, .. } if *patch || *binary => {
+            DiffFormat::Patch(PatchOptions {
+                words,
+                binary: *binary,
+                algorithm,
+                color,
+            })
+        }
+        _ => DiffFormat::Raw,
+    })
+}
+
+// what a tree would record as the mode of a file in the work tree
+fn work_tree_mode(file: &Path, metadata: &fs::Metadata) -> Result<u32> {
+    if metadata.is_symlink() {
+        return Ok(0o120000);
+    }
+
+    Ok(u32::from_str_radix(&file_mode(file)?, 8).unwrap_or(0o100644))
+}
+
+// the directory of the work tree the current directory is in, if it is below the top
+fn cwd_prefix(repository: &Repository) -> Result<Option<String>> {
+    let Some(work_tree) = repository.work_tree() else {
+        return Ok(None);
+    };
+    let work_tree = fs::canonicalize(work_tree)?;
+    let prefix = env::current_dir()?
+        .strip_prefix(work_tree)
+        .ok()
+        .map(paths::to_git);
+
+    Ok(prefix.filter(|prefix| !prefix.is_empty()))
+}
+
+// the words of a command line as git splits an alias: at whitespace outside quotes, with
+// backslash escaping the next character outside single quotes; None if a quote isn't closed
+fn split_command_line(line: &str) -> Option<Vec<String>> {
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (Some(q), c) if c == q => quote = None,
+            (Some('\''), c) => word.get_or_insert_with(String::new).push(c),
+            (_, '\\') => word.get_or_insert_with(String::new).push(chars.next()?),
+            (_, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() {
+        return None;
+    }
+    words.extend(word);
+    Some(words)
+}
+
+// `path` as seen from the directory `base`, both from the top of the tree
+fn relative_path(path: &str, base: &str) -> String {
+    let path: Vec<&str> = path.split('/').collect();
+    let base: Vec<&str> = base.split('/').collect();
+    let common = path.iter().zip(&base).take_while(|(a, b)| a == b).count();
+    let mut relative = "../".repeat(base.len() - common);
+    relative.push_str(&path[common..].join("/"));
+    match relative.is_empty() {
+        true => "./".to_string(),
+        false => relative,
+    }
+}
+
+// with `expected`, that the ref `name` points at it
+fn check_ref_value(repository: &Repository, name: &str, expected: Option<&str>) -> Result<()> {
+    let Some(expected) = expected else {
+        return Ok(());
+    };
+    let expected = repository.rev_parse(expected)?;
+    match repository.find_reference(name) {
+        Some(actual) if actual == expected => Ok(()),
+        Some(actual) => Err(Error::LockRef(
+            name.to_string(),
+            format!("is at {} but expected {}", actual, expected),
+        )),
+        None => Err(Error::LockRef(
+            name.to_string(),
+            format!("unable to resolve reference '{}'", name),
+        )),
+    }
+}
+
+// who the `role`, "author" or "committer", is: GIT_<ROLE>_NAME and GIT_<ROLE>_EMAIL, then
+// <role>.name and <role>.email, then user.name and user.email, then EMAIL for the email; with
+// none of them it is unknown. The time is GIT_<ROLE>_DATE, or now
+fn signature(config: &Config, role: &str) -> Result<Signature> {
+    let variable = |key: &str| format!("GIT_{}_{}", role.to_uppercase(), key);
+    let setting = |key: &str| {
+        env::var(variable(&key.to_uppercase()))
+            .ok()
+            .or_else(|| config.get(&format!("{}.{}", role, key)).map(str::to_string))
+            .or_else(|| config.get(&format!("user.{}", key)).map(str::to_string))
+    };
+    let (Some(name), Some(email)) = (
+        setting("name"),
+        setting("email").or_else(|| env::var("EMAIL").ok()),
+    ) else {
+        let mut role = role.to_string();
+        role[..1].make_ascii_uppercase();
+        return Err(Error::IdentityUnknown(role));
+    };
+    match env::var(variable("DATE")) {
+        Ok(date) => {
+            let (time, offset) = repository::parse_date(&date).ok_or(Error::InvalidDate(date))?;
+            Ok(Signature::new(&name, &email, time, offset))
+        }
+        Err(_) => Ok(Signature::now(&name, &email)),
+    }
+}
+
+// the id of `content` as an object of `object_type`, which is only stored when `write` is set
+fn store_object(
+    repository: &Repository,
+    object_type: GitObjectType,
+    content: &[u8],
+    write: bool,
+) -> Result<String> {
+    objects::check_object(object_type, content)?;
+    match write {
+        true => repository.write_object(object_type, content),
+        false => Ok(objects::encode_object(object_type, content).0),
+    }
+}
+
+// how new trailers are added as --`name` says, or else the setting `key`, which is warned about
+// and left out when git wouldn't know it
+fn trailer_rule<T>(
+    config: &Config,
+    key: &str,
+    name: &str,
+    option: Option<&str>,
+    parse: fn(&str) -> Option<T>,
+) -> Result<Option<T>> {
+    if let Some(value) = option {
+        return match parse(value) {
+            Some(rule) => Ok(Some(rule)),
+            None => Err(Error::Usage(format!(
+                "error: unknown value for --{}: {}",
+                name, value
+            ))),
+        };
+    }
+    let value = config.get(key);
+    let rule = value.and_then(parse);
+    if let (Some(value), None) = (value, &rule) {
+        eprintln!("warning: unknown value '{}' for key '{}'", value, key);
+    }
+    Ok(rule)
+}
+
+// a usage error for `subcommand` the way clap reports its own
+fn usage_error(subcommand: &str, message: &str) -> Error {
+    let mut cli = Cli::command();
+    cli.build();
+    let error = match cli.find_subcommand_mut(subcommand) {
+        Some(command) => command.error(ErrorKind::ArgumentConflict, message),
+        None => cli.error(ErrorKind::ArgumentConflict, message),
+    };
+
+    Error::Usage(error.render().to_string().trim_end().to_string())
+}
+
+// git subtree's errors, which it exits with 1 for
+fn subtree_error(message: &str) -> Error {
+    eprintln!("fatal: {}", message);
+    Error::Exit(1)
+}
+
+// git subtree only takes a ref that a branch could be named after
+fn subtree_check_ref(name: &str) -> Result<()> {
+    match refname::is_valid(&format!("refs/heads/{}", name), refname::Format::default()) {
+        true => Ok(()),
+        false => Err(subtree_error(&format!(
+            "'{}' does not look like a ref",
+            name
+        ))),
+    }
+}
+
+// how far back a clone or fetch goes by --depth, --shallow-since and --shallow-exclude, or
+// nothing when none were given
+fn deepen_for(args: &ShallowArgs) -> Result<Option<Deepen>> {
+    let since = match &args.shallow_since {
+        Some(date) => Some(
+            repository::parse_date(date)
+                .ok_or_else(|| Error::InvalidDate(date.clone()))?
+                .0,
+        ),
+        None => None,
+    };
+    if args.depth.is_none() && since.is_none() && args.shallow_exclude.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(Deepen {
+        depth: args.depth,
+        relative: false,
+        since,
+        not: args.shallow_exclude.clone(),
+    }))
+}
+
+// what --color or --no-color ask for, when either is given
+fn color_option(args: &ColorArgs) -> Result<Option<When>> {
+    match (&args.color, args.no_color) {
+        (_, true) => Ok(Some(When::Never)),
+        (Some(when), false) => When::parse(when).map(Some),
+        (None, false) => Ok(None),
+    }
+}
+
+// like git, progress is shown by default only when someone is watching
+fn progress_for(args: &ProgressArgs) -> Box<dyn Progress> {
+    if args.progress || (!args.quiet && io::stderr().is_terminal()) {
+        Box::new(TerminalProgress::new())
+    } else {
+        Box::new(NoProgress)
+    }
+}
+
+// a directory scanned by write-tree, waiting on the blob ids of its files
+#[derive(Default)]
+struct PendingTree {
+    entries: Vec<TreeEntry>,
+    // (mode, name, index of the file's blob id)
+    files: Vec<(String, String, usize)>,
+    subtrees: Vec<(String, PendingTree)>,
+}
+
+fn make_tree_object(repository: &Repository, tree: PendingTree, ids: &[String]) -> Result<String> {
+    let mut entries = tree.entries;
+    for (name, subtree) in tree.subtrees {
+        let id = make_tree_object(repository, subtree, ids)?;
+        // git has no empty directories
+        if id == repository::EMPTY_TREE {
+            continue;
+        }
+        entries.push(TreeEntry {
+            mode: "40000".into(),
+            name,
+            id,
+        });
+    }
+    for (mode, name, i) in tree.files {
+        entries.push(TreeEntry {
+            mode,
+            name,
+            id: ids[i].clone(),
+        });
+    }
+
+    repository.create_tree(&entries)
+}
+
+// points refs/replace/<id> at `replacement`, unless something already replaces it and
+// `force` isn't given
+fn replace_object(repository: &Repository, id: &str, replacement: &str, force: bool) -> Result<()> {
+    let ref_name = format!("refs/replace/{}", id);
+    if !force && repository.find_reference(&ref_name).is_some() {
+        eprintln!("error: replace ref '{}' already exists", ref_name);
+        return Err(Error::Exit(255));
+    }
+    repository.set_reference(&ref_name, replacement)
+}
+
+// prints the report on the signature of each of the objects `names` found as `ids`, and with
+// `verbose` the content that was signed; fails if any isn't found, isn't of `object_type` or
+// hasn't a good signature
+fn verify_objects(
+    repository: &Repository,
+    names: &[String],
+    ids: Vec<Option<String>>,
+    object_type: GitObjectType,
+    verbose: bool,
+    raw: bool,
+) -> Result<()> {
+    let config = Config::for_repository(repository.common_dir());
+    let mut failed = false;
+    for (name, id) in names.iter().zip(ids) {
+        let Some(id) = id else {
+            eprintln!("error: {} '{}' not found.", object_type, name);
+            failed = true;
+            continue;
+        };
+        let object = repository.find_object(&id)?;
+        if object.object_type != object_type {
+            eprintln!(
+                "error: {}: cannot verify a non-{} object of type {}.",
+                name, object_type, object.object_type
+            );
+            failed = true;
+            continue;
+        }
+        let content = String::from_utf8_lossy(&object.data);
+        let (payload, signature) = match object_type {
+            GitObjectType::Commit => sign::split_commit_signature(&content),
+            _ => {
+                let (payload, signature) = sign::split_signature(&content);
+                (payload.to_string(), signature.map(str::to_string))
+            }
+        };
+        // an unsigned commit fails quietly, where a tag says so
+        let Some(signature) = signature else {
+            if object_type == GitObjectType::Tag {
+                if verbose {
+                    print!("{}", payload);
+                }
+                eprintln!("error: no signature found");
+            }
+            failed = true;
+            continue;
+        };
+        match sign::verifier(&config, &signature).verify(payload.as_bytes(), &signature) {
+            Ok(verification) => {
+                if verbose {
+                    print!("{}", payload);
+                }
+                match raw {
+                    true => eprint!("{}", verification.raw),
+                    false => eprint!("{}", verification.report),
+                }
+                failed |= !verification.good;
+            }
+            Err(error) => {
+                eprintln!("error: {}", error);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        return Err(Error::Exit(1));
+    }
+    Ok(())
+}
+
+// what log --show-signature says about a commit's signature: the verifier's report, "No
+// signature" when it couldn't be checked, or nothing when the commit isn't signed
+fn signature_report(repository: &Repository, config: &Config, id: &str) -> Result<String> {
+    let object = repository.find_object(id)?;
+    let (payload, signature) = sign::split_commit_signature(&String::from_utf8_lossy(&object.data));
+    let Some(signature) = signature else {
+        return Ok(String::new());
+    };
+    Ok(
+        match sign::verifier(config, &signature).verify(payload.as_bytes(), &signature) {
+            Ok(verification) => verification.report,
+            Err(_) => "No signature\n".to_string(),
+        },
+    )
+}
+
+// the key to sign with, if signing: the one asked for, or with only `setting` saying to sign,
+// user.signingKey, and otherwise empty for the signer's default one
+fn signing_key(
+    config: &Config,
+    requested: Option<&str>,
+    refused: bool,
+    setting: &str,
+) -> Option<String> {
+    let by_setting = || config.get(setting).and_then(config::parse_bool) == Some(true);
+    if refused || (requested.is_none() && !by_setting()) {
+        return None;
+    }
+    let key = requested
+        .filter(|key| !key.is_empty())
+        .or_else(|| config.get("user.signingKey"));
+    Some(key.unwrap_or_default().to_string())
+}
+
+// what git says it merged, as fmt-merge-msg does: the branches, remote-tracking branches and
+// tags by kind, then anything else as a commit, and the branch merged into unless it's the main
+// one
+fn merge_message(repository: &Repository, names: &[&str]) -> String {
+    let kinds = [
+        ("refs/heads/", "branch", "branches"),
+        (
+            "refs/remotes/",
+            "remote-tracking branch",
+            "remote-tracking branches",
+        ),
+        ("refs/tags/", "tag", "tags"),
+    ];
+    let kind_of = |name: &&str| {
+        kinds.iter().position(|(prefix, _, _)| {
+            repository
+                .find_reference(&format!("{}{}", prefix, name))
+                .is_some()
+        })
+    };
+    // "'a'", "'a' and 'b'", "'a', 'b' and 'c'"
+    let join = |names: &[String]| match names.split_last() {
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => format!("{} and {}", rest.join(", "), last),
+        None => String::new(),
+    };
+
+    let mut groups = vec![];
+    for (i, (_, one, many)) in kinds.iter().enumerate() {
+        let quoted: Vec<String> = names
+            .iter()
+            .filter(|name| kind_of(name) == Some(i))
+            .map(|name| format!("'{}'", name))
+            .collect();
+        if !quoted.is_empty() {
+            let kind = if quoted.len() == 1 { one } else { many };
+            groups.push(format!("{} {}", kind, join(&quoted)));
+        }
+    }
+    let mut message = format!("Merge {}", groups.join(", "));
+    for name in names.iter().filter(|name| kind_of(name).is_none()) {
+        if message.len() > "Merge ".len() {
+            message += "; ";
+        }
+        message += &format!("commit '{}'", name);
+    }
+    if let Some(branch) = repository
+        .head_target()
+        .and_then(|target| target.strip_prefix("refs/heads/").map(str::to_string))
+        .filter(|branch| branch != "master" && branch != "main")
+    {
+        message += &format!(" into {}", branch);
+    }
+    message
+}
+
+// removes the directories `file` was in that it leaves empty, up to the work tree
+fn remove_empty_parents(work_tree: &Path, file: &Path) {
+    let mut dir = file.parent();
+    while let Some(parent) = dir.filter(|dir| *dir != work_tree) {
+        if fs::remove_dir(parent).is_err() {
+            break;
+        }
+        dir = parent.parent();
+    }
+}
+
+// trees only record whether a file is executable, going by the owner's execute bit
+#[cfg(unix)]
+fn file_mode(path: &Path) -> io::Result<String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let perms = fs::metadata(path)?.permissions();
+    if perms.mode() & 0o100 != 0 {
+        Ok("100755".into())
+    } else {
+        Ok("100644".into())
+    }
+}
+
+// there is no executable bit to read, so guess it from the content like Git for Windows does
+#[cfg(not(unix))]
+fn file_mode(path: &Path) -> io::Result<String> {
+    let mut head = [0u8; 2];
+    let executable = fs::File::open(path)
+        .and_then(|mut file| io::Read::read_exact(&mut file, &mut head))
+        .is_ok()
+        && (&head == b"#!" || &head == b"MZ");
+    if executable {
+        Ok("100755".into())
+    } else {
+        Ok("100644".into())
+    }
+}
+
+// a symlink is stored as a blob holding its target, with "/" separators
+#[cfg(unix)]
+fn symlink_target(path: &Path) -> io::Result<Vec<u8>> {
+    use std::os::unix::ffi::OsStrExt;
+
+    Ok(fs::read_link(path)?.as_os_str().as_bytes().to_vec())
+}
+
+#[cfg(not(unix))]
+fn symlink_target(path: &Path) -> io::Result<Vec<u8>> {
+    let target = fs::read_link(path)?;
+    Ok(target.to_string_lossy().replace('\\', "/").into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempDir};
+
+    // an app working on the repository at `work_tree`, as --git-dir and --work-tree would have
+    // it, wherever the tests run
+    fn app(work_tree: &Path) -> App {
+        App {
+            git_dir: Some(work_tree.join(".git")),
+            work_tree: Some(work_tree.to_path_buf()),
+            ..App::default()
+        }
+    }
+
+    #[test]
+    fn clone_leaves_a_clean_status() {
+        let dir = TempDir::new("clone-status");
+        let source = testing::init(&dir.path().join("source"));
+        let commit = testing::commit(
+            &source,
+            &[
+                ("a", 0o100644, "a\n"),
+                ("dir/b", 0o100644, "b\n"),
+                ("run", 0o100755, "#!/bin/sh\n"),
+            ],
+            &[],
+            "initial\n",
+        );
+        source.update_reference("HEAD", &commit).unwrap();
+
+        let clone = dir.path().join("clone");
+        let remote = dir.path().join("source").display().to_string();
+        Repo::new(&remote, &clone.join(".git"), &clone)
+            .clone()
+            .unwrap();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = |path: &str| fs::metadata(clone.join(path)).unwrap().permissions().mode();
+            assert_ne!(mode("run") & 0o100, 0);
+            assert_eq!(mode("a") & 0o111, 0);
+        }
+
+        let mut status = vec![];
+        app(&clone)
+            .write_status(&mut status, None, &[], false)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&status),
+            "On branch main\nnothing to commit, working tree clean\n"
+        );
+    }
+
+    #[test]
+    fn commits_on_a_detached_head() {
+        let dir = TempDir::new("detached");
+        let repository = testing::init(dir.path());
+        let first = testing::commit(&repository, &[("a", 0o100644, "one\n")], &[], "first\n");
+        let second = testing::commit(
+            &repository,
+            &[("a", 0o100644, "two\n")],
+            &[&first],
+            "second\n",
+        );
+        repository.update_reference("HEAD", &second).unwrap();
+        let mut config = fs::OpenOptions::new()
+            .append(true)
+            .open(dir.path().join(".git/config"))
+            .unwrap();
+        writeln!(
+            config,
+            "[user]\n\tname = A U Thor\n\temail = author@example.com"
+        )
+        .unwrap();
+        let app = app(dir.path());
+        app.checkout_paths(Some("HEAD"), &[".".to_string()])
+            .unwrap();
+        let status = || {
+            let mut status = vec![];
+            app.write_status(&mut status, None, &[], false).unwrap();
+            String::from_utf8(status).unwrap()
+        };
+
+        // a commit that isn't a branch is checked out with HEAD holding its id
+        app.checkout(&first).unwrap();
+        assert_eq!(repository.head_target(), None);
+        assert_eq!(repository.head(), Some(first.clone()));
+        assert_eq!(fs::read_to_string(dir.path().join("a")).unwrap(), "one\n");
+        assert_eq!(
+            status(),
+            format!(
+                "HEAD detached at {}\nnothing to commit, working tree clean\n",
+                pretty::abbreviate(&first)
+            )
+        );
+
+        // committing moves HEAD alone, leaving the branch where it was
+        fs::write(dir.path().join("b"), "b\n").unwrap();
+        let blob = repository
+            .write_object(GitObjectType::Blob, b"b\n")
+            .unwrap();
+        let mut index = repository.index().unwrap();
+        index.add(IndexEntry::new("b", 0o100644, &blob));
+        index.write(&repository.index_path()).unwrap();
+        app.commit(&["third".to_string()], None, false, None, false, false)
+            .unwrap();
+        let third = repository.head().unwrap();
+        assert_eq!(repository.head_target(), None);
+        assert_eq!(repository.commit_parents(&third).unwrap(), [first]);
+        assert_eq!(
+            repository.find_reference("refs/heads/main"),
+            Some(second.clone())
+        );
+        assert!(status().starts_with(&format!(
+            "HEAD detached at {}\n",
+            pretty::abbreviate(&third)
+        )));
+
+        // log decorates the detached HEAD without a branch, and the branch on its own commit
+        let decorations = pretty::decorations(&repository);
+        assert_eq!(decorations[&third], [pretty::Decoration::Head(None)]);
+        assert_eq!(
+            decorations[&second],
+            [pretty::Decoration::Branch("main".to_string())]
+        );
+
+        // checking out the branch attaches HEAD again
+        app.checkout("main").unwrap();
+        assert_eq!(repository.head_target().as_deref(), Some("refs/heads/main"));
+        assert_eq!(repository.head(), Some(second));
+        assert!(!dir.path().join("b").exists());
     }
 }
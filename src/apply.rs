@@ -0,0 +1,485 @@
+use crate::{
+    error::{Error, Result},
+    objects::{self, GitObjectType},
+    patch::BinaryHunk,
+    xdiff,
+};
+
+// patches as diff writes them, a "diff --git" header and then hunks of lines or a GIT binary
+// patch for each file, read back into the changes they make so that they can be applied the
+// way "git apply" does
+
+// the patch of one file
+pub struct FilePatch {
+    // the file before and after, missing when it is added or deleted
+    pub old_path: Option<String>,
+    pub new_path: Option<String>,
+    pub old_mode: Option<u32>,
+    pub new_mode: Option<u32>,
+    // the blob ids of the "index" line, which binary patches give in full
+    old_id: Option<String>,
+    new_id: Option<String>,
+    body: Body,
+}
+
+enum Body {
+    Text(Vec<Hunk>),
+    // the hunk that makes the new content and then the one that makes the old content back;
+    // none when the patch only says that the files differ
+    Binary(Vec<BinaryHunk>),
+}
+
+struct Hunk {
+    // the line each side of the hunk starts at, counting from 1, or the line before it when
+    // that side is empty
+    old_start: usize,
+    new_start: usize,
+    // each line marked ' ', '-' or '+', with its newline unless it ends a file that has none
+    lines: Vec<(u8, Vec<u8>)>,
+}
+
+impl FilePatch {
+    // the path the patch is about: the new file's, unless it is deleted
+    pub fn path(&self) -> &str {
+        self.new_path
+            .as_deref()
+            .or(self.old_path.as_deref())
+            .unwrap_or_default()
+    }
+
+    // the patch that undoes this one, which for a binary patch takes the hunk that goes back
+    pub fn reverse(self) -> Result<FilePatch> {
+        let path = self.path().to_string();
+        let body = match self.body {
+            Body::Text(hunks) => Body::Text(
+                hunks
+                    .into_iter()
+                    .map(|hunk| Hunk {
+                        old_start: hunk.new_start,
+                        new_start: hunk.old_start,
+                        lines: hunk
+                            .lines
+                            .into_iter()
+                            .map(|(kind, line)| match kind {
+                                b'-' => (b'+', line),
+                                b'+' => (b'-', line),
+                                _ => (kind, line),
+                            })
+                            .collect(),
+                    })
+                    .collect(),
+            ),
+            Body::Binary(hunks) if hunks.len() == 1 => {
+                return Err(Error::IrreversibleBinaryPatch(path))
+            }
+            Body::Binary(mut hunks) => {
+                hunks.reverse();
+                Body::Binary(hunks)
+            }
+        };
+
+        Ok(FilePatch {
+            old_path: self.new_path,
+            new_path: self.old_path,
+            old_mode: self.new_mode,
+            new_mode: self.old_mode,
+            old_id: self.new_id,
+            new_id: self.old_id,
+            body,
+        })
+    }
+
+    // what the patch makes of `old`, the content of the file before it, which is empty when
+    // the file is added; a deleted file has to come out empty
+    pub fn apply(&self, old: &[u8]) -> Result<Vec<u8>> {
+        let path = self.path();
+        let new = match &self.body {
+            Body::Text(hunks) => apply_hunks(path, old, hunks)?,
+            Body::Binary(hunks) => {
+                let hunk = hunks
+                    .first()
+                    .ok_or_else(|| Error::BinaryPatchWithoutData(path.to_string()))?;
+                // a binary patch only fits the very blob it was made from
+                let blob_id =
+                    |content: &[u8]| objects::encode_object(GitObjectType::Blob, content).0;
+                match &self.old_id {
+                    Some(id) if self.old_path.is_some() && *id != blob_id(old) => {
+                        return Err(Error::BinaryPatchMismatch(path.to_string(), id.clone()))
+                    }
+                    _ => {}
+                }
+                let new = hunk.apply(old)?;
+                match &self.new_id {
+                    Some(id) if self.new_path.is_some() && *id != blob_id(&new) => {
+                        return Err(Error::BinaryPatchIncorrect(path.to_string()))
+                    }
+                    _ => {}
+                }
+                new
+            }
+        };
+        if self.new_path.is_none() && !new.is_empty() {
+            return Err(Error::RemovalLeavesContents(path.to_string()));
+        }
+
+        Ok(new)
+    }
+}
+
+// the patch of every file in `input`, leaving out whatever comes before each "diff --git" line,
+// such as the message of a commit
+pub fn parse(input: &[u8]) -> Result<Vec<FilePatch>> {
+    let lines = xdiff::lines(input);
+    let mut patches = vec![];
+    let mut i = 0;
+    while i < lines.len() {
+        let Some(names) = lines[i].strip_prefix(b"diff --git ") else {
+            i += 1;
+            continue;
+        };
+        let start = i;
+        let path = git_diff_path(&String::from_utf8_lossy(trim_newline(names)));
+        let mut patch = FilePatch {
+            old_path: path.clone(),
+            new_path: path,
+            old_mode: None,
+            new_mode: None,
+            old_id: None,
+            new_id: None,
+            body: Body::Text(vec![]),
+        };
+        i += 1;
+
+        // the header lines up to the hunks; the likes of "similarity index" say nothing that
+        // applying the patch needs
+        while let Some(line) = lines.get(i) {
+            let line = String::from_utf8_lossy(trim_newline(line));
+            let corrupt = || Error::CorruptPatch(i + 1);
+            if line.starts_with("diff --git ")
+                || line.starts_with("@@ ")
+                || line.starts_with("Binary files ")
+                || line == "GIT binary patch"
+            {
+                break;
+            }
+            if let Some(mode) = line.strip_prefix("old mode ") {
+                patch.old_mode = Some(parse_mode(mode).ok_or_else(corrupt)?);
+            } else if let Some(mode) = line.strip_prefix("new mode ") {
+                patch.new_mode = Some(parse_mode(mode).ok_or_else(corrupt)?);
+            } else if let Some(mode) = line.strip_prefix("deleted file mode ") {
+                patch.old_mode = Some(parse_mode(mode).ok_or_else(corrupt)?);
+                patch.new_path = None;
+            } else if let Some(mode) = line.strip_prefix("new file mode ") {
+                patch.new_mode = Some(parse_mode(mode).ok_or_else(corrupt)?);
+                patch.old_path = None;
+            } else if let Some(path) = line.strip_prefix("rename from ") {
+                patch.old_path = Some(path.to_string());
+            } else if let Some(path) = line.strip_prefix("rename to ") {
+                patch.new_path = Some(path.to_string());
+            } else if let Some(index) = line.strip_prefix("index ") {
+                // "index <old>..<new>", with the mode after when it is the same on both sides
+                let (ids, mode) = index.split_once(' ').unwrap_or((index, ""));
+                let (old_id, new_id) = ids.split_once("..").ok_or_else(corrupt)?;
+                patch.old_id = Some(old_id.to_string());
+                patch.new_id = Some(new_id.to_string());
+                if !mode.is_empty() {
+                    let mode = parse_mode(mode).ok_or_else(corrupt)?;
+                    patch.old_mode = Some(mode);
+                    patch.new_mode = Some(mode);
+                }
+            } else if let Some(name) = line.strip_prefix("--- ") {
+                patch.old_path = patch_name(name, "a/");
+            } else if let Some(name) = line.strip_prefix("+++ ") {
+                patch.new_path = patch_name(name, "b/");
+            }
+            i += 1;
+        }
+
+        let line = lines.get(i).map_or(&b""[..], |line| trim_newline(line));
+        if line == b"GIT binary patch" {
+            i += 1;
+            let mut hunks = vec![];
+            while let Some(header) = lines
+                .get(i)
+                .map(|line| String::from_utf8_lossy(trim_newline(line)))
+            {
+                if !header.starts_with("literal ") && !header.starts_with("delta ") {
+                    break;
+                }
+                let at = i;
+                i += 1;
+                // lines of base85 up to a blank one
+                let data: Vec<&[u8]> = lines[i..]
+                    .iter()
+                    .map(|line| trim_newline(line))
+                    .take_while(|line| !line.is_empty())
+                    .collect();
+                i += data.len() + 1;
+                hunks.push(BinaryHunk::parse(&header, &data).ok_or(Error::CorruptPatch(at + 1))?);
+            }
+            if hunks.is_empty() {
+                return Err(Error::CorruptPatch(i + 1));
+            }
+            patch.body = Body::Binary(hunks);
+        } else if line.starts_with(b"Binary files ") {
+            i += 1;
+            patch.body = Body::Binary(vec![]);
+        } else {
+            let mut hunks = vec![];
+            while lines.get(i).is_some_and(|line| line.starts_with(b"@@ ")) {
+                hunks.push(parse_hunk(&lines, &mut i)?);
+            }
+            patch.body = Body::Text(hunks);
+        }
+
+        if patch.old_path.is_none() && patch.new_path.is_none() {
+            return Err(Error::CorruptPatch(start + 1));
+        }
+        patches.push(patch);
+    }
+    if patches.is_empty() {
+        return Err(Error::NoPatches);
+    }
+
+    Ok(patches)
+}
+
+// a hunk starting with its "@@ -<old> +<new> @@" line at `i`, leaving `i` after it
+fn parse_hunk(lines: &[&[u8]], i: &mut usize) -> Result<Hunk> {
+    let corrupt = |i: usize| Error::CorruptPatch(i + 1);
+    let header = String::from_utf8_lossy(lines[*i]);
+    let mut ranges = header[3..].split(' ');
+    let (old_start, mut old_left) = ranges
+        .next()
+        .and_then(|range| parse_range(range.strip_prefix('-')?))
+        .ok_or_else(|| corrupt(*i))?;
+    let (new_start, mut new_left) = ranges
+        .next()
+        .and_then(|range| parse_range(range.strip_prefix('+')?))
+        .ok_or_else(|| corrupt(*i))?;
+    *i += 1;
+
+    let mut hunk = Hunk {
+        old_start,
+        new_start,
+        lines: vec![],
+    };
+    while old_left > 0 || new_left > 0 {
+        let line = lines.get(*i).ok_or_else(|| corrupt(*i))?;
+        let (kind, text) = match line.split_first() {
+            // a context line that lost its space, as some editors do to blank lines
+            Some((b'\n', _)) => (b' ', &line[..]),
+            Some((kind @ (b' ' | b'-' | b'+'), text)) => (*kind, text),
+            _ => return Err(corrupt(*i)),
+        };
+        if kind != b'+' {
+            old_left = old_left.checked_sub(1).ok_or_else(|| corrupt(*i))?;
+        }
+        if kind != b'-' {
+            new_left = new_left.checked_sub(1).ok_or_else(|| corrupt(*i))?;
+        }
+        hunk.lines.push((kind, text.to_vec()));
+        *i += 1;
+
+        // "\ No newline at end of file" says the line before it has none
+        if lines.get(*i).is_some_and(|line| line.starts_with(b"\\")) {
+            if let Some((_, text)) = hunk.lines.last_mut() {
+                if text.ends_with(b"\n") {
+                    text.pop();
+                }
+            }
+            *i += 1;
+        }
+    }
+
+    Ok(hunk)
+}
+
+// `content` with the old lines of each hunk, found where the hunk says they are or as close to
+// that as they can be found, replaced with its new ones
+fn apply_hunks(path: &str, content: &[u8], hunks: &[Hunk]) -> Result<Vec<u8>> {
+    let lines = xdiff::lines(content);
+    let mut applied = vec![];
+    let mut done = 0;
+    // how far from where they said the hunks so far were found
+    let mut offset = 0;
+    for hunk in hunks {
+        let side = |left_out: u8| -> Vec<&[u8]> {
+            hunk.lines
+                .iter()
+                .filter(|(kind, _)| *kind != left_out)
+                .map(|(_, line)| &line[..])
+                .collect()
+        };
+        let (old, new) = (side(b'+'), side(b'-'));
+        let start = match old.is_empty() {
+            true => hunk.old_start,
+            false => hunk.old_start.saturating_sub(1),
+        };
+        let expected = start.saturating_add_signed(offset).clamp(done, lines.len());
+        let at = (0..=lines.len())
+            .flat_map(|distance| [Some(expected + distance), expected.checked_sub(distance)])
+            .flatten()
+            .find(|&at| at >= done && lines.get(at..at + old.len()) == Some(&old[..]))
+            .ok_or_else(|| Error::PatchFailed(path.to_string(), hunk.old_start))?;
+
+        applied.extend(lines[done..at].concat());
+        applied.extend(new.concat());
+        done = at + old.len();
+        offset = at as isize - start as isize;
+    }
+    applied.extend(lines[done..].concat());
+
+    Ok(applied)
+}
+
+// the path in "diff --git a/<path> b/<path>", when both sides name the same one
+fn git_diff_path(names: &str) -> Option<String> {
+    let names = names.strip_prefix("a/")?;
+    let len = names.len().checked_sub(3)? / 2;
+    let (old, new) = (names.get(..len)?, names.get(len..)?);
+    (new.strip_prefix(" b/")? == old).then(|| old.to_string())
+}
+
+// the path in a "---" or "+++" line, without its "a/" or "b/" and anything after a tab, or
+// none for /dev/null
+fn patch_name(name: &str, prefix: &str) -> Option<String> {
+    let name = name.split('\t').next().unwrap_or(name);
+    match name {
+        "/dev/null" => None,
+        name => Some(name.strip_prefix(prefix).unwrap_or(name).to_string()),
+    }
+}
+
+// "<start>,<count>", or "<start>" for a count of 1
+fn parse_range(range: &str) -> Option<(usize, usize)> {
+    match range.split_once(',') {
+        Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+        None => Some((range.parse().ok()?, 1)),
+    }
+}
+
+fn parse_mode(mode: &str) -> Option<u32> {
+    u32::from_str_radix(mode, 8).ok()
+}
+
+fn trim_newline(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::patch::{self, PatchOptions, Side};
+
+    // the patch diff writes for `path` going from `old` to `new`
+    fn diff(path: &str, old: Option<&[u8]>, new: Option<&[u8]>, binary: bool) -> Vec<u8> {
+        let side = |content: &[u8]| Side {
+            mode: 0o100644,
+            id: objects::encode_object(GitObjectType::Blob, content).0,
+            content: content.to_vec(),
+        };
+        let options = PatchOptions {
+            words: None,
+            binary: true,
+            algorithm: Default::default(),
+            color: false,
+        };
+        let mut out = vec![];
+        let (old, new) = (old.map(side), new.map(side));
+        patch::write_patch(&mut out, path, old.as_ref(), new.as_ref(), binary, &options).unwrap();
+        out
+    }
+
+    fn apply(patch: &[u8], old: &[u8]) -> Vec<u8> {
+        let mut patches = parse(patch).unwrap();
+        assert_eq!(patches.len(), 1);
+        patches.remove(0).apply(old).unwrap()
+    }
+
+    fn reverse(patch: &[u8], new: &[u8]) -> Vec<u8> {
+        let mut patches = parse(patch).unwrap();
+        patches.remove(0).reverse().unwrap().apply(new).unwrap()
+    }
+
+    #[test]
+    fn text_patches_apply_both_ways() {
+        let old = (1..=20)
+            .map(|i| format!("line {}\n", i))
+            .collect::<String>();
+        let new = old
+            .replace("line 3\n", "line three\n")
+            .replace("line 15\n", "line 15\nline 15 and a half\n")
+            + "no newline";
+        let patch = diff("f", Some(old.as_bytes()), Some(new.as_bytes()), false);
+        assert_eq!(apply(&patch, old.as_bytes()), new.as_bytes());
+        assert_eq!(reverse(&patch, new.as_bytes()), old.as_bytes());
+
+        // the hunks still apply when lines came or went above them
+        let moved = format!("extra\n{}", old);
+        let moved_new = format!("extra\n{}", new);
+        assert_eq!(apply(&patch, moved.as_bytes()), moved_new.as_bytes());
+
+        let changed = old.replace("line 2\n", "line two\n");
+        assert!(matches!(
+            parse(&patch).unwrap().remove(0).apply(changed.as_bytes()),
+            Err(Error::PatchFailed(_, 1))
+        ));
+    }
+
+    #[test]
+    fn added_and_deleted_files() {
+        let added = diff("dir/f", None, Some(b"a\nb\n"), false);
+        let patch = &parse(&added).unwrap()[0];
+        assert_eq!(patch.old_path, None);
+        assert_eq!(patch.new_path.as_deref(), Some("dir/f"));
+        assert_eq!(patch.new_mode, Some(0o100644));
+        assert_eq!(patch.apply(b"").unwrap(), b"a\nb\n");
+
+        let deleted = diff("dir/f", Some(b"a\nb\n"), None, false);
+        assert_eq!(apply(&deleted, b"a\nb\n"), b"");
+        assert!(matches!(
+            parse(&deleted).unwrap().remove(0).apply(b"a\nb\nc\n"),
+            Err(Error::PatchFailed(..) | Error::RemovalLeavesContents(_))
+        ));
+    }
+
+    #[test]
+    fn binary_patches_round_trip() {
+        // an added file is all literal, and a small change to a big file a delta
+        let old: Vec<u8> = (0..4096u32)
+            .flat_map(|i| (i * 7919).to_le_bytes())
+            .collect();
+        let mut new = old.clone();
+        new[1000] = 0;
+        new.extend(b"\0tail");
+
+        let added = diff("bin", None, Some(&old), true);
+        assert!(String::from_utf8_lossy(&added).contains("\nliteral 16384\n"));
+        assert_eq!(apply(&added, b""), old);
+
+        let changed = diff("bin", Some(&old), Some(&new), true);
+        assert!(String::from_utf8_lossy(&changed).contains("\ndelta "));
+        assert_eq!(apply(&changed, &old), new);
+        assert_eq!(reverse(&changed, &new), old);
+
+        // it only fits the blob it was made from
+        assert!(matches!(
+            parse(&changed).unwrap().remove(0).apply(&new),
+            Err(Error::BinaryPatchMismatch(..))
+        ));
+        // and a patch that only says the files differ can't be applied at all
+        let mut options_off = diff("bin", Some(&old), Some(&new), true);
+        let at = options_off
+            .windows(16)
+            .position(|w| w == b"GIT binary patch")
+            .unwrap();
+        options_off.truncate(at);
+        options_off.extend(b"Binary files a/bin and b/bin differ\n");
+        assert!(matches!(
+            parse(&options_off).unwrap().remove(0).apply(&old),
+            Err(Error::BinaryPatchWithoutData(_))
+        ));
+    }
+}
@@ -0,0 +1,219 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::wildmatch::wildmatch;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttributeState {
+    Set,
+    Unset,
+    Value(String),
+    Unspecified,
+}
+
+impl std::fmt::Display for AttributeState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AttributeState::Set => write!(f, "set"),
+            AttributeState::Unset => write!(f, "unset"),
+            AttributeState::Value(value) => write!(f, "{}", value),
+            AttributeState::Unspecified => write!(f, "unspecified"),
+        }
+    }
+}
+
+struct AttributeRule {
+    // directory of the .gitattributes file, relative to the work tree ("" for the root)
+    base: String,
+    pattern: String,
+    assignments: Vec<(String, AttributeState)>,
+}
+
+impl AttributeRule {
+    fn matches(&self, path: &str) -> bool {
+        let path = if self.base.is_empty() {
+            path
+        } else {
+            match path
+                .strip_prefix(&self.base)
+                .and_then(|path| path.strip_prefix('/'))
+            {
+                Some(path) => path,
+                None => return false,
+            }
+        };
+
+        let pattern = self.pattern.strip_prefix('/').unwrap_or(&self.pattern);
+        if self.pattern.contains('/') {
+            wildmatch(pattern.as_bytes(), path.as_bytes(), true)
+        } else {
+            let name = path.rsplit('/').next().unwrap_or(path);
+            wildmatch(pattern.as_bytes(), name.as_bytes(), true)
+        }
+    }
+}
+
+enum AttributeSource {
+    WorkTree(PathBuf),
+    // .gitattributes contents keyed by directory, e.g. taken from a tree being checked out
+    Files(HashMap<String, String>),
+}
+
+pub struct Attributes {
+    source: AttributeSource,
+    git_dir: PathBuf,
+    macros: HashMap<String, Vec<(String, AttributeState)>>,
+    rules: HashMap<String, Vec<AttributeRule>>,
+    info_rules: Vec<AttributeRule>,
+}
+
+impl Attributes {
+    pub fn new(work_tree: &Path, git_dir: &Path) -> Attributes {
+        Attributes::with_source(AttributeSource::WorkTree(work_tree.to_path_buf()), git_dir)
+    }
+
+    pub fn from_files(files: HashMap<String, String>, git_dir: &Path) -> Attributes {
+        Attributes::with_source(AttributeSource::Files(files), git_dir)
+    }
+
+    fn with_source(source: AttributeSource, git_dir: &Path) -> Attributes {
+        let mut attributes = Attributes {
+            source,
+            git_dir: git_dir.to_path_buf(),
+            macros: HashMap::new(),
+            rules: HashMap::new(),
+            info_rules: vec![],
+        };
+        attributes.macros.insert(
+            "binary".into(),
+            vec![
+                ("diff".into(), AttributeState::Unset),
+                ("merge".into(), AttributeState::Unset),
+                ("text".into(), AttributeState::Unset),
+            ],
+        );
+        // macros may only be defined at the top level, so load those files eagerly
+        attributes.load_dir("");
+        let info = fs::read_to_string(attributes.git_dir.join("info/attributes"));
+        attributes.info_rules = attributes.parse(&info.unwrap_or_default(), "", true);
+
+        attributes
+    }
+
+    fn load_dir(&mut self, dir: &str) {
+        if !self.rules.contains_key(dir) {
+            let content = match &self.source {
+                AttributeSource::WorkTree(work_tree) => {
+                    fs::read_to_string(work_tree.join(dir).join(".gitattributes"))
+                        .unwrap_or_default()
+                }
+                AttributeSource::Files(files) => files.get(dir).cloned().unwrap_or_default(),
+            };
+            let rules = self.parse(&content, dir, dir.is_empty());
+            self.rules.insert(dir.to_string(), rules);
+        }
+    }
+
+    fn parse(&mut self, content: &str, base: &str, allow_macros: bool) -> Vec<AttributeRule> {
+        let mut rules = vec![];
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let pattern = parts.next().unwrap();
+            let assignments = parts.map(parse_assignment).collect();
+
+            if let Some(name) = pattern.strip_prefix("[attr]") {
+                if allow_macros {
+                    self.macros.insert(name.to_string(), assignments);
+                }
+                continue;
+            }
+            // negative patterns are forbidden in attribute files
+            if pattern.starts_with('!') {
+                continue;
+            }
+
+            rules.push(AttributeRule {
+                base: base.to_string(),
+                pattern: pattern.to_string(),
+                assignments,
+            });
+        }
+
+        rules
+    }
+
+    // every attribute specified for `path` (relative to the work tree), in definition order
+    pub fn check_all(&mut self, path: &str) -> Vec<(String, AttributeState)> {
+        let mut dirs = vec![String::new()];
+        let mut components: Vec<&str> = path.split('/').collect();
+        components.pop();
+        for i in 1..=components.len() {
+            dirs.push(components[..i].join("/"));
+        }
+        for dir in &dirs {
+            self.load_dir(dir);
+        }
+
+        let mut result: Vec<(String, AttributeState)> = vec![];
+        let rules = dirs
+            .iter()
+            .flat_map(|dir| self.rules[dir].iter())
+            .chain(self.info_rules.iter());
+        for rule in rules.filter(|rule| rule.matches(path)) {
+            for (name, state) in &rule.assignments {
+                self.assign(&mut result, name, state, 0);
+            }
+        }
+
+        result.retain(|(_, state)| *state != AttributeState::Unspecified);
+        result
+    }
+
+    fn assign(
+        &self,
+        result: &mut Vec<(String, AttributeState)>,
+        name: &str,
+        state: &AttributeState,
+        depth: usize,
+    ) {
+        match result.iter_mut().find(|(existing, _)| existing == name) {
+            Some((_, existing)) => *existing = state.clone(),
+            None => result.push((name.to_string(), state.clone())),
+        }
+
+        if *state == AttributeState::Set && depth < 8 {
+            if let Some(expansion) = self.macros.get(name) {
+                for (name, state) in expansion {
+                    self.assign(result, name, state, depth + 1);
+                }
+            }
+        }
+    }
+
+    pub fn check(&mut self, path: &str, name: &str) -> AttributeState {
+        self.check_all(path)
+            .into_iter()
+            .find(|(attribute, _)| attribute == name)
+            .map(|(_, state)| state)
+            .unwrap_or(AttributeState::Unspecified)
+    }
+}
+
+fn parse_assignment(assignment: &str) -> (String, AttributeState) {
+    if let Some(name) = assignment.strip_prefix('-') {
+        (name.to_string(), AttributeState::Unset)
+    } else if let Some(name) = assignment.strip_prefix('!') {
+        (name.to_string(), AttributeState::Unspecified)
+    } else if let Some((name, value)) = assignment.split_once('=') {
+        (name.to_string(), AttributeState::Value(value.to_string()))
+    } else {
+        (assignment.to_string(), AttributeState::Set)
+    }
+}
@@ -0,0 +1,777 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    mem,
+    ops::Range,
+};
+
+use crate::{
+    diff,
+    error::{Error, Result},
+    index::IndexEntry,
+    objects::{self, GitObjectType},
+    pathspec::Pathspecs,
+    regex::Regex,
+    repository::{Commit, Repository, Signature},
+    xdiff::{self, Algorithm},
+};
+
+// blame works out which commit each line of a file comes from as git does: the lines a commit
+// is suspected of are passed on to each parent whose version of the file has them too, and
+// the ones no parent takes are the commit's own. Commits are gone through newest first, so the
+// lines a commit is suspected of have all reached it by the time it's looked at
+
+// how many alphanumeric characters lines need to have to be followed when they moved within a
+// file, and when they were copied from another one
+pub const DEFAULT_MOVE_SCORE: usize = 20;
+pub const DEFAULT_COPY_SCORE: usize = 40;
+
+// how hard blame looks for where lines come from
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Options {
+    // lines are compared without their whitespace
+    pub ignore_whitespace: bool,
+    // lines moved within the file are followed when they have more alphanumeric characters
+    // than this
+    pub move_score: Option<usize>,
+    // lines copied from other files are followed too when they have more than `copy_score`:
+    // from the files the same commit changes, with 2 from any file in the commit that creates
+    // the file, and with 3 from any file in any commit
+    pub copies: usize,
+    pub copy_score: usize,
+}
+
+// a version of a file that lines are blamed on: the file at `path` in `commit`
+#[derive(Clone, Debug)]
+pub struct Origin {
+    pub commit: String,
+    pub path: String,
+    blob: String,
+    // the commit has no parents to blame lines on instead
+    pub boundary: bool,
+    // the version of the file in the first parent that has it, which lines were passed to
+    pub previous: Option<usize>,
+}
+
+// `len` lines of the file blamed from `lno` on, which are the lines of `origin` from `s_lno`
+// on, all counted from 0
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub lno: usize,
+    pub len: usize,
+    pub s_lno: usize,
+    pub origin: usize,
+}
+
+// which origin each line of a file is blamed on
+pub struct Blame {
+    // the lines of the file, each with its newline if it has one
+    pub lines: Vec<Vec<u8>>,
+    pub origins: Vec<Origin>,
+    // in the order of the lines they cover
+    pub entries: Vec<Entry>,
+    commits: HashMap<String, Commit>,
+}
+
+impl Blame {
+    pub fn commit(&self, origin: &Origin) -> &Commit {
+        &self.commits[&origin.commit]
+    }
+}
+
+// the commit that stands for a version of the file in the work tree that hasn't been
+// committed, on top of `head`
+pub fn work_tree_commit(head: &str, path: &str) -> Commit {
+    let author = Signature::now("Not Committed Yet", "not.committed.yet");
+    Commit {
+        id: diff::NULL_ID.to_string(),
+        tree: String::new(),
+        parents: vec![head.to_string()],
+        author: author.clone(),
+        committer: author,
+        message: format!("Version of {} from {}\n", path, path),
+    }
+}
+
+// blames the lines of `ranges` of `content`, the file at `path` in `commit`
+pub fn blame(
+    repository: &Repository,
+    commit: Commit,
+    path: &str,
+    content: Vec<u8>,
+    ranges: &[Range<usize>],
+    options: Options,
+) -> Result<Blame> {
+    let mut scoreboard = Scoreboard {
+        repository,
+        options,
+        lines: xdiff::lines(&content)
+            .into_iter()
+            .map(|line| line.to_vec())
+            .collect(),
+        content,
+        origins: vec![],
+        suspects: vec![],
+        commits: HashMap::new(),
+        commit_origins: HashMap::new(),
+        queue: BinaryHeap::new(),
+        queued: 0,
+        blamed: vec![],
+    };
+    let blob = objects::encode_object(GitObjectType::Blob, &scoreboard.content).0;
+    scoreboard.commits.insert(commit.id.clone(), commit.clone());
+    let origin = scoreboard.origin(&commit.id, path, &blob);
+    scoreboard.give(
+        ranges
+            .iter()
+            .filter(|range| !range.is_empty())
+            .map(|range| Entry {
+                lno: range.start,
+                len: range.len(),
+                s_lno: range.start,
+                origin,
+            })
+            .collect(),
+    );
+
+    while let Some((_, _, id)) = scoreboard.queue.pop() {
+        for origin in scoreboard.commit_origins[&id].clone() {
+            if scoreboard.suspects[origin].is_empty() {
+                continue;
+            }
+            scoreboard.pass_blame(origin)?;
+            // what no parent took is the commit's own
+            let entries = mem::take(&mut scoreboard.suspects[origin]);
+            scoreboard.blamed.extend(entries);
+        }
+    }
+
+    // entries that follow on from each other in both files are taken together
+    let mut blamed = mem::take(&mut scoreboard.blamed);
+    blamed.sort_by_key(|entry| entry.lno);
+    let mut entries: Vec<Entry> = vec![];
+    for entry in blamed {
+        match entries.last_mut() {
+            Some(last)
+                if last.origin == entry.origin
+                    && last.s_lno + last.len == entry.s_lno
+                    && last.lno + last.len == entry.lno =>
+            {
+                last.len += entry.len
+            }
+            _ => entries.push(entry),
+        }
+    }
+
+    Ok(Blame {
+        lines: scoreboard.lines,
+        origins: scoreboard.origins,
+        entries,
+        commits: scoreboard.commits,
+    })
+}
+
+struct Scoreboard<'a> {
+    repository: &'a Repository,
+    options: Options,
+    lines: Vec<Vec<u8>>,
+    content: Vec<u8>,
+    origins: Vec<Origin>,
+    // the entries each origin is still suspected of, in the order of its lines
+    suspects: Vec<Vec<Entry>>,
+    commits: HashMap<String, Commit>,
+    commit_origins: HashMap<String, Vec<usize>>,
+    // the commits with origins that have suspects, newest first and then in the order they
+    // were queued
+    queue: BinaryHeap<(i64, Reverse<usize>, String)>,
+    queued: usize,
+    blamed: Vec<Entry>,
+}
+
+// an entry split around the lines of it that another origin has: the part before them, the
+// part taken by the other origin, and the part after them, any of them possibly missing
+type Split = [Option<Entry>; 3];
+
+impl Scoreboard<'_> {
+    fn commit(&mut self, id: &str) -> Result<Commit> {
+        if let Some(commit) = self.commits.get(id) {
+            return Ok(commit.clone());
+        }
+        let commit = self.repository.find_commit(id)?;
+        self.commits.insert(id.to_string(), commit.clone());
+        Ok(commit)
+    }
+
+    // the origin for `path` in `commit`, made if there's none yet
+    fn origin(&mut self, commit: &str, path: &str, blob: &str) -> usize {
+        let origins = self.commit_origins.entry(commit.to_string()).or_default();
+        if let Some(&origin) = origins
+            .iter()
+            .find(|&&origin| self.origins[origin].path == path)
+        {
+            return origin;
+        }
+        origins.push(self.origins.len());
+        self.origins.push(Origin {
+            commit: commit.to_string(),
+            path: path.to_string(),
+            blob: blob.to_string(),
+            boundary: false,
+            previous: None,
+        });
+        self.suspects.push(vec![]);
+        self.origins.len() - 1
+    }
+
+    // the file an origin is, which for the work tree isn't an object
+    fn content(&self, origin: usize) -> Result<Vec<u8>> {
+        match self.origins[origin].commit == diff::NULL_ID {
+            true => Ok(self.content.clone()),
+            false => Ok(self
+                .repository
+                .find_object(&self.origins[origin].blob)?
+                .data),
+        }
+    }
+
+    // hands entries to the origins they name, queueing the commits of those with nothing to
+    // look at before
+    fn give(&mut self, entries: Vec<Entry>) {
+        let mut given = vec![];
+        for entry in entries {
+            let origin = entry.origin;
+            given.push(origin);
+            if self.suspects[origin].is_empty() {
+                let id = self.origins[origin].commit.clone();
+                let time = match id == diff::NULL_ID {
+                    true => i64::MAX,
+                    false => self.commits[&id].committer.time,
+                };
+                self.queue.push((time, Reverse(self.queued), id));
+                self.queued += 1;
+            }
+            self.suspects[origin].push(entry);
+        }
+        for origin in given {
+            self.suspects[origin].sort_by_key(|entry| entry.s_lno);
+        }
+    }
+
+    // how much an entry is worth following: one more than the alphanumeric characters in its
+    // lines
+    fn score(&self, entry: &Entry) -> usize {
+        1 + self.lines[entry.lno..entry.lno + entry.len]
+            .iter()
+            .flatten()
+            .filter(|b| b.is_ascii_alphanumeric())
+            .count()
+    }
+
+    // `entries` without those too small to follow, which go to `too_small`
+    fn filter_small(
+        &self,
+        entries: Vec<Entry>,
+        too_small: &mut Vec<Entry>,
+        score: usize,
+    ) -> Vec<Entry> {
+        let (big, small): (Vec<Entry>, Vec<Entry>) = entries
+            .into_iter()
+            .partition(|entry| self.score(entry) > score);
+        too_small.extend(small);
+        big
+    }
+
+    // the runs of lines of `new` that the diff from `old` leaves as they are, each with the
+    // line of `old` it starts at. As git does for a diff without context, the whole blocks of
+    // 1024 bytes both end with are left out of it, from the first newline in them on, which
+    // can change where the diff puts the lines that could go in more than one place
+    fn unchanged(&self, old: &[u8], new: &[u8]) -> Vec<(Range<usize>, usize)> {
+        const BLOCK: usize = 1024;
+        let mut trimmed = 0;
+        while trimmed + BLOCK <= old.len().min(new.len())
+            && old[old.len() - trimmed - BLOCK..old.len() - trimmed]
+                == new[new.len() - trimmed - BLOCK..new.len() - trimmed]
+        {
+            trimmed += BLOCK;
+        }
+        let tail = &old[old.len() - trimmed..];
+        let kept = tail
+            .iter()
+            .position(|b| *b == b'\n')
+            .map_or(trimmed, |newline| trimmed - newline - 1);
+        let (old, new_lines) = (
+            xdiff::lines(&old[..old.len() - kept]),
+            xdiff::lines(&new[..new.len() - kept]),
+        );
+        let edits = match self.options.ignore_whitespace {
+            true => xdiff::diff_ignoring_whitespace(&old, &new_lines, Algorithm::Myers, true),
+            false => xdiff::diff(&old, &new_lines, Algorithm::Myers, true),
+        };
+
+        let (mut regions, mut old, mut new_line) = (vec![], 0, 0);
+        for edit in &edits {
+            regions.push((new_line..edit.new.start, old));
+            (old, new_line) = (edit.old.end, edit.new.end);
+        }
+        regions.push((new_line..xdiff::lines(new).len(), old));
+        regions
+    }
+
+    // the origin in `parent` of the file `origin` is: the one at the same path, or else the
+    // one it was renamed from
+    fn parent_origin(&mut self, parent: &Commit, origin: usize) -> Result<Option<usize>> {
+        let Origin { commit, path, .. } = self.origins[origin].clone();
+        if let Some(file) = self.repository.find_file(&parent.tree, &path)? {
+            return Ok(Some(self.origin(&parent.id, &path, &file.id)));
+        }
+        if commit == diff::NULL_ID {
+            return Ok(None);
+        }
+        let tree = &self.commits[&commit].tree;
+        let Some(file) = self.repository.find_file(tree, &path)? else {
+            return Ok(None);
+        };
+        let source = self
+            .repository
+            .copy_source(&parent.tree, tree, &file, false)?;
+        Ok(source.map(|source| self.origin(&parent.id, &source.path, &source.id)))
+    }
+
+    fn pass_blame(&mut self, origin: usize) -> Result<()> {
+        let commit = self.commits[&self.origins[origin].commit].clone();
+        let parents = match commit.id == diff::NULL_ID {
+            true => commit.parents.clone(),
+            false => self.repository.commit_parents(&commit.id)?,
+        };
+        if parents.is_empty() {
+            self.origins[origin].boundary = true;
+            return Ok(());
+        }
+
+        let mut scapegoats = vec![];
+        for parent in &parents {
+            let parent = self.commit(parent)?;
+            let parent_origin = self.parent_origin(&parent, origin)?;
+            // a parent with the file as it is takes all of it
+            if let Some(parent_origin) = parent_origin {
+                if self.origins[parent_origin].blob == self.origins[origin].blob {
+                    let mut entries = mem::take(&mut self.suspects[origin]);
+                    for entry in &mut entries {
+                        entry.origin = parent_origin;
+                    }
+                    self.give(entries);
+                    return Ok(());
+                }
+            }
+            scapegoats.push((parent, parent_origin));
+        }
+        for parent_origin in scapegoats.iter().filter_map(|(_, origin)| *origin) {
+            self.origins[origin].previous.get_or_insert(parent_origin);
+            self.pass_blame_to_parent(origin, parent_origin)?;
+            if self.suspects[origin].is_empty() {
+                return Ok(());
+            }
+        }
+
+        let mut too_small = vec![];
+        if let Some(move_score) = self.options.move_score {
+            let suspects = mem::take(&mut self.suspects[origin]);
+            self.suspects[origin] = self.filter_small(suspects, &mut too_small, move_score);
+            for parent_origin in scapegoats.iter().filter_map(|(_, origin)| *origin) {
+                if self.suspects[origin].is_empty() {
+                    break;
+                }
+                self.find_move_in_parent(origin, parent_origin, &mut too_small)?;
+            }
+        }
+        if self.options.copies > 0 {
+            let move_score = self.options.move_score.unwrap_or(DEFAULT_MOVE_SCORE);
+            let copy_score = self.options.copy_score;
+            // what was too small to be moved may be big enough to be copied, and the other
+            // way around
+            let mut suspects = mem::take(&mut self.suspects[origin]);
+            if copy_score < move_score {
+                suspects.append(&mut too_small);
+            }
+            if copy_score != move_score {
+                suspects = self.filter_small(suspects, &mut too_small, copy_score);
+            }
+            self.suspects[origin] = suspects;
+            for (parent, parent_origin) in &scapegoats {
+                if self.suspects[origin].is_empty() {
+                    break;
+                }
+                self.find_copy_in_parent(origin, parent, *parent_origin, &mut too_small)?;
+            }
+        }
+        self.suspects[origin].append(&mut too_small);
+        self.suspects[origin].sort_by_key(|entry| entry.s_lno);
+        Ok(())
+    }
+
+    // passes the lines a parent's version of the file has as they are to the parent
+    fn pass_blame_to_parent(&mut self, origin: usize, parent: usize) -> Result<()> {
+        let (content, parent_content) = (self.content(origin)?, self.content(parent)?);
+        let regions = self.unchanged(&parent_content, &content);
+
+        let (mut kept, mut passed) = (vec![], vec![]);
+        for entry in mem::take(&mut self.suspects[origin]) {
+            let mut rest = Some(entry);
+            for (region, parent_start) in &regions {
+                let Some(entry) = rest.take() else {
+                    break;
+                };
+                let [before, within, after] = split(&entry, region.clone(), parent, *parent_start);
+                kept.extend(before);
+                passed.extend(within);
+                rest = after;
+            }
+            kept.extend(rest);
+        }
+        self.suspects[origin] = kept;
+        self.give(passed);
+        Ok(())
+    }
+
+    // the best part of `entry` that `parent_content` has too, the one with the highest score
+    // and of those the last, as a split of it
+    fn find_copy_in_blob(
+        &self,
+        entry: &Entry,
+        parent: usize,
+        parent_content: &[u8],
+    ) -> Option<(usize, Split)> {
+        let content = self.lines[entry.lno..entry.lno + entry.len].concat();
+        let mut best: Option<(usize, Split)> = None;
+        for (region, parent_start) in self.unchanged(parent_content, &content) {
+            let region = entry.s_lno + region.start..entry.s_lno + region.end;
+            let split = split(entry, region, parent, parent_start);
+            let Some(within) = &split[1] else {
+                continue;
+            };
+            let score = self.score(within);
+            if best.as_ref().is_none_or(|(best, _)| score >= *best) {
+                best = Some((score, split));
+            }
+        }
+        best
+    }
+
+    // passes the lines moved within the file to the parent's version of it, for as long as
+    // there are parts left big enough to follow
+    fn find_move_in_parent(
+        &mut self,
+        origin: usize,
+        parent: usize,
+        too_small: &mut Vec<Entry>,
+    ) -> Result<()> {
+        let Some(move_score) = self.options.move_score else {
+            return Ok(());
+        };
+        let parent_content = self.content(parent)?;
+        let mut unblamed = mem::take(&mut self.suspects[origin]);
+        let (mut leftover, mut passed) = (vec![], vec![]);
+        while !unblamed.is_empty() {
+            let mut rest = vec![];
+            for entry in unblamed {
+                match self.find_copy_in_blob(&entry, parent, &parent_content) {
+                    Some((score, [before, within, after])) if score > move_score => {
+                        passed.extend(within);
+                        rest.extend(before.into_iter().chain(after));
+                    }
+                    _ => leftover.push(entry),
+                }
+            }
+            unblamed = self.filter_small(rest, too_small, move_score);
+        }
+        self.suspects[origin] = leftover;
+        self.give(passed);
+        Ok(())
+    }
+
+    // passes the lines copied from other files of `parent` to those files: the ones the commit
+    // changes, or all of them when looking harder
+    fn find_copy_in_parent(
+        &mut self,
+        origin: usize,
+        parent: &Commit,
+        parent_origin: Option<usize>,
+        too_small: &mut Vec<Entry>,
+    ) -> Result<()> {
+        let Origin { commit, path, .. } = self.origins[origin].clone();
+        let parent_path =
+            parent_origin.map(|parent_origin| self.origins[parent_origin].path.clone());
+        let harder = self.options.copies >= 3
+            || (self.options.copies >= 2 && parent_path.as_ref() != Some(&path));
+        let mut files: Vec<IndexEntry> = if harder {
+            self.repository.tree_files(&parent.tree, "")?
+        } else if commit == diff::NULL_ID {
+            let base = self.repository.tree_files(&parent.tree, "")?;
+            let index = self.repository.index()?;
+            diff::diff_entries(&base, index.entries())
+                .into_iter()
+                .filter_map(|change| change.old)
+                .collect()
+        } else {
+            let tree = &self.commits[&commit].tree;
+            self.repository
+                .tree_changes(Some(&parent.tree), Some(tree), &Pathspecs::new(vec![]))?
+                .into_iter()
+                .filter_map(|change| change.old)
+                .collect()
+        };
+        // submodules have no lines, and the parent's version of the file was already looked at
+        files.retain(|file| {
+            file.mode & 0o170000 != 0o160000 && Some(&file.path) != parent_path.as_ref()
+        });
+
+        let mut unblamed = mem::take(&mut self.suspects[origin]);
+        let (mut leftover, mut passed) = (vec![], vec![]);
+        while !unblamed.is_empty() {
+            let mut best: Vec<Option<(usize, Split)>> = vec![None; unblamed.len()];
+            for file in &files {
+                let file_origin = self.origin(&parent.id, &file.path, &file.id);
+                let content = self.content(file_origin)?;
+                for (entry, best) in unblamed.iter().zip(&mut best) {
+                    let Some((score, split)) = self.find_copy_in_blob(entry, file_origin, &content)
+                    else {
+                        continue;
+                    };
+                    if best.as_ref().is_none_or(|(best, _)| score >= *best) {
+                        *best = Some((score, split));
+                    }
+                }
+            }
+            let mut rest = vec![];
+            for (entry, best) in unblamed.into_iter().zip(best) {
+                match best {
+                    Some((score, [before, within, after])) if score > self.options.copy_score => {
+                        passed.extend(within);
+                        rest.extend(before.into_iter().chain(after));
+                    }
+                    _ => leftover.push(entry),
+                }
+            }
+            unblamed = self.filter_small(rest, too_small, self.options.copy_score);
+        }
+        self.suspects[origin] = leftover;
+        self.give(passed);
+        Ok(())
+    }
+}
+
+// `entry` split where it overlaps `lines` of its origin, which are the lines of `origin` from
+// `s_lno` on
+fn split(entry: &Entry, lines: Range<usize>, origin: usize, s_lno: usize) -> Split {
+    let end = entry.s_lno + entry.len;
+    let start = lines.start.clamp(entry.s_lno, end);
+    let stop = lines.end.clamp(start, end);
+    let part = |from: usize, to: usize, origin: usize, s_lno: usize| {
+        (from < to).then(|| Entry {
+            lno: entry.lno + from - entry.s_lno,
+            len: to - from,
+            s_lno,
+            origin,
+        })
+    };
+    [
+        part(entry.s_lno, start, entry.origin, entry.s_lno),
+        // with nothing in common the start may be past `lines`, and there's no part to place
+        part(
+            start,
+            stop,
+            origin,
+            (s_lno + start).saturating_sub(lines.start),
+        ),
+        part(stop, end, entry.origin, stop),
+    ]
+}
+
+// the lines the -L options pick out of `lines`, as git has them: "<start>,<end>" where either
+// is a line number or a "/regex/" searched for from the line after the last range, or for the
+// end from the start, and is the first or last line when left out; "<start>,+<count>" and
+// "<start>,-<count>" for the lines from or up to <start>; or ":<regex>" for the function whose
+// first line the regex matches
+pub fn line_ranges(args: &[String], lines: &[&[u8]], path: &str) -> Result<Vec<Range<usize>>> {
+    if args.is_empty() {
+        let whole = 0..lines.len();
+        return Ok(vec![whole]);
+    }
+    let mut ranges: Vec<Range<usize>> = vec![];
+    let mut anchor = 1;
+    for arg in args {
+        let (bottom, top) = parse_range(arg, lines, anchor)?;
+        if (lines.is_empty() && (top > 0 || bottom > 0)) || bottom > lines.len() as i64 {
+            return Err(Error::TooFewLines(path.to_string(), lines.len()));
+        }
+        let bottom = bottom.max(1) as usize;
+        let top = match top < 1 || top > lines.len() as i64 {
+            true => lines.len(),
+            false => top as usize,
+        };
+        ranges.push(bottom - 1..top);
+        anchor = top + 1;
+    }
+
+    // overlapping and adjacent ranges are taken together
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<Range<usize>> = vec![];
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    Ok(merged)
+}
+
+// where a -L range starts and ends, counting from 1 with 0 for not given
+fn parse_range(arg: &str, lines: &[&[u8]], anchor: usize) -> Result<(i64, i64)> {
+    let invalid = || Error::InvalidLineRange(arg.to_string());
+    let anchor = anchor.clamp(1, lines.len() + 1) as i64;
+    if arg.starts_with(':') || arg.starts_with("^:") {
+        return parse_funcname(arg, lines, anchor)?.ok_or_else(invalid);
+    }
+
+    let (mut begin, rest) = parse_location(arg, lines, -anchor, false)?;
+    let (mut end, rest) = match rest.strip_prefix(',') {
+        Some(rest) => parse_location(rest, lines, begin + 1, true)?,
+        None => (0, rest),
+    };
+    if !rest.is_empty() {
+        return Err(invalid());
+    }
+    if begin > 0 && end > 0 && end < begin {
+        (begin, end) = (end, begin);
+    }
+    Ok((begin, end))
+}
+
+// one side of a -L range and what follows it; `begin` is where the range starts for its end,
+// or minus the line a regex is searched for from for its start
+fn parse_location<'a>(
+    spec: &'a str,
+    lines: &[&[u8]],
+    begin: i64,
+    is_end: bool,
+) -> Result<(i64, &'a str)> {
+    let number = |spec: &'a str| -> Option<(i64, &'a str)> {
+        let sign = spec.starts_with(['+', '-']) as usize;
+        let digits = spec[sign..].bytes().take_while(u8::is_ascii_digit).count();
+        let value = spec[..sign + digits].parse().ok()?;
+        Some((value, &spec[sign + digits..]))
+    };
+    if is_end && begin >= 1 && spec.starts_with(['+', '-']) {
+        let Some((count, rest)) = number(spec) else {
+            return Ok((0, spec));
+        };
+        let end = match count {
+            0 => return Err(Error::EmptyLineRange),
+            count if count > 0 => begin + count - 2,
+            count => (begin + count).max(1),
+        };
+        return Ok((end, rest));
+    }
+    if let Some((line, rest)) = number(spec) {
+        if line <= 0 {
+            return Err(Error::InvalidLineNumber(line));
+        }
+        return Ok((line, rest));
+    }
+
+    let (mut begin, mut spec) = (begin, spec);
+    if begin < 0 {
+        match spec.strip_prefix('^') {
+            Some(rest) => (begin, spec) = (1, rest),
+            None => begin = -begin,
+        }
+    }
+    let Some(pattern) = spec.strip_prefix('/') else {
+        return Ok((0, spec));
+    };
+    let Some(len) = pattern_len(pattern, b'/') else {
+        return Ok((0, spec));
+    };
+    let (pattern, rest) = (&pattern[..len], &pattern[len + 1..]);
+
+    // the search goes from the start of line `begin` to the end of the file
+    let start = (begin.max(1) - 1) as usize;
+    let text = lines.get(start..).unwrap_or_default().concat();
+    let no_match = |message: String| Error::LineRangeNoMatch(pattern.to_string(), begin, message);
+    let regex = Regex::new(pattern).map_err(no_match)?;
+    let Some((position, _)) = regex.find_at(&text, 0) else {
+        return Err(no_match("No match".to_string()));
+    };
+    Ok((line_at(lines, start, position) as i64 + 1, rest))
+}
+
+// the line of `lines` that byte `position` of them from line `start` on is in
+fn line_at(lines: &[&[u8]], start: usize, position: usize) -> usize {
+    let mut offset = 0;
+    for (i, line) in lines.iter().enumerate().skip(start) {
+        offset += line.len();
+        if position < offset {
+            return i;
+        }
+    }
+    lines.len()
+}
+
+// how long a pattern is up to `terminator`, skipping over what a backslash escapes
+fn pattern_len(pattern: &str, terminator: u8) -> Option<usize> {
+    let bytes = pattern.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() && bytes[i] != terminator {
+        if bytes[i] == b'\\' && i + 1 < bytes.len() {
+            i += 1;
+        }
+        i += 1;
+    }
+    (i < bytes.len()).then_some(i)
+}
+
+// ":<regex>", or "^:<regex>" to search from the start of the file, for the lines from the
+// first function line the regex matches up to the next function line
+fn parse_funcname(arg: &str, lines: &[&[u8]], anchor: i64) -> Result<Option<(i64, i64)>> {
+    let (anchor, arg) = match arg.strip_prefix('^') {
+        Some(arg) => (1, arg),
+        None => (anchor, arg),
+    };
+    let pattern = &arg[1..];
+    let len = pattern_len(pattern, b':').unwrap_or(pattern.len());
+    if len == 0 || len < pattern.len() {
+        return Ok(None);
+    }
+    let regex = Regex::new(pattern)
+        .map_err(|message| Error::InvalidFuncnameRegex(pattern.to_string(), message))?;
+
+    // like git without a diff driver, a function line starts with a letter, "_" or "$"
+    let is_function = |line: &[u8]| {
+        line.first()
+            .is_some_and(|b| b.is_ascii_alphabetic() || matches!(b, b'_' | b'$'))
+    };
+    let start = (anchor - 1) as usize;
+    let text = lines.get(start..).unwrap_or_default().concat();
+    let mut position = 0;
+    let begin = loop {
+        let Some((found, _)) = regex.find_at(&text, position) else {
+            return Err(Error::LineRangeNoMatch(
+                pattern.to_string(),
+                anchor,
+                "no match".to_string(),
+            ));
+        };
+        let line = line_at(lines, start, found);
+        if line >= lines.len() || is_function(lines[line]) {
+            break line;
+        }
+        position = lines[start..=line].iter().map(|line| line.len()).sum();
+    };
+    if begin >= lines.len() {
+        return Err(Error::LineRangeAtEof(pattern.to_string()));
+    }
+    let end = (begin + 1..lines.len())
+        .find(|&line| is_function(lines[line]))
+        .unwrap_or(lines.len());
+    Ok(Some((begin as i64 + 1, end as i64)))
+}
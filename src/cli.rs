@@ -0,0 +1,1486 @@
+use std::path::PathBuf;
+
+use clap::{Arg, ArgAction, ArgMatches, Args, FromArgMatches, Parser, Subcommand};
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "git",
+    bin_name = "git",
+    about = "A small git implementation",
+    version
+)]
+pub struct Cli {
+    /// Run as if git was started in <path>
+    #[arg(short = 'C', value_name = "path")]
+    pub directories: Vec<PathBuf>,
+    /// Path to the repository (the ".git" directory)
+    #[arg(long, value_name = "path")]
+    pub git_dir: Option<PathBuf>,
+    /// Path to the working tree
+    #[arg(long, value_name = "path")]
+    pub work_tree: Option<PathBuf>,
+    /// Look objects up as they are stored, ignoring what refs/replace/ replaces them with
+    #[arg(long)]
+    pub no_replace_objects: bool,
+    /// Don't pipe the output of commands like log through the pager
+    #[arg(short = 'P', long)]
+    pub no_pager: bool,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Create an empty repository, or reinitialize an existing one
+    Init {
+        /// Make a bare repository, without a work tree
+        #[arg(long)]
+        bare: bool,
+        /// Name of the branch HEAD starts on, instead of init.defaultBranch or "master"
+        #[arg(short = 'b', long)]
+        initial_branch: Option<String>,
+        /// Directory whose files are copied into the new repository
+        #[arg(long, value_name = "template-directory")]
+        template: Option<PathBuf>,
+        /// Only print errors and warnings
+        #[arg(short, long)]
+        quiet: bool,
+        directory: Option<PathBuf>,
+    },
+    /// Print the content, type or size of an object
+    #[command(alias = "cat")]
+    CatFile(CatFileArgs),
+    /// Compute the object id of files, optionally storing them
+    #[command(alias = "hash")]
+    HashObject {
+        /// Write the object into the object database
+        #[arg(short = 'w')]
+        write: bool,
+        /// The type of object to create
+        #[arg(short = 't', value_name = "type", default_value = "blob")]
+        object_type: String,
+        /// Read the object from stdin
+        #[arg(long)]
+        stdin: bool,
+        #[arg(required_unless_present = "stdin")]
+        files: Vec<String>,
+    },
+    /// List the contents of a tree object
+    #[command(alias = "ls")]
+    LsTree(LsTreeArgs),
+    /// Create a tree object from the working tree
+    WriteTree,
+    /// Read trees into the index
+    ReadTree {
+        /// Merge one, two or three trees into the index rather than replacing it
+        #[arg(short = 'm')]
+        merge: bool,
+        /// Keep the index and add the tree under this directory
+        #[arg(long, value_name = "prefix", conflicts_with = "merge")]
+        prefix: Option<String>,
+        #[arg(value_name = "tree-ish", required = true)]
+        trees: Vec<String>,
+    },
+    /// Register file contents in the work tree in the index
+    UpdateIndex(UpdateIndexArgs),
+    /// Create a commit object from a tree
+    CommitTree {
+        tree: String,
+        /// Parent commit, repeated for a merge
+        #[arg(short = 'p')]
+        parents: Vec<String>,
+        /// Commit message, one paragraph each time it is given; read from stdin without it
+        #[arg(short = 'm')]
+        messages: Vec<String>,
+    },
+    /// List commits reachable from some revisions but not others, newest first
+    RevList {
+        /// Print how many commits there are instead
+        #[arg(long)]
+        count: bool,
+        /// Commits to start from; "^A" leaves out A and its history, "A..B" is "^A B", and
+        /// "A...B" is what only one of A and B has
+        #[arg(required = true)]
+        revisions: Vec<String>,
+    },
+    /// Show the commit history
+    Log(LogArgs),
+    /// Show the commit each line of a file was last changed in, with its author and date
+    Blame(BlameArgs),
+    /// Name commits after the refs they can be reached from, such as "tags/v1~2" or "main^2"
+    NameRev(NameRevArgs),
+    /// List the commits on <head> but not <upstream>, marking with "-" those whose change
+    /// upstream already has and with "+" the others
+    Cherry {
+        /// Show the subject of each commit too
+        #[arg(short, long)]
+        verbose: bool,
+        /// Show ids abbreviated to <n> hex digits
+        #[arg(long, value_name = "n", num_args = 0..=1, require_equals = true, default_missing_value = "7")]
+        abbrev: Option<usize>,
+        /// The branch to look for the changes in, by default the one the current branch tracks
+        upstream: Option<String>,
+        /// The branch with the commits to look up, by default HEAD
+        head: Option<String>,
+        /// Leave out the commits <limit> has as well
+        limit: Option<String>,
+    },
+    /// Find the best common ancestors of commits
+    MergeBase {
+        /// Print every best common ancestor rather than one
+        #[arg(short, long)]
+        all: bool,
+        /// Find the common ancestors of all the commits, as an octopus merge would
+        #[arg(long, conflicts_with = "is_ancestor")]
+        octopus: bool,
+        /// Exit with 0 if the first commit is an ancestor of the second, and 1 if not
+        #[arg(long)]
+        is_ancestor: bool,
+        #[arg(required = true)]
+        commits: Vec<String>,
+    },
+    /// Read, or point at another ref, a symbolic ref such as HEAD
+    SymbolicRef {
+        /// Exit with 1 instead of failing when the ref is detached
+        #[arg(short, long)]
+        quiet: bool,
+        /// Leave out "refs/heads/" and the like from the name shown
+        #[arg(long)]
+        short: bool,
+        name: String,
+        target: Option<String>,
+    },
+    /// Point a ref at an object, moving the branch a symbolic ref points at
+    UpdateRef {
+        /// Update a symbolic ref itself, detaching it
+        #[arg(long)]
+        no_deref: bool,
+        /// Delete the ref, only while it points at <new_value> when that is given
+        #[arg(short = 'd')]
+        delete: bool,
+        name: String,
+        #[arg(required_unless_present = "delete")]
+        new_value: Option<String>,
+        /// Only update the ref while it still points here
+        #[arg(conflicts_with = "delete")]
+        old_value: Option<String>,
+    },
+    /// Check that a name can be the name of a ref, printing it with --normalize
+    CheckRefFormat {
+        /// Print the name with the repeated slashes and the one at the start left out
+        #[arg(long, visible_alias = "print")]
+        normalize: bool,
+        /// Allow a name with no "/"
+        #[arg(long, overrides_with = "no_allow_onelevel")]
+        allow_onelevel: bool,
+        #[arg(long, overrides_with = "allow_onelevel")]
+        no_allow_onelevel: bool,
+        /// Allow one "*" in the name, as in a refspec
+        #[arg(long)]
+        refspec_pattern: bool,
+        /// Check a branch name and print it
+        #[arg(
+            long,
+            conflicts_with_all = ["normalize", "allow_onelevel", "no_allow_onelevel", "refspec_pattern"]
+        )]
+        branch: bool,
+        refname: String,
+    },
+    /// Print a variable as git works it out from the environment and the config, one of
+    /// GIT_AUTHOR_IDENT, GIT_COMMITTER_IDENT, GIT_EDITOR and GIT_PAGER
+    Var {
+        /// List the config and every variable
+        #[arg(
+            short,
+            conflicts_with = "variable",
+            required_unless_present = "variable"
+        )]
+        list: bool,
+        variable: Option<String>,
+    },
+    /// Compare two trees, or a commit with its first parent
+    DiffTree {
+        #[command(flatten)]
+        format: DiffFormatArgs,
+        /// Recurse into subtrees
+        #[arg(short = 'r')]
+        recursive: bool,
+        /// Don't print the id of the commit being compared
+        #[arg(long)]
+        no_commit_id: bool,
+        /// Show a commit without parents as adding all of its files
+        #[arg(long)]
+        root: bool,
+        /// One or two tree-ishes, followed by paths to limit the comparison to
+        #[arg(value_name = "tree-ish", required = true)]
+        args: Vec<String>,
+        #[arg(last = true)]
+        paths: Vec<String>,
+    },
+    /// Compare a tree with the work tree, or with the index
+    DiffIndex {
+        #[command(flatten)]
+        format: DiffFormatArgs,
+        /// Compare with the index and ignore the work tree
+        #[arg(long)]
+        cached: bool,
+        #[arg(value_name = "tree-ish")]
+        tree: String,
+        paths: Vec<String>,
+    },
+    /// Compare the index with the work tree
+    DiffFiles {
+        #[command(flatten)]
+        format: DiffFormatArgs,
+        paths: Vec<String>,
+    },
+    /// Merge two trees against their base without touching the index or work tree
+    MergeTree {
+        /// List conflicted paths without their modes and ids
+        #[arg(long)]
+        name_only: bool,
+        base: String,
+        ours: String,
+        theirs: String,
+    },
+    /// Apply "diff --git" patches, including GIT binary patches, to the files in the
+    /// work tree, or in the current directory outside of a repository
+    Apply {
+        /// Undo the patches instead
+        #[arg(short = 'R', long)]
+        reverse: bool,
+        /// Only check that the patches apply, without changing any files
+        #[arg(long)]
+        check: bool,
+        /// The patches to read, or standard input without any
+        #[arg(value_name = "patch")]
+        patches: Vec<String>,
+    },
+    /// Join another commit's history into the current branch, leaving any conflicts in the
+    /// index and the work tree to resolve and commit
+    Merge(MergeArgs),
+    /// Record the index as a new commit on the current branch, concluding a merge
+    Commit {
+        /// Commit message, one paragraph each time it is given
+        #[arg(short = 'm', value_name = "message")]
+        messages: Vec<String>,
+        /// Sign the commit with GPG, using this key or else user.signingkey or the committer
+        #[arg(short = 'S', long, value_name = "key-id", num_args = 0..=1, default_missing_value = "")]
+        gpg_sign: Option<String>,
+        /// Don't sign the commit, even when commit.gpgSign is set
+        #[arg(long, overrides_with = "gpg_sign")]
+        no_gpg_sign: bool,
+        /// Start the message being edited from this file instead of commit.template
+        #[arg(short = 't', long, value_name = "file")]
+        template: Option<String>,
+        /// Show the changes being committed below the message being edited
+        #[arg(short = 'v', long)]
+        verbose: bool,
+        /// Add a Signed-off-by trailer for the committer at the end of the message
+        #[arg(short = 's', long)]
+        signoff: bool,
+    },
+    /// Add trailers such as Signed-off-by to commit messages, or parse them out
+    InterpretTrailers(InterpretTrailersArgs),
+    /// Clean up a message from standard input the way commit does: strip whitespace at the ends
+    /// of lines, and blank lines at the start and end and beyond one between paragraphs
+    Stripspace {
+        /// Drop the comment lines too
+        #[arg(short = 's', long)]
+        strip_comments: bool,
+        /// Turn every line into a comment instead
+        #[arg(short = 'c', long, conflicts_with = "strip_comments")]
+        comment_lines: bool,
+    },
+    /// List, create or delete tags, which can be annotated with a message and signed
+    Tag(TagArgs),
+    /// Write out history as a stream for fast-import
+    FastExport(FastExportArgs),
+    /// Read a stream of history from fast-export or another tool into the repository
+    FastImport(FastImportArgs),
+    /// Rewrite history with paths removed, renamed or stripped of big files, updating the refs
+    Rewrite(RewriteArgs),
+    /// Create, list or delete refs that replace one object with another
+    Replace(ReplaceArgs),
+    /// Check the signatures of commits
+    VerifyCommit {
+        #[command(flatten)]
+        args: VerifyArgs,
+    },
+    /// Check the signatures of tags
+    VerifyTag {
+        #[command(flatten)]
+        args: VerifyArgs,
+    },
+    /// Show the branch, the changes staged and not staged for commit, unmerged paths and
+    /// untracked files
+    Status {
+        /// How to show untracked files: no, normal (untracked directories as themselves) or
+        /// all; -u alone means all
+        #[arg(
+            short = 'u',
+            long,
+            value_name = "mode",
+            num_args = 0..=1,
+            default_missing_value = "all"
+        )]
+        untracked_files: Option<String>,
+        #[arg(value_name = "pathspec")]
+        paths: Vec<String>,
+    },
+    /// Clone a repository over smart HTTP
+    Clone(CloneArgs),
+    /// Fetch refs and objects from a repository into refs/remotes/origin
+    Fetch {
+        #[command(flatten)]
+        progress: ProgressArgs,
+        #[command(flatten)]
+        shallow: ShallowArgs,
+        /// Deepen the history of a shallow repository by this many commits
+        #[arg(long, value_name = "depth", conflicts_with_all = ["depth", "shallow_since", "shallow_exclude"])]
+        deepen: Option<u32>,
+        /// Fetch all of the history a shallow repository is missing
+        #[arg(long, conflicts_with_all = ["depth", "deepen", "shallow_since", "shallow_exclude"])]
+        unshallow: bool,
+        repository: String,
+    },
+    /// Record the resolutions of merge conflicts and reuse them when the same conflicts come
+    /// up again, if rerere.enabled is set
+    Rerere {
+        #[command(subcommand)]
+        command: Option<RerereCommand>,
+    },
+    /// Write and read the multi-pack-index of the object directory
+    MultiPackIndex {
+        #[command(subcommand)]
+        command: MultiPackIndexCommand,
+    },
+    /// Write the commit-graph, which walks of history read instead of the commits
+    CommitGraph {
+        #[command(subcommand)]
+        command: CommitGraphCommand,
+    },
+    /// Move loose refs into the packed-refs file
+    PackRefs {
+        /// Pack every ref, not only tags
+        #[arg(long)]
+        all: bool,
+        /// Keep the loose refs that were packed
+        #[arg(long)]
+        no_prune: bool,
+    },
+    /// Remove the loose objects that no ref, HEAD, index entry or reflog reaches
+    Prune {
+        /// Only report what would be removed
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+        /// Report each object removed
+        #[arg(short, long)]
+        verbose: bool,
+        /// Only remove objects written before this date
+        #[arg(long, value_name = "date")]
+        expire: Option<String>,
+    },
+    /// Pack refs and reachable objects, write the commit-graph and prune old unreachable objects
+    Gc {
+        /// Prune unreachable objects written before this date, 2.weeks.ago unless
+        /// gc.pruneExpire says otherwise
+        #[arg(long, value_name = "date", num_args = 0..=1, require_equals = true)]
+        prune: Option<Option<String>>,
+        /// Don't prune any unreachable objects
+        #[arg(long, conflicts_with = "prune")]
+        no_prune: bool,
+        /// Only run when there are more loose objects than gc.auto or more packs than
+        /// gc.autoPackLimit
+        #[arg(long)]
+        auto: bool,
+        /// With --auto, run in the background, as gc.autoDetach does by default
+        #[arg(long, overrides_with = "no_detach")]
+        detach: bool,
+        #[arg(long, overrides_with = "detach")]
+        no_detach: bool,
+        #[command(flatten)]
+        progress: ProgressArgs,
+    },
+    /// Expire or delete the entries of reflogs
+    Reflog {
+        #[command(subcommand)]
+        command: ReflogCommand,
+    },
+    /// Run tasks that keep the repository quick to work in, now or on a schedule
+    Maintenance {
+        #[command(subcommand)]
+        command: MaintenanceCommand,
+    },
+    /// Run a git command in each repository a multi-valued config variable lists
+    ForEachRepo {
+        /// The variable listing the repositories
+        #[arg(long, value_name = "key")]
+        config: String,
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Switch to a branch, or detach HEAD at any other commit; with paths, check out files
+    /// from a tree-ish into the index and the work tree, or from the index
+    Checkout {
+        /// The branch or commit to switch to, or the commit or tree to take the files from
+        #[arg(value_name = "tree-ish", required_unless_present = "paths")]
+        tree_ish: Option<String>,
+        #[arg(last = true)]
+        paths: Vec<String>,
+    },
+    /// Restore files in the work tree from the index, or in the index from HEAD
+    Restore {
+        /// Restore the files from <tree> instead
+        #[arg(short, long, value_name = "tree")]
+        source: Option<String>,
+        /// Restore the files in the index, from HEAD unless --source is given
+        #[arg(short = 'S', long)]
+        staged: bool,
+        /// Restore the files in the work tree, which is the default without --staged
+        #[arg(short = 'W', long)]
+        worktree: bool,
+        paths: Vec<String>,
+    },
+    /// Set the changes in the work tree and the index aside, to bring them back later
+    Stash(StashArgs),
+    /// Switch to a branch, carrying along the changes in the work tree and the index
+    Switch {
+        /// Create <new-branch> at the commit given, or HEAD, and switch to it
+        #[arg(short, long, value_name = "new-branch", conflicts_with = "detach")]
+        create: Option<String>,
+        /// Switch to the commit given, or HEAD, rather than a branch
+        #[arg(short, long)]
+        detach: bool,
+        branch: Option<String>,
+    },
+    /// Add, list, lock, move and prune the work trees linked to the repository
+    Worktree {
+        #[command(subcommand)]
+        command: WorktreeCommand,
+    },
+    /// Show the submodules checked out in the work tree, run a command in each, or update
+    /// their urls; with no command, show them
+    Submodule {
+        /// Only report errors
+        #[arg(short, long, global = true)]
+        quiet: bool,
+        #[command(subcommand)]
+        command: Option<SubmoduleCommand>,
+    },
+    /// Add another project's history as a directory, merge or pull its changes into it, or
+    /// split the directory's history back out
+    Subtree {
+        /// The directory the subtree is in
+        #[arg(short = 'P', long, global = true, value_name = "prefix")]
+        prefix: Option<String>,
+        /// Only report errors
+        #[arg(short, long, global = true)]
+        quiet: bool,
+        #[command(subcommand)]
+        command: SubtreeCommand,
+    },
+    /// Display gitattributes information
+    CheckAttr {
+        /// Report all attributes set on the paths
+        #[arg(short = 'a', long)]
+        all: bool,
+        /// Attribute names followed by paths, unless paths are given after "--"
+        args: Vec<String>,
+        #[arg(last = true)]
+        paths: Vec<String>,
+    },
+    /// Get, set and list options in the repository's, the user's or the system's config file
+    Config(ConfigArgs),
+    // anything else is looked up as an alias
+    #[command(external_subcommand)]
+    Alias(Vec<String>),
+}
+
+// `git stash` on its own pushes
+#[derive(Debug, Args)]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct StashArgs {
+    #[command(subcommand)]
+    pub command: Option<StashCommand>,
+    #[command(flatten)]
+    pub push: StashPushArgs,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum StashCommand {
+    /// Save the local changes as a new stash entry and reset them to HEAD
+    Push(StashPushArgs),
+    /// List the stash entries, newest first
+    List,
+    /// Show the changes a stash entry records against the commit it was made on
+    Show {
+        /// Show the changes as a patch
+        #[arg(short = 'p', long = "patch")]
+        patch: bool,
+        /// Show a diffstat, which is the default
+        #[arg(long)]
+        stat: bool,
+        stash: Option<String>,
+    },
+    /// Apply a stash entry on top of the work tree, keeping it
+    Apply(StashApplyArgs),
+    /// Apply a stash entry on top of the work tree, and drop it unless that conflicts
+    Pop(StashApplyArgs),
+    /// Remove a stash entry
+    Drop {
+        /// Don't report the entry dropped
+        #[arg(short, long)]
+        quiet: bool,
+        stash: Option<String>,
+    },
+    /// Make a branch at the commit a stash entry was made on and apply the entry there,
+    /// index and all, dropping it if that goes cleanly
+    Branch {
+        branch: String,
+        stash: Option<String>,
+    },
+    /// Remove every stash entry
+    Clear,
+}
+
+#[derive(Debug, Args)]
+pub struct StashPushArgs {
+    /// Stash the untracked files too, and remove them from the work tree
+    #[arg(short = 'u', long)]
+    pub include_untracked: bool,
+    /// Leave the changes already in the index in place
+    #[arg(short = 'k', long)]
+    pub keep_index: bool,
+    /// Describe the entry with this message
+    #[arg(short, long)]
+    pub message: Option<String>,
+    /// Don't report the entry saved
+    #[arg(short, long)]
+    pub quiet: bool,
+    /// Only stash the changes to the files these match, leaving the others as they are
+    #[arg(value_name = "pathspec")]
+    pub pathspecs: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct StashApplyArgs {
+    /// Bring back the changes that were in the index as well
+    #[arg(long)]
+    pub index: bool,
+    /// Don't show the status afterwards
+    #[arg(short, long)]
+    pub quiet: bool,
+    pub stash: Option<String>,
+}
+
+// with no action, one argument gets a value and two set it
+#[derive(Debug, Args)]
+pub struct ConfigArgs {
+    /// Use the system's config file rather than the repository's
+    #[arg(long, group = "location")]
+    pub system: bool,
+    /// Use the user's config file rather than the repository's
+    #[arg(long, group = "location")]
+    pub global: bool,
+    /// Use the repository's config file, which is the default for writing
+    #[arg(long, group = "location")]
+    pub local: bool,
+    /// Use the config file at <file>
+    #[arg(short, long, value_name = "file", group = "location")]
+    pub file: Option<PathBuf>,
+    /// Get the last value of <name>, or the last one matching [<value-pattern>]
+    #[arg(long, group = "action")]
+    pub get: bool,
+    /// Get every value of <name>, or those matching [<value-pattern>]
+    #[arg(long, group = "action")]
+    pub get_all: bool,
+    /// Add <value> to <name> without replacing the values it has
+    #[arg(long, group = "action")]
+    pub add: bool,
+    /// Remove <name>, which must have just one value, or one matching [<value-pattern>]
+    #[arg(long, group = "action")]
+    pub unset: bool,
+    /// Remove every value of <name>, or those matching [<value-pattern>]
+    #[arg(long, group = "action")]
+    pub unset_all: bool,
+    /// List every variable set, with its value
+    #[arg(short, long, group = "action")]
+    pub list: bool,
+    /// Open the config file in the editor
+    #[arg(short, long, group = "action")]
+    pub edit: bool,
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum WorktreeCommand {
+    /// Check a commit out into a new work tree at <path>, on a new branch named after it unless
+    /// a branch or --detach is given
+    Add {
+        /// Create this branch at the commit and check it out
+        #[arg(short = 'b', value_name = "new-branch", conflicts_with = "detach")]
+        branch: Option<String>,
+        /// Check the commit out with HEAD detached, even if it's a branch
+        #[arg(long)]
+        detach: bool,
+        path: PathBuf,
+        #[arg(value_name = "commit-ish")]
+        commit: Option<String>,
+    },
+    /// List the work trees, the main one first
+    List {
+        /// Print one attribute per line, for scripts
+        #[arg(long)]
+        porcelain: bool,
+    },
+    /// Keep a work tree from being pruned or moved, as when it's on a disk that isn't
+    /// always mounted
+    Lock {
+        /// Why it is locked
+        #[arg(long, value_name = "string")]
+        reason: Option<String>,
+        worktree: String,
+    },
+    /// Let a work tree be pruned or moved again
+    Unlock { worktree: String },
+    /// Move a work tree, into <new-path> if that is a directory
+    Move {
+        /// Given twice, move it even if it is locked
+        #[arg(short, long, action = clap::ArgAction::Count)]
+        force: u8,
+        worktree: String,
+        new_path: PathBuf,
+    },
+    /// Remove what is kept in $GIT_DIR/worktrees for work trees that are gone
+    Prune {
+        /// Only report what would be removed
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+        /// Report what is removed
+        #[arg(short, long)]
+        verbose: bool,
+        /// Only prune work trees gone since before this date
+        #[arg(long, value_name = "date")]
+        expire: Option<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SubmoduleCommand {
+    /// Show the commit each submodule is at, marked "-" when it isn't checked out, "+" when
+    /// that isn't the commit the index has and "U" when it has conflicts
+    Status {
+        /// Show the commits the index has rather than the ones checked out
+        #[arg(long)]
+        cached: bool,
+        /// Show the submodules of the submodules too
+        #[arg(long)]
+        recursive: bool,
+        #[arg(value_name = "path")]
+        paths: Vec<String>,
+    },
+    /// Run a shell command in each submodule that is checked out, with $name, $sm_path,
+    /// $displaypath, $sha1 and $toplevel set
+    Foreach {
+        /// Run it in the submodules of the submodules too
+        #[arg(long)]
+        recursive: bool,
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    /// Set the url of each submodule in use, in the config and in the submodule's remote, to
+    /// the one .gitmodules has now
+    Sync {
+        /// Sync the submodules of the submodules too
+        #[arg(long)]
+        recursive: bool,
+        #[arg(value_name = "path")]
+        paths: Vec<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SubtreeCommand {
+    /// Add <commit>, or <ref> fetched from <repository>, as the directory, merging in its
+    /// history
+    Add {
+        #[command(flatten)]
+        merge: SubtreeMergeArgs,
+        #[arg(required = true, num_args = 1..=2, value_name = "commit | repository ref")]
+        args: Vec<String>,
+    },
+    /// Merge the changes up to <commit> into the directory
+    Merge {
+        #[command(flatten)]
+        merge: SubtreeMergeArgs,
+        commit: String,
+    },
+    /// Fetch <ref> from <repository> and merge its changes into the directory
+    Pull {
+        #[command(flatten)]
+        merge: SubtreeMergeArgs,
+        #[arg(value_name = "repository")]
+        repository: String,
+        #[arg(value_name = "ref")]
+        reference: String,
+    },
+    /// Make a history of the directory alone from that of <commit>, or HEAD, and print its
+    /// last commit
+    Split {
+        /// Put this before the message of every commit made
+        #[arg(long, value_name = "annotation")]
+        annotate: Option<String>,
+        /// Create this branch at the history made, or update it when the history has it
+        #[arg(short, long, value_name = "branch")]
+        branch: Option<String>,
+        /// Split the whole history again rather than picking up from earlier --rejoin merges
+        #[arg(long)]
+        ignore_joins: bool,
+        /// A history of the directory alone to connect the one made to
+        #[arg(long, value_name = "commit")]
+        onto: Option<String>,
+        /// Merge the history made back into HEAD
+        #[arg(long)]
+        rejoin: bool,
+        /// Merge it back in as a single commit
+        #[arg(long, requires = "rejoin")]
+        squash: bool,
+        /// Message for the merge commit
+        #[arg(short, long, value_name = "message", requires = "rejoin")]
+        message: Option<String>,
+        commit: Option<String>,
+    },
+}
+
+#[derive(Debug, Args)]
+pub struct SubtreeMergeArgs {
+    /// Bring the changes in as a single commit rather than with their history
+    #[arg(long)]
+    pub squash: bool,
+    /// Message for the merge commit
+    #[arg(short, long, value_name = "message")]
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum RerereCommand {
+    /// Show the paths whose conflicts rerere is recording the resolution of
+    Status,
+    /// Forget the conflicts of the merge going on, keeping only recorded resolutions
+    Clear,
+    /// Forget the recorded resolutions of the conflicts in these paths
+    Forget { paths: Vec<String> },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum MultiPackIndexCommand {
+    /// Write a multi-pack-index covering every pack
+    Write,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CommitGraphCommand {
+    /// Write a commit-graph of the commits the refs and HEAD reach
+    Write,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum MaintenanceCommand {
+    /// Run the tasks given, or else those enabled by maintenance.<task>.enabled
+    Run(MaintenanceRunArgs),
+    /// Register the repository and schedule maintenance of every registered repository
+    Start {
+        /// How to schedule it: auto, crontab or systemd-timer
+        #[arg(long, value_name = "scheduler")]
+        scheduler: Option<String>,
+    },
+    /// Remove the maintenance schedule, leaving repositories registered
+    Stop,
+    /// Add the repository to those scheduled maintenance runs in
+    Register,
+    /// Remove the repository from those scheduled maintenance runs in
+    Unregister,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ReflogCommand {
+    /// Remove the entries older than gc.reflogExpire, or the ones the tip of the ref can't
+    /// reach older than gc.reflogExpireUnreachable
+    Expire(ReflogExpireArgs),
+    /// Remove the entries given as <ref>@{<n>} or <ref>@{<date>}
+    Delete {
+        #[command(flatten)]
+        options: ReflogOptions,
+        #[arg(required = true, value_name = "ref@{specifier}")]
+        entries: Vec<String>,
+    },
+}
+
+#[derive(Debug, Args)]
+pub struct ReflogExpireArgs {
+    #[command(flatten)]
+    pub options: ReflogOptions,
+    /// Remove the entries older than this date
+    #[arg(long, value_name = "time")]
+    pub expire: Option<String>,
+    /// Remove the entries older than this date that the tip of the ref can't reach
+    #[arg(long, value_name = "time")]
+    pub expire_unreachable: Option<String>,
+    /// Remove the entries with commits that are gone or broken
+    #[arg(long)]
+    pub stale_fix: bool,
+    /// Expire the reflogs of every ref
+    #[arg(long, conflicts_with = "refs")]
+    pub all: bool,
+    /// With --all, only the reflogs of the current work tree
+    #[arg(long, requires = "all")]
+    pub single_worktree: bool,
+    pub refs: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct ReflogOptions {
+    /// Make each entry kept start where the one kept before it ends
+    #[arg(long)]
+    pub rewrite: bool,
+    /// Point the ref where the newest entry kept leaves it
+    #[arg(long)]
+    pub updateref: bool,
+    /// Only report what would be removed, with --verbose
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+    /// Report each entry, kept or removed
+    #[arg(long)]
+    pub verbose: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct MaintenanceRunArgs {
+    /// Run this task: prune, loose-objects, gc, commit-graph or pack-refs; may be repeated
+    #[arg(long = "task", value_name = "task")]
+    pub tasks: Vec<String>,
+    /// Run only the tasks scheduled this often or more: hourly, daily or weekly
+    #[arg(long, value_name = "frequency")]
+    pub schedule: Option<String>,
+    #[command(flatten)]
+    pub progress: ProgressArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct UpdateIndexArgs {
+    /// Add files that are not in the index yet
+    #[arg(long)]
+    pub add: bool,
+    /// Remove files that are in the index but no longer in the work tree
+    #[arg(long)]
+    pub remove: bool,
+    /// Remove the files from the index even if they are still in the work tree
+    #[arg(long)]
+    pub force_remove: bool,
+    /// Put an entry into the index as it is, without looking at the work tree
+    #[arg(long, value_name = "mode>,<object>,<path")]
+    pub cacheinfo: Vec<String>,
+    /// Set (+x) or clear (-x) the executable bit of the files
+    #[arg(long, value_name = "(+|-)x", allow_hyphen_values = true)]
+    pub chmod: Option<String>,
+    /// Check every entry against the work tree again, updating the stat data of unchanged files
+    #[arg(long)]
+    pub refresh: bool,
+    /// Carry on when --refresh finds files that need updating
+    #[arg(short = 'q')]
+    pub quiet: bool,
+    /// Keep an untracked cache in the index for status to reuse
+    #[arg(long, overrides_with = "no_untracked_cache")]
+    pub untracked_cache: bool,
+    /// Drop the untracked cache from the index
+    #[arg(long)]
+    pub no_untracked_cache: bool,
+    /// Keep what the core.fsmonitor hook reports in the index
+    #[arg(long, overrides_with = "no_fsmonitor")]
+    pub fsmonitor: bool,
+    /// Drop what the core.fsmonitor hook reported from the index
+    #[arg(long)]
+    pub no_fsmonitor: bool,
+    /// Write the index as the changes to a shared index, starting a new shared index
+    #[arg(long, overrides_with = "no_split_index")]
+    pub split_index: bool,
+    /// Write the whole index in one file again
+    #[arg(long)]
+    pub no_split_index: bool,
+    pub paths: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct LsTreeArgs {
+    /// Recurse into subtrees
+    #[arg(short = 'r')]
+    pub recursive: bool,
+    /// Show only trees
+    #[arg(short = 'd')]
+    pub trees_only: bool,
+    /// Show the size of blobs
+    #[arg(short = 'l', long, conflicts_with = "name_only")]
+    pub long: bool,
+    /// List only file names
+    #[arg(long, alias = "name-status")]
+    pub name_only: bool,
+    /// Show paths from the top of the work tree rather than the current directory
+    #[arg(long)]
+    pub full_name: bool,
+    pub tree_ish: String,
+    /// Only list these paths, or what is under them
+    pub paths: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct CatFileArgs {
+    /// Show the object's type
+    #[arg(short = 't', group = "mode")]
+    pub show_type: bool,
+    /// Show the object's size
+    #[arg(short = 's', group = "mode")]
+    pub size: bool,
+    /// Exit with zero status if the object exists and non-zero otherwise
+    #[arg(short = 'e', group = "mode")]
+    pub exists: bool,
+    /// Pretty-print the object's content
+    #[arg(short = 'p', group = "mode")]
+    pub pretty: bool,
+    /// Print the objects named on stdin with their type and size
+    #[arg(long, group = "mode", value_name = "format", num_args = 0..=1, require_equals = true, default_missing_value = "")]
+    pub batch: Option<String>,
+    /// Print the type and size of the objects named on stdin
+    #[arg(long, group = "mode", value_name = "format", num_args = 0..=1, require_equals = true, default_missing_value = "")]
+    pub batch_check: Option<String>,
+    /// The object, preceded by the type it must have when no option is given
+    #[arg(
+        value_name = "[type] object",
+        required_unless_present_any = ["batch", "batch_check"],
+        conflicts_with_all = ["batch", "batch_check"],
+        num_args = 1..=2
+    )]
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct CloneArgs {
+    #[command(flatten)]
+    pub progress: ProgressArgs,
+    /// Make a bare repository, named "<repo>.git" by default, without a work tree
+    #[arg(long)]
+    pub bare: bool,
+    /// Make a bare repository that copies every ref of the remote under the same name
+    #[arg(long)]
+    pub mirror: bool,
+    /// Check out this branch, or this tag detached, instead of the remote's HEAD
+    #[arg(short, long)]
+    pub branch: Option<String>,
+    /// Fetch only the history of the branch that is checked out
+    #[arg(long)]
+    pub single_branch: bool,
+    #[command(flatten)]
+    pub shallow: ShallowArgs,
+    pub repository: String,
+    pub directory: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct NameRevArgs {
+    /// Print the names alone, without the revisions they are for
+    #[arg(long)]
+    pub name_only: bool,
+    /// Name commits after tags alone
+    #[arg(long)]
+    pub tags: bool,
+    /// Name commits after the refs matching the pattern alone
+    #[arg(long = "refs", value_name = "pattern")]
+    pub patterns: Vec<String>,
+    /// Don't name commits after the refs matching the pattern
+    #[arg(long, value_name = "pattern")]
+    pub exclude: Vec<String>,
+    /// Copy standard input to standard output, naming every object id in it
+    #[arg(long, visible_alias = "stdin")]
+    pub annotate_stdin: bool,
+    /// Fail on a revision that can't be named instead of printing "undefined" for it
+    #[arg(long)]
+    pub no_undefined: bool,
+    /// With --no-undefined, print the abbreviated object id of a commit that can't be named
+    #[arg(long)]
+    pub always: bool,
+    /// The revisions to name
+    pub revisions: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct InterpretTrailersArgs {
+    /// Rewrite the files instead of writing to standard output
+    #[arg(long)]
+    pub in_place: bool,
+    /// Leave out the trailers with an empty value
+    #[arg(long)]
+    pub trim_empty: bool,
+    /// Output the trailers alone
+    #[arg(long)]
+    pub only_trailers: bool,
+    /// Output the trailers the input has, without adding any
+    #[arg(long, conflicts_with = "trailer")]
+    pub only_input: bool,
+    /// Join the lines a trailer value goes on over
+    #[arg(long)]
+    pub unfold: bool,
+    /// Same as --only-trailers --only-input --unfold
+    #[arg(long, conflicts_with = "trailer")]
+    pub parse: bool,
+    /// Don't take a "---" line as the end of the message
+    #[arg(long)]
+    pub no_divider: bool,
+    #[command(flatten)]
+    pub trailers: TrailerArgs,
+    /// The files with the messages; standard input when there are none
+    pub files: Vec<PathBuf>,
+}
+
+// --trailer, and --where, --if-exists and --if-missing, which only go for the trailers given
+// after them, in the order they were given
+#[derive(Clone, Debug, Default)]
+pub struct TrailerArgs(pub Vec<TrailerArg>);
+
+// one of the trailer options; a rule is None for --no-where and the like
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TrailerArg {
+    Trailer(String),
+    Where(Option<String>),
+    IfExists(Option<String>),
+    IfMissing(Option<String>),
+}
+
+impl TrailerArgs {
+    // each option's id and the help for it
+    const OPTIONS: [(&'static str, &'static str); 7] = [
+        (
+            "trailer",
+            "A trailer to add, as token=value or token: value",
+        ),
+        (
+            "where",
+            "Where the trailers after it go: end, start, after or before the last trailer with \
+             the same token; trailer.where, or end, by default",
+        ),
+        (
+            "no-where",
+            "Put the trailers after it where trailer.where says",
+        ),
+        (
+            "if-exists",
+            "What is done with the trailers after it when one with the same token is there: \
+             addIfDifferentNeighbor, addIfDifferent, add, replace or doNothing; \
+             trailer.ifExists, or addIfDifferentNeighbor, by default",
+        ),
+        (
+            "no-if-exists",
+            "Do what trailer.ifExists says with the trailers after it",
+        ),
+        (
+            "if-missing",
+            "What is done with the trailers after it when none with the same token is there: \
+             add or doNothing; trailer.ifMissing, or add, by default",
+        ),
+        (
+            "no-if-missing",
+            "Do what trailer.ifMissing says with the trailers after it",
+        ),
+    ];
+}
+
+impl FromArgMatches for TrailerArgs {
+    fn from_arg_matches(matches: &ArgMatches) -> Result<Self, clap::Error> {
+        let mut args = vec![];
+        for (id, _) in Self::OPTIONS {
+            let Some(indices) = matches.indices_of(id) else {
+                continue;
+            };
+            // the --no-* options go back to the configured rule
+            let values: Vec<Option<String>> = match id.starts_with("no-") {
+                true => vec![None; indices.len()],
+                false => matches
+                    .get_many::<String>(id)
+                    .into_iter()
+                    .flatten()
+                    .map(|value| Some(value.clone()))
+                    .collect(),
+            };
+            for (index, value) in indices.zip(values) {
+                let arg = match id.trim_start_matches("no-") {
+                    "trailer" => TrailerArg::Trailer(value.unwrap_or_default()),
+                    "where" => TrailerArg::Where(value),
+                    "if-exists" => TrailerArg::IfExists(value),
+                    _ => TrailerArg::IfMissing(value),
+                };
+                args.push((index, arg));
+            }
+        }
+        args.sort_by_key(|(index, _)| *index);
+        Ok(TrailerArgs(args.into_iter().map(|(_, arg)| arg).collect()))
+    }
+
+    fn update_from_arg_matches(&mut self, matches: &ArgMatches) -> Result<(), clap::Error> {
+        *self = Self::from_arg_matches(matches)?;
+        Ok(())
+    }
+}
+
+impl Args for TrailerArgs {
+    fn augment_args(command: clap::Command) -> clap::Command {
+        Self::OPTIONS
+            .into_iter()
+            .fold(command, |command, (id, help)| {
+                let arg = Arg::new(id).long(id).help(help);
+                command.arg(match id {
+                    "trailer" => arg
+                        .value_name("token[(=|:)value]")
+                        .action(ArgAction::Append),
+                    "where" => arg.value_name("placement").action(ArgAction::Append),
+                    "if-exists" | "if-missing" => {
+                        arg.value_name("action").action(ArgAction::Append)
+                    }
+                    _ => arg.action(ArgAction::Count),
+                })
+            })
+    }
+
+    fn augment_args_for_update(command: clap::Command) -> clap::Command {
+        Self::augment_args(command)
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct TagArgs {
+    /// Make an annotated tag, with a message and who tagged it when
+    #[arg(short = 'a', long)]
+    pub annotate: bool,
+    /// Make an annotated tag signed in the gpg.format, using user.signingkey or else the
+    /// signer's default key
+    #[arg(short = 's', long)]
+    pub sign: bool,
+    /// Make an annotated tag signed with this key
+    #[arg(short = 'u', long, value_name = "key-id")]
+    pub local_user: Option<String>,
+    /// Tag message, one paragraph each time it is given; makes the tag annotated
+    #[arg(short = 'm', long = "message", value_name = "message")]
+    pub messages: Vec<String>,
+    /// Replace a tag that already exists
+    #[arg(short = 'f', long)]
+    pub force: bool,
+    /// Delete the tags
+    #[arg(short = 'd', long, conflicts_with = "list")]
+    pub delete: bool,
+    /// List the tags, only those matching a pattern when some are given
+    #[arg(short = 'l', long)]
+    pub list: bool,
+    /// Check the signatures of the tags
+    #[arg(short = 'v', long, conflicts_with_all = ["delete", "list"])]
+    pub verify: bool,
+    /// The tag's name and the object it points at, HEAD by default; the names to delete or
+    /// verify; or the patterns to list
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct FastExportArgs {
+    /// Export every ref, and HEAD
+    #[arg(long)]
+    pub all: bool,
+    /// What to do with signed tags: "abort" (the default), "verbatim", "warn", "warn-strip"
+    /// or "strip"
+    #[arg(long, value_name = "mode")]
+    pub signed_tags: Option<String>,
+    /// What to do with tags of objects that weren't exported: "abort" (the default) or "drop"
+    #[arg(long, value_name = "mode")]
+    pub tag_of_filtered_object: Option<String>,
+    /// Refer to blobs by id instead of writing them out
+    #[arg(long)]
+    pub no_data: bool,
+    /// Give every commit's whole tree instead of how it changed
+    #[arg(long)]
+    pub full_tree: bool,
+    /// Give the tags marks too
+    #[arg(long)]
+    pub mark_tags: bool,
+    /// Write the marks of the commits exported to this file when done
+    #[arg(long, value_name = "file")]
+    pub export_marks: Option<PathBuf>,
+    /// Read the marks of commits exported before, which aren't exported again
+    #[arg(long, value_name = "file")]
+    pub import_marks: Option<PathBuf>,
+    /// Commits to export, as for rev-list, under the refs they were reached from
+    pub revisions: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct FastImportArgs {
+    /// Don't print statistics when done
+    #[arg(long)]
+    pub quiet: bool,
+    /// Print statistics when done, the default
+    #[arg(long, overrides_with = "quiet")]
+    pub stats: bool,
+    /// Update branches even when that loses commits
+    #[arg(long)]
+    pub force: bool,
+    /// Fail unless the stream ends with "done"
+    #[arg(long)]
+    pub done: bool,
+    /// How dates in idents are written: "raw" (the default) or "now"
+    #[arg(long, value_name = "format")]
+    pub date_format: Option<String>,
+    /// Write the marks to this file when done
+    #[arg(long, value_name = "file")]
+    pub export_marks: Option<PathBuf>,
+    /// Read the marks of an earlier import from this file
+    #[arg(long, value_name = "file")]
+    pub import_marks: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct RewriteArgs {
+    /// Keep only this file or directory; can be given more than once
+    #[arg(long = "path", value_name = "path")]
+    pub paths: Vec<String>,
+    /// Remove the paths given with --path instead of keeping only them
+    #[arg(long)]
+    pub invert_paths: bool,
+    /// Move the files under one directory to another, as <old>:<new>
+    #[arg(long = "path-rename", value_name = "old:new")]
+    pub renames: Vec<String>,
+    /// Remove files bigger than this, with an optional k, m or g suffix
+    #[arg(long, value_name = "size")]
+    pub strip_blobs_bigger_than: Option<String>,
+    /// Keep commits the filters leave with no changes of their own
+    #[arg(long)]
+    pub keep_empty: bool,
+    /// Overwrite the backup of an earlier rewrite under refs/original/
+    #[arg(short = 'f', long)]
+    pub force: bool,
+    /// Refs to rewrite; every ref when none are given
+    pub refs: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct ReplaceArgs {
+    /// Replace a replacement that already exists
+    #[arg(short = 'f', long)]
+    pub force: bool,
+    /// Delete the replacements of the objects
+    #[arg(short = 'd', long, conflicts_with_all = ["list", "graft"])]
+    pub delete: bool,
+    /// List the objects replaced, only those matching a pattern when one is given
+    #[arg(short = 'l', long)]
+    pub list: bool,
+    /// Replace a commit with one that has other parents
+    #[arg(short = 'g', long, conflicts_with = "list")]
+    pub graft: bool,
+    /// How to list: "short" (the default), "medium" or "long"
+    #[arg(long, value_name = "format")]
+    pub format: Option<String>,
+    /// The object and its replacement; the commit and its new parents; the objects whose
+    /// replacements to delete; or the pattern to list
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct VerifyArgs {
+    /// Print the content of each object that was signed too
+    #[arg(short = 'v', long)]
+    pub verbose: bool,
+    /// Print the signer's output for scripts instead of its report
+    #[arg(long)]
+    pub raw: bool,
+    #[arg(required = true)]
+    pub objects: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct MergeArgs {
+    /// Message for the merge commit
+    #[arg(short = 'm', value_name = "message")]
+    pub message: Option<String>,
+    /// How to merge: "ort" (also "recursive"), the default for one commit, "octopus", the
+    /// default for several, or "ours", which keeps the current tree as it is
+    #[arg(short = 's', long, value_name = "strategy")]
+    pub strategy: Option<String>,
+    /// An option for the strategy: "ours" or "theirs" settles conflicting changes to a file in
+    /// favor of that side, and "subtree=<path>" merges their tree in as the directory <path>
+    #[arg(short = 'X', long, value_name = "option")]
+    pub strategy_option: Vec<String>,
+    /// Make a merge commit even when the branch could be fast-forwarded
+    #[arg(long)]
+    pub no_ff: bool,
+    /// Leave the merged changes in the index and the work tree to commit as an ordinary commit
+    #[arg(long, conflicts_with = "no_ff")]
+    pub squash: bool,
+    /// Give up the merge in progress, putting the index and the work tree back as HEAD has them
+    #[arg(long, exclusive = true)]
+    pub abort: bool,
+    #[arg(required_unless_present = "abort")]
+    pub commits: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct LogArgs {
+    /// Show each commit on one line, as its abbreviated id and subject
+    #[arg(long)]
+    pub oneline: bool,
+    /// Show commits as one of the presets oneline, short, medium, full, fuller and raw, or
+    /// "format:<string>" with placeholders like %H, %h, %an, %ad, %s and %d
+    #[arg(long, value_name = "format", num_args = 0..=1, require_equals = true, default_missing_value = "medium")]
+    pub pretty: Option<String>,
+    /// Like --pretty, but a format string ends each commit with a newline ("tformat:")
+    #[arg(long, value_name = "format")]
+    pub format: Option<String>,
+    /// Show abbreviated commit ids
+    #[arg(long)]
+    pub abbrev_commit: bool,
+    /// Show the refs pointing at each commit
+    #[arg(long)]
+    pub decorate: bool,
+    /// Draw the history as a graph beside the commits
+    #[arg(long)]
+    pub graph: bool,
+    /// Show at most this many commits
+    #[arg(short = 'n', long, value_name = "number")]
+    pub max_count: Option<usize>,
+    /// Follow the history of the one path given back across renames
+    #[arg(long)]
+    pub follow: bool,
+    /// Only show commits that change how many times the string appears in a file
+    #[arg(short = 'S', value_name = "string")]
+    pub pickaxe: Option<String>,
+    /// Only show commits with an added or removed line that the regex matches
+    #[arg(short = 'G', value_name = "regex", conflicts_with = "pickaxe")]
+    pub pickaxe_grep: Option<String>,
+    /// Take the string of -S as an extended regex
+    #[arg(long, requires = "pickaxe")]
+    pub pickaxe_regex: bool,
+    /// Check the signature of each signed commit and show the report on it
+    #[arg(long)]
+    pub show_signature: bool,
+    #[command(flatten)]
+    pub color: ColorArgs,
+    /// Commits to start from, HEAD by default, as for rev-list
+    pub revisions: Vec<String>,
+    /// Only show commits that change these paths
+    #[arg(last = true)]
+    pub paths: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct BlameArgs {
+    /// Only blame the lines in the range: "<start>,<end>", "<start>,+<count>" or
+    /// "<start>,-<count>", where <start> and <end> are line numbers or /regex/, or
+    /// ":<regex>" for a function. Can be given more than once
+    #[arg(short = 'L', value_name = "range")]
+    pub ranges: Vec<String>,
+    /// Ignore whitespace when comparing a line with its parent's
+    #[arg(short = 'w')]
+    pub ignore_whitespace: bool,
+    /// Follow lines moved within the file that have more than <score> alphanumeric
+    /// characters, given as -M=<score>, 20 by default
+    #[arg(short = 'M', value_name = "score", num_args = 0..=1, require_equals = true, default_missing_value = "")]
+    pub moves: Option<String>,
+    /// Like -M, and follow lines copied from the other files the commit changes, 40
+    /// characters by default; twice to also look in every file of the commit that creates the
+    /// file, and three times in every file of every commit
+    #[arg(short = 'C', value_name = "score", num_args = 0..=1, require_equals = true, default_missing_value = "")]
+    pub copies: Vec<String>,
+    /// Show the blame in a form for scripts: a header with the commit, the line numbers in
+    /// it and in the file, and how many lines follow from it, then the details of the commit
+    /// the first time it's seen, then the line after a tab
+    #[arg(long)]
+    pub porcelain: bool,
+    /// Like --porcelain, with the details of the commit after every line's header
+    #[arg(long)]
+    pub line_porcelain: bool,
+    /// The revision to blame the file at, by default the work tree on top of HEAD, and the
+    /// file
+    #[arg(value_name = "rev")]
+    pub args: Vec<String>,
+    /// The file, after "--"
+    #[arg(last = true, value_name = "file")]
+    pub file: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct DiffFormatArgs {
+    /// Show the modes, ids and kind of each change, the default
+    #[arg(long, group = "format")]
+    pub raw: bool,
+    /// Show only the names of changed files
+    #[arg(long, group = "format")]
+    pub name_only: bool,
+    /// Show the names of changed files and the kind of change
+    #[arg(long, group = "format")]
+    pub name_status: bool,
+    /// Show how many lines each file gains and loses, with a graph of them
+    #[arg(long, group = "format")]
+    pub stat: bool,
+    /// Show how many lines each file gains and loses, in a form for scripts
+    #[arg(long, group = "format")]
+    pub numstat: bool,
+    /// Show the changes as a patch
+    #[arg(short = 'p', long, group = "format")]
+    pub patch: bool,
+    /// Show the changes to binary files in the patch as well, in a form that can be applied
+    #[arg(long)]
+    pub binary: bool,
+    /// How the patch finds the lines that changed: "myers" (the default, also "default"),
+    /// "minimal", "patience" or "histogram"
+    #[arg(long, value_name = "algorithm")]
+    pub diff_algorithm: Option<String>,
+    /// Show the words that changed in the patch instead of whole lines, marked as plain text,
+    /// in color, in a form for scripts, or not at all ("none")
+    #[arg(long, value_name = "mode", num_args = 0..=1, require_equals = true, default_missing_value = "plain")]
+    pub word_diff: Option<String>,
+    /// What makes up a word for --word-diff, instead of a run of anything but whitespace
+    #[arg(long, value_name = "regex")]
+    pub word_diff_regex: Option<String>,
+    /// The same as --word-diff=color, optionally with a --word-diff-regex
+    #[arg(long, value_name = "regex", num_args = 0..=1, require_equals = true)]
+    pub color_words: Option<Option<String>>,
+    #[command(flatten)]
+    pub color: ColorArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct ColorArgs {
+    /// Show the output in color: "always" (the default), "never", or "auto" for only when it
+    /// goes to a terminal
+    #[arg(long, value_name = "when", num_args = 0..=1, require_equals = true, default_missing_value = "always", overrides_with = "no_color")]
+    pub color: Option<String>,
+    /// Don't show the output in color
+    #[arg(long, overrides_with = "color")]
+    pub no_color: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ProgressArgs {
+    /// Don't report progress
+    #[arg(short, long)]
+    pub quiet: bool,
+    /// Report progress even when stderr is not a terminal
+    #[arg(long, overrides_with = "quiet")]
+    pub progress: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ShallowArgs {
+    /// Fetch only this many commits from each tip
+    #[arg(long, value_name = "depth", value_parser = clap::value_parser!(u32).range(1..))]
+    pub depth: Option<u32>,
+    /// Fetch only the commits made after this date
+    #[arg(long, value_name = "date", conflicts_with = "depth")]
+    pub shallow_since: Option<String>,
+    /// Fetch no commits reachable from this ref; can be given more than once
+    #[arg(long, value_name = "ref", conflicts_with = "depth")]
+    pub shallow_exclude: Vec<String>,
+}
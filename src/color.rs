@@ -0,0 +1,97 @@
+use std::{
+    env,
+    io::{self, IsTerminal},
+};
+
+use crate::{
+    config::{self, Config},
+    error::{Error, Result},
+};
+
+// the escape codes git writes, each color ended by RESET
+pub const RESET: &str = "\x1b[m";
+pub const BOLD: &str = "\x1b[1m";
+pub const RED: &str = "\x1b[31m";
+pub const GREEN: &str = "\x1b[32m";
+pub const YELLOW: &str = "\x1b[33m";
+pub const CYAN: &str = "\x1b[36m";
+pub const BOLD_RED: &str = "\x1b[1;31m";
+pub const BOLD_GREEN: &str = "\x1b[1;32m";
+pub const BOLD_YELLOW: &str = "\x1b[1;33m";
+pub const BOLD_MAGENTA: &str = "\x1b[1;35m";
+pub const BOLD_CYAN: &str = "\x1b[1;36m";
+pub const BG_RED: &str = "\x1b[41m";
+
+// when output is colored, as --color and the color.* settings say
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum When {
+    Always,
+    Never,
+    // only when the output goes to a terminal that can show colors
+    Auto,
+}
+
+impl When {
+    // the argument of --color
+    pub fn parse(arg: &str) -> Result<When> {
+        When::named(arg).ok_or_else(|| {
+            Error::Usage(
+                "error: option `color' expects \"always\", \"auto\", or \"never\"".to_string(),
+            )
+        })
+    }
+
+    // a color.* setting, where a boolean true means auto and one without a value is true
+    pub fn from_config(value: Option<&str>) -> Option<When> {
+        let Some(value) = value else {
+            return Some(When::Auto);
+        };
+        When::named(value).or_else(|| match config::parse_bool(value)? {
+            true => Some(When::Auto),
+            false => Some(When::Never),
+        })
+    }
+
+    fn named(name: &str) -> Option<When> {
+        match name.to_lowercase().as_str() {
+            "always" => Some(When::Always),
+            "never" => Some(When::Never),
+            "auto" => Some(When::Auto),
+            _ => None,
+        }
+    }
+
+    // whether standard output is colored
+    pub fn enabled(self) -> bool {
+        match self {
+            When::Always => true,
+            When::Never => false,
+            When::Auto => {
+                let dumb = env::var("TERM").map_or(true, |term| term == "dumb");
+                io::stdout().is_terminal() && !dumb
+            }
+        }
+    }
+}
+
+// whether the output that the setting `key` (such as "color.diff") is for is colored: --color or
+// --no-color decide when given, then `key`, then color.ui, which is auto when it isn't set
+pub fn enabled(config: &Config, key: &str, option: Option<When>) -> bool {
+    let setting = |key: &str| match config.values(key).last() {
+        Some(value) => When::from_config(*value),
+        None => None,
+    };
+    option
+        .or_else(|| setting(key))
+        .or_else(|| setting("color.ui"))
+        .unwrap_or(When::Auto)
+        .enabled()
+}
+
+// `text` in `color` when colors are `enabled`
+pub fn paint(enabled: bool, color: &str, text: &str) -> String {
+    match enabled {
+        true => format!("{}{}{}", color, text, RESET),
+        false => text.to_string(),
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use sha1::Digest;
+
+use crate::{
+    error::Result,
+    repository::{self, Repository},
+};
+
+const SIGNATURE: &[u8] = b"CGPH";
+const VERSION: u8 = 1;
+const SHA1_VERSION: u8 = 1;
+const HEADER_SIZE: usize = 8;
+const CHUNK_ENTRY_SIZE: usize = 12;
+
+const OID_FANOUT: &[u8; 4] = b"OIDF";
+const OID_LOOKUP: &[u8; 4] = b"OIDL";
+const COMMIT_DATA: &[u8; 4] = b"CDAT";
+const EXTRA_EDGES: &[u8; 4] = b"EDGE";
+
+const PARENT_NONE: u32 = 0x7000_0000;
+// a second parent with the high bit set indexes into the extra edges of an octopus merge,
+// whose last one has it set in turn
+const EXTRA_EDGE: u32 = 0x8000_0000;
+const MAX_GENERATION: u32 = 0x3fff_ffff;
+
+// objects/info/commit-graph: the commits of the history in one sorted list, each with its
+// tree, its parents by position, its generation and its commit time, so that walks needn't
+// read the commits themselves
+
+// writes a commit-graph of the commits reachable from `tips`, returning how many it lists
+pub fn write(repository: &Repository, tips: &[String]) -> Result<usize> {
+    let mut commits = repository.rev_list(tips, &[])?;
+    let mut parents = HashMap::new();
+    let mut times = HashMap::new();
+    for id in &commits {
+        let commit = repository.find_commit(id)?;
+        parents.insert(id.clone(), commit.parents);
+        times.insert(
+            id.clone(),
+            (commit.tree, commit.committer.time.max(0) as u64),
+        );
+    }
+
+    // a commit's generation is one more than its parents', which come first in reverse
+    let mut generations: HashMap<&str, u32> = HashMap::new();
+    let mut order = repository::topo_order(commits.clone(), &parents);
+    order.reverse();
+    for id in &order {
+        let generation = parents[id]
+            .iter()
+            .filter_map(|parent| generations.get(parent.as_str()))
+            .max()
+            .map_or(1, |generation| (generation + 1).min(MAX_GENERATION));
+        generations.insert(id, generation);
+    }
+
+    commits.sort();
+    let ids: Vec<Vec<u8>> = commits
+        .iter()
+        .map(|id| hex::decode(id).unwrap_or_default())
+        .collect();
+    let position = |id: &str| {
+        commits
+            .binary_search_by(|other| other.as_str().cmp(id))
+            .ok()
+    };
+
+    let mut fanout = vec![];
+    for first in 0..=255u8 {
+        let count = ids.partition_point(|id| id[0] <= first);
+        fanout.extend_from_slice(&(count as u32).to_be_bytes());
+    }
+
+    let mut data = vec![];
+    let mut edges: Vec<u32> = vec![];
+    for id in &commits {
+        let (tree, time) = &times[id];
+        data.extend_from_slice(&hex::decode(tree).unwrap_or_default());
+        let positions: Vec<u32> = parents[id]
+            .iter()
+            .filter_map(|parent| position(parent))
+            .map(|position| position as u32)
+            .collect();
+        let (first, second) = match positions[..] {
+            [] => (PARENT_NONE, PARENT_NONE),
+            [first] => (first, PARENT_NONE),
+            [first, second] => (first, second),
+            [first, ref rest @ ..] => {
+                let start = edges.len() as u32;
+                edges.extend(rest);
+                *edges.last_mut().unwrap() |= EXTRA_EDGE;
+                (first, EXTRA_EDGE | start)
+            }
+        };
+        data.extend_from_slice(&first.to_be_bytes());
+        data.extend_from_slice(&second.to_be_bytes());
+        // the generation in the top 30 bits, then the 34 bits of the commit time
+        let generation = generations[id.as_str()] << 2 | ((time >> 32) & 0x3) as u32;
+        data.extend_from_slice(&generation.to_be_bytes());
+        data.extend_from_slice(&(*time as u32).to_be_bytes());
+    }
+
+    let mut chunks = vec![
+        (OID_FANOUT, fanout),
+        (OID_LOOKUP, ids.concat()),
+        (COMMIT_DATA, data),
+    ];
+    if !edges.is_empty() {
+        let edges = edges.iter().flat_map(|edge| edge.to_be_bytes()).collect();
+        chunks.push((EXTRA_EDGES, edges));
+    }
+
+    let mut graph = SIGNATURE.to_vec();
+    graph.extend_from_slice(&[VERSION, SHA1_VERSION, chunks.len() as u8, 0]);
+    let mut offset = (HEADER_SIZE + (chunks.len() + 1) * CHUNK_ENTRY_SIZE) as u64;
+    for (id, chunk) in &chunks {
+        graph.extend_from_slice(*id);
+        graph.extend_from_slice(&offset.to_be_bytes());
+        offset += chunk.len() as u64;
+    }
+    graph.extend_from_slice(&[0; 4]);
+    graph.extend_from_slice(&offset.to_be_bytes());
+    for (_, chunk) in &chunks {
+        graph.extend_from_slice(chunk);
+    }
+    let checksum = sha1::Sha1::digest(&graph);
+    graph.extend_from_slice(&checksum);
+
+    // readers must never see a partly written graph
+    let path = path(&repository.object_dir());
+    fs::create_dir_all(path.parent().unwrap())?;
+    let temp = path.with_file_name(format!("tmp_graph_{}", std::process::id()));
+    fs::write(&temp, &graph)?;
+    fs::rename(&temp, path)?;
+
+    Ok(commits.len())
+}
+
+pub fn path(object_dir: &Path) -> PathBuf {
+    object_dir.join("info").join("commit-graph")
+}
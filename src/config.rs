@@ -0,0 +1,604 @@
+use std::{
+    env, fs, io,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::{Error, Result},
+    paths,
+    wildmatch::wildmatch,
+};
+
+const SYSTEM_FILE: &str = "/etc/gitconfig";
+// how deep includes can go, which stops an include of itself going on forever
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+#[derive(Debug, Default, Clone)]
+pub struct Config {
+    // (section[.subsection].name, value), in file order; a key without "=" has no value
+    entries: Vec<(String, Option<String>)>,
+}
+
+impl Config {
+    pub fn load(git_dir: &Path) -> Config {
+        Config::from_file(&git_dir.join("config"))
+    }
+
+    // the settings in the one file at `path`, none if it can't be read
+    pub fn from_file(path: &Path) -> Config {
+        fs::read_to_string(path)
+            .map(|content| Config::parse(&content))
+            .unwrap_or_default()
+    }
+
+    // the settings for the whole system: GIT_CONFIG_SYSTEM, or else /etc/gitconfig, unless
+    // GIT_CONFIG_NOSYSTEM is set
+    pub fn system() -> Config {
+        let mut config = Config::default();
+        if let Some(file) = system_file_to_read() {
+            config.include(&file, &Conditions::default(), 0);
+        }
+        config
+    }
+
+    // the user's own settings: GIT_CONFIG_GLOBAL, or else ~/.gitconfig over
+    // $XDG_CONFIG_HOME/git/config
+    pub fn global() -> Config {
+        let mut config = Config::default();
+        for file in global_files() {
+            config.include(&file, &Conditions::default(), 0);
+        }
+        config
+    }
+
+    // what applies outside a repository: the user's settings over the system's
+    pub fn user() -> Config {
+        let mut config = Config::system();
+        config.entries.extend(Config::global().entries);
+        config
+    }
+
+    // what applies in a repository: its own settings over the user's over the system's, where
+    // includeIf sections can depend on the repository
+    pub fn for_repository(git_dir: &Path) -> Config {
+        let conditions = Conditions::for_repository(git_dir);
+        let files = system_file_to_read()
+            .into_iter()
+            .chain(global_files())
+            .chain([git_dir.join("config")]);
+        let mut config = Config::default();
+        for file in files {
+            config.include(&file, &conditions, 0);
+        }
+        config
+    }
+
+    // adds the settings in `file`, with those of the files it includes where it includes them;
+    // a file that can't be read adds nothing, and includes deeper than git allows are left out
+    fn include(&mut self, file: &Path, conditions: &Conditions, depth: usize) {
+        let Ok(content) = fs::read_to_string(file) else {
+            return;
+        };
+        for (name, value) in Config::parse(&content).entries {
+            let included = match (name.as_str(), &value) {
+                ("include.path", Some(path)) => Some(path.clone()),
+                (name, Some(path)) => name
+                    .strip_prefix("includeif.")
+                    .and_then(|name| name.strip_suffix(".path"))
+                    .filter(|condition| conditions.hold(condition, file))
+                    .map(|_| path.clone()),
+                _ => None,
+            };
+            self.entries.push((name, value));
+            if let Some(path) = included.filter(|_| depth < MAX_INCLUDE_DEPTH) {
+                // relative to the directory of the file that includes it
+                let path = match paths::expand_home(&path) {
+                    path if path.is_relative() => file.parent().unwrap_or(Path::new("")).join(path),
+                    path => path,
+                };
+                self.include(&path, conditions, depth + 1);
+            }
+        }
+    }
+
+    pub fn parse(content: &str) -> Config {
+        let entries = scan(content)
+            .into_iter()
+            .filter_map(|line| {
+                let (name, value) = line.variable?;
+                Some((format!("{}.{}", line.section, name), value))
+            })
+            .collect();
+
+        Config { entries }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        let key = normalize_key(key);
+        self.entries
+            .iter()
+            .rev()
+            .find(|(name, _)| *name == key)
+            .map(|(_, value)| value.as_deref().unwrap_or("true"))
+    }
+
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.values(key)
+            .into_iter()
+            .map(|value| value.unwrap_or("true"))
+            .collect()
+    }
+
+    // the values of `key` as they are written, None where it is named without "="
+    pub fn values(&self, key: &str) -> Vec<Option<&str>> {
+        let key = normalize_key(key);
+        self.entries
+            .iter()
+            .filter(|(name, _)| *name == key)
+            .map(|(_, value)| value.as_deref())
+            .collect()
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&str, Option<&str>)> {
+        self.entries
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_deref()))
+    }
+}
+
+// whether `key` can be written, as "section[.subsection].name" where the section and name are
+// letters, digits and "-", the name starting with a letter, and the subsection has no newline
+pub fn check_key(key: &str) -> Result<()> {
+    let (Some(first), Some(last)) = (key.find('.'), key.rfind('.')) else {
+        return Err(Error::ConfigKeyNoSection(key.to_string()));
+    };
+    if first == 0 {
+        return Err(Error::ConfigKeyNoSection(key.to_string()));
+    }
+    if last == key.len() - 1 {
+        return Err(Error::ConfigKeyNoName(key.to_string()));
+    }
+    let keychars = |part: &str| part.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    let name = &key[last + 1..];
+    if !keychars(&key[..first])
+        || !keychars(name)
+        || !name.starts_with(|c: char| c.is_ascii_alphabetic())
+    {
+        return Err(Error::InvalidConfigKey(key.to_string()));
+    }
+    if key.contains('\n') {
+        return Err(Error::ConfigKeyNewline(key.to_string()));
+    }
+    Ok(())
+}
+
+// what the "includeIf.<condition>.path" sections of a config file are tested against: the
+// repository's git directory and the branch checked out in it
+#[derive(Debug, Default)]
+struct Conditions {
+    git_dir: Option<PathBuf>,
+    branch: Option<String>,
+}
+
+impl Conditions {
+    fn for_repository(git_dir: &Path) -> Conditions {
+        let branch = fs::read_to_string(git_dir.join("HEAD"))
+            .ok()
+            .and_then(|head| {
+                let branch = head.trim_end().strip_prefix("ref: refs/heads/")?;
+                Some(branch.to_string())
+            });
+        Conditions {
+            git_dir: Some(git_dir.to_path_buf()),
+            branch,
+        }
+    }
+
+    // whether the condition of an includeIf section in `file` holds, which is one of
+    //   gitdir:<pattern>    the git directory matches the pattern
+    //   gitdir/i:<pattern>  the same, ignoring case
+    //   onbranch:<pattern>  the branch checked out matches the pattern
+    // where a pattern ending in "/" matches everything below it
+    fn hold(&self, condition: &str, file: &Path) -> bool {
+        let prefix = |pattern: &str| match pattern.ends_with('/') {
+            true => format!("{}**", pattern),
+            false => pattern.to_string(),
+        };
+        if let Some(pattern) = condition.strip_prefix("onbranch:") {
+            return self.branch.as_ref().is_some_and(|branch| {
+                wildmatch(prefix(pattern).as_bytes(), branch.as_bytes(), true)
+            });
+        }
+        let (pattern, icase) = match (
+            condition.strip_prefix("gitdir:"),
+            condition.strip_prefix("gitdir/i:"),
+        ) {
+            (Some(pattern), _) => (pattern, false),
+            (_, Some(pattern)) => (pattern, true),
+            _ => return false,
+        };
+        let Some(git_dir) = &self.git_dir else {
+            return false;
+        };
+
+        // "./" is the directory of the file, and a pattern that isn't a full path can match
+        // at any depth
+        let pattern = match pattern.strip_prefix("./") {
+            Some(rest) => file.parent().unwrap_or(Path::new("")).join(rest),
+            None => paths::expand_home(pattern),
+        };
+        let mut pattern = prefix(&pattern.to_string_lossy());
+        if !Path::new(&pattern).is_absolute() {
+            pattern = format!("**/{}", pattern);
+        }
+        let fold = |text: &str| match icase {
+            true => text.to_lowercase(),
+            false => text.to_string(),
+        };
+        let pattern = fold(&pattern);
+        // the git directory as it was found, and with any symbolic links resolved
+        let absolute = env::current_dir()
+            .map(|cwd| cwd.join(git_dir))
+            .unwrap_or_else(|_| git_dir.clone());
+        [Some(absolute), fs::canonicalize(git_dir).ok()]
+            .into_iter()
+            .flatten()
+            .any(|dir| {
+                wildmatch(
+                    pattern.as_bytes(),
+                    fold(&dir.to_string_lossy()).as_bytes(),
+                    true,
+                )
+            })
+    }
+}
+
+// the file `git config --system` reads and writes: GIT_CONFIG_SYSTEM, or else /etc/gitconfig
+pub fn system_file() -> PathBuf {
+    env::var_os("GIT_CONFIG_SYSTEM")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(SYSTEM_FILE))
+}
+
+// the system file settings are read from, which GIT_CONFIG_NOSYSTEM turns off
+fn system_file_to_read() -> Option<PathBuf> {
+    let off = env::var("GIT_CONFIG_NOSYSTEM").is_ok_and(|value| parse_bool(&value) != Some(false));
+    (!off).then(system_file)
+}
+
+// the files the user's settings are read from, in order
+fn global_files() -> Vec<PathBuf> {
+    match env::var_os("GIT_CONFIG_GLOBAL") {
+        Some(file) => vec![PathBuf::from(file)],
+        None => xdg_file()
+            .into_iter()
+            .chain(paths::home_dir().map(|home| home.join(".gitconfig")))
+            .collect(),
+    }
+}
+
+// the file `git config --global` writes to: GIT_CONFIG_GLOBAL, or else ~/.gitconfig unless
+// only $XDG_CONFIG_HOME/git/config exists
+pub fn global_file() -> Option<PathBuf> {
+    if let Some(file) = env::var_os("GIT_CONFIG_GLOBAL") {
+        return Some(PathBuf::from(file));
+    }
+    let home = paths::home_dir()?.join(".gitconfig");
+    match xdg_file().filter(|xdg| !home.exists() && xdg.exists()) {
+        Some(xdg) => Some(xdg),
+        None => Some(home),
+    }
+}
+
+fn xdg_file() -> Option<PathBuf> {
+    paths::config_home().map(|xdg| xdg.join("git/config"))
+}
+
+// sets `key` to `value` in the config file at `path`, in place of its last value if it has one
+pub fn set_value(path: &Path, key: &str, value: &str) -> io::Result<()> {
+    let content = read_file(path)?;
+    let scanned = scan(&content);
+    let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+    match scanned
+        .iter()
+        .rev()
+        .find(|line| line.sets(key) && !line.header)
+    {
+        Some(line) => {
+            lines.splice(line.lines.clone(), [variable_line(key, value)]);
+        }
+        None => insert_variable(&mut lines, &scanned, key, value),
+    }
+    write_file(path, &lines)
+}
+
+// adds another value for `key` to the config file at `path`
+pub fn add_value(path: &Path, key: &str, value: &str) -> io::Result<()> {
+    let content = read_file(path)?;
+    let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+    insert_variable(&mut lines, &scan(&content), key, value);
+    write_file(path, &lines)
+}
+
+// removes the values of `key` from the config file at `path` that `matches` accepts, None
+// standing for a key named without "=", and any section this leaves empty; gives how many were
+// removed
+pub fn remove_values(
+    path: &Path,
+    key: &str,
+    matches: impl Fn(Option<&str>) -> bool,
+) -> io::Result<usize> {
+    let content = read_file(path)?;
+    let scanned = scan(&content);
+    let mut removed: Vec<bool> = scanned
+        .iter()
+        .map(|line| !line.header && line.sets(key) && matches(line.value()))
+        .collect();
+    let count = removed.iter().filter(|removed| **removed).count();
+    if count == 0 {
+        return Ok(0);
+    }
+
+    let lines: Vec<&str> = content.lines().collect();
+    let blank = |line: &Line| {
+        lines[line.lines.clone()]
+            .iter()
+            .all(|line| line.trim().is_empty())
+    };
+    let mut header = None;
+    for i in 0..=scanned.len() {
+        if i < scanned.len() && !scanned[i].header {
+            continue;
+        }
+        if let Some(start) = header {
+            let body = start + 1..i;
+            let emptied = body.clone().any(|j| removed[j])
+                && body.clone().all(|j| removed[j] || blank(&scanned[j]));
+            if emptied {
+                removed[start..i].fill(true);
+            }
+        }
+        header = scanned
+            .get(i)
+            .filter(|line| line.variable.is_none())
+            .map(|_| i);
+    }
+
+    let kept: Vec<String> = scanned
+        .iter()
+        .zip(&removed)
+        .filter(|(_, removed)| !**removed)
+        .flat_map(|(line, _)| {
+            lines[line.lines.clone()]
+                .iter()
+                .map(|line| line.to_string())
+        })
+        .collect();
+    write_file(path, &kept)?;
+    Ok(count)
+}
+
+// a logical line of a config file: the lines it spans, more than one when a value is
+// continued, the section it is in, whether it starts that section, and the variable it sets
+struct Line {
+    lines: Range<usize>,
+    section: String,
+    header: bool,
+    variable: Option<(String, Option<String>)>,
+}
+
+impl Line {
+    fn sets(&self, key: &str) -> bool {
+        self.variable
+            .as_ref()
+            .is_some_and(|(name, _)| format!("{}.{}", self.section, name) == normalize_key(key))
+    }
+
+    fn value(&self) -> Option<&str> {
+        let (_, value) = self.variable.as_ref()?;
+        value.as_deref()
+    }
+}
+
+fn scan(content: &str) -> Vec<Line> {
+    let lines: Vec<&str> = content.lines().collect();
+    let mut scanned = vec![];
+    let mut section = String::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let start = i;
+        let mut line = lines[i].trim_start().to_string();
+        i += 1;
+        // a trailing backslash continues the value on the next line
+        while line.ends_with('\\') && !line.ends_with("\\\\") {
+            line.pop();
+            match lines.get(i) {
+                Some(next) => {
+                    line.push_str(next);
+                    i += 1;
+                }
+                None => break,
+            }
+        }
+
+        let mut header = false;
+        if line.starts_with('[') {
+            match line.find(']') {
+                Some(end) => {
+                    section = parse_section_header(&line[1..end]);
+                    line = line[end + 1..].trim_start().to_string();
+                    header = true;
+                }
+                None => line.clear(),
+            }
+        }
+        let variable = match line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            true => None,
+            false => {
+                let (name, value) = match line.split_once('=') {
+                    Some((name, value)) => (name.trim(), Some(parse_value(value))),
+                    None => (line.trim(), None),
+                };
+                let name = name.split(['#', ';']).next().unwrap_or(name).trim();
+                (!name.is_empty() && !section.is_empty()).then(|| (name.to_lowercase(), value))
+            }
+        };
+        scanned.push(Line {
+            lines: start..i,
+            section: section.clone(),
+            header,
+            variable,
+        });
+    }
+
+    scanned
+}
+
+// a new variable goes after the last one of its section, or into a new section at the end
+fn insert_variable(lines: &mut Vec<String>, scanned: &[Line], key: &str, value: &str) {
+    let (section, _) = key.rsplit_once('.').unwrap_or((key, ""));
+    let normalized = normalize_key(key);
+    let (normalized_section, _) = normalized.rsplit_once('.').unwrap_or((&normalized, ""));
+    let last = scanned.iter().rev().find(|line| {
+        line.section == normalized_section && (line.header || line.variable.is_some())
+    });
+    match last {
+        Some(line) => lines.insert(line.lines.end, variable_line(key, value)),
+        None => {
+            lines.push(match section.split_once('.') {
+                Some((section, subsection)) => format!(
+                    "[{} \"{}\"]",
+                    section,
+                    subsection.replace('\\', "\\\\").replace('"', "\\\"")
+                ),
+                None => format!("[{}]", section),
+            });
+            lines.push(variable_line(key, value));
+        }
+    }
+}
+
+// values are quoted when they have to be, to keep spaces at their ends or comment characters
+fn variable_line(key: &str, value: &str) -> String {
+    let (_, name) = key.rsplit_once('.').unwrap_or(("", key));
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+    let quoted = value.starts_with(' ') || value.ends_with(' ') || value.contains(['#', ';']);
+    match quoted {
+        true => format!("\t{} = \"{}\"", name, escaped),
+        false => format!("\t{} = {}", name, escaped),
+    }
+}
+
+fn read_file(path: &Path) -> io::Result<String> {
+    match fs::read_to_string(path) {
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+        result => result,
+    }
+}
+
+// written to a lock file first, so that readers never see half of it
+fn write_file(path: &Path, lines: &[String]) -> io::Result<()> {
+    let mut content = lines.join("\n");
+    if !content.is_empty() {
+        content.push('\n');
+    }
+    let mut lock = path.as_os_str().to_owned();
+    lock.push(".lock");
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&lock, content)?;
+    fs::rename(&lock, path)
+}
+
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" | "" => Some(false),
+        _ => None,
+    }
+}
+
+// an integer with an optional k, m or g suffix
+pub fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let (number, unit) = match value.chars().last()? {
+        'k' | 'K' => (&value[..value.len() - 1], 1 << 10),
+        'm' | 'M' => (&value[..value.len() - 1], 1 << 20),
+        'g' | 'G' => (&value[..value.len() - 1], 1 << 30),
+        _ => (value, 1),
+    };
+
+    number.parse::<u64>().ok()?.checked_mul(unit)
+}
+
+// section and variable names are case-insensitive, subsections are not
+fn normalize_key(key: &str) -> String {
+    let (section, rest) = key.split_once('.').unwrap_or((key, ""));
+    match rest.rsplit_once('.') {
+        Some((subsection, name)) => format!(
+            "{}.{}.{}",
+            section.to_lowercase(),
+            subsection,
+            name.to_lowercase()
+        ),
+        None => format!("{}.{}", section.to_lowercase(), rest.to_lowercase()),
+    }
+}
+
+fn parse_section_header(header: &str) -> String {
+    let header = header.trim();
+    match header.split_once(|c: char| c.is_whitespace()) {
+        Some((section, subsection)) => {
+            let subsection = subsection.trim();
+            let subsection = subsection
+                .strip_prefix('"')
+                .and_then(|subsection| subsection.strip_suffix('"'))
+                .unwrap_or(subsection)
+                .replace("\\\"", "\"")
+                .replace("\\\\", "\\");
+            format!("{}.{}", section.to_lowercase(), subsection)
+        }
+        // deprecated [section.subsection] syntax
+        None => match header.split_once('.') {
+            Some((section, subsection)) => format!("{}.{}", section.to_lowercase(), subsection),
+            None => header.to_lowercase(),
+        },
+    }
+}
+
+fn parse_value(value: &str) -> String {
+    let mut result = String::new();
+    let mut quoted = false;
+    let mut chars = value.trim().chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' | ';' if !quoted => break,
+            '\\' => match chars.next() {
+                Some('n') => result.push('\n'),
+                Some('t') => result.push('\t'),
+                Some('b') => {
+                    result.pop();
+                }
+                Some(c) => result.push(c),
+                None => {}
+            },
+            c => result.push(c),
+        }
+    }
+
+    if quoted {
+        result
+    } else {
+        result.trim_end().to_string()
+    }
+}
@@ -0,0 +1,457 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+};
+
+use crate::{
+    attributes::{AttributeState, Attributes},
+    config::{self, Config},
+    pktline::{PktLineReader, PktLineWriter, MAX_PKT_LEN},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CrlfAction {
+    Binary,
+    // text that is stored with LF and checked out with LF
+    TextInput,
+    // text that is stored with LF and checked out with CRLF
+    TextCrlf,
+    // like the text variants, but only for content that doesn't look binary
+    AutoInput,
+    AutoCrlf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AutoCrlf {
+    False,
+    True,
+    Input,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FilterCommand {
+    Clean,
+    Smudge,
+}
+
+impl FilterCommand {
+    fn name(&self) -> &'static str {
+        match self {
+            FilterCommand::Clean => "clean",
+            FilterCommand::Smudge => "smudge",
+        }
+    }
+}
+
+// a `filter.<name>.process` command speaking the long-running filter protocol
+struct FilterProcess {
+    child: Child,
+    input: Option<PktLineWriter<ChildStdin>>,
+    output: PktLineReader<ChildStdout>,
+    capabilities: Vec<String>,
+}
+
+impl FilterProcess {
+    fn start(command: &str, work_tree: &Path) -> io::Result<FilterProcess> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .current_dir(work_tree)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let mut input = PktLineWriter::new(child.stdin.take().unwrap());
+        let mut output = PktLineReader::new(child.stdout.take().unwrap());
+
+        input.write_line("git-filter-client")?;
+        input.write_line("version=2")?;
+        input.write_flush()?;
+        let welcome = read_text_until_flush(&mut output)?;
+        if welcome.first().map(String::as_str) != Some("git-filter-server")
+            || !welcome.iter().any(|line| line == "version=2")
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unexpected filter process handshake from '{}'", command),
+            ));
+        }
+
+        input.write_line("capability=clean")?;
+        input.write_line("capability=smudge")?;
+        input.write_flush()?;
+        let capabilities = read_text_until_flush(&mut output)?
+            .iter()
+            .filter_map(|line| line.strip_prefix("capability="))
+            .map(|capability| capability.to_string())
+            .collect();
+
+        Ok(FilterProcess {
+            child,
+            input: Some(input),
+            output,
+            capabilities,
+        })
+    }
+
+    // Ok(None) means the filter declined (or failed) this path and the content is used as-is
+    fn apply(
+        &mut self,
+        command: FilterCommand,
+        path: &str,
+        content: &[u8],
+    ) -> io::Result<Option<Vec<u8>>> {
+        if !self.capabilities.iter().any(|c| c == command.name()) {
+            return Ok(None);
+        }
+        let input = self.input.as_mut().unwrap();
+        input.write_line(&format!("command={}", command.name()))?;
+        input.write_line(&format!("pathname={}", path))?;
+        input.write_flush()?;
+        for chunk in content.chunks(MAX_PKT_LEN - 4) {
+            input.write_data(chunk)?;
+        }
+        input.write_flush()?;
+
+        let mut status = filter_status(&read_text_until_flush(&mut self.output)?);
+        if status.as_deref() != Some("success") {
+            return self.failed(command, status);
+        }
+        let output = self.output.read_until_flush()?.concat();
+        // the filter may revise its status once the content has been sent
+        if let Some(final_status) = filter_status(&read_text_until_flush(&mut self.output)?) {
+            status = Some(final_status);
+        }
+        if status.as_deref() != Some("success") {
+            return self.failed(command, status);
+        }
+
+        Ok(Some(output))
+    }
+
+    fn failed(
+        &mut self,
+        command: FilterCommand,
+        status: Option<String>,
+    ) -> io::Result<Option<Vec<u8>>> {
+        if status.as_deref() == Some("abort") {
+            // the filter doesn't want to see any more requests for this command
+            self.capabilities.retain(|c| c != command.name());
+        }
+        Err(io::Error::other(format!(
+            "filter process reported status '{}'",
+            status.unwrap_or_default()
+        )))
+    }
+}
+
+impl Drop for FilterProcess {
+    fn drop(&mut self) {
+        // closing stdin tells the filter we are done
+        self.input.take();
+        let _ = self.child.wait();
+    }
+}
+
+fn read_text_until_flush<R: Read>(reader: &mut PktLineReader<R>) -> io::Result<Vec<String>> {
+    Ok(reader
+        .read_until_flush()?
+        .iter()
+        .map(|line| {
+            String::from_utf8_lossy(line)
+                .trim_end_matches('\n')
+                .to_string()
+        })
+        .collect())
+}
+
+fn filter_status(lines: &[String]) -> Option<String> {
+    lines
+        .iter()
+        .rev()
+        .find_map(|line| line.strip_prefix("status="))
+        .map(|status| status.to_string())
+}
+
+pub fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+pub struct Converter {
+    attributes: Attributes,
+    config: Config,
+    work_tree: PathBuf,
+    auto_crlf: AutoCrlf,
+    eol_crlf: bool,
+    processes: HashMap<String, FilterProcess>,
+}
+
+impl Converter {
+    pub fn new(attributes: Attributes, config: &Config, work_tree: &Path) -> Converter {
+        let auto_crlf = match config.get("core.autocrlf") {
+            Some(value) if value.eq_ignore_ascii_case("input") => AutoCrlf::Input,
+            Some(value) if config::parse_bool(value) == Some(true) => AutoCrlf::True,
+            _ => AutoCrlf::False,
+        };
+        let eol_crlf = match config.get("core.eol") {
+            Some(eol) => eol.eq_ignore_ascii_case("crlf"),
+            None => cfg!(windows),
+        };
+
+        Converter {
+            attributes,
+            config: config.clone(),
+            work_tree: work_tree.to_path_buf(),
+            auto_crlf,
+            eol_crlf,
+            processes: HashMap::new(),
+        }
+    }
+
+    fn filter_driver(&mut self, path: &str) -> Option<String> {
+        match self.attributes.check(path, "filter") {
+            AttributeState::Value(driver) => Some(driver),
+            _ => None,
+        }
+    }
+
+    fn apply_filter(
+        &mut self,
+        command: FilterCommand,
+        path: &str,
+        content: Vec<u8>,
+    ) -> io::Result<Vec<u8>> {
+        let Some(driver) = self.filter_driver(path) else {
+            return Ok(content);
+        };
+        let required = self
+            .config
+            .get(&format!("filter.{}.required", driver))
+            .and_then(config::parse_bool)
+            .unwrap_or(false);
+
+        let result = if let Some(process) = self
+            .config
+            .get(&format!("filter.{}.process", driver))
+            .map(|process| process.to_string())
+        {
+            self.run_filter_process(&driver, &process, command, path, &content)
+        } else if let Some(filter) =
+            self.config
+                .get(&format!("filter.{}.{}", driver, command.name()))
+        {
+            let filter = filter.replace("%f", &shell_quote(path));
+            run_filter(&filter, &self.work_tree, &content).map(Some)
+        } else {
+            Ok(None)
+        };
+
+        match result {
+            Ok(Some(output)) => Ok(output),
+            Ok(None) if !required => Ok(content),
+            Ok(None) => Err(io::Error::other(format!(
+                "{}: {} filter '{}' is required but not configured",
+                path,
+                command.name(),
+                driver
+            ))),
+            Err(e) if required => Err(io::Error::other(format!(
+                "{}: {} filter '{}' failed: {}",
+                path,
+                command.name(),
+                driver,
+                e
+            ))),
+            Err(e) => {
+                eprintln!(
+                    "error: {}: {} filter '{}' failed: {}",
+                    path,
+                    command.name(),
+                    driver,
+                    e
+                );
+                Ok(content)
+            }
+        }
+    }
+
+    fn run_filter_process(
+        &mut self,
+        driver: &str,
+        process: &str,
+        command: FilterCommand,
+        path: &str,
+        content: &[u8],
+    ) -> io::Result<Option<Vec<u8>>> {
+        if !self.processes.contains_key(driver) {
+            let filter = FilterProcess::start(process, &self.work_tree)?;
+            self.processes.insert(driver.to_string(), filter);
+        }
+        let result = self
+            .processes
+            .get_mut(driver)
+            .unwrap()
+            .apply(command, path, content);
+        // a broken pipe leaves the process unusable; restart it for the next path
+        if matches!(&result, Err(e) if e.kind() != io::ErrorKind::Other) {
+            self.processes.remove(driver);
+        }
+
+        result
+    }
+
+    fn crlf_action(&mut self, path: &str) -> CrlfAction {
+        let attributes = self.attributes.check_all(path);
+        let lookup = |name: &str| {
+            attributes
+                .iter()
+                .find(|(attribute, _)| attribute == name)
+                .map(|(_, state)| state.clone())
+                .unwrap_or(AttributeState::Unspecified)
+        };
+
+        // (is auto, explicit eol) for paths that are marked as text one way or another
+        let (auto, eol) = match (lookup("text"), lookup("eol")) {
+            (AttributeState::Unset, _) => return CrlfAction::Binary,
+            (AttributeState::Value(value), eol) if value == "auto" => (true, eol),
+            (AttributeState::Set, eol) => (false, eol),
+            // an eol attribute on its own marks the path as text
+            (_, eol @ AttributeState::Value(_)) => (false, eol),
+            _ => {
+                return match self.auto_crlf {
+                    AutoCrlf::False => CrlfAction::Binary,
+                    AutoCrlf::Input => CrlfAction::AutoInput,
+                    AutoCrlf::True => CrlfAction::AutoCrlf,
+                }
+            }
+        };
+
+        let crlf = match eol {
+            AttributeState::Value(eol) if eol == "crlf" => true,
+            AttributeState::Value(eol) if eol == "lf" => false,
+            _ => match self.auto_crlf {
+                AutoCrlf::True => true,
+                AutoCrlf::Input => false,
+                AutoCrlf::False => self.eol_crlf,
+            },
+        };
+
+        match (auto, crlf) {
+            (false, false) => CrlfAction::TextInput,
+            (false, true) => CrlfAction::TextCrlf,
+            (true, false) => CrlfAction::AutoInput,
+            (true, true) => CrlfAction::AutoCrlf,
+        }
+    }
+
+    // whether convert_to_git may change the content of `path`; if not, it can be stored
+    // straight from the file
+    pub fn converts_to_git(&mut self, path: &str) -> bool {
+        self.filter_driver(path).is_some() || self.crlf_action(path) != CrlfAction::Binary
+    }
+
+    // the content that should be hashed and stored for a working tree file
+    pub fn convert_to_git(&mut self, path: &str, content: Vec<u8>) -> io::Result<Vec<u8>> {
+        let content = self.apply_filter(FilterCommand::Clean, path, content)?;
+        let action = self.crlf_action(path);
+        if action == CrlfAction::Binary || !content.contains(&b'\r') {
+            return Ok(content);
+        }
+        if matches!(action, CrlfAction::AutoInput | CrlfAction::AutoCrlf) && is_binary(&content) {
+            return Ok(content);
+        }
+
+        let mut output = Vec::with_capacity(content.len());
+        let mut bytes = content.iter().peekable();
+        while let Some(byte) = bytes.next() {
+            if *byte == b'\r' && bytes.peek() == Some(&&b'\n') {
+                continue;
+            }
+            output.push(*byte);
+        }
+
+        Ok(output)
+    }
+
+    // the content that should be written to the working tree for a stored blob
+    pub fn convert_to_working_tree(&mut self, path: &str, content: Vec<u8>) -> io::Result<Vec<u8>> {
+        let content = self.convert_eol_to_working_tree(path, content);
+        self.apply_filter(FilterCommand::Smudge, path, content)
+    }
+
+    fn convert_eol_to_working_tree(&mut self, path: &str, content: Vec<u8>) -> Vec<u8> {
+        let action = self.crlf_action(path);
+        if !matches!(action, CrlfAction::TextCrlf | CrlfAction::AutoCrlf) {
+            return content;
+        }
+        // blobs that already contain CRs were committed that way on purpose
+        if action == CrlfAction::AutoCrlf && (content.contains(&b'\r') || is_binary(&content)) {
+            return content;
+        }
+
+        let mut output = Vec::with_capacity(content.len());
+        let mut previous = 0;
+        for byte in content {
+            if byte == b'\n' && previous != b'\r' {
+                output.push(b'\r');
+            }
+            output.push(byte);
+            previous = byte;
+        }
+
+        output
+    }
+}
+
+fn run_filter(command: &str, work_tree: &Path, content: &[u8]) -> io::Result<Vec<u8>> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .current_dir(work_tree)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    // feed stdin from another thread so a filter that streams its output can't deadlock us
+    let mut stdin = child.stdin.take().unwrap();
+    let input = content.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let mut output = Vec::new();
+    child.stdout.take().unwrap().read_to_end(&mut output)?;
+    let written = writer.join().unwrap();
+    let status = child.wait()?;
+
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "'{}' exited with {}",
+            command, status
+        )));
+    }
+    // filters are allowed to ignore their input
+    if let Err(e) = written {
+        if e.kind() != io::ErrorKind::BrokenPipe {
+            return Err(e);
+        }
+    }
+
+    Ok(output)
+}
+
+// git's heuristic: NULs, lone CRs, or too many control characters mean binary
+pub fn is_binary(content: &[u8]) -> bool {
+    let mut printable = 0;
+    let mut non_printable = 0;
+    for (i, byte) in content.iter().enumerate() {
+        match byte {
+            0 => return true,
+            b'\r' if content.get(i + 1) != Some(&b'\n') => return true,
+            127 => non_printable += 1,
+            b'\x08' | b'\t' | b'\n' | b'\r' | b'\x0c' | b'\x1b' => printable += 1,
+            byte if *byte < 32 => non_printable += 1,
+            _ => printable += 1,
+        }
+    }
+
+    (printable >> 7) < non_printable
+}
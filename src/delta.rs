@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+
+// the deltas git's packs and binary patches use: the sizes of the base and the result, then
+// instructions that either copy a range of the base or insert the bytes that follow them
+
+// how long a run of the base must be to be looked up, and how many places with the same run
+// are tried
+const BLOCK: usize = 16;
+const MAX_CANDIDATES: usize = 64;
+// the most one instruction copies or inserts
+const MAX_COPY: usize = 0x10000;
+const MAX_INSERT: usize = 0x7f;
+
+// a delta that makes `target` out of `base`, or nothing when it would be bigger than
+// `max_size`. Runs of the target found in the base are copied and the rest inserted
+pub fn create_delta(base: &[u8], target: &[u8], max_size: Option<usize>) -> Option<Vec<u8>> {
+    let mut delta = vec![];
+    write_size(&mut delta, base.len());
+    write_size(&mut delta, target.len());
+
+    // where each block of the base starts, by its content; later blocks come first, as they
+    // are closer to the end of the base where a change usually leaves off
+    let mut blocks: HashMap<&[u8], Vec<usize>> = HashMap::new();
+    for start in (0..base.len().saturating_sub(BLOCK - 1))
+        .step_by(BLOCK)
+        .rev()
+    {
+        let offsets = blocks.entry(&base[start..start + BLOCK]).or_default();
+        if offsets.len() < MAX_CANDIDATES {
+            offsets.push(start);
+        }
+    }
+
+    let mut inserted_from = 0;
+    let mut pos = 0;
+    while pos + BLOCK <= target.len() {
+        // the candidate that matches the most bytes from here on
+        let best = blocks
+            .get(&target[pos..pos + BLOCK])
+            .into_iter()
+            .flatten()
+            .map(|&offset| {
+                let len = base[offset..]
+                    .iter()
+                    .zip(&target[pos..])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                (offset, len)
+            })
+            .max_by_key(|&(offset, len)| (len, usize::MAX - offset));
+        let Some((mut offset, mut len)) = best else {
+            pos += 1;
+            continue;
+        };
+
+        // the bytes before the match that the base has too are copied rather than inserted
+        while pos > inserted_from && offset > 0 && base[offset - 1] == target[pos - 1] {
+            (offset, pos, len) = (offset - 1, pos - 1, len + 1);
+        }
+        write_insert(&mut delta, &target[inserted_from..pos]);
+        write_copy(&mut delta, offset, len);
+        pos += len;
+        inserted_from = pos;
+        if max_size.is_some_and(|max_size| delta.len() > max_size) {
+            return None;
+        }
+    }
+    write_insert(&mut delta, &target[inserted_from..]);
+
+    match max_size {
+        Some(max_size) if delta.len() > max_size => None,
+        _ => Some(delta),
+    }
+}
+
+// a size as 7 bits a byte, lowest first, with the top bit set on all but the last
+fn write_size(delta: &mut Vec<u8>, mut size: usize) {
+    while size >= 0x80 {
+        delta.push(size as u8 | 0x80);
+        size >>= 7;
+    }
+    delta.push(size as u8);
+}
+
+fn write_insert(delta: &mut Vec<u8>, data: &[u8]) {
+    for chunk in data.chunks(MAX_INSERT) {
+        delta.push(chunk.len() as u8);
+        delta.extend_from_slice(chunk);
+    }
+}
+
+// a copy instruction sets a bit for each byte of the offset and the size that isn't 0, and a
+// size of 0x10000 is written as none at all
+fn write_copy(delta: &mut Vec<u8>, mut offset: usize, mut len: usize) {
+    while len > 0 {
+        let size = len.min(MAX_COPY);
+        let mut instruction = 0x80u8;
+        let mut bytes = vec![];
+        for i in 0..4 {
+            let byte = (offset >> (i * 8)) as u8;
+            if byte != 0 {
+                instruction |= 1 << i;
+                bytes.push(byte);
+            }
+        }
+        for i in 0..3 {
+            let byte = ((size % MAX_COPY) >> (i * 8)) as u8;
+            if byte != 0 {
+                instruction |= 1 << (4 + i);
+                bytes.push(byte);
+            }
+        }
+        delta.push(instruction);
+        delta.extend(bytes);
+        offset += size;
+        len -= size;
+    }
+}
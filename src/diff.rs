@@ -0,0 +1,458 @@
+use std::io::{self, Write};
+
+use crate::{attributes::AttributeState, color, index::IndexEntry, patch::PatchOptions};
+
+pub const NULL_ID: &str = "0000000000000000000000000000000000000000";
+
+// how the plumbing diff commands print each change
+pub enum DiffFormat {
+    Raw,
+    NameOnly,
+    NameStatus,
+    Stat { color: bool },
+    NumStat,
+    Patch(PatchOptions),
+}
+
+impl DiffFormat {
+    // whether the format looks at the content of files, which always means going into trees
+    pub fn shows_content(&self) -> bool {
+        matches!(
+            self,
+            DiffFormat::Stat { .. } | DiffFormat::NumStat | DiffFormat::Patch(_)
+        )
+    }
+}
+
+// a path that differs between two sides; an id of NULL_ID on the new side stands for work
+// tree content that hasn't been hashed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change {
+    pub path: String,
+    pub old: Option<IndexEntry>,
+    pub new: Option<IndexEntry>,
+    pub unmerged: bool,
+}
+
+impl Change {
+    pub fn status(&self) -> char {
+        match (&self.old, &self.new) {
+            _ if self.unmerged => 'U',
+            (None, _) => 'A',
+            (_, None) => 'D',
+            (Some(old), Some(new)) if (old.mode ^ new.mode) & 0o170000 != 0 => 'T',
+            _ => 'M',
+        }
+    }
+
+    // ":<old mode> <new mode> <old id> <new id> <status>\t<path>"; the formats showing content
+    // are written with `write_stat`, `write_numstat` and `patch::write_patch`
+    pub fn write(&self, out: &mut impl Write, format: &DiffFormat) -> io::Result<()> {
+        match format {
+            DiffFormat::NameOnly => writeln!(out, "{}", self.path),
+            DiffFormat::NameStatus => writeln!(out, "{}\t{}", self.status(), self.path),
+            _ => {
+                let side = |entry: &Option<IndexEntry>| match entry {
+                    Some(entry) => (entry.mode, entry.id.clone()),
+                    None => (0, NULL_ID.to_string()),
+                };
+                let (old_mode, old_id) = side(&self.old);
+                let (new_mode, new_id) = side(&self.new);
+                writeln!(
+                    out,
+                    ":{:06o} {:06o} {} {} {}\t{}",
+                    old_mode,
+                    new_mode,
+                    old_id,
+                    new_id,
+                    self.status(),
+                    self.path
+                )
+            }
+        }
+    }
+}
+
+// how much a changed file changes, as --stat and --numstat count it
+pub enum FileStat {
+    Lines { added: usize, deleted: usize },
+    // a binary file is counted by its size before and after, which is 0 for both when the
+    // content didn't change
+    Binary { old_size: usize, new_size: usize },
+    Unmerged,
+}
+
+impl FileStat {
+    // the lines that differ between two versions of a file, either missing when the file was
+    // added or deleted, or just their sizes when the file is binary
+    pub fn new(old: Option<&[u8]>, new: Option<&[u8]>, same: bool, binary: bool) -> FileStat {
+        let (old, new) = (old.unwrap_or_default(), new.unwrap_or_default());
+        if binary {
+            return match same {
+                true => FileStat::Binary {
+                    old_size: 0,
+                    new_size: 0,
+                },
+                false => FileStat::Binary {
+                    old_size: old.len(),
+                    new_size: new.len(),
+                },
+            };
+        }
+        if same {
+            return FileStat::Lines {
+                added: 0,
+                deleted: 0,
+            };
+        }
+
+        let lines = |content: &[u8]| -> Vec<Vec<u8>> {
+            content
+                .split_inclusive(|b| *b == b'\n')
+                .map(|line| line.to_vec())
+                .collect()
+        };
+        let (old, new) = (lines(old), lines(new));
+        let common = matching_lines(&old, &new).len();
+        FileStat::Lines {
+            added: new.len() - common,
+            deleted: old.len() - common,
+        }
+    }
+}
+
+// whether a diff takes two versions of a file as binary: as its "diff" attribute says when it's
+// set or unset, which the "binary" macro does, or else when either has a NUL in its first 8000
+// bytes as git checks
+pub fn is_binary(attribute: &AttributeState, old: &[u8], new: &[u8]) -> bool {
+    let has_nul = |content: &[u8]| content[..content.len().min(8000)].contains(&0);
+    match attribute {
+        AttributeState::Set => false,
+        AttributeState::Unset => true,
+        _ => has_nul(old) || has_nul(new),
+    }
+}
+
+// "<added>\t<deleted>\t<path>" for each file, with "-" for the counts of a binary one
+pub fn write_numstat(out: &mut impl Write, stats: &[(String, FileStat)]) -> io::Result<()> {
+    for (path, stat) in stats {
+        match stat {
+            FileStat::Lines { added, deleted } => {
+                writeln!(out, "{}\t{}\t{}", added, deleted, path)?
+            }
+            FileStat::Binary { .. } => writeln!(out, "-\t-\t{}", path)?,
+            FileStat::Unmerged => writeln!(out, "0\t0\t{}", path)?,
+        }
+    }
+
+    Ok(())
+}
+
+// " <path> | <lines changed> <+++-->" for each file, laid out in `width` columns as git does,
+// and a line summing them up; in `color` the "+" are green and the "-" red
+pub fn write_stat(
+    out: &mut impl Write,
+    stats: &[(String, FileStat)],
+    width: usize,
+    color: bool,
+) -> io::Result<()> {
+    if stats.is_empty() {
+        return Ok(());
+    }
+    let decimal_width = |n: usize| n.to_string().len();
+    let (mut max_change, mut max_len, mut number_width, mut bin_width) = (0, 0, 0, 0);
+    for (path, stat) in stats {
+        max_len = max_len.max(path.chars().count());
+        match stat {
+            // "Unmerged"
+            FileStat::Unmerged => bin_width = bin_width.max(8),
+            // "Bin <old size> -> <new size> bytes", with the counts lined up with "Bin"
+            FileStat::Binary { old_size, new_size } => {
+                bin_width = bin_width.max(14 + decimal_width(*old_size) + decimal_width(*new_size));
+                number_width = 3;
+            }
+            FileStat::Lines { added, deleted } => max_change = max_change.max(added + deleted),
+        }
+    }
+
+    // the name gets what it needs, then " | ", the count and the graph, which has at least 6
+    // columns; when that is too wide the graph gets 3/8 of the width and the name the rest
+    let number_width = number_width.max(decimal_width(max_change));
+    let width = width.max(16 + 6 + number_width);
+    let mut graph_width = match max_change + 4 > bin_width {
+        true => max_change,
+        false => bin_width - 4,
+    };
+    let mut name_width = max_len;
+    if name_width + number_width + 6 + graph_width > width {
+        if graph_width + number_width + 6 > width * 3 / 8 {
+            graph_width = (width * 3 / 8).saturating_sub(number_width + 6).max(6);
+        }
+        if name_width > width - number_width - 6 - graph_width {
+            name_width = width - number_width - 6 - graph_width;
+        } else {
+            graph_width = width - number_width - 6 - name_width;
+        }
+    }
+    // a graph too narrow for the biggest change is scaled down, keeping at least one column
+    // for any change
+    let scale = |n: usize| match n {
+        0 => 0,
+        n => 1 + n * (graph_width - 1) / max_change,
+    };
+
+    let (mut files, mut insertions, mut deletions) = (0, 0, 0);
+    for (path, stat) in stats {
+        // a name too long loses its start, up to a directory if it can
+        let mut name = path.as_str();
+        let mut prefix = "";
+        let mut len = name_width;
+        if name.chars().count() > name_width {
+            prefix = "...";
+            len = len.saturating_sub(3);
+            let skip = name.chars().count() - len;
+            name = &name[name.char_indices().nth(skip).map_or(name.len(), |(i, _)| i)..];
+            if let Some(slash) = name.find('/') {
+                name = &name[slash..];
+            }
+        }
+        let padding = len.saturating_sub(name.chars().count());
+        write!(out, " {}{}{} | ", prefix, name, " ".repeat(padding))?;
+
+        match stat {
+            FileStat::Unmerged => {
+                writeln!(out, "{:>1$}", "Unmerged", number_width)?;
+                continue;
+            }
+            FileStat::Binary { old_size, new_size } => {
+                write!(out, "{:>1$}", "Bin", number_width)?;
+                match (old_size, new_size) {
+                    (0, 0) => writeln!(out)?,
+                    _ => writeln!(out, " {} -> {} bytes", old_size, new_size)?,
+                }
+            }
+            &FileStat::Lines { added, deleted } => {
+                let (mut add, mut del) = (added, deleted);
+                if graph_width <= max_change {
+                    let total = match scale(add + del) {
+                        total if total < 2 && add > 0 && del > 0 => 2,
+                        total => total,
+                    };
+                    if add < del {
+                        add = scale(add);
+                        del = total - add;
+                    } else {
+                        del = scale(del);
+                        add = total - del;
+                    }
+                }
+                let space = if added + deleted > 0 { " " } else { "" };
+                let graph = |sign: &str, n: usize, paint: &str| {
+                    color::paint(color && n > 0, paint, &sign.repeat(n))
+                };
+                writeln!(
+                    out,
+                    "{:>width$}{}{}{}",
+                    added + deleted,
+                    space,
+                    graph("+", add, color::GREEN),
+                    graph("-", del, color::RED),
+                    width = number_width
+                )?;
+                insertions += added;
+                deletions += deleted;
+            }
+        }
+        files += 1;
+    }
+
+    // the counts of lines are left out when they are 0, unless both are and some file changed
+    if files == 0 {
+        return writeln!(out, " 0 files changed");
+    }
+    write!(
+        out,
+        " {} file{} changed",
+        files,
+        if files == 1 { "" } else { "s" }
+    )?;
+    if insertions > 0 || deletions == 0 {
+        let plural = if insertions == 1 { "" } else { "s" };
+        write!(out, ", {} insertion{}(+)", insertions, plural)?;
+    }
+    if deletions > 0 || insertions == 0 {
+        let plural = if deletions == 1 { "" } else { "s" };
+        write!(out, ", {} deletion{}(-)", deletions, plural)?;
+    }
+    writeln!(out)
+}
+
+// the changes from `old` to `new`, both sorted by path as the index sorts them
+pub fn diff_entries(old: &[IndexEntry], new: &[IndexEntry]) -> Vec<Change> {
+    let mut changes = vec![];
+    let (mut old, mut new) = (old.iter().peekable(), new.iter().peekable());
+    loop {
+        let (before, after) = match (old.peek(), new.peek()) {
+            (None, None) => break,
+            (Some(a), Some(b)) => match a.path.as_bytes().cmp(b.path.as_bytes()) {
+                std::cmp::Ordering::Less => (old.next(), None),
+                std::cmp::Ordering::Greater => (None, new.next()),
+                std::cmp::Ordering::Equal => (old.next(), new.next()),
+            },
+            (Some(_), None) => (old.next(), None),
+            (None, Some(_)) => (None, new.next()),
+        };
+        if let (Some(before), Some(after)) = (before, after) {
+            if before.same_content(after) {
+                continue;
+            }
+        }
+        let path = before
+            .or(after)
+            .map(|entry| entry.path.clone())
+            .unwrap_or_default();
+        changes.push(Change {
+            path,
+            old: before.cloned(),
+            new: after.cloned(),
+            unmerged: false,
+        });
+    }
+
+    changes
+}
+
+// whether `path` is one of `pathspecs` or under one of them; no pathspecs match everything
+pub fn matches_pathspec(path: &str, pathspecs: &[String]) -> bool {
+    pathspecs.is_empty()
+        || pathspecs.iter().any(|spec| {
+            let spec = spec.trim_end_matches('/');
+            spec.is_empty()
+                || path == spec
+                || path
+                    .strip_prefix(spec)
+                    .is_some_and(|rest| rest.starts_with('/'))
+        })
+}
+
+// the lines `a` and `b` have in common, as pairs of their indexes in order; this is Myers'
+// algorithm, splitting each problem where its shortest edit paths cross so it runs in linear
+// space
+pub fn matching_lines<T: PartialEq>(a: &[T], b: &[T]) -> Vec<(usize, usize)> {
+    let mut matches = vec![];
+    match_range(a, b, (0, 0), &mut matches);
+    matches
+}
+
+fn match_range<T: PartialEq>(
+    a: &[T],
+    b: &[T],
+    (a_start, b_start): (usize, usize),
+    matches: &mut Vec<(usize, usize)>,
+) {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    matches.extend((0..prefix).map(|i| (a_start + i, b_start + i)));
+    let (a, b) = (&a[prefix..], &b[prefix..]);
+    let suffix = a
+        .iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a, b) = (&a[..a.len() - suffix], &b[..b.len() - suffix]);
+    let (a_start, b_start) = (a_start + prefix, b_start + prefix);
+
+    if !a.is_empty() && !b.is_empty() {
+        let (x, y) = middle(a, b);
+        match_range(&a[..x], &b[..y], (a_start, b_start), matches);
+        match_range(&a[x..], &b[y..], (a_start + x, b_start + y), matches);
+    }
+    let (a_end, b_end) = (a_start + a.len(), b_start + b.len());
+    matches.extend((0..suffix).map(|i| (a_end + i, b_end + i)));
+}
+
+// a point on a shortest edit path from the start of `a` and `b` to their end, found by
+// searching forwards from the start and backwards from the end until the two meet
+fn middle<T: PartialEq>(a: &[T], b: &[T]) -> (usize, usize) {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (n + m + 1) / 2;
+    let offset = max + 1;
+    let length = (2 * max + 3) as usize;
+    // the furthest x reached on each diagonal, counted from the start and from the end
+    let mut forward = vec![-1isize; length];
+    let mut backward = vec![-1isize; length];
+    forward[(offset + 1) as usize] = 0;
+    backward[(offset + 1) as usize] = 0;
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    // diagonals that have run off the edges are not searched again
+    let (mut forward_start, mut forward_end) = (0, 0);
+    let (mut backward_start, mut backward_end) = (0, 0);
+
+    for d in 0..max {
+        let mut k = -d + forward_start;
+        while k <= d - forward_end {
+            let i = (offset + k) as usize;
+            let mut x = if k == -d || (k != d && forward[i - 1] < forward[i + 1]) {
+                forward[i + 1]
+            } else {
+                forward[i - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[i] = x;
+            if x > n {
+                forward_end += 2;
+            } else if y > m {
+                forward_start += 2;
+            } else if odd {
+                let j = offset + delta - k;
+                if j >= 0
+                    && (j as usize) < length
+                    && backward[j as usize] != -1
+                    && x >= n - backward[j as usize]
+                {
+                    return (x as usize, y as usize);
+                }
+            }
+            k += 2;
+        }
+
+        let mut k = -d + backward_start;
+        while k <= d - backward_end {
+            let i = (offset + k) as usize;
+            let mut x = if k == -d || (k != d && backward[i - 1] < backward[i + 1]) {
+                backward[i + 1]
+            } else {
+                backward[i - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[(n - x - 1) as usize] == b[(m - y - 1) as usize] {
+                x += 1;
+                y += 1;
+            }
+            backward[i] = x;
+            if x > n {
+                backward_end += 2;
+            } else if y > m {
+                backward_start += 2;
+            } else if !odd {
+                let j = offset + delta - k;
+                if j >= 0 && (j as usize) < length && forward[j as usize] != -1 {
+                    let forward_x = forward[j as usize];
+                    let forward_y = forward_x - (j - offset);
+                    if forward_x >= n - x {
+                        return (forward_x as usize, forward_y as usize);
+                    }
+                }
+            }
+            k += 2;
+        }
+    }
+
+    // nothing in common at all
+    (a.len(), 0)
+}
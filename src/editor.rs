@@ -0,0 +1,57 @@
+use std::{
+    env,
+    io::{self, IsTerminal, Write},
+    path::Path,
+    process::Command,
+};
+
+use crate::{
+    config::Config,
+    error::{Error, Result},
+};
+
+const DEFAULT_EDITOR: &str = "vi";
+
+// the editor as git picks it: GIT_EDITOR, core.editor, VISUAL unless the terminal is dumb,
+// EDITOR, and then vi, which a dumb terminal can't run
+pub fn editor(config: &Config) -> Result<String> {
+    let dumb = env::var("TERM").map_or(true, |term| term == "dumb");
+    let editor = env::var("GIT_EDITOR")
+        .ok()
+        .or_else(|| config.get("core.editor").map(str::to_string))
+        .or_else(|| env::var("VISUAL").ok().filter(|_| !dumb))
+        .or_else(|| env::var("EDITOR").ok());
+    match editor {
+        Some(editor) => Ok(editor),
+        None if dumb => Err(Error::NoEditor),
+        None => Ok(DEFAULT_EDITOR.to_string()),
+    }
+}
+
+// opens `path` in the editor and waits for it to be closed; the editor is a shell command, to
+// which the path is given as an argument. ":" leaves the file as it is
+pub fn edit(path: &Path, config: &Config) -> Result<()> {
+    let editor = editor(config)?;
+    if editor == ":" {
+        return Ok(());
+    }
+    let waiting = io::stderr().is_terminal();
+    if waiting {
+        eprint!("hint: Waiting for your editor to close the file... ");
+        let _ = io::stderr().flush();
+    }
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", editor))
+        .arg(&editor)
+        .arg(path)
+        .status()?;
+    if waiting {
+        // the hint goes again once the editor is done with the terminal
+        eprint!("\r\x1b[K");
+    }
+    match status.success() {
+        true => Ok(()),
+        false => Err(Error::EditorFailed(editor)),
+    }
+}
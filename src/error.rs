@@ -0,0 +1,300 @@
+use std::{io, path::PathBuf};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error("not a git repository: '{}'", .0.display())]
+    NotARepository(PathBuf),
+    #[error("this operation must be run in a work tree")]
+    NoWorkTree,
+    #[error("not a valid object name {0}")]
+    ObjectNotFound(String),
+    #[error("short object ID {0} is ambiguous")]
+    AmbiguousObjectId(String),
+    #[error("corrupt object: {0}")]
+    CorruptObject(String),
+    #[error("invalid object type \"{0}\"")]
+    InvalidObjectType(String),
+    #[error("object {id} is a {actual}, not a {expected}")]
+    UnexpectedObjectType {
+        id: String,
+        actual: String,
+        expected: String,
+    },
+    #[error("invalid object id {0}")]
+    InvalidObjectId(String),
+    #[error("destination path '{}' already exists and is not an empty directory.", .0.display())]
+    DestinationExists(PathBuf),
+    #[error("invalid URL '{0}'")]
+    InvalidUrl(String),
+    #[error("unable to access '{url}': The requested URL returned error: {status}")]
+    HttpStatus { url: String, status: u16 },
+    #[error("Authentication failed for '{0}'")]
+    Authentication(String),
+    #[error("Proxy authentication failed for '{0}'")]
+    ProxyAuthentication(String),
+    #[error("protocol error: {0}")]
+    Protocol(String),
+    #[error("remote error: {0}")]
+    Remote(String),
+    #[error("Server does not support {0}")]
+    UnsupportedByServer(String),
+    #[error("--unshallow on a complete repository does not make sense")]
+    UnshallowComplete,
+    #[error("invalid pack: {0}")]
+    InvalidPack(String),
+    #[error("no pack files to index.")]
+    NoPackFiles,
+    #[error("invalid date format: {0}")]
+    InvalidDate(String),
+    #[error(
+        "{0} identity unknown\n\n\
+         *** Please tell me who you are.\n\n\
+         Run\n\n  \
+         git config --global user.email \"you@example.com\"\n  \
+         git config --global user.name \"Your Name\"\n\n\
+         to set your account's default identity.\n\
+         Omit --global to set the identity only in this repository.\n"
+    )]
+    IdentityUnknown(String),
+    #[error("Invalid untracked files mode '{0}'")]
+    InvalidUntrackedMode(String),
+    #[error("index file corrupt: {0}")]
+    CorruptIndex(String),
+    #[error("you need to resolve your current index first")]
+    UnmergedIndex,
+    #[error("There is no merge to abort (MERGE_HEAD missing).")]
+    NoMergeToAbort,
+    #[error("Entry '{0}' would be overwritten by merge. Cannot merge.")]
+    WouldOverwrite(String),
+    #[error("Exiting because of an unresolved conflict.")]
+    UnresolvedConflict,
+    #[error("You have not concluded your merge (MERGE_HEAD exists).")]
+    MergeInProgress,
+    #[error("refusing to merge unrelated histories")]
+    UnrelatedHistories,
+    #[error("unknown strategy option: -X{0}")]
+    UnknownStrategyOption(String),
+    #[error("{0} failed to sign the data")]
+    SigningFailed(String),
+    #[error("unsupported value for gpg.format: {0}")]
+    InvalidSigningFormat(String),
+    #[error("either user.signingkey or gpg.ssh.defaultKeyCommand needs to be configured")]
+    NoSigningKey,
+    #[error("gpg.ssh.defaultKeyCommand {0}")]
+    DefaultKeyCommand(String),
+    #[error(
+        "gpg.ssh.allowedSignersFile needs to be configured and exist for ssh signature verification"
+    )]
+    NoAllowedSigners,
+    #[error("tag '{0}' already exists")]
+    TagExists(String),
+    #[error("{0}")]
+    InvalidImportStream(String),
+    #[error("file renaming caused colliding pathnames: {0}")]
+    RenameCollision(String),
+    #[error("replace depth too high for object {0}")]
+    ReplaceDepth(String),
+    #[error("corrupt mark line: {0}")]
+    CorruptMarkLine(String),
+    #[error("encountered signed tag {0}; use --signed-tags=<mode> to handle it")]
+    SignedTag(String),
+    #[error("tag {0} tags unexported object; use --tag-of-filtered-object=<mode> to handle it")]
+    UnexportedTagTarget(String),
+    #[error("Entry '{0}' overlaps with '{0}'.  Cannot bind.")]
+    IndexOverlap(String),
+    #[error("Unable to process path {0}")]
+    UnprocessablePath(String),
+    #[error("ref {0} is not a symbolic ref")]
+    NotASymbolicRef(String),
+    #[error("Refusing to point {0} outside of refs/")]
+    OutsideRefs(String),
+    #[error("cannot lock ref '{0}': {1}")]
+    LockRef(String, String),
+    #[error("Remote branch {0} not found in upstream origin")]
+    RemoteBranchNotFound(String),
+    #[error("invalid --pretty format: {0}")]
+    InvalidPrettyFormat(String),
+    #[error("--follow requires exactly one pathspec")]
+    FollowPathspec,
+    #[error("invalid regex: {0}")]
+    InvalidRegex(String),
+    #[error("invalid regular expression: {0}")]
+    InvalidWordRegex(String),
+    #[error("$HOME not set")]
+    NoHome,
+    #[error("repository '{0}' is not registered")]
+    NotRegistered(String),
+    #[error("failed to run '{0}'; your system might not support '{1}'")]
+    NoScheduler(String, String),
+    #[error("failed to run '{0}'")]
+    CommandFailed(String),
+    #[error("log for '{0}' only has {1} entries")]
+    ReflogTooShort(String, usize),
+    #[error("'{0}' is not a stash-like commit")]
+    NotAStashLikeCommit(String),
+    #[error("'{0}' is not a working tree")]
+    NotAWorktree(String),
+    #[error("'{0}' is a main working tree")]
+    MainWorktree(String),
+    #[error("The main working tree cannot be locked or unlocked")]
+    LockMainWorktree,
+    #[error("'{0}' is already locked{}", .1.as_ref().map_or(String::new(), |reason| format!(", reason: {}", reason)))]
+    WorktreeLocked(String, Option<String>),
+    #[error("'{0}' is not locked")]
+    WorktreeNotLocked(String),
+    #[error("cannot move a locked working tree{}\nuse 'move -f -f' to override or unlock first", .0.as_ref().map_or(";".to_string(), |reason| format!(", lock reason: {}", reason)))]
+    MoveLockedWorktree(Option<String>),
+    #[error("validation failed, cannot move working tree: {0}")]
+    InvalidWorktree(String),
+    #[error("failed to move '{0}' to '{1}': {2}")]
+    MoveFailed(String, String, String),
+    #[error("'{0}' already exists")]
+    AlreadyExists(String),
+    #[error("'{0}' is already checked out at '{1}'")]
+    AlreadyCheckedOut(String, String),
+    #[error("a branch named '{0}' already exists")]
+    BranchExists(String),
+    #[error("invalid reference: {0}")]
+    InvalidReference(String),
+    #[error("key does not contain a section: {0}")]
+    ConfigKeyNoSection(String),
+    #[error("key does not contain variable name: {0}")]
+    ConfigKeyNoName(String),
+    #[error("invalid key: {0}")]
+    InvalidConfigKey(String),
+    #[error("invalid key (newline): {0}")]
+    ConfigKeyNewline(String),
+    #[error("invalid pattern: {0}")]
+    InvalidValuePattern(String),
+    #[error("not in a git directory")]
+    NotInGitDirectory,
+    #[error("There was a problem with the editor '{0}'.")]
+    EditorFailed(String),
+    #[error("Terminal is dumb, but EDITOR unset")]
+    NoEditor,
+    #[error("bad alias.{0} string: {1}")]
+    BadAlias(String, String),
+    #[error("alias loop detected: expansion of '{0}' does not terminate:\n{1}")]
+    AliasLoop(String, String),
+    #[error("reference is not a tree: {0}")]
+    NotATree(String),
+    #[error("unknown commit {0}")]
+    UnknownCommit(String),
+    #[error("you must specify path(s) to restore")]
+    NoPathsToRestore,
+    #[error("could not resolve {0}")]
+    CouldNotResolve(String),
+    #[error("missing branch or commit argument")]
+    MissingBranch,
+    #[error("a branch is expected, got {0}'{1}'\nhint: If you want to detach HEAD at the commit, try again with the --detach option.")]
+    BranchExpected(String, String),
+    #[error("cannot switch branch while merging\nConsider \"git merge --quit\" or \"git worktree add\".")]
+    SwitchWhileMerging,
+    #[error("Missing ')' at the end of pathspec magic in '{0}'")]
+    UnterminatedPathspecMagic(String),
+    #[error("Invalid pathspec magic '{0}' in '{1}'")]
+    InvalidPathspecMagic(String, String),
+    #[error("Unimplemented pathspec magic '{0}' in '{1}'")]
+    UnimplementedPathspecMagic(char, String),
+    #[error("{0}: 'literal' and 'glob' are incompatible")]
+    IncompatiblePathspecMagic(String),
+    #[error("{0}: '{0}' is outside repository")]
+    PathOutsideRepository(String),
+    #[error("cannot describe '{0}'")]
+    CannotDescribe(String),
+    #[error("'{0}' is not a valid branch name")]
+    InvalidBranchName(String),
+    #[error("'{0}' is not a valid tag name.")]
+    InvalidTagName(String),
+    #[error("update_ref failed for ref '{0}': refusing to update ref with bad name '{0}'")]
+    BadRefName(String),
+    #[error("Refusing to set '{0}' to invalid ref '{1}'")]
+    InvalidSymbolicRefTarget(String, String),
+    #[error("invalid timestamp '{0}' given to '--{1}'")]
+    InvalidTimestamp(String, String),
+    #[error("no input file given for in-place editing")]
+    NoInPlaceFile,
+    #[error("no such path '{0}' in HEAD")]
+    NoSuchPathInHead(String),
+    #[error("no such path {0} in {1}")]
+    NoSuchPath(String, String),
+    #[error("-L invalid line number: {0}")]
+    InvalidLineNumber(i64),
+    #[error("-L invalid empty range")]
+    EmptyLineRange,
+    #[error("-L parameter '{0}' starting at line {1}: {2}")]
+    LineRangeNoMatch(String, i64, String),
+    #[error("-L parameter '{0}': {1}")]
+    InvalidFuncnameRegex(String, String),
+    #[error("-L parameter '{0}' matches at EOF")]
+    LineRangeAtEof(String),
+    // a -L range that can't be parsed, which is a usage error
+    #[error("invalid -L range '{0}'")]
+    InvalidLineRange(String),
+    #[error("file {0} has only {1} line{}", if *.1 == 1 { "" } else { "s" })]
+    TooFewLines(String, usize),
+    #[error("corrupt patch at line {0}")]
+    CorruptPatch(usize),
+    #[error("No valid patches in input (allow with \"--allow-empty\")")]
+    NoPatches,
+    #[error("patch failed: {0}:{1}")]
+    PatchFailed(String, usize),
+    #[error("{0}: No such file or directory")]
+    PatchTargetMissing(String),
+    #[error("{0}: already exists in working directory")]
+    PatchTargetExists(String),
+    #[error("cannot apply binary patch to '{0}' without full index line")]
+    BinaryPatchWithoutData(String),
+    #[error("cannot reverse-apply a binary patch without the reverse hunk to '{0}'")]
+    IrreversibleBinaryPatch(String),
+    #[error("the patch applies to '{0}' ({1}), which does not match the current contents.")]
+    BinaryPatchMismatch(String, String),
+    #[error("binary patch to '{0}' creates incorrect result")]
+    BinaryPatchIncorrect(String),
+    #[error("removal patch leaves file contents: {0}")]
+    RemovalLeavesContents(String),
+    #[error("no submodule mapping found in .gitmodules for path '{0}'")]
+    NoSubmoduleMapping(String),
+    #[error("No url found for submodule path '{0}' in .gitmodules")]
+    NoSubmoduleUrl(String),
+    #[error("could not resolve HEAD ref inside the submodule '{0}'")]
+    SubmoduleHeadUnresolved(String),
+    #[error("run_command returned non-zero status for {0}\n.")]
+    SubmoduleCommandFailed(String),
+    #[error(
+        "run_command returned non-zero status while recursing in the nested submodules of {0}\n."
+    )]
+    NestedSubmoduleCommandFailed(String),
+    #[error("cannot strip one component off url '{0}'")]
+    CannotStripUrl(String),
+    #[error(
+        "could not rev-parse split hash {0} from commit {1}\n\
+         hint: hash might be a tag, try fetching it from the subtree repository:\n\
+         hint:    git fetch <subtree-repository> {0}"
+    )]
+    UnresolvedSplit(String, String),
+    #[error("{0}")]
+    Usage(String),
+    // a command's answer given through its exit status alone
+    #[error("exit status {0}")]
+    Exit(i32),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    // git exits with 129 on usage errors and 128 on any other fatal error, and dies of SIGPIPE
+    // (128 + 13) when whatever reads its output goes away
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Usage(_) => 129,
+            Error::Exit(code) => *code,
+            Error::Io(error) if error.kind() == io::ErrorKind::BrokenPipe => 141,
+            _ => 128,
+        }
+    }
+}
@@ -0,0 +1,91 @@
+// the compressed bitmaps index extensions use: a bit count, a word count, the 64-bit words,
+// and the position of the last marker word. Each marker says how many words of all zeros or
+// all ones come next and then how many literal words follow it
+
+const RUNNING_BIT: u64 = 1;
+const RUNNING_LENGTH_BITS: u32 = 32;
+const MAX_RUNNING_LENGTH: u64 = (1 << RUNNING_LENGTH_BITS) - 1;
+const MAX_LITERAL_WORDS: u64 = (1 << 31) - 1;
+const LITERAL_SHIFT: u32 = 1 + RUNNING_LENGTH_BITS;
+
+// the bits of the bitmap at the start of `data`, with how many bytes it takes up
+pub fn read(data: &[u8]) -> Option<(Vec<bool>, usize)> {
+    let bit_count = u32::from_be_bytes(data.get(0..4)?.try_into().ok()?) as usize;
+    let word_count = u32::from_be_bytes(data.get(4..8)?.try_into().ok()?) as usize;
+    let words: Vec<u64> = data
+        .get(8..8 + word_count * 8)?
+        .chunks_exact(8)
+        .map(|word| u64::from_be_bytes(word.try_into().unwrap()))
+        .collect();
+    let end = 8 + word_count * 8 + 4;
+    if data.len() < end {
+        return None;
+    }
+
+    let mut bits = Vec::with_capacity(bit_count);
+    let mut at = 0;
+    while at < words.len() && bits.len() < bit_count {
+        let marker = words[at];
+        at += 1;
+        let running = marker & RUNNING_BIT != 0;
+        let running_length = ((marker >> 1) & MAX_RUNNING_LENGTH) as usize;
+        bits.extend(std::iter::repeat_n(running, running_length * 64));
+        let literals = (marker >> LITERAL_SHIFT) as usize;
+        for word in words.get(at..at + literals)? {
+            bits.extend((0..64).map(|bit| word & (1 << bit) != 0));
+        }
+        at += literals;
+    }
+    bits.resize(bit_count, false);
+
+    Some((bits, end))
+}
+
+pub fn write(bits: &[bool]) -> Vec<u8> {
+    let words: Vec<u64> = bits
+        .chunks(64)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .enumerate()
+                .filter(|(_, set)| **set)
+                .fold(0, |word, (bit, _)| word | 1 << bit)
+        })
+        .collect();
+
+    // runs of empty or full words are counted, and anything else is copied
+    let mut buffer = vec![];
+    let mut marker = 0;
+    let mut at = 0;
+    while at < words.len() || buffer.is_empty() {
+        marker = buffer.len();
+        buffer.push(0);
+        let running = words.get(at) == Some(&u64::MAX);
+        let fill = if running { u64::MAX } else { 0 };
+        let mut running_length = 0;
+        while at < words.len() && words[at] == fill && running_length < MAX_RUNNING_LENGTH {
+            running_length += 1;
+            at += 1;
+        }
+        let mut literals = 0;
+        while at < words.len()
+            && words[at] != 0
+            && words[at] != u64::MAX
+            && literals < MAX_LITERAL_WORDS
+        {
+            buffer.push(words[at]);
+            literals += 1;
+            at += 1;
+        }
+        buffer[marker] = running as u64 | running_length << 1 | literals << LITERAL_SHIFT;
+    }
+
+    let mut data = vec![];
+    data.extend_from_slice(&(bits.len() as u32).to_be_bytes());
+    data.extend_from_slice(&(buffer.len() as u32).to_be_bytes());
+    for word in &buffer {
+        data.extend_from_slice(&word.to_be_bytes());
+    }
+    data.extend_from_slice(&(marker as u32).to_be_bytes());
+    data
+}
@@ -0,0 +1,414 @@
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    io::Write,
+};
+
+use crate::{
+    diff::Change,
+    error::{Error, Result},
+    objects::GitObjectType,
+    pathspec::Pathspecs,
+    repository::{self, Repository},
+    sign,
+};
+
+// fast-export writes history as the stream fast-import reads: each blob and commit once, in an
+// order where everything comes after what it refers to, with marks (":1", ":2", ...) standing
+// in for object ids, then the refs that weren't set by a commit and the annotated tags
+
+// what is done with tags that have a signature, which no longer holds once the tag is imported
+// somewhere its object has another id
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignedTags {
+    Abort,
+    Verbatim,
+    Warn,
+    WarnStrip,
+    Strip,
+}
+
+impl SignedTags {
+    pub fn parse(mode: &str) -> Option<SignedTags> {
+        match mode {
+            "abort" => Some(SignedTags::Abort),
+            "verbatim" => Some(SignedTags::Verbatim),
+            "warn" => Some(SignedTags::Warn),
+            "warn-strip" => Some(SignedTags::WarnStrip),
+            "strip" => Some(SignedTags::Strip),
+            _ => None,
+        }
+    }
+}
+
+pub struct FastExport<'a> {
+    repository: &'a Repository,
+    pub signed_tags: SignedTags,
+    // refer to blobs by id rather than writing them out
+    pub no_data: bool,
+    // give each commit's whole tree rather than how it differs from its first parent
+    pub full_tree: bool,
+    // give tags marks too
+    pub mark_tags: bool,
+    // leave out tags of objects that weren't exported, rather than failing
+    pub drop_filtered_tags: bool,
+    marks: HashMap<String, u32>,
+    last_mark: u32,
+    // commits with marks from an earlier export, which aren't exported again
+    imported: HashSet<String>,
+}
+
+impl<'a> FastExport<'a> {
+    pub fn new(repository: &'a Repository) -> FastExport<'a> {
+        FastExport {
+            repository,
+            signed_tags: SignedTags::Abort,
+            no_data: false,
+            full_tree: false,
+            mark_tags: false,
+            drop_filtered_tags: false,
+            marks: HashMap::new(),
+            last_mark: 0,
+            imported: HashSet::new(),
+        }
+    }
+
+    // takes on the marks ":<mark> <id>" an export wrote out; only the commits among them keep
+    // theirs, but later marks are numbered past all of them
+    pub fn import_marks(&mut self, content: &str) -> Result<()> {
+        for line in content.lines() {
+            let corrupt = || Error::CorruptMarkLine(line.to_string());
+            let (mark, id) = line
+                .strip_prefix(':')
+                .and_then(|line| line.split_once(' '))
+                .ok_or_else(corrupt)?;
+            let mark: u32 = mark
+                .parse()
+                .ok()
+                .filter(|mark| *mark > 0)
+                .ok_or_else(corrupt)?;
+            if id.len() != 40 || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(corrupt());
+            }
+            self.last_mark = self.last_mark.max(mark);
+            let object = self.repository.find_object(id)?;
+            if object.object_type == GitObjectType::Commit {
+                self.marks.insert(object.id.clone(), mark);
+                self.imported.insert(object.id);
+            }
+        }
+        Ok(())
+    }
+
+    // ":<mark> <id>" for each commit with a mark, as import_marks reads them
+    pub fn export_marks(&self) -> Result<String> {
+        let mut commits = vec![];
+        for (id, mark) in &self.marks {
+            if self.repository.find_object(id)?.object_type == GitObjectType::Commit {
+                commits.push((*mark, id));
+            }
+        }
+        commits.sort();
+        Ok(commits
+            .iter()
+            .map(|(mark, id)| format!(":{} {}\n", mark, id))
+            .collect())
+    }
+
+    // exports what `revisions` select, as rev-list takes them. Each commit is written to the
+    // ref it was reached from, the first one given that leads to it
+    pub fn export(&mut self, out: &mut impl Write, revisions: &[String]) -> Result<()> {
+        let repository = self.repository;
+        let (include, exclude) = repository.parse_revisions(revisions)?;
+
+        // the refs given, by the full name of the ref they stand for; a commit given by id is
+        // exported under the name it was given as
+        let mut sources: HashMap<String, String> = HashMap::new();
+        let mut refs: Vec<(String, String)> = vec![];
+        let mut tags: Vec<(String, String)> = vec![];
+        for name in revisions
+            .iter()
+            .flat_map(|revision| positive_names(revision))
+        {
+            let id = repository.rev_parse(name)?;
+            let Some(full_name) = repository.full_reference_name(name) else {
+                let commit = repository.peel(&id, GitObjectType::Commit)?.id;
+                sources.entry(commit).or_insert_with(|| name.to_string());
+                continue;
+            };
+            let object = repository.find_object(&id)?;
+            let peeled = repository.peel(&id, GitObjectType::Commit);
+            match (object.object_type, peeled) {
+                (GitObjectType::Tag, Ok(commit)) => {
+                    tags.push((full_name.clone(), object.id));
+                    sources.entry(commit.id).or_insert(full_name);
+                }
+                (GitObjectType::Commit, _) => {
+                    refs.push((full_name.clone(), object.id.clone()));
+                    sources.entry(object.id).or_insert(full_name);
+                }
+                (GitObjectType::Blob, _) => self.export_blob(out, &object.id)?,
+                (object_type, _) => eprintln!(
+                    "warning: {}: Unexpected object of type {}, skipping.",
+                    name, object_type
+                ),
+            }
+        }
+        refs.sort();
+        refs.dedup_by(|a, b| a.0 == b.0);
+
+        // commits take the ref of the first child that reaches them
+        let commits = repository.rev_list(&include, &exclude)?;
+        let mut parents = HashMap::new();
+        for id in &commits {
+            let commit_parents = repository.commit_parents(id)?;
+            if let Some(source) = sources.get(id).cloned() {
+                for parent in &commit_parents {
+                    sources.entry(parent.clone()).or_insert(source.clone());
+                }
+            }
+            parents.insert(id.clone(), commit_parents);
+        }
+
+        // parents before their children
+        let mut order = repository::topo_order(commits, &parents);
+        order.reverse();
+        for id in &order {
+            if self.imported.contains(id) {
+                continue;
+            }
+            let ref_name = sources.get(id).map_or("HEAD", String::as_str);
+            refs.retain(|(name, _)| name != ref_name);
+            self.export_commit(out, id, ref_name)?;
+        }
+
+        // the refs that no commit was written to are reset to where they point, or deleted
+        // when that wasn't exported
+        for (name, id) in refs.iter().rev() {
+            let from = match self.marks.get(id) {
+                Some(mark) => format!(":{}", mark),
+                None => "0".repeat(40),
+            };
+            write!(out, "reset {}\nfrom {}\n\n", name, from)?;
+        }
+        for (name, id) in tags.iter().rev() {
+            self.export_tag(out, name, id)?;
+        }
+        Ok(())
+    }
+
+    fn next_mark(&mut self, id: &str) -> u32 {
+        self.last_mark += 1;
+        self.marks.insert(id.to_string(), self.last_mark);
+        self.last_mark
+    }
+
+    fn export_blob(&mut self, out: &mut impl Write, id: &str) -> Result<()> {
+        if self.no_data || self.marks.contains_key(id) {
+            return Ok(());
+        }
+        let object = self.repository.find_object(id)?;
+        let mark = self.next_mark(id);
+        write!(out, "blob\nmark :{}\ndata {}\n", mark, object.data.len())?;
+        out.write_all(&object.data)?;
+        writeln!(out)?;
+        Ok(())
+    }
+
+    // the blobs the commit adds or changes, then the commit with its parents that have marks
+    // and how its tree differs from its first parent's, or the whole tree when that parent
+    // wasn't exported
+    fn export_commit(&mut self, out: &mut impl Write, id: &str, ref_name: &str) -> Result<()> {
+        let repository = self.repository;
+        let commit = repository.find_commit(id)?;
+        let first_parent = commit
+            .parents
+            .first()
+            .filter(|parent| self.marks.contains_key(*parent) && !self.full_tree);
+        let old_tree = match first_parent {
+            Some(parent) => Some(repository.find_commit(parent)?.tree),
+            None => None,
+        };
+        let mut changes = repository.tree_changes(
+            old_tree.as_deref(),
+            Some(&commit.tree),
+            &Pathspecs::default(),
+        )?;
+        for change in &changes {
+            if let Some(new) = change.new.as_ref().filter(|new| new.mode != 0o160000) {
+                self.export_blob(out, &new.id)?;
+            }
+        }
+
+        // the message goes out as it is, in whatever encoding it has
+        let object = repository.find_object(id)?;
+        let end = object
+            .data
+            .windows(2)
+            .position(|pair| pair == b"\n\n")
+            .unwrap_or(object.data.len());
+        let headers = String::from_utf8_lossy(&object.data[..end]);
+        let message = object.data.get(end + 2..).unwrap_or_default();
+        let header = |field: &str| {
+            headers
+                .lines()
+                .find(|line| line.starts_with(&format!("{} ", field)))
+                .unwrap_or_default()
+                .to_string()
+        };
+
+        if commit.parents.is_empty() {
+            writeln!(out, "reset {}", ref_name)?;
+        }
+        let mark = self.next_mark(id);
+        write!(
+            out,
+            "commit {}\nmark :{}\n{}\n{}\ndata {}\n",
+            ref_name,
+            mark,
+            header("author"),
+            header("committer"),
+            message.len(),
+        )?;
+        out.write_all(message)?;
+        let marks: Vec<u32> = commit
+            .parents
+            .iter()
+            .filter_map(|parent| self.marks.get(parent).copied())
+            .collect();
+        for (i, mark) in marks.iter().enumerate() {
+            match i {
+                0 => writeln!(out, "from :{}", mark)?,
+                _ => writeln!(out, "merge :{}", mark)?,
+            }
+        }
+        if self.full_tree {
+            writeln!(out, "deleteall")?;
+        }
+
+        changes.sort_by(depth_first);
+        for change in &changes {
+            match &change.new {
+                None => writeln!(out, "D {}", quote_path(&change.path))?,
+                Some(new) => {
+                    let data = match self.marks.get(&new.id) {
+                        Some(mark) if new.mode != 0o160000 => format!(":{}", mark),
+                        _ => new.id.clone(),
+                    };
+                    writeln!(
+                        out,
+                        "M {:o} {} {}",
+                        new.mode,
+                        data,
+                        quote_path(&change.path)
+                    )?;
+                }
+            }
+        }
+        writeln!(out)?;
+        Ok(())
+    }
+
+    fn export_tag(&mut self, out: &mut impl Write, name: &str, id: &str) -> Result<()> {
+        let object = self.repository.find_object(id)?;
+        let content = String::from_utf8_lossy(&object.data);
+        let (headers, message) = content.split_once("\n\n").unwrap_or((&content, ""));
+        let target = headers
+            .lines()
+            .find_map(|line| line.strip_prefix("object "))
+            .ok_or_else(|| Error::CorruptObject(id.to_string()))?;
+        let tagger = headers
+            .lines()
+            .find(|line| line.starts_with("tagger "))
+            .map(|line| format!("{}\n", line))
+            .unwrap_or_default();
+
+        let mut message = message;
+        if let (payload, Some(_)) = sign::split_signature(message) {
+            match self.signed_tags {
+                SignedTags::Abort => return Err(Error::SignedTag(id.to_string())),
+                SignedTags::Warn => eprintln!("warning: exporting signed tag {}", id),
+                SignedTags::Verbatim => {}
+                SignedTags::WarnStrip => {
+                    eprintln!("warning: stripping signature from tag {}", id);
+                    message = payload;
+                }
+                SignedTags::Strip => message = payload,
+            }
+        }
+
+        let from = match self.marks.get(target) {
+            Some(mark) => format!(":{}", mark),
+            None if self.drop_filtered_tags => return Ok(()),
+            None => return Err(Error::UnexportedTagTarget(id.to_string())),
+        };
+        let name = name.strip_prefix("refs/tags/").unwrap_or(name);
+        writeln!(out, "tag {}", name)?;
+        if self.mark_tags {
+            let mark = self.next_mark(id);
+            writeln!(out, "mark :{}", mark)?;
+        }
+        write!(
+            out,
+            "from {}\n{}data {}\n{}\n",
+            from,
+            tagger,
+            message.len(),
+            message
+        )?;
+        Ok(())
+    }
+}
+
+// the names a revision includes: "a..b" includes b, "a...b" both and "^a" neither
+fn positive_names(revision: &str) -> Vec<&str> {
+    if revision.starts_with('^') {
+        return vec![];
+    }
+    let names = match (revision.split_once("..."), revision.split_once("..")) {
+        (Some((a, b)), _) => vec![a, b],
+        (None, Some((_, b))) => vec![b],
+        (None, None) => vec![revision],
+    };
+    names
+        .into_iter()
+        .map(|name| if name.is_empty() { "HEAD" } else { name })
+        .collect()
+}
+
+// paths in the order they're changed in, with everything in a directory before the directory
+// itself, so that a file can replace a directory
+fn depth_first(a: &Change, b: &Change) -> Ordering {
+    let (a, b) = (a.path.as_bytes(), b.path.as_bytes());
+    let len = a.len().min(b.len());
+    a[..len].cmp(&b[..len]).then(b.len().cmp(&a.len()))
+}
+
+// a path with special characters is quoted with C escapes, and one with spaces just quoted
+fn quote_path(path: &str) -> String {
+    let needs_escape = |b: u8| b < 0x20 || b == b'"' || b == b'\\' || b >= 0x80;
+    if !path.bytes().any(needs_escape) {
+        return match path.contains(' ') {
+            true => format!("\"{}\"", path),
+            false => path.to_string(),
+        };
+    }
+    let mut quoted = String::from("\"");
+    for b in path.bytes() {
+        match b {
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            b'\t' => quoted.push_str("\\t"),
+            b'\n' => quoted.push_str("\\n"),
+            b'\r' => quoted.push_str("\\r"),
+            0x07 => quoted.push_str("\\a"),
+            0x08 => quoted.push_str("\\b"),
+            0x0b => quoted.push_str("\\v"),
+            0x0c => quoted.push_str("\\f"),
+            b if needs_escape(b) => quoted.push_str(&format!("\\{:03o}", b)),
+            b => quoted.push(b as char),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
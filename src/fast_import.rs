@@ -0,0 +1,722 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{BufRead, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    error::{Error, Result},
+    index::IndexEntry,
+    objects::{self, GitObjectType},
+    repository::{Repository, EMPTY_TREE},
+};
+
+// fast-import reads the stream fast-export writes, or any tool writes to bring history over from
+// elsewhere: commands one to a line, with the content of blobs and messages inline as "data",
+// and marks standing in for the objects made earlier in the stream. Branches are built up in
+// memory and only written to their refs at a checkpoint or the end
+
+const NULL_ID: &str = "0000000000000000000000000000000000000000";
+
+// how the dates of idents are written
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DateFormat {
+    // "<seconds> <+hhmm>", as git stores them
+    Raw,
+    // the literal "now", for the time of the import
+    Now,
+}
+
+// a branch as the stream has left it: the commit it's at, and the files of that commit's tree
+#[derive(Default)]
+struct Branch {
+    tip: Option<String>,
+    files: BTreeMap<String, (u32, String)>,
+    // reset to the null id, so the ref goes
+    delete: bool,
+}
+
+// how many objects of a kind were made, and how many of them were there already
+#[derive(Default)]
+struct Count {
+    total: usize,
+    duplicates: usize,
+}
+
+pub struct FastImport<'a> {
+    repository: &'a Repository,
+    // update branches even when their new tip doesn't contain the old one
+    pub force: bool,
+    pub date_format: DateFormat,
+    // fail unless the stream ends with "done"
+    pub done: bool,
+    marks: HashMap<u32, String>,
+    branches: BTreeMap<String, Branch>,
+    tags: Vec<(String, String)>,
+    counts: HashMap<GitObjectType, Count>,
+    // where "feature export-marks" asks for the marks to go
+    pub export_marks: Option<String>,
+}
+
+impl<'a> FastImport<'a> {
+    pub fn new(repository: &'a Repository) -> FastImport<'a> {
+        FastImport {
+            repository,
+            force: false,
+            date_format: DateFormat::Raw,
+            done: false,
+            marks: HashMap::new(),
+            branches: BTreeMap::new(),
+            tags: vec![],
+            counts: HashMap::new(),
+            export_marks: None,
+        }
+    }
+
+    // takes on marks ":<mark> <id>" as export_marks writes them
+    pub fn import_marks(&mut self, content: &str) -> Result<()> {
+        for line in content.lines() {
+            let corrupt = || Error::CorruptMarkLine(line.to_string());
+            let (mark, id) = line
+                .strip_prefix(':')
+                .and_then(|line| line.split_once(' '))
+                .ok_or_else(corrupt)?;
+            let mark = mark
+                .parse()
+                .ok()
+                .filter(|mark| *mark > 0)
+                .ok_or_else(corrupt)?;
+            if !is_object_id(id) {
+                return Err(corrupt());
+            }
+            self.marks.insert(mark, id.to_string());
+        }
+        Ok(())
+    }
+
+    pub fn export_marks(&self) -> String {
+        let mut marks: Vec<(&u32, &String)> = self.marks.iter().collect();
+        marks.sort();
+        marks
+            .iter()
+            .map(|(mark, id)| format!(":{} {}\n", mark, id))
+            .collect()
+    }
+
+    // runs the commands of `input`, writing what progress, get-mark and cat-blob answer to
+    // `out`, then updates the refs. Fails if a branch wasn't updated since it would lose
+    // commits, once the rest are
+    pub fn import(&mut self, input: impl BufRead, out: &mut impl Write) -> Result<()> {
+        let mut stream = Stream {
+            input,
+            pending: None,
+        };
+        let mut done = false;
+        while let Some(line) = stream.next_line()? {
+            if let Some(name) = line.strip_prefix("commit ") {
+                self.commit(&mut stream, name)?;
+            } else if line == "blob" {
+                self.blob(&mut stream)?;
+            } else if let Some(name) = line.strip_prefix("tag ") {
+                self.tag(&mut stream, name)?;
+            } else if let Some(name) = line.strip_prefix("reset ") {
+                self.reset(&mut stream, name)?;
+            } else if let Some(text) = line.strip_prefix("progress ") {
+                writeln!(out, "progress {}", text)?;
+                out.flush()?;
+            } else if line == "checkpoint" {
+                self.update_refs()?;
+            } else if let Some(mark) = line.strip_prefix("get-mark ") {
+                let id = self.mark(mark)?;
+                writeln!(out, "{}", id)?;
+                out.flush()?;
+            } else if let Some(data_ref) = line.strip_prefix("cat-blob ") {
+                let object = self.repository.find_object(&self.data_ref(data_ref)?)?;
+                writeln!(out, "{} blob {}", object.id, object.data.len())?;
+                out.write_all(&object.data)?;
+                writeln!(out)?;
+                out.flush()?;
+            } else if let Some(feature) = line.strip_prefix("feature ") {
+                self.feature(feature)?;
+            } else if line.starts_with("option ") {
+                // options for other importers, or ones only given on the command line
+            } else if line == "done" {
+                done = true;
+                break;
+            } else {
+                return Err(stream_error(format!("Unsupported command: {}", line)));
+            }
+        }
+        if self.done && !done {
+            return Err(stream_error("stream ends early".to_string()));
+        }
+        self.update_refs()
+    }
+
+    fn feature(&mut self, feature: &str) -> Result<()> {
+        match feature.split_once('=') {
+            Some(("date-format", format)) => {
+                self.date_format = match format {
+                    "raw" => DateFormat::Raw,
+                    "now" => DateFormat::Now,
+                    _ => return Err(stream_error(format!("unknown date format: {}", format))),
+                }
+            }
+            Some(("export-marks", file)) => self.export_marks = Some(file.to_string()),
+            Some(("import-marks", file)) => {
+                self.import_marks(&std::fs::read_to_string(file)?)?;
+            }
+            None if feature == "done" => self.done = true,
+            None if feature == "force" => self.force = true,
+            None if ["get-mark", "cat-blob"].contains(&feature) => {}
+            _ => {
+                return Err(stream_error(format!(
+                    "This version of fast-import does not support feature {}.",
+                    feature
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    fn blob(&mut self, stream: &mut Stream<impl BufRead>) -> Result<()> {
+        let mark = stream.optional("mark ")?;
+        stream.optional("original-oid ")?;
+        let data = stream.data()?;
+        let id = self.write(GitObjectType::Blob, &data)?;
+        if let Some(mark) = mark {
+            self.set_mark(&mark, &id)?;
+        }
+        Ok(())
+    }
+
+    fn commit(&mut self, stream: &mut Stream<impl BufRead>, name: &str) -> Result<()> {
+        let mark = stream.optional("mark ")?;
+        stream.optional("original-oid ")?;
+        let author = stream.optional("author ")?;
+        let committer = stream
+            .optional("committer ")?
+            .ok_or_else(|| stream_error("Expected committer but didn't get one".to_string()))?;
+        let committer = self.ident(&committer)?;
+        let author = match author {
+            Some(author) => self.ident(&author)?,
+            None => committer.clone(),
+        };
+        let encoding = stream.optional("encoding ")?;
+        let message = stream.data()?;
+
+        let mut branch = self.branches.remove(name).unwrap_or_default();
+        let mut parents = vec![];
+        if let Some(from) = stream.optional("from ")? {
+            branch.files = BTreeMap::new();
+            branch.tip = None;
+            if from != NULL_ID {
+                let id = self.commit_ish(&from)?;
+                branch.files = self.files(&id)?;
+                branch.tip = Some(id);
+            }
+        }
+        parents.extend(branch.tip.clone());
+        while let Some(merge) = stream.optional("merge ")? {
+            parents.push(self.commit_ish(&merge)?);
+        }
+
+        while let Some(line) = stream.next_line()? {
+            if line.is_empty() {
+                break;
+            }
+            if !self.file_change(stream, &mut branch.files, &line)? {
+                stream.pending = Some(line);
+                break;
+            }
+        }
+
+        let entries: Vec<IndexEntry> = branch
+            .files
+            .iter()
+            .map(|(path, (mode, id))| IndexEntry::new(path, *mode, id))
+            .collect();
+        let tree = self.repository.write_tree_entries(&entries)?;
+        let mut content = format!("tree {}\n", tree).into_bytes();
+        for parent in &parents {
+            content.extend(format!("parent {}\n", parent).as_bytes());
+        }
+        content.extend(format!("author {}\ncommitter {}\n", author, committer).as_bytes());
+        if let Some(encoding) = encoding {
+            content.extend(format!("encoding {}\n", encoding).as_bytes());
+        }
+        content.push(b'\n');
+        content.extend(message);
+        let id = self.write(GitObjectType::Commit, &content)?;
+        if let Some(mark) = mark {
+            self.set_mark(&mark, &id)?;
+        }
+        branch.tip = Some(id);
+        branch.delete = false;
+        self.branches.insert(name.to_string(), branch);
+        Ok(())
+    }
+
+    // applies one of a commit's M, D, C, R or deleteall lines to its files; anything else
+    // ends them
+    fn file_change(
+        &mut self,
+        stream: &mut Stream<impl BufRead>,
+        files: &mut BTreeMap<String, (u32, String)>,
+        line: &str,
+    ) -> Result<bool> {
+        if line == "deleteall" {
+            files.clear();
+        } else if let Some(rest) = line.strip_prefix("M ") {
+            let (mode, rest) = rest.split_once(' ').ok_or_else(|| corrupt_line(line))?;
+            let (data_ref, path) = rest.split_once(' ').ok_or_else(|| corrupt_line(line))?;
+            let (path, _) = parse_path(path, true).ok_or_else(|| corrupt_line(line))?;
+            let mode = match mode {
+                "644" | "100644" => 0o100644,
+                "755" | "100755" => 0o100755,
+                "120000" => 0o120000,
+                "160000" => 0o160000,
+                "40000" | "040000" => 0o040000,
+                _ => return Err(stream_error(format!("Corrupt mode: {}", line))),
+            };
+            let id = match data_ref {
+                "inline" => {
+                    let data = stream.data()?;
+                    self.write(GitObjectType::Blob, &data)?
+                }
+                data_ref => self.data_ref(data_ref)?,
+            };
+            remove_path(files, &path);
+            match mode {
+                0o040000 if id == EMPTY_TREE => {}
+                0o040000 => {
+                    let prefix = if path.is_empty() {
+                        String::new()
+                    } else {
+                        format!("{}/", path)
+                    };
+                    for file in self.repository.tree_files(&id, &prefix)? {
+                        files.insert(file.path, (file.mode, file.id));
+                    }
+                }
+                _ => {
+                    files.insert(path, (mode, id));
+                }
+            }
+        } else if let Some(path) = line.strip_prefix("D ") {
+            let (path, _) = parse_path(path, true).ok_or_else(|| corrupt_line(line))?;
+            remove_path(files, &path);
+        } else if let Some((rename, rest)) = line
+            .strip_prefix("R ")
+            .map(|rest| (true, rest))
+            .or_else(|| line.strip_prefix("C ").map(|rest| (false, rest)))
+        {
+            let (source, rest) = parse_path(rest, false).ok_or_else(|| corrupt_line(line))?;
+            let (target, _) = rest
+                .strip_prefix(' ')
+                .and_then(|rest| parse_path(rest, true))
+                .ok_or_else(|| corrupt_line(line))?;
+            // the file itself, or everything in the directory
+            let moved: Vec<(String, (u32, String))> = files
+                .iter()
+                .filter_map(|(path, entry)| {
+                    let rest = match path == &source {
+                        true => "",
+                        false => path.strip_prefix(&format!("{}/", source))?,
+                    };
+                    let path = match rest.is_empty() {
+                        true => target.clone(),
+                        false => format!("{}/{}", target, rest),
+                    };
+                    Some((path, entry.clone()))
+                })
+                .collect();
+            if moved.is_empty() {
+                return Err(stream_error(format!("Path {} not in branch", source)));
+            }
+            if rename {
+                remove_path(files, &source);
+            }
+            remove_path(files, &target);
+            files.extend(moved);
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    fn tag(&mut self, stream: &mut Stream<impl BufRead>, name: &str) -> Result<()> {
+        let mark = stream.optional("mark ")?;
+        let from = stream
+            .optional("from ")?
+            .ok_or_else(|| stream_error("Expected from command".to_string()))?;
+        let object = match from.starts_with(':') {
+            true => self.mark(&from)?,
+            false => self.commit_ish(&from)?,
+        };
+        stream.optional("original-oid ")?;
+        let tagger = match stream.optional("tagger ")? {
+            Some(tagger) => Some(self.ident(&tagger)?),
+            None => None,
+        };
+        let message = stream.data()?;
+
+        let object_type = self.repository.find_object(&object)?.object_type;
+        let mut content = format!("object {}\ntype {}\ntag {}\n", object, object_type, name);
+        if let Some(tagger) = tagger {
+            content += &format!("tagger {}\n", tagger);
+        }
+        let mut content = content.into_bytes();
+        content.push(b'\n');
+        content.extend(message);
+        let id = self.write(GitObjectType::Tag, &content)?;
+        if let Some(mark) = mark {
+            self.set_mark(&mark, &id)?;
+        }
+        self.tags.push((format!("refs/tags/{}", name), id));
+        Ok(())
+    }
+
+    fn reset(&mut self, stream: &mut Stream<impl BufRead>, name: &str) -> Result<()> {
+        let mut branch = Branch::default();
+        match stream.optional("from ")? {
+            Some(from) if from == NULL_ID => branch.delete = true,
+            Some(from) => {
+                let id = self.commit_ish(&from)?;
+                branch.files = self.files(&id)?;
+                branch.tip = Some(id);
+            }
+            None => {}
+        }
+        // a blank line may end it
+        match stream.next_line()? {
+            Some(line) if line.is_empty() => {}
+            line => stream.pending = line,
+        }
+        self.branches.insert(name.to_string(), branch);
+        Ok(())
+    }
+
+    // points the branches and tags at what the stream made of them; a branch that would lose
+    // commits is left alone, with a warning, unless forced
+    fn update_refs(&mut self) -> Result<()> {
+        let mut failed = false;
+        for (name, branch) in &self.branches {
+            let old = self.repository.find_reference(name);
+            let Some(tip) = &branch.tip else {
+                if branch.delete && old.is_some() {
+                    self.repository.delete_reference(name)?;
+                }
+                continue;
+            };
+            if let Some(old) = old.filter(|old| old != tip) {
+                if !self.force && !self.repository.is_ancestor(&old, tip) {
+                    eprintln!(
+                        "warning: Not updating {} (new tip {} does not contain {})",
+                        name, tip, old
+                    );
+                    failed = true;
+                    continue;
+                }
+            }
+            self.repository.set_reference(name, tip)?;
+        }
+        for (name, id) in &self.tags {
+            self.repository.set_reference(name, id)?;
+        }
+        if let Some(file) = &self.export_marks {
+            std::fs::write(file, self.export_marks())?;
+        }
+        match failed {
+            true => Err(Error::Exit(1)),
+            false => Ok(()),
+        }
+    }
+
+    // what git prints when it's done, less its memory use
+    pub fn statistics(&self) -> String {
+        let line = "-".repeat(69);
+        let count = |object_type| {
+            self.counts
+                .get(&object_type)
+                .map_or((0, 0), |count: &Count| (count.total, count.duplicates))
+        };
+        let types = [
+            ("blobs  ", GitObjectType::Blob),
+            ("commits", GitObjectType::Commit),
+            ("tags   ", GitObjectType::Tag),
+        ];
+        let (total, duplicates) = types.iter().fold((0, 0), |(total, duplicates), (_, kind)| {
+            let (t, d) = count(*kind);
+            (total + t, duplicates + d)
+        });
+        let mut text = format!(
+            "fast-import statistics:\n{}\nTotal objects:   {:>10} ({:>10} duplicates                  )\n",
+            line, total, duplicates
+        );
+        for (name, kind) in types {
+            let (total, duplicates) = count(kind);
+            text += &format!(
+                "      {}:   {:>10} ({:>10} duplicates)\n",
+                name, total, duplicates
+            );
+        }
+        text += &format!(
+            "Total branches:  {:>10}\n      marks:     {:>10} ({:>10} unique    )\n{}\n",
+            self.branches.len(),
+            self.marks.len(),
+            self.marks.len(),
+            line
+        );
+        text
+    }
+
+    fn write(&mut self, object_type: GitObjectType, data: &[u8]) -> Result<String> {
+        let (id, _) = objects::encode_object(object_type, data);
+        let count = self.counts.entry(object_type).or_default();
+        count.total += 1;
+        if self.repository.has_object(&id) {
+            count.duplicates += 1;
+            return Ok(id);
+        }
+        self.repository.write_object(object_type, data)
+    }
+
+    fn set_mark(&mut self, mark: &str, id: &str) -> Result<()> {
+        let number = mark
+            .strip_prefix(':')
+            .and_then(|mark| mark.parse().ok())
+            .ok_or_else(|| stream_error(format!("Missing ':' in mark: {}", mark)))?;
+        self.marks.insert(number, id.to_string());
+        Ok(())
+    }
+
+    fn mark(&self, mark: &str) -> Result<String> {
+        mark.strip_prefix(':')
+            .and_then(|number| number.parse::<u32>().ok())
+            .and_then(|number| self.marks.get(&number).cloned())
+            .ok_or_else(|| stream_error(format!("mark {} not declared", mark)))
+    }
+
+    // a blob by mark or id
+    fn data_ref(&self, data_ref: &str) -> Result<String> {
+        match data_ref.starts_with(':') {
+            true => self.mark(data_ref),
+            false if is_object_id(data_ref) => Ok(data_ref.to_ascii_lowercase()),
+            false => Err(stream_error(format!("Invalid dataref: {}", data_ref))),
+        }
+    }
+
+    // a commit by mark, id, a branch of the stream, or a ref of the repository, which
+    // "<branch>^0" names rather than the branch of the stream
+    fn commit_ish(&self, name: &str) -> Result<String> {
+        let id = if name.starts_with(':') {
+            self.mark(name)?
+        } else if let Some(tip) = self.branches.get(name).and_then(|b| b.tip.clone()) {
+            tip
+        } else {
+            let name = name.strip_suffix("^0").unwrap_or(name);
+            self.repository.rev_parse(name).map_err(|_| {
+                stream_error(format!("Invalid ref name or SHA1 expression: {}", name))
+            })?
+        };
+        Ok(self.repository.peel(&id, GitObjectType::Commit)?.id)
+    }
+
+    fn files(&self, commit: &str) -> Result<BTreeMap<String, (u32, String)>> {
+        Ok(self
+            .repository
+            .tree_files(commit, "")?
+            .into_iter()
+            .map(|file| (file.path, (file.mode, file.id)))
+            .collect())
+    }
+
+    // "<name> <<email>> <when>", with the date checked, or filled in for "now"
+    fn ident(&self, ident: &str) -> Result<String> {
+        let (person, when) = ident
+            .rsplit_once("> ")
+            .filter(|(person, _)| person.contains('<'))
+            .ok_or_else(|| stream_error(format!("Missing < in ident string: {}", ident)))?;
+        let invalid = || stream_error(format!("Invalid raw date \"{}\" in ident: {}", when, ident));
+        match self.date_format {
+            DateFormat::Now if when == "now" => {
+                let seconds = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |time| time.as_secs());
+                Ok(format!("{}> {} +0000", person, seconds))
+            }
+            DateFormat::Now => Err(stream_error(format!(
+                "Date in ident must be 'now': {}",
+                ident
+            ))),
+            DateFormat::Raw => {
+                let (seconds, offset) = when.split_once(' ').ok_or_else(invalid)?;
+                let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+                let valid_offset = offset.len() == 5
+                    && matches!(offset.as_bytes()[0], b'+' | b'-')
+                    && digits(&offset[1..]);
+                match digits(seconds) && valid_offset {
+                    true => Ok(ident.to_string()),
+                    false => Err(invalid()),
+                }
+            }
+        }
+    }
+}
+
+// the input a line at a time, with one line of lookahead
+struct Stream<R> {
+    input: R,
+    pending: Option<String>,
+}
+
+impl<R: BufRead> Stream<R> {
+    // the next line without its newline, skipping comments; nothing at the end of the input
+    fn next_line(&mut self) -> Result<Option<String>> {
+        if let Some(line) = self.pending.take() {
+            return Ok(Some(line));
+        }
+        loop {
+            let mut line = vec![];
+            if self.input.read_until(b'\n', &mut line)? == 0 {
+                return Ok(None);
+            }
+            if line.last() == Some(&b'\n') {
+                line.pop();
+            }
+            if line.first() != Some(&b'#') {
+                return Ok(Some(String::from_utf8_lossy(&line).into_owned()));
+            }
+        }
+    }
+
+    // the rest of the next line if it starts with `prefix`, which it's left to the next read
+    // otherwise
+    fn optional(&mut self, prefix: &str) -> Result<Option<String>> {
+        let Some(line) = self.next_line()? else {
+            return Ok(None);
+        };
+        match line.strip_prefix(prefix) {
+            Some(rest) => Ok(Some(rest.to_string())),
+            None => {
+                self.pending = Some(line);
+                Ok(None)
+            }
+        }
+    }
+
+    // "data <count>" and that many bytes, or "data <<<delimiter>" and the lines up to it;
+    // a newline may follow either
+    fn data(&mut self) -> Result<Vec<u8>> {
+        let line = self
+            .next_line()?
+            .ok_or_else(|| stream_error("Expected 'data n' command, found end".to_string()))?;
+        let Some(size) = line.strip_prefix("data ") else {
+            return Err(stream_error(format!(
+                "Expected 'data n' command, found: {}",
+                line
+            )));
+        };
+        if let Some(delimiter) = size.strip_prefix("<<") {
+            let mut data = vec![];
+            loop {
+                let mut line = vec![];
+                if self.input.read_until(b'\n', &mut line)? == 0 {
+                    return Err(stream_error(format!(
+                        "EOF in data (terminator '{}' not found)",
+                        delimiter
+                    )));
+                }
+                if line.strip_suffix(b"\n").unwrap_or(&line) == delimiter.as_bytes() {
+                    self.skip_newline()?;
+                    return Ok(data);
+                }
+                data.extend(line);
+            }
+        }
+        let size: usize = size
+            .parse()
+            .map_err(|_| stream_error(format!("Invalid data size: {}", size)))?;
+        let mut data = vec![0; size];
+        self.input
+            .read_exact(&mut data)
+            .map_err(|_| stream_error(format!("EOF in data ({} bytes remaining)", size)))?;
+        self.skip_newline()?;
+        Ok(data)
+    }
+
+    fn skip_newline(&mut self) -> Result<()> {
+        if self.input.fill_buf()?.first() == Some(&b'\n') {
+            self.input.consume(1);
+        }
+        Ok(())
+    }
+}
+
+// a path at the start of `text`, C-quoted or not, and what follows it; an unquoted one runs to
+// the end of the line when it's the last thing on it and to the next space otherwise
+fn parse_path(text: &str, last: bool) -> Option<(String, &str)> {
+    let Some(quoted) = text.strip_prefix('"') else {
+        let end = match last {
+            true => text.len(),
+            false => text.find(' ')?,
+        };
+        return Some((text[..end].to_string(), &text[end..]));
+    };
+    let mut path = vec![];
+    let mut bytes = quoted.bytes().enumerate();
+    while let Some((i, b)) = bytes.next() {
+        match b {
+            b'"' => {
+                return Some((
+                    String::from_utf8_lossy(&path).into_owned(),
+                    &quoted[i + 1..],
+                ));
+            }
+            b'\\' => {
+                let (_, escaped) = bytes.next()?;
+                path.push(match escaped {
+                    b'n' => b'\n',
+                    b't' => b'\t',
+                    b'r' => b'\r',
+                    b'a' => 0x07,
+                    b'b' => 0x08,
+                    b'f' => 0x0c,
+                    b'v' => 0x0b,
+                    b'0'..=b'3' => {
+                        let (_, second) = bytes.next()?;
+                        let (_, third) = bytes.next()?;
+                        let digits = [escaped, second, third];
+                        u8::from_str_radix(std::str::from_utf8(&digits).ok()?, 8).ok()?
+                    }
+                    other => other,
+                });
+            }
+            b => path.push(b),
+        }
+    }
+    None
+}
+
+// takes out a file, or everything in a directory
+fn remove_path(files: &mut BTreeMap<String, (u32, String)>, path: &str) {
+    if path.is_empty() {
+        files.clear();
+        return;
+    }
+    let prefix = format!("{}/", path);
+    files.retain(|file, _| file != path && !file.starts_with(&prefix));
+}
+
+fn is_object_id(id: &str) -> bool {
+    id.len() == 40 && id.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+fn corrupt_line(line: &str) -> Error {
+    stream_error(format!("Corrupt file change: {}", line))
+}
+
+fn stream_error(message: String) -> Error {
+    Error::InvalidImportStream(message)
+}
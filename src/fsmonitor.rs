@@ -0,0 +1,86 @@
+use std::{
+    path::Path,
+    process::{Command, Stdio},
+    time::SystemTime,
+};
+
+use crate::config::{self, Config};
+
+// core.fsmonitor names a hook that watches the work tree and says which paths changed since a
+// token it handed out earlier, so that the files it doesn't name needn't even be stat'ed.
+// Version 2 of the protocol runs `<hook> 2 <token>`, which prints a new token and then the
+// paths, each ended with a NUL; version 1 runs `<hook> 1 <nanoseconds>` and prints only the
+// paths, the time of the query standing in for the token. "/" among the paths means the hook
+// can't tell
+
+// a token no hook handed out, which has it report everything
+pub const FAKE_TOKEN: &str = "builtin:fake";
+
+// the hook command, unless core.fsmonitor is unset or asks for git's own daemon
+pub fn hook(config: &Config) -> Option<&str> {
+    config
+        .get("core.fsmonitor")
+        .filter(|hook| !hook.is_empty() && config::parse_bool(hook).is_none())
+}
+
+// the token to ask with next time, and the paths changed since `token`, or None for the paths
+// when anything may have; no token at all means the hook failed
+pub fn query(
+    config: &Config,
+    hook: &str,
+    work_tree: &Path,
+    token: Option<&str>,
+) -> Option<(String, Option<Vec<String>>)> {
+    let version = config.get("core.fsmonitorHookVersion");
+    if version != Some("1") {
+        match run(hook, work_tree, "2", token.unwrap_or(FAKE_TOKEN)) {
+            Some(output) => {
+                let mut fields = output.split('\0');
+                let token = fields.next().unwrap_or_default().to_string();
+                let changed = fields.filter(|path| !path.is_empty());
+                return Some((token, paths(changed)));
+            }
+            None if version == Some("2") => return None,
+            None => {}
+        }
+    }
+
+    // version 1 tokens are times, and a version 2 token is as good as none
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .ok()?
+        .as_nanos()
+        .to_string();
+    let since = token.filter(|token| token.bytes().all(|b| b.is_ascii_digit()));
+    let output = run(hook, work_tree, "1", since.unwrap_or("0"))?;
+    let changed = output.split('\0').filter(|path| !path.is_empty());
+    Some((now, since.and_then(|_| paths(changed))))
+}
+
+fn paths<'a>(changed: impl Iterator<Item = &'a str>) -> Option<Vec<String>> {
+    let mut paths = vec![];
+    for path in changed {
+        if path == "/" {
+            return None;
+        }
+        paths.push(path.to_string());
+    }
+    Some(paths)
+}
+
+fn run(hook: &str, work_tree: &Path, version: &str, token: &str) -> Option<String> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", hook))
+        .arg(hook)
+        .args([version, token])
+        .current_dir(work_tree)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .ok()?;
+    match output.status.success() {
+        true => Some(String::from_utf8_lossy(&output.stdout).into_owned()),
+        false => None,
+    }
+}
@@ -1,502 +1,1854 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fs,
-    io::{self, BufRead, BufReader, Read, Seek, Write},
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
     path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
 };
 
+#[cfg(feature = "async")]
+use crate::http::AsyncHttpClient;
 use sha1::Digest;
 
-use reqwest::blocking as reqwest;
-
-#[derive(Debug)]
-pub struct Ref {
-    pub name: String,
-    pub hash: String,
-}
+use crate::{
+    attributes::Attributes,
+    config::Config,
+    convert::Converter,
+    error::{Error, Result},
+    http::HttpClient,
+    index::{Index, IndexEntry},
+    objects::{self, GitObjectType, TreeEntry},
+    pack::{self, inflate, object_type_from_pack, pack_read_error, parse_object_header},
+    parallel, paths,
+    pktline::{Packet, PktLineReader, PktLineWriter},
+    progress::{NoProgress, Progress},
+    repository::{self, Repository},
+};
 
 #[derive(Debug)]
 pub struct Refs {
     pub refs: HashMap<String, String>,
-    services: Vec<String>,
-    head: String,
+    pub capabilities: Vec<String>,
+    pub head: Option<String>,
 }
 
-#[derive(Clone)]
-pub enum GitObjectType {
-    Blob,
-    Commit,
-    Tag,
-    Tree,
-}
-
-impl From<u8> for GitObjectType {
-    fn from(obj_type: u8) -> Self {
-        match obj_type {
-            1 => GitObjectType::Commit,
-            2 => GitObjectType::Tree,
-            3 => GitObjectType::Blob,
-            4 => GitObjectType::Tag,
-            _ => panic!("unknown object type: {}", obj_type),
-        }
-    }
-}
-
-impl std::fmt::Display for GitObjectType {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            GitObjectType::Blob => write!(f, "blob"),
-            GitObjectType::Commit => write!(f, "commit"),
-            GitObjectType::Tag => write!(f, "tag"),
-            GitObjectType::Tree => write!(f, "tree"),
-        }
+impl Refs {
+    // the ref a symbolic ref points at, from "symref=<name>:<target>" capabilities
+    pub fn symref(&self, name: &str) -> Option<&str> {
+        self.capabilities.iter().find_map(|capability| {
+            let (symref, target) = capability.strip_prefix("symref=")?.split_once(':')?;
+            (symref == name).then_some(target)
+        })
     }
 }
 
 pub struct GitObject {
-    id: String,
     content: Vec<u8>,
-    size: u128,
     object_type: GitObjectType,
 }
 
 impl GitObject {
     fn new(content: Vec<u8>, object_type: GitObjectType) -> GitObject {
-        let size = content.len() as u128;
-        let header = format!("{} {}\0", object_type, size).into_bytes();
-        // println!("header: {:?}", String::from_utf8_lossy(&header[..]));
-
-        let content_with_header = [&header[..], &content[..]].concat();
-        let mut hasher = sha1::Sha1::new();
-        hasher.update(&content_with_header);
-        let hash = hasher.finalize();
         GitObject {
-            id: hex::encode(hash.as_slice()),
             content,
-            size,
             object_type,
         }
     }
 
-    fn persist(&self, object_dir: &str) {
-        let id = &self.id;
-        let subfolder = &id[0..2];
-        let filename = &id[2..];
-        let path = format!("{}/{}", object_dir, subfolder);
-        if fs::create_dir_all(&path).is_err() {
-            panic!("Failed to create .git/objects/{} directory", subfolder);
-        }
-        let header = format!("{} {}\0", self.object_type, self.size).into_bytes();
-        let content = [&header[..], &self.content[..]].concat();
-        let mut compressed = Vec::new();
-        let mut compressor =
-            flate2::write::ZlibEncoder::new(&mut compressed, flate2::Compression::fast());
-        compressor.write_all(&content).unwrap();
-        compressor.finish().unwrap();
-        fs::write(format!("{}/{}", path, filename), compressed).unwrap();
-    }
-}
-
-pub fn get_refs(git_url: &str) -> Result<Refs, Box<dyn std::error::Error>> {
-    let body = reqwest::get(format!("{}/info/refs?service=git-upload-pack", git_url).as_str())?;
-    let body = body.bytes().unwrap();
-    let body = String::from_utf8_lossy(&body[..]);
-    let parts = body.split('\n').skip(1);
-    let mut services = Vec::new();
-    let mut head = String::new();
-    let refs = parts
-        .filter(|part| *part != "0000")
-        .map(|part| {
-            let parts: Vec<&str> = part.split('\0').collect();
-            let mut ref_name = String::new();
-            let mut ref_hash = String::new();
-            if parts.len() == 2 {
-                // println!("{}", parts[0]);
-                let (header, current_ref_name) = parts[0][4..].split_once(' ').unwrap();
-                ref_hash = header[4..].to_string();
-                ref_name = current_ref_name.to_string();
-                head = ref_hash.clone();
-                services = parts[1].split(' ').map(|x| x.to_string()).collect();
-            } else {
-                let (head, current_ref_name) = parts[0].split_once(' ').unwrap();
-                ref_hash = head[4..].to_string();
-                ref_name = current_ref_name.to_string();
+    fn persist(&self, object_dir: &path::Path) -> Result<()> {
+        objects::write_loose_object(object_dir, self.object_type, &self.content)?;
+
+        Ok(())
+    }
+}
+
+pub fn get_refs(client: &mut HttpClient) -> Result<Refs> {
+    let body = client.get("info/refs?service=git-upload-pack")?;
+
+    parse_ref_advertisement(&body)
+}
+
+#[cfg(feature = "async")]
+pub async fn get_refs_async(client: &mut AsyncHttpClient) -> Result<Refs> {
+    let body = client.get("info/refs?service=git-upload-pack").await?;
+
+    parse_ref_advertisement(&body)
+}
+
+pub fn parse_ref_advertisement(body: &[u8]) -> Result<Refs> {
+    let mut reader = PktLineReader::new(body);
+
+    let mut packet = reader.read_packet()?;
+    // smart HTTP prefixes the advertisement with a "# service=..." section
+    if let Some(service) = packet.as_ref().and_then(|packet| packet.as_text()) {
+        if let Some(service) = service.strip_prefix("# service=") {
+            if service != "git-upload-pack" {
+                return Err(Error::Protocol(format!(
+                    "unexpected service in ref advertisement: {}",
+                    service
+                )));
             }
+            reader.read_until_flush()?;
+            packet = reader.read_packet()?;
+        }
+    }
 
-            (ref_name, ref_hash)
-        })
-        .collect();
+    let mut refs = HashMap::new();
+    let mut capabilities = Vec::new();
+    let mut first = true;
+    while let Some(Packet::Data(line)) = packet {
+        let line = String::from_utf8(line)
+            .map_err(|_| Error::Protocol("ref advertisement is not valid UTF-8".to_string()))?;
+        let line = line.strip_suffix('\n').unwrap_or(&line);
+        let (line, caps) = match line.split_once('\0') {
+            Some((line, caps)) if first => (line, Some(caps)),
+            Some(_) => {
+                return Err(Error::Protocol(
+                    "capabilities after the first ref".to_string(),
+                ))
+            }
+            None => (line, None),
+        };
+        if let Some(caps) = caps {
+            capabilities = caps.split_whitespace().map(|x| x.to_string()).collect();
+        }
+        first = false;
 
-    Ok(Refs {
+        let (ref_hash, ref_name) = line.split_once(' ').ok_or_else(|| {
+            Error::Protocol(format!("malformed ref advertisement line: {}", line))
+        })?;
+        if ref_hash.len() != 40 || !ref_hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(Error::InvalidObjectId(ref_hash.to_string()));
+        }
+        // empty repositories advertise only their capabilities
+        if ref_name != "capabilities^{}" {
+            refs.insert(ref_name.to_string(), ref_hash.to_string());
+        }
+
+        packet = reader.read_packet()?;
+    }
+
+    let mut refs = Refs {
         refs,
-        services,
-        head,
-    })
+        capabilities,
+        head: None,
+    };
+    refs.head = refs.symref("HEAD").map(|head| head.to_string());
+
+    Ok(refs)
+}
+
+// the depth that asks for all of history, which makes a shallow repository complete
+pub const INFINITE_DEPTH: u32 = 0x7fffffff;
+
+// how much history a shallow clone, or a fetch into a shallow repository, brings in
+#[derive(Clone, Debug, Default)]
+pub struct Deepen {
+    // the commits from each tip, or with `relative`, beyond those that are shallow now
+    pub depth: Option<u32>,
+    pub relative: bool,
+    // only commits made after this time
+    pub since: Option<i64>,
+    // no commits reachable from these refs
+    pub not: Vec<String>,
+}
+
+// the commits a repository has without their parents, which the server can't count on it
+// having the history of, and the history asked for
+#[derive(Default)]
+pub struct Shallow {
+    pub commits: Vec<String>,
+    pub deepen: Option<Deepen>,
+}
+
+// the commits a deepening fetch leaves shallow, and those whose parents it brought in
+#[derive(Default)]
+pub struct ShallowUpdate {
+    pub shallow: Vec<String>,
+    pub unshallow: Vec<String>,
 }
 
 pub struct Repo {
-    objects: HashMap<String, GitObject>,
     head: String,
     remote: String,
-    git_dir: String,
+    git_dir: path::PathBuf,
+    work_tree: path::PathBuf,
     refs: HashMap<String, String>,
+    services: Vec<String>,
+    head_ref: Option<String>,
+    progress: Box<dyn Progress>,
+    bare: bool,
+    mirror: bool,
+    branch: Option<String>,
+    single_branch: bool,
+    deepen: Option<Deepen>,
 }
 
 impl Repo {
-    pub fn new(remote: &str, git_dir: &str) -> Repo {
+    pub fn new(remote: &str, git_dir: &path::Path, work_tree: &path::Path) -> Repo {
         Repo {
-            objects: HashMap::new(),
             head: String::new(),
             remote: remote.to_string(),
-            git_dir: git_dir.to_string(),
+            git_dir: git_dir.to_path_buf(),
+            work_tree: work_tree.to_path_buf(),
             refs: HashMap::new(),
+            services: Vec::new(),
+            head_ref: None,
+            progress: Box::new(NoProgress),
+            bare: false,
+            mirror: false,
+            branch: None,
+            single_branch: false,
+            deepen: None,
         }
     }
 
-    pub fn clone(&mut self) {
-        let refs = get_refs(&self.remote).unwrap();
-        self.refs = refs.refs;
+    pub fn with_progress(mut self, progress: Box<dyn Progress>) -> Repo {
+        self.progress = progress;
+        self
+    }
+
+    // clones into `git_dir` alone, with the remote's branches and tags as local ones and
+    // nothing checked out
+    pub fn bare(mut self) -> Repo {
+        self.bare = true;
+        self
+    }
+
+    // a bare clone that keeps every ref the remote has, under the same name
+    pub fn mirror(mut self) -> Repo {
+        self.bare = true;
+        self.mirror = true;
+        self
+    }
+
+    // checks out `name`, a branch or a tag, instead of the remote's HEAD; a fetch takes only
+    // that ref, into FETCH_HEAD alone
+    pub fn branch(mut self, name: &str) -> Repo {
+        self.branch = Some(name.to_string());
+        self
+    }
+
+    // fetches only the history of the branch that is checked out
+    pub fn single_branch(mut self) -> Repo {
+        self.single_branch = true;
+        self
+    }
+
+    // cuts the history cloned short, or deepens that of a shallow repository being fetched
+    // into
+    pub fn deepen(mut self, deepen: Deepen) -> Repo {
+        self.deepen = Some(deepen);
+        self
+    }
+
+    fn report_refs(&mut self) {
+        let count = self.refs.len() as u64;
+        self.progress.start("Enumerating refs", Some(count));
+        self.progress.update(count);
+        self.progress.finish();
+    }
+
+    pub fn clone(&mut self) -> Result<()> {
+        if let Some(path) = local_source(&self.remote) {
+            return self.clone_local(&path);
+        }
+        let mut client = HttpClient::new(&self.remote, &Config::default())?;
+        self.set_refs(get_refs(&mut client)?);
+        if !self.refs.contains_key("HEAD") {
+            return self.init_empty();
+        }
+        self.select_branch()?;
+        let hashes = self.refs.values().cloned().collect();
+        let update = self.receive(&mut client, hashes, vec![])?;
+        self.update_shallow(&update)?;
+        self.finish_clone()
+    }
+
+    #[cfg(feature = "async")]
+    pub async fn clone_async(&mut self) -> Result<()> {
+        if let Some(path) = local_source(&self.remote) {
+            return self.clone_local(&path);
+        }
+        let mut client = AsyncHttpClient::new(&self.remote, &Config::default())?;
+        self.set_refs(get_refs_async(&mut client).await?);
+        if !self.refs.contains_key("HEAD") {
+            return self.init_empty();
+        }
+        self.select_branch()?;
         let hashes = self.refs.values().cloned().collect();
-        self.objects = get_objects(&self.remote, hashes).unwrap();
-        // println!("{:#?}", self.refs);
-        self.persist_objects();
-        self.populate_refs();
-        self.checkout_head();
-    }
-
-    fn checkout_head(&mut self) {
-        let commit_object = &self.objects[&self.head];
-        let tree_object = String::from_utf8(commit_object.content.clone()).unwrap();
-        let (tree_line, _) = tree_object.split_once('\n').unwrap();
-        let tree = tree_line.replace("tree ", "");
-        let mut pool = vec![(self.git_dir.clone(), tree)];
-        while !pool.is_empty() {
-            let (path, tree_id) = pool.pop().unwrap();
-            // println!("treeid = {tree_id} - {path}");
-            let entries = parse_tree_object(&self.objects[&tree_id].content);
-            for (mode, name, sha) in entries {
-                let path = format!("{path}/{name}");
-                // println!("mode: {mode} - name: {name} - sha: {sha}");
+        let update = self.receive_async(&mut client, hashes, vec![]).await?;
+        self.update_shallow(&update)?;
+        self.finish_clone()
+    }
+
+    // a repository on this machine is read directly: its refs stand in for an advertisement
+    // and its objects are hardlinked, or copied where links aren't possible
+    fn clone_local(&mut self, path: &path::Path) -> Result<()> {
+        if self.deepen.is_some() {
+            eprintln!("warning: --depth is ignored in local clones; use file:// instead.");
+        }
+        let source = Repository::open(path)?;
+        // the config records a plain path as an absolute one
+        if !self.remote.starts_with("file://") {
+            self.remote = path.canonicalize()?.display().to_string();
+        }
+        let mut refs: HashMap<String, String> = source.references().collect();
+        for (name, id) in refs.clone() {
+            if !name.starts_with("refs/tags/") {
+                continue;
+            }
+            if let Ok(commit) = source.peel(&id, GitObjectType::Commit) {
+                if commit.id != id {
+                    refs.insert(format!("{}^{{}}", name), commit.id);
+                }
+            }
+        }
+        if let Some(head) = source.head() {
+            refs.insert("HEAD".to_string(), head);
+        }
+        self.set_refs(Refs {
+            refs,
+            capabilities: vec![],
+            head: source.head_target(),
+        });
+
+        link_objects(&source.object_dir(), &self.object_dir()?)?;
+        if !self.refs.contains_key("HEAD") {
+            return self.init_empty();
+        }
+        self.select_branch()?;
+        self.finish_clone()
+    }
+
+    pub fn fetch(&mut self) -> Result<()> {
+        let config = Config::load(&self.git_dir());
+        let mut client = HttpClient::new(&self.remote, &config)?;
+        self.set_refs(get_refs(&mut client)?);
+        if self.branch.is_some() {
+            self.single_branch = true;
+            self.select_branch()?;
+        }
+        let hashes = self.wanted_objects();
+
+        if hashes.is_empty() {
+            println!("Already up to date.");
+        } else {
+            let haves = self.local_haves();
+            let update = self.receive(&mut client, hashes, haves)?;
+            self.update_shallow(&update)?;
+        }
+
+        self.finish_fetch()
+    }
+
+    #[cfg(feature = "async")]
+    pub async fn fetch_async(&mut self) -> Result<()> {
+        let config = Config::load(&self.git_dir());
+        let mut client = AsyncHttpClient::new(&self.remote, &config)?;
+        self.set_refs(get_refs_async(&mut client).await?);
+        if self.branch.is_some() {
+            self.single_branch = true;
+            self.select_branch()?;
+        }
+        let hashes = self.wanted_objects();
+
+        if hashes.is_empty() {
+            println!("Already up to date.");
+        } else {
+            let haves = self.local_haves();
+            let update = self.receive_async(&mut client, hashes, haves).await?;
+            self.update_shallow(&update)?;
+        }
+
+        self.finish_fetch()
+    }
+
+    // the refs the remote advertises, without fetching anything
+    pub fn remote_refs(&self) -> Result<Refs> {
+        let config = Config::load(&self.git_dir());
+        get_refs(&mut HttpClient::new(&self.remote, &config)?)
+    }
+
+    #[cfg(feature = "async")]
+    pub async fn remote_refs_async(&self) -> Result<Refs> {
+        let config = Config::load(&self.git_dir());
+        get_refs_async(&mut AsyncHttpClient::new(&self.remote, &config)?).await
+    }
+
+    // fetches the objects reachable from `hashes`; when the transfer fails part way, what
+    // has been received is kept and the tips that are still incomplete are asked for again
+    fn receive(
+        &mut self,
+        client: &mut HttpClient,
+        mut hashes: Vec<String>,
+        mut haves: Vec<String>,
+    ) -> Result<ShallowUpdate> {
+        let object_dir = self.object_dir()?;
+        let shallow = self.shallow();
+        let mut attempt = 1;
+        loop {
+            let result = get_objects(
+                client,
+                hashes.clone(),
+                haves.clone(),
+                &self.services,
+                &shallow,
+                &object_dir,
+                self.progress.as_mut(),
+            );
+            match result {
+                Err(error) if attempt < MAX_ATTEMPTS && is_transient(&error) => {
+                    eprintln!("warning: {}; retrying", error);
+                    if !prepare_retry(&object_dir, &mut hashes, &mut haves) {
+                        return Ok(ShallowUpdate::default());
+                    }
+                    std::thread::sleep(Duration::from_secs(attempt));
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    #[cfg(feature = "async")]
+    async fn receive_async(
+        &mut self,
+        client: &mut AsyncHttpClient,
+        mut hashes: Vec<String>,
+        mut haves: Vec<String>,
+    ) -> Result<ShallowUpdate> {
+        let object_dir = self.object_dir()?;
+        let shallow = self.shallow();
+        let mut attempt = 1;
+        loop {
+            let result = get_objects_async(
+                client,
+                hashes.clone(),
+                haves.clone(),
+                &self.services,
+                &shallow,
+                &object_dir,
+                self.progress.as_mut(),
+            )
+            .await;
+            match result {
+                Err(error) if attempt < MAX_ATTEMPTS && is_transient(&error) => {
+                    eprintln!("warning: {}; retrying", error);
+                    if !prepare_retry(&object_dir, &mut hashes, &mut haves) {
+                        return Ok(ShallowUpdate::default());
+                    }
+                    tokio::time::sleep(Duration::from_secs(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn set_refs(&mut self, refs: Refs) {
+        self.refs = refs.refs;
+        self.services = refs.capabilities;
+        self.head_ref = refs.head;
+        self.report_refs();
+    }
+
+    // points HEAD at the branch or tag asked for, and leaves out every other ref when only
+    // one branch is cloned
+    fn select_branch(&mut self) -> Result<()> {
+        if let Some(name) = &self.branch {
+            let ref_name = [
+                format!("refs/heads/{}", name),
+                format!("refs/tags/{}", name),
+            ]
+            .into_iter()
+            .find(|ref_name| self.refs.contains_key(ref_name))
+            .ok_or_else(|| Error::RemoteBranchNotFound(name.clone()))?;
+            // a tag is checked out at the commit it points at
+            let id = self
+                .refs
+                .get(&format!("{}^{{}}", ref_name))
+                .unwrap_or(&self.refs[&ref_name])
+                .clone();
+            self.refs.insert("HEAD".to_string(), id);
+            self.head_ref = Some(ref_name);
+        }
+        if self.head_ref.is_none() {
+            let head = &self.refs["HEAD"];
+            self.head_ref = self
+                .refs
+                .iter()
+                .find(|(ref_name, id)| ref_name.starts_with("refs/heads/") && *id == head)
+                .map(|(ref_name, _)| ref_name.clone());
+        }
+
+        if self.single_branch {
+            let selected = self.head_ref.clone().unwrap_or_default();
+            let peeled = format!("{}^{{}}", selected);
+            self.refs.retain(|ref_name, _| {
+                ref_name == "HEAD" || *ref_name == selected || *ref_name == peeled
+            });
+        }
+
+        Ok(())
+    }
+
+    fn init_empty(&self) -> Result<()> {
+        eprintln!("warning: You appear to have cloned an empty repository.");
+        fs::create_dir_all(self.git_dir().join("objects"))?;
+        fs::create_dir_all(self.git_dir().join("refs"))?;
+        fs::write(self.git_dir().join("HEAD"), "ref: refs/heads/master\n")?;
+        self.write_config()
+    }
+
+    fn finish_clone(&mut self) -> Result<()> {
+        self.write_config()?;
+        self.populate_refs()?;
+        if self.bare {
+            return Ok(());
+        }
+        self.checkout_head()
+    }
+
+    fn write_config(&self) -> Result<()> {
+        let mut config = format!(
+            "[core]\n\trepositoryformatversion = 0\n\tfilemode = true\n\tbare = {}\n\
+             [remote \"origin\"]\n\turl = {}\n",
+            self.bare, self.remote
+        );
+        let selected = self.head_ref.as_deref().filter(|_| self.single_branch);
+        match selected.and_then(|ref_name| ref_name.strip_prefix("refs/heads/")) {
+            _ if self.mirror => config.push_str("\tfetch = +refs/*:refs/*\n\tmirror = true\n"),
+            _ if self.bare => {}
+            Some(branch) => config.push_str(&format!(
+                "\tfetch = +refs/heads/{}:refs/remotes/origin/{}\n",
+                branch, branch
+            )),
+            None => match selected.filter(|ref_name| ref_name.starts_with("refs/tags/")) {
+                Some(tag) => config.push_str(&format!("\tfetch = +{}:{}\n", tag, tag)),
+                None => config.push_str("\tfetch = +refs/heads/*:refs/remotes/origin/*\n"),
+            },
+        }
+        fs::write(self.git_dir().join("config"), config)?;
+        Ok(())
+    }
+
+    // what a fetch into a shallow repository tells the server
+    fn shallow(&self) -> Shallow {
+        Shallow {
+            commits: repository::read_shallow(&self.git_dir()),
+            deepen: self.deepen.clone(),
+        }
+    }
+
+    // .git/shallow as the server says it is after a deepening fetch: the commits it was
+    // already shallow at and the new ones, without those it now has the parents of
+    fn update_shallow(&self, update: &ShallowUpdate) -> Result<()> {
+        if self.deepen.is_none() {
+            return Ok(());
+        }
+        let mut commits = repository::read_shallow(&self.git_dir());
+        commits.extend(update.shallow.iter().cloned());
+        commits.retain(|id| !update.unshallow.contains(id));
+        repository::write_shallow(&self.git_dir(), &commits)
+    }
+
+    // the advertised tips to ask for: those we don't have yet, or all of them when the
+    // history behind them is to be deepened
+    fn wanted_objects(&self) -> Vec<String> {
+        match self.deepen {
+            Some(_) => self.refs.values().cloned().collect(),
+            None => self.missing_objects(),
+        }
+    }
+
+    // the advertised tips we don't have yet
+    fn missing_objects(&self) -> Vec<String> {
+        let object_dir = self.git_dir().join("objects");
+        self.refs
+            .values()
+            .filter(|hash| !objects::has_object(&object_dir, hash))
+            .cloned()
+            .collect()
+    }
+
+    // what was fetched, for scripts and `merge FETCH_HEAD`: the branch the remote's HEAD is on
+    // comes first as the one to merge, then the other branches and any new tags
+    fn write_fetch_head(&self) -> Result<()> {
+        let head = self.head_ref.clone().or_else(|| {
+            let head = self.refs.get("HEAD")?;
+            self.refs
+                .iter()
+                .find(|(ref_name, id)| ref_name.starts_with("refs/heads/") && *id == head)
+                .map(|(ref_name, _)| ref_name.clone())
+        });
+        let mut names: Vec<&String> = self.refs.keys().collect();
+        names.sort();
+        let remote = self.remote.trim_end_matches('/');
+        let remote = remote.strip_suffix(".git").unwrap_or(remote);
+
+        let mut merge = String::new();
+        let mut others = String::new();
+        for kind in ["branch", "tag"] {
+            for name in &names {
+                let short = match kind {
+                    "branch" => name.strip_prefix("refs/heads/"),
+                    _ => name.strip_prefix("refs/tags/"),
+                };
+                let Some(short) = short.filter(|short| !short.ends_with("^{}")) else {
+                    continue;
+                };
+                // tags only count when they are new or asked for
+                let local = paths::to_native(&self.git_dir(), name);
+                if kind == "tag"
+                    && head.as_ref() != Some(*name)
+                    && fs::read_to_string(local).is_ok_and(|id| id.trim() == self.refs[*name])
+                {
+                    continue;
+                }
+                let line = |status| {
+                    format!(
+                        "{}\t{}\t{} '{}' of {}\n",
+                        self.refs[*name], status, kind, short, remote
+                    )
+                };
+                match head.as_ref() == Some(*name) {
+                    true => merge.push_str(&line("")),
+                    false => others.push_str(&line("not-for-merge")),
+                }
+            }
+        }
+        fs::write(self.git_dir().join("FETCH_HEAD"), merge + &others)?;
+
+        Ok(())
+    }
+
+    // FETCH_HEAD, and the remote-tracking refs unless a single ref was asked for, which only
+    // goes into FETCH_HEAD
+    fn finish_fetch(&self) -> Result<()> {
+        self.write_fetch_head()?;
+        match &self.branch {
+            Some(name) => {
+                let kind = match &self.head_ref {
+                    Some(ref_name) if ref_name.starts_with("refs/tags/") => "tag",
+                    _ => "branch",
+                };
+                println!("From {}", self.remote);
+                println!(" * {:<17} {:<10} -> FETCH_HEAD", kind, name);
+                Ok(())
+            }
+            None => self.update_remote_refs(),
+        }
+    }
+
+    fn update_remote_refs(&self) -> Result<()> {
+        println!("From {}", self.remote);
+        for (ref_name, ref_hash) in &self.refs {
+            let local_ref = if let Some(branch) = ref_name.strip_prefix("refs/heads/") {
+                format!("refs/remotes/origin/{}", branch)
+            } else if ref_name.starts_with("refs/tags/") && !ref_name.ends_with("^{}") {
+                ref_name.clone()
+            } else {
+                continue;
+            };
+
+            let p = paths::to_native(&self.git_dir(), &local_ref);
+            let old_hash = fs::read_to_string(&p).unwrap_or_default();
+            if old_hash.trim() == ref_hash {
+                continue;
+            }
+            fs::create_dir_all(p.parent().unwrap())?;
+            fs::write(&p, format!("{}\n", ref_hash))?;
+            println!(" * {} -> {}", ref_name, local_ref);
+        }
+
+        Ok(())
+    }
+
+    // local commits, newest first, that the server can use to find a common base
+    fn local_haves(&self) -> Vec<String> {
+        let object_dir = self.git_dir().join("objects");
+        let mut tips = vec![];
+        let mut dirs = vec![self.git_dir().join("refs")];
+        while let Some(dir) = dirs.pop() {
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                if entry.path().is_dir() {
+                    dirs.push(entry.path());
+                } else if let Ok(content) = fs::read_to_string(entry.path()) {
+                    if !content.starts_with("ref: ") {
+                        tips.push(content.trim().to_string());
+                    }
+                }
+            }
+        }
+
+        let mut haves: Vec<String> = vec![];
+        let mut queue: std::collections::VecDeque<String> = tips.into();
+        while let Some(id) = queue.pop_front() {
+            if haves.contains(&id) || haves.len() >= MAX_HAVES {
+                continue;
+            }
+            let Some(object) = read_object(&object_dir, &id) else {
+                continue;
+            };
+            String::from_utf8_lossy(&object.content)
+                .lines()
+                .take_while(|line| !line.is_empty())
+                .filter_map(|line| line.strip_prefix("parent "))
+                .for_each(|parent| queue.push_back(parent.to_string()));
+            haves.push(id);
+        }
+
+        haves
+    }
+
+    fn object(&self, id: &str) -> Result<GitObject> {
+        read_object(&self.git_dir().join("objects"), id)
+            .ok_or_else(|| Error::ObjectNotFound(id.to_string()))
+    }
+
+    fn object_dir(&self) -> Result<path::PathBuf> {
+        let object_dir = self.git_dir().join("objects");
+        fs::create_dir_all(&object_dir)?;
+        Ok(object_dir)
+    }
+
+    fn checkout_head(&mut self) -> Result<()> {
+        let commit_object = self.object(&self.head)?;
+        let tree_object = String::from_utf8_lossy(&commit_object.content);
+        let tree = tree_object
+            .lines()
+            .next()
+            .and_then(|line| line.strip_prefix("tree "))
+            .ok_or_else(|| Error::CorruptObject(self.head.clone()))?
+            .to_string();
+        let mut files = vec![];
+        let mut pool = vec![(String::new(), tree)];
+        while let Some((dir, tree_id)) = pool.pop() {
+            let entries = objects::parse_tree(&self.object(&tree_id)?.content)?;
+            for TreeEntry {
+                mode,
+                name,
+                id: sha,
+            } in entries
+            {
+                let path = if dir.is_empty() {
+                    name
+                } else {
+                    format!("{dir}/{name}")
+                };
                 if mode == "40000" {
-                    fs::create_dir_all(&path);
+                    fs::create_dir_all(paths::to_native(&self.work_tree, &path))?;
                     pool.push((path, sha));
                 } else {
-                    let blob_object = &self.objects[&sha];
-                    // println!("blob {sha}: {path}");
-                    fs::write(path, &blob_object.content);
+                    files.push((path, mode, sha));
                 }
             }
         }
-    }
 
-    fn populate_refs(&mut self) {
-        let refs_dir = format!("{}/.git/refs", self.git_dir);
+        // attributes come from the tree being checked out, not the (empty) working tree
+        let mut attribute_files = HashMap::new();
+        for (path, _, sha) in &files {
+            let dir = match path.rsplit_once('/') {
+                Some((dir, ".gitattributes")) => dir,
+                None if path == ".gitattributes" => "",
+                _ => continue,
+            };
+            let content = String::from_utf8_lossy(&self.object(sha)?.content).to_string();
+            attribute_files.insert(dir.to_string(), content);
+        }
+        let git_dir = self.git_dir();
+        let config = Config::load(&git_dir);
+        let workers = checkout_workers(&config, files.len());
+        let converter = Mutex::new(Converter::new(
+            Attributes::from_files(attribute_files, &git_dir),
+            &config,
+            &self.work_tree,
+        ));
 
-        if fs::create_dir_all(refs_dir).is_err() {
-            panic!("Failed to create .git/refs directory");
+        let ignore_case = paths::is_case_insensitive(&self.work_tree);
+        let mut written: HashMap<String, String> = HashMap::new();
+        let mut collided = vec![];
+        if ignore_case {
+            for (path, ..) in &files {
+                if let Some(other) = written.insert(path.to_lowercase(), path.clone()) {
+                    collided.push(other);
+                    collided.push(path.clone());
+                }
+            }
         }
 
-        self.head = self
-            .refs
-            .iter()
-            .find(|(ref_name, _)| *ref_name == "HEAD")
-            .unwrap()
-            .1
-            .clone();
+        self.progress
+            .start("Updating files", Some(files.len() as u64));
+        let done = AtomicU64::new(0);
+        let progress = Mutex::new(&mut self.progress);
+        let object_dir = git_dir.join("objects");
+        let work_tree = &self.work_tree;
+        let entries = parallel::map(&files, workers, |(path, mode, sha)| {
+            let entry = checkout_file(&object_dir, work_tree, &converter, path, mode, sha)?;
+            let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+            progress
+                .lock()
+                .unwrap_or_else(|error| error.into_inner())
+                .update(done);
+            Ok(entry)
+        })?;
+        self.progress.finish();
+
+        // the index starts out as the tree checked out, with what the files look like now
+        let mut index = Index::default();
+        for entry in entries {
+            index.add(entry);
+        }
+        index.write(&git_dir.join("index"))?;
 
-        self.refs.iter().for_each(|(ref_name, ref_hash)| {
-            if ref_name == "HEAD"
-                || ref_name.starts_with("refs/pull")
-                || ref_name.starts_with("refs/tags")
+        if !collided.is_empty() {
+            collided.sort();
+            collided.dedup();
+            eprintln!(
+                "warning: the following paths have collided (e.g. case-sensitive paths\n\
+                 on a case-insensitive filesystem) and only one from the same\n\
+                 colliding group is in the working tree:\n"
+            );
+            for path in collided {
+                eprintln!("  '{}'", path);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn git_dir(&self) -> path::PathBuf {
+        self.git_dir.clone()
+    }
+
+    fn populate_refs(&mut self) -> Result<()> {
+        let refs_dir = self.git_dir().join("refs");
+        fs::create_dir_all(refs_dir)?;
+
+        self.head = self.refs["HEAD"].clone();
+        // a tag leaves HEAD detached
+        let detached = self
+            .head_ref
+            .as_ref()
+            .is_some_and(|head_ref| head_ref.starts_with("refs/tags/"));
+        if detached {
+            fs::write(self.git_dir().join("HEAD"), format!("{}\n", self.head))?;
+        }
+
+        for (ref_name, ref_hash) in &self.refs {
+            if ref_name == "HEAD" || ref_name.ends_with("^{}") {
+                continue;
+            }
+            // a mirror copies every ref as it is
+            if self.mirror {
+                let path = paths::to_native(&self.git_dir(), ref_name);
+                fs::create_dir_all(path.parent().unwrap())?;
+                fs::write(path, ref_hash)?;
+                if self.head_ref.as_ref() == Some(ref_name) {
+                    fs::write(self.git_dir().join("HEAD"), format!("ref: {}", ref_name))?;
+                }
+                continue;
+            }
+            if ref_name.starts_with("refs/pull") {
+                continue;
+            }
+            // a bare clone keeps tags as they are and has no remote-tracking refs; otherwise only
+            // a tag that is checked out is kept
+            let checked_out = self.head_ref.as_ref() == Some(ref_name);
+            if ref_name.starts_with("refs/tags") && !self.bare && !checked_out
+                || ref_name.starts_with("refs/remotes") && self.bare
             {
-                return;
+                continue;
             }
 
-            if ref_hash == &self.head {
-                fs::write(
-                    format!("{}/.git/HEAD", self.git_dir),
-                    format!("ref: {}", ref_name),
-                )
-                .unwrap();
+            let is_head = match &self.head_ref {
+                Some(head_ref) => head_ref == ref_name && !detached,
+                None => ref_hash == &self.head,
+            };
+            if is_head {
+                fs::write(self.git_dir().join("HEAD"), format!("ref: {}", ref_name))?;
             }
-            let p = format!("{}/.git/{}", self.git_dir, ref_name);
-            let p = path::Path::new(&p);
+            let p = paths::to_native(&self.git_dir(), ref_name);
             let parent = p.parent().unwrap();
             if !parent.exists() {
-                fs::create_dir_all(p.parent().unwrap()).unwrap();
+                fs::create_dir_all(parent)?;
             }
 
-            let mut path = p.to_str().unwrap().to_string();
+            let mut path = p.clone();
             let mut content = ref_hash.clone();
-            // println!("{:?}", parent);
             if ref_name.starts_with("refs/remotes") {
-                path = format!("{}/HEAD", parent.to_str().unwrap());
+                path = parent.join("HEAD");
                 content = format!("ref: {}", ref_name);
             }
 
-            fs::write(path, content).unwrap();
-        });
-    }
-    fn persist_objects(&mut self) {
-        let object_dir = format!("{}/.git/objects", self.git_dir);
-        if fs::create_dir_all(&object_dir).is_err() {
-            panic!("Failed to create .git/objects directory");
+            fs::write(path, content)?;
         }
 
-        self.objects
-            .iter()
-            .for_each(|(_, obj)| obj.persist(&object_dir));
+        Ok(())
+    }
+}
+
+// the path a remote names when it is a path or a file:// URL rather than a server
+fn local_source(remote: &str) -> Option<path::PathBuf> {
+    match remote.strip_prefix("file://") {
+        Some(path) => Some(path::PathBuf::from(path)),
+        None if !remote.contains("://") => Some(path::PathBuf::from(remote)),
+        None => None,
     }
 }
 
+fn link_objects(from: &path::Path, to: &path::Path) -> Result<()> {
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            fs::create_dir_all(&target)?;
+            link_objects(&entry.path(), &target)?;
+        } else if !target.exists() && fs::hard_link(entry.path(), &target).is_err() {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+
+    Ok(())
+}
+
+// fetches the objects reachable from `hashes` into `object_dir`, and with `shallow` deepening,
+// where the server says the history fetched now ends
 pub fn get_objects(
-    git_url: &str,
+    client: &mut HttpClient,
     hashes: Vec<String>,
-) -> Result<HashMap<String, GitObject>, Box<dyn std::error::Error>> {
-    let mut objects = HashMap::new();
-    let mut wants = hashes
-        .iter()
-        .map(|x| {
-            let want = format!("want {}", x);
-            let length = want.len() + 5;
-            format!("{:04x}{}", length, want)
-        })
-        .collect::<Vec<String>>();
-    wants.dedup();
-    let wants = wants[0..].join("\n");
-    let client = reqwest::Client::new();
-    let body = format!("{}{}", wants, "\n00000009done\n");
-    let url = format!("{}/git-upload-pack", git_url);
-    let res = client
-        .post(url)
-        .header("Content-Type", "application/x-git-upload-pack-request")
-        .body(body)
-        .send()?;
+    haves: Vec<String>,
+    services: &[String],
+    shallow: &Shallow,
+    object_dir: &path::Path,
+    progress: &mut dyn Progress,
+) -> Result<ShallowUpdate> {
+    let wants = want_request(hashes, services, shallow)?;
+    // a deepening fetch first asks where the new shallow commits are, which the server
+    // then repeats before each answer to come
+    let mut update = ShallowUpdate::default();
+    if shallow.deepen.is_some() {
+        update = read_shallow_update(upload_pack(client, wants.clone())?)?;
+    }
+    let mut common = vec![];
+    for batch in haves.chunks(HAVES_PER_ROUND) {
+        let body = have_request(&wants, common.iter().chain(batch), false)?;
+        if read_acks(upload_pack(client, body)?, &mut common)? {
+            break;
+        }
+    }
+
+    let body = have_request(&wants, common.iter(), true)?;
+    let response = upload_pack(client, body)?;
+
+    read_pack_response(response, services, object_dir, progress)?;
+    Ok(update)
+}
+
+#[cfg(feature = "async")]
+pub async fn get_objects_async(
+    client: &mut AsyncHttpClient,
+    hashes: Vec<String>,
+    haves: Vec<String>,
+    services: &[String],
+    shallow: &Shallow,
+    object_dir: &path::Path,
+    progress: &mut dyn Progress,
+) -> Result<ShallowUpdate> {
+    use tokio::io::AsyncWriteExt;
+
+    let wants = want_request(hashes, services, shallow)?;
+    let mut update = ShallowUpdate::default();
+    if shallow.deepen.is_some() {
+        let response = upload_pack_async(client, wants.clone())
+            .await?
+            .bytes()
+            .await?;
+        update = read_shallow_update(&response[..])?;
+    }
+    let mut common = vec![];
+    for batch in haves.chunks(HAVES_PER_ROUND) {
+        let body = have_request(&wants, common.iter().chain(batch), false)?;
+        let response = upload_pack_async(client, body).await?.bytes().await?;
+        if read_acks(&response[..], &mut common)? {
+            break;
+        }
+    }
+
+    // the pack is parsed with blocking reads, so spool the response to disk first rather
+    // than holding a thread while the network is slow
+    let body = have_request(&wants, common.iter(), true)?;
+    let mut response = upload_pack_async(client, body).await?;
+    let pack_dir = object_dir.join("pack");
+    fs::create_dir_all(&pack_dir)?;
+    let spool_path = pack_dir.join(format!("tmp_response_{}", std::process::id()));
+    let mut spool = tokio::fs::File::create(&spool_path).await?;
+    let mut received = 0;
+    progress.start("Receiving pack", None);
+    let spooled = async {
+        while let Some(chunk) = response.chunk().await? {
+            spool.write_all(&chunk).await?;
+            received += chunk.len() as u64;
+            progress.bytes(received);
+        }
+        spool.flush().await?;
+        Ok::<_, Error>(())
+    }
+    .await;
+    progress.finish();
+    let result = spooled.and_then(|_| {
+        let spool = fs::File::open(&spool_path)?;
+        read_pack_response(spool, services, object_dir, progress)
+    });
+    let _ = fs::remove_file(&spool_path);
 
-    let res_bytes = res.bytes()?;
+    result.map(|_| update)
+}
+
+// the "want" lines of an upload-pack request, asking for the capabilities we understand,
+// then the commits we are shallow at and how far to deepen the history
+fn want_request(hashes: Vec<String>, services: &[String], shallow: &Shallow) -> Result<Vec<u8>> {
+    let supports = |capability: &str| services.iter().any(|service| service == capability);
+    let mut capabilities = vec![];
+    if supports("multi_ack_detailed") {
+        capabilities.push("multi_ack_detailed");
+    }
+    capabilities.extend(side_band(services));
+    if let Some(deepen) = &shallow.deepen {
+        let required = [
+            ("shallow", "shallow clients", true),
+            ("deepen-relative", "--deepen", deepen.relative),
+            ("deepen-since", "--shallow-since", deepen.since.is_some()),
+            ("deepen-not", "--shallow-exclude", !deepen.not.is_empty()),
+        ];
+        for (capability, what, needed) in required {
+            if needed && !supports(capability) {
+                return Err(Error::UnsupportedByServer(what.to_string()));
+            }
+            if needed && capability != "shallow" {
+                capabilities.push(capability);
+            }
+        }
+    }
+
+    let mut hashes = hashes;
+    hashes.sort();
+    hashes.dedup();
+    let mut wants = PktLineWriter::new(Vec::new());
+    for (i, x) in hashes.iter().enumerate() {
+        if i == 0 && !capabilities.is_empty() {
+            wants.write_line(&format!("want {} {}", x, capabilities.join(" ")))?;
+        } else {
+            wants.write_line(&format!("want {}", x))?;
+        }
+    }
+    for id in &shallow.commits {
+        wants.write_line(&format!("shallow {}", id))?;
+    }
+    if let Some(deepen) = &shallow.deepen {
+        if let Some(depth) = deepen.depth {
+            wants.write_line(&format!("deepen {}", depth))?;
+        }
+        if let Some(since) = deepen.since {
+            wants.write_line(&format!("deepen-since {}", since))?;
+        }
+        for name in &deepen.not {
+            wants.write_line(&format!("deepen-not {}", name))?;
+        }
+    }
+    wants.write_flush()?;
 
-    let mut reader = BufReader::new(&res_bytes[..]);
+    Ok(wants.into_inner())
+}
 
-    let mut bytes = vec![0; 8];
-    reader.read_exact(&mut bytes).unwrap();
+// a negotiation round, or with `done` the final request that asks for the pack
+fn have_request<'a>(
+    wants: &[u8],
+    haves: impl Iterator<Item = &'a String>,
+    done: bool,
+) -> Result<Vec<u8>> {
+    let mut body = PktLineWriter::new(wants.to_vec());
+    for x in haves {
+        body.write_line(&format!("have {}", x))?;
+    }
+    if done {
+        body.write_line("done")?;
+    } else {
+        body.write_flush()?;
+    }
+
+    Ok(body.into_inner())
+}
+
+fn side_band(services: &[String]) -> Option<&'static str> {
+    ["side-band-64k", "side-band"]
+        .into_iter()
+        .find(|capability| services.iter().any(|service| service == capability))
+}
+
+// reads the pack the server sends after our "done", writing its objects to `object_dir`
+fn read_pack_response(
+    response: impl Read,
+    services: &[String],
+    object_dir: &path::Path,
+    progress: &mut dyn Progress,
+) -> Result<()> {
+    let mut reader = PktLineReader::new(response);
+
+    // the server answers our "done" with its final ACK (or a NAK) before the pack;
+    // multi_ack_detailed servers may repeat "ACK <sha> common" lines first
+    loop {
+        let packet = reader.read_packet()?.ok_or_else(|| {
+            Error::Protocol("unexpected end of response before the packfile".to_string())
+        })?;
+        // a deepening fetch is told its shallow commits again first
+        if matches!(packet, Packet::Flush) {
+            continue;
+        }
+        let line = packet
+            .as_text()
+            .ok_or_else(|| Error::Protocol("unexpected packet before the packfile".to_string()))?;
+        if line.starts_with("shallow ") || line.starts_with("unshallow ") {
+            continue;
+        }
+        match parse_ack(line) {
+            Some((_, Some(_))) => continue,
+            _ => break,
+        }
+    }
+
+    let pack_dir = object_dir.join("pack");
+    fs::create_dir_all(&pack_dir)?;
+    let spill_path = pack_dir.join(format!("tmp_pack_{}", std::process::id()));
+    let stream = PackStream::new(reader, side_band(services).is_some(), &spill_path)?;
+    let result = index_pack(stream, &spill_path, object_dir, progress);
+    let _ = fs::remove_file(&spill_path);
+
+    result
+}
+
+// a delta whose base may not have been seen yet, resolved from the spilled pack afterwards
+struct PendingDelta {
+    base: String,
+    offset: u64,
+}
+
+// writes every object of the pack to `object_dir` as it is received; deltas are resolved
+// afterwards from the copy of the pack on disk, so only one object is in memory at a time
+fn index_pack<R: Read>(
+    stream: PackStream<R>,
+    spill_path: &path::Path,
+    object_dir: &path::Path,
+    progress: &mut dyn Progress,
+) -> Result<()> {
+    let mut reader = BufReader::new(stream);
 
     let mut pack = vec![0; 4];
     reader
         .read_exact(&mut pack)
-        .expect("invalid packfile signature");
-    // println!("{:?}", String::from_utf8_lossy(&pack));
+        .map_err(pack_read_error("signature"))?;
+    if pack != b"PACK" {
+        return Err(Error::InvalidPack("bad signature".to_string()));
+    }
     // ignore version
     reader
         .read_exact(&mut pack)
-        .expect("invalid packfile version");
-    // println!("{:?}", String::from_utf8_lossy(&pack));
+        .map_err(pack_read_error("version"))?;
 
     let mut number_of_objects = [0; 4];
     reader
         .read_exact(&mut number_of_objects)
-        .expect("invalid number of objects");
-    // number_of_objects
-    //     .iter()
-    //     .for_each(|b| println!("byte: {:02x}", b));
+        .map_err(pack_read_error("object count"))?;
     let number_of_objects = u32::from_be_bytes(number_of_objects);
-    // println!("number_of_objects: {}", number_of_objects);
+    reader.get_mut().report_remote(progress);
+
+    let mut deltas = vec![];
+    progress.start("Receiving objects", Some(number_of_objects as u64));
+    let received = receive_objects(
+        &mut reader,
+        number_of_objects,
+        &mut deltas,
+        object_dir,
+        progress,
+    );
+    progress.finish();
+    let mut stream = reader.into_inner();
+    stream.report_remote(progress);
 
-    for _ in 0..number_of_objects {
-        let (object_type, object_size) = parse_object_header(&mut reader);
-        let mut base_object_bin_hash = vec![0u8; 20];
-        let mut base_object_hash = String::new();
+    match received.and_then(|_| stream.finish()) {
+        Ok(()) => resolve_deltas(deltas, spill_path, object_dir, progress),
+        Err(error) => {
+            // the deltas that arrived whole can still be resolved, which leaves less for a
+            // retry to fetch
+            if stream.flush_spill().is_ok() {
+                let _ = resolve_deltas(deltas, spill_path, object_dir, &mut NoProgress);
+            }
+            Err(error)
+        }
+    }
+}
+
+fn receive_objects<R: Read>(
+    reader: &mut BufReader<PackStream<R>>,
+    number_of_objects: u32,
+    deltas: &mut Vec<PendingDelta>,
+    object_dir: &path::Path,
+    progress: &mut dyn Progress,
+) -> Result<()> {
+    for done in 0..number_of_objects {
+        let (object_type, object_size) = parse_object_header(reader)?;
 
         if object_type == 7 {
+            let mut base_object_bin_hash = [0u8; 20];
             reader
                 .read_exact(&mut base_object_bin_hash)
-                .expect("invalid base object hash");
-            base_object_hash = hex::encode(&base_object_bin_hash);
+                .map_err(pack_read_error("delta base id"))?;
+            let offset = reader.get_ref().position() - reader.buffer().len() as u64;
+            io::copy(
+                &mut flate2::bufread::ZlibDecoder::new(&mut *reader),
+                &mut io::sink(),
+            )
+            .map_err(pack_read_error("delta"))
+            .map_err(|error| cut_off(reader, error, "delta"))?;
+            deltas.push(PendingDelta {
+                base: hex::encode(base_object_bin_hash),
+                offset,
+            });
+        } else {
+            let object_type = object_type_from_pack(object_type)?;
+            let object = inflate(reader, object_size)
+                .map_err(|error| cut_off(reader, error, "object data"))?;
+            GitObject::new(object, object_type).persist(object_dir)?;
         }
 
-        let mut object = {
-            let object_size = if object_size > 0 { object_size } else { 1 };
-            let mut object = vec![0u8; object_size as usize];
-            let mut decompressor = flate2::bufread::ZlibDecoder::new(&mut reader);
-            if decompressor.read_exact(&mut object).is_err() {}
-            object
-        };
+        progress.update(done as u64 + 1);
+        progress.bytes(reader.get_ref().position());
+        reader.get_mut().report_remote(progress);
+    }
 
-        if object_type != 7 {
-            if object_size == 0 {
-                object = vec![];
-            }
-            let object = GitObject::new(object, object_type.into());
-            objects.insert(object.id.clone(), object);
-        } else {
-            // println!("base_object_hash: {}", base_object_hash);
+    // all that is left is the checksum
+    io::copy(reader, &mut io::sink())?;
+    progress.bytes(reader.get_ref().position());
 
-            if let Some(base_object) = objects.get(&base_object_hash) {
-                let object = reconstruct_object(object, base_object);
+    Ok(())
+}
 
-                objects.insert(object.id.clone(), object);
-            } else {
-                println!("base object not found");
-            }
-            // println!();
+// a pack cut off partway through an object can look to the decompressor like a corrupt
+// stream; when nothing is left to read, the pack was cut short
+fn cut_off<R: Read>(reader: &mut BufReader<PackStream<R>>, error: Error, what: &str) -> Error {
+    match error {
+        Error::Io(_) if reader.fill_buf().is_ok_and(|rest| rest.is_empty()) => {
+            Error::InvalidPack(format!("truncated {}", what))
         }
+        error => error,
+    }
+}
+
+fn resolve_deltas(
+    deltas: Vec<PendingDelta>,
+    spill_path: &path::Path,
+    object_dir: &path::Path,
+    progress: &mut dyn Progress,
+) -> Result<()> {
+    if deltas.is_empty() {
+        return Ok(());
     }
 
-    Ok(objects)
+    let mut pack = BufReader::new(fs::File::open(spill_path)?);
+    progress.start("Resolving deltas", Some(deltas.len() as u64));
+    let mut resolved = 0;
+    let mut pending = deltas;
+    // a delta's base can itself be a delta appearing later in the pack
+    while !pending.is_empty() {
+        let count = pending.len();
+        let mut waiting = vec![];
+        for delta in pending {
+            let Some(base_object) = read_object(object_dir, &delta.base) else {
+                waiting.push(delta);
+                continue;
+            };
+            pack.seek(SeekFrom::Start(delta.offset))?;
+            let mut data = vec![];
+            flate2::bufread::ZlibDecoder::new(&mut pack)
+                .read_to_end(&mut data)
+                .map_err(pack_read_error("delta"))?;
+            reconstruct_object(data, &base_object)?.persist(object_dir)?;
+            resolved += 1;
+            progress.update(resolved);
+        }
+        if waiting.len() == count {
+            return Err(Error::InvalidPack(format!(
+                "delta base {} not found",
+                waiting[0].base
+            )));
+        }
+        pending = waiting;
+    }
+    progress.finish();
+
+    Ok(())
 }
 
-fn reconstruct_object(delta_object: Vec<u8>, base_object: &GitObject) -> GitObject {
-    let mut reader = BufReader::new(delta_object.as_slice());
-    let _base_object_size = parse_size_encoding(&mut reader, 0);
-    let _target_object_size = parse_size_encoding(&mut reader, 0);
-    let base_object_content = &base_object.content;
+// checkout.workers, with anything below 1 meaning one per core; small checkouts aren't worth
+// the threads below checkout.thresholdForParallelism files
+fn checkout_workers(config: &Config, files: usize) -> usize {
+    let threshold = config
+        .get("checkout.thresholdForParallelism")
+        .and_then(|threshold| threshold.parse().ok())
+        .unwrap_or(DEFAULT_PARALLEL_CHECKOUT_THRESHOLD);
+    if files < threshold {
+        return 1;
+    }
+    match config
+        .get("checkout.workers")
+        .and_then(|workers| workers.parse::<i64>().ok())
+    {
+        Some(workers) if workers >= 1 => workers as usize,
+        _ => parallel::available_workers(),
+    }
+}
 
-    let mut target_object: Vec<u8> = vec![];
-    loop {
-        let mut byte = vec![0; 1];
-        if reader.read_exact(&mut byte).is_err() {
-            break;
+fn checkout_file(
+    object_dir: &path::Path,
+    work_tree: &path::Path,
+    converter: &Mutex<Converter>,
+    path: &str,
+    mode: &str,
+    id: &str,
+) -> Result<IndexEntry> {
+    let file_path = paths::to_native(work_tree, path);
+    let mut entry = IndexEntry::new(
+        path,
+        u32::from_str_radix(mode, 8).map_err(|_| Error::CorruptObject(id.to_string()))?,
+        id,
+    );
+    // a submodule's commit is in another repository, so it is left an empty directory
+    if mode == "160000" {
+        fs::create_dir_all(&file_path)?;
+        return Ok(entry);
+    }
+    let blob_object =
+        read_object(object_dir, id).ok_or_else(|| Error::ObjectNotFound(id.to_string()))?;
+    if mode == "120000" {
+        write_symlink(&blob_object.content, &file_path)?;
+    } else {
+        // filters may be one long-running process, so conversions take turns
+        let content = converter
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .convert_to_working_tree(path, blob_object.content)?;
+        fs::write(&file_path, content)?;
+        set_executable(&file_path, mode == "100755")?;
+    }
+    entry.set_stat(&fs::symlink_metadata(&file_path)?);
+
+    Ok(entry)
+}
+
+#[cfg(unix)]
+pub fn set_executable(path: &path::Path, executable: bool) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut perms = fs::metadata(path)?.permissions();
+    let mode = perms.mode();
+    perms.set_mode(match executable {
+        true => mode | (mode & 0o444) >> 2,
+        false => mode & !0o111,
+    });
+    fs::set_permissions(path, perms)
+}
+
+// the executable bit isn't on disk to set
+#[cfg(not(unix))]
+pub fn set_executable(_path: &path::Path, _executable: bool) -> io::Result<()> {
+    Ok(())
+}
+
+// a symlink blob holds the link target
+#[cfg(unix)]
+pub fn write_symlink(target: &[u8], path: &path::Path) -> io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+
+    std::os::unix::fs::symlink(std::ffi::OsStr::from_bytes(target), path)
+}
+
+// creating symlinks needs extra privileges on Windows, so check the link out as a plain file
+// holding its target, like git does with core.symlinks=false
+#[cfg(not(unix))]
+pub fn write_symlink(target: &[u8], path: &path::Path) -> io::Result<()> {
+    fs::write(path, target)
+}
+
+const HAVES_PER_ROUND: usize = 32;
+const DEFAULT_PARALLEL_CHECKOUT_THRESHOLD: usize = 100;
+const MAX_ATTEMPTS: u64 = 3;
+const MAX_HAVES: usize = 256;
+
+// failures worth retrying: the connection broke, or the pack was cut short
+fn is_transient(error: &Error) -> bool {
+    match error {
+        Error::Http(_) => true,
+        Error::HttpStatus { status, .. } => *status == 429 || *status >= 500,
+        Error::InvalidPack(message) => {
+            message.starts_with("truncated") || message == "checksum mismatch"
         }
-        let msb = byte[0] >> 7;
-        // println!("msb: {} - instruction byte: {:08b}", msb, byte[0]);
-        if msb == 1 {
-            let mut size = 0;
-            let mut offset: u32 = 0;
-            let offset_bitmask = byte[0] & 0b1111;
-            let size_bitmask = (byte[0] >> 4) & 0b111;
-            // println!("offset_bitmask: {:08b}", offset_bitmask);
-            // println!("size_bitmask: {:08b}", size_bitmask);
-            let mut offset_bytes = vec![];
-            let mut size_bytes = vec![];
-
-            for i in 0..4 {
-                // println!("{}: {}", i, offset_bitmask & (1 << i));
-                if offset_bitmask & (1 << i) == 0 {
-                    offset_bytes.push(0);
-                } else {
-                    reader.read_exact(&mut byte).expect("invalid offset bytes");
-                    // println!("read offset byte: {:02x}", byte[0]);
-                    let byte = byte[0] as u32;
-                    offset += byte << (i * 8);
+        Error::Io(error) => {
+            matches!(
+                error.kind(),
+                io::ErrorKind::UnexpectedEof
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::TimedOut
+            ) || error
+                .get_ref()
+                .is_some_and(|error| error.is::<reqwest::Error>())
+        }
+        _ => false,
+    }
+}
+
+// after a transfer failed part way: drops the tips that arrived whole from `hashes`, and puts
+// the history that did arrive ahead of `haves` so it isn't sent again; false when there is
+// nothing left to fetch
+fn prepare_retry(
+    object_dir: &path::Path,
+    hashes: &mut Vec<String>,
+    haves: &mut Vec<String>,
+) -> bool {
+    hashes.retain(|id| !is_complete(object_dir, id));
+    if hashes.is_empty() {
+        return false;
+    }
+    let mut received = complete_commits(object_dir, hashes);
+    received.retain(|id| !haves.contains(id));
+    haves.splice(0..0, received);
+    true
+}
+
+// whether `id` and everything it references are all in the object database
+fn is_complete(object_dir: &path::Path, id: &str) -> bool {
+    let mut seen = HashSet::new();
+    let mut stack = vec![id.to_string()];
+    while let Some(id) = stack.pop() {
+        if !seen.insert(id.clone()) {
+            continue;
+        }
+        let Some(object) = read_object(object_dir, &id) else {
+            return false;
+        };
+        match object.object_type {
+            GitObjectType::Commit | GitObjectType::Tag => String::from_utf8_lossy(&object.content)
+                .lines()
+                .take_while(|line| !line.is_empty())
+                .filter_map(|line| {
+                    line.strip_prefix("tree ")
+                        .or_else(|| line.strip_prefix("parent "))
+                        .or_else(|| line.strip_prefix("object "))
+                })
+                .for_each(|id| stack.push(id.to_string())),
+            GitObjectType::Tree => {
+                let Ok(entries) = objects::parse_tree(&object.content) else {
+                    return false;
+                };
+                for entry in entries {
+                    match entry.mode.as_str() {
+                        "40000" => stack.push(entry.id),
+                        // submodule commits live in another repository
+                        "160000" => {}
+                        // blobs don't reference anything, so there's no need to read them
+                        _ if !objects::has_object(object_dir, &entry.id) => return false,
+                        _ => {}
+                    }
                 }
             }
-            // println!("offset : {} - 0x{:08x}", offset, offset);
-            for i in 0..3 {
-                // println!("{}: {}", i, size_bitmask & (1 << i));
-                if size_bitmask & (1 << i) == 0 {
-                    size_bytes.push(0);
-                } else {
-                    reader.read_exact(&mut byte).expect("invalid size bytes");
-                    // println!("read size byte: {:02x}", byte[0]);
-                    let byte = byte[0] as u32;
-                    size += byte << (i * 8);
+            GitObjectType::Blob => {}
+        }
+    }
+
+    true
+}
+
+// the newest commits reachable from `wants` whose history is all in the object database,
+// which a retry can tell the server it has
+fn complete_commits(object_dir: &path::Path, wants: &[String]) -> Vec<String> {
+    // the tree and parents of every commit there is, reachable from the wants
+    let mut commits = HashMap::new();
+    let mut stack = wants.to_vec();
+    while let Some(id) = stack.pop() {
+        if commits.contains_key(&id) {
+            continue;
+        }
+        let Some(object) = read_object(object_dir, &id) else {
+            continue;
+        };
+        let content = String::from_utf8_lossy(&object.content);
+        let headers = content.lines().take_while(|line| !line.is_empty());
+        match object.object_type {
+            GitObjectType::Tag => stack.extend(
+                headers
+                    .filter_map(|line| line.strip_prefix("object "))
+                    .map(str::to_string),
+            ),
+            GitObjectType::Commit => {
+                let (mut tree, mut parents) = (String::new(), vec![]);
+                for line in headers {
+                    if let Some(id) = line.strip_prefix("tree ") {
+                        tree = id.to_string();
+                    } else if let Some(id) = line.strip_prefix("parent ") {
+                        parents.push(id.to_string());
+                    }
                 }
+                stack.extend(parents.iter().cloned());
+                commits.insert(id, (tree, parents));
             }
+            _ => {}
+        }
+    }
 
-            // println!(
-            //     "offset bytes: {}",
-            //     offset_bytes
-            //         .iter()
-            //         .map(|x| format!("{:02x}", x))
-            //         .collect::<String>()
-            // );
-            if size == 0 {
-                size = 0x10000;
-            }
+    // a commit is complete when its tree and its parents are, so parents are settled first
+    let mut complete: HashMap<String, bool> = HashMap::new();
+    let mut trees = HashMap::new();
+    let mut stack: Vec<(String, bool)> = commits.keys().map(|id| (id.clone(), false)).collect();
+    while let Some((id, parents_done)) = stack.pop() {
+        if complete.contains_key(&id) {
+            continue;
+        }
+        let Some((tree, parents)) = commits.get(&id) else {
+            complete.insert(id, false);
+            continue;
+        };
+        if !parents_done {
+            stack.push((id.clone(), true));
+            stack.extend(parents.iter().map(|parent| (parent.clone(), false)));
+            continue;
+        }
+        let done = parents.iter().all(|parent| complete[parent])
+            && is_tree_complete(object_dir, tree, &mut trees);
+        complete.insert(id, done);
+    }
 
-            target_object.extend(&base_object_content[offset as usize..(offset + size) as usize]);
-        } else {
-            let size = byte[0] & 0x7f;
-            let mut add_object = vec![0; size as usize];
-            reader
-                .read_exact(&mut add_object)
-                .expect("invalid data object for delta insert instruction");
-            target_object.extend(&add_object);
+    // those whose children aren't complete
+    let mut newest = vec![];
+    let mut seen = HashSet::new();
+    let mut stack: Vec<&String> = wants
+        .iter()
+        .filter(|id| commits.contains_key(*id))
+        .collect();
+    while let Some(id) = stack.pop() {
+        if !seen.insert(id) {
+            continue;
+        }
+        match complete.get(id) {
+            Some(true) => newest.push(id.clone()),
+            _ => stack.extend(commits.get(id).into_iter().flat_map(|(_, parents)| parents)),
         }
     }
-    let output = GitObject::new(target_object, base_object.object_type.clone());
-    // println!("output id = {}", output.id);
-    // println!("output type = {}", output.object_type);
-    // println!("output content = {}", String::from_utf8_lossy(&output.content));
-    output
+    newest.sort();
+    newest
+}
+
+// whether a tree and everything in it are in the object database, remembering the answer for
+// each tree in `trees`
+fn is_tree_complete(object_dir: &path::Path, id: &str, trees: &mut HashMap<String, bool>) -> bool {
+    if let Some(&done) = trees.get(id) {
+        return done;
+    }
+    let entries =
+        read_object(object_dir, id).and_then(|tree| objects::parse_tree(&tree.content).ok());
+    let done = entries.is_some_and(|entries| {
+        entries.iter().all(|entry| match entry.mode.as_str() {
+            "40000" => is_tree_complete(object_dir, &entry.id, trees),
+            "160000" => true,
+            _ => objects::has_object(object_dir, &entry.id),
+        })
+    });
+    trees.insert(id.to_string(), done);
+    done
 }
 
-fn parse_tree_object(content: &[u8]) -> Vec<(String, String, String)> {
-    let mut reader = BufReader::new(content);
+fn read_object(object_dir: &path::Path, id: &str) -> Option<GitObject> {
+    let (object_type, content) = objects::read_object(object_dir, id)?;
 
-    let mut i = 0;
-    let mut result = vec![];
+    Some(GitObject::new(content, object_type))
+}
 
-    loop {
-        let mut bytes = vec![];
-        let read_result = reader.read_until(b'\0', &mut bytes);
-        if let Ok(l) = read_result {
-            if l > 20 {
-                bytes.pop().unwrap();
-            } else if l == 0 {
-                break;
-            }
-        } else {
-            break;
+// collects the commits the server ACKs into `common`, returning whether negotiation is over
+fn read_acks(response: impl Read, common: &mut Vec<String>) -> Result<bool> {
+    let mut ready = false;
+    for packet in PktLineReader::new(response) {
+        let packet = packet?;
+        let Some((id, status)) = packet.as_text().and_then(parse_ack) else {
+            continue;
+        };
+        if !common.contains(&id) {
+            common.push(id);
         }
+        // a plain ACK (no multi_ack) means the server found its common base
+        ready |= matches!(status, Some("ready") | None);
+    }
+
+    Ok(ready || !common.is_empty())
+}
 
-        if i == 0 {
-            let (mode, name) = std::str::from_utf8(&bytes)
-                .unwrap()
-                .split_once(' ')
-                .unwrap();
-            result.push((mode.to_string(), name.replace('\0', "").to_string(), "".into()));
+// the "shallow" and "unshallow" lines that start the answer to a deepening request
+fn read_shallow_update(response: impl Read) -> Result<ShallowUpdate> {
+    let mut update = ShallowUpdate::default();
+    for packet in PktLineReader::new(response) {
+        let packet = packet?;
+        let Some(line) = packet.as_text() else {
+            break;
+        };
+        if let Some(id) = line.strip_prefix("shallow ") {
+            update.shallow.push(id.to_string());
+        } else if let Some(id) = line.strip_prefix("unshallow ") {
+            update.unshallow.push(id.to_string());
         } else {
-            while bytes.len() <= 20 {
-                let mut next_part = vec![];
-                if let Ok(l) = reader.read_until(b'\0', &mut next_part) {
-                    if l == 0 {
-                        break;
-                    }
-                } else {
-                    break;
-                }
-                bytes = [bytes, next_part].concat();
-            }
+            return Err(Error::Protocol(format!(
+                "expected shallow/unshallow, got {}",
+                line
+            )));
+        }
+    }
+    Ok(update)
+}
 
-            let sha = &bytes[0..20];
-            result[i - 1].2 = hex::encode(sha);
+fn upload_pack(client: &mut HttpClient, body: Vec<u8>) -> Result<reqwest::blocking::Response> {
+    client.post(
+        "git-upload-pack",
+        "application/x-git-upload-pack-request",
+        body,
+    )
+}
 
-            if let Some((mode, name)) = std::str::from_utf8(&bytes[20..]).unwrap().split_once(' ') {
-                result.push((mode.to_string(), name.replace('\0', "").to_string(), "".into()))
-            };
+#[cfg(feature = "async")]
+async fn upload_pack_async(
+    client: &mut AsyncHttpClient,
+    body: Vec<u8>,
+) -> Result<reqwest::Response> {
+    client
+        .post(
+            "git-upload-pack",
+            "application/x-git-upload-pack-request",
+            body,
+        )
+        .await
+}
+
+fn parse_ack(line: &str) -> Option<(String, Option<&str>)> {
+    let mut parts = line.strip_prefix("ACK ")?.split(' ');
+    let id = parts.next()?.to_string();
+
+    Some((id, parts.next()))
+}
+
+// the pack in an upload-pack response, taken out of the side-band if there is one and copied
+// to a file on disk as it is read
+struct PackStream<R> {
+    reader: PktLineReader<R>,
+    side_band: bool,
+    // pack data from the last side-band packet that hasn't been read yet
+    pending: Vec<u8>,
+    spill: io::BufWriter<fs::File>,
+    position: u64,
+    hasher: sha1::Sha1,
+    // the last 20 bytes read, which are the pack's checksum once the stream ends
+    trailer: Vec<u8>,
+    // the remote's progress messages that haven't been reported yet
+    remote: Vec<u8>,
+}
+
+impl<R: Read> PackStream<R> {
+    fn new(reader: PktLineReader<R>, side_band: bool, spill_path: &path::Path) -> Result<Self> {
+        Ok(PackStream {
+            reader,
+            side_band,
+            pending: Vec::new(),
+            spill: io::BufWriter::new(fs::File::create(spill_path)?),
+            position: 0,
+            hasher: sha1::Sha1::new(),
+            trailer: Vec::new(),
+            remote: Vec::new(),
+        })
+    }
+
+    // the number of pack bytes read so far
+    fn position(&self) -> u64 {
+        self.position
+    }
+
+    // progress messages can be split across packets, so only whole lines are reported
+    fn report_remote(&mut self, progress: &mut dyn Progress) {
+        while let Some(end) = self.remote.iter().position(|b| *b == b'\r' || *b == b'\n') {
+            let line: Vec<u8> = self.remote.drain(..=end).collect();
+            progress.remote(&line);
         }
-        i += 1;
     }
 
-    result
+    // checks the pack's checksum and makes sure the copy on disk is complete
+    fn finish(&mut self) -> Result<()> {
+        if self.trailer[..] != self.hasher.clone().finalize()[..] {
+            return Err(Error::InvalidPack("checksum mismatch".to_string()));
+        }
+
+        Ok(self.flush_spill()?)
+    }
+
+    fn flush_spill(&mut self) -> io::Result<()> {
+        self.spill.flush()
+    }
+
+    fn fill_pending(&mut self) -> io::Result<()> {
+        while self.pending.is_empty() {
+            let Some(Packet::Data(packet)) = self.reader.read_packet()? else {
+                return Ok(());
+            };
+            let Some((band, data)) = packet.split_first() else {
+                continue;
+            };
+            match band {
+                1 => self.pending.extend(data),
+                2 => self.remote.extend(data),
+                3 => {
+                    let message = String::from_utf8_lossy(data).trim_end().to_string();
+                    return Err(io::Error::other(Error::Remote(message)));
+                }
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        Error::Protocol(format!("invalid side-band channel: {}", band)),
+                    ))
+                }
+            }
+        }
+
+        Ok(())
+    }
 }
 
-fn parse_object_header<T: Read>(reader: &mut T) -> (u8, u128) {
-    let mut first_byte = [0; 1];
-    let _ = reader.read_exact(&mut first_byte);
-    let obj_type = (first_byte[0] >> 4) & 0x07;
-    let mut object_size = (first_byte[0] & 0xF) as u128;
-    let msb = first_byte[0] >> 7;
-    if msb == 1 {
-        object_size = parse_size_encoding(reader, object_size as u32);
+impl<R: Read> Read for PackStream<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = if self.side_band {
+            self.fill_pending()?;
+            let n = buf.len().min(self.pending.len());
+            buf[..n].copy_from_slice(&self.pending[..n]);
+            self.pending.drain(..n);
+            n
+        } else {
+            self.reader.get_mut().read(buf)?
+        };
+
+        self.spill.write_all(&buf[..n])?;
+        self.position += n as u64;
+        // everything but the trailing checksum is hashed
+        self.trailer.extend_from_slice(&buf[..n]);
+        if self.trailer.len() > 20 {
+            let hashed = self.trailer.len() - 20;
+            self.hasher.update(&self.trailer[..hashed]);
+            self.trailer.drain(..hashed);
+        }
+
+        Ok(n)
     }
+}
 
-    (obj_type, object_size)
+fn reconstruct_object(delta_object: Vec<u8>, base_object: &GitObject) -> Result<GitObject> {
+    let content = pack::apply_delta(&base_object.content, &delta_object)?;
+    Ok(GitObject::new(content, base_object.object_type))
 }
 
-fn parse_size_encoding<T: Read>(reader: &mut T, base_size: u32) -> u128 {
-    let mut object_size = base_size as u128;
-    let mut msb = 1;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    // a pack of `objects`, each its pack type and content, in the order given and without deltas
+    fn pack(objects: &[(u8, Vec<u8>)]) -> Vec<u8> {
+        let mut pack = b"PACK".to_vec();
+        pack.extend(2u32.to_be_bytes());
+        pack.extend((objects.len() as u32).to_be_bytes());
+        for (pack_type, content) in objects {
+            let mut size = content.len();
+            let mut byte = (pack_type << 4) | (size & 0x0f) as u8;
+            size >>= 4;
+            while size > 0 {
+                pack.push(byte | 0x80);
+                byte = (size & 0x7f) as u8;
+                size >>= 7;
+            }
+            pack.push(byte);
+            let mut encoder =
+                flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+            encoder.write_all(content).unwrap();
+            pack.extend(encoder.finish().unwrap());
+        }
+        let checksum = sha1::Sha1::digest(&pack);
+        pack.extend(checksum);
+        pack
+    }
 
-    let mut c = 0;
-    while msb != 0 {
-        let mut first_byte = [0; 1];
-        let result = reader.read_exact(&mut first_byte);
-        msb = first_byte[0] >> 7;
-        let current_byte: u128 = (first_byte[0] & 0b0111_1111) as u128;
-        object_size = (current_byte << (4 + 7 * c)) + (object_size);
-        c += 1;
+    fn id(object_type: GitObjectType, content: &[u8]) -> String {
+        objects::hash_object_from(object_type, content.len() as u64, content).unwrap()
     }
 
-    object_size
+    #[test]
+    fn retry_has_the_history_a_cut_pack_brought() {
+        let client = TempDir::new("cut-pack");
+        let client = client.path();
+        // three commits of a file each, the blobs random enough not to compress much
+        let (mut objects, mut commits) = (vec![], vec![]);
+        let mut seed = 1u32;
+        for i in 0..3 {
+            let blob: Vec<u8> = (0..4096)
+                .map(|_| {
+                    seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                    (seed >> 16) as u8
+                })
+                .collect();
+            let mut tree = b"100644 file\0".to_vec();
+            tree.extend(hex::decode(id(GitObjectType::Blob, &blob)).unwrap());
+            let mut commit = format!("tree {}\n", id(GitObjectType::Tree, &tree));
+            if let Some(parent) = commits.last() {
+                commit += &format!("parent {}\n", parent);
+            }
+            commit += &format!(
+                "author A U Thor <author@example.com> {i} +0000\n\
+                 committer A U Thor <author@example.com> {i} +0000\n\ncommit {i}\n"
+            );
+            commits.push(id(GitObjectType::Commit, commit.as_bytes()));
+            objects.extend([(1, commit.into_bytes()), (2, tree), (3, blob)]);
+        }
+
+        // the transfer stops partway through the last blob
+        let pack = pack(&objects);
+        let mut response = PktLineWriter::new(vec![]);
+        response.write_line("NAK").unwrap();
+        let mut response = response.into_inner();
+        response.extend(&pack[..pack.len() - 100]);
+        let error = read_pack_response(&response[..], &[], client, &mut NoProgress).unwrap_err();
+        assert!(
+            matches!(&error, Error::InvalidPack(message) if message.starts_with("truncated")),
+            "{}",
+            error
+        );
+        assert!(is_transient(&error));
+
+        let want = commits[2].clone();
+        let haves = complete_commits(client, std::slice::from_ref(&want));
+        assert_eq!(haves, vec![commits[1].clone()]);
+
+        // so the retry asks only for what the last commit brings
+        let wants = want_request(vec![want], &[], &Shallow::default()).unwrap();
+        let first = have_request(&wants, [].iter(), true).unwrap();
+        let retry = have_request(&wants, haves.iter(), true).unwrap();
+        assert!(!String::from_utf8_lossy(&first).contains("have "));
+        assert!(String::from_utf8_lossy(&retry).contains(&format!("have {}\n", commits[1])));
+    }
 }
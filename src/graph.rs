@@ -0,0 +1,480 @@
+// the ascii history graph drawn beside "log --graph": every line of history is a column, at
+// even screen positions, and the edges between the columns of one row and the next go in the
+// odd positions between them; a commit can take several lines of graph to draw, one for each
+// line of text shown for it, with padding lines once it's done
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Padding,
+    Skip,
+    PreCommit,
+    Commit,
+    PostMerge,
+    Collapsing,
+}
+
+const MERGE_CHARS: [char; 3] = ['/', '|', '\\'];
+
+#[derive(Debug)]
+pub struct Graph {
+    commit: String,
+    // the parents of the commit that are shown too
+    parents: Vec<String>,
+    state: State,
+    prev_state: State,
+    // the commits each column leads to, before and after the current commit
+    columns: Vec<String>,
+    new_columns: Vec<String>,
+    // for every screen position in the current row, the column in `new_columns` the edge there
+    // leads to, or -1; only the first `mapping_size` entries are in use
+    mapping: Vec<isize>,
+    old_mapping: Vec<isize>,
+    mapping_size: usize,
+    width: usize,
+    commit_index: usize,
+    prev_commit_index: usize,
+    // where the edge to the first parent of a merge goes: 0 when it's to the left of the
+    // merge, 1 when it's below it, and -1 before it's known
+    merge_layout: isize,
+    edges_added: isize,
+    prev_edges_added: isize,
+    expansion_row: usize,
+}
+
+impl Default for Graph {
+    fn default() -> Self {
+        Graph::new()
+    }
+}
+
+impl Graph {
+    pub fn new() -> Graph {
+        Graph {
+            commit: String::new(),
+            parents: vec![],
+            state: State::Padding,
+            prev_state: State::Padding,
+            columns: vec![],
+            new_columns: vec![],
+            mapping: vec![],
+            old_mapping: vec![],
+            mapping_size: 0,
+            width: 0,
+            commit_index: 0,
+            prev_commit_index: 0,
+            merge_layout: 0,
+            edges_added: 0,
+            prev_edges_added: 0,
+            expansion_row: 0,
+        }
+    }
+
+    // moves on to the next commit shown, with those of its parents that are shown too
+    pub fn update(&mut self, commit: &str, parents: Vec<String>) {
+        self.commit = commit.to_string();
+        self.parents = parents;
+        self.prev_commit_index = self.commit_index;
+        self.update_columns();
+        self.expansion_row = 0;
+
+        // no line has been drawn in the new state yet, so prev_state stays as it is
+        self.state = if self.state != State::Padding {
+            State::Skip
+        } else if self.needs_pre_commit_line() {
+            State::PreCommit
+        } else {
+            State::Commit
+        };
+    }
+
+    // whether everything for the current commit has been drawn
+    pub fn is_commit_finished(&self) -> bool {
+        self.state == State::Padding
+    }
+
+    // the next line of the graph, and whether it's the one with the commit on it
+    pub fn next_line(&mut self) -> (String, bool) {
+        let mut line = String::new();
+        let mut shown_commit = false;
+        match self.state {
+            State::Padding => self.padding_row(&mut line),
+            State::Skip => self.skip_line(&mut line),
+            State::PreCommit => self.pre_commit_line(&mut line),
+            State::Commit => {
+                self.commit_line(&mut line);
+                shown_commit = true;
+            }
+            State::PostMerge => self.post_merge_line(&mut line),
+            State::Collapsing => self.collapsing_line(&mut line),
+        }
+        self.pad(&mut line);
+        (line, shown_commit)
+    }
+
+    // a line that leaves every column as it is, e.g. for the blank line between commits
+    pub fn padding_line(&mut self) -> String {
+        if self.state != State::Commit {
+            return self.next_line().0;
+        }
+
+        let mut line = String::new();
+        for column in &self.columns {
+            line.push('|');
+            if *column == self.commit && self.parents.len() > 2 {
+                line.push_str(&" ".repeat((self.parents.len() - 2) * 2));
+            } else {
+                line.push(' ');
+            }
+        }
+        self.pad(&mut line);
+        self.prev_state = State::Padding;
+        line
+    }
+
+    fn pad(&self, line: &mut String) {
+        let width = line.chars().count();
+        if width < self.width {
+            line.push_str(&" ".repeat(self.width - width));
+        }
+    }
+
+    fn set_state(&mut self, state: State) {
+        self.prev_state = self.state;
+        self.state = state;
+    }
+
+    // two rows to make room for each parent of an octopus merge that is joined with dashes
+    fn needs_pre_commit_line(&self) -> bool {
+        self.parents.len() >= 3
+            && self.commit_index + 1 < self.columns.len()
+            && (self.expansion_row as isize) < self.dashed_parents() * 2
+    }
+
+    fn dashed_parents(&self) -> isize {
+        self.parents.len() as isize + self.merge_layout - 3
+    }
+
+    fn is_mapping_correct(&self) -> bool {
+        self.mapping[..self.mapping_size]
+            .iter()
+            .enumerate()
+            .all(|(i, &target)| target < 0 || target as usize == i / 2)
+    }
+
+    fn find_new_column(&self, commit: &str) -> Option<usize> {
+        self.new_columns.iter().position(|column| column == commit)
+    }
+
+    // the commit each column leads to after the current commit, which takes the place of its
+    // column (or a new one at the end) with its parents
+    fn update_columns(&mut self) {
+        std::mem::swap(&mut self.columns, &mut self.new_columns);
+        self.new_columns.clear();
+
+        let max_new_columns = self.columns.len() + self.parents.len();
+        self.mapping_size = 2 * max_new_columns;
+        if self.mapping.len() < self.mapping_size {
+            self.mapping.resize(self.mapping_size, -1);
+            self.old_mapping.resize(self.mapping_size, -1);
+        }
+        self.mapping[..self.mapping_size].fill(-1);
+
+        self.width = 0;
+        self.prev_edges_added = self.edges_added;
+        self.edges_added = 0;
+
+        let mut seen_this = false;
+        for i in 0..=self.columns.len() {
+            let column = match self.columns.get(i) {
+                Some(column) => column.clone(),
+                None if seen_this => break,
+                None => self.commit.clone(),
+            };
+
+            if column == self.commit {
+                seen_this = true;
+                self.commit_index = i;
+                self.merge_layout = -1;
+                for parent in self.parents.clone() {
+                    self.insert_into_new_columns(&parent, Some(i));
+                }
+                // the commit always takes up some room, even without parents
+                if self.parents.is_empty() {
+                    self.width += 2;
+                }
+            } else {
+                self.insert_into_new_columns(&column, None);
+            }
+        }
+
+        while self.mapping_size > 1 && self.mapping[self.mapping_size - 1] < 0 {
+            self.mapping_size -= 1;
+        }
+    }
+
+    // `index` is the column of the current commit when `commit` is one of its parents
+    fn insert_into_new_columns(&mut self, commit: &str, index: Option<usize>) {
+        let i = match self.find_new_column(commit) {
+            Some(i) => i,
+            None => {
+                self.new_columns.push(commit.to_string());
+                self.new_columns.len() - 1
+            }
+        };
+
+        let mapping_index = match index {
+            Some(index) if self.parents.len() > 1 && self.merge_layout == -1 => {
+                // the first parent of a merge: lay the merge out by whether that parent is
+                // to the left of it
+                let distance = index as isize - i as isize;
+                let shift = if distance > 1 { 2 * distance - 3 } else { 1 };
+                self.merge_layout = if distance > 0 { 0 } else { 1 };
+                self.edges_added = self.parents.len() as isize + self.merge_layout - 2;
+
+                let mapping_index = self.width as isize + (self.merge_layout - 1) * shift;
+                self.width += 2 * self.merge_layout as usize;
+                mapping_index as usize
+            }
+            _ if self.edges_added > 0
+                && self.width >= 2
+                && self.mapping[self.width - 2] == i as isize =>
+            {
+                // a column the merge added joins the one before it right away
+                self.edges_added = -1;
+                self.width - 2
+            }
+            _ => {
+                self.width += 2;
+                self.width - 2
+            }
+        };
+        self.mapping[mapping_index] = i as isize;
+    }
+
+    fn padding_row(&self, line: &mut String) {
+        for _ in &self.new_columns {
+            line.push_str("| ");
+        }
+    }
+
+    // the previous commit never got to finish, so mark the gap
+    fn skip_line(&mut self, line: &mut String) {
+        line.push_str("...");
+        if self.needs_pre_commit_line() {
+            self.set_state(State::PreCommit);
+        } else {
+            self.set_state(State::Commit);
+        }
+    }
+
+    // an octopus merge spreads the columns right of it out before it's drawn, to make room
+    fn pre_commit_line(&mut self, line: &mut String) {
+        let mut seen_this = false;
+        for (i, column) in self.columns.iter().enumerate() {
+            if *column == self.commit {
+                seen_this = true;
+                line.push('|');
+                line.push_str(&" ".repeat(self.expansion_row));
+            } else if seen_this && self.expansion_row == 0 {
+                if self.prev_state == State::PostMerge && self.prev_commit_index < i {
+                    line.push('\\');
+                } else {
+                    line.push('|');
+                }
+            } else if seen_this {
+                line.push('\\');
+            } else {
+                line.push('|');
+            }
+            line.push(' ');
+        }
+
+        self.expansion_row += 1;
+        if !self.needs_pre_commit_line() {
+            self.set_state(State::Commit);
+        }
+    }
+
+    fn commit_line(&mut self, line: &mut String) {
+        let mut seen_this = false;
+        for i in 0..=self.columns.len() {
+            let column = match self.columns.get(i) {
+                Some(column) => column,
+                None if seen_this => break,
+                None => &self.commit,
+            };
+
+            if *column == self.commit {
+                seen_this = true;
+                line.push('*');
+                if self.parents.len() > 2 {
+                    self.draw_octopus_merge(line);
+                }
+            } else if seen_this && self.edges_added > 1 {
+                line.push('\\');
+            } else if seen_this && self.edges_added == 1 {
+                // right after a merge whose edge into this column was drawn as '\', keep it
+                if self.prev_state == State::PostMerge
+                    && self.prev_edges_added > 0
+                    && self.prev_commit_index < i
+                {
+                    line.push('\\');
+                } else {
+                    line.push('|');
+                }
+            } else if self.prev_state == State::Collapsing
+                && self.old_mapping.get(2 * i + 1) == Some(&(i as isize))
+                && self
+                    .mapping
+                    .get(2 * i)
+                    .is_some_and(|&target| target < i as isize)
+            {
+                line.push('/');
+            } else {
+                line.push('|');
+            }
+            line.push(' ');
+        }
+
+        if self.parents.len() > 1 {
+            self.set_state(State::PostMerge);
+        } else if self.is_mapping_correct() {
+            self.set_state(State::Padding);
+        } else {
+            self.set_state(State::Collapsing);
+        }
+    }
+
+    // the parents of an octopus merge past the first two are joined to it with dashes
+    fn draw_octopus_merge(&self, line: &mut String) {
+        let dashed_parents = self.dashed_parents();
+        for i in 0..dashed_parents {
+            line.push('-');
+            line.push(if i == dashed_parents - 1 { '.' } else { '-' });
+        }
+    }
+
+    // the edges from a merge down to each of its parents
+    fn post_merge_line(&mut self, line: &mut String) {
+        let first_parent = self.parents[0].clone();
+        let mut seen_parent = false;
+        let mut seen_this = false;
+        for i in 0..=self.columns.len() {
+            let column = match self.columns.get(i) {
+                Some(column) => column,
+                None if seen_this => break,
+                None => &self.commit,
+            };
+
+            if *column == self.commit {
+                seen_this = true;
+                let mut index = self.merge_layout as usize;
+                for j in 0..self.parents.len() {
+                    line.push(MERGE_CHARS[index]);
+                    if index == 2 {
+                        if self.edges_added > 0 || j + 1 < self.parents.len() {
+                            line.push(' ');
+                        }
+                    } else {
+                        index += 1;
+                    }
+                }
+                if self.edges_added == 0 {
+                    line.push(' ');
+                }
+            } else if seen_this {
+                line.push(if self.edges_added > 0 { '\\' } else { '|' });
+                line.push(' ');
+            } else {
+                line.push('|');
+                if self.merge_layout != 0 || i + 1 != self.commit_index {
+                    line.push(if seen_parent { '_' } else { ' ' });
+                }
+            }
+
+            if *column == first_parent {
+                seen_parent = true;
+            }
+        }
+
+        if self.is_mapping_correct() {
+            self.set_state(State::Padding);
+        } else {
+            self.set_state(State::Collapsing);
+        }
+    }
+
+    // moves every edge that isn't in its column yet one step to the left, crossing at most
+    // one other edge at a time
+    fn collapsing_line(&mut self, line: &mut String) {
+        let size = self.mapping_size;
+        std::mem::swap(&mut self.mapping, &mut self.old_mapping);
+        self.mapping[..size].fill(-1);
+
+        let mut horizontal_edge: isize = -1;
+        let mut horizontal_edge_target: isize = -1;
+        for i in 0..size {
+            let target = self.old_mapping[i];
+            if target < 0 {
+                continue;
+            }
+            // columns only ever move to the left
+            let position = target as usize * 2;
+            if position == i {
+                self.mapping[i] = target;
+            } else if self.mapping[i - 1] < 0 {
+                self.mapping[i - 1] = target;
+                if horizontal_edge == -1 {
+                    horizontal_edge = i as isize;
+                    horizontal_edge_target = target;
+                    for j in (position + 3..i.saturating_sub(2)).step_by(2) {
+                        self.mapping[j] = target;
+                    }
+                }
+            } else if self.mapping[i - 1] == target {
+                // joins the edge to its left, which goes to the same commit
+            } else {
+                // crosses the edge to its left
+                self.mapping[i - 2] = target;
+                if horizontal_edge == -1 {
+                    horizontal_edge_target = target;
+                    horizontal_edge = i as isize - 1;
+                    for j in (position + 3..i.saturating_sub(2)).step_by(2) {
+                        self.mapping[j] = target;
+                    }
+                }
+            }
+        }
+
+        self.old_mapping[..size].copy_from_slice(&self.mapping[..size]);
+        if self.mapping[size - 1] < 0 {
+            self.mapping_size -= 1;
+        }
+
+        let mut used_horizontal = false;
+        for i in 0..self.mapping_size {
+            let target = self.mapping[i];
+            if target < 0 {
+                line.push(' ');
+            } else if target as usize * 2 == i {
+                line.push('|');
+            } else if target == horizontal_edge_target && i as isize != horizontal_edge - 1 {
+                // only the first segment of a horizontal edge carries on to the next line
+                if i != target as usize * 2 + 3 {
+                    self.mapping[i] = -1;
+                }
+                used_horizontal = true;
+                line.push('_');
+            } else {
+                if used_horizontal && (i as isize) < horizontal_edge {
+                    self.mapping[i] = -1;
+                }
+                line.push('/');
+            }
+        }
+
+        if self.is_mapping_correct() {
+            self.set_state(State::Padding);
+        }
+    }
+}
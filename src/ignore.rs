@@ -0,0 +1,165 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{config::Config, paths, wildmatch::wildmatch};
+
+struct IgnoreRule {
+    // directory of the .gitignore file, relative to the work tree ("" for the root)
+    base: String,
+    pattern: String,
+    negated: bool,
+    dir_only: bool,
+}
+
+impl IgnoreRule {
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let path = if self.base.is_empty() {
+            path
+        } else {
+            match path
+                .strip_prefix(&self.base)
+                .and_then(|path| path.strip_prefix('/'))
+            {
+                Some(path) => path,
+                None => return false,
+            }
+        };
+
+        // a pattern with a slash other than at the end is anchored to its directory
+        match self.pattern.contains('/') {
+            true => {
+                let pattern = self.pattern.strip_prefix('/').unwrap_or(&self.pattern);
+                wildmatch(pattern.as_bytes(), path.as_bytes(), true)
+            }
+            false => {
+                let name = path.rsplit('/').next().unwrap_or(path);
+                wildmatch(self.pattern.as_bytes(), name.as_bytes(), false)
+            }
+        }
+    }
+}
+
+// which untracked files nobody wants to hear about: the .gitignore files of the work tree,
+// then $GIT_DIR/info/exclude, then core.excludesFile
+pub struct Ignore {
+    work_tree: PathBuf,
+    rules: HashMap<String, Vec<IgnoreRule>>,
+    ignored_dirs: HashMap<String, bool>,
+    // core.excludesFile followed by info/exclude, the latter taking precedence
+    global_rules: Vec<IgnoreRule>,
+}
+
+impl Ignore {
+    pub fn new(work_tree: &Path, git_dir: &Path, config: &Config) -> Ignore {
+        let mut global_rules = vec![];
+        for file in excludes_file(config)
+            .into_iter()
+            .chain([git_dir.join("info/exclude")])
+        {
+            global_rules.extend(parse(&fs::read_to_string(file).unwrap_or_default(), ""));
+        }
+        Ignore {
+            work_tree: work_tree.to_path_buf(),
+            rules: HashMap::new(),
+            ignored_dirs: HashMap::new(),
+            global_rules,
+        }
+    }
+
+    // whether `path`, relative to the work tree, is ignored; nothing in an ignored directory
+    // can be brought back, even where something else in it is tracked
+    pub fn is_ignored(&mut self, path: &str, is_dir: bool) -> bool {
+        let mut end = 0;
+        while let Some(slash) = path[end..].find('/') {
+            end += slash;
+            let dir = &path[..end];
+            let ignored = match self.ignored_dirs.get(dir) {
+                Some(ignored) => *ignored,
+                None => {
+                    let ignored = self.matches(dir, true);
+                    self.ignored_dirs.insert(dir.to_string(), ignored);
+                    ignored
+                }
+            };
+            if ignored {
+                return true;
+            }
+            end += 1;
+        }
+        self.matches(path, is_dir)
+    }
+
+    // the last rule that matches decides, with the .gitignore files closest to `path` going
+    // last
+    fn matches(&mut self, path: &str, is_dir: bool) -> bool {
+        let mut dirs = vec![String::new()];
+        let mut components: Vec<&str> = path.split('/').collect();
+        components.pop();
+        for i in 1..=components.len() {
+            dirs.push(components[..i].join("/"));
+        }
+        for dir in &dirs {
+            if !self.rules.contains_key(dir) {
+                let file = paths::to_native(&self.work_tree, dir).join(".gitignore");
+                let content = fs::read_to_string(file).unwrap_or_default();
+                self.rules.insert(dir.clone(), parse(&content, dir));
+            }
+        }
+
+        self.global_rules
+            .iter()
+            .chain(dirs.iter().flat_map(|dir| self.rules[dir].iter()))
+            .rev()
+            .find(|rule| rule.matches(path, is_dir))
+            .is_some_and(|rule| !rule.negated)
+    }
+}
+
+// core.excludesFile, or $XDG_CONFIG_HOME/git/ignore when it isn't set
+pub fn excludes_file(config: &Config) -> Option<PathBuf> {
+    match config.get("core.excludesFile") {
+        Some(file) => Some(paths::expand_home(file)),
+        None => paths::config_home().map(|home| home.join("git").join("ignore")),
+    }
+}
+
+fn parse(content: &str, base: &str) -> Vec<IgnoreRule> {
+    let mut rules = vec![];
+    for line in content.lines() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // trailing spaces don't count unless escaped
+        let mut pattern = line.trim_end_matches(' ');
+        if pattern.ends_with('\\') && pattern.len() < line.len() {
+            pattern = &line[..pattern.len() + 1];
+        }
+        let negated = pattern.starts_with('!');
+        let pattern = pattern.strip_prefix('!').unwrap_or(pattern);
+        let pattern = match pattern.strip_prefix('\\') {
+            Some(rest) if rest.starts_with(['#', '!']) => rest,
+            _ => pattern,
+        };
+        let dir_only = pattern.ends_with('/');
+        let pattern = pattern.trim_end_matches('/');
+        if pattern.is_empty() {
+            continue;
+        }
+
+        rules.push(IgnoreRule {
+            base: base.to_string(),
+            pattern: pattern.to_string(),
+            negated,
+            dir_only,
+        });
+    }
+
+    rules
+}
@@ -0,0 +1,924 @@
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, Write},
+    iter::repeat,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use sha1::Digest;
+
+use crate::{
+    error::{Error, Result},
+    ewah,
+    untracked::UntrackedCache,
+};
+
+const SIGNATURE: &[u8] = b"DIRC";
+const HEADER_SIZE: usize = 12;
+// everything in an entry before its flags: ten 32-bit stat fields and the object id
+const STAT_SIZE: usize = 40;
+const ENTRY_SIZE: usize = STAT_SIZE + 20 + 2;
+
+const ASSUME_VALID: u16 = 0x8000;
+const EXTENDED: u16 = 0x4000;
+const STAGE_SHIFT: u16 = 12;
+const NAME_MASK: u16 = 0x0fff;
+
+const UNTRACKED_CACHE: &[u8; 4] = b"UNTR";
+const FSMONITOR: &[u8; 4] = b"FSMN";
+const FSMONITOR_VERSION: u32 = 2;
+const LINK: &[u8; 4] = b"link";
+
+// splitIndex.maxPercentChange
+const DEFAULT_MAX_PERCENT_CHANGE: u32 = 20;
+
+// a file in the index, with what stat said about it when it was last looked at
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub ctime: (u32, u32),
+    pub mtime: (u32, u32),
+    pub dev: u32,
+    pub ino: u32,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+    pub id: String,
+    // 0 for a merged entry, otherwise 1 for the base, 2 for ours and 3 for theirs
+    pub stage: u8,
+    pub assume_valid: bool,
+    // skip-worktree and intent-to-add, only in version 3 and later
+    pub extended_flags: u16,
+    pub path: String,
+    // core.fsmonitor's hook hasn't reported the file since it was last seen to match
+    pub fsmonitor_valid: bool,
+}
+
+impl IndexEntry {
+    // an entry for `path` with no stat data, as if it was never checked out
+    pub fn new(path: &str, mode: u32, id: &str) -> IndexEntry {
+        IndexEntry {
+            mode,
+            id: id.to_string(),
+            path: path.to_string(),
+            ..IndexEntry::default()
+        }
+    }
+
+    // whether the two record the same content, never mind the stat data
+    pub fn same_content(&self, other: &IndexEntry) -> bool {
+        self.mode == other.mode && self.id == other.id
+    }
+
+    // whether the two would be written the same, which whether the fsmonitor hook reported
+    // either has no part in
+    fn same_entry(&self, other: &IndexEntry) -> bool {
+        (
+            self.ctime, self.mtime, self.dev, self.ino, self.mode, self.uid, self.gid,
+        ) == (
+            other.ctime,
+            other.mtime,
+            other.dev,
+            other.ino,
+            other.mode,
+            other.uid,
+            other.gid,
+        ) && (
+            self.size,
+            &self.id,
+            self.stage,
+            self.assume_valid,
+            self.extended_flags,
+        ) == (
+            other.size,
+            &other.id,
+            other.stage,
+            other.assume_valid,
+            other.extended_flags,
+        ) && self.path == other.path
+    }
+
+    // the fields are only as wide as the index has room for, so large values wrap
+    #[cfg(unix)]
+    pub fn set_stat(&mut self, metadata: &fs::Metadata) {
+        use std::os::unix::fs::MetadataExt;
+
+        self.ctime = (metadata.ctime() as u32, metadata.ctime_nsec() as u32);
+        self.mtime = (metadata.mtime() as u32, metadata.mtime_nsec() as u32);
+        self.dev = metadata.dev() as u32;
+        self.ino = metadata.ino() as u32;
+        self.uid = metadata.uid();
+        self.gid = metadata.gid();
+        self.size = metadata.size() as u32;
+    }
+
+    #[cfg(not(unix))]
+    pub fn set_stat(&mut self, metadata: &fs::Metadata) {
+        let time = |time: io::Result<std::time::SystemTime>| {
+            time.ok()
+                .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                .map_or((0, 0), |time| (time.as_secs() as u32, time.subsec_nanos()))
+        };
+        self.ctime = time(metadata.created());
+        self.mtime = time(metadata.modified());
+        self.size = metadata.len() as u32;
+    }
+
+    // whether the file still looks the way it did when the entry was made
+    pub fn stat_matches(&self, metadata: &fs::Metadata) -> bool {
+        let mut current = self.clone();
+        current.set_stat(metadata);
+        current == *self
+    }
+}
+
+// how a split index is written: how much of it may differ from the shared index before a new
+// one is started, and the time before which unused shared indexes were last written to be
+// removed
+#[derive(Clone, Copy, Debug)]
+pub struct SplitIndex {
+    pub max_percent_change: u32,
+    pub shared_index_expire: i64,
+}
+
+impl Default for SplitIndex {
+    fn default() -> SplitIndex {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |now| now.as_secs() as i64);
+        SplitIndex {
+            max_percent_change: DEFAULT_MAX_PERCENT_CHANGE,
+            shared_index_expire: now - 14 * 24 * 60 * 60,
+        }
+    }
+}
+
+// the entries of sharedindex.<id>, which a split index only records the changes to
+#[derive(Debug)]
+struct SharedIndex {
+    id: String,
+    entries: Vec<IndexEntry>,
+}
+
+// what the link extension of a split index says: the shared index it goes with, which of its
+// entries are gone and which the split index has a replacement for
+struct Link {
+    id: String,
+    delete: Vec<bool>,
+    replace: Vec<bool>,
+}
+
+// .git/index: every tracked file sorted by path and stage
+#[derive(Debug, Default)]
+pub struct Index {
+    entries: Vec<IndexEntry>,
+    split: Option<SplitIndex>,
+    shared: Option<SharedIndex>,
+    untracked: Option<UntrackedCache>,
+    // the fsmonitor hook's token, and for each entry as read whether the hook had reported it
+    fsmonitor_token: Option<String>,
+    fsmonitor_dirty: Vec<bool>,
+}
+
+impl Index {
+    // a missing index is an empty one
+    pub fn read(path: &Path) -> Result<Index> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Index::default()),
+            Err(error) => return Err(error.into()),
+        };
+        let (mut index, link) = Index::parse(&data)?;
+        if let Some(link) = link {
+            index.merge_shared(path, link)?;
+        }
+
+        Ok(index)
+    }
+
+    fn parse(data: &[u8]) -> Result<(Index, Option<Link>)> {
+        let corrupt = |what: &str| Error::CorruptIndex(what.to_string());
+        if data.len() < HEADER_SIZE + 20 || !data.starts_with(SIGNATURE) {
+            return Err(corrupt("bad signature"));
+        }
+        let (content, checksum) = data.split_at(data.len() - 20);
+        // index.skipHash leaves the checksum zeroed
+        if checksum != [0; 20] && sha1::Sha1::digest(content)[..] != *checksum {
+            return Err(corrupt("bad index file sha1 signature"));
+        }
+        let version = be_u32(content, 4).unwrap_or(0);
+        if !(2..=4).contains(&version) {
+            return Err(corrupt(&format!("bad index version {}", version)));
+        }
+        let count = be_u32(content, 8).unwrap_or(0) as usize;
+
+        let mut entries = Vec::with_capacity(count);
+        let mut at = HEADER_SIZE;
+        let mut previous = Vec::new();
+        for _ in 0..count {
+            let field =
+                |i: usize| be_u32(content, at + i * 4).ok_or_else(|| corrupt("truncated entry"));
+            let id = content
+                .get(at + STAT_SIZE..at + STAT_SIZE + 20)
+                .ok_or_else(|| corrupt("truncated entry"))?;
+            let flags =
+                be_u16(content, at + STAT_SIZE + 20).ok_or_else(|| corrupt("truncated entry"))?;
+            let mut entry = IndexEntry {
+                ctime: (field(0)?, field(1)?),
+                mtime: (field(2)?, field(3)?),
+                dev: field(4)?,
+                ino: field(5)?,
+                mode: field(6)?,
+                uid: field(7)?,
+                gid: field(8)?,
+                size: field(9)?,
+                id: hex::encode(id),
+                stage: ((flags >> STAGE_SHIFT) & 3) as u8,
+                assume_valid: flags & ASSUME_VALID != 0,
+                ..IndexEntry::default()
+            };
+            let mut name = at + ENTRY_SIZE;
+            if flags & EXTENDED != 0 {
+                if version < 3 {
+                    return Err(corrupt("extended flags in a version 2 index"));
+                }
+                entry.extended_flags =
+                    be_u16(content, name).ok_or_else(|| corrupt("truncated entry"))?;
+                name += 2;
+            }
+
+            let path = if version == 4 {
+                // each path drops some bytes from the end of the one before and adds its own
+                let (strip, suffix) =
+                    read_varint(content, name).ok_or_else(|| corrupt("truncated entry"))?;
+                let end = content[suffix..]
+                    .iter()
+                    .position(|b| *b == b'\0')
+                    .ok_or_else(|| corrupt("unterminated path"))?;
+                if strip > previous.len() {
+                    return Err(corrupt("bad path prefix"));
+                }
+                previous.truncate(previous.len() - strip);
+                previous.extend_from_slice(&content[suffix..suffix + end]);
+                at = suffix + end + 1;
+                previous.clone()
+            } else {
+                let end = content[name..]
+                    .iter()
+                    .position(|b| *b == b'\0')
+                    .ok_or_else(|| corrupt("unterminated path"))?;
+                // entries are padded with 1 to 8 NULs to a multiple of 8 bytes
+                at += (name - at + end + 8) & !7;
+                content[name..name + end].to_vec()
+            };
+            entry.path = String::from_utf8(path).map_err(|_| corrupt("path is not UTF-8"))?;
+            entries.push(entry);
+        }
+
+        // extensions follow the entries; the optional ones start with an upper case letter
+        let mut index = Index {
+            entries,
+            ..Index::default()
+        };
+        let mut link = None;
+        while at + 8 <= content.len() {
+            let signature = &content[at..at + 4];
+            let size = be_u32(content, at + 4).unwrap_or(0) as usize;
+            let data = content
+                .get(at + 8..at + 8 + size)
+                .ok_or_else(|| corrupt("truncated extension"))?;
+            match signature {
+                _ if signature == LINK => {
+                    link = Some(read_link(data).ok_or_else(|| corrupt("bad link extension"))?)
+                }
+                _ if signature == UNTRACKED_CACHE => index.untracked = UntrackedCache::parse(data),
+                _ if signature == FSMONITOR => index.read_fsmonitor(data),
+                _ if signature[0].is_ascii_uppercase() => {}
+                _ => {
+                    return Err(corrupt(&format!(
+                        "unsupported extension {}",
+                        String::from_utf8_lossy(signature)
+                    )))
+                }
+            }
+            at += 8 + size;
+        }
+
+        Ok((index, link))
+    }
+
+    // a split index has only the entries that differ from the shared one: first those that
+    // replace the shared entries the link extension marks, without their paths, then the new
+    // ones
+    fn merge_shared(&mut self, path: &Path, link: Link) -> Result<()> {
+        let corrupt = |what: &str| Error::CorruptIndex(what.to_string());
+        let file = shared_index_path(path, &link.id);
+        let data = fs::read(&file).map_err(|error| {
+            io::Error::new(
+                error.kind(),
+                format!("{}: index file open failed: {}", file.display(), error),
+            )
+        })?;
+        let (shared, _) = Index::parse(&data)?;
+        if hex::encode(&data[data.len() - 20..]) != link.id {
+            return Err(corrupt(&format!(
+                "broken index, expect {} in {}",
+                link.id,
+                file.display()
+            )));
+        }
+        // keeps it from expiring while something still uses it
+        freshen(&file);
+
+        let mut split = std::mem::take(&mut self.entries).into_iter();
+        let mut entries = shared.entries.clone();
+        for (i, _) in link.replace.iter().enumerate().filter(|(_, set)| **set) {
+            let (Some(base), Some(mut entry)) = (entries.get_mut(i), split.next()) else {
+                return Err(corrupt("bad replace bitmap in link extension"));
+            };
+            if !entry.path.is_empty() {
+                return Err(corrupt("replacement entry with a name in link extension"));
+            }
+            entry.path = std::mem::take(&mut base.path);
+            *base = entry;
+        }
+        let mut deleted = link.delete.into_iter().chain(repeat(false));
+        entries.retain(|_| !deleted.next().unwrap_or(false));
+        self.entries = entries;
+        for entry in split {
+            if entry.path.is_empty() {
+                return Err(corrupt("entry without a name in split index"));
+            }
+            match self.position(&entry.path, entry.stage) {
+                Ok(i) => self.entries[i] = entry,
+                Err(i) => self.entries.insert(i, entry),
+            }
+        }
+
+        self.split = Some(SplitIndex::default());
+        self.shared = Some(SharedIndex {
+            id: link.id,
+            entries: shared.entries,
+        });
+        Ok(())
+    }
+
+    // the version, the token, and a bitmap of the entries the hook had reported
+    fn read_fsmonitor(&mut self, data: &[u8]) {
+        if be_u32(data, 0) != Some(FSMONITOR_VERSION) {
+            return;
+        }
+        let Some(end) = data.iter().skip(4).position(|b| *b == b'\0') else {
+            return;
+        };
+        let bitmap = data.get(4 + end + 1 + 4..).and_then(ewah::read);
+        if let Some((dirty, _)) = bitmap {
+            self.fsmonitor_token = Some(String::from_utf8_lossy(&data[4..4 + end]).into_owned());
+            self.fsmonitor_dirty = dirty;
+        }
+    }
+
+    // written through index.lock so readers never see a partly written index
+    pub fn write(&mut self, path: &Path) -> Result<()> {
+        self.sort();
+        let mut extensions = vec![];
+        if let Some(untracked) = &self.untracked {
+            let mut extension = vec![];
+            untracked.write(&mut extension);
+            write_extension(&mut extensions, UNTRACKED_CACHE, &extension);
+        }
+        if let Some(token) = &self.fsmonitor_token {
+            let mut extension = FSMONITOR_VERSION.to_be_bytes().to_vec();
+            extension.extend_from_slice(token.as_bytes());
+            extension.push(0);
+            let dirty: Vec<bool> = self
+                .entries
+                .iter()
+                .map(|entry| !entry.fsmonitor_valid)
+                .collect();
+            let bitmap = ewah::write(&dirty);
+            extension.extend_from_slice(&(bitmap.len() as u32).to_be_bytes());
+            extension.extend_from_slice(&bitmap);
+            write_extension(&mut extensions, FSMONITOR, &extension);
+        }
+
+        let data = match self.split {
+            Some(split) => self.encode_split(path, split, &extensions)?,
+            None => encode(&self.entries, &extensions)?,
+        };
+        write_locked(path, &data)
+    }
+
+    // only what differs from the shared index is written, unless that's too much of the
+    // index, when everything goes into a new shared index and the split index is left empty
+    fn encode_split(
+        &mut self,
+        path: &Path,
+        split: SplitIndex,
+        extensions: &[u8],
+    ) -> Result<Vec<u8>> {
+        let changes = self
+            .shared
+            .as_ref()
+            .filter(|shared| shared_index_path(path, &shared.id).exists())
+            .map(|shared| {
+                (
+                    shared.id.clone(),
+                    split_changes(&shared.entries, &self.entries),
+                )
+            })
+            .filter(|(_, (changed, _, _))| match split.max_percent_change {
+                0 => false,
+                100.. => true,
+                max => changed.len() * 100 <= self.entries.len() * max as usize,
+            });
+
+        let (id, entries, delete, replace) = match changes {
+            Some((id, (changed, delete, replace))) => {
+                freshen(&shared_index_path(path, &id));
+                (id, changed, delete, replace)
+            }
+            None => {
+                let data = encode(&self.entries, &[])?;
+                let id = hex::encode(&data[data.len() - 20..]);
+                let file = shared_index_path(path, &id);
+                if !file.exists() {
+                    write_locked(&file, &data)?;
+                }
+                remove_expired_shared_indexes(path, &id, split.shared_index_expire);
+                self.shared = Some(SharedIndex {
+                    id: id.clone(),
+                    entries: self.entries.clone(),
+                });
+                let unchanged = vec![false; self.entries.len()];
+                (id, vec![], unchanged.clone(), unchanged)
+            }
+        };
+
+        let mut link = hex::decode(&id).map_err(|_| Error::InvalidObjectId(id.clone()))?;
+        link.extend_from_slice(&ewah::write(&delete));
+        link.extend_from_slice(&ewah::write(&replace));
+        let mut data = vec![];
+        write_extension(&mut data, LINK, &link);
+        data.extend_from_slice(extensions);
+        encode(&entries, &data)
+    }
+
+    pub fn split_index(&self) -> Option<SplitIndex> {
+        self.split
+    }
+
+    // None writes the whole index again; turning it on again writes a new shared index
+    pub fn set_split_index(&mut self, split: Option<SplitIndex>) {
+        if split.is_none() {
+            self.shared = None;
+        }
+        self.split = split;
+    }
+
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    pub fn find(&self, path: &str, stage: u8) -> Option<&IndexEntry> {
+        self.position(path, stage).ok().map(|i| &self.entries[i])
+    }
+
+    pub fn find_mut(&mut self, path: &str, stage: u8) -> Option<&mut IndexEntry> {
+        self.position(path, stage)
+            .ok()
+            .map(|i| &mut self.entries[i])
+    }
+
+    pub fn has_conflicts(&self) -> bool {
+        self.entries.iter().any(|entry| entry.stage != 0)
+    }
+
+    // adding a merged entry resolves any conflict at its path
+    pub fn add(&mut self, entry: IndexEntry) {
+        if let Some(untracked) = &mut self.untracked {
+            if !self
+                .entries
+                .iter()
+                .any(|existing| existing.path == entry.path)
+            {
+                untracked.invalidate(&entry.path);
+            }
+        }
+        if entry.stage == 0 {
+            self.entries
+                .retain(|existing| existing.path != entry.path || existing.stage == 0);
+        }
+        match self.position(&entry.path, entry.stage) {
+            Ok(i) => self.entries[i] = entry,
+            Err(i) => self.entries.insert(i, entry),
+        }
+    }
+
+    // removes every stage of `path`, saying whether there was any
+    pub fn remove(&mut self, path: &str) -> bool {
+        let count = self.entries.len();
+        self.entries.retain(|entry| entry.path != path);
+        if let Some(untracked) = &mut self.untracked {
+            untracked.invalidate(path);
+        }
+        self.entries.len() != count
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        if let Some(untracked) = &mut self.untracked {
+            untracked.clear();
+        }
+    }
+
+    pub fn take_untracked_cache(&mut self) -> Option<UntrackedCache> {
+        self.untracked.take()
+    }
+
+    pub fn set_untracked_cache(&mut self, untracked: Option<UntrackedCache>) {
+        self.untracked = untracked;
+    }
+
+    pub fn fsmonitor_token(&self) -> Option<&str> {
+        self.fsmonitor_token.as_deref()
+    }
+
+    // takes in what the fsmonitor hook said when asked with the token the index was read with:
+    // the entries it was still quiet about since they were last seen to match stay valid,
+    // unless it couldn't tell (`changed` is None). No token drops the extension. This has to
+    // come before the entries change
+    pub fn update_fsmonitor(&mut self, token: Option<String>, changed: Option<&[String]>) {
+        let dirty = std::mem::take(&mut self.fsmonitor_dirty);
+        let known = self.fsmonitor_token.is_some() && dirty.len() == self.entries.len();
+        // a directory stands for everything in it
+        let changed: Option<HashSet<&str>> = changed.map(|changed| {
+            changed
+                .iter()
+                .map(|path| path.trim_end_matches('/'))
+                .collect()
+        });
+        for (entry, dirty) in self
+            .entries
+            .iter_mut()
+            .zip(dirty.into_iter().chain(repeat(true)))
+        {
+            entry.fsmonitor_valid = match (&changed, token.is_some() && known && !dirty) {
+                (Some(changed), true) => {
+                    !changed.contains(entry.path.as_str())
+                        && !entry
+                            .path
+                            .match_indices('/')
+                            .any(|(slash, _)| changed.contains(&entry.path[..slash]))
+                }
+                _ => false,
+            };
+        }
+        self.fsmonitor_token = token;
+    }
+
+    // a merge of a tree into the index as it was read from `head`, switching to `merge`: paths
+    // the index changed are kept unless the merge changes them too
+    pub fn two_way_merge(&mut self, head: &[IndexEntry], merge: &[IndexEntry]) -> Result<()> {
+        if self.has_conflicts() {
+            return Err(Error::UnmergedIndex);
+        }
+        for path in union_paths(&[head, merge]) {
+            let head = find_entry(head, path);
+            let merge = find_entry(merge, path);
+            let current = self.find(path, 0);
+            if same(head, merge) {
+                continue;
+            }
+            match current {
+                Some(current) if merge.is_some_and(|merge| merge.same_content(current)) => {}
+                Some(current) if !head.is_some_and(|head| head.same_content(current)) => {
+                    return Err(Error::WouldOverwrite(path.to_string()));
+                }
+                _ => self.take(path, merge),
+            }
+        }
+
+        Ok(())
+    }
+
+    // a merge of `ours` and `theirs` from `base`: paths only one side changed are resolved to
+    // that side and the rest are left as conflicts, with one stage per tree that has the path
+    pub fn three_way_merge(
+        &mut self,
+        base: &[IndexEntry],
+        ours: &[IndexEntry],
+        theirs: &[IndexEntry],
+    ) -> Result<()> {
+        if self.has_conflicts() {
+            return Err(Error::UnmergedIndex);
+        }
+        for path in union_paths(&[base, ours, theirs]) {
+            let base = find_entry(base, path);
+            let ours = find_entry(ours, path);
+            let theirs = find_entry(theirs, path);
+            // deletions are left for whoever resolves the conflicts
+            let merged = match (base, ours, theirs) {
+                (_, Some(ours), Some(theirs)) if ours.same_content(theirs) => Some(ours),
+                (Some(base), Some(ours), Some(theirs)) if base.same_content(ours) => Some(theirs),
+                (Some(base), Some(ours), Some(theirs)) if base.same_content(theirs) => Some(ours),
+                (None, Some(ours), None) => Some(ours),
+                (None, None, Some(theirs)) => Some(theirs),
+                _ => None,
+            };
+
+            // the index has to agree with our side wherever the merge would change it
+            let current = self.find(path, 0);
+            if let (Some(current), Some(merged)) = (current, merged) {
+                if merged.same_content(current) {
+                    continue;
+                }
+            }
+            if let Some(current) = current {
+                if !ours.is_some_and(|ours| ours.same_content(current)) {
+                    return Err(Error::WouldOverwrite(path.to_string()));
+                }
+            }
+
+            self.remove(path);
+            match merged {
+                Some(merged) => self.take(path, Some(merged)),
+                None => {
+                    for (stage, entry) in [(1, base), (2, ours), (3, theirs)] {
+                        if let Some(entry) = entry {
+                            self.add(IndexEntry {
+                                stage,
+                                ..IndexEntry::new(path, entry.mode, &entry.id)
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // sets `path` to what a tree has there, keeping the stat data if the content is the same
+    fn take(&mut self, path: &str, entry: Option<&IndexEntry>) {
+        let Some(entry) = entry else {
+            self.remove(path);
+            return;
+        };
+        if self
+            .find(path, 0)
+            .is_some_and(|current| current.same_content(entry))
+        {
+            return;
+        }
+        self.add(IndexEntry::new(path, entry.mode, &entry.id));
+    }
+
+    fn position(&self, path: &str, stage: u8) -> Result<usize, usize> {
+        self.entries.binary_search_by(|entry| {
+            (entry.path.as_bytes(), entry.stage).cmp(&(path.as_bytes(), stage))
+        })
+    }
+
+    fn sort(&mut self) {
+        self.entries
+            .sort_by(|a, b| (a.path.as_bytes(), a.stage).cmp(&(b.path.as_bytes(), b.stage)));
+    }
+}
+
+// the entries and extensions as an index file, with its checksum
+fn encode(entries: &[IndexEntry], extensions: &[u8]) -> Result<Vec<u8>> {
+    let extended = entries.iter().any(|entry| entry.extended_flags != 0);
+    let mut data = SIGNATURE.to_vec();
+    data.extend_from_slice(&(if extended { 3u32 } else { 2 }).to_be_bytes());
+    data.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+    for entry in entries {
+        let start = data.len();
+        for field in [
+            entry.ctime.0,
+            entry.ctime.1,
+            entry.mtime.0,
+            entry.mtime.1,
+            entry.dev,
+            entry.ino,
+            entry.mode,
+            entry.uid,
+            entry.gid,
+            entry.size,
+        ] {
+            data.extend_from_slice(&field.to_be_bytes());
+        }
+        let id = hex::decode(&entry.id).map_err(|_| Error::InvalidObjectId(entry.id.clone()))?;
+        data.extend_from_slice(&id);
+        let mut flags = (entry.stage as u16 & 3) << STAGE_SHIFT;
+        flags |= entry.path.len().min(NAME_MASK as usize) as u16;
+        if entry.assume_valid {
+            flags |= ASSUME_VALID;
+        }
+        if entry.extended_flags != 0 {
+            flags |= EXTENDED;
+        }
+        data.extend_from_slice(&flags.to_be_bytes());
+        if entry.extended_flags != 0 {
+            data.extend_from_slice(&entry.extended_flags.to_be_bytes());
+        }
+        data.extend_from_slice(entry.path.as_bytes());
+        let length = data.len() - start;
+        data.resize(start + ((length + 8) & !7), 0);
+    }
+    data.extend_from_slice(extensions);
+    let checksum = sha1::Sha1::digest(&data);
+    data.extend_from_slice(&checksum);
+
+    Ok(data)
+}
+
+fn write_locked(path: &Path, data: &[u8]) -> Result<()> {
+    let mut lock = path.as_os_str().to_owned();
+    lock.push(".lock");
+    let lock = PathBuf::from(lock);
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&lock)
+        .map_err(|error| {
+            io::Error::new(
+                error.kind(),
+                format!("Unable to create '{}': {}", lock.display(), error),
+            )
+        })?;
+    let written = file.write_all(data).and_then(|_| file.sync_all());
+    drop(file);
+    if let Err(error) = written.and_then(|_| fs::rename(&lock, path)) {
+        let _ = fs::remove_file(&lock);
+        return Err(error.into());
+    }
+
+    Ok(())
+}
+
+// the shared index `id`, which lives beside the index that uses it
+fn shared_index_path(index: &Path, id: &str) -> PathBuf {
+    index.with_file_name(format!("sharedindex.{}", id))
+}
+
+fn read_link(data: &[u8]) -> Option<Link> {
+    let id = hex::encode(data.get(..20)?);
+    // an index that has all its entries in the shared one may leave the bitmaps out
+    if data.len() == 20 {
+        return Some(Link {
+            id,
+            delete: vec![],
+            replace: vec![],
+        });
+    }
+    let (delete, size) = ewah::read(&data[20..])?;
+    let (replace, _) = ewah::read(&data[20 + size..])?;
+    Some(Link {
+        id,
+        delete,
+        replace,
+    })
+}
+
+// the entries that differ from the shared ones: replacements for changed shared entries, in
+// the order of those, with no paths, and then the entries that are new, along with which shared
+// entries are gone and which are replaced
+fn split_changes(
+    shared: &[IndexEntry],
+    entries: &[IndexEntry],
+) -> (Vec<IndexEntry>, Vec<bool>, Vec<bool>) {
+    fn key(entry: &IndexEntry) -> (&[u8], u8) {
+        (entry.path.as_bytes(), entry.stage)
+    }
+    let mut delete = vec![true; shared.len()];
+    let mut replace = vec![false; shared.len()];
+    let mut changed = vec![];
+    let mut added = vec![];
+    let mut i = 0;
+    for entry in entries {
+        while i < shared.len() && key(&shared[i]) < key(entry) {
+            i += 1;
+        }
+        if i < shared.len() && key(&shared[i]) == key(entry) {
+            delete[i] = false;
+            if !entry.same_entry(&shared[i]) {
+                replace[i] = true;
+                changed.push(IndexEntry {
+                    path: String::new(),
+                    ..entry.clone()
+                });
+            }
+            i += 1;
+        } else {
+            added.push(entry.clone());
+        }
+    }
+    changed.extend(added);
+
+    (changed, delete, replace)
+}
+
+// touches the shared index, whose modification time says when it was last used
+fn freshen(path: &Path) {
+    if let Ok(file) = fs::File::options().append(true).open(path) {
+        let _ = file.set_modified(SystemTime::now());
+    }
+}
+
+// the shared indexes besides `id` that nothing has used since `expire`
+fn remove_expired_shared_indexes(index: &Path, id: &str, expire: i64) {
+    let dir = match index.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let Ok(files) = fs::read_dir(dir) else {
+        return;
+    };
+    for file in files.flatten() {
+        let name = file.file_name();
+        let Some(other) = name
+            .to_str()
+            .and_then(|name| name.strip_prefix("sharedindex."))
+        else {
+            continue;
+        };
+        let modified = file
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map_or(0, |time| time.as_secs() as i64);
+        if other != id && modified <= expire {
+            let _ = fs::remove_file(file.path());
+        }
+    }
+}
+
+fn write_extension(data: &mut Vec<u8>, signature: &[u8; 4], extension: &[u8]) {
+    data.extend_from_slice(signature);
+    data.extend_from_slice(&(extension.len() as u32).to_be_bytes());
+    data.extend_from_slice(extension);
+}
+
+fn same(a: Option<&IndexEntry>, b: Option<&IndexEntry>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.same_content(b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+// the entry for `path` in a list sorted by path, with one stage of each
+pub fn find_entry<'a>(entries: &'a [IndexEntry], path: &str) -> Option<&'a IndexEntry> {
+    entries
+        .binary_search_by(|entry| entry.path.as_bytes().cmp(path.as_bytes()))
+        .ok()
+        .map(|i| &entries[i])
+}
+
+// every path in any of the sorted lists, in order
+fn union_paths<'a>(lists: &[&'a [IndexEntry]]) -> Vec<&'a str> {
+    let mut paths: Vec<&str> = lists
+        .iter()
+        .flat_map(|entries| entries.iter().map(|entry| entry.path.as_str()))
+        .collect();
+    paths.sort_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
+    paths.dedup();
+
+    paths
+}
+
+// the offset encoding git uses in version 4 paths and some extensions, with where the value
+// ends
+pub fn read_varint(data: &[u8], mut at: usize) -> Option<(usize, usize)> {
+    let mut byte = *data.get(at)?;
+    let mut value = (byte & 0x7f) as usize;
+    while byte & 0x80 != 0 {
+        at += 1;
+        byte = *data.get(at)?;
+        value = ((value + 1) << 7) | (byte & 0x7f) as usize;
+    }
+
+    Some((value, at + 1))
+}
+
+pub fn write_varint(data: &mut Vec<u8>, mut value: usize) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    while value >= 0x80 {
+        value = (value >> 7) - 1;
+        bytes.push(0x80 | (value & 0x7f) as u8);
+    }
+    bytes.reverse();
+    data.extend_from_slice(&bytes);
+}
+
+fn be_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn be_u16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
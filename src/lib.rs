@@ -1,4 +1,65 @@
 mod app;
+mod apply;
+mod attributes;
+mod blame;
+mod cli;
+mod color;
+mod commit_graph;
+mod config;
+mod convert;
+mod credential;
+mod delta;
+mod diff;
+mod editor;
+mod error;
+mod ewah;
+mod fast_export;
+mod fast_import;
+mod fsmonitor;
 mod git_client;
+mod graph;
+mod http;
+mod ignore;
+mod index;
+mod maintenance;
+mod merge;
+mod message;
+mod midx;
+#[cfg(all(feature = "mmap", unix))]
+mod mmap;
+mod name_rev;
+mod objects;
+mod pack;
+mod pager;
+mod parallel;
+mod patch;
+mod paths;
+mod pathspec;
+mod pickaxe;
+pub mod pktline;
+mod pretty;
+mod progress;
+mod reflog;
+mod refname;
+mod regex;
+mod repository;
+mod rerere;
+mod rewrite;
+mod sign;
+mod submodule;
+mod subtree;
+#[cfg(test)]
+mod testing;
+mod trace;
+mod trailer;
+mod untracked;
+mod wildmatch;
+mod worktree;
+mod xdiff;
 
 pub use app::App;
+pub use error::{Error, Result};
+pub use git_client::{Refs, Repo};
+pub use objects::{GitObjectType, TreeEntry};
+pub use progress::{NoProgress, Progress, TerminalProgress};
+pub use repository::{Ancestors, Commit, Object, Repository, Signature};
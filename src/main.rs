@@ -2,13 +2,22 @@
 use std::env;
 #[allow(unused_imports)]
 use std::fs;
+use std::io;
 
-use git_starter_rust::App;
+use git_starter_rust::{App, Error};
 
 fn main() {
     // Uncomment this block to pass the first stage
     let args: Vec<String> = env::args().collect();
 
-    App::new().run(args); 
-
+    if let Err(error) = App::new().run(args) {
+        match &error {
+            Error::Usage(usage) => eprintln!("{}", usage),
+            Error::Exit(_) => {}
+            // the reader went away, as in `rgit ls-tree -r HEAD | head`, which isn't worth a word
+            Error::Io(error) if error.kind() == io::ErrorKind::BrokenPipe => {}
+            error => eprintln!("fatal: {}", error),
+        }
+        std::process::exit(error.exit_code());
+    }
 }
@@ -0,0 +1,873 @@
+use std::{
+    collections::HashSet,
+    env, fs, io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::SystemTime,
+};
+
+use crate::{
+    commit_graph,
+    config::{self, Config},
+    diff,
+    error::{Error, Result},
+    index::Index,
+    midx,
+    objects::{self, GitObjectType},
+    pack::{self, PackIndex, PackObject, PackOptions},
+    paths,
+    progress::Progress,
+    reflog,
+    repository::{self, Repository},
+    worktree,
+};
+
+// maintenance keeps a repository quick to work in: refs packed into one file, objects into
+// packs, and a commit-graph for walks to read instead of the commits. `maintenance run` does
+// the tasks asked for or enabled, and `maintenance start` has cron or systemd run it hourly,
+// daily and weekly in every repository registered in the global maintenance.repo
+
+const CRON_BEGIN: &str = "# BEGIN GIT MAINTENANCE SCHEDULE";
+const CRON_END: &str = "# END GIT MAINTENANCE SCHEDULE";
+// how many loose objects the loose-objects task packs at a time, unless
+// maintenance.loose-objects.batchSize says otherwise
+const LOOSE_OBJECTS_BATCH_SIZE: usize = 50_000;
+// how old an unreachable object has to be before gc removes it, unless gc.pruneExpire says
+// otherwise; anything younger may belong to a command still running
+const DEFAULT_PRUNE_EXPIRE: &str = "2.weeks.ago";
+// how many loose objects it takes for gc --auto to run, unless gc.auto says otherwise
+const DEFAULT_GC_AUTO: i64 = 6700;
+// how many packs it takes for gc --auto to run, unless gc.autoPackLimit says otherwise
+const DEFAULT_GC_AUTO_PACK_LIMIT: i64 = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Task {
+    Prune,
+    LooseObjects,
+    Gc,
+    CommitGraph,
+    PackRefs,
+}
+
+impl Task {
+    // in the order they run in when more than one is enabled
+    pub const ALL: [Task; 5] = [
+        Task::Prune,
+        Task::LooseObjects,
+        Task::Gc,
+        Task::CommitGraph,
+        Task::PackRefs,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Task::Prune => "prune",
+            Task::LooseObjects => "loose-objects",
+            Task::Gc => "gc",
+            Task::CommitGraph => "commit-graph",
+            Task::PackRefs => "pack-refs",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Task> {
+        Task::ALL.into_iter().find(|task| task.name() == name)
+    }
+
+    // how often maintenance.strategy=incremental has the task run, if at all
+    fn incremental_schedule(self) -> Option<Schedule> {
+        match self {
+            Task::CommitGraph => Some(Schedule::Hourly),
+            Task::LooseObjects => Some(Schedule::Daily),
+            Task::PackRefs => Some(Schedule::Weekly),
+            Task::Prune | Task::Gc => None,
+        }
+    }
+
+    pub fn run(self, repository: &Repository, progress: &mut dyn Progress) -> Result<()> {
+        match self {
+            Task::Prune => prune(repository, prune_expire(repository)?, false).map(|_| ()),
+            Task::LooseObjects => pack_loose_objects(repository, progress),
+            Task::Gc => gc(repository, prune_expire(repository)?, progress),
+            Task::CommitGraph => write_commit_graph(repository).map(|_| ()),
+            Task::PackRefs => repository.pack_references(true, true),
+        }
+    }
+}
+
+// ordered so that the more often a schedule runs, the greater it is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Schedule {
+    Weekly,
+    Daily,
+    Hourly,
+}
+
+impl Schedule {
+    pub const ALL: [Schedule; 3] = [Schedule::Hourly, Schedule::Daily, Schedule::Weekly];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Schedule::Hourly => "hourly",
+            Schedule::Daily => "daily",
+            Schedule::Weekly => "weekly",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Schedule> {
+        Schedule::ALL
+            .into_iter()
+            .find(|schedule| schedule.name() == name)
+    }
+
+    // the hourly run skips midnight, when the daily one runs, and the daily one skips Sunday,
+    // when the weekly one does
+    fn cron_times(self) -> &'static str {
+        match self {
+            Schedule::Hourly => "0 1-23 * * *",
+            Schedule::Daily => "0 0 * * 1-6",
+            Schedule::Weekly => "0 0 * * 0",
+        }
+    }
+
+    fn systemd_calendar(self) -> &'static str {
+        match self {
+            Schedule::Hourly => "*-*-* 1..23:00:00",
+            Schedule::Daily => "Mon..Sat *-*-* 0:00:00",
+            Schedule::Weekly => "Sun *-*-* 0:00:00",
+        }
+    }
+}
+
+// the tasks to run: those given, or else the ones maintenance.<task>.enabled turns on, which
+// is only gc by default; with a schedule, only those that maintenance.<task>.schedule, or the
+// incremental maintenance.strategy, has run at least that often
+pub fn tasks(config: &Config, selected: &[Task], schedule: Option<Schedule>) -> Vec<Task> {
+    let incremental = schedule.is_some()
+        && config
+            .get("maintenance.strategy")
+            .is_some_and(|strategy| strategy.eq_ignore_ascii_case("incremental"));
+    let candidates = match selected.is_empty() {
+        true => Task::ALL.to_vec(),
+        false => selected.to_vec(),
+    };
+    candidates
+        .into_iter()
+        .filter(|task| {
+            let mut enabled = *task == Task::Gc;
+            let mut task_schedule = None;
+            if incremental {
+                task_schedule = task.incremental_schedule();
+                enabled |= task_schedule.is_some();
+            }
+            if let Some(value) = config.get(&format!("maintenance.{}.enabled", task.name())) {
+                enabled = config::parse_bool(value).unwrap_or(enabled);
+            }
+            if let Some(value) = config.get(&format!("maintenance.{}.schedule", task.name())) {
+                task_schedule = Schedule::from_name(value);
+            }
+            (enabled || !selected.is_empty())
+                && schedule.is_none_or(|schedule| task_schedule >= Some(schedule))
+        })
+        .collect()
+}
+
+// objects/maintenance.lock, held while maintenance runs so that only one run works on a
+// repository at a time
+pub struct Lock(PathBuf);
+
+impl Lock {
+    // None when another run holds it
+    pub fn acquire(object_dir: &Path) -> Result<Option<Lock>> {
+        let path = object_dir.join("maintenance.lock");
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(_) => Ok(Some(Lock(path))),
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+// the expiry gc prunes with when not told one, from gc.pruneExpire
+pub fn prune_expire(repository: &Repository) -> Result<i64> {
+    let config = Config::for_repository(repository.common_dir());
+    let date = config.get("gc.pruneExpire").unwrap_or(DEFAULT_PRUNE_EXPIRE);
+    repository::parse_expiry_date(date).ok_or_else(|| Error::InvalidDate(date.to_string()))
+}
+
+// packs the refs, expires the old reflog entries, packs every object something reaches into
+// one place, writes the commit-graph and prunes what nothing reaches and was last written
+// before `expire`, and the work trees gone for longer than gc.worktreePruneExpire
+pub fn gc(repository: &Repository, expire: i64, progress: &mut dyn Progress) -> Result<()> {
+    repository.pack_references(true, true)?;
+    reflog::expire_all(repository, None, None, reflog::Options::default(), false)?;
+    repack(repository, expire, progress)?;
+    write_commit_graph(repository)?;
+    prune(repository, expire, false)?;
+    let config = Config::for_repository(repository.common_dir());
+    let date = config
+        .get("gc.worktreePruneExpire")
+        .unwrap_or(worktree::DEFAULT_PRUNE_EXPIRE);
+    let worktree_expire =
+        repository::parse_expiry_date(date).ok_or_else(|| Error::InvalidDate(date.to_string()))?;
+    worktree::prune(repository, worktree_expire, false, false)?;
+    Ok(())
+}
+
+// whether gc --auto has something to do: there are more packs than gc.autoPackLimit, not
+// counting the kept ones, or more loose objects than gc.auto, which is guessed from how many
+// are in one of the 256 directories they are spread over. 0 turns a check off, and gc.auto
+// set to 0 turns off both
+pub fn needs_gc(repository: &Repository) -> bool {
+    let config = Config::for_repository(repository.common_dir());
+    let limit = |key: &str, default: i64| {
+        config
+            .get(key)
+            .map_or(default, |value| value.parse().unwrap_or(0))
+    };
+    let auto = limit("gc.auto", DEFAULT_GC_AUTO);
+    if auto <= 0 {
+        return false;
+    }
+    let object_dir = repository.object_dir();
+    let pack_limit = limit("gc.autoPackLimit", DEFAULT_GC_AUTO_PACK_LIMIT);
+    let packs = pack::pack_indexes(&object_dir)
+        .into_iter()
+        .filter(|index| !index.with_extension("keep").exists())
+        .count();
+    if pack_limit > 0 && packs as i64 > pack_limit {
+        return true;
+    }
+    let loose = fs::read_dir(object_dir.join("17"))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            name.len() == 38 && name.bytes().all(|b| b.is_ascii_hexdigit())
+        })
+        .count();
+    loose as i64 > (auto + 255) / 256
+}
+
+// removes the loose objects nothing reaches that were last written before `expire`, or with
+// `dry_run` only finds them, giving each with its type when it can be read; the loose copies
+// of packed objects go as well. Younger objects are kept since a command still running may be
+// about to refer to them
+pub fn prune(
+    repository: &Repository,
+    expire: i64,
+    dry_run: bool,
+) -> Result<Vec<(String, Option<GitObjectType>)>> {
+    let object_dir = repository.object_dir();
+    let reachable: HashSet<String> = reachable_objects(repository, &roots(repository)?)?
+        .into_iter()
+        .map(|object| object.id)
+        .collect();
+
+    let mut pruned = vec![];
+    for id in objects::loose_objects(&object_dir) {
+        if reachable.contains(&id) || modified(&loose_path(&object_dir, &id)) > expire {
+            continue;
+        }
+        let object_type =
+            objects::read_object(&object_dir, &id).map(|(object_type, _)| object_type);
+        if !dry_run {
+            fs::remove_file(loose_path(&object_dir, &id))?;
+        }
+        pruned.push((id, object_type));
+    }
+    if !dry_run {
+        prune_packed(&object_dir)?;
+    }
+    Ok(pruned)
+}
+
+// writes the commit-graph of the commits the refs and HEAD reach, giving how many it lists;
+// nothing is written when shallow commits or replacements make history other than what the
+// commits themselves say
+pub fn write_commit_graph(repository: &Repository) -> Result<usize> {
+    let replaced = repository
+        .references()
+        .any(|(name, _)| name.starts_with("refs/replace/"));
+    if replaced || !repository::read_shallow(repository.common_dir()).is_empty() {
+        return Ok(0);
+    }
+    let mut tips: Vec<String> = repository
+        .references()
+        .map(|(_, id)| id)
+        .chain(repository.head())
+        .filter_map(|id| repository.peel(&id, GitObjectType::Commit).ok())
+        .map(|commit| commit.id)
+        .collect();
+    tips.sort();
+    tips.dedup();
+    if tips.is_empty() {
+        return Ok(0);
+    }
+    commit_graph::write(repository, &tips)
+}
+
+// every object reachable from the refs, HEAD, the index and the reflogs goes into one new
+// pack, apart from those in packs kept with a .keep file, and the other packs are removed.
+// What those held that nothing reaches is left loose with the pack's time, so that prune
+// expires it as it would have the pack, unless the pack has expired already
+fn repack(repository: &Repository, expire: i64, progress: &mut dyn Progress) -> Result<()> {
+    let object_dir = repository.object_dir();
+    let (kept, old): (Vec<PathBuf>, Vec<PathBuf>) = pack::pack_indexes(&object_dir)
+        .into_iter()
+        .partition(|index| index.with_extension("keep").exists());
+
+    // objects only in an alternate stay there
+    let mut local: HashSet<String> = objects::loose_objects(&object_dir).into_iter().collect();
+    local.extend(packed_objects(&old)?);
+    let in_kept: HashSet<String> = packed_objects(&kept)?.into_iter().collect();
+
+    progress.start("Enumerating objects", None);
+    let reachable = reachable_objects(repository, &roots(repository)?)?;
+    progress.update(reachable.len() as u64);
+    progress.finish();
+    let reachable_ids: HashSet<String> = reachable.iter().map(|object| object.id.clone()).collect();
+    let objects: Vec<PackObject> = reachable
+        .into_iter()
+        .filter(|object| local.contains(&object.id) && !in_kept.contains(&object.id))
+        .collect();
+    let new = match objects.is_empty() {
+        true => None,
+        false => Some(pack::write_pack(
+            &object_dir,
+            &objects,
+            &pack_options(repository),
+            progress,
+        )?),
+    };
+
+    for index in &old {
+        if new
+            .as_ref()
+            .is_some_and(|new| *index == pack_path(&object_dir, new, "idx"))
+        {
+            continue;
+        }
+        let pack = index.with_extension("pack");
+        let pack_time = modified(&pack);
+        for id in packed_objects(std::slice::from_ref(index))? {
+            let path = loose_path(&object_dir, &id);
+            if reachable_ids.contains(&id) || pack_time <= expire || path.exists() {
+                continue;
+            }
+            let (object_type, content) = objects::read_object(&object_dir, &id)
+                .ok_or_else(|| Error::ObjectNotFound(id.clone()))?;
+            objects::write_loose_object(&object_dir, object_type, &content)?;
+            fs::File::options()
+                .write(true)
+                .open(&path)?
+                .set_modified(fs::metadata(&pack)?.modified()?)?;
+        }
+        for extension in ["pack", "idx", "rev", "bitmap"] {
+            match fs::remove_file(index.with_extension(extension)) {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error.into()),
+                _ => {}
+            }
+        }
+    }
+    prune_packed(&object_dir)?;
+
+    // a multi-pack-index would still list the packs that are gone
+    let pack_dir = object_dir.join("pack");
+    if midx::path(&pack_dir).exists() {
+        match pack::pack_indexes(&object_dir).is_empty() {
+            true => fs::remove_file(midx::path(&pack_dir))?,
+            false => {
+                midx::write(&pack_dir)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// packs loose objects, whether anything reaches them or not, a batch at a time, and removes
+// them once they are packed
+fn pack_loose_objects(repository: &Repository, progress: &mut dyn Progress) -> Result<()> {
+    let object_dir = repository.object_dir();
+    prune_packed(&object_dir)?;
+    let config = Config::for_repository(repository.common_dir());
+    let batch_size = match config.get("maintenance.loose-objects.batchSize") {
+        Some(size) => config::parse_size(size)
+            .map(|size| size as usize)
+            .filter(|size| *size > 0)
+            .unwrap_or(usize::MAX),
+        None => LOOSE_OBJECTS_BATCH_SIZE,
+    };
+    let loose: Vec<PackObject> = objects::loose_objects(&object_dir)
+        .into_iter()
+        .take(batch_size)
+        .map(|id| PackObject {
+            id,
+            path: String::new(),
+        })
+        .collect();
+    if loose.is_empty() {
+        return Ok(());
+    }
+    pack::write_pack(&object_dir, &loose, &pack_options(repository), progress)?;
+    prune_packed(&object_dir)?;
+    Ok(())
+}
+
+// pack.window and pack.depth bound the search for deltas, and repack.useDeltaBaseOffset off
+// has deltas name their bases by id, as older readers need
+fn pack_options(repository: &Repository) -> PackOptions {
+    let config = Config::for_repository(repository.common_dir());
+    let mut options = PackOptions::default();
+    if let Some(window) = config.get("pack.window").and_then(config::parse_size) {
+        options.window = window as usize;
+    }
+    if let Some(depth) = config.get("pack.depth").and_then(config::parse_size) {
+        options.depth = depth as usize;
+    }
+    if let Some(ofs_delta) = config
+        .get("repack.useDeltaBaseOffset")
+        .and_then(config::parse_bool)
+    {
+        options.ofs_delta = ofs_delta;
+    }
+    options
+}
+
+// removes the loose objects that are in a pack as well, giving how many
+fn prune_packed(object_dir: &Path) -> Result<usize> {
+    let mut count = 0;
+    for id in objects::loose_objects(object_dir) {
+        if pack::has_packed_object(object_dir, &id) {
+            fs::remove_file(loose_path(object_dir, &id))?;
+            count += 1;
+        }
+    }
+    // the fan-out directories left empty go too
+    for entry in fs::read_dir(object_dir).into_iter().flatten().flatten() {
+        let name = entry.file_name();
+        if name.len() == 2
+            && name
+                .to_string_lossy()
+                .bytes()
+                .all(|b| b.is_ascii_hexdigit())
+        {
+            let _ = fs::remove_dir(entry.path());
+        }
+    }
+    Ok(count)
+}
+
+// an object to walk from, with its type when that is known without reading it, and its path
+type Root = (String, Option<GitObjectType>, String);
+
+// what keeps objects from being garbage: the refs, the HEAD, index and reflogs of every
+// worktree; index entries are known to be blobs
+fn roots(repository: &Repository) -> Result<Vec<Root>> {
+    let mut roots: Vec<Root> = repository
+        .references()
+        .map(|(_, id)| (id, None, String::new()))
+        .collect();
+
+    let common_dir = repository.common_dir();
+    let mut git_dirs = vec![common_dir.to_path_buf(), repository.git_dir().to_path_buf()];
+    for entry in fs::read_dir(common_dir.join("worktrees"))
+        .into_iter()
+        .flatten()
+        .flatten()
+    {
+        git_dirs.push(entry.path());
+    }
+    git_dirs.dedup();
+    for git_dir in git_dirs {
+        let head = fs::read_to_string(git_dir.join("HEAD")).unwrap_or_default();
+        let head = head.trim();
+        if head.len() == 40 && head.bytes().all(|b| b.is_ascii_hexdigit()) {
+            roots.push((head.to_string(), None, String::new()));
+        }
+        let logs = git_dir.join("logs");
+        let mut reflogs = vec![logs.join("HEAD")];
+        if git_dir == common_dir {
+            reflogs.extend(files_below(&logs.join("refs")));
+        }
+        for reflog in reflogs {
+            // an entry whose objects are gone already keeps nothing
+            roots.extend(
+                reflog_ids(&reflog)
+                    .into_iter()
+                    .filter(|id| repository.has_object(id))
+                    .map(|id| (id, None, String::new())),
+            );
+        }
+        let index = Index::read(&git_dir.join("index"))?;
+        roots.extend(
+            index
+                .entries()
+                .iter()
+                .filter(|entry| entry.mode != 0o160000)
+                .map(|entry| {
+                    let path = entry.path.clone();
+                    (entry.id.clone(), Some(GitObjectType::Blob), path)
+                }),
+        );
+    }
+    Ok(roots)
+}
+
+// the old and new ids of each entry of a reflog, but for the zero id of a ref's creation
+// or deletion
+fn reflog_ids(path: &Path) -> Vec<String> {
+    let mut ids = vec![];
+    for line in fs::read_to_string(path).unwrap_or_default().lines() {
+        let mut fields = line.split(' ');
+        for id in [fields.next(), fields.next()].into_iter().flatten() {
+            if id.len() == 40 && id.bytes().all(|b| b.is_ascii_hexdigit()) && id != diff::NULL_ID {
+                ids.push(id.to_string());
+            }
+        }
+    }
+    ids.sort();
+    ids.dedup();
+    ids
+}
+
+fn files_below(dir: &Path) -> Vec<PathBuf> {
+    let mut files = vec![];
+    for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        match entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+            true => files.extend(files_below(&entry.path())),
+            false => files.push(entry.path()),
+        }
+    }
+    files
+}
+
+// when `path` was last written, in seconds since the epoch
+fn modified(path: &Path) -> i64 {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map_or(0, |time| time.as_secs() as i64)
+}
+
+// every object reachable from `roots`, each once with the path it was first come to at,
+// commits first; submodule commits are another repository's and are not followed
+fn reachable_objects(repository: &Repository, roots: &[Root]) -> Result<Vec<PackObject>> {
+    let mut seen = HashSet::new();
+    let mut reachable = vec![];
+    let mut stack: Vec<Root> = roots.iter().rev().cloned().collect();
+    while let Some((id, object_type, path)) = stack.pop() {
+        if !seen.insert(id.clone()) {
+            continue;
+        }
+        // blobs needn't be read to know where they lead
+        if object_type == Some(GitObjectType::Blob) {
+            reachable.push((PackObject { id, path }, GitObjectType::Blob));
+            continue;
+        }
+        let object = repository.find_object(&id)?;
+        match object.object_type {
+            GitObjectType::Commit => {
+                let commit = repository.find_commit(&id)?;
+                for parent in commit.parents.into_iter().rev() {
+                    stack.push((parent, Some(GitObjectType::Commit), String::new()));
+                }
+                stack.push((commit.tree, Some(GitObjectType::Tree), String::new()));
+            }
+            GitObjectType::Tree => {
+                for entry in objects::parse_tree(&object.data)?.into_iter().rev() {
+                    if entry.mode != "160000" {
+                        let object_type = entry.object_type();
+                        let path = match path.is_empty() {
+                            true => entry.name,
+                            false => format!("{}/{}", path, entry.name),
+                        };
+                        stack.push((entry.id, Some(object_type), path));
+                    }
+                }
+            }
+            GitObjectType::Tag => {
+                let content = String::from_utf8_lossy(&object.data);
+                let target = content
+                    .lines()
+                    .next()
+                    .and_then(|line| line.strip_prefix("object "))
+                    .ok_or_else(|| Error::CorruptObject(id.clone()))?;
+                stack.push((target.to_string(), None, String::new()));
+            }
+            GitObjectType::Blob => {}
+        }
+        reachable.push((PackObject { id, path }, object.object_type));
+    }
+
+    reachable.sort_by_key(|(_, object_type)| *object_type != GitObjectType::Commit);
+    Ok(reachable.into_iter().map(|(object, _)| object).collect())
+}
+
+// the ids in the packs with these indexes
+fn packed_objects(indexes: &[PathBuf]) -> Result<Vec<String>> {
+    let mut ids = vec![];
+    for path in indexes {
+        let index = PackIndex::open(path)?;
+        ids.extend(
+            (0..index.len())
+                .filter_map(|i| index.id(i))
+                .map(hex::encode),
+        );
+    }
+    Ok(ids)
+}
+
+fn pack_path(object_dir: &Path, name: &str, extension: &str) -> PathBuf {
+    object_dir
+        .join("pack")
+        .join(format!("pack-{}.{}", name, extension))
+}
+
+fn loose_path(object_dir: &Path, id: &str) -> PathBuf {
+    object_dir.join(&id[..2]).join(&id[2..])
+}
+
+// the path a repository is registered under: its work tree, or a bare one's directory
+fn registered_path(repository: &Repository) -> String {
+    let path = repository.work_tree().unwrap_or(repository.common_dir());
+    fs::canonicalize(path)
+        .unwrap_or_else(|_| path.to_path_buf())
+        .to_string_lossy()
+        .into_owned()
+}
+
+// adds the repository to the global maintenance.repo list the schedule goes through, turning
+// off automatic maintenance in it and choosing the incremental strategy unless it has one
+pub fn register(repository: &Repository) -> Result<()> {
+    let global = config::global_file().ok_or(Error::NoHome)?;
+    let path = registered_path(repository);
+    if !Config::global()
+        .get_all("maintenance.repo")
+        .contains(&path.as_str())
+    {
+        config::add_value(&global, "maintenance.repo", &path)?;
+    }
+    let local = repository.common_dir().join("config");
+    config::set_value(&local, "maintenance.auto", "false")?;
+    if Config::load(repository.common_dir())
+        .get("maintenance.strategy")
+        .is_none()
+    {
+        config::set_value(&local, "maintenance.strategy", "incremental")?;
+    }
+    Ok(())
+}
+
+pub fn unregister(repository: &Repository) -> Result<()> {
+    let global = config::global_file().ok_or(Error::NoHome)?;
+    let path = registered_path(repository);
+    match config::remove_values(&global, "maintenance.repo", |value| {
+        value == Some(path.as_str())
+    })? {
+        0 => Err(Error::NotRegistered(path)),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheduler {
+    SystemdTimer,
+    Crontab,
+}
+
+impl Scheduler {
+    // in the order "auto" prefers them
+    pub const ALL: [Scheduler; 2] = [Scheduler::SystemdTimer, Scheduler::Crontab];
+
+    pub fn from_name(name: &str) -> Option<Scheduler> {
+        match name {
+            "systemd-timer" => Some(Scheduler::SystemdTimer),
+            "crontab" => Some(Scheduler::Crontab),
+            _ => None,
+        }
+    }
+
+    pub fn is_available(self) -> bool {
+        let mut command = match self {
+            Scheduler::SystemdTimer => {
+                let mut command = Command::new("systemctl");
+                command.args(["--user", "list-timers"]);
+                command
+            }
+            Scheduler::Crontab => {
+                let mut command = Command::new("crontab");
+                command.arg("-l");
+                command
+            }
+        };
+        let status = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        match self {
+            // crontab -l fails when the user has no crontab yet
+            Scheduler::Crontab => status.is_ok(),
+            Scheduler::SystemdTimer => status.is_ok_and(|status| status.success()),
+        }
+    }
+
+    // installs the schedule that runs maintenance hourly, daily and weekly in every registered
+    // repository, or with `enable` false removes it
+    pub fn update(self, enable: bool) -> Result<()> {
+        let program = env::current_exe()?;
+        match self {
+            Scheduler::Crontab => update_crontab(&program, enable),
+            Scheduler::SystemdTimer => update_systemd_timers(&program, enable),
+        }
+    }
+}
+
+fn scheduled_command(schedule: Schedule) -> String {
+    format!(
+        "for-each-repo --config=maintenance.repo maintenance run --schedule={}",
+        schedule.name()
+    )
+}
+
+// the schedule is a block of the user's crontab, which the rest of it is kept around
+fn update_crontab(program: &Path, enable: bool) -> Result<()> {
+    let listed = Command::new("crontab")
+        .arg("-l")
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|_| Error::NoScheduler("crontab -l".to_string(), "cron".to_string()))?;
+    let current = match listed.status.success() {
+        true => String::from_utf8_lossy(&listed.stdout).into_owned(),
+        false => String::new(),
+    };
+
+    let mut crontab = String::new();
+    let mut in_schedule = false;
+    for line in current.lines() {
+        match line {
+            CRON_BEGIN => in_schedule = true,
+            CRON_END => in_schedule = false,
+            line if !in_schedule => {
+                crontab.push_str(line);
+                crontab.push('\n');
+            }
+            _ => {}
+        }
+    }
+    if enable {
+        crontab.push_str(CRON_BEGIN);
+        crontab.push_str(concat!(
+            "\n# The following schedule was created by Git\n",
+            "# Any edits made in this region might be\n",
+            "# replaced in the future by a Git command.\n\n"
+        ));
+        for schedule in Schedule::ALL {
+            crontab.push_str(&format!(
+                "{} \"{}\" {}\n",
+                schedule.cron_times(),
+                program.display(),
+                scheduled_command(schedule)
+            ));
+        }
+        crontab.push('\n');
+        crontab.push_str(CRON_END);
+        crontab.push('\n');
+    }
+
+    // crontab takes the new table from a file
+    let file = env::temp_dir().join(format!(".git_cron_edit_tmp{}", std::process::id()));
+    fs::write(&file, crontab)?;
+    let status = Command::new("crontab").arg(&file).status();
+    let _ = fs::remove_file(&file);
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        _ => Err(Error::CommandFailed("crontab".to_string())),
+    }
+}
+
+// a timer per schedule in the user's systemd units, each starting an instance of one service
+// with the schedule's name
+fn update_systemd_timers(program: &Path, enable: bool) -> Result<()> {
+    let units = paths::config_home()
+        .ok_or(Error::NoHome)?
+        .join("systemd")
+        .join("user");
+    let systemctl = |action: &str, schedule: Schedule| {
+        Command::new("systemctl")
+            .args(["--user", action, "--now"])
+            .arg(format!("git-maintenance@{}.timer", schedule.name()))
+            .stdout(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    };
+
+    if !enable {
+        for schedule in Schedule::ALL {
+            systemctl("disable", schedule);
+            let _ =
+                fs::remove_file(units.join(format!("git-maintenance@{}.timer", schedule.name())));
+        }
+        let _ = fs::remove_file(units.join("git-maintenance@.service"));
+        return Ok(());
+    }
+
+    fs::create_dir_all(&units)?;
+    fs::write(
+        units.join("git-maintenance@.service"),
+        format!(
+            concat!(
+                "[Unit]\n",
+                "Description=Optimize Git repositories data\n\n",
+                "[Service]\n",
+                "Type=oneshot\n",
+                "ExecStart=\"{}\" for-each-repo --config=maintenance.repo maintenance run ",
+                "--schedule=%i\n",
+                "LockPersonality=yes\n",
+                "MemoryDenyWriteExecute=yes\n",
+                "NoNewPrivileges=yes\n",
+                "RestrictAddressFamilies=AF_UNIX AF_INET AF_INET6\n",
+                "RestrictNamespaces=yes\n",
+                "RestrictRealtime=yes\n",
+                "RestrictSUIDSGID=yes\n",
+                "SystemCallArchitectures=native\n",
+                "SystemCallFilter=@system-service\n"
+            ),
+            program.display()
+        ),
+    )?;
+    for schedule in Schedule::ALL {
+        fs::write(
+            units.join(format!("git-maintenance@{}.timer", schedule.name())),
+            format!(
+                concat!(
+                    "[Unit]\n",
+                    "Description=Optimize Git repositories data\n\n",
+                    "[Timer]\n",
+                    "OnCalendar={}\n",
+                    "Persistent=true\n\n",
+                    "[Install]\n",
+                    "WantedBy=timers.target\n"
+                ),
+                schedule.systemd_calendar()
+            ),
+        )?;
+        if !systemctl("enable", schedule) {
+            return Err(Error::CommandFailed("systemctl".to_string()));
+        }
+    }
+    Ok(())
+}
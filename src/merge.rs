@@ -0,0 +1,320 @@
+use crate::{
+    diff, error::Result, index::IndexEntry, objects::GitObjectType, repository::Repository,
+};
+
+const MARKER_SIZE: usize = 7;
+
+// the result of merging two trees: the merged tree, which has conflict markers in files both
+// sides changed in different ways, and the stages of every conflicted path
+#[derive(Debug, Default)]
+pub struct TreeMerge {
+    pub tree: String,
+    pub conflicts: Vec<IndexEntry>,
+    pub messages: Vec<String>,
+}
+
+// the side -X ours and -X theirs settle conflicting changes for, instead of leaving markers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Favor {
+    Ours,
+    Theirs,
+}
+
+impl TreeMerge {
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+
+    fn add_conflict(
+        &mut self,
+        base: &Option<IndexEntry>,
+        ours: &Option<IndexEntry>,
+        theirs: &Option<IndexEntry>,
+    ) {
+        for (stage, entry) in [(1, base), (2, ours), (3, theirs)] {
+            if let Some(entry) = entry {
+                self.conflicts.push(IndexEntry {
+                    stage,
+                    ..IndexEntry::new(&entry.path, entry.mode, &entry.id)
+                });
+            }
+        }
+    }
+}
+
+// a three-way merge of `ours` and `theirs` from `base`, file by file, that only writes objects;
+// `labels` name ours and theirs in conflict markers and messages
+pub fn merge_trees(
+    repository: &Repository,
+    base: &str,
+    ours: &str,
+    theirs: &str,
+    labels: (&str, &str),
+    favor: Option<Favor>,
+) -> Result<TreeMerge> {
+    let base = repository.tree_files(base, "")?;
+    let ours = repository.tree_files(ours, "")?;
+    let theirs = repository.tree_files(theirs, "")?;
+    let mut paths: Vec<&str> = [&base, &ours, &theirs]
+        .iter()
+        .flat_map(|files| files.iter().map(|file| file.path.as_str()))
+        .collect();
+    paths.sort_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
+    paths.dedup();
+
+    let mut merge = TreeMerge::default();
+    let mut files = vec![];
+    for path in paths {
+        let find = |files: &[IndexEntry]| files.iter().find(|file| file.path == path).cloned();
+        let (base, ours, theirs) = (find(&base), find(&ours), find(&theirs));
+        let same = |a: &Option<IndexEntry>, b: &Option<IndexEntry>| match (a, b) {
+            (Some(a), Some(b)) => a.same_content(b),
+            (None, None) => true,
+            _ => false,
+        };
+
+        let merged = if same(&ours, &theirs) || same(&base, &theirs) {
+            ours.clone()
+        } else if same(&base, &ours) {
+            theirs.clone()
+        } else {
+            match (&ours, &theirs) {
+                (Some(ours_file), Some(theirs_file))
+                    if is_file(ours_file.mode) && is_file(theirs_file.mode) =>
+                {
+                    merge.messages.push(format!("Auto-merging {}", path));
+                    let (file, clean) = merge_files(
+                        repository,
+                        base.as_ref(),
+                        ours_file,
+                        theirs_file,
+                        labels,
+                        favor,
+                    )?;
+                    if !clean {
+                        let kind = if base.is_some() { "content" } else { "add/add" };
+                        merge
+                            .messages
+                            .push(format!("CONFLICT ({}): Merge conflict in {}", kind, path));
+                        merge.add_conflict(&base, &ours, &theirs);
+                    }
+                    Some(file)
+                }
+                (Some(_), None) | (None, Some(_)) => {
+                    // whichever side still has the file wins, but it is still a conflict
+                    let (deleted_in, modified_in) = match ours {
+                        Some(_) => (labels.1, labels.0),
+                        None => (labels.0, labels.1),
+                    };
+                    merge.messages.push(format!(
+                        "CONFLICT (modify/delete): {} deleted in {} and modified in {}.  \
+                         Version {} of {} left in tree.",
+                        path, deleted_in, modified_in, modified_in, path
+                    ));
+                    merge.add_conflict(&base, &ours, &theirs);
+                    ours.clone().or_else(|| theirs.clone())
+                }
+                _ if favor == Some(Favor::Ours) => ours.clone(),
+                _ if favor == Some(Favor::Theirs) => theirs.clone(),
+                _ => {
+                    // symlinks and submodules can't be merged line by line
+                    merge
+                        .messages
+                        .push(format!("CONFLICT (content): Merge conflict in {}", path));
+                    merge.add_conflict(&base, &ours, &theirs);
+                    ours.clone()
+                }
+            }
+        };
+        files.extend(merged);
+    }
+
+    merge.tree = repository.write_tree_entries(&files)?;
+    Ok(merge)
+}
+
+// the tree of `theirs` lined up with `ours` for -X subtree=<prefix>: put at `prefix` in
+// place of what ours has there when ours has the directory, or else taken out of it when
+// theirs has it, and otherwise as it is. When both have it, git scores the two ways of
+// lining them up; here theirs always goes under the prefix
+pub fn shift_tree(
+    repository: &Repository,
+    ours: &str,
+    theirs: &str,
+    prefix: &str,
+) -> Result<String> {
+    let prefix = format!("{}/", prefix.trim_end_matches('/'));
+    let has_prefix = |files: &[IndexEntry]| files.iter().any(|file| file.path.starts_with(&prefix));
+    let ours = repository.tree_files(ours, "")?;
+    if has_prefix(&ours) {
+        let mut files: Vec<IndexEntry> = ours
+            .into_iter()
+            .filter(|file| !file.path.starts_with(&prefix))
+            .collect();
+        files.extend(repository.tree_files(theirs, &prefix)?);
+        files.sort_by(|a, b| a.path.as_bytes().cmp(b.path.as_bytes()));
+        return repository.write_tree_entries(&files);
+    }
+    let theirs_files = repository.tree_files(theirs, "")?;
+    if has_prefix(&theirs_files) {
+        let files: Vec<IndexEntry> = theirs_files
+            .iter()
+            .filter_map(|file| {
+                let path = file.path.strip_prefix(&prefix)?;
+                Some(IndexEntry::new(path, file.mode, &file.id))
+            })
+            .collect();
+        return repository.write_tree_entries(&files);
+    }
+    Ok(repository.peel(theirs, GitObjectType::Tree)?.id)
+}
+
+fn is_file(mode: u32) -> bool {
+    mode & 0o170000 == 0o100000
+}
+
+// merges the content of two versions of a file, writing the result, and says whether it
+// merged cleanly; the executable bit is taken from the side that changed it
+fn merge_files(
+    repository: &Repository,
+    base: Option<&IndexEntry>,
+    ours: &IndexEntry,
+    theirs: &IndexEntry,
+    labels: (&str, &str),
+    favor: Option<Favor>,
+) -> Result<(IndexEntry, bool)> {
+    let content =
+        |entry: &IndexEntry| -> Result<Vec<u8>> { Ok(repository.find_object(&entry.id)?.data) };
+    let base_content = match base {
+        Some(base) if is_file(base.mode) => content(base)?,
+        _ => vec![],
+    };
+    let (merged, mut clean) = merge_content(
+        &base_content,
+        &content(ours)?,
+        &content(theirs)?,
+        labels,
+        favor,
+    );
+
+    let mode = match base {
+        _ if ours.mode == theirs.mode => ours.mode,
+        Some(base) if base.mode == ours.mode => theirs.mode,
+        Some(base) if base.mode == theirs.mode => ours.mode,
+        _ => {
+            clean = false;
+            ours.mode
+        }
+    };
+    let id = repository.write_object(GitObjectType::Blob, &merged)?;
+
+    Ok((IndexEntry::new(&ours.path, mode, &id), clean))
+}
+
+// a line by line three-way merge; where both sides changed the same lines differently, both
+// versions are kept between conflict markers, unless one side is favored
+pub fn merge_content(
+    base: &[u8],
+    ours: &[u8],
+    theirs: &[u8],
+    labels: (&str, &str),
+    favor: Option<Favor>,
+) -> (Vec<u8>, bool) {
+    let lines = |content: &[u8]| -> Vec<Vec<u8>> {
+        content
+            .split_inclusive(|b| *b == b'\n')
+            .map(|line| line.to_vec())
+            .collect()
+    };
+    let (base, ours, theirs) = (lines(base), lines(ours), lines(theirs));
+    // for every line of the base, the line each side kept it as
+    let kept = |other: &[Vec<u8>]| {
+        let mut kept = vec![None; base.len()];
+        for (i, j) in diff::matching_lines(&base, other) {
+            kept[i] = Some(j);
+        }
+        kept
+    };
+    let (in_ours, in_theirs) = (kept(&ours), kept(&theirs));
+
+    let mut merged = vec![];
+    let mut clean = true;
+    let (mut b, mut o, mut t) = (0, 0, 0);
+    while b < base.len() || o < ours.len() || t < theirs.len() {
+        // a line both sides kept where they are now is taken as it is
+        if b < base.len() && in_ours[b] == Some(o) && in_theirs[b] == Some(t) {
+            merged.extend_from_slice(&base[b]);
+            (b, o, t) = (b + 1, o + 1, t + 1);
+            continue;
+        }
+
+        // otherwise the lines up to the next one both kept changed on at least one side
+        let end = (b..base.len())
+            .find(|&i| in_ours[i].is_some() && in_theirs[i].is_some())
+            .unwrap_or(base.len());
+        let (ours_end, theirs_end) = match end < base.len() {
+            true => (
+                in_ours[end].unwrap_or(ours.len()),
+                in_theirs[end].unwrap_or(theirs.len()),
+            ),
+            false => (ours.len(), theirs.len()),
+        };
+        let (base_lines, ours_lines, theirs_lines) =
+            (&base[b..end], &ours[o..ours_end], &theirs[t..theirs_end]);
+        if ours_lines == base_lines || ours_lines == theirs_lines {
+            merged.extend(theirs_lines.concat());
+        } else if theirs_lines == base_lines || favor == Some(Favor::Ours) {
+            merged.extend(ours_lines.concat());
+        } else if favor == Some(Favor::Theirs) {
+            merged.extend(theirs_lines.concat());
+        } else {
+            clean = false;
+            write_conflict(&mut merged, ours_lines, theirs_lines, labels);
+        }
+        (b, o, t) = (end, ours_end, theirs_end);
+    }
+
+    (merged, clean)
+}
+
+// lines both sides have at the start and end of a conflict are left outside the markers
+fn write_conflict(
+    merged: &mut Vec<u8>,
+    ours: &[Vec<u8>],
+    theirs: &[Vec<u8>],
+    labels: (&str, &str),
+) {
+    let prefix = ours.iter().zip(theirs).take_while(|(a, b)| a == b).count();
+    let (ours, theirs, common) = (&ours[prefix..], &theirs[prefix..], &ours[..prefix]);
+    let suffix = ours
+        .iter()
+        .rev()
+        .zip(theirs.iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (ours, theirs, trailing) = (
+        &ours[..ours.len() - suffix],
+        &theirs[..theirs.len() - suffix],
+        &ours[ours.len() - suffix..],
+    );
+
+    merged.extend(common.concat());
+    let marker = |merged: &mut Vec<u8>, c: char, label: &str| {
+        // the side before the marker may not have ended its last line
+        if merged.last().is_some_and(|b| *b != b'\n') {
+            merged.push(b'\n');
+        }
+        merged.extend(c.to_string().repeat(MARKER_SIZE).as_bytes());
+        if !label.is_empty() {
+            merged.push(b' ');
+            merged.extend(label.as_bytes());
+        }
+        merged.push(b'\n');
+    };
+    marker(merged, '<', labels.0);
+    merged.extend(ours.concat());
+    marker(merged, '=', "");
+    merged.extend(theirs.concat());
+    marker(merged, '>', labels.1);
+    merged.extend(trailing.concat());
+}
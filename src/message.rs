@@ -0,0 +1,75 @@
+use crate::config::Config;
+
+const DEFAULT_COMMENT_CHAR: char = '#';
+const CUT_LINE: &str = "------------------------ >8 ------------------------\n";
+
+// what starts a comment line in a message being edited: core.commentChar, or "#"
+pub fn comment_char(config: &Config) -> char {
+    let mut chars = config.get("core.commentChar").unwrap_or_default().chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => c,
+        _ => DEFAULT_COMMENT_CHAR,
+    }
+}
+
+// a message cleaned up as git does it: without the whitespace lines end with, without blank
+// lines at the start and end, with the blank lines between paragraphs down to one, and with
+// every line ending in a newline. With a `comment` character, the lines starting with it go too
+pub fn stripspace(text: &str, comment: Option<char>) -> String {
+    let mut message = String::new();
+    let mut blank = false;
+    for line in text.split_inclusive('\n') {
+        if comment.is_some_and(|comment| line.starts_with(comment)) {
+            continue;
+        }
+        let line = line.trim_end_matches(is_space);
+        if line.is_empty() {
+            blank = true;
+            continue;
+        }
+        if blank && !message.is_empty() {
+            message.push('\n');
+        }
+        blank = false;
+        message.push_str(line);
+        message.push('\n');
+    }
+    message
+}
+
+// `text` as comment lines, with a space after the comment character unless the line is empty or
+// starts with a tab
+pub fn comment_lines(text: &str, comment: char) -> String {
+    text.split_inclusive('\n')
+        .map(|line| line.strip_suffix('\n').unwrap_or(line))
+        .map(|line| match line.is_empty() || line.starts_with('\t') {
+            true => format!("{}{}\n", comment, line),
+            false => format!("{} {}\n", comment, line),
+        })
+        .collect()
+}
+
+// whitespace as git has it, which isn't a vertical tab or a form feed
+pub fn is_space(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n' | '\r')
+}
+
+// the scissors line and what it says about the lines below it
+pub fn cut_lines(comment: char) -> String {
+    format!("{} {}", comment, CUT_LINE)
+        + &comment_lines(
+            "Do not modify or remove the line above.\nEverything below it will be ignored.",
+            comment,
+        )
+}
+
+// `text` down to the scissors line, if it has one
+pub fn cut(text: &str, comment: char) -> &str {
+    let line = format!("{} {}", comment, CUT_LINE);
+    match text.starts_with(&line) {
+        true => "",
+        false => text
+            .find(&format!("\n{}", line))
+            .map_or(text, |start| &text[..start + 1]),
+    }
+}
@@ -0,0 +1,278 @@
+use std::{
+    cmp::Ordering,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use sha1::Digest;
+
+use crate::{
+    error::{Error, Result},
+    pack::PackIndex,
+};
+
+const SIGNATURE: &[u8] = b"MIDX";
+const VERSION: u8 = 1;
+const SHA1_VERSION: u8 = 1;
+const HEADER_SIZE: usize = 12;
+const CHUNK_ENTRY_SIZE: usize = 12;
+const FANOUT_SIZE: usize = 256 * 4;
+
+const PACK_NAMES: &[u8; 4] = b"PNAM";
+const OID_FANOUT: &[u8; 4] = b"OIDF";
+const OID_LOOKUP: &[u8; 4] = b"OIDL";
+const OBJECT_OFFSETS: &[u8; 4] = b"OOFF";
+const LARGE_OFFSETS: &[u8; 4] = b"LOFF";
+
+// offsets with the high bit set index into the large offsets chunk instead
+const LARGE_OFFSET: u32 = 0x8000_0000;
+
+// objects/pack/multi-pack-index: the objects of many packs in one sorted list, each with the
+// pack it is in and its offset there
+pub struct MultiPackIndex {
+    data: Vec<u8>,
+    pack_names: Vec<String>,
+    count: usize,
+    fanout: usize,
+    ids: usize,
+    offsets: usize,
+    large_offsets: Option<usize>,
+}
+
+impl MultiPackIndex {
+    pub fn open(path: &Path) -> Result<MultiPackIndex> {
+        let data = fs::read(path)?;
+        let invalid = |what: &str| Error::InvalidPack(format!("{} in {}", what, path.display()));
+        if data.len() < HEADER_SIZE + 20 || !data.starts_with(SIGNATURE) {
+            return Err(invalid("no multi-pack-index signature"));
+        }
+        if data[4] != VERSION {
+            return Err(invalid(&format!("unsupported version {}", data[4])));
+        }
+        if data[5] != SHA1_VERSION {
+            return Err(invalid(&format!("unsupported hash version {}", data[5])));
+        }
+        if data[7] != 0 {
+            return Err(invalid("unsupported base multi-pack-indexes"));
+        }
+        let chunk_count = data[6] as usize;
+        let pack_count = be_u32(&data, 8).ok_or_else(|| invalid("truncated header"))? as usize;
+
+        // the table ends with an entry marking where the last chunk stops
+        let mut chunks = vec![];
+        for i in 0..chunk_count {
+            let entry = HEADER_SIZE + i * CHUNK_ENTRY_SIZE;
+            let id = data
+                .get(entry..entry + 4)
+                .ok_or_else(|| invalid("truncated chunk table"))?;
+            let start = be_u64(&data, entry + 4);
+            let end = be_u64(&data, entry + CHUNK_ENTRY_SIZE + 4);
+            let (Some(start), Some(end)) = (start, end) else {
+                return Err(invalid("truncated chunk table"));
+            };
+            if start > end || end > (data.len() - 20) as u64 {
+                return Err(invalid("chunk out of bounds"));
+            }
+            chunks.push((id.to_vec(), start as usize, end as usize));
+        }
+        let chunk = |id: &[u8; 4]| {
+            chunks
+                .iter()
+                .find(|(chunk_id, ..)| chunk_id == id)
+                .map(|&(_, start, end)| (start, end))
+        };
+        let missing =
+            |id: &[u8; 4]| invalid(&format!("missing {} chunk", String::from_utf8_lossy(id)));
+
+        let (start, end) = chunk(PACK_NAMES).ok_or_else(|| missing(PACK_NAMES))?;
+        let pack_names: Vec<String> = data[start..end]
+            .split(|b| *b == b'\0')
+            .filter(|name| !name.is_empty())
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .collect();
+        if pack_names.len() != pack_count {
+            return Err(invalid("wrong number of pack names"));
+        }
+
+        let (fanout, end) = chunk(OID_FANOUT).ok_or_else(|| missing(OID_FANOUT))?;
+        if end - fanout != FANOUT_SIZE {
+            return Err(invalid("wrong size of object id fan-out"));
+        }
+        let count = be_u32(&data, fanout + 255 * 4).unwrap_or(0) as usize;
+        let (ids, end) = chunk(OID_LOOKUP).ok_or_else(|| missing(OID_LOOKUP))?;
+        if end - ids != count * 20 {
+            return Err(invalid("wrong size of object id lookup"));
+        }
+        let (offsets, end) = chunk(OBJECT_OFFSETS).ok_or_else(|| missing(OBJECT_OFFSETS))?;
+        if end - offsets != count * 8 {
+            return Err(invalid("wrong size of object offsets"));
+        }
+
+        Ok(MultiPackIndex {
+            large_offsets: chunk(LARGE_OFFSETS).map(|(start, _)| start),
+            data,
+            pack_names,
+            count,
+            fanout,
+            ids,
+            offsets,
+        })
+    }
+
+    // the names of the indexes of the packs covered, "pack-<id>.idx"
+    pub fn pack_names(&self) -> &[String] {
+        &self.pack_names
+    }
+
+    // the pack `id` is in and its offset there
+    pub fn find(&self, id: &[u8]) -> Option<(&str, u64)> {
+        let first = *id.first()? as usize;
+        let mut low = match first {
+            0 => 0,
+            _ => self.fanout(first - 1)? as usize,
+        };
+        let mut high = (self.fanout(first)? as usize).min(self.count);
+        while low < high {
+            let middle = (low + high) / 2;
+            let start = self.ids + middle * 20;
+            match self.data.get(start..start + 20)?.cmp(id) {
+                Ordering::Less => low = middle + 1,
+                Ordering::Greater => high = middle,
+                Ordering::Equal => return self.location(middle),
+            }
+        }
+
+        None
+    }
+
+    fn location(&self, i: usize) -> Option<(&str, u64)> {
+        let pack = be_u32(&self.data, self.offsets + i * 8)? as usize;
+        let offset = be_u32(&self.data, self.offsets + i * 8 + 4)?;
+        let offset = if offset & LARGE_OFFSET == 0 {
+            offset.into()
+        } else {
+            let large = self.large_offsets? + (offset & !LARGE_OFFSET) as usize * 8;
+            be_u64(&self.data, large)?
+        };
+
+        Some((self.pack_names.get(pack)?, offset))
+    }
+
+    fn fanout(&self, i: usize) -> Option<u32> {
+        be_u32(&self.data, self.fanout + i * 4)
+    }
+}
+
+// writes a multi-pack-index covering every pack in `pack_dir`, returning how many objects it
+// lists; an object in several packs is taken from the most recently modified one
+pub fn write(pack_dir: &Path) -> Result<usize> {
+    let mut pack_names: Vec<String> = fs::read_dir(pack_dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "idx"))
+        .filter(|path| path.with_extension("pack").is_file())
+        .filter_map(|path| Some(path.file_name()?.to_str()?.to_string()))
+        .collect();
+    pack_names.sort();
+    if pack_names.is_empty() {
+        return Err(Error::NoPackFiles);
+    }
+
+    let mut objects = vec![];
+    for (pack, name) in pack_names.iter().enumerate() {
+        let path = pack_dir.join(name);
+        let index = PackIndex::open(&path)?;
+        let modified = fs::metadata(path.with_extension("pack"))?.modified()?;
+        for i in 0..index.len() {
+            let (Some(id), Some(offset)) = (index.id(i), index.offset(i)) else {
+                return Err(Error::InvalidPack(format!(
+                    "invalid index {}",
+                    path.display()
+                )));
+            };
+            objects.push((id.to_vec(), modified, pack as u32, offset));
+        }
+    }
+    objects.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)).then(a.2.cmp(&b.2)));
+    objects.dedup_by(|a, b| a.0 == b.0);
+
+    let mut names = vec![];
+    for name in &pack_names {
+        names.extend_from_slice(name.as_bytes());
+        names.push(b'\0');
+    }
+    names.resize(names.len().next_multiple_of(4), 0);
+
+    let mut fanout = vec![];
+    for first in 0..=255u8 {
+        let count = objects.partition_point(|(id, ..)| id[0] <= first);
+        fanout.extend_from_slice(&(count as u32).to_be_bytes());
+    }
+
+    let mut ids = vec![];
+    let mut offsets = vec![];
+    let mut large_offsets = vec![];
+    for (id, _, pack, offset) in &objects {
+        ids.extend_from_slice(id);
+        offsets.extend_from_slice(&pack.to_be_bytes());
+        let offset = match u32::try_from(*offset) {
+            Ok(offset) if offset & LARGE_OFFSET == 0 => offset,
+            _ => {
+                large_offsets.extend_from_slice(&offset.to_be_bytes());
+                LARGE_OFFSET | (large_offsets.len() / 8 - 1) as u32
+            }
+        };
+        offsets.extend_from_slice(&offset.to_be_bytes());
+    }
+
+    let mut chunks = vec![
+        (PACK_NAMES, names),
+        (OID_FANOUT, fanout),
+        (OID_LOOKUP, ids),
+        (OBJECT_OFFSETS, offsets),
+    ];
+    if !large_offsets.is_empty() {
+        chunks.push((LARGE_OFFSETS, large_offsets));
+    }
+
+    let mut data = SIGNATURE.to_vec();
+    data.extend_from_slice(&[VERSION, SHA1_VERSION, chunks.len() as u8, 0]);
+    data.extend_from_slice(&(pack_names.len() as u32).to_be_bytes());
+    let mut offset = (HEADER_SIZE + (chunks.len() + 1) * CHUNK_ENTRY_SIZE) as u64;
+    for (id, chunk) in &chunks {
+        data.extend_from_slice(*id);
+        data.extend_from_slice(&offset.to_be_bytes());
+        offset += chunk.len() as u64;
+    }
+    data.extend_from_slice(&[0; 4]);
+    data.extend_from_slice(&offset.to_be_bytes());
+    for (_, chunk) in &chunks {
+        data.extend_from_slice(chunk);
+    }
+    let checksum = sha1::Sha1::digest(&data);
+    data.extend_from_slice(&checksum);
+
+    // readers must never see a partly written index
+    let temp = pack_dir.join(format!("tmp_midx_{}", std::process::id()));
+    let mut file = fs::File::create(&temp)?;
+    file.write_all(&data)?;
+    drop(file);
+    fs::rename(&temp, path(pack_dir))?;
+
+    Ok(objects.len())
+}
+
+pub fn path(pack_dir: &Path) -> PathBuf {
+    pack_dir.join("multi-pack-index")
+}
+
+fn be_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn be_u64(data: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(at..at + 8)?.try_into().ok()?))
+}
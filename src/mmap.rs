@@ -0,0 +1,159 @@
+use std::{
+    fs,
+    io::{self, BufRead, Read, Seek, SeekFrom},
+    os::unix::io::AsRawFd,
+    path::Path,
+    ptr,
+    sync::{Arc, Mutex},
+};
+
+// windows are mapped on demand, so a large pack never has to be mapped whole; the size is a
+// multiple of every page size in use
+const WINDOW_SIZE: u64 = 64 << 20;
+const MAX_WINDOWS: usize = 16;
+
+struct Window {
+    offset: u64,
+    data: *const u8,
+    len: usize,
+}
+
+// the mapping is read only and unmapped only when the last reader lets go of it
+unsafe impl Send for Window {}
+unsafe impl Sync for Window {}
+
+impl Window {
+    fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.data, self.len) }
+    }
+}
+
+impl Drop for Window {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.data as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+// a file read through memory mapped windows; packs are never modified once written, which is
+// what makes mapping them safe
+pub struct MappedFile {
+    file: fs::File,
+    len: u64,
+    // most recently used last
+    windows: Mutex<Vec<Arc<Window>>>,
+}
+
+impl MappedFile {
+    pub fn open(path: &Path) -> io::Result<MappedFile> {
+        let file = fs::File::open(path)?;
+        let len = file.metadata()?.len();
+        Ok(MappedFile {
+            file,
+            len,
+            windows: Mutex::new(vec![]),
+        })
+    }
+
+    pub fn reader(&self) -> MappedReader<'_> {
+        MappedReader {
+            file: self,
+            window: None,
+            position: 0,
+        }
+    }
+
+    fn window(&self, position: u64) -> io::Result<Arc<Window>> {
+        let offset = position - position % WINDOW_SIZE;
+        let mut windows = self
+            .windows
+            .lock()
+            .unwrap_or_else(|error| error.into_inner());
+        if let Some(i) = windows.iter().position(|window| window.offset == offset) {
+            let window = windows.remove(i);
+            windows.push(window.clone());
+            return Ok(window);
+        }
+
+        let len = (self.len - offset).min(WINDOW_SIZE) as usize;
+        let data = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                self.file.as_raw_fd(),
+                offset as libc::off_t,
+            )
+        };
+        if data == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let window = Arc::new(Window {
+            offset,
+            data: data as *const u8,
+            len,
+        });
+        if windows.len() >= MAX_WINDOWS {
+            windows.remove(0);
+        }
+        windows.push(window.clone());
+
+        Ok(window)
+    }
+}
+
+// reads straight out of the mapped windows, moving to the next one at a window boundary
+pub struct MappedReader<'a> {
+    file: &'a MappedFile,
+    window: Option<Arc<Window>>,
+    position: u64,
+}
+
+impl BufRead for MappedReader<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.position >= self.file.len {
+            return Ok(&[]);
+        }
+        let current = self.window.as_ref().is_some_and(|window| {
+            (window.offset..window.offset + window.len as u64).contains(&self.position)
+        });
+        if !current {
+            self.window = Some(self.file.window(self.position)?);
+        }
+        let window = self.window.as_ref().expect("window was just mapped");
+
+        Ok(&window.as_slice()[(self.position - window.offset) as usize..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.position += amount as u64;
+    }
+}
+
+impl Read for MappedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let amount = available.len().min(buf.len());
+        buf[..amount].copy_from_slice(&available[..amount]);
+        self.consume(amount);
+
+        Ok(amount)
+    }
+}
+
+impl Seek for MappedReader<'_> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let position = match position {
+            SeekFrom::Start(position) => Some(position),
+            SeekFrom::End(delta) => self.file.len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative position")
+        })?;
+
+        Ok(self.position)
+    }
+}
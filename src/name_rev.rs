@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+
+use crate::{
+    error::Result,
+    objects::GitObjectType,
+    repository::{Repository, Signature},
+    wildmatch::wildmatch,
+};
+
+// name-rev names commits after the refs they can be reached from: every ref names the commit
+// it points at, and the names spread to the parents, "~<n>" for n first parents down and
+// "^<m>" for the m-th parent of a merge. Of the names a commit can get, one from a tag beats
+// one from a branch, the older tag the better, and then the fewest steps
+
+// how much further than its first parent a commit's other parents are, so that a name that
+// goes through one is kept only when there is no other
+const MERGE_TRAVERSAL_WEIGHT: usize = 65535;
+
+// how much older than the oldest commit to name a commit can be and still be walked through,
+// for commits that were made on clocks set wrong
+const CUTOFF_DATE_SLOP: i64 = 24 * 60 * 60;
+
+// a name of a commit: the ref it starts from, "^0" after it when the ref is a tag of the
+// commit, and `generation` first parents down from where that name was given
+#[derive(Clone, Debug)]
+struct Name {
+    tip: String,
+    tagger_date: i64,
+    generation: usize,
+    distance: usize,
+    from_tag: bool,
+}
+
+impl Name {
+    // how far the name goes, with following first parents counting as much as going through a
+    // merge, so that "a^2" beats "b~1"
+    fn effective_distance(distance: usize, generation: usize) -> usize {
+        match generation {
+            0 => distance,
+            _ => distance + MERGE_TRAVERSAL_WEIGHT,
+        }
+    }
+
+    fn is_worse_than(&self, other: &Name) -> bool {
+        let distance = Name::effective_distance(self.distance, self.generation);
+        let other_distance = Name::effective_distance(other.distance, other.generation);
+        // between tags, the older tag wins even when it is farther away
+        if self.from_tag && other.from_tag {
+            return self.tagger_date > other.tagger_date
+                || (self.tagger_date == other.tagger_date && distance > other_distance);
+        }
+        if self.from_tag != other.from_tag {
+            return other.from_tag;
+        }
+        if distance != other_distance {
+            return distance > other_distance;
+        }
+        self.tagger_date > other.tagger_date
+    }
+}
+
+// a ref names are spread from, with the commit it points at when it does, through tags or not
+struct Tip {
+    name: String,
+    id: String,
+    commit: Option<String>,
+    tagger_date: i64,
+    from_tag: bool,
+    peeled: bool,
+}
+
+// which refs give names
+pub struct RefFilter {
+    pub tags_only: bool,
+    // the names of the tags without "tags/" when the tags alone give names
+    pub name_only: bool,
+    pub patterns: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+pub struct NameRev<'a> {
+    repository: &'a Repository,
+    names: HashMap<String, Name>,
+    // the refs by the object they point at, which name that object when it isn't a commit
+    tips: HashMap<String, String>,
+    // the parents and commit date of the commits walked
+    commits: HashMap<String, (Vec<String>, i64)>,
+    // commits older than this aren't named, nor are their ancestors
+    cutoff: i64,
+}
+
+impl<'a> NameRev<'a> {
+    // the names of the commits, with the refs the filter keeps; when the `oldest` commit to
+    // name is known, the history beyond it is left alone
+    pub fn new(
+        repository: &'a Repository,
+        filter: &RefFilter,
+        oldest: Option<i64>,
+    ) -> Result<NameRev<'a>> {
+        let mut name_rev = NameRev {
+            repository,
+            names: HashMap::new(),
+            tips: HashMap::new(),
+            commits: HashMap::new(),
+            cutoff: oldest.map_or(i64::MIN, |oldest| oldest.saturating_sub(CUTOFF_DATE_SLOP)),
+        };
+        let mut tips = vec![];
+        for (name, id) in repository.references() {
+            if let Some(tip) = name_rev.tip(filter, name, id)? {
+                name_rev
+                    .tips
+                    .entry(tip.id.clone())
+                    .or_insert(tip.name.clone());
+                tips.push(tip);
+            }
+        }
+
+        // the better names go first, so that the worse ones spread less
+        tips.sort_by_key(|tip| (!tip.from_tag, tip.tagger_date));
+        for tip in tips {
+            if let Some(commit) = &tip.commit {
+                name_rev.name_from(commit, &tip)?;
+            }
+        }
+        Ok(name_rev)
+    }
+
+    // the ref `name` as a tip, unless the filter leaves it out
+    fn tip(&self, filter: &RefFilter, name: String, id: String) -> Result<Option<Tip>> {
+        if filter.tags_only && !name.starts_with("refs/tags/") {
+            return Ok(None);
+        }
+        if filter
+            .exclude
+            .iter()
+            .any(|pattern| subpath_matches(&name, pattern).is_some())
+        {
+            return Ok(None);
+        }
+        let mut abbreviate = filter.tags_only && filter.name_only;
+        if !filter.patterns.is_empty() {
+            let matches: Vec<usize> = filter
+                .patterns
+                .iter()
+                .filter_map(|pattern| subpath_matches(&name, pattern))
+                .collect();
+            if matches.is_empty() {
+                return Ok(None);
+            }
+            // a pattern for the end of the name is for the name shortened
+            abbreviate |= matches.iter().any(|&start| start > 0);
+        }
+
+        let mut object = self.repository.find_object(&id)?;
+        let mut tagger_date = None;
+        let mut peeled = false;
+        while object.object_type == GitObjectType::Tag {
+            let data = String::from_utf8_lossy(&object.data);
+            let header = |field: &str| {
+                data.lines()
+                    .take_while(|line| !line.is_empty())
+                    .find_map(|line| line.strip_prefix(field)?.strip_prefix(' '))
+                    .map(str::to_string)
+            };
+            let Some(target) = header("object") else {
+                break;
+            };
+            tagger_date = Some(
+                header("tagger")
+                    .and_then(|tagger| Signature::parse(&tagger))
+                    .map_or(0, |tagger| tagger.time),
+            );
+            object = self.repository.find_object(&target)?;
+            peeled = true;
+        }
+        let commit = match object.object_type {
+            GitObjectType::Commit => Some(self.repository.find_commit(&object.id)?),
+            _ => None,
+        };
+
+        let from_tag = commit.is_some() && name.starts_with("refs/tags/");
+        let name = match abbreviate {
+            true => self.repository.shorten_reference_name(&name),
+            false => name
+                .strip_prefix("refs/heads/")
+                .or_else(|| name.strip_prefix("refs/"))
+                .unwrap_or(&name)
+                .to_string(),
+        };
+        Ok(Some(Tip {
+            from_tag,
+            tagger_date: tagger_date
+                .or(commit.as_ref().map(|commit| commit.committer.time))
+                .unwrap_or(i64::MAX),
+            commit: commit.map(|commit| commit.id),
+            name,
+            id,
+            peeled,
+        }))
+    }
+
+    // names the commit `tip` points at and spreads the name over its ancestors, as long as
+    // the names are better than the ones they have
+    fn name_from(&mut self, commit: &str, tip: &Tip) -> Result<()> {
+        if self.commit(commit)?.1 < self.cutoff {
+            return Ok(());
+        }
+        let name = Name {
+            tip: match tip.peeled {
+                true => format!("{}^0", tip.name),
+                false => tip.name.clone(),
+            },
+            tagger_date: tip.tagger_date,
+            generation: 0,
+            distance: 0,
+            from_tag: tip.from_tag,
+        };
+        if !self.rename(commit, name) {
+            return Ok(());
+        }
+
+        // the first parent is walked first
+        let mut stack = vec![commit.to_string()];
+        while let Some(commit) = stack.pop() {
+            let name = self.names[&commit].clone();
+            let parents = self.commit(&commit)?.0;
+            let mut named = vec![];
+            for (i, parent) in parents.into_iter().enumerate() {
+                if self.commit(&parent)?.1 < self.cutoff {
+                    continue;
+                }
+                let parent_name = match i {
+                    0 => Name {
+                        tip: name.tip.clone(),
+                        generation: name.generation + 1,
+                        distance: name.distance + 1,
+                        ..name
+                    },
+                    _ => Name {
+                        tip: parent_name(&name, i + 1),
+                        generation: 0,
+                        distance: name.distance + MERGE_TRAVERSAL_WEIGHT,
+                        ..name
+                    },
+                };
+                if self.rename(&parent, parent_name) {
+                    named.push(parent);
+                }
+            }
+            stack.extend(named.into_iter().rev());
+        }
+        Ok(())
+    }
+
+    // gives `commit` the name unless it has a better one
+    fn rename(&mut self, commit: &str, name: Name) -> bool {
+        if let Some(old) = self.names.get(commit) {
+            if !old.is_worse_than(&name) {
+                return false;
+            }
+        }
+        self.names.insert(commit.to_string(), name);
+        true
+    }
+
+    fn commit(&mut self, id: &str) -> Result<(Vec<String>, i64)> {
+        if let Some(commit) = self.commits.get(id) {
+            return Ok(commit.clone());
+        }
+        let commit = self.repository.find_commit(id)?;
+        let commit = (commit.parents, commit.committer.time);
+        self.commits.insert(id.to_string(), commit.clone());
+        Ok(commit)
+    }
+
+    // the name of the object `id`: a commit by the name it got, and anything else by the ref
+    // pointing at it
+    pub fn name(&self, id: &str) -> Option<String> {
+        match self.names.get(id) {
+            Some(name) if name.generation == 0 => Some(name.tip.clone()),
+            Some(name) => Some(format!(
+                "{}~{}",
+                name.tip.strip_suffix("^0").unwrap_or(&name.tip),
+                name.generation
+            )),
+            None => self.tips.get(id).cloned(),
+        }
+    }
+    // `line` with the objects it has the ids of named, after each id, or instead of it when
+    // `name_only`; an id is 40 hexadecimal digits, no more
+    pub fn annotate(&self, line: &str, name_only: bool) -> String {
+        let is_hex = |b: &u8| b.is_ascii_digit() || (b'a'..=b'f').contains(b);
+        let bytes = line.as_bytes();
+        let mut annotated = String::new();
+        // what is left to copy starts here
+        let mut start = 0;
+        let mut digits = 0;
+        for (i, b) in bytes.iter().enumerate() {
+            if !is_hex(b) {
+                digits = 0;
+                continue;
+            }
+            digits += 1;
+            if digits != 40 || bytes.get(i + 1).is_some_and(is_hex) {
+                continue;
+            }
+            digits = 0;
+            let id_start = i + 1 - 40;
+            if let Some(name) = self.name(&line[id_start..=i]) {
+                match name_only {
+                    true => annotated += &format!("{}{}", &line[start..id_start], name),
+                    false => annotated += &format!("{} ({})", &line[start..=i], name),
+                }
+                start = i + 1;
+            }
+        }
+        annotated + &line[start..]
+    }
+}
+
+// the name of the `number`-th parent of a commit named `name`
+fn parent_name(name: &Name, number: usize) -> String {
+    let tip = name.tip.strip_suffix("^0").unwrap_or(&name.tip);
+    match name.generation {
+        0 => format!("{}^{}", tip, number),
+        generation => format!("{}~{}^{}", tip, generation, number),
+    }
+}
+
+// where in the ref `name` the part `pattern` matches starts, which is after a "/" unless it's
+// the whole name
+fn subpath_matches(name: &str, pattern: &str) -> Option<usize> {
+    let mut start = 0;
+    loop {
+        if wildmatch(pattern.as_bytes(), &name.as_bytes()[start..], false) {
+            return Some(start);
+        }
+        start += name[start..].find('/')? + 1;
+    }
+}
@@ -0,0 +1,387 @@
+use std::{
+    env, fmt, fs,
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    process,
+    str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use sha1::Digest;
+
+use crate::error::{Error, Result};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GitObjectType {
+    Blob,
+    Commit,
+    Tag,
+    Tree,
+}
+
+impl From<u8> for GitObjectType {
+    fn from(obj_type: u8) -> Self {
+        match obj_type {
+            1 => GitObjectType::Commit,
+            2 => GitObjectType::Tree,
+            3 => GitObjectType::Blob,
+            4 => GitObjectType::Tag,
+            _ => panic!("unknown object type: {}", obj_type),
+        }
+    }
+}
+
+impl fmt::Display for GitObjectType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GitObjectType::Blob => write!(f, "blob"),
+            GitObjectType::Commit => write!(f, "commit"),
+            GitObjectType::Tag => write!(f, "tag"),
+            GitObjectType::Tree => write!(f, "tree"),
+        }
+    }
+}
+
+impl FromStr for GitObjectType {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "blob" => Ok(GitObjectType::Blob),
+            "commit" => Ok(GitObjectType::Commit),
+            "tag" => Ok(GitObjectType::Tag),
+            "tree" => Ok(GitObjectType::Tree),
+            _ => Err(Error::InvalidObjectType(name.to_string())),
+        }
+    }
+}
+
+const MAX_ALTERNATE_DEPTH: usize = 5;
+
+#[cfg(windows)]
+const PATH_LIST_SEPARATOR: char = ';';
+#[cfg(not(windows))]
+const PATH_LIST_SEPARATOR: char = ':';
+
+// the object directory itself followed by every alternate it borrows objects from
+pub fn object_directories(object_dir: &Path) -> Vec<PathBuf> {
+    let mut dirs = vec![object_dir.to_path_buf()];
+    if let Ok(env_alternates) = env::var("GIT_ALTERNATE_OBJECT_DIRECTORIES") {
+        env_alternates
+            .split(PATH_LIST_SEPARATOR)
+            .filter(|dir| !dir.is_empty())
+            .for_each(|dir| add_alternate(&mut dirs, PathBuf::from(dir), 0));
+    }
+    read_alternates_file(&mut dirs, object_dir, 0);
+
+    dirs
+}
+
+fn read_alternates_file(dirs: &mut Vec<PathBuf>, object_dir: &Path, depth: usize) {
+    if depth > MAX_ALTERNATE_DEPTH {
+        return;
+    }
+    let Ok(alternates) = fs::read_to_string(object_dir.join("info/alternates")) else {
+        return;
+    };
+
+    alternates
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .for_each(|line| add_alternate(dirs, object_dir.join(line), depth + 1));
+}
+
+fn add_alternate(dirs: &mut Vec<PathBuf>, dir: PathBuf, depth: usize) {
+    let dir = dir.canonicalize().unwrap_or(dir);
+    if !dir.is_dir() || dirs.contains(&dir) {
+        return;
+    }
+    dirs.push(dir.clone());
+    read_alternates_file(dirs, &dir, depth);
+}
+
+pub fn find_loose_object(object_dir: &Path, id: &str) -> Option<PathBuf> {
+    if id.len() != 40 || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    object_directories(object_dir)
+        .into_iter()
+        .map(|dir| dir.join(&id[0..2]).join(&id[2..]))
+        .find(|path| path.is_file())
+}
+
+// inflated object including its "<type> <size>\0" header
+pub fn read_loose_object(object_dir: &Path, id: &str) -> Option<Vec<u8>> {
+    let binary_content = fs::read(find_loose_object(object_dir, id)?).ok()?;
+    let mut content = Vec::new();
+    flate2::read::ZlibDecoder::new(&binary_content[..])
+        .read_to_end(&mut content)
+        .ok()?;
+
+    Some(content)
+}
+
+// the type and content of an object, whether it is stored loose or in a pack
+pub fn read_object(object_dir: &Path, id: &str) -> Option<(GitObjectType, Vec<u8>)> {
+    let Some(content) = read_loose_object(object_dir, id) else {
+        let object = crate::pack::read_packed_object(object_dir, id);
+        if let Some((object_type, data)) = &object {
+            tracing::trace!(target: "object", "read {} {} ({} bytes, packed)", object_type, id, data.len());
+        }
+        return object;
+    };
+    let header_end = content.iter().position(|b| *b == b'\0')?;
+    let header = std::str::from_utf8(&content[..header_end]).ok()?;
+    let (object_type, _) = header.split_once(' ')?;
+    let data = content[header_end + 1..].to_vec();
+    tracing::trace!(target: "object", "read {} {} ({} bytes, loose)", object_type, id, data.len());
+
+    Some((object_type.parse().ok()?, data))
+}
+
+pub fn has_object(object_dir: &Path, id: &str) -> bool {
+    find_loose_object(object_dir, id).is_some() || crate::pack::has_packed_object(object_dir, id)
+}
+
+// the ids of the objects stored loose in `object_dir` itself, not its alternates, sorted
+pub fn loose_objects(object_dir: &Path) -> Vec<String> {
+    let mut ids = vec![];
+    for entry in fs::read_dir(object_dir).into_iter().flatten().flatten() {
+        let prefix = entry.file_name().to_string_lossy().into_owned();
+        if prefix.len() != 2 || !prefix.bytes().all(|b| b.is_ascii_hexdigit()) {
+            continue;
+        }
+        for file in fs::read_dir(entry.path()).into_iter().flatten().flatten() {
+            let rest = file.file_name().to_string_lossy().into_owned();
+            if rest.len() == 38 && rest.bytes().all(|b| b.is_ascii_hexdigit()) {
+                ids.push(format!("{}{}", prefix, rest));
+            }
+        }
+    }
+    ids.sort();
+    ids
+}
+
+// the ids of the objects, loose or packed, in `object_dir` and its alternates that start with
+// the lowercase hex `prefix`, which is at least two digits long; sorted and without repeats
+pub fn objects_with_prefix(object_dir: &Path, prefix: &str) -> Vec<String> {
+    let (dir_name, rest) = prefix.split_at(2);
+    let mut ids = vec![];
+    for dir in object_directories(object_dir) {
+        for file in fs::read_dir(dir.join(dir_name))
+            .into_iter()
+            .flatten()
+            .flatten()
+        {
+            let name = file.file_name().to_string_lossy().into_owned();
+            if name.len() == 38 && name.starts_with(rest) {
+                ids.push(format!("{}{}", dir_name, name));
+            }
+        }
+        ids.extend(crate::pack::packed_objects_with_prefix(&dir, prefix));
+    }
+    ids.sort();
+    ids.dedup();
+    ids
+}
+
+// the object id and the "<type> <size>\0<content>" bytes it is the hash of
+pub fn encode_object(object_type: GitObjectType, content: &[u8]) -> (String, Vec<u8>) {
+    let header = format!("{} {}\0", object_type, content.len()).into_bytes();
+    let data = [&header[..], content].concat();
+    let mut hasher = sha1::Sha1::new();
+    hasher.update(&data);
+
+    (hex::encode(hasher.finalize()), data)
+}
+
+pub fn write_loose_object(
+    object_dir: &Path,
+    object_type: GitObjectType,
+    content: &[u8],
+) -> io::Result<String> {
+    let (id, data) = encode_object(object_type, content);
+    let path = object_dir.join(&id[0..2]).join(&id[2..]);
+    if path.is_file() {
+        return Ok(id);
+    }
+
+    let mut compressed = Vec::new();
+    let mut compressor =
+        flate2::write::ZlibEncoder::new(&mut compressed, flate2::Compression::fast());
+    compressor.write_all(&data)?;
+    compressor.finish()?;
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(&path, compressed)?;
+    tracing::trace!(target: "object", "wrote {} {} ({} bytes)", object_type, id, content.len());
+
+    Ok(id)
+}
+
+const STREAM_BUFFER_SIZE: usize = 64 << 10;
+
+// temporary object files need distinct names across threads as well as processes
+static TEMP_OBJECTS: AtomicUsize = AtomicUsize::new(0);
+
+// write_loose_object for content that is too large to hold in memory: `reader` is hashed and
+// compressed a chunk at a time into a temporary file, which is moved into place once the id is
+// known; it has to yield exactly `size` bytes since the header comes first
+pub fn write_loose_object_from(
+    object_dir: &Path,
+    object_type: GitObjectType,
+    size: u64,
+    reader: impl Read,
+) -> io::Result<String> {
+    fs::create_dir_all(object_dir)?;
+    let temp = object_dir.join(format!(
+        "tmp_obj_{}_{}",
+        process::id(),
+        TEMP_OBJECTS.fetch_add(1, Ordering::Relaxed)
+    ));
+    let write = || -> io::Result<String> {
+        let mut compressor = flate2::write::ZlibEncoder::new(
+            BufWriter::new(fs::File::create(&temp)?),
+            flate2::Compression::fast(),
+        );
+        let id = stream_object(object_type, size, reader, &mut compressor)?;
+        compressor.finish()?.flush()?;
+
+        Ok(id)
+    };
+
+    let id = match write() {
+        Ok(id) => id,
+        Err(error) => {
+            let _ = fs::remove_file(&temp);
+            return Err(error);
+        }
+    };
+    let path = object_dir.join(&id[0..2]).join(&id[2..]);
+    if path.is_file() {
+        fs::remove_file(&temp)?;
+    } else {
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::rename(&temp, &path)?;
+        tracing::trace!(target: "object", "wrote {} {} ({} bytes)", object_type, id, size);
+    }
+
+    Ok(id)
+}
+
+// the id of content too large to hold in memory, without storing it
+pub fn hash_object_from(
+    object_type: GitObjectType,
+    size: u64,
+    reader: impl Read,
+) -> io::Result<String> {
+    stream_object(object_type, size, reader, io::sink())
+}
+
+// hashes the header and `size` bytes of `reader` a chunk at a time, copying both to `out`
+fn stream_object(
+    object_type: GitObjectType,
+    size: u64,
+    mut reader: impl Read,
+    mut out: impl Write,
+) -> io::Result<String> {
+    let header = format!("{} {}\0", object_type, size).into_bytes();
+    let mut hasher = sha1::Sha1::new();
+    hasher.update(&header);
+    out.write_all(&header)?;
+
+    let mut buffer = vec![0; STREAM_BUFFER_SIZE];
+    let mut read = 0;
+    loop {
+        let n = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        };
+        hasher.update(&buffer[..n]);
+        out.write_all(&buffer[..n])?;
+        read += n as u64;
+    }
+    if read != size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("expected {} bytes of content but read {}", size, read),
+        ));
+    }
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+// the least a tree, commit or tag has to look like before hash-object will create one
+pub fn check_object(object_type: GitObjectType, content: &[u8]) -> Result<()> {
+    let valid = match object_type {
+        GitObjectType::Blob => true,
+        GitObjectType::Tree => parse_tree(content).is_ok(),
+        GitObjectType::Commit => content.starts_with(b"tree "),
+        GitObjectType::Tag => content.starts_with(b"object "),
+    };
+    if !valid {
+        return Err(Error::CorruptObject(format!("invalid {}", object_type)));
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TreeEntry {
+    pub mode: String,
+    pub name: String,
+    pub id: String,
+}
+
+impl TreeEntry {
+    // submodules are recorded as the commit they have checked out
+    pub fn object_type(&self) -> GitObjectType {
+        match self.mode.as_str() {
+            "40000" => GitObjectType::Tree,
+            "160000" => GitObjectType::Commit,
+            _ => GitObjectType::Blob,
+        }
+    }
+}
+
+// "<mode> <type> <id>\t<name>", as cat-file -p and ls-tree show entries
+impl fmt::Display for TreeEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:0>6} {} {}\t{}",
+            self.mode,
+            self.object_type(),
+            self.id,
+            self.name
+        )
+    }
+}
+
+// "<mode> <name>\0<20 byte id>" repeated
+pub fn parse_tree(content: &[u8]) -> Result<Vec<TreeEntry>> {
+    let malformed = || Error::CorruptObject("malformed tree".to_string());
+    let mut entries = vec![];
+    let mut rest = content;
+    while !rest.is_empty() {
+        let space = rest.iter().position(|b| *b == b' ').ok_or_else(malformed)?;
+        let nul = rest
+            .iter()
+            .position(|b| *b == b'\0')
+            .ok_or_else(malformed)?;
+        if nul < space || rest.len() < nul + 21 {
+            return Err(malformed());
+        }
+        entries.push(TreeEntry {
+            mode: String::from_utf8_lossy(&rest[..space]).to_string(),
+            name: String::from_utf8_lossy(&rest[space + 1..nul]).to_string(),
+            id: hex::encode(&rest[nul + 1..nul + 21]),
+        });
+        rest = &rest[nul + 21..];
+    }
+
+    Ok(entries)
+}
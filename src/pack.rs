@@ -0,0 +1,900 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, VecDeque},
+    fs,
+    io::{self, BufRead, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::SystemTime,
+};
+
+#[cfg(all(feature = "mmap", unix))]
+use crate::mmap::MappedFile;
+use sha1::Digest;
+
+use crate::{
+    delta,
+    error::{Error, Result},
+    midx::{self, MultiPackIndex},
+    objects::{self, GitObjectType},
+    progress::Progress,
+};
+
+const OBJ_OFS_DELTA: u8 = 6;
+const OBJ_REF_DELTA: u8 = 7;
+const MAX_DELTA_CHAIN: usize = 10_000;
+const DEFAULT_DELTA_BASE_CACHE_LIMIT: usize = 96 << 20;
+
+const PACK_SIGNATURE: &[u8] = b"PACK";
+const PACK_VERSION: u32 = 2;
+
+const IDX_V2_MAGIC: &[u8] = b"\xfftOc";
+const IDX_VERSION: u32 = 2;
+const FANOUT_SIZE: usize = 256 * 4;
+// offsets with the high bit set index into the table of 64-bit offsets instead
+const LARGE_OFFSET: u32 = 0x8000_0000;
+
+// a pack's .idx: a fan-out table of object counts by first byte, then the sorted object ids
+// and their offsets in the pack
+pub struct PackIndex {
+    data: Vec<u8>,
+    version: u32,
+    count: usize,
+}
+
+impl PackIndex {
+    pub fn open(path: &Path) -> Result<PackIndex> {
+        let data = fs::read(path)?;
+        let invalid = || Error::InvalidPack(format!("invalid index {}", path.display()));
+        let version = if data.starts_with(IDX_V2_MAGIC) {
+            let version = be_u32(&data, 4).ok_or_else(invalid)?;
+            if version != 2 {
+                return Err(Error::InvalidPack(format!(
+                    "unsupported index version {} in {}",
+                    version,
+                    path.display()
+                )));
+            }
+            version
+        } else {
+            1
+        };
+
+        let mut index = PackIndex {
+            data,
+            version,
+            count: 0,
+        };
+        let count = index.fanout(255).ok_or_else(invalid)? as usize;
+        // ids, crcs and offsets, then the two checksums
+        let size = match version {
+            1 => FANOUT_SIZE + count * 24 + 40,
+            _ => 8 + FANOUT_SIZE + count * 28 + 40,
+        };
+        if index.data.len() < size {
+            return Err(invalid());
+        }
+        index.count = count;
+
+        Ok(index)
+    }
+
+    // the offset of `id` in the pack, found by a binary search between the fan-out bounds
+    pub fn find(&self, id: &[u8]) -> Option<u64> {
+        let first = *id.first()? as usize;
+        let mut low = match first {
+            0 => 0,
+            _ => self.fanout(first - 1)? as usize,
+        };
+        let mut high = self.fanout(first)? as usize;
+        while low < high {
+            let middle = (low + high) / 2;
+            match self.id(middle)?.cmp(id) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => return self.offset(middle),
+            }
+        }
+
+        None
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    // the ids in the index starting with the hex `prefix`, which is at least two digits long,
+    // looked for between the fan-out bounds of its first byte
+    pub fn ids_with_prefix(&self, prefix: &str) -> Vec<String> {
+        let Ok(first) = u8::from_str_radix(&prefix[..2], 16) else {
+            return vec![];
+        };
+        let low = match first {
+            0 => Some(0),
+            _ => self.fanout(first as usize - 1),
+        };
+        let (Some(low), Some(high)) = (low, self.fanout(first as usize)) else {
+            return vec![];
+        };
+        (low as usize..high as usize)
+            .filter_map(|i| self.id(i))
+            .map(hex::encode)
+            .filter(|id| id.starts_with(prefix))
+            .collect()
+    }
+
+    pub fn id(&self, i: usize) -> Option<&[u8]> {
+        let start = match self.version {
+            1 => FANOUT_SIZE + i * 24 + 4,
+            _ => 8 + FANOUT_SIZE + i * 20,
+        };
+        self.data.get(start..start + 20)
+    }
+
+    pub fn offset(&self, i: usize) -> Option<u64> {
+        if self.version == 1 {
+            return be_u32(&self.data, FANOUT_SIZE + i * 24).map(u64::from);
+        }
+        let offsets = 8 + FANOUT_SIZE + self.count * 24;
+        let offset = be_u32(&self.data, offsets + i * 4)?;
+        // offsets past 2GiB are stored in a separate table of 64-bit values
+        if offset & LARGE_OFFSET == 0 {
+            return Some(offset.into());
+        }
+        let large = offsets + self.count * 4 + (offset & !LARGE_OFFSET) as usize * 8;
+        let bytes = self.data.get(large..large + 8)?;
+        Some(u64::from_be_bytes(bytes.try_into().ok()?))
+    }
+
+    fn fanout(&self, i: usize) -> Option<u32> {
+        match self.version {
+            1 => be_u32(&self.data, i * 4),
+            _ => be_u32(&self.data, 8 + i * 4),
+        }
+    }
+}
+
+pub struct Pack {
+    path: PathBuf,
+    // read on first use, since packs covered by a multi-pack-index may never need theirs
+    index: OnceLock<Option<PackIndex>>,
+    #[cfg(all(feature = "mmap", unix))]
+    data: MappedFile,
+}
+
+impl Pack {
+    fn open(index_path: &Path) -> Result<Pack> {
+        let path = index_path.with_extension("pack");
+        if !path.is_file() {
+            return Err(Error::InvalidPack(format!("missing {}", path.display())));
+        }
+        Ok(Pack {
+            index: OnceLock::new(),
+            #[cfg(all(feature = "mmap", unix))]
+            data: MappedFile::open(&path)?,
+            path,
+        })
+    }
+
+    fn index(&self) -> Option<&PackIndex> {
+        self.index
+            .get_or_init(|| PackIndex::open(&self.path.with_extension("idx")).ok())
+            .as_ref()
+    }
+
+    fn find(&self, id: &[u8]) -> Option<u64> {
+        self.index()?.find(id)
+    }
+
+    // follows the delta chain down to its base, then applies the deltas back up
+    fn read_at(&self, offset: u64, object_dir: &Path) -> Result<(GitObjectType, Vec<u8>)> {
+        #[cfg(all(feature = "mmap", unix))]
+        let mut file = self.data.reader();
+        #[cfg(not(all(feature = "mmap", unix)))]
+        let mut file = io::BufReader::new(fs::File::open(&self.path)?);
+        // each delta along with the offset of the object it produces
+        let mut deltas = vec![];
+        let mut offset = offset;
+        let (object_type, mut content) = loop {
+            if let Some(base) = cached_delta_base(&self.path, offset) {
+                break base;
+            }
+            if deltas.len() > MAX_DELTA_CHAIN {
+                return Err(Error::InvalidPack("delta chain too long".to_string()));
+            }
+            file.seek(SeekFrom::Start(offset))?;
+            let (object_type, size) = parse_object_header(&mut file)?;
+            match object_type {
+                OBJ_OFS_DELTA => {
+                    let distance = read_base_distance(&mut file)?;
+                    deltas.push((offset, inflate(&mut file, size)?));
+                    offset = offset.checked_sub(distance).ok_or_else(|| {
+                        Error::InvalidPack("delta base before the start of the pack".to_string())
+                    })?;
+                }
+                OBJ_REF_DELTA => {
+                    let mut base = [0; 20];
+                    file.read_exact(&mut base)
+                        .map_err(pack_read_error("delta base id"))?;
+                    deltas.push((offset, inflate(&mut file, size)?));
+                    match self.find(&base) {
+                        Some(base_offset) => offset = base_offset,
+                        // the base may be stored elsewhere
+                        None => {
+                            let base = hex::encode(base);
+                            let (object_type, content) = objects::read_object(object_dir, &base)
+                                .ok_or_else(|| Error::ObjectNotFound(base))?;
+                            break (object_type, Arc::new(content));
+                        }
+                    }
+                }
+                object_type => {
+                    let object_type = object_type_from_pack(object_type)?;
+                    let content = Arc::new(inflate(&mut file, size)?);
+                    if !deltas.is_empty() {
+                        cache_delta_base(&self.path, offset, object_type, &content);
+                    }
+                    break (object_type, content);
+                }
+            }
+        };
+
+        let mut deltas = deltas.into_iter().rev().peekable();
+        while let Some((offset, delta)) = deltas.next() {
+            content = Arc::new(apply_delta(&content, &delta)?);
+            // everything short of the object asked for is the base of the next delta
+            if deltas.peek().is_some() {
+                cache_delta_base(&self.path, offset, object_type, &content);
+            }
+        }
+
+        Ok((
+            object_type,
+            Arc::try_unwrap(content).unwrap_or_else(|content| content.to_vec()),
+        ))
+    }
+}
+
+// inflated delta bases by pack and offset, so that objects deltified against the same bases
+// don't resolve the whole chain every time
+struct DeltaBase {
+    used: u64,
+    object_type: GitObjectType,
+    content: Arc<Vec<u8>>,
+}
+
+#[derive(Default)]
+struct DeltaBaseCache {
+    size: usize,
+    clock: u64,
+    entries: HashMap<(PathBuf, u64), DeltaBase>,
+    // keys by the clock value of their last use, least recently used first
+    used: BTreeMap<u64, (PathBuf, u64)>,
+}
+
+impl DeltaBaseCache {
+    fn get(&mut self, key: &(PathBuf, u64)) -> Option<(GitObjectType, Arc<Vec<u8>>)> {
+        let base = self.entries.get_mut(key)?;
+        self.used.remove(&base.used);
+        self.clock += 1;
+        base.used = self.clock;
+        self.used.insert(self.clock, key.clone());
+
+        Some((base.object_type, base.content.clone()))
+    }
+
+    fn insert(&mut self, key: (PathBuf, u64), object_type: GitObjectType, content: Arc<Vec<u8>>) {
+        if self.entries.contains_key(&key) {
+            return;
+        }
+        self.clock += 1;
+        self.size += content.len();
+        self.used.insert(self.clock, key.clone());
+        self.entries.insert(
+            key,
+            DeltaBase {
+                used: self.clock,
+                object_type,
+                content,
+            },
+        );
+    }
+
+    fn evict(&mut self, limit: usize) {
+        while self.size > limit {
+            let Some((_, key)) = self.used.pop_first() else {
+                break;
+            };
+            if let Some(base) = self.entries.remove(&key) {
+                self.size -= base.content.len();
+            }
+        }
+    }
+}
+
+static DELTA_BASE_CACHE_LIMIT: AtomicUsize = AtomicUsize::new(DEFAULT_DELTA_BASE_CACHE_LIMIT);
+static DELTA_BASES: Mutex<Option<DeltaBaseCache>> = Mutex::new(None);
+
+// core.deltaBaseCacheLimit: how many bytes of delta bases to keep inflated
+pub fn set_delta_base_cache_limit(limit: usize) {
+    DELTA_BASE_CACHE_LIMIT.store(limit, Ordering::Relaxed);
+    let mut cache = DELTA_BASES
+        .lock()
+        .unwrap_or_else(|error| error.into_inner());
+    if let Some(cache) = cache.as_mut() {
+        cache.evict(limit);
+    }
+}
+
+fn cached_delta_base(pack: &Path, offset: u64) -> Option<(GitObjectType, Arc<Vec<u8>>)> {
+    let mut cache = DELTA_BASES
+        .lock()
+        .unwrap_or_else(|error| error.into_inner());
+    cache.as_mut()?.get(&(pack.to_path_buf(), offset))
+}
+
+fn cache_delta_base(pack: &Path, offset: u64, object_type: GitObjectType, content: &Arc<Vec<u8>>) {
+    let limit = DELTA_BASE_CACHE_LIMIT.load(Ordering::Relaxed);
+    if content.len() > limit {
+        return;
+    }
+    let mut cache = DELTA_BASES
+        .lock()
+        .unwrap_or_else(|error| error.into_inner());
+    let cache = cache.get_or_insert_with(DeltaBaseCache::default);
+    cache.insert((pack.to_path_buf(), offset), object_type, content.clone());
+    cache.evict(limit);
+}
+
+// opened packs, kept for the life of the process since reading an index means reading it all
+static PACKS: Mutex<Option<HashMap<PathBuf, Arc<Pack>>>> = Mutex::new(None);
+
+// unlike packs, multi-pack-indexes are rewritten in place, so they are read again when they change
+struct CachedMultiPackIndex {
+    modified: SystemTime,
+    len: u64,
+    index: Arc<MultiPackIndex>,
+}
+
+static MULTI_PACK_INDEXES: Mutex<Option<HashMap<PathBuf, CachedMultiPackIndex>>> = Mutex::new(None);
+
+fn pack(index_path: &Path) -> Option<Arc<Pack>> {
+    let mut packs = PACKS.lock().unwrap_or_else(|error| error.into_inner());
+    let packs = packs.get_or_insert_with(HashMap::new);
+    if let Some(pack) = packs.get(index_path) {
+        return Some(pack.clone());
+    }
+    let pack = Arc::new(Pack::open(index_path).ok()?);
+    packs.insert(index_path.to_path_buf(), pack.clone());
+
+    Some(pack)
+}
+
+// the index paths of the packs in an object directory
+pub fn pack_indexes(object_dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(object_dir.join("pack")) else {
+        return vec![];
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "idx"))
+        .filter(|path| path.with_extension("pack").is_file())
+        .collect();
+    paths.sort();
+
+    paths
+}
+
+fn multi_pack_index(pack_dir: &Path) -> Option<Arc<MultiPackIndex>> {
+    let path = midx::path(pack_dir);
+    let mut indexes = MULTI_PACK_INDEXES
+        .lock()
+        .unwrap_or_else(|error| error.into_inner());
+    let indexes = indexes.get_or_insert_with(HashMap::new);
+    let Ok(metadata) = fs::metadata(&path) else {
+        indexes.remove(&path);
+        return None;
+    };
+    let modified = metadata.modified().ok()?;
+    if let Some(cached) = indexes.get(&path) {
+        if cached.modified == modified && cached.len == metadata.len() {
+            return Some(cached.index.clone());
+        }
+    }
+    let index = Arc::new(MultiPackIndex::open(&path).ok()?);
+    indexes.insert(
+        path,
+        CachedMultiPackIndex {
+            modified,
+            len: metadata.len(),
+            index: index.clone(),
+        },
+    );
+
+    Some(index)
+}
+
+// the pack holding `id` in one object directory and its offset there, looked up through the
+// multi-pack-index first and then in any packs it doesn't cover
+fn find_packed(object_dir: &Path, id: &[u8]) -> Option<(Arc<Pack>, u64)> {
+    let pack_dir = object_dir.join("pack");
+    let midx = multi_pack_index(&pack_dir);
+    if let Some(midx) = &midx {
+        if let Some((name, offset)) = midx.find(id) {
+            if let Some(pack) = pack(&pack_dir.join(name)) {
+                return Some((pack, offset));
+            }
+        }
+    }
+
+    pack_indexes(object_dir)
+        .into_iter()
+        .filter(|path| {
+            let name = path.file_name().and_then(|name| name.to_str());
+            !midx.as_ref().is_some_and(|midx| {
+                midx.pack_names()
+                    .iter()
+                    .any(|covered| Some(covered.as_str()) == name)
+            })
+        })
+        .filter_map(|path| pack(&path))
+        .find_map(|pack| {
+            let offset = pack.find(id)?;
+            Some((pack, offset))
+        })
+}
+
+pub fn read_packed_object(object_dir: &Path, id: &str) -> Option<(GitObjectType, Vec<u8>)> {
+    let id = hex::decode(id).ok().filter(|id| id.len() == 20)?;
+    objects::object_directories(object_dir)
+        .iter()
+        .find_map(|dir| {
+            let (pack, offset) = find_packed(dir, &id)?;
+            pack.read_at(offset, object_dir).ok()
+        })
+}
+
+// the ids of the objects in the packs of one object directory that start with `prefix`
+pub fn packed_objects_with_prefix(object_dir: &Path, prefix: &str) -> Vec<String> {
+    pack_indexes(object_dir)
+        .iter()
+        .filter_map(|path| PackIndex::open(path).ok())
+        .flat_map(|index| index.ids_with_prefix(prefix))
+        .collect()
+}
+
+pub fn has_packed_object(object_dir: &Path, id: &str) -> bool {
+    let Some(id) = hex::decode(id).ok().filter(|id| id.len() == 20) else {
+        return false;
+    };
+    objects::object_directories(object_dir)
+        .iter()
+        .any(|dir| find_packed(dir, &id).is_some())
+}
+
+// an object for write_pack, with the path it was found at in a tree if it was, since files at
+// the same path make the best delta bases for each other
+pub struct PackObject {
+    pub id: String,
+    pub path: String,
+}
+
+// how write_pack looks for deltas: how many of the objects sorted before each one it tries as
+// its base, how long a chain of deltas may get, and whether a delta finds its base by offset
+// in the pack rather than by id
+pub struct PackOptions {
+    pub window: usize,
+    pub depth: usize,
+    pub ofs_delta: bool,
+}
+
+impl Default for PackOptions {
+    fn default() -> PackOptions {
+        PackOptions {
+            window: 10,
+            depth: 50,
+            ofs_delta: true,
+        }
+    }
+}
+
+// writes `objects` of `object_dir` into a new pack there, and then its .idx, giving the pack's
+// checksum, which both are named after. Objects go in the order given, except that a delta's
+// base always goes ahead of it
+pub fn write_pack(
+    object_dir: &Path,
+    objects: &[PackObject],
+    options: &PackOptions,
+    progress: &mut dyn Progress,
+) -> Result<String> {
+    let read = |object: &PackObject| read_object(object_dir, &object.id);
+    let mut sizes = Vec::with_capacity(objects.len());
+    for object in objects {
+        let (object_type, content) = read(object)?;
+        sizes.push((object_type, content.len()));
+    }
+    let deltas = find_deltas(object_dir, objects, &sizes, options, progress)?;
+
+    let mut order = vec![];
+    let mut placed = vec![false; objects.len()];
+    for i in 0..objects.len() {
+        let mut chain = vec![];
+        let mut next = Some(i);
+        while let Some(j) = next.filter(|j| !placed[*j]) {
+            placed[j] = true;
+            chain.push(j);
+            next = deltas[j].as_ref().map(|(base, _)| *base);
+        }
+        order.extend(chain.into_iter().rev());
+    }
+
+    let pack_dir = object_dir.join("pack");
+    fs::create_dir_all(&pack_dir)?;
+    let temp = pack_dir.join(format!("tmp_pack_{}", std::process::id()));
+    let mut file = BufWriter::new(fs::File::create(&temp)?);
+    let mut hasher = sha1::Sha1::new();
+    let mut header = PACK_SIGNATURE.to_vec();
+    header.extend_from_slice(&PACK_VERSION.to_be_bytes());
+    header.extend_from_slice(&(objects.len() as u32).to_be_bytes());
+    file.write_all(&header)?;
+    hasher.update(&header);
+
+    // (id, crc32 of the entry, offset) for the index
+    let mut entries = Vec::with_capacity(objects.len());
+    let mut offsets = vec![0; objects.len()];
+    let mut offset = header.len() as u64;
+    progress.start("Writing objects", Some(objects.len() as u64));
+    for (done, &i) in order.iter().enumerate() {
+        let object = &objects[i];
+        let (mut entry, content) = match &deltas[i] {
+            Some((base, delta)) if options.ofs_delta => {
+                let mut entry = encode_object_header(OBJ_OFS_DELTA, delta.len() as u64);
+                entry.extend(encode_base_distance(offset - offsets[*base]));
+                (entry, delta.clone())
+            }
+            Some((base, delta)) => {
+                let mut entry = encode_object_header(OBJ_REF_DELTA, delta.len() as u64);
+                let base = &objects[*base].id;
+                entry.extend(hex::decode(base).map_err(|_| Error::InvalidObjectId(base.clone()))?);
+                (entry, delta.clone())
+            }
+            None => {
+                let (object_type, content) = read(object)?;
+                let entry =
+                    encode_object_header(pack_object_type(object_type), content.len() as u64);
+                (entry, content)
+            }
+        };
+        let mut encoder = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(&content)?;
+        entry.extend(encoder.finish()?);
+
+        let mut crc = flate2::Crc::new();
+        crc.update(&entry);
+        let id = hex::decode(&object.id).map_err(|_| Error::InvalidObjectId(object.id.clone()))?;
+        entries.push((id, crc.sum(), offset));
+        offsets[i] = offset;
+        file.write_all(&entry)?;
+        hasher.update(&entry);
+        offset += entry.len() as u64;
+        progress.update(done as u64 + 1);
+    }
+    progress.finish();
+    let checksum = hasher.finalize();
+    file.write_all(&checksum)?;
+    file.into_inner().map_err(|error| error.into_error())?;
+
+    entries.sort();
+    let mut index = IDX_V2_MAGIC.to_vec();
+    index.extend_from_slice(&IDX_VERSION.to_be_bytes());
+    for first in 0..=255u8 {
+        let count = entries.partition_point(|(id, ..)| id[0] <= first);
+        index.extend_from_slice(&(count as u32).to_be_bytes());
+    }
+    for (id, ..) in &entries {
+        index.extend_from_slice(id);
+    }
+    for (_, crc, _) in &entries {
+        index.extend_from_slice(&crc.to_be_bytes());
+    }
+    let mut large_offsets = vec![];
+    for (.., offset) in &entries {
+        let offset = match u32::try_from(*offset) {
+            Ok(offset) if offset & LARGE_OFFSET == 0 => offset,
+            _ => {
+                large_offsets.extend_from_slice(&offset.to_be_bytes());
+                LARGE_OFFSET | (large_offsets.len() / 8 - 1) as u32
+            }
+        };
+        index.extend_from_slice(&offset.to_be_bytes());
+    }
+    index.extend_from_slice(&large_offsets);
+    index.extend_from_slice(&checksum);
+    let index_checksum = sha1::Sha1::digest(&index);
+    index.extend_from_slice(&index_checksum);
+
+    // the pack goes in place first, since readers only look at packs that have an index
+    let name = hex::encode(checksum);
+    let temp_index = pack_dir.join(format!("tmp_idx_{}", std::process::id()));
+    fs::write(&temp_index, &index)?;
+    fs::rename(&temp, pack_dir.join(format!("pack-{}.pack", name)))?;
+    fs::rename(&temp_index, pack_dir.join(format!("pack-{}.idx", name)))?;
+
+    Ok(name)
+}
+
+fn read_object(object_dir: &Path, id: &str) -> Result<(GitObjectType, Vec<u8>)> {
+    objects::read_object(object_dir, id).ok_or_else(|| Error::ObjectNotFound(id.to_string()))
+}
+
+// a delta and the index of the object it applies to
+type Delta = (usize, Vec<u8>);
+
+// the delta, if any, each of `objects` is written as, with the object it is based on. Objects
+// are sorted so that those alike come together, by type, by a hash of the path that sorts
+// files of the same name together, and biggest first, since removing data makes a smaller
+// delta than adding it; each is then tried against the ones in the window before it
+fn find_deltas(
+    object_dir: &Path,
+    objects: &[PackObject],
+    sizes: &[(GitObjectType, usize)],
+    options: &PackOptions,
+    progress: &mut dyn Progress,
+) -> Result<Vec<Option<Delta>>> {
+    let mut deltas: Vec<Option<Delta>> = objects.iter().map(|_| None).collect();
+    if options.window == 0 || options.depth == 0 {
+        return Ok(deltas);
+    }
+    let mut sorted: Vec<usize> = (0..objects.len()).collect();
+    sorted.sort_by_key(|&i| {
+        let (object_type, size) = sizes[i];
+        (
+            Reverse(pack_object_type(object_type)),
+            name_hash(&objects[i].path),
+            Reverse(size),
+            i,
+        )
+    });
+
+    let mut depths = vec![0; objects.len()];
+    let mut window: VecDeque<(usize, GitObjectType, Vec<u8>)> = VecDeque::new();
+    progress.start("Compressing objects", Some(objects.len() as u64));
+    for (done, &i) in sorted.iter().enumerate() {
+        let (object_type, content) = read_object(object_dir, &objects[i].id)?;
+        let mut best: Option<(usize, Vec<u8>)> = None;
+        for (base, base_type, base_content) in window.iter().rev() {
+            if *base_type != object_type
+                || depths[*base] >= options.depth
+                || base_content.len() < content.len() / 32
+            {
+                continue;
+            }
+            // a delta is only worth it at under half the size, and less so the longer the
+            // chain it would make
+            let limit = (content.len() / 2).saturating_sub(20) * (options.depth - depths[*base])
+                / options.depth;
+            let limit = match &best {
+                Some((_, delta)) => limit.min(delta.len() - 1),
+                None => limit,
+            };
+            if limit == 0 {
+                continue;
+            }
+            if let Some(delta) = delta::create_delta(base_content, &content, Some(limit)) {
+                best = Some((*base, delta));
+            }
+        }
+        if let Some((base, _)) = &best {
+            depths[i] = depths[*base] + 1;
+        }
+        deltas[i] = best;
+
+        window.push_back((i, object_type, content));
+        if window.len() > options.window {
+            window.pop_front();
+        }
+        progress.update(done as u64 + 1);
+    }
+    progress.finish();
+    Ok(deltas)
+}
+
+// git's hash of a path for sorting: the last characters count the most, so files with the
+// same name, or at least the same extension, come out close together
+fn name_hash(path: &str) -> u32 {
+    path.bytes()
+        .filter(|b| !b.is_ascii_whitespace())
+        .fold(0, |hash: u32, b| (hash >> 2).wrapping_add((b as u32) << 24))
+}
+
+// the reverse of read_base_distance
+fn encode_base_distance(mut distance: u64) -> Vec<u8> {
+    let mut encoded = vec![(distance & 0x7f) as u8];
+    distance >>= 7;
+    while distance > 0 {
+        distance -= 1;
+        encoded.push(0x80 | (distance & 0x7f) as u8);
+        distance >>= 7;
+    }
+    encoded.reverse();
+    encoded
+}
+
+// the reverse of parse_object_header
+fn encode_object_header(object_type: u8, size: u64) -> Vec<u8> {
+    let mut header = vec![(object_type << 4) | (size & 0x0f) as u8];
+    let mut size = size >> 4;
+    while size > 0 {
+        *header.last_mut().unwrap() |= 0x80;
+        header.push((size & 0x7f) as u8);
+        size >>= 7;
+    }
+    header
+}
+
+fn pack_object_type(object_type: GitObjectType) -> u8 {
+    match object_type {
+        GitObjectType::Commit => 1,
+        GitObjectType::Tree => 2,
+        GitObjectType::Blob => 3,
+        GitObjectType::Tag => 4,
+    }
+}
+
+// "copy" instructions take a range of the base, "insert" instructions carry literal data
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    let truncated = || Error::InvalidPack("truncated delta".to_string());
+    let mut reader = delta;
+    let base_size = read_delta_size(&mut reader).ok_or_else(truncated)?;
+    let target_size = read_delta_size(&mut reader).ok_or_else(truncated)?;
+    if base_size != base.len() as u64 {
+        return Err(Error::InvalidPack(
+            "delta base has the wrong size".to_string(),
+        ));
+    }
+
+    let mut target = Vec::with_capacity(target_size.min(1 << 24) as usize);
+    while let Some((&instruction, rest)) = reader.split_first() {
+        reader = rest;
+        if instruction & 0x80 != 0 {
+            let mut offset = 0usize;
+            let mut size = 0usize;
+            for i in 0..7 {
+                if instruction & (1 << i) == 0 {
+                    continue;
+                }
+                let (&byte, rest) = reader.split_first().ok_or_else(truncated)?;
+                reader = rest;
+                match i {
+                    0..=3 => offset |= (byte as usize) << (i * 8),
+                    _ => size |= (byte as usize) << ((i - 4) * 8),
+                }
+            }
+            if size == 0 {
+                size = 0x10000;
+            }
+            let copy = base
+                .get(offset..offset.saturating_add(size))
+                .ok_or_else(|| Error::InvalidPack("delta copies past its base".to_string()))?;
+            target.extend_from_slice(copy);
+        } else if instruction != 0 {
+            let size = instruction as usize;
+            let data = reader.get(..size).ok_or_else(truncated)?;
+            target.extend_from_slice(data);
+            reader = &reader[size..];
+        } else {
+            return Err(Error::InvalidPack("invalid delta instruction".to_string()));
+        }
+    }
+
+    if target.len() as u64 != target_size {
+        return Err(Error::InvalidPack(
+            "delta result has the wrong size".to_string(),
+        ));
+    }
+
+    Ok(target)
+}
+
+// little-endian groups of 7 bits
+fn read_delta_size(reader: &mut &[u8]) -> Option<u64> {
+    let mut size = 0u64;
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = reader.split_first()?;
+        *reader = rest;
+        size |= ((byte & 0x7f) as u64).checked_shl(shift)?;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Some(size);
+        }
+    }
+}
+
+// how far back an OFS_DELTA's base starts: big-endian groups of 7 bits, each continuation
+// adding one so that no offset has two encodings
+fn read_base_distance(reader: &mut impl Read) -> Result<u64> {
+    let mut byte = [0; 1];
+    reader
+        .read_exact(&mut byte)
+        .map_err(pack_read_error("delta base offset"))?;
+    let mut distance = (byte[0] & 0x7f) as u64;
+    while byte[0] & 0x80 != 0 {
+        reader
+            .read_exact(&mut byte)
+            .map_err(pack_read_error("delta base offset"))?;
+        distance = ((distance + 1) << 7) | (byte[0] & 0x7f) as u64;
+    }
+
+    Ok(distance)
+}
+
+pub fn object_type_from_pack(object_type: u8) -> Result<GitObjectType> {
+    match object_type {
+        1..=4 => Ok(object_type.into()),
+        _ => Err(Error::InvalidPack(format!(
+            "unsupported object type {}",
+            object_type
+        ))),
+    }
+}
+
+// the type in bits 4-6 of the first byte, and the size in its low 4 bits followed by groups
+// of 7 bits while the high bit is set
+pub fn parse_object_header<T: Read>(reader: &mut T) -> Result<(u8, u128)> {
+    let mut first_byte = [0; 1];
+    reader.read_exact(&mut first_byte)?;
+    let obj_type = (first_byte[0] >> 4) & 0x07;
+    let mut object_size = (first_byte[0] & 0xF) as u128;
+    let msb = first_byte[0] >> 7;
+    if msb == 1 {
+        object_size = parse_size_encoding(reader, object_size as u32)?;
+    }
+
+    Ok((obj_type, object_size))
+}
+
+fn parse_size_encoding<T: Read>(reader: &mut T, base_size: u32) -> Result<u128> {
+    let mut object_size = base_size as u128;
+    let mut msb = 1;
+
+    let mut c = 0;
+    while msb != 0 {
+        let mut first_byte = [0; 1];
+        reader.read_exact(&mut first_byte)?;
+        msb = first_byte[0] >> 7;
+        let current_byte: u128 = (first_byte[0] & 0b0111_1111) as u128;
+        object_size += current_byte << (4 + 7 * c);
+        c += 1;
+    }
+
+    Ok(object_size)
+}
+
+pub fn inflate(reader: &mut impl BufRead, size: u128) -> Result<Vec<u8>> {
+    let mut object = Vec::new();
+    flate2::bufread::ZlibDecoder::new(reader)
+        .read_to_end(&mut object)
+        .map_err(pack_read_error("object data"))?;
+    if object.len() as u128 != size {
+        return Err(Error::InvalidPack("object has the wrong size".to_string()));
+    }
+
+    Ok(object)
+}
+
+// a read that runs out of pack data means the pack is truncated; other errors are passed on
+pub fn pack_read_error(what: &str) -> impl Fn(io::Error) -> Error + '_ {
+    move |error| match error.kind() {
+        io::ErrorKind::UnexpectedEof => Error::InvalidPack(format!("truncated {}", what)),
+        _ => error.into(),
+    }
+}
+
+fn be_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
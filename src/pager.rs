@@ -0,0 +1,108 @@
+use std::{
+    env,
+    io::{self, IsTerminal, StdoutLock, Write},
+    process::{Child, ChildStdin, Command, Stdio},
+};
+
+use crate::config::Config;
+
+const DEFAULT_PAGER: &str = "less";
+
+// the pager as git picks it: GIT_PAGER, core.pager, PAGER, and then less; an empty one or
+// "cat" means no pager
+pub fn pager(config: &Config) -> Option<String> {
+    let pager = env::var("GIT_PAGER")
+        .ok()
+        .or_else(|| config.get("core.pager").map(str::to_string))
+        .or_else(|| env::var("PAGER").ok())
+        .unwrap_or_else(|| DEFAULT_PAGER.to_string());
+    match pager.as_str() {
+        "" | "cat" => None,
+        _ => Some(pager),
+    }
+}
+
+// where a command with output that can run long writes: a pager, when stdout is a terminal and
+// there is one, or stdout. Once the pager has quit, what is left is thrown away
+pub enum Pager {
+    Stdout(StdoutLock<'static>),
+    Running {
+        process: Child,
+        // closed when done so that the pager sees the end of the output
+        input: Option<ChildStdin>,
+    },
+}
+
+impl Pager {
+    pub fn start(config: &Config) -> Pager {
+        let stdout = io::stdout();
+        let pager = match stdout.is_terminal() {
+            true => pager(config),
+            false => None,
+        };
+        let Some(pager) = pager else {
+            return Pager::Stdout(stdout.lock());
+        };
+
+        tracing::trace!(target: "command", "run_command: {}", pager);
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(&pager).stdin(Stdio::piped());
+        // less quits when the output fits on the screen, shows colors and leaves the screen as
+        // it is when it's done, unless told otherwise
+        if env::var_os("LESS").is_none() {
+            command.env("LESS", "FRX");
+        }
+        if env::var_os("LV").is_none() {
+            command.env("LV", "-c");
+        }
+        // a pager that can't be run leaves the output where it was going
+        match command.spawn() {
+            Ok(mut process) => {
+                let input = process.stdin.take();
+                Pager::Running { process, input }
+            }
+            Err(_) => Pager::Stdout(stdout.lock()),
+        }
+    }
+
+    // stdout as it is, as for --no-pager
+    pub fn none() -> Pager {
+        Pager::Stdout(io::stdout().lock())
+    }
+}
+
+impl Write for Pager {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Pager::Stdout(stdout) => stdout.write(buf),
+            Pager::Running { input, .. } => match input.as_mut().map(|input| input.write(buf)) {
+                Some(Err(error)) if error.kind() == io::ErrorKind::BrokenPipe => {
+                    *input = None;
+                    Ok(buf.len())
+                }
+                Some(result) => result,
+                None => Ok(buf.len()),
+            },
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Pager::Stdout(stdout) => stdout.flush(),
+            Pager::Running { input, .. } => match input.as_mut().map(|input| input.flush()) {
+                Some(Err(error)) if error.kind() != io::ErrorKind::BrokenPipe => Err(error),
+                _ => Ok(()),
+            },
+        }
+    }
+}
+
+// the command isn't done until the pager is
+impl Drop for Pager {
+    fn drop(&mut self) {
+        if let Pager::Running { process, input } = self {
+            input.take();
+            let _ = process.wait();
+        }
+    }
+}
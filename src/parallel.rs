@@ -0,0 +1,25 @@
+use std::io;
+
+use rayon::prelude::*;
+
+use crate::error::Result;
+
+pub fn available_workers() -> usize {
+    rayon::current_num_threads()
+}
+
+// runs `f` on every item on a pool of `workers` threads, stopping early once one of them fails;
+// the results are in the order of the items
+pub fn map<T, R, F>(items: &[T], workers: usize, f: F) -> Result<Vec<R>>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> Result<R> + Sync,
+{
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(workers)
+        .build()
+        .map_err(io::Error::other)?;
+
+    pool.install(|| items.par_iter().map(&f).collect())
+}
@@ -0,0 +1,884 @@
+use std::io::{self, Read, Write};
+
+use sha1::{Digest, Sha1};
+
+use crate::{
+    color, delta,
+    diff::NULL_ID,
+    error::Result,
+    pack, pretty,
+    regex::Regex,
+    xdiff::{self, Algorithm, Edit},
+};
+
+// the lines of unchanged context around each change
+const CONTEXT: usize = 3;
+
+const META: &str = color::BOLD;
+const FRAGMENT: &str = color::CYAN;
+const OLD: &str = color::RED;
+const NEW: &str = color::GREEN;
+const WHITESPACE: &str = color::BG_RED;
+const RESET: &str = color::RESET;
+
+// how --word-diff shows the words that changed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WordDiffMode {
+    // "[-removed-]{+added+}"
+    Plain,
+    // removed words in red and added ones in green, which colors the rest of the patch too
+    Color,
+    // a line for each run of words, marked with " ", "-" or "+", and "~" for a newline
+    Porcelain,
+}
+
+// how a patch shows what changed in files
+pub struct PatchOptions {
+    pub words: Option<WordDiff>,
+    // a binary file's changes as a "GIT binary patch" that can be applied, instead of saying
+    // only that it differs
+    pub binary: bool,
+    pub algorithm: Algorithm,
+    // the lines in color, with the whitespace errors in added lines marked
+    pub color: bool,
+}
+
+pub struct WordDiff {
+    pub mode: WordDiffMode,
+    // what a word is, instead of a run of anything but whitespace
+    pub regex: Option<Regex>,
+}
+
+// a version of a file in a patch
+pub struct Side {
+    pub mode: u32,
+    pub id: String,
+    pub content: Vec<u8>,
+}
+
+// the content of a version of a file, which is empty when it's missing
+pub fn content(side: Option<&Side>) -> &[u8] {
+    side.map_or(&[], |side| &side.content)
+}
+
+// the patch for `path` going from `old` to `new`, either missing when the file is added or
+// deleted, with the content of a `binary` file left out. Like git, it shows nothing when only
+// the id changed without the content, and a change between kinds of files should be written as
+// a deletion and an addition
+pub fn write_patch(
+    out: &mut impl Write,
+    path: &str,
+    old: Option<&Side>,
+    new: Option<&Side>,
+    binary: bool,
+    options: &PatchOptions,
+) -> io::Result<()> {
+    let words = options.words.as_ref();
+    let color = options.color || words.is_some_and(|words| words.mode == WordDiffMode::Color);
+    let meta = |line: String| match color {
+        true => format!("{}{}{}\n", META, line, RESET),
+        false => format!("{}\n", line),
+    };
+
+    let mut header = meta(format!("diff --git a/{} b/{}", path, path));
+    let mut must_show_header = true;
+    match (old, new) {
+        (None, Some(new)) => header += &meta(format!("new file mode {:06o}", new.mode)),
+        (Some(old), None) => header += &meta(format!("deleted file mode {:06o}", old.mode)),
+        (Some(old), Some(new)) if old.mode != new.mode => {
+            header += &meta(format!("old mode {:06o}", old.mode));
+            header += &meta(format!("new mode {:06o}", new.mode));
+        }
+        _ => must_show_header = false,
+    }
+    let id = |side: Option<&Side>| side.map_or(NULL_ID.to_string(), |side| side.id.clone());
+    if id(old) != id(new) {
+        // a binary patch is applied to the very blob it was made from, so it names it in full
+        let abbreviate = |id: String| match binary && options.binary {
+            true => id,
+            false => pretty::abbreviate(&id).to_string(),
+        };
+        let mut index = format!("index {}..{}", abbreviate(id(old)), abbreviate(id(new)));
+        if let (Some(old), Some(new)) = (old, new) {
+            if old.mode == new.mode {
+                index += &format!(" {:06o}", old.mode);
+            }
+        }
+        header += &meta(index);
+    }
+
+    if binary {
+        if content(old) == content(new) {
+            if must_show_header {
+                out.write_all(header.as_bytes())?;
+            }
+            return Ok(());
+        }
+        out.write_all(header.as_bytes())?;
+        if !options.binary {
+            let name = |prefix: &str, side: Option<&Side>| match side {
+                Some(_) => format!("{}{}", prefix, path),
+                None => "/dev/null".to_string(),
+            };
+            return writeln!(
+                out,
+                "Binary files {} and {} differ",
+                name("a/", old),
+                name("b/", new)
+            );
+        }
+        // the change, then the change back so the patch can be reversed
+        writeln!(out, "GIT binary patch")?;
+        write_binary_hunk(out, content(old), content(new))?;
+        return write_binary_hunk(out, content(new), content(old));
+    }
+
+    let old_lines = old.map_or(vec![], |old| xdiff::lines(&old.content));
+    let new_lines = new.map_or(vec![], |new| xdiff::lines(&new.content));
+    let edits = xdiff::diff(&old_lines, &new_lines, options.algorithm, true);
+    if edits.is_empty() {
+        if must_show_header {
+            out.write_all(header.as_bytes())?;
+        }
+        return Ok(());
+    }
+
+    // a name with a space in it is followed by a tab, so the line can be told from one with
+    // a timestamp after the name
+    let label = |prefix: &str, side: Option<&Side>| match side {
+        Some(_) if path.contains(' ') => format!("{}{}\t", prefix, path),
+        Some(_) => format!("{}{}", prefix, path),
+        None => "/dev/null".to_string(),
+    };
+    header += &meta(format!("--- {}", label("a/", old)));
+    header += &meta(format!("+++ {}", label("b/", new)));
+    out.write_all(header.as_bytes())?;
+
+    // blank lines added at the end are a whitespace error, as git works them out: when the new
+    // content ends in more blank lines than the old, the lines added from where they start (by
+    // git's count, a line early) once the old content's own are passed
+    let old_blank = trailing_blank_lines(content(old));
+    let new_blank = trailing_blank_lines(content(new));
+    let blank_at_eof = (new_blank > old_blank && !old_lines.is_empty()).then(|| {
+        (
+            old_lines.len() - old_blank,
+            (new_lines.len() - new_blank).saturating_sub(1),
+        )
+    });
+
+    let mut lines = LineWriter {
+        out,
+        color,
+        words,
+        minus: vec![],
+        plus: vec![],
+    };
+    let mut function = None;
+    let mut searched_to = None;
+    for hunk in hunks(&edits, CONTEXT) {
+        let (first, last) = (&hunk[0], &hunk[hunk.len() - 1]);
+        let old_start = first.old.start.saturating_sub(CONTEXT);
+        let new_start = first.new.start.saturating_sub(CONTEXT);
+        let after = CONTEXT
+            .min(old_lines.len() - last.old.end)
+            .min(new_lines.len() - last.new.end);
+        let (old_end, new_end) = (last.old.end + after, last.new.end + after);
+
+        // the function the hunk is in is the closest line before it that starts like one,
+        // looking back only as far as the hunk before, whose function it otherwise shares
+        for i in (0..old_start).rev() {
+            if searched_to.is_some_and(|to| i <= to) {
+                break;
+            }
+            if let Some(name) = function_name(old_lines[i]) {
+                function = Some(name);
+                break;
+            }
+        }
+        searched_to = old_start.checked_sub(1);
+
+        lines.hunk_header(
+            range(old_start, old_end - old_start),
+            range(new_start, new_end - new_start),
+            function,
+        )?;
+        let mut new_line = new_start;
+        for edit in &hunk {
+            for line in &new_lines[new_line..edit.new.start] {
+                lines.line(b' ', line)?;
+            }
+            for line in &old_lines[edit.old.clone()] {
+                lines.line(b'-', line)?;
+            }
+            for (i, line) in edit.new.clone().zip(&new_lines[edit.new.clone()]) {
+                match blank_at_eof {
+                    Some((old_from, new_from))
+                        if edit.old.end >= old_from && i >= new_from && is_blank(line) =>
+                    {
+                        lines.blank_at_eof(line)?
+                    }
+                    _ => lines.line(b'+', line)?,
+                }
+            }
+            new_line = edit.new.end;
+        }
+        for line in &new_lines[new_line..new_end] {
+            lines.line(b' ', line)?;
+        }
+    }
+    lines.flush_words()
+}
+
+// adds the patch for `path` to the patch id `hasher` is working out, which is git's patch-id:
+// the patch without its whitespace, hunk headers and blob ids, so that a change has the same
+// id whichever commit it was made on top of. A binary file counts by the ids of its blobs
+pub fn add_to_patch_id(
+    hasher: &mut Sha1,
+    path: &str,
+    old: Option<&Side>,
+    new: Option<&Side>,
+    binary: bool,
+) {
+    let mut add = |text: &[u8]| {
+        let text: Vec<u8> = text
+            .iter()
+            .copied()
+            .filter(|b| !b.is_ascii_whitespace())
+            .collect();
+        hasher.update(&text);
+    };
+    add(format!("diff --git a/{} b/{}", path, path).as_bytes());
+    match (old, new) {
+        (None, Some(new)) => add(format!("new file mode {:06o}", new.mode).as_bytes()),
+        (Some(old), None) => add(format!("deleted file mode {:06o}", old.mode).as_bytes()),
+        (Some(old), Some(new)) if old.mode != new.mode => {
+            add(format!("old mode {:06o}", old.mode).as_bytes());
+            add(format!("new mode {:06o}", new.mode).as_bytes());
+        }
+        _ => {}
+    }
+    if binary {
+        let id = |side: Option<&Side>| side.map_or(NULL_ID.to_string(), |side| side.id.clone());
+        add(id(old).as_bytes());
+        add(id(new).as_bytes());
+        return;
+    }
+    let label = |prefix: &str, side: Option<&Side>| match side {
+        Some(_) => format!("{}{}", prefix, path),
+        None => "/dev/null".to_string(),
+    };
+    add(format!("--- {}", label("a/", old)).as_bytes());
+    add(format!("+++ {}", label("b/", new)).as_bytes());
+
+    let old_lines = xdiff::lines(content(old));
+    let new_lines = xdiff::lines(content(new));
+    let edits = xdiff::diff(&old_lines, &new_lines, Algorithm::Myers, true);
+    let mut add_line = |sign: u8, line: &[u8]| {
+        add(&[sign]);
+        add(line);
+    };
+    for hunk in hunks(&edits, CONTEXT) {
+        let (first, last) = (&hunk[0], &hunk[hunk.len() - 1]);
+        let new_start = first.new.start.saturating_sub(CONTEXT);
+        let after = CONTEXT
+            .min(old_lines.len() - last.old.end)
+            .min(new_lines.len() - last.new.end);
+        let mut new_line = new_start;
+        for edit in &hunk {
+            for line in &new_lines[new_line..edit.new.start] {
+                add_line(b' ', line);
+            }
+            for line in &old_lines[edit.old.clone()] {
+                add_line(b'-', line);
+            }
+            for line in &new_lines[edit.new.clone()] {
+                add_line(b'+', line);
+            }
+            new_line = edit.new.end;
+        }
+        for line in &new_lines[new_line..last.new.end + after] {
+            add_line(b' ', line);
+        }
+    }
+}
+
+// "literal <size>" and the deflated content of `to`, or "delta <size>" and the deflated delta
+// from `from` to it when that comes out smaller, in lines of base85 that start with how many
+// bytes they hold
+fn write_binary_hunk(out: &mut impl Write, from: &[u8], to: &[u8]) -> io::Result<()> {
+    let literal = deflate(to)?;
+    let delta = match from.is_empty() || to.is_empty() {
+        true => None,
+        false => delta::create_delta(from, to, Some(literal.len())),
+    };
+    let (data, kind, size) = match delta {
+        Some(delta) => match deflate(&delta)? {
+            deflated if deflated.len() < literal.len() => (deflated, "delta", delta.len()),
+            _ => (literal, "literal", to.len()),
+        },
+        None => (literal, "literal", to.len()),
+    };
+
+    writeln!(out, "{} {}", kind, size)?;
+    for chunk in data.chunks(52) {
+        // 'A' to 'Z' for 1 to 26 bytes and 'a' to 'z' for 27 to 52
+        let len = match chunk.len() {
+            len @ 1..=26 => b'A' + len as u8 - 1,
+            len => b'a' + len as u8 - 27,
+        };
+        out.write_all(&[len])?;
+        out.write_all(&encode_85(chunk))?;
+        writeln!(out)?;
+    }
+    writeln!(out)
+}
+
+// a hunk of a GIT binary patch, inflated: the whole content it makes, or a delta against the
+// content it applies to
+#[derive(Debug)]
+pub enum BinaryHunk {
+    Literal(Vec<u8>),
+    Delta(Vec<u8>),
+}
+
+impl BinaryHunk {
+    // a hunk as write_binary_hunk writes it, from its "literal <size>" or "delta <size>" line
+    // and the lines of base85 after it, without their newlines; None when it is corrupt
+    pub fn parse(header: &str, lines: &[&[u8]]) -> Option<BinaryHunk> {
+        let (kind, size) = header.split_once(' ')?;
+        let size: usize = size.parse().ok()?;
+        let mut deflated = vec![];
+        for line in lines {
+            let (&len, digits) = line.split_first()?;
+            let len = match len {
+                b'A'..=b'Z' => len - b'A' + 1,
+                b'a'..=b'z' => len - b'a' + 27,
+                _ => return None,
+            } as usize;
+            let decoded = decode_85(digits)?;
+            if decoded.len() != len.div_ceil(4) * 4 {
+                return None;
+            }
+            deflated.extend(&decoded[..len]);
+        }
+
+        let mut data = Vec::with_capacity(size);
+        flate2::read::ZlibDecoder::new(&deflated[..])
+            .read_to_end(&mut data)
+            .ok()?;
+        match (kind, data.len() == size) {
+            ("literal", true) => Some(BinaryHunk::Literal(data)),
+            ("delta", true) => Some(BinaryHunk::Delta(data)),
+            _ => None,
+        }
+    }
+
+    // the content the hunk makes out of `from`
+    pub fn apply(&self, from: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            BinaryHunk::Literal(content) => Ok(content.clone()),
+            BinaryHunk::Delta(delta) => pack::apply_delta(from, delta),
+        }
+    }
+}
+
+fn deflate(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut compressor =
+        flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    compressor.write_all(data)?;
+    compressor.finish()
+}
+
+const BASE85: &[u8; 85] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz!#$%&()*+-;<=>?@^_`{|}~";
+
+// each 4 bytes, big-endian and padded with zeros, as 5 digits of base 85
+fn encode_85(data: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![];
+    for chunk in data.chunks(4) {
+        let mut word = [0u8; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        let mut value = u32::from_be_bytes(word);
+        let mut digits = [0u8; 5];
+        for digit in digits.iter_mut().rev() {
+            *digit = BASE85[(value % 85) as usize];
+            value /= 85;
+        }
+        encoded.extend(digits);
+    }
+    encoded
+}
+
+// each 5 digits of base 85 as 4 bytes, big-endian; None for anything that isn't a digit or a
+// group too big for 4 bytes
+fn decode_85(digits: &[u8]) -> Option<Vec<u8>> {
+    if !digits.len().is_multiple_of(5) {
+        return None;
+    }
+    let mut decoded = vec![];
+    for group in digits.chunks(5) {
+        let mut value: u32 = 0;
+        for digit in group {
+            let digit = BASE85.iter().position(|c| c == digit)? as u32;
+            value = value.checked_mul(85)?.checked_add(digit)?;
+        }
+        decoded.extend(value.to_be_bytes());
+    }
+    Some(decoded)
+}
+
+// the edits shown together in each hunk, which take in the next edit while the unchanged lines
+// between them would fit in the context of both
+fn hunks(edits: &[Edit], context: usize) -> Vec<Vec<Edit>> {
+    let mut hunks: Vec<Vec<Edit>> = vec![];
+    for edit in edits {
+        match hunks.last_mut() {
+            Some(hunk) if edit.old.start - hunk[hunk.len() - 1].old.end <= 2 * context => {
+                hunk.push(edit.clone())
+            }
+            _ => hunks.push(vec![edit.clone()]),
+        }
+    }
+    hunks
+}
+
+// "<start>,<count>" for the lines from the 0-based `start`, with an empty range starting at the
+// line before it and a count of 1 left out
+fn range(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, count),
+    }
+}
+
+// git's default for a line that names a function: one starting with a letter, "_" or "$",
+// without its trailing whitespace and cut to 80 bytes
+fn function_name(line: &[u8]) -> Option<&[u8]> {
+    let first = *line.first()?;
+    if !(first.is_ascii_alphabetic() || first == b'_' || first == b'$') {
+        return None;
+    }
+    let mut name = &line[..line.len().min(80)];
+    while let [rest @ .., b' ' | b'\t' | b'\n' | b'\r'] = name {
+        name = rest;
+    }
+    Some(name)
+}
+
+// an added line without the "+", marking the whitespace errors git looks for by default: spaces
+// before a tab in the indent, and whitespace at the end of the line, which takes in a CR
+fn write_whitespace_errors(out: &mut impl Write, line: &[u8]) -> io::Result<()> {
+    let (line, newline) = match line.strip_suffix(b"\n") {
+        Some(line) => (line, "\n"),
+        None => (line, ""),
+    };
+    let trailing = line.len() - line.iter().rev().take_while(|c| is_space(**c)).count();
+
+    // the indent goes out a tab at a time, with any spaces before the tab marked
+    let mut written = 0;
+    for (i, &c) in line[..trailing].iter().enumerate() {
+        match c {
+            b' ' => continue,
+            b'\t' if written < i => {
+                write!(out, "{}", WHITESPACE)?;
+                out.write_all(&line[written..i])?;
+                write!(out, "{}\t", RESET)?;
+            }
+            b'\t' => out.write_all(&line[written..=i])?,
+            _ => break,
+        }
+        written = i + 1;
+    }
+
+    if written < trailing {
+        write!(out, "{}", NEW)?;
+        out.write_all(&line[written..trailing])?;
+        write!(out, "{}", RESET)?;
+    }
+    if trailing < line.len() {
+        write!(out, "{}", WHITESPACE)?;
+        out.write_all(&line[trailing..])?;
+        write!(out, "{}", RESET)?;
+    }
+    write!(out, "{}", newline)
+}
+
+// a line split into its text and its end, which is its newline and any CR before it
+fn split_line_end(line: &[u8]) -> (&[u8], &[u8]) {
+    let text = line.strip_suffix(b"\n").unwrap_or(line);
+    let text = text.strip_suffix(b"\r").unwrap_or(text);
+    line.split_at(text.len())
+}
+
+// whitespace as C's isspace() has it
+fn is_space(c: u8) -> bool {
+    matches!(c, b' ' | b'\t' | b'\n' | b'\r' | b'\x0b' | b'\x0c')
+}
+
+fn is_blank(line: &[u8]) -> bool {
+    line.iter().all(|c| is_space(*c))
+}
+
+// how many blank lines `content` ends with, counted as git does it, which leaves out a first
+// line that is too short to be looked at
+fn trailing_blank_lines(content: &[u8]) -> usize {
+    let mut count = 0;
+    // where the line being looked at ends, before its newline
+    let mut end = content.len() as isize - 1;
+    if content.ends_with(b"\n") {
+        end -= 1;
+    }
+    while end > 0 {
+        let line_end = end as usize + 1;
+        let start = content[..line_end]
+            .iter()
+            .rposition(|c| *c == b'\n')
+            .map_or(0, |i| i + 1);
+        if !is_blank(&content[start..line_end]) {
+            break;
+        }
+        count += 1;
+        end = start as isize - 2;
+    }
+    count
+}
+
+// writes the lines of hunks, collecting the removed and added lines of a change to show their
+// words when --word-diff is on
+struct LineWriter<'a, W: Write> {
+    out: &'a mut W,
+    color: bool,
+    words: Option<&'a WordDiff>,
+    minus: Vec<u8>,
+    plus: Vec<u8>,
+}
+
+impl<W: Write> LineWriter<'_, W> {
+    fn hunk_header(&mut self, old: String, new: String, function: Option<&[u8]>) -> io::Result<()> {
+        self.flush_words()?;
+        let header = format!("@@ -{} +{} @@", old, new);
+        match self.color {
+            true => write!(self.out, "{}{}{}", FRAGMENT, header, RESET)?,
+            false => write!(self.out, "{}", header)?,
+        }
+        if let Some(function) = function {
+            // as in git, the whole line is cut to fit in 127 bytes
+            let function = &function[..function.len().min(126 - header.len())];
+            match self.color {
+                true => write!(self.out, " {}", RESET)?,
+                false => write!(self.out, " ")?,
+            }
+            self.out.write_all(function)?;
+            if self.color {
+                write!(self.out, "{}", RESET)?;
+            }
+        }
+        writeln!(self.out)
+    }
+
+    fn line(&mut self, prefix: u8, line: &[u8]) -> io::Result<()> {
+        let Some(words) = self.words else {
+            match (self.color, prefix) {
+                (false, _) => {
+                    self.out.write_all(&[prefix])?;
+                    self.out.write_all(line)?;
+                }
+                (true, b'+') => {
+                    write!(self.out, "{}+{}", NEW, RESET)?;
+                    write_whitespace_errors(self.out, line)?;
+                }
+                (true, _) => {
+                    let color = if prefix == b'-' { OLD } else { "" };
+                    let (text, end) = split_line_end(line);
+                    write!(self.out, "{}{}", color, prefix as char)?;
+                    self.out.write_all(text)?;
+                    self.out.write_all(RESET.as_bytes())?;
+                    self.out.write_all(end)?;
+                }
+            }
+            return self.no_newline(line);
+        };
+
+        // the words are compared as if every line ended in a newline
+        let mut line = line.to_vec();
+        if !line.ends_with(b"\n") {
+            line.push(b'\n');
+        }
+        match prefix {
+            b'-' => self.minus.extend(line),
+            b'+' => self.plus.extend(line),
+            _ => {
+                self.flush_words()?;
+                match words.mode {
+                    WordDiffMode::Porcelain => {
+                        self.out.write_all(b" ")?;
+                        self.out.write_all(&line[..line.len() - 1])?;
+                        if self.color {
+                            self.out.write_all(RESET.as_bytes())?;
+                        }
+                        self.out.write_all(b"\n~\n")?;
+                    }
+                    // an empty line has nothing to color, and the color ends before a CR
+                    _ if line == b"\n" => self.out.write_all(b"\n")?,
+                    _ => {
+                        let text = &line[..line.len() - 1];
+                        let (text, cr) = match text.strip_suffix(b"\r") {
+                            Some(text) => (text, "\r"),
+                            None => (text, ""),
+                        };
+                        self.out.write_all(text)?;
+                        if self.color {
+                            self.out.write_all(RESET.as_bytes())?;
+                        }
+                        writeln!(self.out, "{}", cr)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // an added blank line at the end of the file, all of it in the color of a whitespace error
+    fn blank_at_eof(&mut self, line: &[u8]) -> io::Result<()> {
+        if !self.color || self.words.is_some() {
+            return self.line(b'+', line);
+        }
+        let (text, end) = split_line_end(line);
+        write!(self.out, "{}+", WHITESPACE)?;
+        self.out.write_all(text)?;
+        self.out.write_all(RESET.as_bytes())?;
+        self.out.write_all(end)?;
+        self.no_newline(line)
+    }
+
+    // the marker for a last line without a newline
+    fn no_newline(&mut self, line: &[u8]) -> io::Result<()> {
+        if line.ends_with(b"\n") {
+            return Ok(());
+        }
+        writeln!(self.out)?;
+        self.out.write_all(b"\\ No newline at end of file")?;
+        if self.color {
+            self.out.write_all(RESET.as_bytes())?;
+        }
+        writeln!(self.out)
+    }
+
+    // the words of the lines removed and added since the last unchanged line: the new text,
+    // with what was removed from it and what was added shown where they were
+    fn flush_words(&mut self) -> io::Result<()> {
+        let Some(words) = self.words else {
+            return Ok(());
+        };
+        if self.minus.is_empty() && self.plus.is_empty() {
+            return Ok(());
+        }
+        let (minus, plus) = (
+            std::mem::take(&mut self.minus),
+            std::mem::take(&mut self.plus),
+        );
+        let style = words.mode;
+        if plus.is_empty() {
+            return style.write(self.out, self.color, Part::Old, &minus);
+        }
+
+        let old_words = split_words(&minus, words.regex.as_ref());
+        let new_words = split_words(&plus, words.regex.as_ref());
+        let mut current = 0;
+        for edit in word_edits(&minus, &old_words, &plus, &new_words) {
+            // an empty range is where the word before it ends
+            let bounds =
+                |words: &[(usize, usize)], range: &std::ops::Range<usize>| match range.is_empty() {
+                    true => range
+                        .start
+                        .checked_sub(1)
+                        .map_or((0, 0), |i| (words[i].1, words[i].1)),
+                    false => (words[range.start].0, words[range.end - 1].1),
+                };
+            let (minus_start, minus_end) = bounds(&old_words, &edit.old);
+            let (plus_start, plus_end) = bounds(&new_words, &edit.new);
+            if current != plus_start {
+                style.write(
+                    self.out,
+                    self.color,
+                    Part::Context,
+                    &plus[current..plus_start],
+                )?;
+            }
+            if minus_start != minus_end {
+                style.write(
+                    self.out,
+                    self.color,
+                    Part::Old,
+                    &minus[minus_start..minus_end],
+                )?;
+            }
+            if plus_start != plus_end {
+                style.write(self.out, self.color, Part::New, &plus[plus_start..plus_end])?;
+            }
+            current = plus_end;
+        }
+        if current != plus.len() {
+            style.write(self.out, self.color, Part::Context, &plus[current..])?;
+        }
+
+        Ok(())
+    }
+}
+
+// where each word of `text` starts and ends: runs of anything but whitespace, or the matches of
+// `regex` cut at a newline
+fn split_words(text: &[u8], regex: Option<&Regex>) -> Vec<(usize, usize)> {
+    let is_space = |c: u8| matches!(c, b' ' | b'\t' | b'\n' | b'\r');
+    let mut words = vec![];
+    let mut start = 0;
+    while start < text.len() {
+        if let Some(regex) = regex {
+            let Some((match_start, match_end)) = regex.find_at(&text[start..], 0) else {
+                break;
+            };
+            let (match_start, match_end) = (start + match_start, start + match_end);
+            let end = text[match_start..match_end]
+                .iter()
+                .position(|c| *c == b'\n')
+                .map_or(match_end, |i| match_start + i);
+            // an empty match moves on a byte to look again
+            if match_start == end {
+                start = match_start + 1;
+                continue;
+            }
+            if match_start > end {
+                break;
+            }
+            words.push((match_start, end));
+            start = end;
+            continue;
+        }
+
+        while start < text.len() && is_space(text[start]) {
+            start += 1;
+        }
+        if start == text.len() {
+            break;
+        }
+        let mut end = start + 1;
+        while end < text.len() && !is_space(text[end]) {
+            end += 1;
+        }
+        words.push((start, end));
+        start = end;
+    }
+
+    words
+}
+
+// the edits between two lists of words, found as git does by diffing them a word to a line
+// with Myers' algorithm whatever the patch uses; like git, the words both texts end with are
+// set aside first a kilobyte at a time
+fn word_edits(
+    minus: &[u8],
+    old_words: &[(usize, usize)],
+    plus: &[u8],
+    new_words: &[(usize, usize)],
+) -> Vec<Edit> {
+    let join = |text: &[u8], words: &[(usize, usize)]| -> Vec<u8> {
+        let mut joined = vec![];
+        for &(start, end) in words {
+            joined.extend(&text[start..end]);
+            joined.push(b'\n');
+        }
+        joined
+    };
+    let (mut old, mut new) = (join(minus, old_words), join(plus, new_words));
+
+    const BLOCK: usize = 1024;
+    let smaller = old.len().min(new.len());
+    let mut trimmed = 0;
+    while trimmed + BLOCK <= smaller
+        && old[old.len() - trimmed - BLOCK..old.len() - trimmed]
+            == new[new.len() - trimmed - BLOCK..new.len() - trimmed]
+    {
+        trimmed += BLOCK;
+    }
+    // what is set aside starts after a newline, so whole words are
+    let tail = &old[old.len() - trimmed..];
+    let recovered = tail
+        .iter()
+        .position(|c| *c == b'\n')
+        .map_or(trimmed, |i| i + 1);
+    old.truncate(old.len() - (trimmed - recovered));
+    new.truncate(new.len() - (trimmed - recovered));
+
+    xdiff::diff(
+        &xdiff::lines(&old),
+        &xdiff::lines(&new),
+        Algorithm::Myers,
+        false,
+    )
+}
+
+#[derive(Clone, Copy)]
+enum Part {
+    Context,
+    Old,
+    New,
+}
+
+// how each mode marks the parts of the text
+impl WordDiffMode {
+    // (color, prefix, suffix) for a part, where the other modes are colored as well when the
+    // patch is
+    fn marks(self, color: bool, part: Part) -> (&'static str, &'static str, &'static str) {
+        let color = match part {
+            Part::Old if color || self == WordDiffMode::Color => OLD,
+            Part::New if color || self == WordDiffMode::Color => NEW,
+            _ => "",
+        };
+        let (prefix, suffix) = match (self, part) {
+            (WordDiffMode::Plain, Part::Old) => ("[-", "-]"),
+            (WordDiffMode::Plain, Part::New) => ("{+", "+}"),
+            (WordDiffMode::Porcelain, Part::Context) => (" ", "\n"),
+            (WordDiffMode::Porcelain, Part::Old) => ("-", "\n"),
+            (WordDiffMode::Porcelain, Part::New) => ("+", "\n"),
+            _ => ("", ""),
+        };
+        (color, prefix, suffix)
+    }
+
+    fn newline(self) -> &'static str {
+        match self {
+            WordDiffMode::Porcelain => "~\n",
+            _ => "\n",
+        }
+    }
+
+    // `text` marked as `part` a line at a time, with the newlines between them
+    fn write(
+        self,
+        out: &mut impl Write,
+        color: bool,
+        part: Part,
+        mut text: &[u8],
+    ) -> io::Result<()> {
+        let (color, prefix, suffix) = self.marks(color, part);
+        while !text.is_empty() {
+            let newline = text.iter().position(|c| *c == b'\n');
+            let line = &text[..newline.unwrap_or(text.len())];
+            if !line.is_empty() {
+                write!(out, "{}{}", color, prefix)?;
+                out.write_all(line)?;
+                write!(out, "{}", suffix)?;
+                if !color.is_empty() {
+                    write!(out, "{}", RESET)?;
+                }
+            }
+            let Some(newline) = newline else {
+                break;
+            };
+            write!(out, "{}", self.newline())?;
+            text = &text[newline + 1..];
+        }
+        Ok(())
+    }
+}
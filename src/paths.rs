@@ -0,0 +1,49 @@
+use std::{
+    env,
+    path::{Component, Path, PathBuf},
+};
+
+// git paths always use "/", whatever the platform separator is
+pub fn to_native(base: &Path, git_path: &str) -> PathBuf {
+    git_path
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .fold(base.to_path_buf(), |path, part| path.join(part))
+}
+
+pub fn to_git(path: &Path) -> String {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+pub fn home_dir() -> Option<PathBuf> {
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+}
+
+// a path from config, where "~/" stands for the home directory
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+// $XDG_CONFIG_HOME, or ~/.config
+pub fn config_home() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| home_dir().map(|home| home.join(".config")))
+}
+
+// probes the filesystem the way git does on init: if ".GIT" resolves to the ".git" directory,
+// names differing only in case refer to the same file
+pub fn is_case_insensitive(work_tree: &Path) -> bool {
+    work_tree.join(".git").is_dir() && work_tree.join(".GIT").is_dir()
+}
@@ -0,0 +1,263 @@
+use crate::{
+    error::{Error, Result},
+    wildmatch::wildmatch,
+};
+
+// a pathspec names a path from the top of the tree, which matches itself and everything below
+// it, or a pattern with wildcards, where `*` matches across slashes too. Magic in front of it
+// changes that, as ":(glob,icase)path" or in short as ":!path":
+//   top (":/")           the path is from the top even when run in a subdirectory
+//   exclude (":!", ":^") what it matches is left out of what the others match
+//   glob                 wildcards as in .gitignore: `*` stops at a slash and `**` matches
+//                        any number of directories
+//   icase                letters match in either case
+//   literal              wildcards are taken as they are
+
+const WILDCARDS: &[char] = &['*', '?', '[', '\\'];
+
+#[derive(Clone, Debug)]
+pub struct Pathspec {
+    // from the top of the tree
+    path: String,
+    // how much of the path comes before its first wildcard
+    literal_len: usize,
+    glob: bool,
+    icase: bool,
+    exclude: bool,
+}
+
+impl Pathspec {
+    // `arg` given in the directory `cwd`, with any magic it starts with
+    pub fn parse(cwd: Option<&str>, arg: &str) -> Result<Pathspec> {
+        let (mut top, mut exclude, mut glob, mut icase, mut literal) =
+            (false, false, false, false, false);
+        let path = match arg.strip_prefix(":(") {
+            Some(rest) => {
+                let (magic, path) = rest
+                    .split_once(')')
+                    .ok_or_else(|| Error::UnterminatedPathspecMagic(arg.to_string()))?;
+                for word in magic.split(',').filter(|word| !word.is_empty()) {
+                    match word {
+                        "top" => top = true,
+                        "exclude" => exclude = true,
+                        "glob" => glob = true,
+                        "icase" => icase = true,
+                        "literal" => literal = true,
+                        _ => {
+                            return Err(Error::InvalidPathspecMagic(
+                                word.to_string(),
+                                arg.to_string(),
+                            ))
+                        }
+                    }
+                }
+                path
+            }
+            None => match arg.strip_prefix(':') {
+                Some(mut rest) => {
+                    // symbols up to the path, which may be set off by another ':'
+                    while let Some(c) = rest.chars().next() {
+                        match c {
+                            '/' => top = true,
+                            '!' | '^' => exclude = true,
+                            ':' => {
+                                rest = &rest[1..];
+                                break;
+                            }
+                            c if c.is_ascii_alphanumeric() || WILDCARDS.contains(&c) => break,
+                            c if !c.is_ascii() => break,
+                            c => return Err(Error::UnimplementedPathspecMagic(c, arg.to_string())),
+                        }
+                        rest = &rest[1..];
+                    }
+                    rest
+                }
+                None => arg,
+            },
+        };
+        if glob && literal {
+            return Err(Error::IncompatiblePathspecMagic(arg.to_string()));
+        }
+
+        let path = resolve(if top { None } else { cwd }, path)?;
+        let literal_len = match literal {
+            true => path.len(),
+            false => path.find(WILDCARDS).unwrap_or(path.len()),
+        };
+        Ok(Pathspec {
+            path,
+            literal_len,
+            glob,
+            icase,
+            exclude,
+        })
+    }
+
+    // a pathspec for `path` as it is, from the top of the tree
+    pub fn path(path: &str) -> Pathspec {
+        Pathspec {
+            path: path.to_string(),
+            literal_len: path.len(),
+            glob: false,
+            icase: false,
+            exclude: false,
+        }
+    }
+
+    pub fn is_exclude(&self) -> bool {
+        self.exclude
+    }
+
+    // whether `path` is the path of the pathspec or below it, or what its pattern matches
+    pub fn matches(&self, path: &str) -> bool {
+        let (spec, path) = self.folded(path);
+        if spec.is_empty() {
+            return true;
+        }
+        if let Some(rest) = path.strip_prefix(spec.as_str()) {
+            if rest.is_empty() || rest.starts_with('/') || spec.ends_with('/') {
+                return true;
+            }
+        }
+        let literal_len = self.literal_len;
+        literal_len < spec.len()
+            && path.starts_with(&spec[..literal_len])
+            && wildmatch(
+                &spec.as_bytes()[literal_len..],
+                &path.as_bytes()[literal_len..],
+                self.glob,
+            )
+    }
+
+    // whether anything in the directory `dir` could match, so that it's worth looking into
+    pub fn could_contain(&self, dir: &str) -> bool {
+        let (spec, folded) = self.folded(dir);
+        let fixed = &spec[..self.literal_len];
+        self.goes_into(dir)
+            || (self.literal_len < spec.len() && format!("{}/", folded).starts_with(fixed))
+            || self.matches(dir)
+    }
+
+    // whether the path, up to its first wildcard, goes on into the directory `dir`
+    pub fn goes_into(&self, dir: &str) -> bool {
+        let (spec, dir) = self.folded(dir);
+        spec[..self.literal_len].starts_with(&format!("{}/", dir))
+    }
+
+    // the pathspec and `path` as they are compared, which is in lower case with icase
+    fn folded(&self, path: &str) -> (String, String) {
+        match self.icase {
+            true => (self.path.to_ascii_lowercase(), path.to_ascii_lowercase()),
+            false => (self.path.clone(), path.to_string()),
+        }
+    }
+}
+
+// what a list of pathspecs matches: paths any of those that aren't excludes match, or any path
+// when they all are, and then only those no exclude matches. No pathspecs match everything
+#[derive(Clone, Debug, Default)]
+pub struct Pathspecs(Vec<Pathspec>);
+
+impl Pathspecs {
+    pub fn new(pathspecs: Vec<Pathspec>) -> Pathspecs {
+        Pathspecs(pathspecs)
+    }
+
+    // the pathspecs `args` given in the directory `cwd`
+    pub fn parse(cwd: Option<&str>, args: &[String]) -> Result<Pathspecs> {
+        let pathspecs = args
+            .iter()
+            .map(|arg| Pathspec::parse(cwd, arg))
+            .collect::<Result<_>>()?;
+        Ok(Pathspecs(pathspecs))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Pathspec> {
+        self.0.iter()
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        self.includes(path) && !self.0.iter().any(|spec| spec.exclude && spec.matches(path))
+    }
+
+    // whether `path` is among what the pathspecs that aren't excludes match
+    pub fn includes(&self, path: &str) -> bool {
+        let mut included = self.0.iter().filter(|spec| !spec.exclude).peekable();
+        included.peek().is_none() || included.any(|spec| spec.matches(path))
+    }
+
+    pub fn could_contain(&self, dir: &str) -> bool {
+        let mut included = self.0.iter().filter(|spec| !spec.exclude).peekable();
+        included.peek().is_none() || included.any(|spec| spec.could_contain(dir))
+    }
+
+    pub fn goes_into(&self, dir: &str) -> bool {
+        self.0
+            .iter()
+            .any(|spec| !spec.exclude && spec.goes_into(dir))
+    }
+}
+
+// `path` given relative to `cwd` as a path from the top of the tree, with "." and ".."
+// resolved; naming a directory with "." keeps the trailing "/" that stands for its contents.
+// A ".." above the top of the tree is an error rather than staying at the top
+pub fn resolve(cwd: Option<&str>, path: &str) -> Result<String> {
+    let mut components: Vec<&str> = cwd.into_iter().flat_map(|cwd| cwd.split('/')).collect();
+    let mut directory = false;
+    for component in path.split('/').filter(|component| !component.is_empty()) {
+        directory = matches!(component, "." | "..") || path.ends_with('/');
+        match component {
+            "." => {}
+            ".." => {
+                components
+                    .pop()
+                    .ok_or_else(|| Error::PathOutsideRepository(path.to_string()))?;
+            }
+            component => components.push(component),
+        }
+    }
+    let spec = components.join("/");
+    Ok(match directory && !spec.is_empty() {
+        true => format!("{}/", spec),
+        false => spec,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_rejects_climbing_above_the_top() {
+        assert_eq!(resolve(Some("a/b"), "../c").unwrap(), "a/c");
+        assert_eq!(resolve(Some("a"), "..").unwrap(), "");
+        assert_eq!(resolve(Some("a"), ".").unwrap(), "a/");
+        for (cwd, path) in [
+            (None, ".."),
+            (None, "../a"),
+            (Some("a"), "../../a"),
+            (Some("a"), "../b/../.."),
+        ] {
+            assert!(
+                matches!(resolve(cwd, path), Err(Error::PathOutsideRepository(_))),
+                "{:?} {}",
+                cwd,
+                path
+            );
+        }
+        assert!(matches!(
+            Pathspec::parse(Some("a"), "../../*.rs"),
+            Err(Error::PathOutsideRepository(_))
+        ));
+        // from the top, ".." has nowhere to go whatever the current directory is
+        assert!(Pathspec::parse(Some("a/b"), ":/../a").is_err());
+    }
+}
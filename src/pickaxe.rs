@@ -0,0 +1,83 @@
+use crate::{diff, regex::Regex};
+
+// what "log -S" and "log -G" look for in the files a commit changes
+pub enum Pickaxe {
+    // a change in how many times a string appears
+    Count(Vec<u8>),
+    // the same for what a regex matches, with --pickaxe-regex
+    CountMatches(Regex),
+    // an added or removed line a regex matches
+    Lines(Regex),
+}
+
+impl Pickaxe {
+    // whether a file going from `old` to `new`, either missing when the file is added or
+    // deleted, changes in the way this looks for
+    pub fn finds(&self, old: Option<&[u8]>, new: Option<&[u8]>) -> bool {
+        let (old, new) = (old.unwrap_or_default(), new.unwrap_or_default());
+        match self {
+            Pickaxe::Count(needle) => count(old, needle) != count(new, needle),
+            Pickaxe::CountMatches(regex) => count_matches(old, regex) != count_matches(new, regex),
+            // like git, binary files have no lines to look through
+            Pickaxe::Lines(_) if is_binary(old) || is_binary(new) => false,
+            Pickaxe::Lines(regex) => {
+                // as in git, each line is matched with its newline
+                let lines = |content: &[u8]| -> Vec<Vec<u8>> {
+                    content
+                        .split_inclusive(|b| *b == b'\n')
+                        .map(|line| line.to_vec())
+                        .collect()
+                };
+                let (old, new) = (lines(old), lines(new));
+                let (mut removed, mut added) = (vec![true; old.len()], vec![true; new.len()]);
+                for (i, j) in diff::matching_lines(&old, &new) {
+                    (removed[i], added[j]) = (false, false);
+                }
+
+                let changed = |lines: &[Vec<u8>], changed: &[bool]| {
+                    lines
+                        .iter()
+                        .zip(changed)
+                        .any(|(line, changed)| *changed && regex.is_match(line))
+                };
+                changed(&old, &removed) || changed(&new, &added)
+            }
+        }
+    }
+}
+
+// how many times `needle` appears in `content` without overlapping
+fn count(content: &[u8], needle: &[u8]) -> usize {
+    if needle.is_empty() {
+        return 0;
+    }
+    let (mut count, mut rest) = (0, content);
+    while let Some(i) = rest
+        .windows(needle.len())
+        .position(|window| window == needle)
+    {
+        count += 1;
+        rest = &rest[i + needle.len()..];
+    }
+
+    count
+}
+
+fn count_matches(content: &[u8], regex: &Regex) -> usize {
+    let (mut count, mut pos) = (0, 0);
+    while let Some((start, end)) = regex.find_at(content, pos) {
+        count += 1;
+        // an empty match moves on a byte so the next one can't be the same
+        pos = end.max(start + 1);
+        if pos > content.len() {
+            break;
+        }
+    }
+
+    count
+}
+
+// git's check for whether a diff shows a file as binary: a NUL in its first 8000 bytes
+fn is_binary(content: &[u8]) -> bool {
+    content[..content.len().min(8000)].contains(&0)
+}
@@ -0,0 +1,210 @@
+use std::io::{self, Read, Write};
+
+pub const MAX_PKT_LEN: usize = 65520;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Packet {
+    Data(Vec<u8>),
+    Flush,
+    Delim,
+    ResponseEnd,
+}
+
+impl Packet {
+    pub fn as_data(&self) -> Option<&[u8]> {
+        match self {
+            Packet::Data(data) => Some(data),
+            _ => None,
+        }
+    }
+
+    // pkt-line payloads conventionally end with a LF that isn't part of the value
+    pub fn as_text(&self) -> Option<&str> {
+        let data = self.as_data()?;
+        let data = data.strip_suffix(b"\n").unwrap_or(data);
+        std::str::from_utf8(data).ok()
+    }
+}
+
+pub struct PktLineReader<R> {
+    inner: R,
+}
+
+impl<R: Read> PktLineReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner }
+    }
+
+    // the next packet, or None once the stream has ended
+    pub fn read_packet(&mut self) -> io::Result<Option<Packet>> {
+        let mut length = [0; 4];
+        let mut read = 0;
+        while read < length.len() {
+            match self.inner.read(&mut length[read..])? {
+                0 if read == 0 => return Ok(None),
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                n => read += n,
+            }
+        }
+
+        let length = std::str::from_utf8(&length)
+            .ok()
+            .and_then(|length| usize::from_str_radix(length, 16).ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid pkt-line length"))?;
+
+        let packet = match length {
+            0 => Packet::Flush,
+            1 => Packet::Delim,
+            2 => Packet::ResponseEnd,
+            3 => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "invalid pkt-line length",
+                ))
+            }
+            _ => {
+                let mut payload = vec![0; length - 4];
+                self.inner.read_exact(&mut payload)?;
+                Packet::Data(payload)
+            }
+        };
+        trace('<', &packet);
+
+        Ok(Some(packet))
+    }
+
+    // the data packets up to the next flush, which is read too
+    pub fn read_until_flush(&mut self) -> io::Result<Vec<Vec<u8>>> {
+        let mut packets = vec![];
+        while let Some(packet) = self.read_packet()? {
+            match packet {
+                Packet::Data(data) => packets.push(data),
+                Packet::Flush => break,
+                _ => {}
+            }
+        }
+
+        Ok(packets)
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Iterator for PktLineReader<R> {
+    type Item = io::Result<Packet>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_packet().transpose()
+    }
+}
+
+pub struct PktLineWriter<W> {
+    inner: W,
+}
+
+impl<W: Write> PktLineWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    pub fn write_data(&mut self, data: &[u8]) -> io::Result<()> {
+        if data.len() + 4 > MAX_PKT_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "pkt-line payload too long",
+            ));
+        }
+        trace_data('>', data);
+        write!(self.inner, "{:04x}", data.len() + 4)?;
+        self.inner.write_all(data)
+    }
+
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        self.write_data(format!("{}\n", line).as_bytes())
+    }
+
+    pub fn write_flush(&mut self) -> io::Result<()> {
+        trace('>', &Packet::Flush);
+        self.inner.write_all(b"0000")
+    }
+
+    pub fn write_delim(&mut self) -> io::Result<()> {
+        trace('>', &Packet::Delim);
+        self.inner.write_all(b"0001")
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+// logs a packet received ('<') or sent ('>') for RGIT_TRACE_PACKET
+fn trace(direction: char, packet: &Packet) {
+    match packet {
+        Packet::Data(data) => trace_data(direction, data),
+        Packet::Flush => tracing::trace!(target: "packet", "{} 0000", direction),
+        Packet::Delim => tracing::trace!(target: "packet", "{} 0001", direction),
+        Packet::ResponseEnd => tracing::trace!(target: "packet", "{} 0002", direction),
+    }
+}
+
+fn trace_data(direction: char, data: &[u8]) {
+    tracing::trace!(target: "packet", "{} {}", direction, crate::trace::packet(data));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packets_read_back_as_written() {
+        let mut writer = PktLineWriter::new(vec![]);
+        writer.write_line("want abc").unwrap();
+        writer.write_delim().unwrap();
+        writer.write_data(b"\0binary").unwrap();
+        writer.write_flush().unwrap();
+        let written = writer.into_inner();
+        assert_eq!(written, b"000dwant abc\n0001000b\0binary0000");
+
+        let packets: Vec<Packet> = PktLineReader::new(&written[..])
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            packets,
+            [
+                Packet::Data(b"want abc\n".to_vec()),
+                Packet::Delim,
+                Packet::Data(b"\0binary".to_vec()),
+                Packet::Flush,
+            ]
+        );
+        assert_eq!(packets[0].as_text(), Some("want abc"));
+    }
+
+    #[test]
+    fn read_until_flush_stops_after_the_flush() {
+        let mut reader = PktLineReader::new(&b"0006a\n0006b\n00000006c\n"[..]);
+        assert_eq!(reader.read_until_flush().unwrap(), [b"a\n", b"b\n"]);
+        assert_eq!(
+            reader.read_packet().unwrap(),
+            Some(Packet::Data(b"c\n".to_vec()))
+        );
+        assert_eq!(reader.read_packet().unwrap(), None);
+    }
+
+    #[test]
+    fn bad_lengths_are_rejected() {
+        for input in [&b"zzzzdata"[..], b"0003", b"00"] {
+            assert!(PktLineReader::new(input).read_packet().is_err());
+        }
+        assert!(PktLineWriter::new(vec![])
+            .write_data(&vec![0; MAX_PKT_LEN - 3])
+            .is_err());
+    }
+}
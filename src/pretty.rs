@@ -0,0 +1,512 @@
+use std::collections::HashMap;
+
+use crate::{
+    color,
+    error::{Error, Result},
+    objects::GitObjectType,
+    repository::{Commit, Repository, Signature},
+};
+
+const ABBREV: usize = 7;
+
+// how "log" shows each commit: one of git's presets, or a format string with placeholders
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    Oneline,
+    Short,
+    Medium,
+    Full,
+    Fuller,
+    Raw,
+    // a format string; "tformat:" ends every commit with a newline, "format:" only puts one
+    // between them
+    User { template: String, terminator: bool },
+}
+
+impl Format {
+    // a --pretty argument: a preset name, "format:<string>", "tformat:<string>", or a bare
+    // format string, which is taken as tformat as long as it has a placeholder
+    pub fn parse(name: &str) -> Result<Format> {
+        let format = match name {
+            "oneline" => Format::Oneline,
+            "short" => Format::Short,
+            "medium" => Format::Medium,
+            "full" => Format::Full,
+            "fuller" => Format::Fuller,
+            "raw" => Format::Raw,
+            _ => {
+                let (template, terminator) = if let Some(template) = name.strip_prefix("format:") {
+                    (template, false)
+                } else if let Some(template) = name.strip_prefix("tformat:") {
+                    (template, true)
+                } else if name.is_empty() || name.contains('%') {
+                    (name, true)
+                } else {
+                    return Err(Error::InvalidPrettyFormat(name.to_string()));
+                };
+                Format::User {
+                    template: template.to_string(),
+                    terminator,
+                }
+            }
+        };
+
+        Ok(format)
+    }
+
+    // whether each commit's text is followed by a newline, rather than separated from the next
+    // by one
+    pub fn uses_terminator(&self) -> bool {
+        match self {
+            Format::Oneline => true,
+            Format::User { terminator, .. } => *terminator,
+            _ => false,
+        }
+    }
+
+    // an empty format string shows nothing at all for each commit, not even a newline
+    pub fn is_empty(&self) -> bool {
+        matches!(self, Format::User { template, .. } if template.is_empty())
+    }
+}
+
+// a ref pointing at a commit, as "log --decorate" shows it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Decoration {
+    // with the branch HEAD is on, when that points at the same commit
+    Head(Option<String>),
+    Branch(String),
+    Tag(String),
+    Remote(String),
+    Stash,
+}
+
+impl Decoration {
+    // "HEAD -> main", "tag: v1" or "origin/main", in the colors git gives each kind of ref
+    fn label(&self, color: bool) -> String {
+        let paint = |code: &str, text: &str| color::paint(color, code, text);
+        match self {
+            Decoration::Head(None) => paint(color::BOLD_CYAN, "HEAD"),
+            Decoration::Head(Some(branch)) => format!(
+                "{}{}",
+                paint(color::BOLD_CYAN, "HEAD -> "),
+                paint(color::BOLD_GREEN, branch)
+            ),
+            Decoration::Branch(branch) => paint(color::BOLD_GREEN, branch),
+            Decoration::Tag(tag) => paint(color::BOLD_YELLOW, &format!("tag: {}", tag)),
+            Decoration::Remote(remote) => paint(color::BOLD_RED, remote),
+            Decoration::Stash => paint(color::BOLD_MAGENTA, "refs/stash"),
+        }
+    }
+}
+
+pub struct Pretty {
+    pub format: Format,
+    // show abbreviated commit ids in the presets
+    pub abbrev_commit: bool,
+    // show the refs pointing at each commit in the presets, as %d does
+    pub decorate: bool,
+    // for each commit the refs pointing at it
+    pub decorations: HashMap<String, Vec<Decoration>>,
+    // the ids and decorations of the presets in color; the placeholders aren't
+    pub color: bool,
+}
+
+impl Pretty {
+    pub fn new(format: Format) -> Pretty {
+        Pretty {
+            format,
+            abbrev_commit: false,
+            decorate: false,
+            decorations: HashMap::new(),
+            color: false,
+        }
+    }
+
+    // the text shown for a commit, without the newline that separates or ends it
+    pub fn format(&self, commit: &Commit) -> String {
+        self.format_signed(commit, "")
+    }
+
+    // the text shown for a commit with the report on its signature, as --show-signature puts
+    // it right after the commit's id
+    pub fn format_signed(&self, commit: &Commit, signature: &str) -> String {
+        let id = match self.abbrev_commit {
+            true => abbreviate(&commit.id),
+            false => &commit.id,
+        };
+        let decoration = match self.decorate {
+            true => self.decoration(&commit.id, self.color),
+            false => String::new(),
+        };
+        let paint = |text: &str| color::paint(self.color, color::YELLOW, text);
+
+        let (header, body) = match &self.format {
+            Format::User { template, .. } => {
+                return format!("{}{}", signature, self.expand(template, commit));
+            }
+            Format::Oneline => {
+                return format!(
+                    "{}{} {}{}",
+                    paint(id),
+                    decoration,
+                    signature,
+                    subject(&commit.message)
+                );
+            }
+            Format::Raw => {
+                let mut header = format!("tree {}\n", commit.tree);
+                for parent in &commit.parents {
+                    header.push_str(&format!("parent {}\n", parent));
+                }
+                header.push_str(&format!(
+                    "author {}\ncommitter {}\n",
+                    commit.author, commit.committer
+                ));
+                (header, message(&commit.message))
+            }
+            Format::Short => (
+                format!("Author: {}\n", person(&commit.author)),
+                title(&commit.message),
+            ),
+            Format::Medium => (
+                format!(
+                    "Author: {}\nDate:   {}\n",
+                    person(&commit.author),
+                    commit.author.date()
+                ),
+                message(&commit.message),
+            ),
+            Format::Full => (
+                format!(
+                    "Author: {}\nCommit: {}\n",
+                    person(&commit.author),
+                    person(&commit.committer)
+                ),
+                message(&commit.message),
+            ),
+            Format::Fuller => (
+                format!(
+                    "Author:     {}\nAuthorDate: {}\nCommit:     {}\nCommitDate: {}\n",
+                    person(&commit.author),
+                    commit.author.date(),
+                    person(&commit.committer),
+                    commit.committer.date()
+                ),
+                message(&commit.message),
+            ),
+        };
+
+        let mut text = format!(
+            "{}{}\n{}",
+            paint(&format!("commit {}", id)),
+            decoration,
+            signature
+        );
+        if commit.parents.len() > 1 && self.format != Format::Raw {
+            let parents: Vec<&str> = commit.parents.iter().map(|id| abbreviate(id)).collect();
+            text.push_str(&format!("Merge: {}\n", parents.join(" ")));
+        }
+        text.push_str(&header);
+        text.push('\n');
+        for line in body.lines() {
+            text.push_str(&format!("    {}\n", line));
+        }
+        text.truncate(text.trim_end().len());
+        text.push('\n');
+        text
+    }
+
+    // " (HEAD -> main, tag: v1)", or nothing when no ref points at the commit; in `color` the
+    // parentheses and commas are yellow
+    fn decoration(&self, id: &str, color: bool) -> String {
+        let Some(decorations) = self.decorations.get(id) else {
+            return String::new();
+        };
+        let paint = |text: &str| color::paint(color, color::YELLOW, text);
+        let labels: Vec<String> = decorations
+            .iter()
+            .map(|decoration| decoration.label(color))
+            .collect();
+        format!("{}{}{}", paint(" ("), labels.join(&paint(", ")), paint(")"))
+    }
+
+    fn expand(&self, template: &str, commit: &Commit) -> String {
+        let mut text = String::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('%') {
+            text.push_str(&rest[..start]);
+            rest = &rest[start + 1..];
+
+            // "%+x" puts a newline before x and "% x" a space, unless x is empty, and "%-x"
+            // takes away the newlines before it when it is
+            let modifier = rest.chars().next().filter(|c| matches!(c, '+' | '-' | ' '));
+            let placeholder = match modifier {
+                Some(_) => &rest[1..],
+                None => rest,
+            };
+            let Some((value, length)) = self.placeholder(placeholder, commit) else {
+                text.push('%');
+                continue;
+            };
+            rest = &placeholder[length..];
+
+            match modifier {
+                Some('+') if !value.is_empty() => text.push('\n'),
+                Some(' ') if !value.is_empty() => text.push(' '),
+                Some('-') if value.is_empty() => text.truncate(text.trim_end_matches('\n').len()),
+                _ => {}
+            }
+            text.push_str(&value);
+        }
+        text.push_str(rest);
+        text
+    }
+
+    // the value of the placeholder `rest` starts with, and how long that placeholder is
+    fn placeholder(&self, rest: &str, commit: &Commit) -> Option<(String, usize)> {
+        let mut chars = rest.chars();
+        let value = match chars.next()? {
+            '%' => "%".to_string(),
+            'n' => "\n".to_string(),
+            'x' => {
+                let byte = u8::from_str_radix(rest.get(1..3)?, 16).ok()?;
+                return Some(((byte as char).to_string(), 3));
+            }
+            'H' => commit.id.clone(),
+            'h' => abbreviate(&commit.id).to_string(),
+            'T' => commit.tree.clone(),
+            't' => abbreviate(&commit.tree).to_string(),
+            'P' => commit.parents.join(" "),
+            'p' => {
+                let parents: Vec<&str> = commit.parents.iter().map(|id| abbreviate(id)).collect();
+                parents.join(" ")
+            }
+            's' => subject(&commit.message),
+            'f' => sanitized_subject(&commit.message),
+            'b' => body(&commit.message),
+            'B' => commit.message.clone(),
+            'd' => self.decoration(&commit.id, false),
+            'D' => self
+                .decorations
+                .get(&commit.id)
+                .map(|decorations| {
+                    let labels: Vec<String> = decorations
+                        .iter()
+                        .map(|decoration| decoration.label(false))
+                        .collect();
+                    labels.join(", ")
+                })
+                .unwrap_or_default(),
+            'a' => return Some((signature_placeholder(&commit.author, chars.next()?)?, 2)),
+            'c' => return Some((signature_placeholder(&commit.committer, chars.next()?)?, 2)),
+            'C' => {
+                for (name, code) in [
+                    ("red", "\x1b[31m"),
+                    ("green", "\x1b[32m"),
+                    ("blue", "\x1b[34m"),
+                    ("reset", "\x1b[m"),
+                ] {
+                    if rest[1..].starts_with(name) {
+                        return Some((code.to_string(), name.len() + 1));
+                    }
+                }
+                return None;
+            }
+            _ => return None,
+        };
+
+        Some((value, 1))
+    }
+}
+
+// the author (%a) or committer (%c) placeholders
+fn signature_placeholder(signature: &Signature, field: char) -> Option<String> {
+    let value = match field {
+        'n' | 'N' => signature.name.clone(),
+        'e' | 'E' => signature.email.clone(),
+        'l' | 'L' => signature
+            .email
+            .split('@')
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+        'd' => signature.date(),
+        'D' => signature.format_date("%a, %-d %b %Y %H:%M:%S %z"),
+        'r' => relative_date(signature.time, chrono::Utc::now().timestamp()),
+        't' => signature.time.to_string(),
+        'i' => signature.format_date("%Y-%m-%d %H:%M:%S %z"),
+        'I' => signature.format_date("%Y-%m-%dT%H:%M:%S%:z"),
+        's' => signature.format_date("%Y-%m-%d"),
+        _ => return None,
+    };
+
+    Some(value)
+}
+
+fn person(signature: &Signature) -> String {
+    format!("{} <{}>", signature.name, signature.email)
+}
+
+pub fn abbreviate(id: &str) -> &str {
+    &id[..id.len().min(ABBREV)]
+}
+
+// the message without the blank lines before it
+fn message(message: &str) -> String {
+    message.trim_start_matches('\n').to_string()
+}
+
+// the first paragraph of a commit message, as it is
+fn title(message: &str) -> String {
+    let lines: Vec<&str> = message
+        .trim_start_matches('\n')
+        .lines()
+        .take_while(|line| !line.trim().is_empty())
+        .collect();
+    lines.join("\n")
+}
+
+// the first paragraph of a commit message, on one line
+pub fn subject(message: &str) -> String {
+    let title = title(message);
+    let lines: Vec<&str> = title.lines().map(|line| line.trim()).collect();
+    lines.join(" ")
+}
+
+// everything after the first paragraph of a commit message
+fn body(message: &str) -> String {
+    let title_lines = title(message).lines().count();
+    let mut rest = message.trim_start_matches('\n');
+    for _ in 0..title_lines {
+        rest = rest.split_once('\n').map_or("", |(_, rest)| rest);
+    }
+    // and the blank lines after it
+    while let Some((line, after)) = rest.split_once('\n') {
+        if !line.trim().is_empty() {
+            break;
+        }
+        rest = after;
+    }
+    rest.to_string()
+}
+
+// the first line of the message as something that can be a file name: only letters, digits,
+// '.' and '_', with a '-' for every run of anything else
+fn sanitized_subject(message: &str) -> String {
+    let line = message
+        .trim_start_matches('\n')
+        .lines()
+        .next()
+        .unwrap_or_default();
+    let mut sanitized = String::new();
+    let mut space = false;
+    let mut previous = None;
+    for c in line.chars() {
+        if c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+            if space && !sanitized.is_empty() {
+                sanitized.push('-');
+            }
+            space = false;
+            // runs of dots become one
+            if !(c == '.' && previous == Some('.')) {
+                sanitized.push(c);
+            }
+        } else {
+            space = true;
+        }
+        previous = Some(c);
+    }
+    sanitized.trim_end_matches(['.', '-']).to_string()
+}
+
+// how long ago `time` was, as "5 minutes ago" or "2 years, 3 months ago"
+fn relative_date(time: i64, now: i64) -> String {
+    let plural = |n: i64, unit: &str| match n {
+        1 => format!("1 {}", unit),
+        _ => format!("{} {}s", n, unit),
+    };
+    if now < time {
+        return "in the future".to_string();
+    }
+
+    let seconds = now - time;
+    if seconds < 90 {
+        return format!("{} ago", plural(seconds, "second"));
+    }
+    let minutes = (seconds + 30) / 60;
+    if minutes < 90 {
+        return format!("{} ago", plural(minutes, "minute"));
+    }
+    let hours = (minutes + 30) / 60;
+    if hours < 36 {
+        return format!("{} ago", plural(hours, "hour"));
+    }
+    let days = (hours + 12) / 24;
+    if days < 14 {
+        return format!("{} ago", plural(days, "day"));
+    }
+    if days < 70 {
+        return format!("{} ago", plural((days + 3) / 7, "week"));
+    }
+    if days < 365 {
+        return format!("{} ago", plural((days + 15) / 30, "month"));
+    }
+    if days < 1825 {
+        let total_months = (days * 12 * 2 + 365) / (365 * 2);
+        let (years, months) = (total_months / 12, total_months % 12);
+        return match months {
+            0 => format!("{} ago", plural(years, "year")),
+            _ => format!("{}, {} ago", plural(years, "year"), plural(months, "month")),
+        };
+    }
+    format!("{} ago", plural((days + 183) / 365, "year"))
+}
+
+// the refs pointing at each commit, as "log --decorate" shows them: HEAD first, with the
+// branch it's on, then the others in reverse order of their names
+pub fn decorations(repository: &Repository) -> HashMap<String, Vec<Decoration>> {
+    let head_branch = repository.head_target();
+    let mut decorations: HashMap<String, Vec<Decoration>> = HashMap::new();
+    let mut head_branches = vec![];
+    for (name, id) in repository.references() {
+        let decoration = if let Some(branch) = name.strip_prefix("refs/heads/") {
+            Decoration::Branch(branch.to_string())
+        } else if let Some(tag) = name.strip_prefix("refs/tags/") {
+            Decoration::Tag(tag.to_string())
+        } else if let Some(remote) = name.strip_prefix("refs/remotes/") {
+            Decoration::Remote(remote.to_string())
+        } else if name == "refs/stash" {
+            Decoration::Stash
+        } else {
+            continue;
+        };
+        let Ok(commit) = repository.peel(&id, GitObjectType::Commit) else {
+            continue;
+        };
+
+        // the branch HEAD is on is shown with it instead, when it's a commit HEAD points at
+        if head_branch.as_deref() == Some(name.as_str()) {
+            if let Decoration::Branch(branch) = decoration {
+                head_branches.push((commit.id, branch));
+            }
+            continue;
+        }
+        decorations
+            .entry(commit.id)
+            .or_default()
+            .insert(0, decoration);
+    }
+
+    if let Some(head) = repository.head() {
+        let branch = head_branches
+            .into_iter()
+            .find(|(id, _)| *id == head)
+            .map(|(_, branch)| branch);
+        decorations
+            .entry(head)
+            .or_default()
+            .insert(0, Decoration::Head(branch));
+    }
+    decorations
+}
@@ -0,0 +1,150 @@
+use std::{
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+// receives progress of long running operations such as clone and fetch, one stage at a time;
+// Send and Sync so that async operations reporting progress can move between threads
+pub trait Progress: Send + Sync {
+    // a new stage begins, with the number of items to process when it is known
+    fn start(&mut self, _title: &str, _total: Option<u64>) {}
+
+    // the number of items processed so far in the current stage
+    fn update(&mut self, _done: u64) {}
+
+    // the number of bytes transferred so far in the current stage
+    fn bytes(&mut self, _bytes: u64) {}
+
+    fn finish(&mut self) {}
+
+    // a line of the remote's own progress, sent over side-band 2
+    fn remote(&mut self, _line: &[u8]) {}
+}
+
+// discards all progress
+pub struct NoProgress;
+
+impl Progress for NoProgress {}
+
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+// git's progress meter: "Receiving objects:  45% (450/1000), 1.20 MiB | 512.00 KiB/s",
+// redrawn in place on stderr
+pub struct TerminalProgress {
+    title: String,
+    total: Option<u64>,
+    done: u64,
+    bytes: Option<u64>,
+    started: Instant,
+    drawn: Option<Instant>,
+}
+
+impl TerminalProgress {
+    pub fn new() -> TerminalProgress {
+        TerminalProgress {
+            title: String::new(),
+            total: None,
+            done: 0,
+            bytes: None,
+            started: Instant::now(),
+            drawn: None,
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut parts = vec![];
+        match self.total {
+            Some(total) if total > 0 => {
+                let percent = self.done * 100 / total;
+                parts.push(format!("{:3}% ({}/{})", percent, self.done, total));
+            }
+            Some(_) => parts.push("100% (0/0)".to_string()),
+            // a transfer of unknown size only counts bytes
+            None if self.bytes.is_some() => {}
+            None => parts.push(self.done.to_string()),
+        }
+        if let Some(bytes) = self.bytes {
+            let elapsed = self.started.elapsed().as_secs_f64().max(0.001);
+            parts.push(format!(
+                "{} | {}/s",
+                humanise_bytes(bytes),
+                humanise_bytes((bytes as f64 / elapsed) as u64)
+            ));
+        }
+
+        format!("{}: {}", self.title, parts.join(", "))
+    }
+
+    fn draw(&mut self, force: bool) {
+        if self.title.is_empty() {
+            return;
+        }
+        let now = Instant::now();
+        if !force && matches!(self.drawn, Some(drawn) if now - drawn < REDRAW_INTERVAL) {
+            return;
+        }
+        self.drawn = Some(now);
+        // clear anything left over from a longer line
+        let _ = write!(io::stderr(), "{}\x1b[K\r", self.render());
+    }
+}
+
+impl Default for TerminalProgress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Progress for TerminalProgress {
+    fn start(&mut self, title: &str, total: Option<u64>) {
+        self.finish();
+        self.title = title.to_string();
+        self.total = total;
+        self.done = 0;
+        self.bytes = None;
+        self.started = Instant::now();
+        self.drawn = None;
+        // until there is something to count, there is nothing to show
+        if total.is_some() {
+            self.draw(true);
+        }
+    }
+
+    fn update(&mut self, done: u64) {
+        self.done = done;
+        self.draw(false);
+    }
+
+    fn bytes(&mut self, bytes: u64) {
+        self.bytes = Some(bytes);
+        self.draw(false);
+    }
+
+    fn finish(&mut self) {
+        if self.title.is_empty() {
+            return;
+        }
+        let _ = writeln!(io::stderr(), "{}, done.\x1b[K", self.render());
+        self.title.clear();
+    }
+
+    fn remote(&mut self, line: &[u8]) {
+        let mut stderr = io::stderr();
+        let _ = stderr.write_all(b"remote: ");
+        let _ = stderr.write_all(line);
+        let _ = stderr.flush();
+    }
+}
+
+fn humanise_bytes(bytes: u64) -> String {
+    const KIB: u64 = 1 << 10;
+    const MIB: u64 = 1 << 20;
+    const GIB: u64 = 1 << 30;
+    let scaled = |unit: u64| format!("{}.{:02}", bytes / unit, (bytes % unit) * 100 / unit);
+    match bytes {
+        bytes if bytes >= GIB => format!("{} GiB", scaled(GIB)),
+        bytes if bytes >= MIB => format!("{} MiB", scaled(MIB)),
+        bytes if bytes >= KIB => format!("{} KiB", scaled(KIB)),
+        bytes => format!("{} bytes", bytes),
+    }
+}
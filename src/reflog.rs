@@ -0,0 +1,252 @@
+use std::{
+    collections::HashSet,
+    io::{self, Write},
+};
+
+use crate::{
+    config::Config,
+    diff,
+    error::{Error, Result},
+    objects::GitObjectType,
+    repository::{self, Repository},
+    wildmatch::wildmatch,
+    worktree,
+};
+
+const DEFAULT_EXPIRE: &str = "90.days.ago";
+const DEFAULT_EXPIRE_UNREACHABLE: &str = "30.days.ago";
+
+// how old the entries of a reflog get before they go, and how old the ones that the tip of the
+// ref can't reach; 0 is never
+#[derive(Clone, Copy, Debug)]
+pub struct Expiry {
+    pub total: i64,
+    pub unreachable: i64,
+}
+
+impl Expiry {
+    // the expiry for the reflog of `name`: the one given, or else the one set by
+    // gc.<pattern>.reflogExpire and gc.<pattern>.reflogExpireUnreachable for the first pattern
+    // matching the name, or else gc.reflogExpire and gc.reflogExpireUnreachable. The stash
+    // never expires unless a pattern says otherwise
+    pub fn for_reflog(
+        config: &Config,
+        name: &str,
+        total: Option<i64>,
+        unreachable: Option<i64>,
+    ) -> Result<Expiry> {
+        let mut patterns: Vec<(&str, Expiry)> = vec![];
+        for (key, value) in config.entries() {
+            let Some(key) = key.strip_prefix("gc.") else {
+                continue;
+            };
+            let Some((pattern, setting)) = key.rsplit_once('.') else {
+                continue;
+            };
+            if setting != "reflogexpire" && setting != "reflogexpireunreachable" {
+                continue;
+            }
+            let date = expiry_date(value.unwrap_or("true"))?;
+            let index = match patterns.iter().position(|(other, _)| *other == pattern) {
+                Some(index) => index,
+                None => {
+                    // a pattern not given one of the two never expires by it
+                    let never = Expiry {
+                        total: 0,
+                        unreachable: 0,
+                    };
+                    patterns.push((pattern, never));
+                    patterns.len() - 1
+                }
+            };
+            match setting {
+                "reflogexpire" => patterns[index].1.total = date,
+                _ => patterns[index].1.unreachable = date,
+            }
+        }
+
+        let configured = patterns
+            .iter()
+            .find(|(pattern, _)| wildmatch(pattern.as_bytes(), name.as_bytes(), false))
+            .map(|(_, expiry)| *expiry);
+        let expiry = match configured {
+            Some(expiry) => expiry,
+            None if name == "refs/stash" => Expiry {
+                total: 0,
+                unreachable: 0,
+            },
+            None => Expiry {
+                total: expiry_date(config.get("gc.reflogExpire").unwrap_or(DEFAULT_EXPIRE))?,
+                unreachable: expiry_date(
+                    config
+                        .get("gc.reflogExpireUnreachable")
+                        .unwrap_or(DEFAULT_EXPIRE_UNREACHABLE),
+                )?,
+            },
+        };
+        Ok(Expiry {
+            total: total.unwrap_or(expiry.total),
+            unreachable: unreachable.unwrap_or(expiry.unreachable),
+        })
+    }
+}
+
+fn expiry_date(date: &str) -> Result<i64> {
+    repository::parse_expiry_date(date).ok_or_else(|| Error::InvalidDate(date.to_string()))
+}
+
+// what expiring does besides dropping entries
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Options {
+    // each entry kept moves the ref from where the one kept before it moved it to
+    pub rewrite: bool,
+    // the ref is pointed where the newest entry kept moved it, unless it is symbolic
+    pub update_ref: bool,
+    // entries with commits that are gone, or whose tree is, go too
+    pub stale_fix: bool,
+    pub dry_run: bool,
+    // each entry is reported, kept or pruned
+    pub verbose: bool,
+}
+
+// drops the entries of the reflog of `name` older than `expiry.total`, the ones older than
+// `expiry.unreachable` whose commits the ref can't reach, which for HEAD is any ref, and the
+// entry at `drop`, counting from the oldest
+pub fn expire(
+    repository: &Repository,
+    name: &str,
+    expiry: Expiry,
+    drop: Option<usize>,
+    options: Options,
+) -> Result<()> {
+    let commit = |id: &str| match id == diff::NULL_ID {
+        true => None,
+        false => repository.peel(id, GitObjectType::Commit).ok(),
+    };
+    // what the entries old enough to go when unreachable are checked against; with no tip,
+    // they go whatever they are
+    let tips: Option<Vec<String>> = if expiry.unreachable <= expiry.total {
+        None
+    } else if name == "HEAD" {
+        Some(
+            repository
+                .references()
+                .filter_map(|(_, id)| commit(&id))
+                .map(|commit| commit.id)
+                .collect(),
+        )
+    } else {
+        repository
+            .find_reference(name)
+            .and_then(|id| repository.find_commit(&id).ok())
+            .map(|commit| vec![commit.id])
+    };
+    let mut reachable: Option<HashSet<String>> = None;
+    let mut unreachable = |id: &str| -> bool {
+        let Some(tips) = &tips else {
+            return true;
+        };
+        // an object that isn't a commit is kept
+        let Some(commit) = commit(id) else {
+            return false;
+        };
+        !reachable
+            .get_or_insert_with(|| {
+                tips.iter()
+                    .flat_map(|tip| repository.ancestors(tip))
+                    .collect()
+            })
+            .contains(&commit.id)
+    };
+    let is_complete = |id: &str| {
+        id == diff::NULL_ID
+            || repository
+                .find_commit(id)
+                .is_ok_and(|commit| repository.has_object(&commit.tree))
+    };
+
+    let mut out = io::stdout().lock();
+    let mut kept = vec![];
+    let mut last_kept = diff::NULL_ID.to_string();
+    for (i, mut entry) in repository.reflog(name).into_iter().enumerate() {
+        if options.rewrite {
+            entry.old = last_kept.clone();
+        }
+        let time = entry.committer.time;
+        let prune = time < expiry.total
+            || (options.stale_fix && !(is_complete(&entry.old) && is_complete(&entry.new)))
+            || (time < expiry.unreachable && (unreachable(&entry.old) || unreachable(&entry.new)))
+            || drop == Some(i);
+        match (prune, options.verbose) {
+            (true, true) if options.dry_run => writeln!(out, "would prune {}", entry.message)?,
+            (true, true) => writeln!(out, "prune {}", entry.message)?,
+            (false, true) => writeln!(out, "keep {}", entry.message)?,
+            (_, false) => {}
+        }
+        if !prune {
+            last_kept = entry.new.clone();
+            kept.push(entry);
+        }
+    }
+    if options.dry_run {
+        return Ok(());
+    }
+    repository.write_reflog(name, &kept)?;
+    if options.update_ref
+        && last_kept != diff::NULL_ID
+        && repository.symbolic_reference(name).is_none()
+    {
+        repository.set_reference(name, &last_kept)?;
+    }
+    Ok(())
+}
+
+// expires every reflog, each by the expiry set for it unless one is given, in every work tree
+// unless `single_worktree`
+pub fn expire_all(
+    repository: &Repository,
+    total: Option<i64>,
+    unreachable: Option<i64>,
+    options: Options,
+    single_worktree: bool,
+) -> Result<()> {
+    let config = Config::for_repository(repository.common_dir());
+    for name in repository.reflog_names() {
+        let expiry = Expiry::for_reflog(&config, &name, total, unreachable)?;
+        expire(repository, &name, expiry, None, options)?;
+    }
+    if single_worktree {
+        return Ok(());
+    }
+    // the other work trees have a HEAD of their own
+    for worktree in worktree::list(repository) {
+        if worktree.git_dir == repository.git_dir() {
+            continue;
+        }
+        let Ok(other) = Repository::open_with(&worktree.git_dir, Some(&worktree.path)) else {
+            continue;
+        };
+        if other.reflog_names().iter().any(|name| name == "HEAD") {
+            let expiry = Expiry::for_reflog(&config, "HEAD", total, unreachable)?;
+            expire(&other, "HEAD", expiry, None, options)?;
+        }
+    }
+    Ok(())
+}
+
+// the entry of a reflog `selector` picks, counting from the oldest: "<n>" for the n-th newest,
+// starting from 0, or a date for the newest one before it
+pub fn selected_entry(repository: &Repository, name: &str, selector: &str) -> Option<usize> {
+    let entries = repository.reflog(name);
+    let count = match selector.parse::<usize>() {
+        Ok(n) => entries.len().checked_sub(n)?,
+        Err(_) => {
+            let date = repository::parse_expiry_date(selector).unwrap_or(0);
+            entries
+                .iter()
+                .filter(|entry| entry.committer.time < date)
+                .count()
+        }
+    };
+    count.checked_sub(1)
+}
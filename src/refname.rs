@@ -0,0 +1,63 @@
+// what a ref name can be besides what every ref name has to be
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Format {
+    // a name with no "/", such as "HEAD"
+    pub allow_onelevel: bool,
+    // a name with a "*" in it, as one side of a refspec is, but only one
+    pub refspec_pattern: bool,
+}
+
+// whether `name` can name a ref, as git has it: it is made of components split by "/", none of
+// them empty, starting with "." or ending with ".lock", it has no "..", no "@{", no control
+// characters, spaces or any of "~^:?[\*", it doesn't end with "." and isn't "@"
+pub fn is_valid(name: &str, format: Format) -> bool {
+    if name == "@" {
+        return false;
+    }
+    let mut pattern = format.refspec_pattern;
+    let mut components = 0;
+    for component in name.split('/') {
+        if !is_valid_component(component, &mut pattern) {
+            return false;
+        }
+        components += 1;
+    }
+    !name.ends_with('.') && (format.allow_onelevel || components > 1)
+}
+
+fn is_valid_component(component: &str, pattern: &mut bool) -> bool {
+    let mut last = 0;
+    for &b in component.as_bytes() {
+        match b {
+            b'.' if last == b'.' => return false,
+            b'{' if last == b'@' => return false,
+            b'*' if *pattern => *pattern = false,
+            0..=0x1f | 0x7f | b' ' | b'~' | b'^' | b':' | b'?' | b'[' | b'\\' | b'*' => {
+                return false
+            }
+            _ => {}
+        }
+        last = b;
+    }
+    !component.is_empty() && !component.starts_with('.') && !component.ends_with(".lock")
+}
+
+// whether `name` can name a branch, which it can't when it looks like an option or is "HEAD"
+pub fn is_valid_branch(name: &str) -> bool {
+    !name.starts_with('-')
+        && name != "HEAD"
+        && is_valid(&format!("refs/heads/{}", name), Format::default())
+}
+
+// `name` without a "/" at the start or right after another
+pub fn collapse_slashes(name: &str) -> String {
+    let mut collapsed = String::new();
+    let mut last = '/';
+    for c in name.chars() {
+        if c != '/' || last != '/' {
+            collapsed.push(c);
+        }
+        last = c;
+    }
+    collapsed
+}
@@ -0,0 +1,358 @@
+// POSIX extended regular expressions, as git compiles them for -G and --pickaxe-regex: text is
+// matched as lines, so "^" and "$" also match next to a newline and "." never matches one
+pub struct Regex {
+    node: Node,
+}
+
+enum Node {
+    Byte(u8),
+    Set(Box<[bool; 256]>),
+    LineStart,
+    LineEnd,
+    TextStart,
+    TextEnd,
+    // \b and \B
+    WordBoundary(bool),
+    // \< and \>
+    WordStart,
+    WordEnd,
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: usize,
+        max: Option<usize>,
+    },
+}
+
+// the messages glibc's regcomp gives
+const BAD_REPEAT: &str = "Invalid preceding regular expression";
+const BAD_INTERVAL: &str = "Invalid content of \\{\\}";
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, String> {
+        let mut parser = Parser {
+            pattern: pattern.as_bytes(),
+            pos: 0,
+        };
+        let node = parser.alternate(0)?;
+
+        Ok(Regex { node })
+    }
+
+    pub fn is_match(&self, text: &[u8]) -> bool {
+        (0..=text.len()).any(|start| walk(&self.node, text, start, &mut |_| true))
+    }
+
+    // where the leftmost match at or after `start` begins and ends, taking the longest one
+    // there as POSIX does
+    pub fn find_at(&self, text: &[u8], start: usize) -> Option<(usize, usize)> {
+        (start..=text.len()).find_map(|start| {
+            let mut end = None;
+            walk(&self.node, text, start, &mut |pos| {
+                end = end.max(Some(pos));
+                false
+            });
+            end.map(|end| (start, end))
+        })
+    }
+}
+
+struct Parser<'a> {
+    pattern: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.pattern.get(self.pos).copied()
+    }
+
+    // branches separated by "|", up to the ")" that closes a group `depth` deep
+    fn alternate(&mut self, depth: usize) -> Result<Node, String> {
+        let mut branches = vec![self.concat(depth)?];
+        while self.peek() == Some(b'|') {
+            self.pos += 1;
+            branches.push(self.concat(depth)?);
+        }
+
+        Ok(match branches.len() {
+            1 => branches.remove(0),
+            _ => Node::Alternate(branches),
+        })
+    }
+
+    fn concat(&mut self, depth: usize) -> Result<Node, String> {
+        let mut nodes = vec![];
+        while let Some(c) = self.peek() {
+            if c == b'|' || (c == b')' && depth > 0) {
+                break;
+            }
+            let mut node = self.atom(depth)?;
+            while let Some((min, max)) = self.repeat()? {
+                node = Node::Repeat {
+                    node: Box::new(node),
+                    min,
+                    max,
+                };
+            }
+            nodes.push(node);
+        }
+
+        Ok(Node::Concat(nodes))
+    }
+
+    fn atom(&mut self, depth: usize) -> Result<Node, String> {
+        let c = self.pattern[self.pos];
+        self.pos += 1;
+        Ok(match c {
+            b'(' => {
+                let node = self.alternate(depth + 1)?;
+                if self.peek() != Some(b')') {
+                    return Err("Unmatched ( or \\(".to_string());
+                }
+                self.pos += 1;
+                node
+            }
+            b'*' | b'+' | b'?' | b'{' => return Err(BAD_REPEAT.to_string()),
+            b'^' => Node::LineStart,
+            b'$' => Node::LineEnd,
+            b'.' => {
+                let mut set = Box::new([true; 256]);
+                set[b'\n' as usize] = false;
+                Node::Set(set)
+            }
+            b'[' => self.bracket()?,
+            b'\\' => {
+                let Some(c) = self.peek() else {
+                    return Err("Trailing backslash".to_string());
+                };
+                self.pos += 1;
+                match c {
+                    b'w' | b'W' | b's' | b'S' => {
+                        let class = if c.eq_ignore_ascii_case(&b'w') {
+                            is_word
+                        } else {
+                            u8::is_ascii_whitespace
+                        };
+                        let mut set = Box::new([false; 256]);
+                        for byte in 0..=255u8 {
+                            set[byte as usize] = class(&byte) != c.is_ascii_uppercase();
+                        }
+                        Node::Set(set)
+                    }
+                    b'b' => Node::WordBoundary(true),
+                    b'B' => Node::WordBoundary(false),
+                    b'<' => Node::WordStart,
+                    b'>' => Node::WordEnd,
+                    b'`' => Node::TextStart,
+                    b'\'' => Node::TextEnd,
+                    b'1'..=b'9' => return Err("Invalid back reference".to_string()),
+                    c => Node::Byte(c),
+                }
+            }
+            c => Node::Byte(c),
+        })
+    }
+
+    // a "*", "+", "?" or "{m,n}" after an atom, as the least and most times it's repeated
+    fn repeat(&mut self) -> Result<Option<(usize, Option<usize>)>, String> {
+        let bounds = match self.peek() {
+            Some(b'*') => (0, None),
+            Some(b'+') => (1, None),
+            Some(b'?') => (0, Some(1)),
+            Some(b'{') => {
+                self.pos += 1;
+                let min = self.number().ok_or(BAD_INTERVAL)?;
+                let max = match self.peek() {
+                    Some(b',') => {
+                        self.pos += 1;
+                        self.number()
+                    }
+                    _ => Some(min),
+                };
+                match self.peek() {
+                    Some(b'}') => {}
+                    Some(_) => return Err(BAD_INTERVAL.to_string()),
+                    None => return Err("Unmatched \\{".to_string()),
+                }
+                if max.is_some_and(|max| max < min) {
+                    return Err(BAD_INTERVAL.to_string());
+                }
+                (min, max)
+            }
+            _ => return Ok(None),
+        };
+        self.pos += 1;
+
+        Ok(Some(bounds))
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.pattern[start..self.pos])
+            .ok()?
+            .parse()
+            .ok()
+    }
+
+    // a bracket expression after its "[", with ranges, classes like "[:alpha:]" and "]" taken
+    // as itself when it comes first; a negated one never matches a newline
+    fn bracket(&mut self) -> Result<Node, String> {
+        const UNMATCHED: &str = "Unmatched [, [^, [:, [., or [=";
+        let negated = self.peek() == Some(b'^');
+        if negated {
+            self.pos += 1;
+        }
+
+        let mut set = Box::new([false; 256]);
+        let mut first = true;
+        loop {
+            let c = self.peek().ok_or(UNMATCHED)?;
+            self.pos += 1;
+            if c == b']' && !first {
+                break;
+            }
+            first = false;
+
+            let start = match (c, self.peek()) {
+                (b'[', Some(b':')) => {
+                    let name = self.bracket_item(b':').ok_or(UNMATCHED)?;
+                    let class = class(&name).ok_or("Invalid character class name")?;
+                    for byte in 0..=255u8 {
+                        set[byte as usize] |= class(&byte);
+                    }
+                    continue;
+                }
+                (b'[', Some(delimiter @ (b'=' | b'.'))) => {
+                    match self.bracket_item(delimiter).ok_or(UNMATCHED)?[..] {
+                        [c] => c,
+                        _ => return Err("Invalid collation character".to_string()),
+                    }
+                }
+                (c, _) => c,
+            };
+            let end = match (self.peek(), self.pattern.get(self.pos + 1)) {
+                (Some(b'-'), Some(end)) if *end != b']' => {
+                    self.pos += 2;
+                    *end
+                }
+                _ => start,
+            };
+            if end < start {
+                return Err("Invalid range end".to_string());
+            }
+            for byte in start..=end {
+                set[byte as usize] = true;
+            }
+        }
+
+        if negated {
+            for byte in set.iter_mut() {
+                *byte = !*byte;
+            }
+            set[b'\n' as usize] = false;
+        }
+        Ok(Node::Set(set))
+    }
+
+    // the name in "[:name:]", "[=c=]" or "[.c.]", after its "["
+    fn bracket_item(&mut self, delimiter: u8) -> Option<Vec<u8>> {
+        let start = self.pos + 1;
+        let length = self.pattern[start..]
+            .windows(2)
+            .position(|pair| pair == [delimiter, b']'])?;
+        self.pos = start + length + 2;
+        Some(self.pattern[start..start + length].to_vec())
+    }
+}
+
+fn class(name: &[u8]) -> Option<fn(&u8) -> bool> {
+    Some(match name {
+        b"alpha" => u8::is_ascii_alphabetic,
+        b"digit" => u8::is_ascii_digit,
+        b"alnum" => u8::is_ascii_alphanumeric,
+        b"upper" => u8::is_ascii_uppercase,
+        b"lower" => u8::is_ascii_lowercase,
+        b"space" => |c| c.is_ascii_whitespace() || *c == b'\x0b',
+        b"blank" => |c| *c == b' ' || *c == b'\t',
+        b"punct" => u8::is_ascii_punctuation,
+        b"print" => |c| c.is_ascii_graphic() || *c == b' ',
+        b"graph" => u8::is_ascii_graphic,
+        b"cntrl" => u8::is_ascii_control,
+        b"xdigit" => u8::is_ascii_hexdigit,
+        _ => return None,
+    })
+}
+
+fn is_word(c: &u8) -> bool {
+    c.is_ascii_alphanumeric() || *c == b'_'
+}
+
+// tries `node` at `pos`, handing each place a match of it could end to `next` until that
+// accepts one
+fn walk(node: &Node, text: &[u8], pos: usize, next: &mut dyn FnMut(usize) -> bool) -> bool {
+    let word_before = pos > 0 && is_word(&text[pos - 1]);
+    let word_after = text.get(pos).is_some_and(is_word);
+    match node {
+        Node::Byte(c) => text.get(pos) == Some(c) && next(pos + 1),
+        Node::Set(set) => text.get(pos).is_some_and(|c| set[*c as usize]) && next(pos + 1),
+        Node::LineStart => (pos == 0 || text[pos - 1] == b'\n') && next(pos),
+        Node::LineEnd => (pos == text.len() || text[pos] == b'\n') && next(pos),
+        Node::TextStart => pos == 0 && next(pos),
+        Node::TextEnd => pos == text.len() && next(pos),
+        Node::WordBoundary(boundary) => (word_before != word_after) == *boundary && next(pos),
+        Node::WordStart => !word_before && word_after && next(pos),
+        Node::WordEnd => word_before && !word_after && next(pos),
+        Node::Concat(nodes) => walk_concat(nodes, text, pos, next),
+        Node::Alternate(branches) => branches.iter().any(|branch| walk(branch, text, pos, next)),
+        Node::Repeat { node, min, max } => walk_repeat(node, *min, *max, text, pos, next),
+    }
+}
+
+fn walk_concat(
+    nodes: &[Node],
+    text: &[u8],
+    pos: usize,
+    next: &mut dyn FnMut(usize) -> bool,
+) -> bool {
+    match nodes.split_first() {
+        None => next(pos),
+        Some((node, rest)) => walk(node, text, pos, &mut |pos| {
+            walk_concat(rest, text, pos, next)
+        }),
+    }
+}
+
+// as many more times as possible first; a repeat that matches nothing once it has all it needs
+// is left out, so it can't go on forever
+fn walk_repeat(
+    node: &Node,
+    min: usize,
+    max: Option<usize>,
+    text: &[u8],
+    pos: usize,
+    next: &mut dyn FnMut(usize) -> bool,
+) -> bool {
+    if max != Some(0) {
+        let more = walk(node, text, pos, &mut |end| {
+            (end != pos || min > 0)
+                && walk_repeat(
+                    node,
+                    min.saturating_sub(1),
+                    max.map(|max| max - 1),
+                    text,
+                    end,
+                    next,
+                )
+        });
+        if more {
+            return true;
+        }
+    }
+
+    min == 0 && next(pos)
+}
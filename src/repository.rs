@@ -0,0 +1,1994 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap, HashMap, HashSet, VecDeque},
+    env, fmt, fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use crate::{
+    config::{self, Config},
+    diff::{self, Change},
+    error::{Error, Result},
+    index::{Index, IndexEntry, SplitIndex},
+    objects::{self, GitObjectType, TreeEntry},
+    pack, paths,
+    pathspec::{Pathspec, Pathspecs},
+    pickaxe::Pickaxe,
+    refname,
+};
+
+// the tree with nothing in it, which every repository has whether it's stored or not
+pub const EMPTY_TREE: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+const MAX_SYMREF_DEPTH: usize = 5;
+// how many replacements of a replacement are followed
+const MAX_REPLACE_DEPTH: usize = 5;
+// the fewest digits an abbreviated object id can have
+const MIN_ABBREV: usize = 4;
+// how alike, in percent, a file has to be to one that appeared to be where it was renamed or
+// copied from
+const RENAME_SCORE: u32 = 50;
+
+pub struct Repository {
+    git_dir: PathBuf,
+    // where objects, refs and config live; differs from git_dir in linked worktrees
+    common_dir: PathBuf,
+    work_tree: Option<PathBuf>,
+    // objects are looked up as what refs/replace/<id> points at, unless GIT_NO_REPLACE_OBJECTS
+    // or core.useReplaceRefs turns that off; the refs are read the first time it's needed
+    replace_objects: bool,
+    replacements: OnceLock<HashMap<String, String>>,
+    // the commits of a shallow repository whose parents it doesn't have, which are taken to
+    // have none
+    shallow: OnceLock<HashSet<String>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Object {
+    pub id: String,
+    pub object_type: GitObjectType,
+    pub data: Vec<u8>,
+}
+
+// a line of a reflog: where the ref moved from and to, who moved it when, and why
+#[derive(Clone, Debug)]
+pub struct ReflogEntry {
+    pub old: String,
+    pub new: String,
+    pub committer: Signature,
+    pub message: String,
+}
+
+impl fmt::Display for ReflogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} {} {}\t{}",
+            self.old, self.new, self.committer, self.message
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Commit {
+    pub id: String,
+    pub tree: String,
+    pub parents: Vec<String>,
+    pub author: Signature,
+    pub committer: Signature,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Signature {
+    pub name: String,
+    pub email: String,
+    // seconds since the epoch and the author's UTC offset in minutes
+    pub time: i64,
+    pub offset: i32,
+}
+
+impl Signature {
+    pub fn new(name: &str, email: &str, time: i64, offset: i32) -> Signature {
+        Signature {
+            name: name.to_string(),
+            email: email.to_string(),
+            time,
+            offset,
+        }
+    }
+
+    pub fn now(name: &str, email: &str) -> Signature {
+        let now = chrono::Local::now();
+        Signature::new(
+            name,
+            email,
+            now.timestamp(),
+            now.offset().local_minus_utc() / 60,
+        )
+    }
+
+    // "<name> <<email>> <seconds> <offset>" as in commit and tag headers
+    pub fn parse(value: &str) -> Option<Signature> {
+        let (name, rest) = value.split_once(" <")?;
+        let (email, date) = rest.split_once("> ")?;
+        let (time, offset) = date.split_once(' ')?;
+        Some(Signature::new(
+            name,
+            email,
+            time.parse().ok()?,
+            parse_offset(offset)?,
+        ))
+    }
+
+    // the time in the signer's own offset, e.g. "Fri Oct 16 01:37:06 2026 +0000"
+    pub fn date(&self) -> String {
+        self.format_date("%a %b %-d %H:%M:%S %Y %z")
+    }
+
+    // the time in the signer's own offset, in a chrono format
+    pub fn format_date(&self, format: &str) -> String {
+        let offset = chrono::FixedOffset::east_opt(self.offset * 60)
+            .unwrap_or_else(|| chrono::FixedOffset::east_opt(0).unwrap());
+        match chrono::DateTime::from_timestamp(self.time, 0) {
+            Some(time) => time.with_timezone(&offset).format(format).to_string(),
+            None => String::new(),
+        }
+    }
+}
+
+// the content of a commit object
+pub fn commit_content(
+    tree: &str,
+    parents: &[&str],
+    author: &Signature,
+    committer: &Signature,
+    message: &str,
+) -> String {
+    let mut content = format!("tree {}\n", tree);
+    for parent in parents {
+        content.push_str(&format!("parent {}\n", parent));
+    }
+    content.push_str(&format!("author {}\n", author));
+    content.push_str(&format!("committer {}\n\n", committer));
+    content.push_str(message);
+    if !message.ends_with('\n') {
+        content.push('\n');
+    }
+    content
+}
+
+// the content of an annotated tag object, pointing at `object` of type `object_type`
+pub fn tag_content(
+    object: &str,
+    object_type: GitObjectType,
+    name: &str,
+    tagger: &Signature,
+    message: &str,
+) -> String {
+    let mut content = format!(
+        "object {}\ntype {}\ntag {}\ntagger {}\n\n{}",
+        object, object_type, name, tagger, message
+    );
+    if !message.is_empty() && !message.ends_with('\n') {
+        content.push('\n');
+    }
+    content
+}
+
+// `commits`, newest first, reordered so that no parent comes before any of its children and
+// each line of history is shown in one go, as for "log --graph"
+pub fn topo_order(commits: Vec<String>, parents: &HashMap<String, Vec<String>>) -> Vec<String> {
+    // one more than the number of children in `commits` that still have to come first
+    let mut indegree: HashMap<String, usize> = commits.iter().map(|id| (id.clone(), 1)).collect();
+    for parent in commits.iter().flat_map(|id| &parents[id]) {
+        if let Some(count) = indegree.get_mut(parent) {
+            *count += 1;
+        }
+    }
+
+    // a stack, so the last parent's history comes out before the first parent's; the tips go
+    // in reversed so that they come out in the order they were given
+    let mut stack: Vec<String> = commits
+        .iter()
+        .rev()
+        .filter(|id| indegree[*id] == 1)
+        .cloned()
+        .collect();
+    let mut sorted = vec![];
+    while let Some(id) = stack.pop() {
+        for parent in &parents[&id] {
+            if let Some(count) = indegree.get_mut(parent) {
+                *count -= 1;
+                if *count == 1 {
+                    stack.push(parent.clone());
+                }
+            }
+        }
+        sorted.push(id);
+    }
+
+    sorted
+}
+
+// how much of two files' content is the same, as a percentage of the larger one, by lines
+fn similarity(a: &[u8], b: &[u8]) -> u32 {
+    let lines = |content: &[u8]| -> Vec<Vec<u8>> {
+        content
+            .split_inclusive(|b| *b == b'\n')
+            .map(|line| line.to_vec())
+            .collect()
+    };
+    let (a_lines, b_lines) = (lines(a), lines(b));
+    let common: usize = diff::matching_lines(&a_lines, &b_lines)
+        .iter()
+        .map(|(i, _)| a_lines[*i].len())
+        .sum();
+    match a.len().max(b.len()) {
+        0 => 100,
+        size => (common * 100 / size) as u32,
+    }
+}
+
+// a date as git takes it in GIT_AUTHOR_DATE and GIT_COMMITTER_DATE: "<seconds> <offset>",
+// "@<seconds>", RFC 2822 or ISO 8601, which is in local time when it has no offset
+pub fn parse_date(date: &str) -> Option<(i64, i32)> {
+    let date = date.trim();
+    let raw = date.strip_prefix('@').unwrap_or(date);
+    let (seconds, offset) = raw.split_once(' ').unwrap_or((raw, "+0000"));
+    if let (Ok(seconds), Some(offset)) = (seconds.parse::<i64>(), parse_offset(offset)) {
+        return Some((seconds, offset));
+    }
+
+    let with_offset = |date: chrono::DateTime<chrono::FixedOffset>| {
+        (date.timestamp(), date.offset().local_minus_utc() / 60)
+    };
+    if let Ok(date) = chrono::DateTime::parse_from_rfc2822(date) {
+        return Some(with_offset(date));
+    }
+    for format in [
+        "%Y-%m-%dT%H:%M:%S%#z",
+        "%Y-%m-%d %H:%M:%S %#z",
+        "%Y-%m-%d %H:%M:%S%#z",
+    ] {
+        if let Ok(date) = chrono::DateTime::parse_from_str(date, format) {
+            return Some(with_offset(date));
+        }
+    }
+    for format in ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S"] {
+        if let Ok(date) = chrono::NaiveDateTime::parse_from_str(date, format) {
+            let date = date.and_local_timezone(chrono::Local).earliest()?;
+            return Some((date.timestamp(), date.offset().local_minus_utc() / 60));
+        }
+    }
+
+    None
+}
+
+// the time before which something counts as expired, as gc --prune and prune --expire take
+// it: "never" expires nothing and "now" everything, and otherwise it is a date or one like
+// "2.weeks.ago"
+pub fn parse_expiry_date(date: &str) -> Option<i64> {
+    match date {
+        "never" | "false" => return Some(0),
+        "now" | "all" => return Some(i64::MAX),
+        _ => {}
+    }
+    let now = chrono::Utc::now().timestamp();
+    let words: Vec<&str> = date
+        .split(['.', ' '])
+        .filter(|word| !word.is_empty())
+        .collect();
+    if let [count, unit, "ago"] = words[..] {
+        let count: i64 = count.parse().ok()?;
+        let seconds = match unit.strip_suffix('s').unwrap_or(unit) {
+            "second" => 1,
+            "minute" => 60,
+            "hour" => 60 * 60,
+            "day" => 24 * 60 * 60,
+            "week" => 7 * 24 * 60 * 60,
+            "month" => 30 * 24 * 60 * 60,
+            "year" => 365 * 24 * 60 * 60,
+            _ => return None,
+        };
+        return Some(now - count * seconds);
+    }
+    parse_date(date).map(|(seconds, _)| seconds)
+}
+
+// "+hhmm" or "-hhmm" as minutes east of UTC
+fn parse_offset(offset: &str) -> Option<i32> {
+    let (sign, digits) = match offset.split_at_checked(1)? {
+        ("+", digits) => (1, digits),
+        ("-", digits) => (-1, digits),
+        _ => return None,
+    };
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i32 = digits[..2].parse().ok()?;
+    let minutes: i32 = digits[2..].parse().ok()?;
+
+    Some(sign * (hours * 60 + minutes))
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.offset < 0 { '-' } else { '+' };
+        let offset = self.offset.abs();
+        write!(
+            f,
+            "{} <{}> {} {}{:02}{:02}",
+            self.name,
+            self.email,
+            self.time,
+            sign,
+            offset / 60,
+            offset % 60
+        )
+    }
+}
+
+impl Repository {
+    // creates a repository at `git_dir`, bare without a work tree; one that already exists keeps
+    // its HEAD and config
+    pub fn init(
+        git_dir: &Path,
+        work_tree: Option<&Path>,
+        initial_branch: &str,
+    ) -> Result<Repository> {
+        fs::create_dir_all(git_dir.join("objects"))?;
+        fs::create_dir_all(git_dir.join("refs").join("heads"))?;
+        fs::create_dir_all(git_dir.join("refs").join("tags"))?;
+        if !git_dir.join("HEAD").exists() {
+            fs::write(
+                git_dir.join("HEAD"),
+                format!("ref: refs/heads/{}\n", initial_branch),
+            )?;
+        }
+        if !git_dir.join("config").exists() {
+            let mut config = format!(
+                "[core]\n\trepositoryformatversion = 0\n\tfilemode = true\n\tbare = {}\n",
+                work_tree.is_none()
+            );
+            if work_tree.is_some() {
+                config.push_str("\tlogallrefupdates = true\n");
+            }
+            fs::write(git_dir.join("config"), config)?;
+        }
+
+        Ok(Repository::at(git_dir, work_tree))
+    }
+
+    // `path` is either a work tree containing `.git` (a directory or a gitfile) or a bare
+    // repository
+    pub fn open(path: &Path) -> Result<Repository> {
+        if let Some(git_dir) = dot_git(path) {
+            return Ok(Repository::at(&git_dir, Some(path)));
+        }
+        if is_git_dir(path) {
+            return Ok(Repository::at(path, None));
+        }
+
+        Err(Error::NotARepository(path.to_path_buf()))
+    }
+
+    // opens the repository containing `path`, looking in each parent directory in turn but
+    // not in any of GIT_CEILING_DIRECTORIES, or above them; only `path` itself can be one
+    pub fn discover(path: &Path) -> Result<Repository> {
+        let ceilings: Vec<PathBuf> = env::var_os("GIT_CEILING_DIRECTORIES")
+            .map(|dirs| env::split_paths(&dirs).collect())
+            .unwrap_or_default();
+        for dir in path.ancestors() {
+            if dir != path && ceilings.iter().any(|ceiling| ceiling == dir) {
+                break;
+            }
+            if let Ok(repository) = Repository::open(dir) {
+                return Ok(repository);
+            }
+        }
+
+        Err(Error::NotARepository(path.to_path_buf()))
+    }
+
+    pub fn open_with(git_dir: &Path, work_tree: Option<&Path>) -> Result<Repository> {
+        if !is_git_dir(git_dir) {
+            return Err(Error::NotARepository(git_dir.to_path_buf()));
+        }
+
+        Ok(Repository::at(git_dir, work_tree))
+    }
+
+    fn at(git_dir: &Path, work_tree: Option<&Path>) -> Repository {
+        let common_dir = match fs::read_to_string(git_dir.join("commondir")) {
+            Ok(common_dir) => git_dir.join(common_dir.trim()),
+            Err(_) => git_dir.to_path_buf(),
+        };
+
+        let config = Config::load(&common_dir);
+        if let Some(limit) = config
+            .get("core.deltaBaseCacheLimit")
+            .and_then(config::parse_size)
+        {
+            pack::set_delta_base_cache_limit(limit.try_into().unwrap_or(usize::MAX));
+        }
+        let replace_objects = env::var_os("GIT_NO_REPLACE_OBJECTS").is_none()
+            && config
+                .get("core.useReplaceRefs")
+                .and_then(config::parse_bool)
+                .unwrap_or(true);
+
+        Repository {
+            git_dir: git_dir.to_path_buf(),
+            common_dir,
+            work_tree: work_tree.map(|path| path.to_path_buf()),
+            replace_objects,
+            replacements: OnceLock::new(),
+            shallow: OnceLock::new(),
+        }
+    }
+
+    // looks objects up as they are stored, whatever replaces them
+    pub fn without_replacements(mut self) -> Repository {
+        self.replace_objects = false;
+        self
+    }
+
+    pub fn git_dir(&self) -> &Path {
+        &self.git_dir
+    }
+
+    pub fn common_dir(&self) -> &Path {
+        &self.common_dir
+    }
+
+    pub fn work_tree(&self) -> Option<&Path> {
+        self.work_tree.as_deref()
+    }
+
+    pub fn object_dir(&self) -> PathBuf {
+        self.common_dir.join("objects")
+    }
+
+    // GIT_INDEX_FILE lets scripts build trees in a scratch index
+    pub fn index_path(&self) -> PathBuf {
+        match env::var_os("GIT_INDEX_FILE") {
+            Some(path) => PathBuf::from(path),
+            None => self.git_dir.join("index"),
+        }
+    }
+
+    // core.splitIndex decides whether the index is written split, and it stays the way it was
+    // read when that isn't set
+    pub fn index(&self) -> Result<Index> {
+        let mut index = Index::read(&self.index_path())?;
+        let config = Config::for_repository(&self.common_dir);
+        let split = config
+            .get("core.splitIndex")
+            .and_then(config::parse_bool)
+            .unwrap_or(index.split_index().is_some());
+        if split {
+            index.set_split_index(Some(self.split_index(&config)?));
+        } else {
+            index.set_split_index(None);
+        }
+        Ok(index)
+    }
+
+    // splitIndex.maxPercentChange and splitIndex.sharedIndexExpire
+    pub fn split_index(&self, config: &Config) -> Result<SplitIndex> {
+        let mut split = SplitIndex::default();
+        if let Some(percent) = config
+            .get("splitIndex.maxPercentChange")
+            .and_then(|percent| percent.parse().ok())
+            .filter(|percent| *percent <= 100)
+        {
+            split.max_percent_change = percent;
+        }
+        if let Some(date) = config.get("splitIndex.sharedIndexExpire") {
+            split.shared_index_expire =
+                parse_expiry_date(date).ok_or_else(|| Error::InvalidDate(date.to_string()))?;
+        }
+        Ok(split)
+    }
+
+    // the object `id` names, with the content of its replacement if it has one, though under
+    // its own id
+    pub fn find_object(&self, id: &str) -> Result<Object> {
+        let object_dir = self.object_dir();
+        let replaced = self.replacement(id)?;
+        let target = replaced.as_deref().unwrap_or(id);
+        let (object_type, data) = objects::read_object(&object_dir, target).ok_or_else(|| {
+            // a loose object that can't be read is damaged rather than missing
+            match objects::find_loose_object(&object_dir, target) {
+                Some(_) => Error::CorruptObject(target.to_string()),
+                None => Error::ObjectNotFound(target.to_string()),
+            }
+        })?;
+
+        Ok(Object {
+            id: id.to_string(),
+            object_type,
+            data,
+        })
+    }
+
+    // what replaces `id`, following replacements of replacements
+    fn replacement(&self, id: &str) -> Result<Option<String>> {
+        if !self.replace_objects {
+            return Ok(None);
+        }
+        let replacements = self.replacements.get_or_init(|| {
+            self.references()
+                .filter_map(|(name, id)| {
+                    Some((name.strip_prefix("refs/replace/")?.to_string(), id))
+                })
+                .collect()
+        });
+        let mut replaced = None;
+        for _ in 0..MAX_REPLACE_DEPTH {
+            match replacements.get(replaced.as_deref().unwrap_or(id)) {
+                Some(target) => replaced = Some(target.clone()),
+                None => return Ok(replaced),
+            }
+        }
+        Err(Error::ReplaceDepth(id.to_string()))
+    }
+
+    pub fn has_object(&self, id: &str) -> bool {
+        objects::has_object(&self.object_dir(), id)
+    }
+
+    // the object `id` names, followed through tags, and from a commit to its tree, until it
+    // has the type asked for
+    pub fn peel(&self, id: &str, object_type: GitObjectType) -> Result<Object> {
+        let mut object = self.find_object(id)?;
+        loop {
+            let target = match object.object_type {
+                actual if actual == object_type => return Ok(object),
+                GitObjectType::Tag => header_values(&object.data, "object"),
+                GitObjectType::Commit if object_type == GitObjectType::Tree => {
+                    header_values(&object.data, "tree")
+                }
+                actual => {
+                    return Err(Error::UnexpectedObjectType {
+                        id: id.to_string(),
+                        actual: actual.to_string(),
+                        expected: object_type.to_string(),
+                    })
+                }
+            };
+            let target = target
+                .first()
+                .ok_or_else(|| Error::CorruptObject(object.id.clone()))?;
+            object = self.find_object(target)?;
+        }
+    }
+
+    pub fn write_object(&self, object_type: GitObjectType, data: &[u8]) -> Result<String> {
+        Ok(objects::write_loose_object(
+            &self.object_dir(),
+            object_type,
+            data,
+        )?)
+    }
+
+    pub fn write_object_from(
+        &self,
+        object_type: GitObjectType,
+        size: u64,
+        reader: impl Read,
+    ) -> Result<String> {
+        Ok(objects::write_loose_object_from(
+            &self.object_dir(),
+            object_type,
+            size,
+            reader,
+        )?)
+    }
+
+    pub fn create_blob(&self, data: &[u8]) -> Result<String> {
+        self.write_object(GitObjectType::Blob, data)
+    }
+
+    // entries may come in any order; they are written in the order git expects
+    pub fn create_tree(&self, entries: &[TreeEntry]) -> Result<String> {
+        let mut entries = entries.to_vec();
+        // a subtree sorts as if its name ended with "/"
+        entries.sort_by_key(|entry| {
+            if entry.mode == "40000" {
+                format!("{}/", entry.name)
+            } else {
+                entry.name.clone()
+            }
+        });
+
+        let mut content = vec![];
+        for entry in entries {
+            content.extend(format!("{} {}\0", entry.mode, entry.name).as_bytes());
+            content.extend(
+                hex::decode(&entry.id).map_err(|_| Error::InvalidObjectId(entry.id.clone()))?,
+            );
+        }
+
+        self.write_object(GitObjectType::Tree, &content)
+    }
+
+    pub fn create_commit(
+        &self,
+        tree: &str,
+        parents: &[&str],
+        author: &Signature,
+        committer: &Signature,
+        message: &str,
+    ) -> Result<String> {
+        let content = commit_content(tree, parents, author, committer, message);
+        self.write_object(GitObjectType::Commit, content.as_bytes())
+    }
+
+    // the entries of a tree, or of the tree a commit points at
+    pub fn tree_entries(&self, id: &str) -> Result<Vec<TreeEntry>> {
+        let object = self.find_object(id)?;
+        match object.object_type {
+            GitObjectType::Tree => objects::parse_tree(&object.data),
+            GitObjectType::Commit => match header_values(&object.data, "tree").first() {
+                Some(tree) => self.tree_entries(tree),
+                None => Err(Error::CorruptObject(id.to_string())),
+            },
+            object_type => Err(Error::UnexpectedObjectType {
+                id: id.to_string(),
+                actual: object_type.to_string(),
+                expected: "tree".to_string(),
+            }),
+        }
+    }
+
+    // everything below a tree-ish that isn't a tree itself, by its path under `prefix`, in the
+    // order of the index
+    pub fn tree_files(&self, id: &str, prefix: &str) -> Result<Vec<IndexEntry>> {
+        let mut files = vec![];
+        let mut trees = vec![(self.peel(id, GitObjectType::Tree)?.id, prefix.to_string())];
+        while let Some((id, prefix)) = trees.pop() {
+            for entry in self.tree_entries(&id)? {
+                let path = format!("{}{}", prefix, entry.name);
+                if entry.object_type() == GitObjectType::Tree {
+                    trees.push((entry.id, format!("{}/", path)));
+                    continue;
+                }
+                let mode = u32::from_str_radix(&entry.mode, 8)
+                    .map_err(|_| Error::CorruptObject(id.clone()))?;
+                files.push(IndexEntry::new(&path, mode, &entry.id));
+            }
+        }
+        files.sort_by(|a, b| a.path.as_bytes().cmp(b.path.as_bytes()));
+
+        Ok(files)
+    }
+
+    // the file at `path` in a tree-ish, None when there is none or it's a tree
+    pub fn find_file(&self, id: &str, path: &str) -> Result<Option<IndexEntry>> {
+        let mut tree = self.peel(id, GitObjectType::Tree)?.id;
+        let mut components = path.split('/').peekable();
+        while let Some(name) = components.next() {
+            let Some(entry) = self
+                .tree_entries(&tree)?
+                .into_iter()
+                .find(|entry| entry.name == name)
+            else {
+                return Ok(None);
+            };
+            let is_tree = entry.object_type() == GitObjectType::Tree;
+            if components.peek().is_some() {
+                match is_tree {
+                    true => tree = entry.id,
+                    false => return Ok(None),
+                }
+                continue;
+            }
+            if is_tree {
+                return Ok(None);
+            }
+            let mode = u32::from_str_radix(&entry.mode, 8)
+                .map_err(|_| Error::CorruptObject(tree.clone()))?;
+            return Ok(Some(IndexEntry::new(path, mode, &entry.id)));
+        }
+        Ok(None)
+    }
+
+    // the reverse of tree_files: writes the trees that hold `files`, which have to be in the
+    // order of the index, and returns the id of the top one
+    pub fn write_tree_entries(&self, files: &[IndexEntry]) -> Result<String> {
+        self.write_subtree(files, "")
+    }
+
+    fn write_subtree(&self, files: &[IndexEntry], prefix: &str) -> Result<String> {
+        let mut entries = vec![];
+        let mut rest = files;
+        while let Some(file) = rest.first() {
+            let name = &file.path[prefix.len()..];
+            let Some((dir, _)) = name.split_once('/') else {
+                entries.push(TreeEntry {
+                    mode: format!("{:o}", file.mode),
+                    name: name.to_string(),
+                    id: file.id.clone(),
+                });
+                rest = &rest[1..];
+                continue;
+            };
+            let dir_prefix = format!("{}{}/", prefix, dir);
+            let end = rest
+                .iter()
+                .position(|file| !file.path.starts_with(&dir_prefix))
+                .unwrap_or(rest.len());
+            entries.push(TreeEntry {
+                mode: "40000".into(),
+                name: dir.to_string(),
+                id: self.write_subtree(&rest[..end], &dir_prefix)?,
+            });
+            rest = &rest[end..];
+        }
+
+        self.create_tree(&entries)
+    }
+
+    pub fn find_commit(&self, id: &str) -> Result<Commit> {
+        let object = self.peel(id, GitObjectType::Commit)?;
+        let data = String::from_utf8_lossy(&object.data);
+        let corrupt = || Error::CorruptObject(object.id.clone());
+        let (_, message) = data.split_once("\n\n").unwrap_or((&data, ""));
+        let signature = |field: &str| {
+            header_values(&object.data, field)
+                .first()
+                .and_then(|value| Signature::parse(value))
+                .ok_or_else(corrupt)
+        };
+
+        Ok(Commit {
+            tree: header_values(&object.data, "tree")
+                .pop()
+                .ok_or_else(corrupt)?,
+            parents: match self.is_shallow_commit(&object.id) {
+                true => vec![],
+                false => header_values(&object.data, "parent"),
+            },
+            author: signature("author")?,
+            committer: signature("committer")?,
+            message: message.to_string(),
+            id: object.id.clone(),
+        })
+    }
+
+    pub fn commit_parents(&self, id: &str) -> Result<Vec<String>> {
+        let object = self.find_object(id)?;
+        if self.is_shallow_commit(id) {
+            return Ok(vec![]);
+        }
+        Ok(header_values(&object.data, "parent"))
+    }
+
+    fn is_shallow_commit(&self, id: &str) -> bool {
+        self.shallow
+            .get_or_init(|| read_shallow(&self.common_dir).into_iter().collect())
+            .contains(id)
+    }
+
+    // commits reachable from `id`, breadth first, each once
+    pub fn ancestors(&self, id: &str) -> Ancestors<'_> {
+        Ancestors {
+            repository: self,
+            queue: VecDeque::from([id.to_string()]),
+            seen: HashSet::new(),
+        }
+    }
+
+    pub fn is_ancestor(&self, ancestor: &str, descendant: &str) -> bool {
+        self.ancestors(descendant).any(|id| id == ancestor)
+    }
+
+    // the best common ancestors of `one` and any of `others`, which are the common ancestors
+    // no other common ancestor descends from, newest first
+    pub fn merge_bases(&self, one: &str, others: &[String]) -> Result<Vec<String>> {
+        let ours: HashSet<String> = self.ancestors(one).collect();
+        let mut common = vec![];
+        let mut seen = HashSet::new();
+        for id in others.iter().flat_map(|other| self.ancestors(other)) {
+            if ours.contains(&id) && seen.insert(id.clone()) {
+                common.push(id);
+            }
+        }
+        self.independent(common)
+    }
+
+    // the best common ancestors of all of `commits`, as for an octopus merge
+    pub fn octopus_merge_bases(&self, commits: &[String]) -> Result<Vec<String>> {
+        let Some((first, rest)) = commits.split_first() else {
+            return Ok(vec![]);
+        };
+        let mut common: Vec<String> = self.ancestors(first).collect();
+        for commit in rest {
+            let ancestors: HashSet<String> = self.ancestors(commit).collect();
+            common.retain(|id| ancestors.contains(id));
+        }
+        self.independent(common)
+    }
+
+    // `commits` without any that another of them descends from, newest first
+    fn independent(&self, commits: Vec<String>) -> Result<Vec<String>> {
+        let mut parents = VecDeque::new();
+        for id in &commits {
+            parents.extend(self.commit_parents(id)?);
+        }
+        let redundant: HashSet<String> = Ancestors {
+            repository: self,
+            queue: parents,
+            seen: HashSet::new(),
+        }
+        .collect();
+
+        let mut independent = vec![];
+        for id in commits {
+            if !redundant.contains(&id) {
+                let time = self.commit_time(&id)?;
+                independent.push((time, id));
+            }
+        }
+        independent.sort_by_key(|(time, _)| Reverse(*time));
+        Ok(independent.into_iter().map(|(_, id)| id).collect())
+    }
+
+    // when a commit was committed, in seconds since the epoch
+    fn commit_time(&self, id: &str) -> Result<i64> {
+        let committer = header_values(&self.find_object(id)?.data, "committer");
+        committer
+            .first()
+            .and_then(|committer| committer.rsplit(' ').nth(1)?.parse().ok())
+            .ok_or_else(|| Error::CorruptObject(id.to_string()))
+    }
+
+    // the commits revisions like "A", "^A", "A..B" and "A...B" take in and leave out; a side
+    // of a range left empty is HEAD
+    pub fn parse_revisions(&self, revisions: &[String]) -> Result<(Vec<String>, Vec<String>)> {
+        let commit = |name: &str| -> Result<String> {
+            let name = if name.is_empty() { "HEAD" } else { name };
+            Ok(self.peel(&self.rev_parse(name)?, GitObjectType::Commit)?.id)
+        };
+        let (mut include, mut exclude) = (vec![], vec![]);
+        for revision in revisions {
+            if let Some((a, b)) = revision.split_once("...") {
+                let (a, b) = (commit(a)?, commit(b)?);
+                // what either side has that the other doesn't
+                exclude.extend(self.merge_bases(&a, std::slice::from_ref(&b))?);
+                include.extend([a, b]);
+            } else if let Some((a, b)) = revision.split_once("..") {
+                exclude.push(commit(a)?);
+                include.push(commit(b)?);
+            } else if let Some(name) = revision.strip_prefix('^') {
+                exclude.push(commit(name)?);
+            } else {
+                include.push(commit(revision)?);
+            }
+        }
+
+        Ok((include, exclude))
+    }
+
+    // the commits reachable from `include` but not from `exclude`, newest first
+    pub fn rev_list(&self, include: &[String], exclude: &[String]) -> Result<Vec<String>> {
+        let excluded: HashSet<String> = Ancestors {
+            repository: self,
+            queue: exclude.iter().cloned().collect(),
+            seen: HashSet::new(),
+        }
+        .collect();
+
+        // commits with the same time come out in the order they were found
+        let mut queue = BinaryHeap::new();
+        let mut seen = HashSet::new();
+        let mut order = 0;
+        for id in include {
+            if !excluded.contains(id) && seen.insert(id.clone()) {
+                queue.push((self.commit_time(id)?, Reverse(order), id.clone()));
+                order += 1;
+            }
+        }
+        let mut commits = vec![];
+        while let Some((_, _, id)) = queue.pop() {
+            for parent in self.commit_parents(&id)? {
+                if !excluded.contains(&parent) && seen.insert(parent.clone()) {
+                    queue.push((self.commit_time(&parent)?, Reverse(order), parent));
+                    order += 1;
+                }
+            }
+            commits.push(id);
+        }
+
+        Ok(commits)
+    }
+
+    // the commits `rev_list` gives, marking those that change what `pathspecs` match; like
+    // git, a commit with a parent it doesn't differ from is left out and only that parent's
+    // history is followed
+    pub fn rev_list_paths(
+        &self,
+        include: &[String],
+        exclude: &[String],
+        pathspecs: &Pathspecs,
+    ) -> Result<PathHistory> {
+        let excluded: HashSet<String> = Ancestors {
+            repository: self,
+            queue: exclude.iter().cloned().collect(),
+            seen: HashSet::new(),
+        }
+        .collect();
+
+        // as in git, the left out history is walked alongside the rest and only known to be
+        // left out once the walk gets to it, which decides which parents count as relevant
+        // below; the parents of each commit read so far are the ones its history goes through
+        let mut uninteresting = HashSet::new();
+        let mut read: HashMap<String, Vec<String>> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        let mut seen = HashSet::new();
+        let mut order = 0;
+        for id in include.iter().chain(exclude) {
+            if seen.insert(id.clone()) {
+                queue.push((self.commit_time(id)?, Reverse(order), id.clone()));
+                order += 1;
+            }
+            read.insert(id.clone(), self.commit_parents(id)?);
+        }
+        for id in exclude {
+            uninteresting.insert(id.clone());
+            mark_uninteresting(&read[id].clone(), &read, &mut uninteresting);
+        }
+
+        let mut walked = vec![];
+        // the parents each commit's history is followed through, and whether it's shown
+        let mut followed: HashMap<String, (Vec<String>, bool)> = HashMap::new();
+        while let Some((_, _, id)) = queue.pop() {
+            if uninteresting.contains(&id) {
+                // nothing more to show once all that's left is left out
+                if queue.iter().all(|(_, _, id)| uninteresting.contains(id)) {
+                    break;
+                }
+                for parent in read[&id].clone() {
+                    uninteresting.insert(parent.clone());
+                    let parents = self.commit_parents(&parent)?;
+                    mark_uninteresting(&parents, &read, &mut uninteresting);
+                    read.insert(parent.clone(), parents);
+                    if seen.insert(parent.clone()) {
+                        queue.push((self.commit_time(&parent)?, Reverse(order), parent));
+                        order += 1;
+                    }
+                }
+                continue;
+            }
+
+            let commit = self.find_commit(&id)?;
+            // a parent the commit doesn't differ from is the only one followed, as long as it's
+            // relevant: not left out, or left out by name; other parents only decide whether
+            // the commit is shown when there are no relevant ones
+            let mut same_parent = None;
+            let (mut relevant_parents, mut relevant_change, mut irrelevant_change) =
+                (0, false, false);
+            for parent in &commit.parents {
+                let relevant = !uninteresting.contains(parent) || exclude.contains(parent);
+                if relevant {
+                    relevant_parents += 1;
+                }
+                let parent_tree = self.find_commit(parent)?.tree;
+                let changes =
+                    self.tree_changes(Some(&parent_tree), Some(&commit.tree), pathspecs)?;
+                match (changes.is_empty(), relevant) {
+                    (true, true) => {
+                        same_parent = Some(parent.clone());
+                        break;
+                    }
+                    (true, false) => {}
+                    (false, true) => relevant_change = true,
+                    (false, false) => irrelevant_change = true,
+                }
+            }
+            let (parents, shown) = match same_parent {
+                Some(parent) => (vec![parent], false),
+                None if commit.parents.is_empty() => {
+                    let changes = self.tree_changes(None, Some(&commit.tree), pathspecs)?;
+                    (commit.parents, !changes.is_empty())
+                }
+                None if relevant_parents > 0 => (commit.parents, relevant_change),
+                None => (commit.parents, irrelevant_change),
+            };
+
+            for parent in &parents {
+                if !read.contains_key(parent) {
+                    read.insert(parent.clone(), self.commit_parents(parent)?);
+                }
+                if seen.insert(parent.clone()) {
+                    queue.push((self.commit_time(parent)?, Reverse(order), parent.clone()));
+                    order += 1;
+                }
+            }
+            read.insert(id.clone(), parents.clone());
+            followed.insert(id.clone(), (parents, shown));
+            walked.push(id);
+        }
+
+        walked.retain(|id| !excluded.contains(id));
+        Ok(PathHistory {
+            commits: walked,
+            parents: followed
+                .iter()
+                .map(|(id, (parents, _))| (id.clone(), parents.clone()))
+                .collect(),
+            shown: followed
+                .into_iter()
+                .filter(|(_, (_, shown))| *shown)
+                .map(|(id, _)| id)
+                .collect(),
+            excluded,
+            bottoms: exclude.iter().cloned().collect(),
+        })
+    }
+
+    // the changes between two trees in what `pathspecs` match, only looking into subtrees that
+    // differ and could hold a path they match; a missing tree is an empty one
+    pub fn tree_changes(
+        &self,
+        old: Option<&str>,
+        new: Option<&str>,
+        pathspecs: &Pathspecs,
+    ) -> Result<Vec<Change>> {
+        let mut changes = vec![];
+        self.subtree_changes(old, new, "", pathspecs, &mut changes)?;
+        changes.sort_by(|a, b| a.path.as_bytes().cmp(b.path.as_bytes()));
+        Ok(changes)
+    }
+
+    fn subtree_changes(
+        &self,
+        old: Option<&str>,
+        new: Option<&str>,
+        prefix: &str,
+        pathspecs: &Pathspecs,
+        changes: &mut Vec<Change>,
+    ) -> Result<()> {
+        let entries = |id: Option<&str>| -> Result<Vec<TreeEntry>> {
+            match id {
+                Some(id) => self.tree_entries(id),
+                None => Ok(vec![]),
+            }
+        };
+        let (old, new) = (entries(old)?, entries(new)?);
+        let mut names: Vec<&str> = old
+            .iter()
+            .chain(&new)
+            .map(|entry| entry.name.as_str())
+            .collect();
+        names.sort();
+        names.dedup();
+
+        for name in names {
+            let find =
+                |entries: &[TreeEntry]| entries.iter().find(|entry| entry.name == name).cloned();
+            let (before, after) = (find(&old), find(&new));
+            if let (Some(before), Some(after)) = (&before, &after) {
+                if before.id == after.id && before.mode == after.mode {
+                    continue;
+                }
+            }
+            let path = format!("{}{}", prefix, name);
+            let is_tree = |entry: &TreeEntry| entry.object_type() == GitObjectType::Tree;
+
+            // a tree on either side is looked into if the paths could be in it, and a file on
+            // the other side is a change of its own
+            let (old_tree, new_tree) = (
+                before.as_ref().filter(|entry| is_tree(entry)),
+                after.as_ref().filter(|entry| is_tree(entry)),
+            );
+            if (old_tree.is_some() || new_tree.is_some()) && pathspecs.could_contain(&path) {
+                self.subtree_changes(
+                    old_tree.map(|entry| entry.id.as_str()),
+                    new_tree.map(|entry| entry.id.as_str()),
+                    &format!("{}/", path),
+                    pathspecs,
+                    changes,
+                )?;
+            }
+            let file = |entry: Option<TreeEntry>| -> Result<Option<IndexEntry>> {
+                let Some(entry) = entry.filter(|entry| entry.object_type() != GitObjectType::Tree)
+                else {
+                    return Ok(None);
+                };
+                let mode = u32::from_str_radix(&entry.mode, 8)
+                    .map_err(|_| Error::CorruptObject(entry.id.clone()))?;
+                Ok(Some(IndexEntry::new(&path, mode, &entry.id)))
+            };
+            let (old_file, new_file) = (file(before)?, file(after)?);
+            if (old_file.is_some() || new_file.is_some()) && pathspecs.matches(&path) {
+                changes.push(Change {
+                    path: path.clone(),
+                    old: old_file,
+                    new: new_file,
+                    unmerged: false,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    // the commits among `commits`, taken in the order given, that log finds changes in against
+    // `parents`: ones to what `pathspecs` match, of the kind `pickaxe` looks for if given.
+    // Merges never have any. With `follow`, once a commit adds the one path by renaming or
+    // copying another file, the commits after it are looked at for that file instead
+    pub fn changing(
+        &self,
+        commits: &[String],
+        parents: &HashMap<String, Vec<String>>,
+        pathspecs: &Pathspecs,
+        follow: bool,
+        pickaxe: Option<&Pickaxe>,
+    ) -> Result<HashSet<String>> {
+        let mut pathspecs = pathspecs.clone();
+        let mut changing = HashSet::new();
+        for id in commits {
+            let commit = self.find_commit(id)?;
+            let parent_tree = match &parents[id][..] {
+                [] => None,
+                [parent] => Some(self.find_commit(parent)?.tree),
+                _ => continue,
+            };
+            let mut changes =
+                self.tree_changes(parent_tree.as_deref(), Some(&commit.tree), &pathspecs)?;
+            if let (true, Some(parent_tree), [change]) = (follow, &parent_tree, &mut changes[..]) {
+                if let (None, Some(added)) = (&change.old, &change.new) {
+                    if let Some(source) =
+                        self.copy_source(parent_tree, &commit.tree, added, true)?
+                    {
+                        pathspecs = Pathspecs::new(vec![Pathspec::path(&source.path)]);
+                        change.old = Some(source);
+                    }
+                }
+            }
+            if let Some(pickaxe) = pickaxe {
+                let content = |entry: &Option<IndexEntry>| -> Result<Option<Vec<u8>>> {
+                    entry
+                        .as_ref()
+                        .map(|entry| self.entry_content(entry))
+                        .transpose()
+                };
+                let mut found = false;
+                for change in &changes {
+                    let (old, new) = (content(&change.old)?, content(&change.new)?);
+                    found |= pickaxe.finds(old.as_deref(), new.as_deref());
+                }
+                if !found {
+                    continue;
+                }
+            }
+            if !changes.is_empty() {
+                changing.insert(id.clone());
+            }
+        }
+
+        Ok(changing)
+    }
+
+    // what a diff compares for a file: its blob, or for a submodule the commit it's at
+    pub fn entry_content(&self, entry: &IndexEntry) -> Result<Vec<u8>> {
+        if entry.mode & 0o170000 == 0o160000 {
+            return Ok(format!("Subproject commit {}\n", entry.id).into_bytes());
+        }
+
+        Ok(self.find_object(&entry.id)?.data)
+    }
+
+    // the file of the tree `old` that `added` in `new` was renamed or copied from: one with the
+    // same content, or else the most alike with at least half of it in common. As in git, a
+    // file that went away is taken over one that is still there, which is only a copy, and
+    // without `copies` is never taken
+    pub fn copy_source(
+        &self,
+        old: &str,
+        new: &str,
+        added: &IndexEntry,
+        copies: bool,
+    ) -> Result<Option<IndexEntry>> {
+        let kept: HashSet<String> = self
+            .tree_files(new, "")?
+            .into_iter()
+            .map(|file| file.path)
+            .collect();
+        let mut files = self.tree_files(old, "")?;
+        files.retain(|file| copies || !kept.contains(&file.path));
+        // stable, so files that went away come first and otherwise stay in order
+        files.sort_by_key(|file| kept.contains(&file.path));
+
+        // the same content wins, and with it the same file name
+        let name = |path: &str| path.rsplit('/').next().unwrap_or(path).to_string();
+        if let Some(file) = files
+            .iter()
+            .filter(|file| file.id == added.id)
+            .min_by_key(|file| {
+                (
+                    kept.contains(&file.path),
+                    name(&file.path) != name(&added.path),
+                )
+            })
+        {
+            return Ok(Some(file.clone()));
+        }
+
+        let content = self.find_object(&added.id)?.data;
+        let mut best: Option<(bool, u32, &IndexEntry)> = None;
+        for file in files.iter().filter(|file| file.mode & 0o170000 != 0o160000) {
+            let score = similarity(&self.find_object(&file.id)?.data, &content);
+            let copy = kept.contains(&file.path);
+            if score >= RENAME_SCORE
+                && best.is_none_or(|(best_copy, best, _)| (best_copy, score) > (copy, best))
+            {
+                best = Some((copy, score, file));
+            }
+        }
+
+        Ok(best.map(|(_, _, file)| file.clone()))
+    }
+
+    // an object id, a ref name resolved to the object it points at, or a unique abbreviation
+    // of an id, followed by any number of "~n" for the nth first-parent ancestor, "^n" for the
+    // nth parent (the commit itself for 0), "^{type}" to peel to a type and "^{}" to peel tags
+    pub fn rev_parse(&self, name: &str) -> Result<String> {
+        let not_found = || Error::ObjectNotFound(name.to_string());
+        let (base, mut suffixes) = name.split_at(name.find(['~', '^']).unwrap_or(name.len()));
+        let mut id = self.resolve_name(base)?;
+        while let Some(operator) = suffixes.chars().next() {
+            let rest = &suffixes[operator.len_utf8()..];
+            if let (Some(peel), '^') = (rest.strip_prefix('{'), operator) {
+                let (object_type, rest) = peel.split_once('}').ok_or_else(not_found)?;
+                id = match object_type {
+                    "" => self.peel_tags(&id)?,
+                    object_type => self.peel(&id, object_type.parse()?)?.id,
+                };
+                suffixes = rest;
+                continue;
+            }
+
+            let digits = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            let (count, rest) = rest.split_at(digits);
+            let count: usize = match count {
+                "" => 1,
+                count => count.parse().map_err(|_| not_found())?,
+            };
+            let commit = self.peel(&id, GitObjectType::Commit)?.id;
+            id = match operator {
+                '~' => {
+                    let mut commit = commit;
+                    for _ in 0..count {
+                        commit = self
+                            .commit_parents(&commit)?
+                            .into_iter()
+                            .next()
+                            .ok_or_else(not_found)?;
+                    }
+                    commit
+                }
+                '^' if count == 0 => commit,
+                '^' => self
+                    .commit_parents(&commit)?
+                    .into_iter()
+                    .nth(count - 1)
+                    .ok_or_else(not_found)?,
+                _ => return Err(not_found()),
+            };
+            suffixes = rest;
+        }
+
+        Ok(id)
+    }
+
+    // a full id, a ref name, or else an abbreviated id of at least 4 digits that only one
+    // object has
+    fn resolve_name(&self, name: &str) -> Result<String> {
+        let is_hex = !name.is_empty() && name.bytes().all(|b| b.is_ascii_hexdigit());
+        if is_hex && name.len() == 40 {
+            return Ok(name.to_ascii_lowercase());
+        }
+        if let Some(id) = self.find_reference(name) {
+            return Ok(id);
+        }
+        if !is_hex || name.len() < MIN_ABBREV {
+            return Err(Error::ObjectNotFound(name.to_string()));
+        }
+
+        let ids = objects::objects_with_prefix(&self.object_dir(), &name.to_ascii_lowercase());
+        match &ids[..] {
+            [id] => Ok(id.clone()),
+            [] => Err(Error::ObjectNotFound(name.to_string())),
+            _ => Err(Error::AmbiguousObjectId(name.to_string())),
+        }
+    }
+
+    // the object `id` names, followed through tags until it isn't one
+    fn peel_tags(&self, id: &str) -> Result<String> {
+        let mut object = self.find_object(id)?;
+        while object.object_type == GitObjectType::Tag {
+            let target = header_values(&object.data, "object");
+            let target = target
+                .first()
+                .ok_or_else(|| Error::CorruptObject(object.id.clone()))?;
+            object = self.find_object(target)?;
+        }
+        Ok(object.id)
+    }
+
+    // the commit HEAD points at, if any
+    pub fn head(&self) -> Option<String> {
+        self.find_reference("HEAD")
+    }
+
+    // the branch HEAD points at, or None when it is detached
+    pub fn head_target(&self) -> Option<String> {
+        self.symbolic_reference("HEAD")
+    }
+
+    // the ref `name` points at when it is a symbolic ref rather than an object id
+    pub fn symbolic_reference(&self, name: &str) -> Option<String> {
+        self.read_reference(name)?
+            .strip_prefix("ref: ")
+            .map(|target| target.to_string())
+    }
+
+    // points `name`, or the ref it stands for when it is symbolic, at `id`: on a branch this
+    // moves the branch, while a detached HEAD moves itself
+    pub fn update_reference(&self, name: &str, id: &str) -> Result<()> {
+        self.write_reference(&self.dereference(name), id)
+    }
+
+    // the ref that `name` ends up standing for, following symbolic refs
+    pub fn dereference(&self, name: &str) -> String {
+        let mut name = name.to_string();
+        for _ in 0..MAX_SYMREF_DEPTH {
+            match self.symbolic_reference(&name) {
+                Some(target) => name = target,
+                None => break,
+            }
+        }
+        name
+    }
+
+    // resolves a full ref name, or a short one the way `git rev-parse` does
+    pub fn find_reference(&self, name: &str) -> Option<String> {
+        [
+            name.to_string(),
+            format!("refs/{}", name),
+            format!("refs/tags/{}", name),
+            format!("refs/heads/{}", name),
+            format!("refs/remotes/{}", name),
+            format!("refs/remotes/{}/HEAD", name),
+        ]
+        .iter()
+        .find_map(|name| self.resolve_reference(name, 0))
+    }
+
+    // the full name of the ref a name stands for, the ref it ends up at when it is symbolic
+    pub fn full_reference_name(&self, name: &str) -> Option<String> {
+        let full_name = [
+            name.to_string(),
+            format!("refs/{}", name),
+            format!("refs/tags/{}", name),
+            format!("refs/heads/{}", name),
+            format!("refs/remotes/{}", name),
+            format!("refs/remotes/{}/HEAD", name),
+        ]
+        .into_iter()
+        .find(|name| self.resolve_reference(name, 0).is_some())?;
+        Some(self.dereference(&full_name))
+    }
+
+    // the shortest name that stands for the ref `name` and no other, as "refs/heads/main" is
+    // "main" unless there is a ref that a short name resolves to first, such as a tag "main"
+    pub fn shorten_reference_name(&self, name: &str) -> String {
+        let rules = [
+            ("", ""),
+            ("refs/", ""),
+            ("refs/tags/", ""),
+            ("refs/heads/", ""),
+            ("refs/remotes/", ""),
+            ("refs/remotes/", "/HEAD"),
+        ];
+        for i in (1..rules.len()).rev() {
+            let (prefix, suffix) = rules[i];
+            let short = name
+                .strip_prefix(prefix)
+                .and_then(|rest| rest.strip_suffix(suffix));
+            let Some(short) = short.filter(|short| !short.is_empty()) else {
+                continue;
+            };
+            let ambiguous = rules[..i].iter().any(|(prefix, suffix)| {
+                let name = format!("{}{}{}", prefix, short, suffix);
+                self.resolve_reference(&name, 0).is_some()
+            });
+            if !ambiguous {
+                return short.to_string();
+            }
+        }
+        name.to_string()
+    }
+
+    fn resolve_reference(&self, name: &str, depth: usize) -> Option<String> {
+        if depth > MAX_SYMREF_DEPTH {
+            return None;
+        }
+        let value = self.read_reference(name)?;
+        match value.strip_prefix("ref: ") {
+            Some(target) => self.resolve_reference(target, depth + 1),
+            None => Some(value),
+        }
+    }
+
+    // the raw value of a ref: an object id or "ref: <target>"; FETCH_HEAD has a line per ref
+    // fetched, and names the first
+    fn read_reference(&self, name: &str) -> Option<String> {
+        if let Ok(content) = fs::read_to_string(self.reference_path(name)) {
+            let line = content.lines().next().unwrap_or_default();
+            return Some(
+                line.split('\t')
+                    .next()
+                    .unwrap_or_default()
+                    .trim()
+                    .to_string(),
+            );
+        }
+
+        self.packed_references()
+            .into_iter()
+            .find(|(packed, _)| packed == name)
+            .map(|(_, id)| id)
+    }
+
+    pub fn set_reference(&self, name: &str, id: &str) -> Result<()> {
+        self.write_reference(name, id)
+    }
+
+    pub fn set_symbolic_reference(&self, name: &str, target: &str) -> Result<()> {
+        self.write_reference(name, &format!("ref: {}", target))
+    }
+
+    // refuses a name no ref can have, so that one is never made
+    fn write_reference(&self, name: &str, value: &str) -> Result<()> {
+        let format = refname::Format {
+            allow_onelevel: true,
+            ..Default::default()
+        };
+        if !refname::is_valid(name, format) {
+            return Err(Error::BadRefName(name.to_string()));
+        }
+        let path = self.reference_path(name);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, format!("{}\n", value))?;
+
+        Ok(())
+    }
+
+    // removes a ref, loose or packed
+    pub fn delete_reference(&self, name: &str) -> Result<()> {
+        match fs::remove_file(self.reference_path(name)) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error.into()),
+            _ => {}
+        }
+        let packed_refs = self.common_dir.join("packed-refs");
+        let Ok(content) = fs::read_to_string(&packed_refs) else {
+            return Ok(());
+        };
+        // the line peeling a packed tag goes with it
+        let mut kept = String::new();
+        let mut deleted = false;
+        for line in content.lines() {
+            let removed = match line.strip_prefix('^') {
+                Some(_) => deleted,
+                None => {
+                    deleted = line
+                        .split_once(' ')
+                        .is_some_and(|(_, packed)| packed == name);
+                    deleted
+                }
+            };
+            if !removed {
+                kept.push_str(line);
+                kept.push('\n');
+            }
+        }
+        if kept != content {
+            fs::write(packed_refs, kept)?;
+        }
+        Ok(())
+    }
+
+    // moves loose refs into packed-refs, every one or only tags, removing the loose files when
+    // `prune`; symbolic refs and those that belong to one worktree stay loose
+    pub fn pack_references(&self, all: bool, prune: bool) -> Result<()> {
+        let mut packed: BTreeMap<String, String> = self.packed_references().into_iter().collect();
+        let mut loose = vec![];
+        let mut dirs = vec![self.common_dir.join("refs")];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+                let path = entry.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                let Ok(relative) = path.strip_prefix(&self.common_dir) else {
+                    continue;
+                };
+                let name = paths::to_git(relative);
+                if (!all && !name.starts_with("refs/tags/"))
+                    || ["refs/worktree/", "refs/bisect/", "refs/rewritten/"]
+                        .iter()
+                        .any(|prefix| name.starts_with(prefix))
+                {
+                    continue;
+                }
+                let value = fs::read_to_string(&path).unwrap_or_default();
+                let id = value.trim();
+                if id.len() == 40 && id.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    packed.insert(name.clone(), id.to_string());
+                    loose.push((path, id.to_string()));
+                }
+            }
+        }
+
+        // each annotated tag is followed by what it peels to, so that readers needn't look
+        let mut content = "# pack-refs with: peeled fully-peeled sorted \n".to_string();
+        for (name, id) in &packed {
+            content.push_str(&format!("{} {}\n", id, name));
+            let mut peeled = id.clone();
+            while let Ok(object) = self.find_object(&peeled) {
+                if object.object_type != GitObjectType::Tag {
+                    break;
+                }
+                match header_values(&object.data, "object").pop() {
+                    Some(target) => peeled = target,
+                    None => break,
+                }
+            }
+            if peeled != *id {
+                content.push_str(&format!("^{}\n", peeled));
+            }
+        }
+        let packed_refs = self.common_dir.join("packed-refs");
+        let lock = self.common_dir.join("packed-refs.lock");
+        fs::write(&lock, content)?;
+        fs::rename(&lock, packed_refs)?;
+
+        if prune {
+            // a ref moved on since it was read stays loose
+            let refs = self.common_dir.join("refs");
+            for (path, id) in loose {
+                if fs::read_to_string(&path).is_ok_and(|value| value.trim() == id) {
+                    fs::remove_file(&path)?;
+                }
+                let mut dir = path.parent();
+                while let Some(parent) =
+                    dir.filter(|dir| *dir != refs && dir.parent() != Some(refs.as_path()))
+                {
+                    if fs::remove_dir(parent).is_err() {
+                        break;
+                    }
+                    dir = parent.parent();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // every ref under refs/ with the object it resolves to, sorted by name
+    pub fn references(&self) -> impl Iterator<Item = (String, String)> + '_ {
+        let mut names = vec![];
+        let mut dirs = vec![self.common_dir.join("refs")];
+        while let Some(dir) = dirs.pop() {
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                if entry.path().is_dir() {
+                    dirs.push(entry.path());
+                } else if let Ok(relative) = entry.path().strip_prefix(&self.common_dir) {
+                    names.push(paths::to_git(relative));
+                }
+            }
+        }
+        names.extend(self.packed_references().into_iter().map(|(name, _)| name));
+        names.sort();
+        names.dedup();
+
+        names
+            .into_iter()
+            .filter_map(|name| self.resolve_reference(&name, 0).map(|id| (name, id)))
+    }
+
+    // HEAD and a few ref namespaces belong to each worktree, everything else is shared
+    fn reference_path(&self, name: &str) -> PathBuf {
+        match is_shared_reference(name) {
+            true => paths::to_native(&self.common_dir, name),
+            false => paths::to_native(&self.git_dir, name),
+        }
+    }
+
+    // the reflog of `name`, oldest first; lines that can't be made sense of are skipped
+    pub fn reflog(&self, name: &str) -> Vec<ReflogEntry> {
+        let content = fs::read_to_string(self.reflog_path(name)).unwrap_or_default();
+        content
+            .lines()
+            .filter_map(|line| {
+                let (old, rest) = line.split_once(' ')?;
+                let (new, rest) = rest.split_once(' ')?;
+                let (committer, message) = rest.split_once('\t').unwrap_or((rest, ""));
+                Some(ReflogEntry {
+                    old: old.to_string(),
+                    new: new.to_string(),
+                    committer: Signature::parse(committer)?,
+                    message: message.to_string(),
+                })
+            })
+            .collect()
+    }
+
+    pub fn append_reflog(&self, name: &str, entry: &ReflogEntry) -> Result<()> {
+        let path = self.reflog_path(name);
+        fs::create_dir_all(path.parent().unwrap())?;
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        file.write_all(entry.to_string().as_bytes())?;
+        Ok(())
+    }
+
+    // replaces the reflog of `name`, which is left empty rather than removed when there's
+    // nothing left in it
+    pub fn write_reflog(&self, name: &str, entries: &[ReflogEntry]) -> Result<()> {
+        let content: String = entries.iter().map(|entry| entry.to_string()).collect();
+        fs::write(self.reflog_path(name), content)?;
+        Ok(())
+    }
+
+    pub fn delete_reflog(&self, name: &str) -> Result<()> {
+        match fs::remove_file(self.reflog_path(name)) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error.into()),
+            _ => Ok(()),
+        }
+    }
+
+    // the full name of the ref with a reflog that `name` stands for, with the rules of
+    // find_reference
+    pub fn reflog_name(&self, name: &str) -> Option<String> {
+        [
+            name.to_string(),
+            format!("refs/{}", name),
+            format!("refs/tags/{}", name),
+            format!("refs/heads/{}", name),
+            format!("refs/remotes/{}", name),
+            format!("refs/remotes/{}/HEAD", name),
+        ]
+        .into_iter()
+        .find(|name| self.reflog_path(name).is_file())
+    }
+
+    // the refs of this work tree that have a reflog, HEAD first and then the rest by name
+    pub fn reflog_names(&self) -> Vec<String> {
+        let mut names = vec![];
+        for logs in [self.common_dir.join("logs"), self.git_dir.join("logs")] {
+            let mut dirs = vec![logs.join("refs")];
+            while let Some(dir) = dirs.pop() {
+                for entry in fs::read_dir(&dir).into_iter().flatten().flatten() {
+                    let path = entry.path();
+                    if path.is_dir() {
+                        dirs.push(path);
+                    } else if let Ok(relative) = path.strip_prefix(&logs) {
+                        names.push(paths::to_git(relative));
+                    }
+                }
+            }
+        }
+        names.sort();
+        names.dedup();
+        // a reflog is where its ref is, shared or not
+        names.retain(|name| self.reflog_path(name).is_file());
+        if self.reflog_path("HEAD").is_file() {
+            names.insert(0, "HEAD".to_string());
+        }
+        names
+    }
+
+    fn reflog_path(&self, name: &str) -> PathBuf {
+        match is_shared_reference(name) {
+            true => paths::to_native(&self.common_dir.join("logs"), name),
+            false => paths::to_native(&self.git_dir.join("logs"), name),
+        }
+    }
+
+    fn packed_references(&self) -> Vec<(String, String)> {
+        let Ok(content) = fs::read_to_string(self.common_dir.join("packed-refs")) else {
+            return vec![];
+        };
+
+        content
+            .lines()
+            .filter(|line| !line.starts_with('#') && !line.starts_with('^'))
+            .filter_map(|line| {
+                let (id, name) = line.split_once(' ')?;
+                Some((name.to_string(), id.to_string()))
+            })
+            .collect()
+    }
+}
+
+// a walk limited to paths: every commit it went through, newest first, the parents it followed
+// from each, and the commits that change the paths
+pub struct PathHistory {
+    pub commits: Vec<String>,
+    pub parents: HashMap<String, Vec<String>>,
+    pub shown: HashSet<String>,
+    // the commits left out, and those of them left out by name
+    excluded: HashSet<String>,
+    bottoms: HashSet<String>,
+}
+
+impl PathHistory {
+    // the parents of a commit that is shown, with each one that isn't standing for the first one
+    // down its history that is; parents left out of the walk stay as they are
+    pub fn rewritten_parents(&self, id: &str) -> Vec<String> {
+        let relevant = |id: &String| !self.excluded.contains(id) || self.bottoms.contains(id);
+        let rewrite = |parent: &String| -> Option<String> {
+            let mut id = parent;
+            loop {
+                let Some(parents) = self
+                    .parents
+                    .get(id)
+                    .filter(|_| !self.shown.contains(id) && !self.excluded.contains(id))
+                else {
+                    return Some(id.clone());
+                };
+                // a merge stands for its history only when just one of its parents is relevant
+                id = match &parents[..] {
+                    [] => return None,
+                    [parent] => parent,
+                    parents => {
+                        match &parents.iter().filter(|id| relevant(id)).collect::<Vec<_>>()[..] {
+                            [parent] => parent,
+                            _ => return Some(id.clone()),
+                        }
+                    }
+                };
+            }
+        };
+
+        let mut rewritten = vec![];
+        for parent in self.parents[id].iter().filter_map(rewrite) {
+            if !rewritten.contains(&parent) {
+                rewritten.push(parent);
+            }
+        }
+        rewritten
+    }
+}
+
+// marks `parents` left out, and the history of those already read that isn't yet
+fn mark_uninteresting(
+    parents: &[String],
+    read: &HashMap<String, Vec<String>>,
+    uninteresting: &mut HashSet<String>,
+) {
+    let mut pending = parents.to_vec();
+    while let Some(id) = pending.pop() {
+        if uninteresting.insert(id.clone()) {
+            pending.extend(read.get(&id).into_iter().flatten().cloned());
+        }
+    }
+}
+
+pub struct Ancestors<'a> {
+    repository: &'a Repository,
+    queue: VecDeque<String>,
+    seen: HashSet<String>,
+}
+
+impl Iterator for Ancestors<'_> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        while let Some(id) = self.queue.pop_front() {
+            if !self.seen.insert(id.clone()) {
+                continue;
+            }
+            // a missing parent (e.g. past a shallow boundary) ends that line of history
+            let Ok(parents) = self.repository.commit_parents(&id) else {
+                continue;
+            };
+            self.queue.extend(parents);
+            return Some(id);
+        }
+
+        None
+    }
+}
+
+// the commits listed in .git/shallow, which a shallow clone has without their parents
+fn is_shared_reference(name: &str) -> bool {
+    name.starts_with("refs/")
+        && !["refs/worktree/", "refs/bisect/", "refs/rewritten/"]
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+}
+
+pub fn read_shallow(common_dir: &Path) -> Vec<String> {
+    fs::read_to_string(common_dir.join("shallow"))
+        .unwrap_or_default()
+        .lines()
+        .map(str::to_string)
+        .collect()
+}
+
+// writes .git/shallow, or removes it once no commit is shallow any more
+pub fn write_shallow(common_dir: &Path, commits: &[String]) -> Result<()> {
+    let file = common_dir.join("shallow");
+    if commits.is_empty() {
+        return match fs::remove_file(file) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error.into()),
+            _ => Ok(()),
+        };
+    }
+    let mut commits = commits.to_vec();
+    commits.sort();
+    commits.dedup();
+    fs::write(
+        file,
+        commits
+            .iter()
+            .map(|id| format!("{}\n", id))
+            .collect::<String>(),
+    )?;
+    Ok(())
+}
+
+fn is_git_dir(path: &Path) -> bool {
+    path.join("HEAD").is_file()
+        && (path.join("objects").is_dir() || path.join("commondir").is_file())
+}
+
+// the git directory of a work tree: `.git` itself, or where a `.git` file ("gitdir: <path>")
+// points, as in submodules and linked worktrees
+fn dot_git(work_tree: &Path) -> Option<PathBuf> {
+    let dot_git = work_tree.join(".git");
+    if dot_git.is_file() {
+        let content = fs::read_to_string(&dot_git).ok()?;
+        let git_dir = work_tree.join(content.trim().strip_prefix("gitdir:")?.trim());
+        return is_git_dir(&git_dir).then_some(git_dir);
+    }
+
+    is_git_dir(&dot_git).then_some(dot_git)
+}
+
+// values of a commit or tag header field, e.g. every "parent"
+fn header_values(data: &[u8], field: &str) -> Vec<String> {
+    String::from_utf8_lossy(data)
+        .lines()
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.strip_prefix(field)?.strip_prefix(' '))
+        .map(|value| value.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        pack::{PackObject, PackOptions},
+        progress::NoProgress,
+        testing::{self, TempDir},
+    };
+
+    #[test]
+    fn rev_parse_follows_parents_and_peels() {
+        let dir = TempDir::new("rev-parse");
+        let repository = testing::init(dir.path());
+        let file = |content| [("f", 0o100644, content)];
+        let first = testing::commit(&repository, &file("1\n"), &[], "first\n");
+        let second = testing::commit(&repository, &file("2\n"), &[&first], "second\n");
+        let side = testing::commit(&repository, &file("s\n"), &[&first], "side\n");
+        let merge = testing::commit(&repository, &file("m\n"), &[&second, &side], "merge\n");
+        repository.update_reference("HEAD", &merge).unwrap();
+        let tagger = Signature::new("A U Thor", "author@example.com", 1700000000, 0);
+        let tag = |object: &str, object_type, name: &str| {
+            let content = tag_content(object, object_type, name, &tagger, "tag\n");
+            let id = repository
+                .write_object(GitObjectType::Tag, content.as_bytes())
+                .unwrap();
+            repository
+                .set_reference(&format!("refs/tags/{}", name), &id)
+                .unwrap();
+            id
+        };
+        tag(&second, GitObjectType::Commit, "v1");
+        let v1 = repository.rev_parse("v1").unwrap();
+        let v2 = tag(&v1, GitObjectType::Tag, "v2");
+
+        let rev_parse = |name: &str| repository.rev_parse(name).unwrap();
+        assert_eq!(rev_parse("HEAD~"), second);
+        assert_eq!(rev_parse("main~2"), first);
+        assert_eq!(rev_parse("HEAD^"), second);
+        assert_eq!(rev_parse("HEAD^2"), side);
+        assert_eq!(rev_parse("HEAD^0"), merge);
+        assert_eq!(rev_parse("HEAD^2~1"), first);
+        assert_eq!(rev_parse("HEAD~1^1"), first);
+        let tree = repository.peel(&merge, GitObjectType::Tree).unwrap().id;
+        assert_eq!(rev_parse("HEAD^{tree}"), tree);
+        assert_eq!(rev_parse("v2^{}"), second);
+        assert_eq!(rev_parse("v2^{tag}"), v2);
+        assert_eq!(rev_parse("v2^{commit}"), second);
+        assert_eq!(rev_parse("v2~1"), first);
+
+        for name in [
+            "HEAD~3",
+            "HEAD^3",
+            "HEAD^{blob}",
+            "HEAD^{",
+            "HEAD~x",
+            "HEAD^é",
+        ] {
+            assert!(repository.rev_parse(name).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn rev_parse_expands_unique_abbreviations() {
+        let dir = TempDir::new("rev-parse-abbrev");
+        let repository = testing::init(dir.path());
+
+        // blobs until two of them share their first four digits
+        let mut by_prefix = HashMap::new();
+        let (one, other) = (0..)
+            .find_map(|i: u32| {
+                let id = repository
+                    .write_object(GitObjectType::Blob, i.to_string().as_bytes())
+                    .unwrap();
+                let other = by_prefix.insert(id[..4].to_string(), id.clone())?;
+                Some((id, other))
+            })
+            .unwrap();
+        assert!(matches!(
+            repository.rev_parse(&one[..4]),
+            Err(Error::AmbiguousObjectId(_))
+        ));
+        let unique = (5..40).find(|&i| one[..i] != other[..i]).unwrap();
+        assert_eq!(repository.rev_parse(&one[..unique]).unwrap(), one);
+        assert_eq!(
+            repository
+                .rev_parse(&other[..unique].to_ascii_uppercase())
+                .unwrap(),
+            other
+        );
+        assert!(repository.rev_parse(&one[..3]).is_err());
+
+        // objects only in a pack are found too
+        let packed = repository
+            .write_object(GitObjectType::Blob, b"packed\n")
+            .unwrap();
+        let object_dir = repository.object_dir();
+        let objects = [PackObject {
+            id: packed.clone(),
+            path: String::new(),
+        }];
+        pack::write_pack(
+            &object_dir,
+            &objects,
+            &PackOptions::default(),
+            &mut NoProgress,
+        )
+        .unwrap();
+        fs::remove_file(objects::find_loose_object(&object_dir, &packed).unwrap()).unwrap();
+        assert_eq!(repository.rev_parse(&packed[..7]).unwrap(), packed);
+    }
+}
@@ -0,0 +1,208 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use sha1::{Digest, Sha1};
+
+use crate::{
+    config::{self, Config},
+    error::Result,
+    merge, paths,
+    repository::Repository,
+};
+
+// rerere ("reuse recorded resolution"): the sides of a file's conflicts name a directory under
+// .git/rr-cache that keeps the file as it conflicted, the preimage, and as it was resolved, the
+// postimage, so that when the same conflicts come up again they can be resolved the same way.
+// MERGE_RR lists the conflicts of the merge going on that are still to be resolved
+
+const MARKER_SIZE: usize = 7;
+
+// rerere.enabled, or when it isn't set, whether there is an rr-cache to use
+pub fn is_enabled(repository: &Repository) -> bool {
+    let config = Config::load(repository.common_dir());
+    match config.get("rerere.enabled").and_then(config::parse_bool) {
+        Some(enabled) => enabled,
+        None => cache_dir(repository).is_dir(),
+    }
+}
+
+// whether resolutions that were reused are added to the index too
+pub fn autoupdate(repository: &Repository) -> bool {
+    let config = Config::load(repository.common_dir());
+    config
+        .get("rerere.autoupdate")
+        .and_then(config::parse_bool)
+        .unwrap_or(false)
+}
+
+// the conflicts of a file in the form they are recorded in, with the labels left off the
+// markers, anything from a common ancestor left out and the two sides in sorted order, and
+// the id they hash to; nothing when the file has no conflicts, or one not closed
+pub fn normalize(content: &[u8]) -> Option<(String, Vec<u8>)> {
+    enum State {
+        Outside,
+        Ours,
+        Base,
+        Theirs,
+    }
+    let marker = |line: &[u8], c: u8| {
+        line.len() >= MARKER_SIZE
+            && line[..MARKER_SIZE].iter().all(|b| *b == c)
+            && matches!(line.get(MARKER_SIZE), None | Some(b' ' | b'\n'))
+    };
+
+    let mut hasher = Sha1::new();
+    let mut normalized = vec![];
+    let (mut one, mut two) = (vec![], vec![]);
+    let mut state = State::Outside;
+    let mut hunks = 0;
+    for line in content.split_inclusive(|b| *b == b'\n') {
+        state = match state {
+            State::Outside if marker(line, b'<') => State::Ours,
+            State::Outside => {
+                normalized.extend_from_slice(line);
+                State::Outside
+            }
+            State::Ours if marker(line, b'|') => State::Base,
+            State::Ours | State::Base if marker(line, b'=') => State::Theirs,
+            State::Ours => {
+                one.extend_from_slice(line);
+                State::Ours
+            }
+            State::Base => State::Base,
+            State::Theirs if marker(line, b'>') => {
+                if one > two {
+                    std::mem::swap(&mut one, &mut two);
+                }
+                for side in [&one, &two] {
+                    hasher.update(side);
+                    hasher.update([0]);
+                }
+                normalized.extend(format!("{}\n", "<".repeat(MARKER_SIZE)).as_bytes());
+                normalized.append(&mut one);
+                normalized.extend(format!("{}\n", "=".repeat(MARKER_SIZE)).as_bytes());
+                normalized.append(&mut two);
+                normalized.extend(format!("{}\n", ">".repeat(MARKER_SIZE)).as_bytes());
+                hunks += 1;
+                State::Outside
+            }
+            State::Theirs => {
+                two.extend_from_slice(line);
+                State::Theirs
+            }
+        };
+    }
+
+    match (state, hunks) {
+        (State::Outside, 1..) => Some((hex::encode(hasher.finalize()), normalized)),
+        _ => None,
+    }
+}
+
+// the conflicts MERGE_RR lists, as (id, path)
+pub fn read_merge_rr(repository: &Repository) -> Vec<(String, String)> {
+    let content = fs::read(repository.git_dir().join("MERGE_RR")).unwrap_or_default();
+    content
+        .split(|b| *b == 0)
+        .filter_map(|entry| {
+            let entry = String::from_utf8_lossy(entry);
+            let (id, path) = entry.split_once('\t')?;
+            Some((id.to_string(), path.to_string()))
+        })
+        .collect()
+}
+
+// "<id>\t<path>\0" for each conflict, and no MERGE_RR at all when there are none
+pub fn write_merge_rr(repository: &Repository, conflicts: &[(String, String)]) -> Result<()> {
+    let file = repository.git_dir().join("MERGE_RR");
+    if conflicts.is_empty() {
+        return match fs::remove_file(file) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error.into()),
+            _ => Ok(()),
+        };
+    }
+    let mut content = vec![];
+    for (id, path) in conflicts {
+        content.extend(format!("{}\t{}\0", id, path).as_bytes());
+    }
+    fs::write(file, content)?;
+    Ok(())
+}
+
+pub fn cache_dir(repository: &Repository) -> PathBuf {
+    repository.common_dir().join("rr-cache")
+}
+
+// records the preimage of each of the `unmerged` files it hasn't seen conflict in this merge,
+// then goes through every conflict of the merge: one with a recorded resolution is resolved
+// with it, and a file with no conflict left in it has its resolution recorded. Returns the
+// paths it resolved; `autoupdate` only says they will be staged
+pub fn rerere(
+    repository: &Repository,
+    work_tree: &Path,
+    unmerged: &[String],
+    autoupdate: bool,
+) -> Result<Vec<String>> {
+    let mut conflicts = read_merge_rr(repository);
+    for path in unmerged {
+        if conflicts.iter().any(|(_, known)| known == path) {
+            continue;
+        }
+        let Ok(content) = fs::read(paths::to_native(work_tree, path)) else {
+            continue;
+        };
+        let Some((id, preimage)) = normalize(&content) else {
+            continue;
+        };
+        let dir = cache_dir(repository).join(&id);
+        if !dir.join("preimage").exists() {
+            fs::create_dir_all(&dir)?;
+            fs::write(dir.join("preimage"), preimage)?;
+            eprintln!("Recorded preimage for '{}'", path);
+        }
+        conflicts.push((id, path.clone()));
+    }
+
+    let mut resolved = vec![];
+    let mut remaining = vec![];
+    for (id, path) in conflicts {
+        let dir = cache_dir(repository).join(&id);
+        let file = paths::to_native(work_tree, &path);
+        let content = fs::read(&file).unwrap_or_default();
+        match normalize(&content) {
+            Some((_, current)) => {
+                // the recorded resolution is merged in as the preimage's changes would be
+                let (Ok(preimage), Ok(postimage)) = (
+                    fs::read(dir.join("preimage")),
+                    fs::read(dir.join("postimage")),
+                ) else {
+                    remaining.push((id, path));
+                    continue;
+                };
+                let (content, clean) =
+                    merge::merge_content(&preimage, &current, &postimage, ("", ""), None);
+                if !clean {
+                    remaining.push((id, path));
+                    continue;
+                }
+                fs::write(&file, content)?;
+                match autoupdate {
+                    true => eprintln!("Staged '{}' using previous resolution.", path),
+                    false => eprintln!("Resolved '{}' using previous resolution.", path),
+                }
+                resolved.push(path);
+            }
+            None => {
+                if !dir.join("postimage").exists() && dir.join("preimage").exists() {
+                    fs::write(dir.join("postimage"), content)?;
+                    eprintln!("Recorded resolution for '{}'.", path);
+                }
+            }
+        }
+    }
+    write_merge_rr(repository, &remaining)?;
+
+    Ok(resolved)
+}
@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+
+use crate::{
+    error::{Error, Result},
+    index::IndexEntry,
+    objects::GitObjectType,
+    repository::{self, Repository},
+    sign,
+};
+
+// rewrite goes through every commit the refs lead to, parents first, and writes each one again
+// with its tree put through the path filters and its parents the rewritten ones. A commit that
+// comes out the same keeps its id, signature and all; one that the filters leave with nothing
+// of its own is dropped, its children taking its parent in its place
+
+// the headers that only hold for the commit as it was
+const DROPPED_HEADERS: [&str; 3] = ["gpgsig ", "gpgsig-sha256 ", "mergetag "];
+
+pub struct Rewrite<'a> {
+    repository: &'a Repository,
+    // the paths to keep, files or whole directories, or with invert_paths the ones to remove
+    pub paths: Vec<String>,
+    pub invert_paths: bool,
+    // (old, new) prefixes, tried in turn on the paths that are kept
+    pub renames: Vec<(String, String)>,
+    // files bigger than this are removed
+    pub max_blob_size: Option<u64>,
+    // keep commits that the filters empty, rather than dropping them
+    pub keep_empty: bool,
+    // each commit rewritten, by its old id: the new one, or nothing when it was dropped with
+    // no parent to stand for it
+    commits: HashMap<String, Option<String>>,
+    trees: HashMap<String, String>,
+    sizes: HashMap<String, u64>,
+}
+
+impl<'a> Rewrite<'a> {
+    pub fn new(repository: &'a Repository) -> Rewrite<'a> {
+        Rewrite {
+            repository,
+            paths: vec![],
+            invert_paths: false,
+            renames: vec![],
+            max_blob_size: None,
+            keep_empty: false,
+            commits: HashMap::new(),
+            trees: HashMap::new(),
+            sizes: HashMap::new(),
+        }
+    }
+
+    // rewrites the commits `tips` lead to, giving each tip's new id
+    pub fn rewrite(&mut self, tips: &[String]) -> Result<HashMap<String, Option<String>>> {
+        let repository = self.repository;
+        let commits = repository.rev_list(tips, &[])?;
+        let mut parents = HashMap::new();
+        for id in &commits {
+            parents.insert(id.clone(), repository.commit_parents(id)?);
+        }
+        let mut order = repository::topo_order(commits, &parents);
+        order.reverse();
+        for id in &order {
+            let new = self.rewrite_commit(id, &parents[id])?;
+            self.commits.insert(id.clone(), new);
+        }
+        Ok(tips
+            .iter()
+            .map(|tip| (tip.clone(), self.commits.get(tip).cloned().flatten()))
+            .collect())
+    }
+
+    // the object a ref pointing at `id` is to point at now: a rewritten commit, an annotated
+    // tag of one written again without its signature, which no longer holds, or anything else
+    // as it is; nothing when the commit was dropped
+    pub fn rewrite_ref(&mut self, id: &str) -> Result<Option<String>> {
+        let object = self.repository.find_object(id)?;
+        match object.object_type {
+            GitObjectType::Commit => Ok(self.commits.get(id).cloned().unwrap_or(Some(object.id))),
+            GitObjectType::Tag => {
+                let content = String::from_utf8_lossy(&object.data).into_owned();
+                let Some(target) = content
+                    .lines()
+                    .next()
+                    .and_then(|line| line.strip_prefix("object "))
+                else {
+                    return Err(Error::CorruptObject(object.id));
+                };
+                let Some(new_target) = self.rewrite_ref(target)? else {
+                    return Ok(None);
+                };
+                if new_target == target {
+                    return Ok(Some(object.id));
+                }
+                let (content, _) = sign::split_signature(&content);
+                let content = content.replacen(target, &new_target, 1);
+                let id = self
+                    .repository
+                    .write_object(GitObjectType::Tag, content.as_bytes())?;
+                Ok(Some(id))
+            }
+            _ => Ok(Some(object.id)),
+        }
+    }
+
+    fn rewrite_commit(&mut self, id: &str, old_parents: &[String]) -> Result<Option<String>> {
+        let repository = self.repository;
+        let object = repository.find_object(id)?;
+        let old_tree = repository.find_commit(id)?.tree;
+        let tree = self.rewrite_tree(&old_tree)?;
+
+        let mut parents: Vec<String> = vec![];
+        for parent in old_parents {
+            let new = match self.commits.get(parent) {
+                Some(new) => new.clone(),
+                None => Some(parent.clone()),
+            };
+            if let Some(new) = new.filter(|new| !parents.contains(new)) {
+                parents.push(new);
+            }
+        }
+
+        // a commit the filters leave the same as its only parent is dropped, unless it was
+        // made empty to begin with
+        let parent_tree = match parents.as_slice() {
+            [] => Some(repository::EMPTY_TREE.to_string()),
+            [parent] => Some(self.commit_tree(parent)?),
+            _ => None,
+        };
+        let old_parent_tree = match old_parents {
+            [] => Some(repository::EMPTY_TREE.to_string()),
+            [parent] => Some(repository.find_commit(parent)?.tree),
+            _ => None,
+        };
+        if !self.keep_empty
+            && parent_tree.as_ref() == Some(&tree)
+            && (old_parent_tree.as_ref() != Some(&old_tree) || old_parents.len() > 1)
+        {
+            return Ok(parents.pop());
+        }
+
+        if tree == old_tree && parents == old_parents {
+            self.trees.insert(id.to_string(), tree);
+            return Ok(Some(id.to_string()));
+        }
+
+        let content = commit_content(&object.data, &tree, &parents);
+        let new = repository.write_object(GitObjectType::Commit, &content)?;
+        self.trees.insert(new.clone(), tree);
+        Ok(Some(new))
+    }
+
+    fn commit_tree(&mut self, id: &str) -> Result<String> {
+        if let Some(tree) = self.trees.get(id) {
+            return Ok(tree.clone());
+        }
+        let tree = self.repository.find_commit(id)?.tree;
+        self.trees.insert(id.to_string(), tree.clone());
+        Ok(tree)
+    }
+
+    // the tree with the files the filters remove left out and the rest renamed
+    fn rewrite_tree(&mut self, id: &str) -> Result<String> {
+        let mut files = vec![];
+        for mut file in self.repository.tree_files(id, "")? {
+            let selected = self
+                .paths
+                .iter()
+                .any(|path| is_under(&file.path, path.trim_end_matches('/')));
+            if (!self.paths.is_empty() && selected == self.invert_paths) || self.too_big(&file)? {
+                continue;
+            }
+            for (old, new) in &self.renames {
+                let (old, new) = (old.trim_end_matches('/'), new.trim_end_matches('/'));
+                if is_under(&file.path, old) {
+                    file.path = match new.is_empty() {
+                        true => file.path[old.len()..].trim_start_matches('/').to_string(),
+                        false => format!("{}{}", new, &file.path[old.len()..]),
+                    };
+                    break;
+                }
+            }
+            files.push(file);
+        }
+
+        files.sort_by(|a, b| a.path.as_bytes().cmp(b.path.as_bytes()));
+        if let Some(pair) = files.windows(2).find(|pair| pair[0].path == pair[1].path) {
+            return Err(Error::RenameCollision(pair[0].path.clone()));
+        }
+        self.repository.write_tree_entries(&files)
+    }
+
+    fn too_big(&mut self, file: &IndexEntry) -> Result<bool> {
+        let Some(max) = self.max_blob_size else {
+            return Ok(false);
+        };
+        if file.mode == 0o160000 {
+            return Ok(false);
+        }
+        let size = match self.sizes.get(&file.id) {
+            Some(size) => *size,
+            None => {
+                let size = self.repository.find_object(&file.id)?.data.len() as u64;
+                self.sizes.insert(file.id.clone(), size);
+                size
+            }
+        };
+        Ok(size > max)
+    }
+}
+
+// a commit's content with another tree and parents; the other headers and the message stay as
+// they are, but not a signature or the tags merged in, which would no longer hold
+pub fn commit_content(data: &[u8], tree: &str, parents: &[String]) -> Vec<u8> {
+    let split = data
+        .windows(2)
+        .position(|pair| pair == b"\n\n")
+        .map_or(data.len(), |i| i + 1);
+    let (headers, message) = data.split_at(split);
+    let mut content = format!("tree {}\n", tree).into_bytes();
+    for parent in parents {
+        content.extend(format!("parent {}\n", parent).as_bytes());
+    }
+    let mut in_dropped = false;
+    for line in headers.split_inclusive(|b| *b == b'\n') {
+        if line.starts_with(b" ") && in_dropped {
+            continue;
+        }
+        in_dropped = DROPPED_HEADERS
+            .iter()
+            .any(|header| line.starts_with(header.as_bytes()));
+        if !in_dropped && !line.starts_with(b"tree ") && !line.starts_with(b"parent ") {
+            content.extend_from_slice(line);
+        }
+    }
+    content.extend_from_slice(message);
+    content
+}
+
+// whether a commit has a signature, which commit_content leaves out
+pub fn has_signature(data: &[u8]) -> bool {
+    data.split(|b| *b == b'\n')
+        .take_while(|line| !line.is_empty())
+        .any(|line| line.starts_with(b"gpgsig"))
+}
+
+// whether `path` is `prefix` or a file in the directory it names
+fn is_under(path: &str, prefix: &str) -> bool {
+    prefix.is_empty()
+        || path
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
@@ -0,0 +1,362 @@
+use std::{
+    env, fs,
+    io::{self, Read, Write},
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    config::Config,
+    error::{Error, Result},
+    paths,
+};
+
+// how each kind of signature starts, and so where a tag's message ends
+const PGP_SIGNATURE: &str = "-----BEGIN PGP SIGNATURE-----";
+const PGP_MESSAGE: &str = "-----BEGIN PGP MESSAGE-----";
+const SSH_SIGNATURE: &str = "-----BEGIN SSH SIGNATURE-----";
+
+// what checking a signature came to, what the checker had to say about it, and its output
+// for scripts, which for gpg is the status lines rather than the report
+pub struct Verification {
+    pub good: bool,
+    pub report: String,
+    pub raw: String,
+}
+
+// something that makes detached signatures of commits and tags, and checks them
+pub trait Signer {
+    // the armored signature of `payload`, as it goes into the object
+    fn sign(&self, payload: &[u8]) -> Result<String>;
+    fn verify(&self, payload: &[u8], signature: &str) -> Result<Verification>;
+}
+
+// gpg, or gpg.program, signing with a key it knows by id, or by the committer's identity
+pub struct Gpg {
+    program: String,
+    key: String,
+}
+
+impl Gpg {
+    pub fn new(config: &Config, key: &str) -> Gpg {
+        let program = config
+            .get("gpg.openpgp.program")
+            .or_else(|| config.get("gpg.program"))
+            .unwrap_or("gpg");
+        Gpg {
+            program: program.to_string(),
+            key: key.to_string(),
+        }
+    }
+}
+
+impl Signer for Gpg {
+    // gpg reports on the status fd whether it made the signature, which its exit code alone
+    // doesn't say
+    fn sign(&self, payload: &[u8]) -> Result<String> {
+        let (status, signature, report) = run(
+            Command::new(&self.program).args(["--status-fd=2", "-bsau", &self.key]),
+            payload,
+        )
+        .map_err(|_| Error::SigningFailed("gpg".to_string()))?;
+        if !status.success() || !report.contains("\n[GNUPG:] SIG_CREATED ") {
+            for line in report.lines().filter(|line| !line.starts_with("[GNUPG:] ")) {
+                eprintln!("{}", line);
+            }
+            return Err(Error::SigningFailed("gpg".to_string()));
+        }
+        Ok(signature)
+    }
+
+    fn verify(&self, payload: &[u8], signature: &str) -> Result<Verification> {
+        let file = TempFile::new(".git_vtag_tmp", signature.as_bytes())?;
+        let (_, status, report) = run(
+            Command::new(&self.program)
+                .args(["--keyid-format=long", "--status-fd=1", "--verify"])
+                .arg(&file.0)
+                .arg("-"),
+            payload,
+        )?;
+        let good = status
+            .lines()
+            .any(|line| line.starts_with("[GNUPG:] GOODSIG "));
+        Ok(Verification {
+            good,
+            report,
+            raw: status,
+        })
+    }
+}
+
+// ssh-keygen, or gpg.ssh.program, signing with a key file or a public key whose private half
+// is in the agent, and checking signatures against the keys gpg.ssh.allowedSignersFile allows
+pub struct Ssh {
+    program: String,
+    key: String,
+    default_key_command: Option<String>,
+    allowed_signers: Option<PathBuf>,
+}
+
+impl Ssh {
+    pub fn new(config: &Config, key: &str) -> Ssh {
+        Ssh {
+            program: config
+                .get("gpg.ssh.program")
+                .unwrap_or("ssh-keygen")
+                .to_string(),
+            key: key.to_string(),
+            default_key_command: config.get("gpg.ssh.defaultKeyCommand").map(str::to_string),
+            allowed_signers: config
+                .get("gpg.ssh.allowedSignersFile")
+                .map(paths::expand_home),
+        }
+    }
+
+    // the key, or the first one gpg.ssh.defaultKeyCommand gives when none was set
+    fn key(&self) -> Result<String> {
+        if !self.key.is_empty() {
+            return Ok(self.key.clone());
+        }
+        let Some(command) = &self.default_key_command else {
+            return Err(Error::NoSigningKey);
+        };
+        let (status, output, report) = run(Command::new("sh").args(["-c", command]), &[])?;
+        if !status.success() {
+            return Err(Error::DefaultKeyCommand(format!(
+                "failed: {}",
+                report.trim_end()
+            )));
+        }
+        output
+            .lines()
+            .find(|line| is_literal_key(line))
+            .map(str::to_string)
+            .ok_or_else(|| {
+                Error::DefaultKeyCommand(format!(
+                    "succeeded but returned no keys: {}",
+                    output.trim_end()
+                ))
+            })
+    }
+}
+
+impl Signer for Ssh {
+    fn sign(&self, payload: &[u8]) -> Result<String> {
+        let key = self.key()?;
+        let mut command = Command::new(&self.program);
+        command.args(["-Y", "sign", "-n", "git", "-f"]);
+        // ssh-keygen only takes a key as a file, and only signs with a public one's private half
+        // from the agent
+        let _literal;
+        match is_literal_key(&key) {
+            true => {
+                let key = key.strip_prefix("key::").unwrap_or(&key);
+                _literal = TempFile::new(".git_signing_key_tmp", key.as_bytes())?;
+                command.arg(&_literal.0).arg("-U");
+            }
+            false => {
+                command.arg(paths::expand_home(&key));
+            }
+        }
+        let (status, signature, report) = run(&mut command, payload)
+            .map_err(|_| Error::SigningFailed("ssh-keygen".to_string()))?;
+        if !status.success() || !signature.starts_with(SSH_SIGNATURE) {
+            eprintln!("error: {}", report.trim_end());
+            return Err(Error::SigningFailed("ssh-keygen".to_string()));
+        }
+        Ok(signature)
+    }
+
+    // the principals the allowed signers file has for the signature's key are tried in turn;
+    // with none, the signature is still checked so that the report says whose key made it, but
+    // it doesn't count as good
+    fn verify(&self, payload: &[u8], signature: &str) -> Result<Verification> {
+        let Some(allowed_signers) = self.allowed_signers.as_ref().filter(|file| file.exists())
+        else {
+            return Err(Error::NoAllowedSigners);
+        };
+        let file = TempFile::new(".git_vtag_tmp", signature.as_bytes())?;
+        let (status, principals, principals_report) = run(
+            Command::new(&self.program)
+                .args(["-Y", "find-principals", "-f"])
+                .arg(allowed_signers)
+                .arg("-s")
+                .arg(&file.0),
+            &[],
+        )?;
+
+        let mut good = false;
+        let (mut output, mut report) = (String::new(), String::new());
+        match status.success() && !principals.trim().is_empty() {
+            true => {
+                for principal in principals.lines().filter(|line| !line.is_empty()) {
+                    let status;
+                    (status, output, report) = run(
+                        Command::new(&self.program)
+                            .args(["-Y", "verify", "-n", "git", "-f"])
+                            .arg(allowed_signers)
+                            .args(["-I", principal, "-s"])
+                            .arg(&file.0),
+                        payload,
+                    )?;
+                    good = status.success() && output.starts_with("Good");
+                    if good {
+                        break;
+                    }
+                }
+            }
+            false => {
+                (_, output, report) = run(
+                    Command::new(&self.program)
+                        .args(["-Y", "check-novalidate", "-n", "git", "-s"])
+                        .arg(&file.0),
+                    payload,
+                )?;
+            }
+        }
+
+        let mut all = String::new();
+        for part in [output.trim(), principals_report.trim(), report.trim()] {
+            if !part.is_empty() {
+                all += part;
+                all.push('\n');
+            }
+        }
+        Ok(Verification {
+            good,
+            raw: all.clone(),
+            report: all,
+        })
+    }
+}
+
+// the signer gpg.format asks for, with `key` as the signing key, or when it's empty the
+// signer's default one
+pub fn signer(config: &Config, key: &str, identity: &str) -> Result<Box<dyn Signer>> {
+    match config.get("gpg.format").unwrap_or("openpgp") {
+        "openpgp" => match key.is_empty() {
+            true => Ok(Box::new(Gpg::new(config, identity))),
+            false => Ok(Box::new(Gpg::new(config, key))),
+        },
+        "ssh" => Ok(Box::new(Ssh::new(config, key))),
+        format => Err(Error::InvalidSigningFormat(format.to_string())),
+    }
+}
+
+// the signer that can check `signature`, by the kind it is rather than by gpg.format
+pub fn verifier(config: &Config, signature: &str) -> Box<dyn Signer> {
+    match signature.starts_with(SSH_SIGNATURE) {
+        true => Box::new(Ssh::new(config, "")),
+        false => Box::new(Gpg::new(config, "")),
+    }
+}
+
+// a tag's content split where the signature after its message starts, if it has one
+pub fn split_signature(content: &str) -> (&str, Option<&str>) {
+    let start = content
+        .match_indices('\n')
+        .map(|(i, _)| i + 1)
+        .rev()
+        .find(|&i| {
+            [PGP_SIGNATURE, PGP_MESSAGE, SSH_SIGNATURE]
+                .iter()
+                .any(|armor| content[i..].starts_with(armor))
+        });
+    match start {
+        Some(start) => (&content[..start], Some(&content[start..])),
+        None => (content, None),
+    }
+}
+
+// a commit's content without its gpgsig header, which is what was signed, and the signature
+// the header holds, if it has one
+pub fn split_commit_signature(content: &str) -> (String, Option<String>) {
+    let (headers, message) = content.split_once("\n\n").unwrap_or((content, ""));
+    let mut payload = String::new();
+    let mut signature: Option<String> = None;
+    let mut in_signature = false;
+    for line in headers.lines() {
+        if let Some(value) = line.strip_prefix("gpgsig ") {
+            signature = Some(format!("{}\n", value));
+            in_signature = true;
+        } else if let Some(value) = line.strip_prefix(' ').filter(|_| in_signature) {
+            signature
+                .as_mut()
+                .unwrap()
+                .push_str(&format!("{}\n", value));
+        } else {
+            in_signature = false;
+            payload.push_str(&format!("{}\n", line));
+        }
+    }
+    if content.contains("\n\n") {
+        payload.push('\n');
+        payload.push_str(message);
+    }
+    (payload, signature)
+}
+
+// a commit with its signature in a gpgsig header after the others, each line past the first
+// continued with a space
+pub fn add_signature_header(commit: &str, signature: &str) -> String {
+    let (headers, message) = commit.split_once("\n\n").unwrap_or((commit, ""));
+    let signature = signature.trim_end_matches('\n').replace('\n', "\n ");
+    format!("{}\ngpgsig {}\n\n{}", headers, signature, message)
+}
+
+// a public key given as is, rather than the path of a key file
+fn is_literal_key(key: &str) -> bool {
+    key.starts_with("key::") || key.starts_with("ssh-")
+}
+
+// a file in the temporary directory that the signing programs read from, removed when dropped
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(prefix: &str, content: &[u8]) -> io::Result<TempFile> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "{}{}_{}",
+            prefix,
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = env::temp_dir().join(name);
+        fs::write(&path, content)?;
+        Ok(TempFile(path))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+// runs `command` with `input` on its stdin, giving its exit status, stdout and stderr
+fn run(
+    command: &mut Command,
+    input: &[u8],
+) -> io::Result<(std::process::ExitStatus, String, String)> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // fed from another thread, so that a signer writing as it reads can't deadlock us
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let mut stderr = child.stderr.take().unwrap();
+    let reader = std::thread::spawn(move || {
+        let mut report = String::new();
+        stderr.read_to_string(&mut report).map(|_| report)
+    });
+    let mut output = String::new();
+    child.stdout.take().unwrap().read_to_string(&mut output)?;
+    let _ = writer.join().unwrap();
+    let report = reader.join().unwrap()?;
+
+    Ok((child.wait()?, output, report))
+}
@@ -0,0 +1,282 @@
+use std::collections::HashMap;
+
+use crate::{
+    config::{self, Config},
+    error::{Error, Result},
+    name_rev::{NameRev, RefFilter},
+    objects::GitObjectType,
+    pathspec::Pathspecs,
+    pretty,
+    repository::Repository,
+};
+
+// a submodule is a repository of its own checked out inside the work tree, which the index and
+// trees record as a gitlink, the commit it should be at. .gitmodules maps the paths of the
+// gitlinks to names, and gives each a url; the repository's own config only has the
+// submodules that are in use, with the url to fetch them from
+
+// the variables that point git at a repository, which a command run in a submodule mustn't
+// inherit from the superproject
+pub const LOCAL_ENV: &[&str] = &[
+    "GIT_ALTERNATE_OBJECT_DIRECTORIES",
+    "GIT_COMMON_DIR",
+    "GIT_CONFIG",
+    "GIT_DIR",
+    "GIT_GRAFT_FILE",
+    "GIT_IMPLICIT_WORK_TREE",
+    "GIT_INDEX_FILE",
+    "GIT_NO_REPLACE_OBJECTS",
+    "GIT_OBJECT_DIRECTORY",
+    "GIT_PREFIX",
+    "GIT_REPLACE_REF_BASE",
+    "GIT_SHALLOW_FILE",
+    "GIT_WORK_TREE",
+];
+
+// describe gives up looking for a closer tag once it has this many
+const MAX_CANDIDATES: usize = 10;
+
+pub struct Submodule {
+    pub name: String,
+    pub path: String,
+    pub url: Option<String>,
+}
+
+// the settings of .gitmodules in the work tree, or else in the index, or else in HEAD
+pub fn gitmodules(repository: &Repository) -> Result<Config> {
+    if let Some(work_tree) = repository.work_tree() {
+        let file = work_tree.join(".gitmodules");
+        if file.exists() {
+            return Ok(Config::from_file(&file));
+        }
+    }
+    let mut entry = repository.index()?.find(".gitmodules", 0).cloned();
+    if entry.is_none() {
+        if let Some(head) = repository.head() {
+            let tree = repository.find_commit(&head)?.tree;
+            entry = repository.find_file(&tree, ".gitmodules")?;
+        }
+    }
+    match entry {
+        Some(entry) => {
+            let content = repository.entry_content(&entry)?;
+            Ok(Config::parse(&String::from_utf8_lossy(&content)))
+        }
+        None => Ok(Config::default()),
+    }
+}
+
+// the submodule .gitmodules has at `path`
+pub fn find(gitmodules: &Config, path: &str) -> Option<Submodule> {
+    let name = gitmodules.entries().find_map(|(key, value)| {
+        let name = key.strip_prefix("submodule.")?.strip_suffix(".path")?;
+        (value? == path).then_some(name)
+    })?;
+    Some(Submodule {
+        name: name.to_string(),
+        path: path.to_string(),
+        url: gitmodules
+            .get(&format!("submodule.{}.url", name))
+            .map(str::to_string),
+    })
+}
+
+// whether the repository uses the submodule: submodule.<name>.active if it's set, or else
+// whether submodule.active matches its path if that is, or else whether it has a url
+pub fn is_active(config: &Config, submodule: &Submodule) -> Result<bool> {
+    if let Some(active) = config.get(&format!("submodule.{}.active", submodule.name)) {
+        return Ok(config::parse_bool(active).unwrap_or(false));
+    }
+    let patterns = config.get_all("submodule.active");
+    if !patterns.is_empty() {
+        let patterns: Vec<String> = patterns.into_iter().map(str::to_string).collect();
+        return Ok(Pathspecs::parse(None, &patterns)?.matches(&submodule.path));
+    }
+    Ok(config
+        .get(&format!("submodule.{}.url", submodule.name))
+        .is_some())
+}
+
+// the remote a repository fetches from: the one its branch tracks, or else the only one it
+// has, or else "origin"
+pub fn default_remote(repository: &Repository) -> String {
+    let config = Config::for_repository(repository.common_dir());
+    let branch = repository.head_target();
+    if let Some(branch) = branch
+        .as_deref()
+        .and_then(|b| b.strip_prefix("refs/heads/"))
+    {
+        if let Some(remote) = config.get(&format!("branch.{}.remote", branch)) {
+            return remote.to_string();
+        }
+    }
+    let mut remotes: Vec<&str> = config
+        .entries()
+        .filter_map(|(key, _)| {
+            let (name, _) = key.strip_prefix("remote.")?.rsplit_once('.')?;
+            Some(name)
+        })
+        .collect();
+    remotes.sort_unstable();
+    remotes.dedup();
+    match remotes[..] {
+        [remote] => remote.to_string(),
+        _ => "origin".to_string(),
+    }
+}
+
+// `url` from .gitmodules, which starts with "./" or "../", resolved against `remote_url`, the
+// url of the superproject's remote. Each "../" takes a component off the remote's url, which
+// for "host:path" urls can be what follows the colon. A relative result is made relative to
+// the submodule's work tree by `up_path`, the way up from it to the superproject's
+pub fn relative_url(remote_url: &str, url: &str, up_path: Option<&str>) -> Result<String> {
+    let mut remote = remote_url
+        .strip_suffix('/')
+        .unwrap_or(remote_url)
+        .to_string();
+    let is_relative = is_local(&remote) && !remote.starts_with('/');
+    if is_relative && !remote.starts_with("./") && !remote.starts_with("../") {
+        remote = format!("./{}", remote);
+    }
+
+    let mut url = url;
+    let mut colon = false;
+    loop {
+        if let Some(rest) = url.strip_prefix("../") {
+            url = rest;
+            colon |= chop_last_dir(&mut remote, is_relative)?;
+        } else if let Some(rest) = url.strip_prefix("./") {
+            url = rest;
+        } else {
+            break;
+        }
+    }
+    let mut resolved = format!("{}{}{}", remote, if colon { ":" } else { "/" }, url);
+    if url.ends_with('/') {
+        resolved.pop();
+    }
+    let resolved = resolved.strip_prefix("./").unwrap_or(&resolved);
+    Ok(match up_path {
+        Some(up_path) if is_relative => format!("{}{}", up_path, resolved),
+        _ => resolved.to_string(),
+    })
+}
+
+// takes the last component off a url, saying whether it came after a colon
+fn chop_last_dir(url: &mut String, is_relative: bool) -> Result<bool> {
+    if let Some(slash) = url.rfind('/') {
+        url.truncate(slash);
+        return Ok(false);
+    }
+    if let Some(colon) = url.rfind(':') {
+        url.truncate(colon);
+        return Ok(true);
+    }
+    if is_relative || url == "." {
+        return Err(Error::CannotStripUrl(url.clone()));
+    }
+    *url = ".".to_string();
+    Ok(false)
+}
+
+// whether a url is a path rather than "host:path" for ssh
+fn is_local(url: &str) -> bool {
+    match (url.find(':'), url.find('/')) {
+        (None, _) => true,
+        (Some(colon), Some(slash)) => slash < colon,
+        (Some(_), None) => false,
+    }
+}
+
+// "../" for each directory of `path`, the way up from a submodule's work tree to the
+// superproject's
+pub fn up_path(path: &str) -> String {
+    "../".repeat(path.trim_end_matches('/').split('/').count())
+}
+
+// a name for the commit `id` of a submodule, the first of what describe gives with annotated
+// tags, with all tags, with --contains, and with all refs and --always
+pub fn rev_name(repository: &Repository, id: &str) -> Option<String> {
+    if !repository.has_object(id) {
+        return None;
+    }
+    // each commit a ref points at, by the best name for it: annotated tags beat lightweight
+    // ones, which beat other refs, and otherwise the first ref by name wins
+    let mut names: HashMap<String, (String, usize)> = HashMap::new();
+    for (name, target) in repository.references() {
+        let Ok(commit) = repository.peel(&target, GitObjectType::Commit) else {
+            continue;
+        };
+        let priority = match name.starts_with("refs/tags/") {
+            true => match repository.find_object(&target) {
+                Ok(object) if object.object_type == GitObjectType::Tag => 2,
+                _ => 1,
+            },
+            false => 0,
+        };
+        match names.get(&commit.id) {
+            Some((_, best)) if *best >= priority => {}
+            _ => {
+                names.insert(commit.id, (name, priority));
+            }
+        }
+    }
+
+    for least in [2, 1] {
+        let tags: HashMap<&str, &str> = names
+            .iter()
+            .filter(|(_, (_, priority))| *priority >= least)
+            .map(|(commit, (name, _))| (commit.as_str(), &name["refs/tags/".len()..]))
+            .collect();
+        if let Some(name) = describe(repository, id, &tags) {
+            return Some(name);
+        }
+    }
+    let filter = RefFilter {
+        tags_only: true,
+        name_only: true,
+        patterns: vec![],
+        exclude: vec![],
+    };
+    if let Some(name) = NameRev::new(repository, &filter, None)
+        .ok()
+        .and_then(|names| names.name(id))
+    {
+        return Some(name);
+    }
+    let refs: HashMap<&str, &str> = names
+        .iter()
+        .map(|(commit, (name, _))| (commit.as_str(), &name["refs/".len()..]))
+        .collect();
+    Some(describe(repository, id, &refs).unwrap_or_else(|| pretty::abbreviate(id).to_string()))
+}
+
+// `id` by the name `names` gives it, or else as "<name>-<n>-g<abbreviated id>" after the
+// named ancestor that leaves the fewest commits, `n`, of its history out
+fn describe(repository: &Repository, id: &str, names: &HashMap<&str, &str>) -> Option<String> {
+    if let Some(name) = names.get(id) {
+        return Some(name.to_string());
+    }
+    let candidates: Vec<String> = repository
+        .ancestors(id)
+        .filter(|ancestor| names.contains_key(ancestor.as_str()))
+        .take(MAX_CANDIDATES)
+        .collect();
+    let mut best: Option<(usize, &str)> = None;
+    for candidate in &candidates {
+        let depth = repository
+            .rev_list(&[id.to_string()], std::slice::from_ref(candidate))
+            .ok()?
+            .len();
+        if best.is_none_or(|(least, _)| depth < least) {
+            best = Some((depth, candidate));
+        }
+    }
+    let (depth, candidate) = best?;
+    Some(format!(
+        "{}-{}-g{}",
+        names[candidate],
+        depth,
+        pretty::abbreviate(id)
+    ))
+}
@@ -0,0 +1,327 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    error::{Error, Result},
+    objects::GitObjectType,
+    pretty,
+    repository::{self, Repository},
+};
+
+// a subtree is another project's history merged in as a directory. The commits that bring it
+// in say so in their messages: "git-subtree-dir: <dir>", and "git-subtree-split: <commit>",
+// the commit of the project's own history the directory has from then on. A merge of that
+// history also has "git-subtree-mainline: <commit>", the parent it was merged into, while a
+// squash of the project's changes into a single commit has only the split. Splitting the
+// directory back out gives a history of commits with only what's in it, which joins up with
+// the project's own history wherever that was merged in
+
+// a commit that brought the subtree in: `mainline` and `split` are what its message says
+pub struct Join {
+    pub commit: String,
+    pub mainline: Option<String>,
+    pub split: String,
+}
+
+// the commits reachable from `rev` that brought in the subtree at `dir`, newest first; with
+// `adds_only`, only those that added it
+pub fn joins(repository: &Repository, rev: &str, dir: &str, adds_only: bool) -> Result<Vec<Join>> {
+    let add = format!("Add '{}/' from commit '", dir);
+    let is_dir = |line: &str| {
+        line.strip_prefix("git-subtree-dir: ")
+            .is_some_and(|rest| rest.trim_end_matches('/') == dir)
+    };
+    let mut joins = vec![];
+    for commit in repository.rev_list(&[rev.to_string()], &[])? {
+        let message = repository.find_commit(&commit)?.message;
+        let matches = match adds_only {
+            true => message.lines().any(|line| line.starts_with(&add)),
+            false => message.lines().any(is_dir),
+        };
+        if !matches {
+            continue;
+        }
+        let (mut mainline, mut split) = (None, None);
+        for line in message.lines() {
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some("git-subtree-mainline:"), Some(id)) => mainline = Some(id.to_string()),
+                (Some("git-subtree-split:"), Some(id)) => {
+                    let resolved = repository
+                        .rev_parse(id)
+                        .and_then(|id| repository.peel(&id, GitObjectType::Commit));
+                    match resolved {
+                        Ok(object) => split = Some(object.id),
+                        Err(_) => {
+                            return Err(Error::UnresolvedSplit(id.to_string(), commit.clone()))
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        if let Some(split) = split {
+            joins.push(Join {
+                commit,
+                mainline,
+                split,
+            });
+        }
+    }
+    Ok(joins)
+}
+
+// the last squash of the subtree at `dir` into HEAD's history, and the commit of the
+// project's history it is at; a merge of that history counts as a squash of it
+pub fn latest_squash(repository: &Repository, dir: &str) -> Result<Option<(String, String)>> {
+    let Some(head) = repository.head() else {
+        return Ok(None);
+    };
+    let Some(join) = joins(repository, &head, dir, false)?.into_iter().next() else {
+        return Ok(None);
+    };
+    let squash = match join.mainline {
+        Some(_) => match repository.commit_parents(&join.commit)?.get(1) {
+            Some(parent) => parent.clone(),
+            None => return Err(Error::ObjectNotFound(format!("{}^2", join.commit))),
+        },
+        None => join.commit,
+    };
+    Ok(Some((squash, join.split)))
+}
+
+// the message of a commit squashing the project's history from `old` to `new`, listing the
+// commits that were added and those that were taken away
+pub fn squash_message(
+    repository: &Repository,
+    dir: &str,
+    old: Option<&str>,
+    new: &str,
+) -> Result<String> {
+    let mut message = match old {
+        Some(old) => {
+            let mut message = format!(
+                "Squashed '{}/' changes from {}..{}\n\n",
+                dir,
+                pretty::abbreviate(old),
+                pretty::abbreviate(new)
+            );
+            let (old, new) = (old.to_string(), new.to_string());
+            for (include, exclude, marker) in [(&new, &old, ""), (&old, &new, "REVERT: ")] {
+                let commits = repository
+                    .rev_list(std::slice::from_ref(include), std::slice::from_ref(exclude))?;
+                for id in commits {
+                    let commit = repository.find_commit(&id)?;
+                    message += &format!(
+                        "{}{} {}\n",
+                        marker,
+                        pretty::abbreviate(&id),
+                        pretty::subject(&commit.message)
+                    );
+                }
+            }
+            message
+        }
+        None => format!(
+            "Squashed '{}/' content from commit {}\n",
+            dir,
+            pretty::abbreviate(new)
+        ),
+    };
+    message += &format!("\ngit-subtree-dir: {}\ngit-subtree-split: {}\n", dir, new);
+    Ok(message)
+}
+
+// the tree at `dir` in a commit, if it has one there
+pub fn subtree(repository: &Repository, commit: &str, dir: &str) -> Result<Option<String>> {
+    let mut tree = repository.peel(commit, GitObjectType::Tree)?.id;
+    for name in dir.split('/') {
+        let entry = repository
+            .tree_entries(&tree)?
+            .into_iter()
+            .find(|entry| entry.name == name && entry.object_type() == GitObjectType::Tree);
+        match entry {
+            Some(entry) => tree = entry.id,
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(tree))
+}
+
+// makes the history of the directory `dir` alone, commit by commit: a commit whose directory
+// is the same as that of one of its parents in the new history becomes that parent, and any
+// other is copied with the directory as its tree, keeping its authorship and message
+pub struct Split<'a> {
+    repository: &'a Repository,
+    dir: String,
+    annotate: String,
+    quiet: bool,
+    // the commit of the new history each commit became
+    new: HashMap<String, String>,
+    // the commits without the directory
+    no_tree: HashSet<String>,
+    // the last commit split, and the one it became
+    pub latest: Option<(String, String)>,
+    // for the progress line: the commits of the history given, those split, and those split
+    // that weren't in it
+    count: usize,
+    total: usize,
+    created: usize,
+    extra: usize,
+}
+
+impl<'a> Split<'a> {
+    pub fn new(repository: &'a Repository, dir: &str, annotate: &str, quiet: bool) -> Self {
+        Split {
+            repository,
+            dir: dir.to_string(),
+            annotate: annotate.to_string(),
+            quiet,
+            new: HashMap::new(),
+            no_tree: HashSet::new(),
+            latest: None,
+            count: 0,
+            total: 0,
+            created: 0,
+            extra: 0,
+        }
+    }
+
+    // the history of `onto` is already that of the directory alone, so its commits stay as
+    // they are
+    pub fn onto(&mut self, onto: &str) -> Result<()> {
+        for id in self.repository.rev_list(&[onto.to_string()], &[])? {
+            self.new.insert(id.clone(), id);
+        }
+        Ok(())
+    }
+
+    // splits the history of `rev`, picking up from the joins in it: the mainline a project's
+    // history was merged into became what was merged, as did a squash of it
+    pub fn run(&mut self, rev: &str, ignore_joins: bool) -> Result<()> {
+        let mut exclude = vec![];
+        for join in joins(self.repository, rev, &self.dir, ignore_joins)? {
+            let Some(mainline) = join.mainline else {
+                self.new.entry(join.commit).or_insert(join.split);
+                continue;
+            };
+            self.new
+                .entry(mainline.clone())
+                .or_insert(join.split.clone());
+            self.new
+                .entry(join.split.clone())
+                .or_insert(join.split.clone());
+            for commit in [&mainline, &join.split] {
+                if let Ok(Some(parent)) = self
+                    .repository
+                    .commit_parents(commit)
+                    .map(|parents| parents.into_iter().next())
+                {
+                    exclude.push(parent);
+                }
+            }
+        }
+
+        let commits = self.repository.rev_list(&[rev.to_string()], &exclude)?;
+        let mut parents = HashMap::new();
+        for id in &commits {
+            parents.insert(id.clone(), self.repository.commit_parents(id)?);
+        }
+        let mut commits = repository::topo_order(commits, &parents);
+        commits.reverse();
+        self.total = commits.len();
+        for id in &commits {
+            self.count += 1;
+            self.split(id, &parents[id])?;
+        }
+        Ok(())
+    }
+
+    fn split(&mut self, id: &str, parents: &[String]) -> Result<()> {
+        if !self.quiet {
+            eprint!(
+                "{}/{} ({}) [{}]\r",
+                self.count, self.total, self.created, self.extra
+            );
+        }
+        if self.new.contains_key(id) {
+            return Ok(());
+        }
+        self.created += 1;
+        // parents left out of the history given are split first
+        for parent in parents {
+            if !self.new.contains_key(parent) && !self.no_tree.contains(parent) {
+                self.extra += 1;
+                let grandparents = self.repository.commit_parents(parent)?;
+                self.split(parent, &grandparents)?;
+            }
+        }
+        let new_parents: Vec<String> = parents
+            .iter()
+            .filter_map(|parent| self.new.get(parent).cloned())
+            .collect();
+
+        let Some(tree) = subtree(self.repository, id, &self.dir)? else {
+            self.no_tree.insert(id.to_string());
+            if !new_parents.is_empty() {
+                self.new.insert(id.to_string(), id.to_string());
+            }
+            return Ok(());
+        };
+        let new = self.copy_or_skip(id, &tree, &new_parents)?;
+        self.new.insert(id.to_string(), new.clone());
+        self.latest = Some((id.to_string(), new));
+        Ok(())
+    }
+
+    // a parent with the same tree stands for the commit, unless another parent has history
+    // that would be lost, or no one parent has all of the others' history
+    fn copy_or_skip(&self, id: &str, tree: &str, new_parents: &[String]) -> Result<String> {
+        let mut identical: Option<&String> = None;
+        let mut non_identical = None;
+        let mut copy = false;
+        let mut parents: Vec<&str> = vec![];
+        for parent in new_parents {
+            if self.repository.find_commit(parent)?.tree == tree {
+                match identical {
+                    Some(other) => {
+                        let base = self
+                            .repository
+                            .merge_bases(other, std::slice::from_ref(parent))?
+                            .into_iter()
+                            .next();
+                        if base.as_ref() == Some(other) {
+                            identical = Some(parent);
+                        } else if base.as_ref() != Some(parent) {
+                            copy = true;
+                        }
+                    }
+                    None => identical = Some(parent),
+                }
+            } else {
+                non_identical = Some(parent);
+            }
+            if !parents.contains(&parent.as_str()) {
+                parents.push(parent);
+            }
+        }
+
+        if let (Some(identical), Some(non_identical)) = (identical, non_identical) {
+            let extras = self.repository.rev_list(
+                std::slice::from_ref(non_identical),
+                std::slice::from_ref(identical),
+            )?;
+            copy |= !extras.is_empty();
+        }
+        if let Some(identical) = identical.filter(|_| !copy) {
+            return Ok(identical.clone());
+        }
+        let commit = self.repository.find_commit(id)?;
+        self.repository.create_commit(
+            tree,
+            &parents,
+            &commit.author,
+            &commit.committer,
+            &format!("{}{}", self.annotate, commit.message),
+        )
+    }
+}
@@ -0,0 +1,66 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    index::IndexEntry,
+    objects::GitObjectType,
+    repository::{Repository, Signature},
+};
+
+// a directory of its own under the temp dir, removed when dropped, even when an assertion
+// fails
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let count = COUNT.fetch_add(1, Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("rgit-{}-{}-{}", name, std::process::id(), count));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+// a repository with a work tree at `path`, on the branch "main"
+pub fn init(path: &Path) -> Repository {
+    Repository::init(&path.join(".git"), Some(path), "main").unwrap()
+}
+
+// a commit of `files`, each a path, its mode and its content, with the same author and time
+// every time so that ids don't change between runs
+pub fn commit(
+    repository: &Repository,
+    files: &[(&str, u32, &str)],
+    parents: &[&str],
+    message: &str,
+) -> String {
+    let mut entries: Vec<IndexEntry> = files
+        .iter()
+        .map(|(path, mode, content)| {
+            let id = repository
+                .write_object(GitObjectType::Blob, content.as_bytes())
+                .unwrap();
+            IndexEntry::new(path, *mode, &id)
+        })
+        .collect();
+    entries.sort_by(|a, b| a.path.as_bytes().cmp(b.path.as_bytes()));
+    let tree = repository.write_tree_entries(&entries).unwrap();
+    let signature = Signature::new("A U Thor", "author@example.com", 1700000000, 0);
+    repository
+        .create_commit(&tree, parents, &signature, &signature, message)
+        .unwrap()
+}
@@ -0,0 +1,117 @@
+use std::{
+    env,
+    fs::OpenOptions,
+    io,
+    path::Path,
+    sync::{Mutex, Once},
+};
+
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::{
+    filter::Targets,
+    fmt::{self, writer::BoxMakeWriter},
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+    Layer,
+};
+
+use crate::convert::shell_quote;
+
+// what GIT_TRACE and GIT_TRACE_PACKET are to git, RGIT_TRACE and RGIT_TRACE_PACKET are here:
+// "1", "2" or "true" log to stderr, an absolute path appends to that file, and unset, "0" or
+// "false" log nothing. Events are sent with one of these targets:
+//   command  RGIT_TRACE, the commands run
+//   object   RGIT_TRACE, the objects read and written
+//   packet   RGIT_TRACE_PACKET, the pkt-lines sent and received
+const TRACE: (&str, &[&str]) = ("RGIT_TRACE", &["command", "object"]);
+const TRACE_PACKET: (&str, &[&str]) = ("RGIT_TRACE_PACKET", &["packet"]);
+
+static INIT: Once = Once::new();
+
+// sets up the logging the environment asks for; only the first call in a process does anything
+pub fn init() {
+    INIT.call_once(|| {
+        let layers: Vec<_> = [TRACE, TRACE_PACKET]
+            .into_iter()
+            .filter_map(|(variable, targets)| {
+                let writer = destination(variable)?;
+                let filter = targets.iter().fold(Targets::new(), |filter, target| {
+                    filter.with_target(*target, LevelFilter::TRACE)
+                });
+                Some(
+                    fmt::layer()
+                        .with_writer(writer)
+                        .with_ansi(false)
+                        .with_filter(filter)
+                        .boxed(),
+                )
+            })
+            .collect();
+        if !layers.is_empty() {
+            let _ = tracing_subscriber::registry().with(layers).try_init();
+        }
+    });
+}
+
+// where the trace `variable` asks for goes, if anywhere
+fn destination(variable: &str) -> Option<BoxMakeWriter> {
+    let value = env::var_os(variable)?;
+    match value.to_str() {
+        Some("" | "0" | "false") => None,
+        Some("1" | "2" | "true") => Some(BoxMakeWriter::new(io::stderr)),
+        _ if Path::new(&value).is_absolute() => {
+            match OpenOptions::new().create(true).append(true).open(&value) {
+                Ok(file) => Some(BoxMakeWriter::new(Mutex::new(file))),
+                Err(error) => {
+                    eprintln!(
+                        "warning: could not open '{}' for tracing: {}",
+                        Path::new(&value).display(),
+                        error
+                    );
+                    None
+                }
+            }
+        }
+        _ => {
+            eprintln!(
+                "warning: unknown trace value for '{}': {}\n         If you want to trace into a file, then please set {} to an absolute pathname (starting with /)",
+                variable,
+                value.to_string_lossy(),
+                variable
+            );
+            None
+        }
+    }
+}
+
+// a command line as a shell would take it back, quoting only the words that need it
+pub fn command_line(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| {
+            let plain = !arg.is_empty()
+                && arg
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_./:=@%+,".contains(c));
+            match plain {
+                true => arg.clone(),
+                false => shell_quote(arg),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// a pkt-line payload as it is traced: without its final LF, with bytes that aren't printable as
+// octal escapes, and pack data, which is all a packet on side band 1 is, only by its length
+pub fn packet(data: &[u8]) -> String {
+    if let Some(pack) = data.strip_prefix(b"\x01") {
+        return format!("\\1<{} bytes of pack data>", pack.len());
+    }
+    let data = data.strip_suffix(b"\n").unwrap_or(data);
+    data.iter()
+        .map(|&b| match b {
+            b' '..=b'~' => (b as char).to_string(),
+            b => format!("\\{:o}", b),
+        })
+        .collect()
+}
@@ -0,0 +1,444 @@
+use crate::message::{self, is_space};
+
+// trailers are the "Token: value" lines of the last paragraph of a commit message, such as
+// Signed-off-by; a value goes on over the lines after it that start with whitespace
+
+// the prefixes git itself writes trailers with, which are enough for a paragraph to be one of
+// trailers when at least a quarter of its lines are trailers
+const GIT_GENERATED_PREFIXES: [&str; 2] = ["Signed-off-by: ", "(cherry picked from commit "];
+
+const SIGN_OFF: &str = "Signed-off-by";
+
+// where a new trailer goes: at the end or the start of the trailers, or after or before the
+// one with the same token
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Where {
+    End,
+    Start,
+    After,
+    Before,
+}
+
+impl Where {
+    pub fn parse(name: &str) -> Option<Where> {
+        match name.to_lowercase().as_str() {
+            "end" => Some(Where::End),
+            "start" => Some(Where::Start),
+            "after" => Some(Where::After),
+            "before" => Some(Where::Before),
+            _ => None,
+        }
+    }
+
+    // whether the trailers are looked through from the last one
+    fn after_or_end(self) -> bool {
+        matches!(self, Where::After | Where::End)
+    }
+}
+
+// what is done with a new trailer when one with the same token is there
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IfExists {
+    // unless the trailer next to where it goes is the same
+    AddIfDifferentNeighbor,
+    // unless one of the trailers is the same
+    AddIfDifferent,
+    Add,
+    Replace,
+    DoNothing,
+}
+
+impl IfExists {
+    pub fn parse(name: &str) -> Option<IfExists> {
+        match name.to_lowercase().as_str() {
+            "addifdifferentneighbor" => Some(IfExists::AddIfDifferentNeighbor),
+            "addifdifferent" => Some(IfExists::AddIfDifferent),
+            "add" => Some(IfExists::Add),
+            "replace" => Some(IfExists::Replace),
+            "donothing" => Some(IfExists::DoNothing),
+            _ => None,
+        }
+    }
+}
+
+// what is done with a new trailer when none with the same token is there
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IfMissing {
+    Add,
+    DoNothing,
+}
+
+impl IfMissing {
+    pub fn parse(name: &str) -> Option<IfMissing> {
+        match name.to_lowercase().as_str() {
+            "add" => Some(IfMissing::Add),
+            "donothing" => Some(IfMissing::DoNothing),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rules {
+    pub place: Where,
+    pub if_exists: IfExists,
+    pub if_missing: IfMissing,
+}
+
+impl Default for Rules {
+    fn default() -> Rules {
+        Rules {
+            place: Where::End,
+            if_exists: IfExists::AddIfDifferentNeighbor,
+            if_missing: IfMissing::Add,
+        }
+    }
+}
+
+// a line of the trailers: a token and its value, or, without a token, a line that isn't a
+// trailer but is among them
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Trailer {
+    pub token: Option<String>,
+    pub value: String,
+}
+
+impl Trailer {
+    // a trailer as --trailer gives it, "token=value" or "token: value", or the token alone;
+    // None when the token is empty
+    pub fn from_arg(arg: &str) -> Option<Trailer> {
+        match find_separator(arg, ":=") {
+            Some(0) => None,
+            Some(separator) => Some(Trailer::split(arg, separator)),
+            None => Some(Trailer {
+                token: Some(arg.trim_matches(is_space).to_string()),
+                value: String::new(),
+            }),
+        }
+    }
+
+    fn split(line: &str, separator: usize) -> Trailer {
+        Trailer {
+            token: Some(line[..separator].trim_matches(is_space).to_string()),
+            value: line[separator + 1..].trim_matches(is_space).to_string(),
+        }
+    }
+
+    // the value on one line, its continuation lines joined by single spaces
+    pub fn unfold(&mut self) {
+        let mut value = String::new();
+        let mut lines = self.value.split('\n');
+        value.push_str(lines.next().unwrap_or_default());
+        for line in lines {
+            value.push(' ');
+            value.push_str(line.trim_start_matches(is_space));
+        }
+        self.value = value.trim_matches(is_space).to_string();
+    }
+
+    // the tokens are the same when one starts with the other, letter case aside, leaving out
+    // what they end with that isn't a letter or a digit
+    fn same_token(&self, other: &Trailer) -> bool {
+        let (Some(token), Some(other)) = (&self.token, &other.token) else {
+            return false;
+        };
+        let len = |token: &str| {
+            token
+                .trim_end_matches(|c: char| !c.is_ascii_alphanumeric())
+                .len()
+        };
+        let len = len(token).min(len(other));
+        token.as_bytes()[..len].eq_ignore_ascii_case(&other.as_bytes()[..len])
+    }
+
+    fn same(&self, other: &Trailer) -> bool {
+        self.same_token(other) && self.value.eq_ignore_ascii_case(&other.value)
+    }
+}
+
+impl std::fmt::Display for Trailer {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.token {
+            Some(token) => write!(f, "{}: {}", token, self.value),
+            None => write!(f, "{}", self.value),
+        }
+    }
+}
+
+// where the trailers of a message are: `start..end` of it, and the lines there, continuation
+// lines joined to the line they continue
+pub struct Block {
+    pub start: usize,
+    pub end: usize,
+    // whether the line before the trailers is blank, so that there is no need for one
+    pub blank_line_before: bool,
+    lines: Vec<String>,
+}
+
+impl Block {
+    // the trailers of `text`, which end at a "---" line when `divider`, then before the
+    // comment and blank lines at the end
+    pub fn find(text: &str, comment: char, divider: bool) -> Block {
+        let mut end = text.len();
+        if divider {
+            let mut start = 0;
+            for line in text.split_inclusive('\n') {
+                let rest = line.strip_prefix("---");
+                if rest.is_some_and(|rest| rest.starts_with(is_space)) {
+                    end = start;
+                    break;
+                }
+                start += line.len();
+            }
+        }
+        let end = message_end(&text[..end], comment);
+        let start = block_start(&text[..end], comment);
+
+        let mut lines: Vec<String> = vec![];
+        let mut continued = false;
+        for line in text[start..end].split_inclusive('\n') {
+            match lines.last_mut() {
+                Some(last) if continued && line.starts_with(is_space) => last.push_str(line),
+                _ => {
+                    continued = find_separator(line, ":").is_some_and(|separator| separator > 0);
+                    lines.push(line.to_string());
+                }
+            }
+        }
+        Block {
+            start,
+            end,
+            blank_line_before: last_line(&text[..start]).is_some_and(is_blank),
+            lines,
+        }
+    }
+
+    // the trailers and the other lines there are among them, without the comments
+    pub fn trailers(&self, comment: char) -> Vec<Trailer> {
+        self.lines
+            .iter()
+            .filter(|line| !line.starts_with(comment))
+            .map(|line| match find_separator(line, ":") {
+                Some(separator) if separator > 0 => Trailer::split(line, separator),
+                _ => Trailer {
+                    token: None,
+                    value: line.strip_suffix('\n').unwrap_or(line).to_string(),
+                },
+            })
+            .collect()
+    }
+}
+
+// adds `new` to `trailers` where the `rules` say
+pub fn add(trailers: &mut Vec<Trailer>, new: Trailer, rules: &Rules) {
+    let backwards = rules.place.after_or_end();
+    let mut order: Vec<usize> = (0..trailers.len()).collect();
+    if backwards {
+        order.reverse();
+    }
+    let Some(&same) = order.iter().find(|&&i| trailers[i].same_token(&new)) else {
+        if rules.if_missing == IfMissing::Add {
+            match backwards {
+                true => trailers.push(new),
+                false => trailers.insert(0, new),
+            }
+        }
+        return;
+    };
+
+    // the trailer the new one goes next to
+    let on = match rules.place {
+        Where::After | Where::Before => same,
+        _ => order[0],
+    };
+    let at = if backwards { on + 1 } else { on };
+    match rules.if_exists {
+        IfExists::DoNothing => {}
+        IfExists::Replace => {
+            trailers.insert(at, new);
+            trailers.remove(if same >= at { same + 1 } else { same });
+        }
+        IfExists::Add => trailers.insert(at, new),
+        IfExists::AddIfDifferent => {
+            // those before the same one when going after it, and those after it otherwise
+            let others = match backwards {
+                true => &trailers[..=same],
+                false => &trailers[same..],
+            };
+            if !others.iter().any(|trailer| trailer.same(&new)) {
+                trailers.insert(at, new);
+            }
+        }
+        IfExists::AddIfDifferentNeighbor => {
+            if !trailers[on].same(&new) {
+                trailers.insert(at, new);
+            }
+        }
+    }
+}
+
+// `message` signed off by `identity`, as commit -s does it: the Signed-off-by trailer is added
+// to the trailers, in a paragraph of its own when there are none, unless it's already the last
+// one; the comment and blank lines at the end stay there
+pub fn sign_off(message: &mut String, identity: &str, comment: char) {
+    let sign_off = format!("{}: {}\n", SIGN_OFF, identity);
+    let mut end = message_end(message, comment);
+    if end == message.len() && !message.is_empty() && !message.ends_with('\n') {
+        message.push('\n');
+        end = message.len();
+    }
+
+    let block = Block::find(&message[..end], comment, false);
+    let last = match message[..end] == sign_off {
+        true => true,
+        false => block
+            .lines
+            .last()
+            .is_some_and(|line| line.starts_with(&sign_off)),
+    };
+    if message[..end] != sign_off && block.lines.is_empty() {
+        // a line to end the message on, and then a blank one
+        let newlines = match end {
+            0 => "\n\n",
+            _ if end == 1 || message.as_bytes()[end - 2] != b'\n' => "\n",
+            _ => "",
+        };
+        message.insert_str(end, newlines);
+        end += newlines.len();
+    }
+    if !last {
+        message.insert_str(end, &sign_off);
+    }
+}
+
+// whether `text` has nothing but whitespace and Signed-off-by lines, which don't make a message
+pub fn is_empty(text: &str) -> bool {
+    text.lines()
+        .all(|line| line.starts_with(&format!("{}: ", SIGN_OFF)) || line.chars().all(is_space))
+}
+
+// where a message ends before the comment and blank lines that finish it, which, like the
+// conflicts listed the old way, stay below what is added to it
+pub fn message_end(text: &str, comment: char) -> usize {
+    let cutoff = message::cut(text, comment).len();
+    // where the lines that can be left out start, 0 when none do
+    let mut start = 0;
+    let mut in_conflicts = false;
+    let mut position = 0;
+    for line in text[..cutoff].split_inclusive('\n') {
+        if line.starts_with(comment) || line.starts_with('\n') {
+            if start == 0 {
+                start = position;
+            }
+        } else if line.starts_with("Conflicts:\n") {
+            in_conflicts = true;
+            if start == 0 {
+                start = position;
+            }
+        } else if start != 0 && !(in_conflicts && line.starts_with('\t')) {
+            start = 0;
+            in_conflicts = false;
+        }
+        position += line.len();
+    }
+    match start {
+        0 => cutoff,
+        _ => start,
+    }
+}
+
+// where the trailers start in `text`: after a blank line, with all the lines below it trailers
+// or their continuations, or at least a quarter of them when one is a trailer git writes. The
+// first paragraph can't be trailers; without trailers, it's the end
+fn block_start(text: &str, comment: char) -> usize {
+    let mut lines = vec![];
+    let mut start = 0;
+    for line in text.split_inclusive('\n') {
+        lines.push((start, line));
+        start += line.len();
+    }
+    let end_of_title = lines
+        .iter()
+        .find(|(_, line)| !line.starts_with(comment) && is_blank(line))
+        .map_or(text.len(), |&(start, _)| start);
+
+    let mut only_spaces = true;
+    let mut recognized_prefix = false;
+    let mut trailer_lines = 0;
+    let mut non_trailer_lines = 0;
+    // lines that continue a trailer if one is above them, and aren't trailers otherwise
+    let mut continuation_lines = 0;
+    for &(start, line) in lines.iter().rev() {
+        if start < end_of_title {
+            break;
+        }
+        if line.starts_with(comment) {
+            non_trailer_lines += continuation_lines;
+            continuation_lines = 0;
+            continue;
+        }
+        if is_blank(line) {
+            if only_spaces {
+                continue;
+            }
+            non_trailer_lines += continuation_lines;
+            let trailers = match recognized_prefix {
+                true => trailer_lines * 3 >= non_trailer_lines,
+                false => trailer_lines > 0 && non_trailer_lines == 0,
+            };
+            return match trailers {
+                true => start + line.len(),
+                false => text.len(),
+            };
+        }
+        only_spaces = false;
+
+        if GIT_GENERATED_PREFIXES
+            .iter()
+            .any(|prefix| line.starts_with(prefix))
+        {
+            trailer_lines += 1;
+            continuation_lines = 0;
+            recognized_prefix = true;
+        } else if find_separator(line, ":").is_some_and(|separator| separator > 0)
+            && !line.starts_with(is_space)
+        {
+            trailer_lines += 1;
+            continuation_lines = 0;
+        } else if line.starts_with(is_space) {
+            continuation_lines += 1;
+        } else {
+            non_trailer_lines += 1 + continuation_lines;
+            continuation_lines = 0;
+        }
+    }
+    text.len()
+}
+
+// where the token of a trailer line ends with one of the `separators`: after letters, digits
+// and dashes, and then maybe whitespace
+fn find_separator(line: &str, separators: &str) -> Option<usize> {
+    let mut whitespace = false;
+    for (i, c) in line.char_indices() {
+        if separators.contains(c) {
+            return Some(i);
+        }
+        if !whitespace && (c.is_ascii_alphanumeric() || c == '-') {
+            continue;
+        }
+        if i > 0 && (c == ' ' || c == '\t') {
+            whitespace = true;
+            continue;
+        }
+        break;
+    }
+    None
+}
+
+fn last_line(text: &str) -> Option<&str> {
+    text.split_inclusive('\n').next_back()
+}
+
+fn is_blank(line: &str) -> bool {
+    line.chars().all(is_space)
+}
@@ -0,0 +1,532 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env, fs,
+    path::Path,
+};
+
+use crate::{
+    config::Config,
+    error::Result,
+    ewah,
+    ignore::{self, Ignore},
+    index::{self, IndexEntry},
+    objects::{self, GitObjectType},
+    paths,
+};
+
+// the untracked cache index extension remembers what each directory of the work tree held
+// that isn't tracked, along with the directory's stat data: as long as that stays the same,
+// and so do the ignore rules, status lists the same names again without reading the
+// directory. Directories are only stat'ed, so a work tree that rarely changes is quick to
+// look at however big it is
+
+// git's DIR_SHOW_OTHER_DIRECTORIES and DIR_HIDE_EMPTY_DIRECTORIES: a directory nothing in
+// which is tracked is listed as itself, and only when something in it isn't ignored
+const DIR_FLAGS: u32 = 0x2 | 0x4;
+const EXCLUDE_PER_DIR: &str = ".gitignore";
+// ctime, mtime, dev, ino, uid, gid and size, as the index stores them
+const STAT_SIZE: usize = 36;
+
+type Stat = [u32; 9];
+
+// a file of ignore rules as it was when they were read: its stat data and its id as a blob
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Stamp {
+    stat: Stat,
+    id: String,
+}
+
+impl Stamp {
+    // a missing file is taken to be empty
+    fn of(file: &Path) -> Stamp {
+        let stat = fs::metadata(file).map_or([0; 9], |metadata| stat_data(&metadata));
+        let content = fs::read(file).unwrap_or_default();
+        Stamp {
+            stat,
+            id: objects::encode_object(GitObjectType::Blob, &content).0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Directory {
+    name: String,
+    // the directory's stat data when its entries were read, or None once they may be stale
+    stat: Option<Stat>,
+    // untracked itself, and only looked into to see whether anything in it is to be listed
+    check_only: bool,
+    // the id of its .gitignore as a blob, which is that of an empty one when there is none
+    exclude_id: Option<String>,
+    // its untracked files, and the repositories nested in it with a trailing slash
+    untracked: Vec<String>,
+    dirs: Vec<Directory>,
+}
+
+impl Directory {
+    fn new(name: &str) -> Directory {
+        Directory {
+            name: name.to_string(),
+            ..Directory::default()
+        }
+    }
+
+    // whether anything in it or below is to be listed
+    fn has_untracked(&self) -> bool {
+        !self.untracked.is_empty() || self.dirs.iter().any(Directory::has_untracked)
+    }
+
+    fn count(&self) -> usize {
+        1 + self.dirs.iter().map(Directory::count).sum::<usize>()
+    }
+
+    fn preorder(&self, visit: &mut dyn FnMut(&Directory)) {
+        visit(self);
+        for dir in &self.dirs {
+            dir.preorder(visit);
+        }
+    }
+
+    fn preorder_mut(&mut self, visit: &mut dyn FnMut(&mut Directory)) {
+        visit(self);
+        for dir in &mut self.dirs {
+            dir.preorder_mut(visit);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UntrackedCache {
+    // where the work tree is and what system it is on, since stat data means nothing elsewhere
+    ident: Vec<u8>,
+    info_exclude: Stamp,
+    excludes_file: Stamp,
+    flags: u32,
+    exclude_per_dir: String,
+    root: Option<Directory>,
+}
+
+impl UntrackedCache {
+    pub fn new(work_tree: &Path) -> UntrackedCache {
+        UntrackedCache {
+            ident: ident(work_tree),
+            info_exclude: Stamp::default(),
+            excludes_file: Stamp::default(),
+            flags: DIR_FLAGS,
+            exclude_per_dir: EXCLUDE_PER_DIR.to_string(),
+            root: None,
+        }
+    }
+
+    // the extension's content; one that can't be made sense of is as good as none
+    pub fn parse(data: &[u8]) -> Option<UntrackedCache> {
+        let (length, mut at) = index::read_varint(data, 0)?;
+        let ident = data.get(at..at + length)?.to_vec();
+        at += length;
+        let info_stat = read_stat(data, at)?;
+        let excludes_stat = read_stat(data, at + STAT_SIZE)?;
+        at += 2 * STAT_SIZE;
+        let flags = u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?);
+        let info_id = hex::encode(data.get(at + 4..at + 24)?);
+        let excludes_id = hex::encode(data.get(at + 24..at + 44)?);
+        at += 44;
+        let exclude_per_dir = read_string(data, &mut at)?;
+        let mut cache = UntrackedCache {
+            ident,
+            info_exclude: Stamp {
+                stat: info_stat,
+                id: info_id,
+            },
+            excludes_file: Stamp {
+                stat: excludes_stat,
+                id: excludes_id,
+            },
+            flags,
+            exclude_per_dir,
+            root: None,
+        };
+
+        let (count, next) = index::read_varint(data, at)?;
+        at = next;
+        if count == 0 {
+            return Some(cache);
+        }
+        let mut root = read_directory(data, &mut at)?;
+        if root.count() != count {
+            return None;
+        }
+        // then which directories have stat data, which are only checked, and which have the
+        // id of their .gitignore, followed by the stat data and the ids themselves
+        let mut bitmaps = vec![];
+        for _ in 0..3 {
+            let (bits, size) = ewah::read(data.get(at..)?)?;
+            bitmaps.push(bits);
+            at += size;
+        }
+        let (mut i, mut failed) = (0, false);
+        root.preorder_mut(&mut |dir| {
+            dir.check_only = bitmaps[1].get(i) == Some(&true);
+            if bitmaps[0].get(i) == Some(&true) {
+                match read_stat(data, at) {
+                    Some(stat) => dir.stat = Some(stat),
+                    None => failed = true,
+                }
+                at += STAT_SIZE;
+            }
+            i += 1;
+        });
+        i = 0;
+        root.preorder_mut(&mut |dir| {
+            if bitmaps[2].get(i) == Some(&true) {
+                match data.get(at..at + 20) {
+                    Some(id) => dir.exclude_id = Some(hex::encode(id)),
+                    None => failed = true,
+                }
+                at += 20;
+            }
+            i += 1;
+        });
+        if failed {
+            return None;
+        }
+        cache.root = Some(root);
+        Some(cache)
+    }
+
+    pub fn write(&self, data: &mut Vec<u8>) {
+        index::write_varint(data, self.ident.len());
+        data.extend_from_slice(&self.ident);
+        for stat in [self.info_exclude.stat, self.excludes_file.stat] {
+            write_stat(data, &stat);
+        }
+        data.extend_from_slice(&self.flags.to_be_bytes());
+        for stamp in [&self.info_exclude, &self.excludes_file] {
+            data.extend_from_slice(&hex::decode(&stamp.id).unwrap_or_else(|_| vec![0; 20]));
+        }
+        data.extend_from_slice(self.exclude_per_dir.as_bytes());
+        data.push(0);
+
+        let Some(root) = &self.root else {
+            index::write_varint(data, 0);
+            return;
+        };
+        index::write_varint(data, root.count());
+        write_directory(data, root);
+        let (mut valid, mut check_only, mut has_id) = (vec![], vec![], vec![]);
+        let (mut stats, mut ids) = (vec![], vec![]);
+        root.preorder(&mut |dir| {
+            valid.push(dir.stat.is_some());
+            check_only.push(dir.check_only);
+            has_id.push(dir.exclude_id.is_some());
+            if let Some(stat) = &dir.stat {
+                write_stat(&mut stats, stat);
+            }
+            if let Some(id) = &dir.exclude_id {
+                ids.extend_from_slice(&hex::decode(id).unwrap_or_else(|_| vec![0; 20]));
+            }
+        });
+        for bits in [valid, check_only, has_id] {
+            data.extend_from_slice(&ewah::write(&bits));
+        }
+        data.extend_from_slice(&stats);
+        data.extend_from_slice(&ids);
+        data.push(0);
+    }
+
+    // the directories holding `path` have to be read again once it is tracked or no longer is
+    pub fn invalidate(&mut self, path: &str) {
+        let Some(mut dir) = self.root.as_mut() else {
+            return;
+        };
+        dir.stat = None;
+        let mut components: Vec<&str> = path.split('/').collect();
+        components.pop();
+        for component in components {
+            let Some(child) = dir.dirs.iter_mut().find(|dir| dir.name == component) else {
+                return;
+            };
+            child.stat = None;
+            dir = child;
+        }
+    }
+
+    // forgets every directory, as when the whole index is replaced
+    pub fn clear(&mut self) {
+        self.root = None;
+    }
+
+    // what was cached under other ignore rules, or somewhere else, is no use
+    fn validate(&mut self, work_tree: &Path, git_dir: &Path, config: &Config) {
+        let ident = ident(work_tree);
+        let info_exclude = Stamp::of(&git_dir.join("info/exclude"));
+        let excludes_file = ignore::excludes_file(config)
+            .map(|file| Stamp::of(&file))
+            .unwrap_or_default();
+        if self.ident != ident
+            || self.flags != DIR_FLAGS
+            || self.exclude_per_dir != EXCLUDE_PER_DIR
+            || self.info_exclude.id != info_exclude.id
+            || self.excludes_file.id != excludes_file.id
+        {
+            *self = UntrackedCache::new(work_tree);
+        }
+        self.info_exclude = info_exclude;
+        self.excludes_file = excludes_file;
+    }
+}
+
+// the untracked files of the work tree that aren't ignored, sorted. Unless `all` is set, a
+// directory with nothing tracked in it is listed as "<dir>/" instead of what it holds, and only
+// when it holds something; `cache`, if given, is brought up to date and saves reading the
+// directories that didn't change
+pub fn untracked_files(
+    work_tree: &Path,
+    git_dir: &Path,
+    config: &Config,
+    entries: &[IndexEntry],
+    all: bool,
+    cache: Option<&mut UntrackedCache>,
+) -> Result<Vec<String>> {
+    let mut tracked = HashSet::new();
+    let mut tracked_dirs = HashSet::new();
+    for entry in entries {
+        tracked.insert(entry.path.as_str());
+        let mut path = entry.path.as_str();
+        while let Some((dir, _)) = path.rsplit_once('/') {
+            if !tracked_dirs.insert(dir) {
+                break;
+            }
+            path = dir;
+        }
+    }
+    let mut walk = Walk {
+        work_tree,
+        tracked,
+        tracked_dirs,
+        ignore: Ignore::new(work_tree, git_dir, config),
+        all,
+        found: vec![],
+    };
+
+    match cache {
+        // the cache only knows directories as the default listing shows them
+        Some(cache) if !all => {
+            cache.validate(work_tree, git_dir, config);
+            let root = cache.root.get_or_insert_with(Directory::default);
+            walk.read_dir("", root)?;
+        }
+        _ => {
+            walk.read_dir("", &mut Directory::default())?;
+        }
+    }
+
+    let mut found = walk.found;
+    found.sort();
+    Ok(found)
+}
+
+struct Walk<'a> {
+    work_tree: &'a Path,
+    tracked: HashSet<&'a str>,
+    // every directory something tracked is in
+    tracked_dirs: HashSet<&'a str>,
+    ignore: Ignore,
+    all: bool,
+    found: Vec<String>,
+}
+
+impl Walk<'_> {
+    // brings `dir`, at `path`, up to date, listing what it holds unless it is only checked;
+    // says whether it holds anything to list
+    fn read_dir(&mut self, path: &str, dir: &mut Directory) -> Result<bool> {
+        let native = paths::to_native(self.work_tree, path);
+        let Ok(metadata) = fs::symlink_metadata(&native) else {
+            *dir = Directory::new(&dir.name);
+            return Ok(false);
+        };
+        // new ignore rules could change anything below
+        let exclude_id = Stamp::of(&native.join(EXCLUDE_PER_DIR)).id;
+        if dir.exclude_id.as_ref() != Some(&exclude_id) {
+            dir.stat = None;
+            dir.dirs.clear();
+            dir.exclude_id = Some(exclude_id);
+        }
+        // the stat data comes first, so that a change made while reading shows next time
+        let stat = stat_data(&metadata);
+        if dir.stat != Some(stat) {
+            self.read_entries(path, dir)?;
+            dir.stat = Some(stat);
+        }
+
+        let prefix = match path.is_empty() {
+            true => String::new(),
+            false => format!("{}/", path),
+        };
+        let list = !dir.check_only;
+        let mut any = !dir.untracked.is_empty();
+        if list {
+            for name in &dir.untracked {
+                self.found.push(format!("{}{}", prefix, name));
+            }
+        }
+        for child in &mut dir.dirs {
+            let path = format!("{}{}", prefix, child.name);
+            if !self.read_dir(&path, child)? {
+                continue;
+            }
+            any = true;
+            if list && child.check_only {
+                self.found.push(format!("{}/", path));
+            }
+        }
+        Ok(any)
+    }
+
+    // what `dir` holds that isn't tracked or ignored; the directories it had before are kept
+    // for those it still has
+    fn read_entries(&mut self, path: &str, dir: &mut Directory) -> Result<()> {
+        let mut old: HashMap<String, Directory> = dir
+            .dirs
+            .drain(..)
+            .map(|child| (child.name.clone(), child))
+            .collect();
+        dir.untracked.clear();
+
+        let native = paths::to_native(self.work_tree, path);
+        let mut names = vec![];
+        for entry in fs::read_dir(&native)? {
+            let entry = entry?;
+            let is_dir = entry.file_type()?.is_dir();
+            names.push((entry.file_name().to_string_lossy().into_owned(), is_dir));
+        }
+        names.sort();
+
+        let prefix = match path.is_empty() {
+            true => String::new(),
+            false => format!("{}/", path),
+        };
+        for (name, is_dir) in names {
+            if name == ".git" {
+                continue;
+            }
+            let full = format!("{}{}", prefix, name);
+            if self.tracked.contains(full.as_str()) {
+                continue;
+            }
+            if !is_dir {
+                if !self.ignore.is_ignored(&full, false) {
+                    dir.untracked.push(name);
+                }
+                continue;
+            }
+            let tracked_below = self.tracked_dirs.contains(full.as_str());
+            if !tracked_below && self.ignore.is_ignored(&full, true) {
+                continue;
+            }
+            // another repository is listed as a whole, never looked into
+            if !tracked_below && native.join(&name).join(".git").exists() {
+                dir.untracked.push(format!("{}/", name));
+                continue;
+            }
+            let mut child = old.remove(&name).unwrap_or_else(|| Directory::new(&name));
+            child.check_only = !tracked_below && !self.all;
+            dir.dirs.push(child);
+        }
+
+        Ok(())
+    }
+}
+
+// what the cache has to match: "Location <work tree>, system <kernel>" and a NUL
+fn ident(work_tree: &Path) -> Vec<u8> {
+    let system = match env::consts::OS {
+        "linux" => "Linux",
+        "macos" => "Darwin",
+        "windows" => "Windows",
+        "freebsd" => "FreeBSD",
+        other => other,
+    };
+    format!("Location {}, system {}\0", work_tree.display(), system).into_bytes()
+}
+
+fn stat_data(metadata: &fs::Metadata) -> Stat {
+    let mut entry = IndexEntry::default();
+    entry.set_stat(metadata);
+    [
+        entry.ctime.0,
+        entry.ctime.1,
+        entry.mtime.0,
+        entry.mtime.1,
+        entry.dev,
+        entry.ino,
+        entry.uid,
+        entry.gid,
+        entry.size,
+    ]
+}
+
+fn read_stat(data: &[u8], at: usize) -> Option<Stat> {
+    let mut stat = [0; 9];
+    for (i, field) in stat.iter_mut().enumerate() {
+        *field = u32::from_be_bytes(data.get(at + i * 4..at + i * 4 + 4)?.try_into().ok()?);
+    }
+    Some(stat)
+}
+
+fn write_stat(data: &mut Vec<u8>, stat: &Stat) {
+    for field in stat {
+        data.extend_from_slice(&field.to_be_bytes());
+    }
+}
+
+fn read_string(data: &[u8], at: &mut usize) -> Option<String> {
+    let end = *at + data.get(*at..)?.iter().position(|b| *b == b'\0')?;
+    let string = String::from_utf8_lossy(&data[*at..end]).into_owned();
+    *at = end + 1;
+    Some(string)
+}
+
+// how many names, how many subdirectories, the directory's name, the names, then the
+// subdirectories the same way
+fn read_directory(data: &[u8], at: &mut usize) -> Option<Directory> {
+    let (untracked_count, next) = index::read_varint(data, *at)?;
+    let (dir_count, next) = index::read_varint(data, next)?;
+    *at = next;
+    let mut dir = Directory::new(&read_string(data, at)?);
+    for _ in 0..untracked_count {
+        dir.untracked.push(read_string(data, at)?);
+    }
+    for _ in 0..dir_count {
+        dir.dirs.push(read_directory(data, at)?);
+    }
+    // untracked directories are listed by name as well as having entries of their own, which
+    // are looked at again each time
+    let checked: HashSet<String> = dir
+        .dirs
+        .iter()
+        .map(|child| format!("{}/", child.name))
+        .collect();
+    dir.untracked.retain(|name| !checked.contains(name));
+    Some(dir)
+}
+
+fn write_directory(data: &mut Vec<u8>, dir: &Directory) {
+    let mut untracked = dir.untracked.clone();
+    untracked.extend(
+        dir.dirs
+            .iter()
+            .filter(|child| child.check_only && child.has_untracked())
+            .map(|child| format!("{}/", child.name)),
+    );
+    untracked.sort();
+    index::write_varint(data, untracked.len());
+    index::write_varint(data, dir.dirs.len());
+    data.extend_from_slice(dir.name.as_bytes());
+    data.push(0);
+    for name in &untracked {
+        data.extend_from_slice(name.as_bytes());
+        data.push(0);
+    }
+    for child in &dir.dirs {
+        write_directory(data, child);
+    }
+}
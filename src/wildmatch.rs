@@ -0,0 +1,133 @@
+// git-style glob matching: with `pathname` set, `*` and `?` never match a '/'
+// and `**` surrounded by slashes matches any number of directories
+pub fn wildmatch(pattern: &[u8], text: &[u8], pathname: bool) -> bool {
+    match_from(pattern, text, pathname)
+}
+
+fn match_from(pattern: &[u8], text: &[u8], pathname: bool) -> bool {
+    let (mut p, mut t) = (0, 0);
+    while p < pattern.len() {
+        match pattern[p] {
+            b'?' => {
+                if t >= text.len() || (pathname && text[t] == b'/') {
+                    return false;
+                }
+                p += 1;
+                t += 1;
+            }
+            b'*' => {
+                let double = pattern.get(p + 1) == Some(&b'*');
+                if double && pathname {
+                    let at_start = p == 0 || pattern[p - 1] == b'/';
+                    let rest = &pattern[p + 2..];
+                    if at_start && rest.is_empty() {
+                        return true;
+                    }
+                    if at_start && rest[0] == b'/' {
+                        // "**/" matches zero or more leading directories
+                        let rest = &rest[1..];
+                        let text = &text[t..];
+                        if match_from(rest, text, pathname) {
+                            return true;
+                        }
+                        return text
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, b)| **b == b'/')
+                            .any(|(i, _)| match_from(rest, &text[i + 1..], pathname));
+                    }
+                }
+
+                let mut next = p + 1;
+                while next < pattern.len() && pattern[next] == b'*' {
+                    next += 1;
+                }
+                let rest = &pattern[next..];
+                let mut i = t;
+                loop {
+                    if match_from(rest, &text[i..], pathname) {
+                        return true;
+                    }
+                    if i >= text.len() || (pathname && text[i] == b'/') {
+                        return false;
+                    }
+                    i += 1;
+                }
+            }
+            b'[' => {
+                if t >= text.len() || (pathname && text[t] == b'/') {
+                    return false;
+                }
+                match match_class(&pattern[p..], text[t]) {
+                    Some((true, len)) => {
+                        p += len;
+                        t += 1;
+                    }
+                    Some((false, _)) => return false,
+                    // an unterminated class is matched literally
+                    None => {
+                        if text[t] != b'[' {
+                            return false;
+                        }
+                        p += 1;
+                        t += 1;
+                    }
+                }
+            }
+            b'\\' if p + 1 < pattern.len() => {
+                if t >= text.len() || text[t] != pattern[p + 1] {
+                    return false;
+                }
+                p += 2;
+                t += 1;
+            }
+            c => {
+                if t >= text.len() || text[t] != c {
+                    return false;
+                }
+                p += 1;
+                t += 1;
+            }
+        }
+    }
+
+    t == text.len()
+}
+
+// returns whether `c` is in the bracket expression at the start of `pattern`, and its length
+fn match_class(pattern: &[u8], c: u8) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negated = matches!(pattern.get(i), Some(b'!') | Some(b'^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let mut start = *pattern.get(i)?;
+        if start == b']' && !first {
+            break;
+        }
+        first = false;
+        if start == b'\\' {
+            i += 1;
+            start = *pattern.get(i)?;
+        }
+        i += 1;
+
+        if pattern.get(i) == Some(&b'-') && pattern.get(i + 1).is_some_and(|end| *end != b']') {
+            let mut end = pattern[i + 1];
+            i += 2;
+            if end == b'\\' {
+                end = *pattern.get(i)?;
+                i += 1;
+            }
+            matched |= start <= c && c <= end;
+        } else {
+            matched |= start == c;
+        }
+    }
+
+    Some((matched != negated, i + 1))
+}
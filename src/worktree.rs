@@ -0,0 +1,289 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::{
+    config::{self, Config},
+    error::{Error, Result},
+    repository::Repository,
+};
+
+// besides the main work tree, `worktree add` makes linked ones, each with a directory of its
+// own in $GIT_DIR/worktrees/<id> for its HEAD, index and the rest. Three files there tie the
+// two together: "gitdir" has the path of the work tree's .git file, "commondir" the way back
+// to the repository, and "locked", when it's there, why the work tree mustn't be pruned or
+// moved. The .git file of the work tree says "gitdir: <that directory>"
+
+// gc.worktreePruneExpire
+pub const DEFAULT_PRUNE_EXPIRE: &str = "3.months.ago";
+
+pub struct Worktree {
+    pub path: PathBuf,
+    // where its HEAD and index are; the id is None for the main work tree
+    pub git_dir: PathBuf,
+    pub id: Option<String>,
+    pub bare: bool,
+    pub head: Option<String>,
+    // the branch HEAD is on, as a full ref name
+    pub branch: Option<String>,
+    pub locked: Option<String>,
+    // why prune would remove it
+    pub prunable: Option<String>,
+}
+
+impl Worktree {
+    pub fn is_main(&self) -> bool {
+        self.id.is_none()
+    }
+}
+
+// the main work tree, then the linked ones by id
+pub fn list(repository: &Repository) -> Vec<Worktree> {
+    let common_dir = repository.common_dir();
+    let config = Config::for_repository(common_dir);
+    let bare = config
+        .get("core.bare")
+        .and_then(config::parse_bool)
+        .unwrap_or(false);
+    // from a linked work tree, the common directory is only found by way of "../.."
+    let canonical = fs::canonicalize(common_dir).unwrap_or_else(|_| common_dir.to_path_buf());
+    let path = match bare {
+        true => canonical.clone(),
+        false => canonical.parent().unwrap_or(&canonical).to_path_buf(),
+    };
+    let mut worktrees = vec![worktree(repository, path, common_dir, None, bare)];
+
+    for id in ids(common_dir) {
+        let git_dir = common_dir.join("worktrees").join(&id);
+        let gitdir = fs::read_to_string(git_dir.join("gitdir")).unwrap_or_default();
+        let gitdir = PathBuf::from(gitdir.trim_end_matches('\n'));
+        let path = gitdir.parent().unwrap_or(&gitdir).to_path_buf();
+        worktrees.push(worktree(repository, path, &git_dir, Some(id), false));
+    }
+    worktrees
+}
+
+fn worktree(
+    repository: &Repository,
+    path: PathBuf,
+    git_dir: &Path,
+    id: Option<String>,
+    bare: bool,
+) -> Worktree {
+    let head = fs::read_to_string(git_dir.join("HEAD")).unwrap_or_default();
+    let head = head.trim();
+    let (head, branch) = match head.strip_prefix("ref: ") {
+        Some(branch) => (repository.find_reference(branch), Some(branch.to_string())),
+        None => (Some(head.to_string()), None),
+    };
+    let (locked, prunable) = match &id {
+        Some(id) => (
+            locked(repository.common_dir(), id),
+            prune_reason(repository.common_dir(), id, i64::MAX),
+        ),
+        None => (None, None),
+    };
+    Worktree {
+        path,
+        git_dir: git_dir.to_path_buf(),
+        id,
+        bare,
+        head: head.filter(|head| !head.is_empty()),
+        branch,
+        locked,
+        prunable,
+    }
+}
+
+// a work tree by its path, or by the last components of its path where only one ends in them
+pub fn find(repository: &Repository, name: &str) -> Result<Worktree> {
+    let worktrees = list(repository);
+    let wanted = fs::canonicalize(name).ok();
+    let suffix = name.trim_end_matches('/');
+    let mut found = worktrees
+        .iter()
+        .position(|worktree| wanted.is_some() && fs::canonicalize(&worktree.path).ok() == wanted);
+    if found.is_none() && !suffix.is_empty() {
+        let matching: Vec<usize> = (0..worktrees.len())
+            .filter(|i| {
+                let path = worktrees[*i].path.to_string_lossy();
+                path == suffix || path.ends_with(&format!("/{}", suffix))
+            })
+            .collect();
+        if let [only] = matching[..] {
+            found = Some(only);
+        }
+    }
+
+    found
+        .map(|i| worktrees.into_iter().nth(i).unwrap())
+        .ok_or_else(|| Error::NotAWorktree(name.to_string()))
+}
+
+// the ids of the linked work trees
+fn ids(common_dir: &Path) -> Vec<String> {
+    let mut ids: Vec<String> = fs::read_dir(common_dir.join("worktrees"))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
+    ids.sort();
+    ids
+}
+
+// an id for a new work tree at `path`, its last component with a number added if that's taken
+pub fn new_id(common_dir: &Path, path: &Path) -> String {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let worktrees = common_dir.join("worktrees");
+    if !worktrees.join(&name).exists() {
+        return name;
+    }
+    (1..)
+        .map(|n| format!("{}{}", name, n))
+        .find(|id| !worktrees.join(id).exists())
+        .unwrap()
+}
+
+// sets up $GIT_DIR/worktrees/<id> for a work tree at `path` whose HEAD is `head`, and the .git
+// file of the work tree pointing at it
+pub fn create(common_dir: &Path, id: &str, path: &Path, head: &str) -> Result<PathBuf> {
+    let git_dir = common_dir.join("worktrees").join(id);
+    fs::create_dir_all(&git_dir)?;
+    fs::write(
+        git_dir.join("gitdir"),
+        format!("{}\n", path.join(".git").display()),
+    )?;
+    fs::write(git_dir.join("commondir"), "../..\n")?;
+    fs::write(git_dir.join("HEAD"), format!("{}\n", head))?;
+    fs::create_dir_all(path)?;
+    fs::write(
+        path.join(".git"),
+        format!("gitdir: {}\n", git_dir.display()),
+    )?;
+    Ok(git_dir)
+}
+
+// why the work tree `id` is locked, which may be no reason at all
+pub fn locked(common_dir: &Path, id: &str) -> Option<String> {
+    let content = fs::read_to_string(common_dir.join("worktrees").join(id).join("locked")).ok()?;
+    Some(content.trim_end_matches('\n').to_string())
+}
+
+pub fn lock(common_dir: &Path, id: &str, reason: &str) -> Result<()> {
+    let mut content = reason.to_string();
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    fs::write(
+        common_dir.join("worktrees").join(id).join("locked"),
+        content,
+    )?;
+    Ok(())
+}
+
+pub fn unlock(common_dir: &Path, id: &str) -> Result<()> {
+    fs::remove_file(common_dir.join("worktrees").join(id).join("locked"))?;
+    Ok(())
+}
+
+// moves the work tree, which has to still be where it was, and points its directory in
+// $GIT_DIR/worktrees at where it went
+pub fn relocate(worktree: &Worktree, to: &Path) -> Result<()> {
+    let invalid = |what: String| Error::InvalidWorktree(what);
+    if !worktree.path.exists() {
+        return Err(invalid(format!(
+            "'{}' does not exist",
+            worktree.path.display()
+        )));
+    }
+    let dot_git = worktree.path.join(".git");
+    let content = fs::read_to_string(&dot_git)
+        .map_err(|_| invalid(format!("'{}' is not a .git file", dot_git.display())))?;
+    let points_back = content
+        .trim()
+        .strip_prefix("gitdir:")
+        .and_then(|gitdir| fs::canonicalize(gitdir.trim()).ok())
+        .is_some_and(|gitdir| fs::canonicalize(&worktree.git_dir).ok() == Some(gitdir));
+    if !points_back {
+        return Err(invalid(format!(
+            "'{}' does not point back to '{}'",
+            dot_git.display(),
+            worktree.git_dir.display()
+        )));
+    }
+
+    fs::rename(&worktree.path, to).map_err(|error| {
+        Error::MoveFailed(
+            worktree.path.display().to_string(),
+            to.display().to_string(),
+            error.to_string(),
+        )
+    })?;
+    let to = fs::canonicalize(to)?;
+    fs::write(
+        worktree.git_dir.join("gitdir"),
+        format!("{}\n", to.join(".git").display()),
+    )?;
+    Ok(())
+}
+
+// why the directory of the work tree `id` in $GIT_DIR/worktrees is no use anymore, if it
+// isn't locked; one whose work tree is gone goes once its gitdir file was last touched before
+// `expire`
+pub fn prune_reason(common_dir: &Path, id: &str, expire: i64) -> Option<String> {
+    let git_dir = common_dir.join("worktrees").join(id);
+    if !git_dir.is_dir() {
+        return Some("not a valid directory".to_string());
+    }
+    if git_dir.join("locked").exists() {
+        return None;
+    }
+    let gitdir_file = git_dir.join("gitdir");
+    let Ok(gitdir) = fs::read_to_string(&gitdir_file) else {
+        return Some("gitdir file does not exist".to_string());
+    };
+    let gitdir = gitdir.trim_end_matches('\n');
+    if gitdir.is_empty() {
+        return Some("invalid gitdir file".to_string());
+    }
+    if Path::new(gitdir).exists() {
+        return None;
+    }
+    let modified = fs::metadata(&gitdir_file)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map_or(0, |time| time.as_secs() as i64);
+    (modified <= expire).then(|| "gitdir file points to non-existent location".to_string())
+}
+
+// removes what's kept for the work trees that are gone, saying why with `verbose`, or only
+// saying what would go with `dry_run`
+pub fn prune(repository: &Repository, expire: i64, dry_run: bool, verbose: bool) -> Result<()> {
+    let common_dir = repository.common_dir();
+    for id in ids(common_dir) {
+        let Some(reason) = prune_reason(common_dir, &id, expire) else {
+            continue;
+        };
+        if dry_run || verbose {
+            eprintln!("Removing worktrees/{}: {}", id, reason);
+        }
+        if !dry_run {
+            let git_dir = common_dir.join("worktrees").join(&id);
+            match git_dir.is_dir() {
+                true => fs::remove_dir_all(git_dir)?,
+                false => fs::remove_file(git_dir)?,
+            }
+        }
+    }
+    if !dry_run {
+        let _ = fs::remove_dir(common_dir.join("worktrees"));
+    }
+    Ok(())
+}
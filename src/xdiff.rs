@@ -0,0 +1,1115 @@
+use std::{borrow::Cow, collections::HashMap, ops::Range};
+
+// the line diff git uses for patches, ported from its xdiff library so the same changes come out
+// in the same places: Myers' algorithm with git's shortcuts for costly inputs, or the patience or
+// histogram algorithms, then each group of changed lines slid to where it reads best
+
+// lines of the old file replaced by lines of the new one, either range possibly empty
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Edit {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+// how the lines that changed are found
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Algorithm {
+    // Myers' algorithm, settling for a good enough answer when the shortest one gets costly
+    #[default]
+    Myers,
+    // Myers' algorithm, always finding the fewest lines to change
+    Minimal,
+    // lines found just once in each file are matched up first, in order
+    Patience,
+    // lines found the fewest times in the old file are matched up first
+    Histogram,
+}
+
+// git's limits for lines found many times over, which are left out of the search
+const MAX_EQUAL_LIMIT: usize = 1024;
+const SIMILAR_SCAN_WINDOW: usize = 100;
+const KEEP_DISCARDED_RUN: usize = 4;
+// and for when a search gets too costly and settles for a good enough split
+const MAX_COST_MIN: usize = 256;
+const HEURISTIC_MIN_COST: usize = 256;
+const SNAKE_COUNT: isize = 20;
+const HEURISTIC_FACTOR: isize = 4;
+// the histogram algorithm leaves lines found more often than this to Myers' algorithm
+const MAX_CHAIN_LENGTH: usize = 64;
+
+// the lines of `content`, each with its newline
+pub fn lines(content: &[u8]) -> Vec<&[u8]> {
+    content.split_inclusive(|b| *b == b'\n').collect()
+}
+
+// the edits from `old` to `new`, in order; the indent heuristic moves groups that could go in
+// several places to fit the indentation around them, as git does by default
+pub fn diff<'a>(
+    old: &'a [&'a [u8]],
+    new: &'a [&'a [u8]],
+    algorithm: Algorithm,
+    indent_heuristic: bool,
+) -> Vec<Edit> {
+    diff_by(old, new, algorithm, indent_heuristic, Cow::Borrowed)
+}
+
+// the same, with lines that differ only in whitespace taken to be equal, as with -w
+pub fn diff_ignoring_whitespace<'a>(
+    old: &'a [&'a [u8]],
+    new: &'a [&'a [u8]],
+    algorithm: Algorithm,
+    indent_heuristic: bool,
+) -> Vec<Edit> {
+    diff_by(old, new, algorithm, indent_heuristic, |line| {
+        // git's isspace, which unlike Rust's takes in vertical tabs
+        let space = |b: &u8| matches!(b, b' ' | b'\t' | b'\n' | b'\x0b' | b'\x0c' | b'\r');
+        Cow::Owned(line.iter().filter(|b| !space(b)).copied().collect())
+    })
+}
+
+fn diff_by<'a>(
+    old: &'a [&'a [u8]],
+    new: &'a [&'a [u8]],
+    algorithm: Algorithm,
+    indent_heuristic: bool,
+    key: impl Fn(&'a [u8]) -> Cow<'a, [u8]>,
+) -> Vec<Edit> {
+    // lines are compared by the class of equal lines they belong to
+    let mut classes: HashMap<Cow<'a, [u8]>, usize> = HashMap::new();
+    let mut classify = |lines: &'a [&'a [u8]]| -> Vec<usize> {
+        lines
+            .iter()
+            .map(|line| {
+                let next = classes.len();
+                *classes.entry(key(line)).or_insert(next)
+            })
+            .collect()
+    };
+    let old_classes = classify(old);
+    let new_classes = classify(new);
+    let mut old = File::new(old, old_classes);
+    let mut new = File::new(new, new_classes);
+
+    let (old_lines, new_lines) = (0..old.len(), 0..new.len());
+    match algorithm {
+        Algorithm::Myers => myers(&mut old, &mut new, old_lines, new_lines, false),
+        Algorithm::Minimal => myers(&mut old, &mut new, old_lines, new_lines, true),
+        Algorithm::Patience => patience(&mut old, &mut new, old_lines, new_lines),
+        Algorithm::Histogram => histogram(&mut old, &mut new, old_lines, new_lines),
+    }
+
+    compact(&mut old, &mut new, indent_heuristic);
+    compact(&mut new, &mut old, indent_heuristic);
+
+    // the runs of changed lines, taken together where both files have one at the same place
+    let mut edits = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if old.changed[i] || new.changed[j] {
+            let (old_start, new_start) = (i, j);
+            while old.changed[i] {
+                i += 1;
+            }
+            while new.changed[j] {
+                j += 1;
+            }
+            edits.push(Edit {
+                old: old_start..i,
+                new: new_start..j,
+            });
+        } else {
+            i += 1;
+            j += 1;
+        }
+    }
+
+    edits
+}
+
+// marks the lines of the two ranges that changed by Myers' algorithm, looking at them as git
+// looks at whole files: the lines both start and end with are left alone, and lines the other
+// range lacks are changed without searching
+fn myers(
+    old: &mut File,
+    new: &mut File,
+    old_lines: Range<usize>,
+    new_lines: Range<usize>,
+    minimal: bool,
+) {
+    let mut counts: HashMap<usize, [usize; 2]> = HashMap::new();
+    for &class in &old.classes[old_lines.clone()] {
+        counts.entry(class).or_default()[0] += 1;
+    }
+    for &class in &new.classes[new_lines.clone()] {
+        counts.entry(class).or_default()[1] += 1;
+    }
+
+    let prefix = old.classes[old_lines.clone()]
+        .iter()
+        .zip(&new.classes[new_lines.clone()])
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = old.classes[old_lines.start + prefix..old_lines.end]
+        .iter()
+        .rev()
+        .zip(
+            new.classes[new_lines.start + prefix..new_lines.end]
+                .iter()
+                .rev(),
+        )
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_kept = old.discard(
+        old_lines.start + prefix..old_lines.end - suffix,
+        old_lines.len(),
+        &counts,
+        1,
+    );
+    let new_kept = new.discard(
+        new_lines.start + prefix..new_lines.end - suffix,
+        new_lines.len(),
+        &counts,
+        0,
+    );
+
+    let classes = |file: &File, kept: &[usize]| -> Vec<usize> {
+        kept.iter().map(|&i| file.classes[i]).collect()
+    };
+    let mut search = Search {
+        old: classes(old, &old_kept),
+        new: classes(new, &new_kept),
+        forward: vec![0; old_kept.len() + new_kept.len() + 3],
+        backward: vec![0; old_kept.len() + new_kept.len() + 3],
+        offset: new_kept.len() as isize + 1,
+        max_cost: bogo_sqrt(old_kept.len() + new_kept.len() + 3).max(MAX_COST_MIN) as isize,
+        old_changed: vec![false; old_kept.len()],
+        new_changed: vec![false; new_kept.len()],
+    };
+    let (old_len, new_len) = (search.old.len(), search.new.len());
+    search.compare(0, old_len, 0, new_len, minimal);
+    for (i, &line) in old_kept.iter().enumerate() {
+        old.changed[line] |= search.old_changed[i];
+    }
+    for (i, &line) in new_kept.iter().enumerate() {
+        new.changed[line] |= search.new_changed[i];
+    }
+}
+
+// xdiff's integer square root estimate, a power of two
+fn bogo_sqrt(mut n: usize) -> usize {
+    let mut root = 1;
+    while n > 0 {
+        root <<= 1;
+        n >>= 2;
+    }
+    root
+}
+
+struct File<'a> {
+    lines: &'a [&'a [u8]],
+    classes: Vec<usize>,
+    // whether each line is changed, with one more unchanged entry past the end
+    changed: Vec<bool>,
+}
+
+impl<'a> File<'a> {
+    fn new(lines: &'a [&'a [u8]], classes: Vec<usize>) -> File<'a> {
+        File {
+            lines,
+            classes,
+            changed: vec![false; lines.len() + 1],
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    // whether the line before `i` is changed, which none before the first is
+    fn changed_before(&self, i: usize) -> bool {
+        i > 0 && self.changed[i - 1]
+    }
+
+    // the lines worth searching, out of a range `len` lines long: ones the other range has a
+    // few times, and ones it has many times unless they sit among lines it lacks. The rest are
+    // marked changed
+    fn discard(
+        &mut self,
+        lines: Range<usize>,
+        len: usize,
+        counts: &HashMap<usize, [usize; 2]>,
+        other: usize,
+    ) -> Vec<usize> {
+        let limit = bogo_sqrt(len).min(MAX_EQUAL_LIMIT);
+        // 0 for no match, 1 for a few and 2 for many
+        let matches: Vec<u8> = self.classes[lines.clone()]
+            .iter()
+            .map(|class| match counts[class][other] {
+                0 => 0,
+                n if n >= limit => 2,
+                _ => 1,
+            })
+            .collect();
+
+        let mut kept = vec![];
+        for (i, line) in lines.enumerate() {
+            if matches[i] == 1 || (matches[i] == 2 && !among_unmatched(&matches, i)) {
+                kept.push(line);
+            } else {
+                self.changed[line] = true;
+            }
+        }
+        kept
+    }
+}
+
+// whether the line `i`, found many times in the other file, is in a run of such lines and lines
+// with no match at all that has enough of the latter to leave it out of the search
+fn among_unmatched(matches: &[u8], i: usize) -> bool {
+    let start = i.saturating_sub(SIMILAR_SCAN_WINDOW);
+    let last = (matches.len() - 1).min(i + SIMILAR_SCAN_WINDOW);
+
+    let (mut unmatched_before, mut many_before) = (0, 1);
+    for m in matches[start..i].iter().rev() {
+        match m {
+            0 => unmatched_before += 1,
+            2 => many_before += 1,
+            _ => break,
+        }
+    }
+    if unmatched_before == 0 {
+        return false;
+    }
+    let (mut unmatched, mut many) = (0, 1);
+    for m in &matches[i + 1..=last] {
+        match m {
+            0 => unmatched += 1,
+            2 => many += 1,
+            _ => break,
+        }
+    }
+    if unmatched == 0 {
+        return false;
+    }
+    unmatched += unmatched_before;
+    many += many_before;
+
+    many * KEEP_DISCARDED_RUN < many + unmatched
+}
+
+// marks the lines of the two ranges that changed by the patience algorithm: of the lines each
+// range has just once, the longest run of them in the same order in both is kept, the lines
+// around those are gone into the same way, and Myers' algorithm takes over where none are left
+fn patience(old: &mut File, new: &mut File, old_lines: Range<usize>, new_lines: Range<usize>) {
+    if old_lines.is_empty() || new_lines.is_empty() {
+        old.changed[old_lines].fill(true);
+        new.changed[new_lines].fill(true);
+        return;
+    }
+
+    // the classes of the old range by where they're first found, with the line of the new
+    // range they match; a class found twice in either range matches nothing
+    struct Unique {
+        old: usize,
+        new: Option<usize>,
+        unique: bool,
+    }
+    let mut by_class: HashMap<usize, usize> = HashMap::new();
+    let mut uniques: Vec<Unique> = vec![];
+    for line in old_lines.clone() {
+        match by_class.get(&old.classes[line]) {
+            Some(&i) => uniques[i].unique = false,
+            None => {
+                by_class.insert(old.classes[line], uniques.len());
+                uniques.push(Unique {
+                    old: line,
+                    new: None,
+                    unique: true,
+                });
+            }
+        }
+    }
+    let mut any_matches = false;
+    for line in new_lines.clone() {
+        if let Some(&i) = by_class.get(&new.classes[line]) {
+            any_matches = true;
+            match uniques[i].new {
+                Some(_) => uniques[i].unique = false,
+                None => uniques[i].new = Some(line),
+            }
+        }
+    }
+    if !any_matches {
+        old.changed[old_lines].fill(true);
+        new.changed[new_lines].fill(true);
+        return;
+    }
+
+    // the longest run of unique lines in order in both, found by patience sorting: each line
+    // goes on the first pile whose top comes after it in the new range, and remembers the top
+    // of the pile before
+    let mut piles: Vec<usize> = vec![];
+    let mut previous: Vec<Option<usize>> = vec![None; uniques.len()];
+    for (i, unique) in uniques.iter().enumerate() {
+        let Some(new_line) = unique.new.filter(|_| unique.unique) else {
+            continue;
+        };
+        let pile = piles.partition_point(|&top| uniques[top].new < Some(new_line));
+        previous[i] = pile.checked_sub(1).map(|pile| piles[pile]);
+        match piles.get_mut(pile) {
+            Some(top) => *top = i,
+            None => piles.push(i),
+        }
+    }
+    let Some(&last) = piles.last() else {
+        return myers(old, new, old_lines, new_lines, false);
+    };
+    let mut common = vec![];
+    let mut next = Some(last);
+    while let Some(i) = next {
+        common.push((uniques[i].old, uniques[i].new.unwrap_or_default()));
+        next = previous[i];
+    }
+    common.reverse();
+
+    // between the common lines, and before the first and after the last, the lines that match
+    // next to them are kept and the rest gone into
+    let (mut old_line, mut new_line) = (old_lines.start, new_lines.start);
+    let mut k = 0;
+    loop {
+        let (mut old_next, mut new_next) = match common.get(k) {
+            Some(&(old_next, new_next)) => (old_next, new_next),
+            None => (old_lines.end, new_lines.end),
+        };
+        if k < common.len() {
+            while old_next > old_line
+                && new_next > new_line
+                && old.classes[old_next - 1] == new.classes[new_next - 1]
+            {
+                old_next -= 1;
+                new_next -= 1;
+            }
+        }
+        while old_line < old_next
+            && new_line < new_next
+            && old.classes[old_line] == new.classes[new_line]
+        {
+            old_line += 1;
+            new_line += 1;
+        }
+        if old_next > old_line || new_next > new_line {
+            patience(old, new, old_line..old_next, new_line..new_next);
+        }
+        if k == common.len() {
+            return;
+        }
+
+        // a run of common lines next to each other is skipped at once
+        while common
+            .get(k + 1)
+            .is_some_and(|&(o, n)| (o, n) == (common[k].0 + 1, common[k].1 + 1))
+        {
+            k += 1;
+        }
+        (old_line, new_line) = (common[k].0 + 1, common[k].1 + 1);
+        k += 1;
+    }
+}
+
+// a run of lines both ranges have, as the histogram algorithm picks it
+enum Lcs {
+    Found(Range<usize>, Range<usize>),
+    // nothing in common
+    None,
+    // only lines found too many times in common
+    TooCommon,
+}
+
+// marks the lines of the two ranges that changed by the histogram algorithm: the longest run of
+// lines both have, among those whose lines the old range has the fewest times, is kept and the
+// lines on either side of it gone into the same way
+fn histogram(
+    old: &mut File,
+    new: &mut File,
+    mut old_lines: Range<usize>,
+    mut new_lines: Range<usize>,
+) {
+    loop {
+        if old_lines.is_empty() || new_lines.is_empty() {
+            old.changed[old_lines].fill(true);
+            new.changed[new_lines].fill(true);
+            return;
+        }
+        match find_lcs(old, new, old_lines.clone(), new_lines.clone()) {
+            Lcs::Found(old_run, new_run) => {
+                histogram(
+                    old,
+                    new,
+                    old_lines.start..old_run.start,
+                    new_lines.start..new_run.start,
+                );
+                old_lines = old_run.end..old_lines.end;
+                new_lines = new_run.end..new_lines.end;
+            }
+            Lcs::None => {
+                old.changed[old_lines].fill(true);
+                new.changed[new_lines].fill(true);
+                return;
+            }
+            Lcs::TooCommon => return myers(old, new, old_lines, new_lines, false),
+        }
+    }
+}
+
+// the run the histogram algorithm keeps: every line of the new range is looked for in the old
+// one, and the run around each place it's found replaces the best so far when it's longer or
+// its rarest line is rarer
+fn find_lcs(old: &File, new: &File, old_lines: Range<usize>, new_lines: Range<usize>) -> Lcs {
+    // where each class is first found in the old range, where each line's class is found next,
+    // and how many times each class is found
+    let mut first: HashMap<usize, usize> = HashMap::new();
+    let mut next: Vec<Option<usize>> = vec![None; old_lines.len()];
+    let mut counts: HashMap<usize, usize> = HashMap::new();
+    for line in old_lines.clone().rev() {
+        let class = old.classes[line];
+        next[line - old_lines.start] = first.insert(class, line);
+        *counts.entry(class).or_default() += 1;
+    }
+    let count = |line: usize| counts[&old.classes[line]];
+
+    // the best run in the old and new ranges, inclusive, and how many times its rarest line
+    // is found
+    let mut best: Option<(usize, usize, usize, usize)> = None;
+    let mut best_count = MAX_CHAIN_LENGTH + 1;
+    let mut has_common = false;
+    let (old_last, new_last) = (old_lines.end - 1, new_lines.end - 1);
+    let mut new_line = new_lines.start;
+    while new_line <= new_last {
+        let mut new_next = new_line + 1;
+        let class = new.classes[new_line];
+        if let Some(&first) = first.get(&class) {
+            has_common = true;
+            if counts[&class] <= best_count {
+                let mut old_line = first;
+                loop {
+                    let (mut old_start, mut new_start) = (old_line, new_line);
+                    let (mut old_end, mut new_end) = (old_line, new_line);
+                    let mut rarest = counts[&class];
+                    while old_lines.start < old_start
+                        && new_lines.start < new_start
+                        && old.classes[old_start - 1] == new.classes[new_start - 1]
+                    {
+                        old_start -= 1;
+                        new_start -= 1;
+                        if rarest > 1 {
+                            rarest = rarest.min(count(old_start));
+                        }
+                    }
+                    while old_end < old_last
+                        && new_end < new_last
+                        && old.classes[old_end + 1] == new.classes[new_end + 1]
+                    {
+                        old_end += 1;
+                        new_end += 1;
+                        if rarest > 1 {
+                            rarest = rarest.min(count(old_end));
+                        }
+                    }
+
+                    new_next = new_next.max(new_end + 1);
+                    let best_len = best.map_or(0, |(old_start, old_end, ..)| old_end - old_start);
+                    if best_len < old_end - old_start || rarest < best_count {
+                        best = Some((old_start, old_end, new_start, new_end));
+                        best_count = rarest;
+                    }
+
+                    // the next place the line is found past the run
+                    let mut following = next[old_line - old_lines.start];
+                    while let Some(line) = following.filter(|&line| line <= old_end) {
+                        following = next[line - old_lines.start];
+                    }
+                    match following {
+                        Some(line) => old_line = line,
+                        None => break,
+                    }
+                }
+            }
+        }
+        new_line = new_next;
+    }
+
+    match best {
+        _ if has_common && best_count > MAX_CHAIN_LENGTH => Lcs::TooCommon,
+        Some((old_start, old_end, new_start, new_end)) => {
+            Lcs::Found(old_start..old_end + 1, new_start..new_end + 1)
+        }
+        None => Lcs::None,
+    }
+}
+
+// the state of Myers' search over the lines left in, with the furthest point reached on each
+// diagonal searching forwards and backwards
+struct Search {
+    old: Vec<usize>,
+    new: Vec<usize>,
+    forward: Vec<isize>,
+    backward: Vec<isize>,
+    // where diagonal 0 is in `forward` and `backward`
+    offset: isize,
+    max_cost: isize,
+    old_changed: Vec<bool>,
+    new_changed: Vec<bool>,
+}
+
+// where a search splits its box in two, and whether each half must be searched for the
+// shortest edits
+struct Split {
+    old: usize,
+    new: usize,
+    minimal_before: bool,
+    minimal_after: bool,
+}
+
+impl Search {
+    fn compare(
+        &mut self,
+        mut old_start: usize,
+        mut old_end: usize,
+        mut new_start: usize,
+        mut new_end: usize,
+        minimal: bool,
+    ) {
+        while old_start < old_end
+            && new_start < new_end
+            && self.old[old_start] == self.new[new_start]
+        {
+            old_start += 1;
+            new_start += 1;
+        }
+        while old_start < old_end
+            && new_start < new_end
+            && self.old[old_end - 1] == self.new[new_end - 1]
+        {
+            old_end -= 1;
+            new_end -= 1;
+        }
+
+        if old_start == old_end {
+            self.new_changed[new_start..new_end].fill(true);
+        } else if new_start == new_end {
+            self.old_changed[old_start..old_end].fill(true);
+        } else {
+            let split = self.split(old_start, old_end, new_start, new_end, minimal);
+            self.compare(
+                old_start,
+                split.old,
+                new_start,
+                split.new,
+                split.minimal_before,
+            );
+            self.compare(split.old, old_end, split.new, new_end, split.minimal_after);
+        }
+    }
+
+    fn forward(&self, diagonal: isize) -> isize {
+        self.forward[(diagonal + self.offset) as usize]
+    }
+
+    fn backward(&self, diagonal: isize) -> isize {
+        self.backward[(diagonal + self.offset) as usize]
+    }
+
+    // the middle of a shortest edit path through the box, searching from both of its corners
+    // until they meet; unless `minimal`, a costly search takes a long enough run of matching
+    // lines it has found, or failing that the point reached furthest
+    fn split(
+        &mut self,
+        old_start: usize,
+        old_end: usize,
+        new_start: usize,
+        new_end: usize,
+        minimal: bool,
+    ) -> Split {
+        let (off1, lim1) = (old_start as isize, old_end as isize);
+        let (off2, lim2) = (new_start as isize, new_end as isize);
+        let (dmin, dmax) = (off1 - lim2, lim1 - off2);
+        let (fmid, bmid) = (off1 - off2, lim1 - lim2);
+        let odd = (fmid - bmid) & 1 != 0;
+        let (mut fmin, mut fmax) = (fmid, fmid);
+        let (mut bmin, mut bmax) = (bmid, bmid);
+        let offset = self.offset;
+        let at = |diagonal: isize| (diagonal + offset) as usize;
+        let split = |i1: isize, i2: isize, minimal_before, minimal_after| Split {
+            old: i1 as usize,
+            new: i2 as usize,
+            minimal_before,
+            minimal_after,
+        };
+
+        self.forward[at(fmid)] = off1;
+        self.backward[at(bmid)] = lim1;
+        let mut cost = 1;
+        loop {
+            let mut got_snake = false;
+
+            // the diagonals reached grow by one each way, or shrink where they leave the box
+            if fmin > dmin {
+                fmin -= 1;
+                self.forward[at(fmin - 1)] = -1;
+            } else {
+                fmin += 1;
+            }
+            if fmax < dmax {
+                fmax += 1;
+                self.forward[at(fmax + 1)] = -1;
+            } else {
+                fmax -= 1;
+            }
+            let mut d = fmax;
+            while d >= fmin {
+                let mut i1 = if self.forward(d - 1) >= self.forward(d + 1) {
+                    self.forward(d - 1) + 1
+                } else {
+                    self.forward(d + 1)
+                };
+                let previous = i1;
+                let mut i2 = i1 - d;
+                while i1 < lim1 && i2 < lim2 && self.old[i1 as usize] == self.new[i2 as usize] {
+                    i1 += 1;
+                    i2 += 1;
+                }
+                if i1 - previous > SNAKE_COUNT {
+                    got_snake = true;
+                }
+                self.forward[at(d)] = i1;
+                if odd && bmin <= d && d <= bmax && self.backward(d) <= i1 {
+                    return split(i1, i2, true, true);
+                }
+                d -= 2;
+            }
+
+            if bmin > dmin {
+                bmin -= 1;
+                self.backward[at(bmin - 1)] = isize::MAX;
+            } else {
+                bmin += 1;
+            }
+            if bmax < dmax {
+                bmax += 1;
+                self.backward[at(bmax + 1)] = isize::MAX;
+            } else {
+                bmax -= 1;
+            }
+            let mut d = bmax;
+            while d >= bmin {
+                let mut i1 = if self.backward(d - 1) < self.backward(d + 1) {
+                    self.backward(d - 1)
+                } else {
+                    self.backward(d + 1) - 1
+                };
+                let previous = i1;
+                let mut i2 = i1 - d;
+                while i1 > off1
+                    && i2 > off2
+                    && self.old[i1 as usize - 1] == self.new[i2 as usize - 1]
+                {
+                    i1 -= 1;
+                    i2 -= 1;
+                }
+                if previous - i1 > SNAKE_COUNT {
+                    got_snake = true;
+                }
+                self.backward[at(d)] = i1;
+                if !odd && fmin <= d && d <= fmax && i1 <= self.forward(d) {
+                    return split(i1, i2, true, true);
+                }
+                d -= 2;
+            }
+
+            if minimal {
+                cost += 1;
+                continue;
+            }
+
+            // a path that has come far, for its cost, and ends in a long run of matching lines
+            if got_snake && cost > HEURISTIC_MIN_COST as isize {
+                let mut best: Option<(isize, isize, isize)> = None;
+                let mut d = fmax;
+                while d >= fmin {
+                    let i1 = self.forward(d);
+                    let i2 = i1 - d;
+                    let v = (i1 - off1) + (i2 - off2) - (d - fmid).abs();
+                    if v > HEURISTIC_FACTOR * cost
+                        && best.map_or(v > 0, |(best, ..)| v > best)
+                        && off1 + SNAKE_COUNT <= i1
+                        && i1 < lim1
+                        && off2 + SNAKE_COUNT <= i2
+                        && i2 < lim2
+                        && (1..=SNAKE_COUNT)
+                            .all(|k| self.old[(i1 - k) as usize] == self.new[(i2 - k) as usize])
+                    {
+                        best = Some((v, i1, i2));
+                    }
+                    d -= 2;
+                }
+                if let Some((_, i1, i2)) = best {
+                    return split(i1, i2, true, false);
+                }
+
+                let mut d = bmax;
+                while d >= bmin {
+                    let i1 = self.backward(d);
+                    let i2 = i1 - d;
+                    let v = (lim1 - i1) + (lim2 - i2) - (d - bmid).abs();
+                    if v > HEURISTIC_FACTOR * cost
+                        && best.map_or(v > 0, |(best, ..)| v > best)
+                        && off1 < i1
+                        && i1 <= lim1 - SNAKE_COUNT
+                        && off2 < i2
+                        && i2 <= lim2 - SNAKE_COUNT
+                        && (0..SNAKE_COUNT)
+                            .all(|k| self.old[(i1 + k) as usize] == self.new[(i2 + k) as usize])
+                    {
+                        best = Some((v, i1, i2));
+                    }
+                    d -= 2;
+                }
+                if let Some((_, i1, i2)) = best {
+                    return split(i1, i2, false, true);
+                }
+            }
+
+            // too costly: take whichever of the two searches got furthest
+            if cost >= self.max_cost {
+                let (mut fbest, mut fbest1) = (-1, -1);
+                let mut d = fmax;
+                while d >= fmin {
+                    let mut i1 = self.forward(d).min(lim1);
+                    let mut i2 = i1 - d;
+                    if lim2 < i2 {
+                        (i1, i2) = (lim2 + d, lim2);
+                    }
+                    if fbest < i1 + i2 {
+                        (fbest, fbest1) = (i1 + i2, i1);
+                    }
+                    d -= 2;
+                }
+
+                let (mut bbest, mut bbest1) = (isize::MAX, isize::MAX);
+                let mut d = bmax;
+                while d >= bmin {
+                    let mut i1 = self.backward(d).max(off1);
+                    let mut i2 = i1 - d;
+                    if i2 < off2 {
+                        (i1, i2) = (off2 + d, off2);
+                    }
+                    if i1 + i2 < bbest {
+                        (bbest, bbest1) = (i1 + i2, i1);
+                    }
+                    d -= 2;
+                }
+
+                return if (lim1 + lim2) - bbest < fbest - (off1 + off2) {
+                    split(fbest1, fbest - fbest1, true, false)
+                } else {
+                    split(bbest1, bbest - bbest1, false, true)
+                };
+            }
+            cost += 1;
+        }
+    }
+}
+
+// a run of changed lines, possibly empty, from `start` up to `end`
+#[derive(Clone, Copy)]
+struct Group {
+    start: usize,
+    end: usize,
+}
+
+impl Group {
+    fn first(file: &File) -> Group {
+        let mut end = 0;
+        while file.changed[end] {
+            end += 1;
+        }
+        Group { start: 0, end }
+    }
+
+    fn next(&mut self, file: &File) -> bool {
+        if self.end == file.len() {
+            return false;
+        }
+        self.start = self.end + 1;
+        self.end = self.start;
+        while file.changed[self.end] {
+            self.end += 1;
+        }
+        true
+    }
+
+    fn previous(&mut self, file: &File) -> bool {
+        if self.start == 0 {
+            return false;
+        }
+        self.end = self.start - 1;
+        self.start = self.end;
+        while file.changed_before(self.start) {
+            self.start -= 1;
+        }
+        true
+    }
+
+    // moves the group a line down when the line after it is the same as its first, joining
+    // any group it then touches
+    fn slide_down(&mut self, file: &mut File) -> bool {
+        if self.end < file.len() && file.classes[self.start] == file.classes[self.end] {
+            file.changed[self.start] = false;
+            file.changed[self.end] = true;
+            self.start += 1;
+            self.end += 1;
+            while file.changed[self.end] {
+                self.end += 1;
+            }
+            return true;
+        }
+        false
+    }
+
+    fn slide_up(&mut self, file: &mut File) -> bool {
+        if self.start > 0 && file.classes[self.start - 1] == file.classes[self.end - 1] {
+            self.start -= 1;
+            self.end -= 1;
+            file.changed[self.start] = true;
+            file.changed[self.end] = false;
+            while file.changed_before(self.start) {
+                self.start -= 1;
+            }
+            return true;
+        }
+        false
+    }
+}
+
+// how far the indent heuristic slides a group at most, and its weights
+const INDENT_HEURISTIC_MAX_SLIDING: isize = 100;
+const MAX_INDENT: isize = 200;
+const MAX_BLANKS: isize = 20;
+const START_OF_FILE_PENALTY: isize = 1;
+const END_OF_FILE_PENALTY: isize = 21;
+const TOTAL_BLANK_WEIGHT: isize = -30;
+const POST_BLANK_WEIGHT: isize = 6;
+const RELATIVE_INDENT_PENALTY: isize = -4;
+const RELATIVE_INDENT_WITH_BLANK_PENALTY: isize = 10;
+const RELATIVE_OUTDENT_PENALTY: isize = 24;
+const RELATIVE_OUTDENT_WITH_BLANK_PENALTY: isize = 17;
+const RELATIVE_DEDENT_PENALTY: isize = 23;
+const RELATIVE_DEDENT_WITH_BLANK_PENALTY: isize = 17;
+const INDENT_WEIGHT: isize = 60;
+
+// slides each group of changed lines in `file` as far down as it goes, merging groups it meets,
+// then back up to line up with a group of `other` if it passed one, or else to where the indent
+// heuristic likes it best. `other` gets empty groups moved in step so the two stay paired
+fn compact(file: &mut File, other: &mut File, indent_heuristic: bool) {
+    let mut group = Group::first(file);
+    let mut other_group = Group::first(other);
+
+    loop {
+        if group.end != group.start {
+            let mut size;
+            let mut earliest_end;
+            let mut end_matching_other;
+            loop {
+                size = group.end - group.start;
+                end_matching_other = None;
+
+                while group.slide_up(file) {
+                    other_group.previous(other);
+                }
+                earliest_end = group.end;
+                if other_group.end > other_group.start {
+                    end_matching_other = Some(group.end);
+                }
+
+                while group.slide_down(file) {
+                    other_group.next(other);
+                    if other_group.end > other_group.start {
+                        end_matching_other = Some(group.end);
+                    }
+                }
+                if size == group.end - group.start {
+                    break;
+                }
+            }
+
+            if group.end == earliest_end {
+                // it can't move
+            } else if end_matching_other.is_some() {
+                while other_group.end == other_group.start {
+                    group.slide_up(file);
+                    other_group.previous(other);
+                }
+            } else if indent_heuristic {
+                let (end, size) = (group.end as isize, size as isize);
+                let mut shift = (earliest_end as isize)
+                    .max(end - size - 1)
+                    .max(end - INDENT_HEURISTIC_MAX_SLIDING);
+                let mut best: Option<(isize, Score)> = None;
+                while shift <= end {
+                    let mut score = Score::default();
+                    score.add(&measure_split(file, shift));
+                    score.add(&measure_split(file, shift - size));
+                    if best
+                        .as_ref()
+                        .is_none_or(|(_, best)| score.compare(best) <= 0)
+                    {
+                        best = Some((shift, score));
+                    }
+                    shift += 1;
+                }
+                if let Some((best_shift, _)) = best {
+                    while group.end as isize > best_shift {
+                        group.slide_up(file);
+                        other_group.previous(other);
+                    }
+                }
+            }
+        }
+
+        if !group.next(file) {
+            break;
+        }
+        other_group.next(other);
+    }
+}
+
+// the indentation of a line in columns, with tabs to multiples of 8, or -1 when it's blank
+fn indent(line: &[u8]) -> isize {
+    let mut indent = 0;
+    for &c in line {
+        match c {
+            b' ' => indent += 1,
+            b'\t' => indent += 8 - indent % 8,
+            b'\n' | b'\r' => {}
+            _ => return indent,
+        }
+        if indent >= MAX_INDENT {
+            return MAX_INDENT;
+        }
+    }
+    -1
+}
+
+// the lines around a split before line `split`
+struct Measurement {
+    end_of_file: bool,
+    indent: isize,
+    pre_blank: isize,
+    pre_indent: isize,
+    post_blank: isize,
+    post_indent: isize,
+}
+
+fn measure_split(file: &File, split: isize) -> Measurement {
+    let len = file.len() as isize;
+    let line_indent = |i: isize| indent(file.lines[i as usize]);
+    let (end_of_file, indent) = match split >= len {
+        true => (true, -1),
+        false => (false, line_indent(split)),
+    };
+
+    let (mut pre_blank, mut pre_indent) = (0, -1);
+    let mut i = split - 1;
+    while i >= 0 {
+        pre_indent = line_indent(i);
+        if pre_indent != -1 {
+            break;
+        }
+        pre_blank += 1;
+        if pre_blank == MAX_BLANKS {
+            pre_indent = 0;
+            break;
+        }
+        i -= 1;
+    }
+
+    let (mut post_blank, mut post_indent) = (0, -1);
+    let mut i = split + 1;
+    while i < len {
+        post_indent = line_indent(i);
+        if post_indent != -1 {
+            break;
+        }
+        post_blank += 1;
+        if post_blank == MAX_BLANKS {
+            post_indent = 0;
+            break;
+        }
+        i += 1;
+    }
+
+    Measurement {
+        end_of_file,
+        indent,
+        pre_blank,
+        pre_indent,
+        post_blank,
+        post_indent,
+    }
+}
+
+#[derive(Default)]
+struct Score {
+    effective_indent: isize,
+    penalty: isize,
+}
+
+impl Score {
+    fn add(&mut self, m: &Measurement) {
+        if m.pre_indent == -1 && m.pre_blank == 0 {
+            self.penalty += START_OF_FILE_PENALTY;
+        }
+        if m.end_of_file {
+            self.penalty += END_OF_FILE_PENALTY;
+        }
+
+        // the blank lines after the split count the one right after it
+        let post_blank = if m.indent == -1 { 1 + m.post_blank } else { 0 };
+        let total_blank = m.pre_blank + post_blank;
+        self.penalty += TOTAL_BLANK_WEIGHT * total_blank;
+        self.penalty += POST_BLANK_WEIGHT * post_blank;
+
+        let indent = if m.indent != -1 {
+            m.indent
+        } else {
+            m.post_indent
+        };
+        let any_blanks = total_blank != 0;
+        self.effective_indent += indent;
+
+        if indent == -1 || m.pre_indent == -1 || indent == m.pre_indent {
+        } else if indent > m.pre_indent {
+            self.penalty += match any_blanks {
+                true => RELATIVE_INDENT_WITH_BLANK_PENALTY,
+                false => RELATIVE_INDENT_PENALTY,
+            };
+        } else if m.post_indent != -1 && m.post_indent > indent {
+            // less indented than the line before but more than the one after: likely a new block
+            self.penalty += match any_blanks {
+                true => RELATIVE_OUTDENT_WITH_BLANK_PENALTY,
+                false => RELATIVE_OUTDENT_PENALTY,
+            };
+        } else {
+            // likely the end of a block
+            self.penalty += match any_blanks {
+                true => RELATIVE_DEDENT_WITH_BLANK_PENALTY,
+                false => RELATIVE_DEDENT_PENALTY,
+            };
+        }
+    }
+
+    fn compare(&self, other: &Score) -> isize {
+        let indents = (self.effective_indent - other.effective_indent).signum();
+        INDENT_WEIGHT * indents + (self.penalty - other.penalty)
+    }
+}